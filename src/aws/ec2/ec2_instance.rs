@@ -1,18 +1,32 @@
-use std::any::Any;
+use std::{any::Any, time::Duration};
 
 use aws_sdk_ec2::{error::ProvideErrorMetadata, types as ec2_types};
 use tracing::info;
 
-use crate::aws::{
-    AWSClient,
-    internal::wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitError},
+use crate::{
+    aws::{
+        AWSClient,
+        ec2::preflight::{ImageArchitecture, InstanceTypeSupport},
+        internal::{
+            retry::{
+                RetryPolicy, is_capacity_unavailable, is_throttling, retry_capacity,
+                retry_throttled,
+            },
+            tags::{COMPONENT_TAG, DeploymentTags, merge_tag_specifications},
+            wait_and_refresh::{
+                RefreshFunctionReturn, StateChangeConfig, WaitDefaults, WaitError, read_with_grace,
+            },
+        },
+    },
+    utils::{base64, redact::Sensitive, timeouts::ResourceTimeouts},
 };
 
 #[derive(Debug, Clone)]
 pub struct InstanceOpts {
     block_device_mappings: Option<Vec<ec2_types::BlockDeviceMapping>>,
-    capacity_reservation_specification: Option<ec2_types::CapacityReservationSpecification>,
-    client_token: Option<String>,
+    pub(crate) capacity_reservation_specification:
+        Option<ec2_types::CapacityReservationSpecification>,
+    pub(crate) client_token: Option<String>,
     cpu_options: Option<ec2_types::CpuOptionsRequest>,
     credit_specification: Option<ec2_types::CreditSpecificationRequest>,
     disable_api_termination: Option<bool>,
@@ -23,13 +37,13 @@ pub struct InstanceOpts {
     iam_instance_profile: Option<ec2_types::IamInstanceProfileSpecification>,
     pub(crate) image_id: String,
     instance_initiated_shutdown_behavior: Option<String>,
-    instance_market_options: Option<ec2_types::InstanceMarketOptionsRequest>,
+    pub(crate) instance_market_options: Option<ec2_types::InstanceMarketOptionsRequest>,
     pub(crate) instance_type: ec2_types::InstanceType,
     ipv6_address_count: Option<i32>,
     ipv6_addresses: Option<Vec<ec2_types::InstanceIpv6Address>>,
     key_name: Option<String>,
-    launch_template: Option<ec2_types::LaunchTemplateSpecification>,
-    maintenance_options: Option<ec2_types::InstanceMaintenanceOptionsRequest>,
+    pub(crate) launch_template: Option<ec2_types::LaunchTemplateSpecification>,
+    pub(crate) maintenance_options: Option<ec2_types::InstanceMaintenanceOptionsRequest>,
     max_count: i32,
     metadata_options: Option<ec2_types::InstanceMetadataOptionsRequest>,
     min_count: i32,
@@ -42,12 +56,66 @@ pub struct InstanceOpts {
     security_groups: Option<Vec<String>>,
     subnet_id: Option<String>,
     tag_specifications: Option<Vec<ec2_types::TagSpecification>>,
-    user_data: Option<String>,
+    pub(crate) user_data: Option<Sensitive>, // Always base64, as RunInstances expects it
+    pub(crate) wait_for_status_checks: bool, // Wait for system and instance status checks to pass after running
+    pub(crate) status_checks_timeout: Duration,
+    pub(crate) spot_fulfillment_timeout: Duration, // Budget for finding Spot capacity and fulfilling the request
+    pub(crate) public_ip_timeout: Duration, // How long a requested public IP may lag behind running
+    pub(crate) component_id: Option<String>, // Value of COMPONENT_TAG, set by apply
+}
+
+pub const STATUS_CHECK_OK: &str = "ok";
+/// Summaries of a status check that has not passed yet but may still
+pub const STATUS_CHECK_PENDING: &[&str] = &["initializing", "insufficient-data"];
+/// Shown in a plan for values AWS only assigns when the instance is launched
+pub const KNOWN_AFTER_APPLY: &str = "(known after apply)";
+
+/// Result of destroying an instance, both count as success
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestroyOutcome {
+    Terminated,
+    AlreadyGone, // The instance did not exist (any more) when the destroy started
+}
+pub const DEFAULT_STATUS_CHECKS_TIMEOUT: Duration = Duration::from_secs(600); // 10 minutes
+pub const DEFAULT_SPOT_FULFILLMENT_TIMEOUT: Duration = Duration::from_secs(300); // 5 minutes
+pub const DEFAULT_PUBLIC_IP_TIMEOUT: Duration = Duration::from_secs(120); // 2 minutes
+
+/// States the public IP refresh reports for each instance
+const PUBLIC_IP_ASSIGNED: &str = "public-ip:assigned";
+const PUBLIC_IP_PENDING: &str = "public-ip:pending";
+
+/// State the Spot request refresh reports once the request has launched its instance
+const SPOT_FULFILLED: &str = "fulfilled";
+
+/// Where a Spot request stands, from its state and status code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpotProgress {
+    Fulfilled,
+    Pending(String), // Status code of a request AWS is still evaluating or launching
+    WaitingForCapacity(String), // No capacity yet, the request stays open until the timeout
+    Failed(String),  // Status code of a request that will not be fulfilled
+}
+
+/**
+ * Sort a Spot request by its state (`open`, `active`, `closed`, ...) and status code
+ * (`pending-evaluation`, `fulfilled`, `capacity-not-available`, ...).
+ */
+pub fn spot_progress(state: &str, status_code: &str) -> SpotProgress {
+    match state {
+        "active" if status_code == SPOT_FULFILLED => SpotProgress::Fulfilled,
+        "active" | "open" if is_capacity_unavailable(status_code) => {
+            SpotProgress::WaitingForCapacity(status_code.to_string())
+        }
+        "active" | "open" => SpotProgress::Pending(status_code.to_string()),
+        _ => SpotProgress::Failed(status_code.to_string()),
+    }
 }
 
 #[derive(Clone)]
 pub struct EC2Instance {
     client: aws_sdk_ec2::Client,
+    timeouts: ResourceTimeouts, // Bound the waits for running and terminated instances
+    wait: WaitDefaults,
 }
 
 #[derive(thiserror::Error, Debug, PartialEq, Eq)]
@@ -56,18 +124,74 @@ pub enum EC2Error {
     InstanceNotFound,
     #[error("Error while creating EC2 instance")]
     InstanceNotCreated,
+    #[error("Resource not found: {0}")]
+    ResourceNotFound(String), // Any EC2 resource other than an instance, e.g. a VPC
     #[error("Options error: {0}")]
     OptionsError(String),
     #[error("AWS SDK error: {0}")]
     SdkError(String),
+    #[error("Not authorized: {0}")]
+    Unauthorized(String),
     #[error("Error while waiting for state to confirm the resource change: {0}")]
     StateError(#[from] WaitError),
+    #[error("Instance must be replaced to apply the change: {0}")]
+    RequiresReplacement(String),
+    #[error("Throttled by AWS, gave up after retrying: {0}")]
+    Throttled(String),
+    #[error("Spot request not fulfilled: {0}")]
+    SpotRequestFailed(String),
+}
+
+/// Attributes of an existing instance an update changes in place, None when unchanged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstanceChanges {
+    pub instance_type: Option<ec2_types::InstanceType>,
+    pub user_data: Option<Sensitive>,
+    pub disable_api_termination: Option<bool>,
+    pub security_group_ids: Option<Vec<String>>,
+}
+
+impl InstanceChanges {
+    pub fn is_empty(&self) -> bool {
+        self.changed().is_empty()
+    }
+
+    /// Names of the changed attributes, in the order they are modified
+    pub fn changed(&self) -> Vec<&'static str> {
+        [
+            ("instance_type", self.instance_type.is_some()),
+            ("user_data", self.user_data.is_some()),
+            (
+                "disable_api_termination",
+                self.disable_api_termination.is_some(),
+            ),
+            ("security_group_ids", self.security_group_ids.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, changed)| changed.then_some(name))
+        .collect()
+    }
+
+    /// AWS only accepts a new instance type or user data while the instance is stopped
+    pub fn needs_stop(&self) -> bool {
+        self.instance_type.is_some() || self.user_data.is_some()
+    }
 }
 
 impl<T: ProvideErrorMetadata + std::fmt::Display> From<T> for EC2Error {
     fn from(value: T) -> Self {
         match value.code() {
             Some(code) if code == "InvalidInstanceID.NotFound" => EC2Error::InstanceNotFound,
+            Some(code) if code.ends_with(".NotFound") => {
+                EC2Error::ResourceNotFound(value.message().unwrap_or(code).to_string())
+            }
+            Some(code) if code == "UnauthorizedOperation" || code == "AccessDenied" => {
+                EC2Error::Unauthorized(value.message().unwrap_or(code).to_string())
+            }
+            // Throttling only surfaces once both the SDK and retry_throttled have given up
+            Some(code) if is_throttling(code) => {
+                EC2Error::Throttled(value.message().unwrap_or(code).to_string())
+            }
             _ => {
                 let error_message = format!(
                     "AWS SDK error: {} (code: {:?}, message: {:?})",
@@ -75,7 +199,7 @@ impl<T: ProvideErrorMetadata + std::fmt::Display> From<T> for EC2Error {
                     value.code(),
                     value.message()
                 );
-                println!(
+                tracing::debug!(
                     "Converted AWS SDK error to EC2Error: {} (code: {:?}, message: {:?})",
                     value,
                     value.code(),
@@ -87,14 +211,565 @@ impl<T: ProvideErrorMetadata + std::fmt::Display> From<T> for EC2Error {
     }
 }
 
+impl InstanceOpts {
+    /**
+     * Make creating the instance idempotent for a component: it and its volumes get the
+     * deployment tags so a rerun finds it, and `client_token` is used unless the properties set
+     * their own. Tags the properties set on the instance or its volumes win.
+     */
+    pub fn for_component(mut self, tags: &DeploymentTags, client_token: &str) -> Self {
+        if self.client_token.is_none() {
+            self.client_token = Some(client_token.to_string());
+        }
+        let injected = tags.tags();
+        let specifications = self.tag_specifications.take().unwrap_or_default();
+        let specifications =
+            merge_tag_specifications(specifications, ec2_types::ResourceType::Instance, &injected);
+        let specifications =
+            merge_tag_specifications(specifications, ec2_types::ResourceType::Volume, &injected);
+        self.tag_specifications = Some(specifications);
+        self.component_id = Some(tags.component_id());
+        self
+    }
+
+    /// Whether the instance is launched as a Spot Instance
+    pub fn is_spot(&self) -> bool {
+        self.instance_market_options
+            .as_ref()
+            .and_then(|m| m.market_type())
+            == Some(&ec2_types::MarketType::Spot)
+    }
+
+    /// Whether a network interface asks for a public IP, AWS may assign it after the instance runs
+    pub fn requests_public_ip(&self) -> bool {
+        self.network_interfaces
+            .iter()
+            .flatten()
+            .any(|iface| iface.associate_public_ip_address() == Some(true))
+    }
+
+    /// Problems RunInstances would reject that can be found without calling AWS
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let instance_type = self.instance_type.as_str();
+        // Interpolated later at apply, nothing to check yet
+        if !instance_type.contains("${")
+            && !ec2_types::InstanceType::values().contains(&instance_type)
+        {
+            problems.push(format!("unknown instance_type '{}'", instance_type));
+        }
+        if self.min_count < 1 {
+            problems.push(format!(
+                "min_count must be at least 1, got {}",
+                self.min_count
+            ));
+        }
+        if self.min_count > self.max_count {
+            problems.push(format!(
+                "min_count {} is greater than max_count {}",
+                self.min_count, self.max_count
+            ));
+        }
+        problems
+    }
+
+    /**
+     * Properties that cannot be changed on `current` and differ from it, as `<property>: <old> ->
+     * <new>`. Any of them means the instance has to be replaced rather than updated.
+     */
+    pub fn replacement_reasons(&self, current: &ec2_types::Instance) -> Vec<String> {
+        let mut reasons = Vec::new();
+        let mut compare = |property: &str, wanted: Option<&str>, actual: Option<&str>| {
+            if let Some(wanted) = wanted.filter(|wanted| Some(*wanted) != actual) {
+                reasons.push(format!(
+                    "{}: {} -> {}",
+                    property,
+                    actual.unwrap_or("-"),
+                    wanted
+                ));
+            }
+        };
+        compare("ami", Some(self.image_id.as_str()), current.image_id());
+        compare("subnet_id", self.subnet_id.as_deref(), current.subnet_id());
+        compare("key_name", self.key_name.as_deref(), current.key_name());
+        reasons
+    }
+
+    /**
+     * Mutable attributes that differ from `current`. User data and termination protection are not
+     * part of DescribeInstances, they are passed in from DescribeInstanceAttribute.
+     * Attributes the properties leave unset are never changed.
+     */
+    pub fn changes(
+        &self,
+        current: &ec2_types::Instance,
+        current_user_data: Option<&str>,
+        current_disable_api_termination: Option<bool>,
+    ) -> InstanceChanges {
+        let mut changes = InstanceChanges::default();
+        if current.instance_type() != Some(&self.instance_type) {
+            changes.instance_type = Some(self.instance_type.clone());
+        }
+        if let Some(user_data) = &self.user_data {
+            if current_user_data != Some(&**user_data) {
+                changes.user_data = Some(user_data.clone());
+            }
+        }
+        if let Some(disabled) = self.disable_api_termination {
+            if current_disable_api_termination != Some(disabled) {
+                changes.disable_api_termination = Some(disabled);
+            }
+        }
+        if let Some(wanted) = &self.security_group_ids {
+            let mut wanted = wanted.clone();
+            wanted.sort();
+            wanted.dedup();
+            let mut actual: Vec<String> = current
+                .security_groups()
+                .iter()
+                .filter_map(|g| g.group_id().map(|id| id.to_string()))
+                .collect();
+            actual.sort();
+            if wanted != actual {
+                changes.security_group_ids = Some(wanted);
+            }
+        }
+        changes
+    }
+
+    /// Values apply launches the instance with, defaults filled in and ids AWS assigns unknown
+    pub fn computed(&self) -> serde_json::Value {
+        let mut computed = serde_json::json!({
+            "instance_id": KNOWN_AFTER_APPLY,
+            "private_ip": KNOWN_AFTER_APPLY,
+            "public_ip": KNOWN_AFTER_APPLY,
+            "public_dns": KNOWN_AFTER_APPLY,
+            "availability_zone": KNOWN_AFTER_APPLY,
+            "subnet_id": KNOWN_AFTER_APPLY,
+            "instance_type": self.instance_type.as_str(),
+            "image_id": self.image_id,
+            "min_count": self.min_count,
+            "max_count": self.max_count,
+            "wait_for_status_checks": self.wait_for_status_checks,
+        });
+        if self.is_spot() {
+            computed["spot_request_id"] = serde_json::Value::String(KNOWN_AFTER_APPLY.to_string());
+        }
+        if self.wait_for_status_checks {
+            computed["status_checks_seconds"] =
+                serde_json::Value::String(KNOWN_AFTER_APPLY.to_string());
+        }
+        if self.max_count > 1 {
+            computed["instance_ids"] = serde_json::Value::String(KNOWN_AFTER_APPLY.to_string());
+        }
+        computed
+    }
+}
+
+/**
+ * User data of the instance base64 encoded, from `user_data` or the file named by `user_data_file`.
+ * Text that already is base64 is kept as is, `user_data_base64: true` forces that for values the
+ * check cannot recognise.
+ */
+fn user_data_from_yaml(yaml: &serde_yaml::Value) -> Result<Option<String>, EC2Error> {
+    let inline = yaml.get("user_data").and_then(|v| v.as_str());
+    let file = yaml.get("user_data_file").and_then(|v| v.as_str());
+    let content = match (inline, file) {
+        (Some(_), Some(_)) => {
+            return Err(EC2Error::OptionsError(
+                "user_data and user_data_file cannot be set together".to_string(),
+            ));
+        }
+        (Some(inline), None) => inline.as_bytes().to_vec(),
+        (None, Some(file)) => std::fs::read(file).map_err(|err| {
+            EC2Error::OptionsError(format!("Failed to read user_data_file {}: {}", file, err))
+        })?,
+        (None, None) => return Ok(None),
+    };
+
+    let pre_encoded = yaml
+        .get("user_data_base64")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+    let compact: String = String::from_utf8_lossy(&content)
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    let looks_encoded = base64::decode(&compact).is_some_and(|d| String::from_utf8(d).is_ok());
+    if pre_encoded || looks_encoded {
+        return Ok(Some(compact));
+    }
+    Ok(Some(base64::encode(&content)))
+}
+
+/**
+ * `key` of the nested `block` at `path` as an SDK enum, None when it is not set. A value the enum
+ * does not list is an error naming the accepted ones.
+ */
+fn enum_at<T: for<'a> From<&'a str>>(
+    block: &serde_yaml::Value,
+    path: &str,
+    key: &str,
+    accepted: &[&str],
+) -> Result<Option<T>, EC2Error> {
+    let Some(value) = block.get(key).and_then(|v| v.as_str()) else {
+        return Ok(None);
+    };
+    if !accepted.contains(&value) {
+        return Err(EC2Error::OptionsError(format!(
+            "{}.{} must be one of {}, got '{}'",
+            path,
+            key,
+            accepted.join(", "),
+            value
+        )));
+    }
+    Ok(Some(T::from(value)))
+}
+
+/// A string property that may also be written as a number, e.g. a template version or a price
+fn string_or_number(value: &serde_yaml::Value) -> Option<String> {
+    match value {
+        serde_yaml::Value::String(s) => Some(s.clone()),
+        serde_yaml::Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// `launch_template: {id | name, version}`, the properties of the component override the template
+fn launch_template_from_yaml(
+    yaml: &serde_yaml::Value,
+) -> Result<Option<ec2_types::LaunchTemplateSpecification>, EC2Error> {
+    let Some(block) = yaml.get("launch_template") else {
+        return Ok(None);
+    };
+    let invalid = |message: &str| EC2Error::OptionsError(format!("launch_template: {}", message));
+    let id = block.get("id").and_then(|v| v.as_str());
+    let name = block.get("name").and_then(|v| v.as_str());
+    let builder = ec2_types::LaunchTemplateSpecification::builder();
+    let builder = match (id, name) {
+        (Some(_), Some(_)) => return Err(invalid("id and name cannot be set together")),
+        (Some(id), None) => builder.launch_template_id(id),
+        (None, Some(name)) => builder.launch_template_name(name),
+        (None, None) => return Err(invalid("set the id or the name of the launch template")),
+    };
+    let version = match block.get("version") {
+        Some(version) => Some(
+            string_or_number(version)
+                .ok_or_else(|| invalid("version must be a number, $Latest or $Default"))?,
+        ),
+        None => None,
+    };
+    Ok(Some(builder.set_version(version).build()))
+}
+
+/**
+ * `instance_market_options: {market_type, spot_options: {max_price, spot_instance_type,
+ * instance_interruption_behavior}}`. A one-time Spot request can only be terminated on
+ * interruption, AWS rejects stop and hibernate for it.
+ */
+fn market_options_from_yaml(
+    yaml: &serde_yaml::Value,
+) -> Result<Option<ec2_types::InstanceMarketOptionsRequest>, EC2Error> {
+    let Some(block) = yaml.get("instance_market_options") else {
+        return Ok(None);
+    };
+    let invalid =
+        |message: String| EC2Error::OptionsError(format!("instance_market_options: {}", message));
+    let market_type: Option<ec2_types::MarketType> = enum_at(
+        block,
+        "instance_market_options",
+        "market_type",
+        ec2_types::MarketType::values(),
+    )?;
+
+    let spot_options = match block.get("spot_options") {
+        None => None,
+        Some(_)
+            if market_type
+                .as_ref()
+                .is_some_and(|m| *m != ec2_types::MarketType::Spot) =>
+        {
+            return Err(invalid(
+                "spot_options only apply to market_type spot".to_string(),
+            ));
+        }
+        Some(spot) => {
+            let path = "instance_market_options.spot_options";
+            let mut builder = ec2_types::SpotMarketOptions::builder();
+            if let Some(max_price) = spot.get("max_price") {
+                let max_price = string_or_number(max_price)
+                    .filter(|p| p.parse::<f64>().is_ok_and(|p| p > 0.0))
+                    .ok_or_else(|| {
+                        invalid(format!(
+                            "max_price must be a positive price, got {:?}",
+                            max_price
+                        ))
+                    })?;
+                builder = builder.max_price(max_price);
+            }
+            let spot_instance_type: Option<ec2_types::SpotInstanceType> = enum_at(
+                spot,
+                path,
+                "spot_instance_type",
+                ec2_types::SpotInstanceType::values(),
+            )?;
+            let interruption: Option<ec2_types::InstanceInterruptionBehavior> = enum_at(
+                spot,
+                path,
+                "instance_interruption_behavior",
+                ec2_types::InstanceInterruptionBehavior::values(),
+            )?;
+            // Requests are one-time unless they are persistent
+            let one_time = spot_instance_type
+                .as_ref()
+                .is_none_or(|t| *t == ec2_types::SpotInstanceType::OneTime);
+            if let Some(behavior) = interruption.as_ref() {
+                if one_time && *behavior != ec2_types::InstanceInterruptionBehavior::Terminate {
+                    return Err(invalid(format!(
+                        "a one-time Spot request can only terminate on interruption, set \
+                         spot_instance_type: persistent to {}",
+                        behavior.as_str()
+                    )));
+                }
+            }
+            Some(
+                builder
+                    .set_spot_instance_type(spot_instance_type)
+                    .set_instance_interruption_behavior(interruption)
+                    .build(),
+            )
+        }
+    };
+
+    // Spot options are only read for Spot Instances, so they imply the market type
+    let market_type = market_type.or(spot_options.as_ref().map(|_| ec2_types::MarketType::Spot));
+    Ok(Some(
+        ec2_types::InstanceMarketOptionsRequest::builder()
+            .set_market_type(market_type)
+            .set_spot_options(spot_options)
+            .build(),
+    ))
+}
+
+/**
+ * `capacity_reservation_specification` takes either a `capacity_reservation_preference` or a
+ * target, the `capacity_reservation_id` of one reservation or the
+ * `capacity_reservation_resource_group_arn` of a group of them.
+ */
+fn capacity_reservation_from_yaml(
+    yaml: &serde_yaml::Value,
+) -> Result<Option<ec2_types::CapacityReservationSpecification>, EC2Error> {
+    let Some(block) = yaml.get("capacity_reservation_specification") else {
+        return Ok(None);
+    };
+    let invalid = |message: &str| {
+        EC2Error::OptionsError(format!("capacity_reservation_specification: {}", message))
+    };
+    let preference: Option<ec2_types::CapacityReservationPreference> = enum_at(
+        block,
+        "capacity_reservation_specification",
+        "capacity_reservation_preference",
+        ec2_types::CapacityReservationPreference::values(),
+    )?;
+    let get = |key: &str| block.get(key).and_then(|v| v.as_str());
+    let id = get("capacity_reservation_id");
+    let group_arn = get("capacity_reservation_resource_group_arn");
+    if preference.is_some() && (id.is_some() || group_arn.is_some()) {
+        return Err(invalid(
+            "capacity_reservation_preference cannot be set together with a reservation",
+        ));
+    }
+    if id.is_some() && group_arn.is_some() {
+        return Err(invalid(
+            "capacity_reservation_id and capacity_reservation_resource_group_arn cannot be set \
+             together",
+        ));
+    }
+
+    let builder = ec2_types::CapacityReservationSpecification::builder();
+    let specification = if let Some(preference) = preference {
+        builder.capacity_reservation_preference(preference)
+    } else if id.is_some() || group_arn.is_some() {
+        builder.capacity_reservation_target(
+            ec2_types::CapacityReservationTarget::builder()
+                .set_capacity_reservation_id(id.map(|i| i.to_string()))
+                .set_capacity_reservation_resource_group_arn(group_arn.map(|a| a.to_string()))
+                .build(),
+        )
+    } else {
+        return Err(invalid(
+            "set capacity_reservation_preference, capacity_reservation_id or \
+             capacity_reservation_resource_group_arn",
+        ));
+    };
+    Ok(Some(specification.build()))
+}
+
+/// `maintenance_options: {auto_recovery: default | disabled}`
+fn maintenance_options_from_yaml(
+    yaml: &serde_yaml::Value,
+) -> Result<Option<ec2_types::InstanceMaintenanceOptionsRequest>, EC2Error> {
+    let Some(block) = yaml.get("maintenance_options") else {
+        return Ok(None);
+    };
+    let auto_recovery: Option<ec2_types::InstanceAutoRecoveryState> = enum_at(
+        block,
+        "maintenance_options",
+        "auto_recovery",
+        ec2_types::InstanceAutoRecoveryState::values(),
+    )?;
+    Ok(Some(
+        ec2_types::InstanceMaintenanceOptionsRequest::builder()
+            .set_auto_recovery(auto_recovery)
+            .build(),
+    ))
+}
+
+/// Anchor a relative `user_data_file` to `base_dir`, the directory of the config file naming it
+pub fn anchor_user_data_file(properties: &mut serde_yaml::Value, base_dir: &std::path::Path) {
+    let Some(file) = properties.get_mut("user_data_file") else {
+        return;
+    };
+    let anchored = match file.as_str().map(std::path::Path::new) {
+        Some(path) if path.is_relative() => base_dir.join(path),
+        _ => return,
+    };
+    *file = serde_yaml::Value::String(anchored.display().to_string());
+}
+
+/// Filters of an `ami_lookup:` block, resolved to an AMI id in place of a hardcoded `ami`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmiLookup {
+    pub owners: Vec<String>,  // Account ids or aliases such as `amazon`
+    pub name_pattern: String, // `*` wildcards allowed
+    pub architecture: Option<String>,
+    pub most_recent: bool, // Pick the newest of several matches, and look it up again on every run
+}
+
+impl AmiLookup {
+    /// The `ami_lookup` block of the instance properties, None when there is none
+    pub fn from_yaml(properties: &serde_yaml::Value) -> Result<Option<Self>, EC2Error> {
+        let Some(block) = properties.get("ami_lookup") else {
+            return Ok(None);
+        };
+        let invalid = |message: &str| EC2Error::OptionsError(format!("ami_lookup: {}", message));
+        if !block.is_mapping() {
+            return Err(invalid("expected a map with owners and name_pattern"));
+        }
+        let owners: Vec<String> = block
+            .get("owners")
+            .and_then(|o| o.as_sequence())
+            .map(|o| {
+                o.iter()
+                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if owners.is_empty() {
+            return Err(invalid(
+                "owners needs at least one owner, e.g. amazon or an account id",
+            ));
+        }
+        let name_pattern = block
+            .get("name_pattern")
+            .and_then(|p| p.as_str())
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| invalid("name_pattern is required, e.g. al2023-ami-*-x86_64"))?;
+        Ok(Some(AmiLookup {
+            owners,
+            name_pattern: name_pattern.to_string(),
+            architecture: block
+                .get("architecture")
+                .and_then(|a| a.as_str())
+                .map(|a| a.to_string()),
+            most_recent: block
+                .get("most_recent")
+                .and_then(|m| m.as_bool())
+                .unwrap_or(false),
+        }))
+    }
+
+    /// The filters as shown in errors, also recorded in state to notice when they change
+    pub fn filters(&self) -> String {
+        let mut filters = format!(
+            "owners={}, name={}",
+            self.owners.join(","),
+            self.name_pattern
+        );
+        if let Some(architecture) = &self.architecture {
+            filters.push_str(&format!(", architecture={}", architecture));
+        }
+        filters
+    }
+}
+
+/**
+ * Pick the AMI a lookup resolves to from the images DescribeImages returned. Several matches are
+ * only accepted with `most_recent`, which takes the newest by creation date; otherwise the error
+ * lists the newest matches so the name pattern can be narrowed.
+ */
+pub fn select_image(images: &[ec2_types::Image], lookup: &AmiLookup) -> Result<String, EC2Error> {
+    let mut matches: Vec<&ec2_types::Image> = images
+        .iter()
+        .filter(|image| image.image_id().is_some())
+        .collect();
+    // ISO 8601 dates sort chronologically as strings
+    matches.sort_by(|a, b| b.creation_date().cmp(&a.creation_date()));
+    match matches.as_slice() {
+        [] => Err(EC2Error::OptionsError(format!(
+            "No available AMI matches ami_lookup ({})",
+            lookup.filters()
+        ))),
+        [newest, rest @ ..] if rest.is_empty() || lookup.most_recent => {
+            Ok(newest.image_id().unwrap_or_default().to_string())
+        }
+        _ => {
+            let top: Vec<String> = matches
+                .iter()
+                .take(5)
+                .map(|image| {
+                    format!(
+                        "{} ({}, {})",
+                        image.image_id().unwrap_or_default(),
+                        image.name().unwrap_or("-"),
+                        image.creation_date().unwrap_or("-")
+                    )
+                })
+                .collect();
+            Err(EC2Error::OptionsError(format!(
+                "ami_lookup ({}) matches {} AMIs, narrow name_pattern or set most_recent: true. Newest: {}",
+                lookup.filters(),
+                matches.len(),
+                top.join(", ")
+            )))
+        }
+    }
+}
+
 impl EC2Instance {
     pub fn new(client: aws_sdk_ec2::Client) -> Self {
-        EC2Instance { client }
+        EC2Instance {
+            client,
+            timeouts: ResourceTimeouts::default(),
+            wait: WaitDefaults::default(),
+        }
     }
 
     pub fn from_config(config: &aws_types::SdkConfig) -> Self {
-        let client = aws_sdk_ec2::Client::new(config);
-        EC2Instance { client }
+        EC2Instance::new(aws_sdk_ec2::Client::new(config))
+    }
+
+    /// Wait as long as the `timeouts:` block of the component allows instead of the defaults
+    pub fn with_timeouts(mut self, timeouts: ResourceTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
     }
 
     pub fn opts_from_yaml(yaml: &serde_yaml::Value) -> Result<InstanceOpts, EC2Error> {
@@ -141,7 +816,7 @@ impl EC2Instance {
         let key_name = get_string(yaml, "key_name");
         let subnet_id = get_string(yaml, "subnet_id");
         let private_ip_address = get_string(yaml, "private_ip_address");
-        let user_data = get_string(yaml, "user_data");
+        let user_data = user_data_from_yaml(yaml)?.map(Sensitive::new);
         let client_token = get_string(yaml, "client_token");
         let disable_api_termination = get_bool(yaml, "disable_api_termination");
         let ebs_optimized = get_bool(yaml, "ebs_optimized");
@@ -151,6 +826,22 @@ impl EC2Instance {
             get_string(yaml, "instance_initiated_shutdown_behavior");
         let security_group_ids = get_string_vec(yaml, "security_group_ids");
         let security_groups = get_string_vec(yaml, "security_groups");
+        let wait_for_status_checks = get_bool(yaml, "wait_for_status_checks").unwrap_or(false);
+        let status_checks_timeout = yaml
+            .get("status_checks_timeout")
+            .and_then(|t| t.as_u64())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_STATUS_CHECKS_TIMEOUT);
+        let spot_fulfillment_timeout = yaml
+            .get("spot_fulfillment_timeout")
+            .and_then(|t| t.as_u64())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_SPOT_FULFILLMENT_TIMEOUT);
+        let public_ip_timeout = yaml
+            .get("public_ip_timeout")
+            .and_then(|t| t.as_u64())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_PUBLIC_IP_TIMEOUT);
 
         // Parse nested optional structures
         let monitoring = yaml.get("monitoring").and_then(|m| {
@@ -406,6 +1097,11 @@ impl EC2Instance {
             })
         });
 
+        let launch_template = launch_template_from_yaml(yaml)?;
+        let instance_market_options = market_options_from_yaml(yaml)?;
+        let capacity_reservation_specification = capacity_reservation_from_yaml(yaml)?;
+        let maintenance_options = maintenance_options_from_yaml(yaml)?;
+
         // Parse IPv6 addresses
         let ipv6_addresses = yaml.get("ipv6_addresses").and_then(|ipv6| {
             ipv6.as_sequence().map(|addresses| {
@@ -425,7 +1121,7 @@ impl EC2Instance {
 
         let opts = InstanceOpts {
             block_device_mappings,
-            capacity_reservation_specification: None, // Complex nested structure
+            capacity_reservation_specification,
             client_token,
             cpu_options,
             credit_specification,
@@ -437,13 +1133,13 @@ impl EC2Instance {
             iam_instance_profile,
             image_id,
             instance_initiated_shutdown_behavior,
-            instance_market_options: None, // Complex nested structure
+            instance_market_options,
             instance_type,
             ipv6_address_count,
             ipv6_addresses,
             key_name,
-            launch_template: None,     // Complex nested structure
-            maintenance_options: None, // Complex nested structure
+            launch_template,
+            maintenance_options,
             max_count,
             metadata_options,
             min_count,
@@ -457,26 +1153,178 @@ impl EC2Instance {
             subnet_id,
             tag_specifications,
             user_data,
+            wait_for_status_checks,
+            status_checks_timeout,
+            spot_fulfillment_timeout,
+            public_ip_timeout,
+            component_id: None,
         };
 
         Ok(opts)
     }
 
     pub async fn start_instance(&self, instance_id: &str) -> Result<(), EC2Error> {
-        self.client
-            .start_instances()
-            .instance_ids(instance_id)
-            .send()
-            .await?;
+        let request = self.client.start_instances().instance_ids(instance_id);
+        retry_throttled(&RetryPolicy::from_env(), "StartInstances", || {
+            request.clone().send()
+        })
+        .await?;
         Ok(())
     }
 
     pub async fn stop_instance(&self, instance_id: &str) -> Result<(), EC2Error> {
-        self.client
-            .stop_instances()
-            .instance_ids(instance_id)
+        let request = self.client.stop_instances().instance_ids(instance_id);
+        retry_throttled(&RetryPolicy::from_env(), "StopInstances", || {
+            request.clone().send()
+        })
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * Bring an existing instance in line with `opts`.
+     * Attributes that can change in place are modified one ModifyInstanceAttribute call each, a
+     * running instance is stopped first and started again when the instance type or user data
+     * change. Properties that cannot change fail with RequiresReplacement before anything is
+     * modified. Returns the instance as it is afterwards and what was changed.
+     */
+    pub async fn update_instance(
+        &self,
+        instance_id: &str,
+        opts: &InstanceOpts,
+    ) -> Result<(ec2_types::Instance, InstanceChanges), EC2Error> {
+        let current = self.describe_instance(instance_id).await?;
+        let reasons = opts.replacement_reasons(&current);
+        if !reasons.is_empty() {
+            return Err(EC2Error::RequiresReplacement(format!(
+                "{} ({})",
+                instance_id,
+                reasons.join(", ")
+            )));
+        }
+        let user_data = self
+            .client
+            .describe_instance_attribute()
+            .instance_id(instance_id)
+            .attribute(ec2_types::InstanceAttributeName::UserData)
+            .send()
+            .await?;
+        let termination = self
+            .client
+            .describe_instance_attribute()
+            .instance_id(instance_id)
+            .attribute(ec2_types::InstanceAttributeName::DisableApiTermination)
             .send()
             .await?;
+        let changes = opts.changes(
+            &current,
+            user_data.user_data().and_then(|v| v.value()),
+            termination
+                .disable_api_termination()
+                .and_then(|v| v.value()),
+        );
+        if changes.is_empty() {
+            info!("EC2 instance {} is up to date", instance_id);
+            return Ok((current, changes));
+        }
+        info!(
+            "Updating EC2 instance {}: {}",
+            instance_id,
+            changes.changed().join(", ")
+        );
+
+        let running =
+            current.state().and_then(|s| s.name()) == Some(&ec2_types::InstanceStateName::Running);
+        let restart = running && changes.needs_stop();
+        if restart {
+            self.stop_instance(instance_id).await?;
+            self.wait_until_stopped(instance_id).await?;
+        }
+        if let Some(instance_type) = &changes.instance_type {
+            self.client
+                .modify_instance_attribute()
+                .instance_id(instance_id)
+                .instance_type(
+                    ec2_types::AttributeValue::builder()
+                        .value(instance_type.as_str())
+                        .build(),
+                )
+                .send()
+                .await?;
+        }
+        if let Some(user_data) = &changes.user_data {
+            // The SDK base64 encodes blobs itself, it wants the raw bytes
+            let bytes = base64::decode(user_data).ok_or_else(|| {
+                EC2Error::OptionsError("user_data is not valid base64".to_string())
+            })?;
+            self.client
+                .modify_instance_attribute()
+                .instance_id(instance_id)
+                .user_data(
+                    ec2_types::BlobAttributeValue::builder()
+                        .value(aws_sdk_ec2::primitives::Blob::new(bytes))
+                        .build(),
+                )
+                .send()
+                .await?;
+        }
+        if let Some(disabled) = changes.disable_api_termination {
+            self.client
+                .modify_instance_attribute()
+                .instance_id(instance_id)
+                .disable_api_termination(
+                    ec2_types::AttributeBooleanValue::builder()
+                        .value(disabled)
+                        .build(),
+                )
+                .send()
+                .await?;
+        }
+        if let Some(group_ids) = &changes.security_group_ids {
+            self.client
+                .modify_instance_attribute()
+                .instance_id(instance_id)
+                .set_groups(Some(group_ids.clone()))
+                .send()
+                .await?;
+        }
+
+        let instance = if restart {
+            self.start_instance(instance_id).await?;
+            self.wait_until_running(vec![instance_id.to_string()], opts, self.timeouts.update)
+                .await?
+                .into_iter()
+                .next()
+                .ok_or(EC2Error::InstanceNotCreated)?
+        } else {
+            self.describe_instance(instance_id).await?
+        };
+        info!("EC2 instance {} updated", instance_id);
+        Ok((instance, changes))
+    }
+
+    /// Wait for an instance that is being stopped to reach `stopped`
+    async fn wait_until_stopped(&self, instance_id: &str) -> Result<(), EC2Error> {
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ec2_types::InstanceStateName::Stopped.to_string()],
+            vec![
+                ec2_types::InstanceStateName::Running.to_string(),
+                ec2_types::InstanceStateName::Stopping.to_string(),
+            ],
+            Box::new(EC2Instance::wait_for_completion),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                instance_id.to_string(),
+            )
+            .await?;
         Ok(())
     }
 
@@ -505,15 +1353,274 @@ impl EC2Instance {
         Err(EC2Error::InstanceNotFound)
     }
 
-    pub async fn terminate_instance(&self, instance_id: &str) -> Result<(), EC2Error> {
-        self.client
-            .terminate_instances()
-            .instance_ids(instance_id)
+    /**
+     * Read an instance recorded in state, `None` when it no longer exists.
+     * NotFound within the not-found grace window of `created_at` is retried instead of being
+     * reported as a deletion.
+     */
+    pub async fn read_instance(
+        &self,
+        instance_id: &str,
+        created_at: Option<std::time::SystemTime>,
+    ) -> Result<Option<aws_sdk_ec2::types::Instance>, EC2Error> {
+        let resource = read_with_grace(
+            Box::new(EC2Instance::wait_for_completion),
+            AWSClient::EC2Client(self.client.clone()),
+            instance_id.to_string(),
+            created_at,
+            &self.wait,
+        )
+        .await?;
+        Ok(resource.and_then(|r| {
+            r.downcast::<aws_sdk_ec2::types::Instance>()
+                .ok()
+                .map(|i| *i)
+        }))
+    }
+
+    /// Most recent available AMI of an owner whose name matches the pattern (`*` wildcards allowed)
+    pub async fn find_latest_image(
+        &self,
+        owner: &str,
+        name_pattern: &str,
+    ) -> Result<String, EC2Error> {
+        let resp = self
+            .client
+            .describe_images()
+            .owners(owner)
+            .filters(
+                ec2_types::Filter::builder()
+                    .name("name")
+                    .values(name_pattern)
+                    .build(),
+            )
+            .filters(
+                ec2_types::Filter::builder()
+                    .name("state")
+                    .values("available")
+                    .build(),
+            )
             .send()
             .await?;
+
+        resp.images()
+            .iter()
+            .filter(|image| image.image_id().is_some())
+            .max_by_key(|image| image.creation_date().unwrap_or_default().to_string())
+            .and_then(|image| image.image_id())
+            .map(|id| id.to_string())
+            .ok_or_else(|| {
+                EC2Error::OptionsError(format!("No AMI of '{}' matches '{}'", owner, name_pattern))
+            })
+    }
+
+    /// AMI id an `ami_lookup` block resolves to, see `select_image`
+    pub async fn lookup_image(&self, lookup: &AmiLookup) -> Result<String, EC2Error> {
+        let filter = |name: &str, value: &str| {
+            ec2_types::Filter::builder()
+                .name(name)
+                .values(value)
+                .build()
+        };
+        let mut request = self
+            .client
+            .describe_images()
+            .set_owners(Some(lookup.owners.clone()))
+            .filters(filter("name", &lookup.name_pattern))
+            .filters(filter("state", "available"));
+        if let Some(architecture) = &lookup.architecture {
+            request = request.filters(filter("architecture", architecture));
+        }
+        let resp = request.send().await?;
+        select_image(resp.images(), lookup)
+    }
+
+    /// Architecture and virtualization type of an AMI
+    pub async fn describe_image_architecture(
+        &self,
+        image_id: &str,
+    ) -> Result<ImageArchitecture, EC2Error> {
+        let resp = self
+            .client
+            .describe_images()
+            .image_ids(image_id)
+            .send()
+            .await?;
+
+        let image = resp
+            .images()
+            .first()
+            .ok_or_else(|| EC2Error::OptionsError(format!("AMI '{}' was not found", image_id)))?;
+        Ok(ImageArchitecture {
+            architecture: image
+                .architecture()
+                .map(|a| a.as_str().to_string())
+                .unwrap_or_default(),
+            virtualization_type: image.virtualization_type().map(|v| v.as_str().to_string()),
+        })
+    }
+
+    /// Architectures and virtualization types supported by an instance type
+    pub async fn describe_instance_type_support(
+        &self,
+        instance_type: &ec2_types::InstanceType,
+    ) -> Result<InstanceTypeSupport, EC2Error> {
+        let resp = self
+            .client
+            .describe_instance_types()
+            .instance_types(instance_type.clone())
+            .send()
+            .await?;
+
+        let info = resp.instance_types().first().ok_or_else(|| {
+            EC2Error::OptionsError(format!(
+                "Instance type '{}' is not offered in this region",
+                instance_type.as_str()
+            ))
+        })?;
+        Ok(InstanceTypeSupport {
+            architectures: info
+                .processor_info()
+                .map(|p| {
+                    p.supported_architectures()
+                        .iter()
+                        .map(|a| a.as_str().to_string())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            virtualization_types: info
+                .supported_virtualization_types()
+                .iter()
+                .map(|v| v.as_str().to_string())
+                .collect(),
+        })
+    }
+
+    /// Availability zones of the current region offering the instance type
+    pub async fn describe_instance_type_offerings(
+        &self,
+        instance_type: &ec2_types::InstanceType,
+    ) -> Result<Vec<String>, EC2Error> {
+        let resp = self
+            .client
+            .describe_instance_type_offerings()
+            .location_type(ec2_types::LocationType::AvailabilityZone)
+            .filters(
+                ec2_types::Filter::builder()
+                    .name("instance-type")
+                    .values(instance_type.as_str())
+                    .build(),
+            )
+            .send()
+            .await?;
+
+        Ok(resp
+            .instance_type_offerings()
+            .iter()
+            .filter_map(|o| o.location().map(|l| l.to_string()))
+            .collect())
+    }
+
+    /// Availability zone a subnet lives in
+    pub async fn describe_subnet_availability_zone(
+        &self,
+        subnet_id: &str,
+    ) -> Result<String, EC2Error> {
+        let resp = self
+            .client
+            .describe_subnets()
+            .subnet_ids(subnet_id)
+            .send()
+            .await?;
+
+        resp.subnets()
+            .first()
+            .and_then(|s| s.availability_zone())
+            .map(|az| az.to_string())
+            .ok_or_else(|| EC2Error::OptionsError(format!("Subnet '{}' was not found", subnet_id)))
+    }
+
+    pub async fn terminate_instance(&self, instance_id: &str) -> Result<(), EC2Error> {
+        let request = self.client.terminate_instances().instance_ids(instance_id);
+        retry_throttled(&RetryPolicy::from_env(), "TerminateInstances", || {
+            request.clone().send()
+        })
+        .await?;
         Ok(())
     }
 
+    /**
+     * Terminate an instance and wait until it reports `terminated`.
+     * An instance that is already gone is not an error, the destroy is reported as AlreadyGone.
+     */
+    pub async fn destroy_instance(&self, instance_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        // A persistent Spot request would launch a replacement, so it is cancelled first
+        let spot_request_id = match self.describe_instance(instance_id).await {
+            Ok(instance) => instance.spot_instance_request_id().map(|id| id.to_string()),
+            Err(EC2Error::InstanceNotFound) => None,
+            Err(err) => return Err(err),
+        };
+        if let Some(spot_request_id) = &spot_request_id {
+            self.cancel_spot_request(spot_request_id).await?;
+        }
+        match self.terminate_instance(instance_id).await {
+            Ok(()) => {}
+            Err(EC2Error::InstanceNotFound) => {
+                info!("EC2 instance {} is already gone", instance_id);
+                return Ok(DestroyOutcome::AlreadyGone);
+            }
+            Err(err) => return Err(err),
+        }
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ec2_types::InstanceStateName::Terminated.to_string()],
+            vec![], // running, stopping and shutting-down can all show up before terminated
+            Box::new(EC2Instance::wait_for_completion),
+            None,
+            self.timeouts.delete,
+            None,
+            None,
+            None,
+        );
+        match wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                instance_id.to_string(),
+            )
+            .await
+        {
+            Ok(_) => {}
+            // Terminated instances eventually disappear from DescribeInstances
+            Err(WaitError::NotFound { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+        info!("EC2 instance {} terminated", instance_id);
+        Ok(DestroyOutcome::Terminated)
+    }
+
+    /// Cancel a Spot request, a request that is already gone counts as cancelled
+    pub async fn cancel_spot_request(&self, spot_request_id: &str) -> Result<(), EC2Error> {
+        let request = self
+            .client
+            .cancel_spot_instance_requests()
+            .spot_instance_request_ids(spot_request_id);
+        match retry_throttled(
+            &RetryPolicy::from_env(),
+            "CancelSpotInstanceRequests",
+            || request.clone().send(),
+        )
+        .await
+        {
+            Ok(_) => {
+                info!("Spot request {} cancelled", spot_request_id);
+                Ok(())
+            }
+            Err(err) if err.code() == Some("InvalidSpotInstanceRequestID.NotFound") => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
     pub async fn list_instances(&self) -> Result<Vec<aws_sdk_ec2::types::Instance>, EC2Error> {
         let resp = self.client.describe_instances().send().await?;
         let mut instances = Vec::new();
@@ -529,16 +1636,91 @@ impl EC2Instance {
         Ok(instances)
     }
 
+    /// Instances already created for a component, terminated and terminating ones are ignored
+    pub async fn find_component_instances(
+        &self,
+        component_id: &str,
+    ) -> Result<Vec<aws_sdk_ec2::types::Instance>, EC2Error> {
+        let resp = self
+            .client
+            .describe_instances()
+            .filters(
+                ec2_types::Filter::builder()
+                    .name(format!("tag:{}", COMPONENT_TAG))
+                    .values(component_id)
+                    .build(),
+            )
+            .filters(
+                ec2_types::Filter::builder()
+                    .name("instance-state-name")
+                    .values("pending")
+                    .values("running")
+                    .values("stopping")
+                    .values("stopped")
+                    .build(),
+            )
+            .send()
+            .await?;
+        Ok(resp
+            .reservations()
+            .iter()
+            .flat_map(|r| r.instances())
+            .cloned()
+            .collect())
+    }
+
     /**
-     * Creates an EC2 instance with the given configuration options.
-     * This will wait until the instance is in the 'running' state before returning.
+     * Creates an EC2 instance with the given configuration options and returns the first one
+     * launched, see `create_instances` for options with a `max_count` above 1.
      */
     pub async fn create_instance(
         &self,
         config: &InstanceOpts,
     ) -> Result<aws_sdk_ec2::types::Instance, EC2Error> {
-        tracing::info!("Creating EC2 instance with config: {:?}", config);
-        println!("Creating EC2 instance with config: {:?}", config);
+        self.create_instances(config)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or(EC2Error::InstanceNotCreated)
+    }
+
+    /**
+     * Creates the EC2 instances of the given configuration options, between `min_count` and
+     * `max_count` of them. This will wait until every instance is in the 'running' state before
+     * returning. When the options belong to a component that already has live instances, those
+     * instances are returned instead of launching more.
+     */
+    pub async fn create_instances(
+        &self,
+        config: &InstanceOpts,
+    ) -> Result<Vec<aws_sdk_ec2::types::Instance>, EC2Error> {
+        if let Some(component_id) = &config.component_id {
+            let existing = self.find_component_instances(component_id).await?;
+            if !existing.is_empty() {
+                let instance_ids: Vec<&str> =
+                    existing.iter().filter_map(|i| i.instance_id()).collect();
+                info!(
+                    "EC2 instances {} already exist for {}, not creating more",
+                    instance_ids.join(", "),
+                    component_id
+                );
+                let pending = existing.iter().any(|instance| {
+                    instance.state().and_then(|s| s.name())
+                        == Some(&ec2_types::InstanceStateName::Pending)
+                });
+                if !pending {
+                    return Ok(existing);
+                }
+                let instance_ids = existing
+                    .iter()
+                    .map(|i| i.instance_id().unwrap_or_default().to_string())
+                    .collect();
+                return self
+                    .wait_until_running(instance_ids, config, self.timeouts.create)
+                    .await;
+            }
+        }
+
         let config_clone = config.clone();
         let mut request = self
             .client
@@ -641,41 +1823,357 @@ impl EC2Instance {
             request = request.set_tag_specifications(Some(tag_specifications));
         }
         if let Some(user_data) = &config_clone.user_data {
-            request = request.user_data(user_data);
+            request = request.user_data(&**user_data);
         }
         info!("Creating EC2 instance with config: {:?}", &config);
-        let resp = request.send().await?;
+        // Safe to send again, with a client token a retried RunInstances returns the same instance
+        let policy = RetryPolicy::from_env();
+        let resp = if config.is_spot() {
+            let budget = config.spot_fulfillment_timeout;
+            retry_capacity(&policy, budget, "RunInstances", || request.clone().send())
+                .await
+                .map_err(|err| match err.code() {
+                    Some(code) if is_capacity_unavailable(code) => {
+                        EC2Error::SpotRequestFailed(format!(
+                            "{} after {:?}: {}",
+                            code,
+                            budget,
+                            err.message().unwrap_or_default()
+                        ))
+                    }
+                    _ => err.into(),
+                })?
+        } else {
+            retry_throttled(&policy, "RunInstances", || request.clone().send()).await?
+        };
+
+        if resp.instances().is_empty() {
+            info!("EC2 instance creation failed: No instances returned");
+            return Err(EC2Error::InstanceNotCreated);
+        }
+        if resp.instances().len() > 1 {
+            info!(
+                "Launched {} EC2 instances, waiting for all of them to be running",
+                resp.instances().len()
+            );
+        }
+        let mut instance_ids = Vec::with_capacity(resp.instances().len());
+        for instance in resp.instances() {
+            let instance_id = instance.instance_id().unwrap_or_default().to_string();
+            // A reused client token returns the instance it first created, even once terminated
+            if matches!(
+                instance.state().and_then(|s| s.name()),
+                Some(ec2_types::InstanceStateName::Terminated)
+                    | Some(ec2_types::InstanceStateName::ShuttingDown)
+            ) {
+                return Err(EC2Error::OptionsError(format!(
+                    "client token {} belongs to instance {} which is terminated, set another client_token to create a new instance",
+                    config.client_token.as_deref().unwrap_or_default(),
+                    instance_id
+                )));
+            }
+            if let Some(spot_request_id) = instance.spot_instance_request_id() {
+                self.wait_for_spot_request(spot_request_id, &instance_id, config)
+                    .await?;
+            }
+            instance_ids.push(instance_id);
+        }
+        self.wait_until_running(instance_ids, config, self.timeouts.create)
+            .await
+    }
 
+    /**
+     * Wait for the Spot request of a new instance to be fulfilled. The status code of the request
+     * is printed while it waits. A request that fails, or is still short of capacity once
+     * `spot_fulfillment_timeout` has passed, is cancelled along with its instance.
+     */
+    async fn wait_for_spot_request(
+        &self,
+        spot_request_id: &str,
+        instance_id: &str,
+        config: &InstanceOpts,
+    ) -> Result<(), EC2Error> {
         let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![SPOT_FULFILLED.to_string()],
+            vec![], // Any open status code keeps waiting, failed requests end the refresh
+            Box::new(EC2Instance::refresh_spot_request),
+            None,
+            Some(config.spot_fulfillment_timeout),
+            None,
+            None,
+            None,
+        );
+        let failure = match wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                spot_request_id.to_string(),
+            )
+            .await
+        {
+            Ok(_) => {
+                info!(
+                    "Spot request {} fulfilled by {}",
+                    spot_request_id, instance_id
+                );
+                return Ok(());
+            }
+            Err(WaitError::Timeout {
+                last_state,
+                timeout,
+                ..
+            }) => format!(
+                "{} still {} after {:?}",
+                spot_request_id, last_state, timeout
+            ),
+            Err(WaitError::RefreshError(message)) => message,
+            Err(err) => format!("{}: {}", spot_request_id, err),
+        };
+        // Best effort, the failure is what gets reported
+        if let Err(err) = self.cancel_spot_request(spot_request_id).await {
+            tracing::warn!("Failed to cancel Spot request {}: {}", spot_request_id, err);
+        }
+        if let Err(err) = self.terminate_instance(instance_id).await {
+            tracing::warn!("Failed to terminate Spot instance {}: {}", instance_id, err);
+        }
+        Err(EC2Error::SpotRequestFailed(failure))
+    }
+
+    fn refresh_spot_request(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for Spot request".to_string()),
+            };
+
+            let resp = match ec2_client
+                .describe_spot_instance_requests()
+                .spot_instance_request_ids(resource_id.clone())
+                .send()
+                .await
+            {
+                Ok(resp) => resp,
+                // Like instance ids, a new request id can be unknown for a few seconds
+                Err(e) if e.code() == Some("InvalidSpotInstanceRequestID.NotFound") => {
+                    return Ok(None);
+                }
+                Err(e) => return Err(format!("Failed to describe Spot request: {}", e)),
+            };
+            let Some(request) = resp.spot_instance_requests().first() else {
+                return Ok(None);
+            };
+            let state = request.state().map(|s| s.as_str()).unwrap_or("unknown");
+            let status = request.status();
+            let code = status.and_then(|s| s.code()).unwrap_or("unknown");
+            match spot_progress(state, code) {
+                SpotProgress::Fulfilled => Ok(Some((
+                    Box::new(request.clone()) as Box<dyn Any + Send>,
+                    vec![SPOT_FULFILLED.to_string()],
+                ))),
+                SpotProgress::Pending(code) | SpotProgress::WaitingForCapacity(code) => {
+                    info!("Spot request {}: {}", resource_id, code);
+                    Ok(Some((
+                        Box::new(request.clone()) as Box<dyn Any + Send>,
+                        vec![code],
+                    )))
+                }
+                SpotProgress::Failed(code) => Err(format!(
+                    "{} is {} ({}): {}",
+                    resource_id,
+                    state,
+                    code,
+                    status.and_then(|s| s.message()).unwrap_or("-")
+                )),
+            }
+        })
+    }
+
+    /**
+     * Wait for new instances to be running, their status checks are left to the caller, see
+     * `wait_for_status_checks`. The instances are polled together, the wait is over once every
+     * one of them is running.
+     * `timeout` bounds the wait for running, the default wait timeout applies when it is None.
+     * Instances that asked for a public IP are then given `public_ip_timeout` to get it.
+     */
+    async fn wait_until_running(
+        &self,
+        instance_ids: Vec<String>,
+        config: &InstanceOpts,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<aws_sdk_ec2::types::Instance>, EC2Error> {
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
             vec![ec2_types::InstanceStateName::Running.to_string()],
             vec![ec2_types::InstanceStateName::Pending.to_string()],
-            Box::new(EC2Instance::wait_for_completion),
+            Box::new(EC2Instance::refresh_instances),
+            None,
+            timeout,
+            None,
+            None,
             None,
+        );
+        let result = wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                instance_ids.join(","),
+            )
+            .await?;
+        let Some(created_instances) = result else {
+            info!("EC2 instance creation failed: No instances returned");
+            return Err(EC2Error::InstanceNotCreated);
+        };
+        info!(
+            "EC2 instances created successfully: {:?}",
+            created_instances
+        );
+        let instances = *created_instances
+            .downcast::<Vec<aws_sdk_ec2::types::Instance>>()
+            .map_err(|_| EC2Error::InstanceNotCreated)?;
+        if config.requests_public_ip() {
+            return self
+                .wait_for_public_ips(instances, config.public_ip_timeout)
+                .await;
+        }
+        Ok(instances)
+    }
+
+    /**
+     * Wait for every instance to have a public IP, AWS can assign it a little after the instance
+     * is running. Past `timeout` the instances are returned as they are, each one still without
+     * an address is reported as an AWS3014 warning.
+     */
+    async fn wait_for_public_ips(
+        &self,
+        instances: Vec<aws_sdk_ec2::types::Instance>,
+        timeout: Duration,
+    ) -> Result<Vec<aws_sdk_ec2::types::Instance>, EC2Error> {
+        if instances.iter().all(|i| i.public_ip_address().is_some()) {
+            return Ok(instances);
+        }
+        let instance_ids: Vec<String> = instances
+            .iter()
+            .filter_map(|i| i.instance_id())
+            .map(|id| id.to_string())
+            .collect();
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![PUBLIC_IP_ASSIGNED.to_string()],
+            vec![PUBLIC_IP_PENDING.to_string()],
+            Box::new(EC2Instance::refresh_public_ips),
             None,
+            Some(timeout),
             None,
             None,
             None,
         );
-        if let Some(instances) = resp.instances {
-            let result = wait_state_config
-                .wait_until_state(
-                    AWSClient::EC2Client(self.client.clone()),
-                    instances[0].instance_id.as_ref().unwrap().clone(),
-                )
-                .await?;
-            if let Some(created_instances) = result {
-                info!("EC2 instance created successfully: {:?}", created_instances);
-                return Ok(*created_instances
-                    .downcast::<aws_sdk_ec2::types::Instance>()
-                    .unwrap());
-            }
+        let result = wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                instance_ids.join(","),
+            )
+            .await;
+        let instances = match result {
+            Ok(Some(found)) => *found
+                .downcast::<Vec<aws_sdk_ec2::types::Instance>>()
+                .map_err(|_| EC2Error::InstanceNotCreated)?,
+            // Not an error, the instances are read once more with whatever they have by now
+            Ok(None) | Err(WaitError::Timeout { .. }) => self
+                .client
+                .describe_instances()
+                .set_instance_ids(Some(instance_ids))
+                .send()
+                .await?
+                .reservations()
+                .iter()
+                .flat_map(|r| r.instances())
+                .cloned()
+                .collect(),
+            Err(err) => return Err(err.into()),
+        };
+        for instance in instances.iter().filter(|i| i.public_ip_address().is_none()) {
+            let message = format!(
+                "EC2 instance {} has no public IP after {:?}, its public_ip and public_dns outputs are empty",
+                instance.instance_id().unwrap_or_default(),
+                timeout
+            );
+            tracing::warn!("warning[AWS3014]: {}", message);
         }
+        Ok(instances)
+    }
+
+    /// Like `refresh_instances`, with one state per instance telling whether it has a public IP
+    fn refresh_public_ips(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let Some((instances, _)) = EC2Instance::refresh_instances(client, resource_id).await?
+            else {
+                return Ok(None);
+            };
+            let states = instances
+                .downcast_ref::<Vec<aws_sdk_ec2::types::Instance>>()
+                .map(|instances| {
+                    instances
+                        .iter()
+                        .map(|i| match i.public_ip_address() {
+                            Some(_) => PUBLIC_IP_ASSIGNED.to_string(),
+                            None => PUBLIC_IP_PENDING.to_string(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            Ok(Some((instances, states)))
+        })
+    }
 
-        info!("EC2 instance creation failed: No instances returned");
-        Err(EC2Error::InstanceNotCreated)
+    /**
+     * Waits until both the system and the instance status checks report `ok`, returns how long
+     * that took. A running instance can still fail these checks and be unusable, an impaired
+     * check fails the wait with the check in its unexpected state.
+     */
+    pub async fn wait_for_status_checks(
+        &self,
+        instance_id: String,
+        timeout: Duration,
+    ) -> Result<Duration, EC2Error> {
+        let started = std::time::Instant::now();
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![
+                format!("system:{}", STATUS_CHECK_OK),
+                format!("instance:{}", STATUS_CHECK_OK),
+            ],
+            // Any combination of these keeps waiting, `impaired` and the rest end it
+            ["system", "instance"]
+                .iter()
+                .flat_map(|check| {
+                    STATUS_CHECK_PENDING
+                        .iter()
+                        .chain([&STATUS_CHECK_OK])
+                        .map(move |status| format!("{}:{}", check, status))
+                })
+                .collect(),
+            Box::new(EC2Instance::refresh_status_checks),
+            None,
+            Some(timeout),
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                instance_id.clone(),
+            )
+            .await?;
+
+        let elapsed = started.elapsed();
+        info!(
+            "Status checks passed for EC2 instance {} after {:?}",
+            instance_id, elapsed
+        );
+        Ok(elapsed)
     }
 
-    fn wait_for_completion(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+    fn refresh_status_checks(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
         Box::pin(async move {
             let ec2_client = match client {
                 AWSClient::EC2Client(c) => c,
@@ -683,11 +2181,50 @@ impl EC2Instance {
             };
 
             let resp = ec2_client
+                .describe_instance_status()
+                .instance_ids(resource_id.clone())
+                .include_all_instances(true)
+                .send()
+                .await
+                .map_err(|e| format!("Failed to describe instance status: {}", e))?;
+
+            let Some(status) = resp.instance_statuses().first() else {
+                return Ok(None);
+            };
+            let summary = |s: Option<&ec2_types::InstanceStatusSummary>| {
+                s.and_then(|s| s.status())
+                    .map(|s| s.as_str().to_string())
+                    // No summary yet, the checks have not started
+                    .unwrap_or_else(|| STATUS_CHECK_PENDING[0].to_string())
+            };
+            Ok(Some((
+                Box::new(status.clone()) as Box<dyn Any + Send>,
+                vec![
+                    format!("system:{}", summary(status.system_status())),
+                    format!("instance:{}", summary(status.instance_status())),
+                ],
+            )))
+        })
+    }
+
+    fn wait_for_completion(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for EC2 instance".to_string()),
+            };
+
+            let resp = match ec2_client
                 .describe_instances()
                 .instance_ids(resource_id.clone())
                 .send()
                 .await
-                .map_err(|e| format!("Failed to describe instance: {}", e))?;
+            {
+                Ok(resp) => resp,
+                // A freshly launched id can be unknown for a few seconds, let the waiter retry it
+                Err(e) if e.code() == Some("InvalidInstanceID.NotFound") => return Ok(None),
+                Err(e) => return Err(format!("Failed to describe instance: {}", e)),
+            };
 
             if let Some(reservations) = resp.reservations {
                 for reservation in reservations {
@@ -701,13 +2238,61 @@ impl EC2Instance {
                                 .map(|n| n.as_str().to_string())
                                 .unwrap_or_else(|| "unknown".to_string());
 
-                            return Ok(Some((Box::new(instance) as Box<dyn Any>, vec![state])));
+                            return Ok(Some((
+                                Box::new(instance) as Box<dyn Any + Send>,
+                                vec![state],
+                            )));
                         }
                     }
                 }
             }
 
-            Err("Instance not found".to_string())
+            Ok(None)
+        })
+    }
+
+    /// Refresh for the comma separated instance ids of `resource_id`, one state per instance
+    fn refresh_instances(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for EC2 instance".to_string()),
+            };
+            let instance_ids: Vec<String> = resource_id.split(',').map(String::from).collect();
+
+            let resp = match ec2_client
+                .describe_instances()
+                .set_instance_ids(Some(instance_ids.clone()))
+                .send()
+                .await
+            {
+                Ok(resp) => resp,
+                // A freshly launched id can be unknown for a few seconds, let the waiter retry it
+                Err(e) if e.code() == Some("InvalidInstanceID.NotFound") => return Ok(None),
+                Err(e) => return Err(format!("Failed to describe instances: {}", e)),
+            };
+
+            let instances: Vec<aws_sdk_ec2::types::Instance> = resp
+                .reservations()
+                .iter()
+                .flat_map(|r| r.instances())
+                .cloned()
+                .collect();
+            // Not all of them are visible yet
+            if instances.len() < instance_ids.len() {
+                return Ok(None);
+            }
+            let states = instances
+                .iter()
+                .map(|instance| {
+                    instance
+                        .state()
+                        .and_then(|s| s.name())
+                        .map(|n| n.as_str().to_string())
+                        .unwrap_or_else(|| "unknown".to_string())
+                })
+                .collect();
+            Ok(Some((Box::new(instances) as Box<dyn Any + Send>, states)))
         })
     }
 }
@@ -0,0 +1,375 @@
+use std::any::Any;
+
+use aws_sdk_ec2::{error::ProvideErrorMetadata, types as ec2_types};
+use tracing::info;
+
+use crate::aws::{
+    AWSClient,
+    ec2::ec2_instance::{DestroyOutcome, EC2Error},
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tag_specification, tags_from_yaml},
+        wait_and_refresh::{
+            RefreshFunction, RefreshFunctionReturn, StateChangeConfig, WaitDefaults,
+        },
+    },
+};
+
+const ASSOCIATED: &str = "associated";
+const NOT_ASSOCIATED: &str = "not-associated";
+const INSTANCE_PENDING: &str = "instance-pending"; // The instance is not running yet
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ElasticIpOpts {
+    pub(crate) associate_with: Option<String>, // Instance id the address is associated with
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct ElasticIp {
+    client: aws_sdk_ec2::Client,
+    wait: WaitDefaults,
+}
+
+impl ElasticIpOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+impl ElasticIp {
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_ec2::Client::new(config);
+        ElasticIp {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// `associate_with` is an instance id by now, apply has filled in references to instances
+    pub fn opts_from_yaml(yaml: &serde_yaml::Value) -> Result<ElasticIpOpts, EC2Error> {
+        let associate_with = yaml
+            .get("associate_with")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
+        if let Some(instance_id) = associate_with.as_deref() {
+            if !instance_id.starts_with("i-") {
+                return Err(EC2Error::OptionsError(format!(
+                    "associate_with must be an instance id, got '{}'",
+                    instance_id
+                )));
+            }
+        }
+
+        Ok(ElasticIpOpts {
+            associate_with,
+            tags: tags_from_yaml(yaml).map_err(EC2Error::OptionsError)?,
+        })
+    }
+
+    /**
+     * Allocate a VPC address and associate it with `associate_with` when set. An address that
+     * cannot be associated is released again, it would otherwise be billed without being in state.
+     */
+    pub async fn allocate_address(
+        &self,
+        opts: &ElasticIpOpts,
+    ) -> Result<ec2_types::Address, EC2Error> {
+        info!("Allocating Elastic IP with config: {:?}", opts);
+        let tags = tag_specification(ec2_types::ResourceType::ElasticIp, &opts.tags);
+        let request = self
+            .client
+            .allocate_address()
+            .domain(ec2_types::DomainType::Vpc)
+            .set_tag_specifications(tags.map(|t| vec![t]));
+        let resp = retry_throttled(&RetryPolicy::from_env(), "AllocateAddress", || {
+            request.clone().send()
+        })
+        .await?;
+        let allocation_id = resp
+            .allocation_id()
+            .ok_or_else(|| EC2Error::SdkError("AllocateAddress returned no allocation id".into()))?
+            .to_string();
+        info!(
+            "Elastic IP {} allocated ({})",
+            allocation_id,
+            resp.public_ip().unwrap_or("-")
+        );
+
+        let Some(instance_id) = &opts.associate_with else {
+            return self.describe_address(&allocation_id).await;
+        };
+        match self.associate_address(&allocation_id, instance_id).await {
+            Ok(address) => Ok(address),
+            Err(err) => {
+                if let Err(release) = self.release_address(&allocation_id).await {
+                    tracing::warn!(
+                        "Failed to release Elastic IP {}: {}",
+                        allocation_id,
+                        release
+                    );
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /**
+     * Associate the address with `instance_id` once the instance is running, then wait until
+     * DescribeAddresses shows the association.
+     */
+    pub async fn associate_address(
+        &self,
+        allocation_id: &str,
+        instance_id: &str,
+    ) -> Result<ec2_types::Address, EC2Error> {
+        let instance = instance_id.to_string();
+        let try_associate: RefreshFunction = Box::new(move |client, resource_id| {
+            ElasticIp::try_associate(client, resource_id, instance.clone())
+        });
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ASSOCIATED.to_string()],
+            vec![INSTANCE_PENDING.to_string()],
+            try_associate,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                allocation_id.to_string(),
+            )
+            .await?;
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ASSOCIATED.to_string()],
+            vec![NOT_ASSOCIATED.to_string()],
+            Box::new(ElasticIp::wait_for_association),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let result = wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                allocation_id.to_string(),
+            )
+            .await?;
+        info!(
+            "Elastic IP {} associated with {}",
+            allocation_id, instance_id
+        );
+        match result.and_then(|a| a.downcast::<ec2_types::Address>().ok()) {
+            Some(address) => Ok(*address),
+            None => self.describe_address(allocation_id).await,
+        }
+    }
+
+    /// Disassociate the address and wait until DescribeAddresses no longer shows the association
+    pub async fn disassociate_address(
+        &self,
+        allocation_id: &str,
+        association_id: &str,
+    ) -> Result<(), EC2Error> {
+        let request = self
+            .client
+            .disassociate_address()
+            .association_id(association_id);
+        let disassociated =
+            retry_throttled(&RetryPolicy::from_env(), "DisassociateAddress", || {
+                request.clone().send()
+            })
+            .await;
+        match disassociated.map_err(EC2Error::from) {
+            Ok(_) | Err(EC2Error::ResourceNotFound(_)) => {}
+            Err(err) => return Err(err),
+        }
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![NOT_ASSOCIATED.to_string()],
+            vec![ASSOCIATED.to_string()],
+            Box::new(ElasticIp::wait_for_association),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                allocation_id.to_string(),
+            )
+            .await?;
+        info!("Elastic IP {} disassociated", allocation_id);
+        Ok(())
+    }
+
+    /// Move the association of an allocated address to `instance_id`, or remove it when None
+    pub async fn update_association(
+        &self,
+        allocation_id: &str,
+        instance_id: Option<&str>,
+    ) -> Result<ec2_types::Address, EC2Error> {
+        let address = self.describe_address(allocation_id).await?;
+        if address.instance_id() == instance_id {
+            return Ok(address);
+        }
+        if let Some(association_id) = address.association_id() {
+            self.disassociate_address(allocation_id, association_id)
+                .await?;
+        }
+        match instance_id {
+            Some(instance_id) => self.associate_address(allocation_id, instance_id).await,
+            None => self.describe_address(allocation_id).await,
+        }
+    }
+
+    pub async fn describe_address(
+        &self,
+        allocation_id: &str,
+    ) -> Result<ec2_types::Address, EC2Error> {
+        let request = self
+            .client
+            .describe_addresses()
+            .allocation_ids(allocation_id);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeAddresses", || {
+            request.clone().send()
+        })
+        .await?;
+        resp.addresses()
+            .first()
+            .cloned()
+            .ok_or_else(|| EC2Error::ResourceNotFound(allocation_id.to_string()))
+    }
+
+    /**
+     * Disassociate the address when it is associated, then release it. An address that no longer
+     * exists is reported as AlreadyGone.
+     */
+    pub async fn destroy_address(&self, allocation_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        let address = match self.describe_address(allocation_id).await {
+            Ok(address) => address,
+            Err(EC2Error::ResourceNotFound(_)) => {
+                info!("Elastic IP {} is already released", allocation_id);
+                return Ok(DestroyOutcome::AlreadyGone);
+            }
+            Err(err) => return Err(err),
+        };
+        if let Some(association_id) = address.association_id() {
+            self.disassociate_address(allocation_id, association_id)
+                .await?;
+        }
+        self.release_address(allocation_id).await
+    }
+
+    async fn release_address(&self, allocation_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        let request = self.client.release_address().allocation_id(allocation_id);
+        let released = retry_throttled(&RetryPolicy::from_env(), "ReleaseAddress", || {
+            request.clone().send()
+        })
+        .await;
+        match released.map_err(EC2Error::from) {
+            Ok(_) => {
+                info!("Elastic IP {} released", allocation_id);
+                Ok(DestroyOutcome::Terminated)
+            }
+            Err(EC2Error::ResourceNotFound(_)) => {
+                info!("Elastic IP {} is already released", allocation_id);
+                Ok(DestroyOutcome::AlreadyGone)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Refresh function that attempts the association, pending while the instance is not running
+    fn try_associate(
+        client: AWSClient,
+        resource_id: String,
+        instance_id: String,
+    ) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for Elastic IP".to_string()),
+            };
+
+            let request = ec2_client
+                .associate_address()
+                .allocation_id(resource_id.clone())
+                .instance_id(instance_id.clone());
+            let state = match retry_throttled(&RetryPolicy::from_env(), "AssociateAddress", || {
+                request.clone().send()
+            })
+            .await
+            {
+                Ok(_) => ASSOCIATED,
+                Err(err) if err.code() == Some("IncorrectInstanceState") => INSTANCE_PENDING,
+                Err(err) => {
+                    return Err(format!(
+                        "Failed to associate Elastic IP {} with {}: {}",
+                        resource_id, instance_id, err
+                    ));
+                }
+            };
+            Ok(Some((
+                Box::new(resource_id.clone()) as Box<dyn Any + Send>,
+                vec![state.to_string()],
+            )))
+        })
+    }
+
+    fn wait_for_association(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for Elastic IP".to_string()),
+            };
+
+            let request = ec2_client
+                .describe_addresses()
+                .allocation_ids(resource_id.clone());
+            let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeAddresses", || {
+                request.clone().send()
+            })
+            .await;
+            let resp = match resp.map_err(EC2Error::from) {
+                Ok(resp) => resp,
+                Err(EC2Error::ResourceNotFound(_)) => return Ok(None),
+                Err(err) => {
+                    return Err(format!(
+                        "Failed to describe Elastic IP {}: {}",
+                        resource_id, err
+                    ));
+                }
+            };
+            let Some(address) = resp.addresses().first() else {
+                return Ok(None);
+            };
+            let state = match address.association_id() {
+                Some(_) => ASSOCIATED,
+                None => NOT_ASSOCIATED,
+            };
+            Ok(Some((
+                Box::new(address.clone()) as Box<dyn Any + Send>,
+                vec![state.to_string()],
+            )))
+        })
+    }
+}
@@ -0,0 +1,262 @@
+use std::{io::Write, path::Path};
+
+use aws_sdk_ec2::{primitives::Blob, types as ec2_types};
+use tracing::info;
+
+use crate::{
+    aws::{
+        ec2::ec2_instance::{DestroyOutcome, EC2Error},
+        internal::{
+            retry::{RetryPolicy, retry_throttled},
+            tags::{merge_tags, tag_specification, tags_from_yaml},
+        },
+    },
+    utils::redact::Sensitive,
+};
+
+const KEY_TYPES: &[&str] = &["rsa", "ed25519"];
+const DEFAULT_KEY_TYPE: &str = "rsa";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyPairOpts {
+    pub(crate) key_name: String,
+    pub(crate) public_key: Option<String>, // Imported when set, generated by AWS otherwise
+    pub(crate) key_type: String,           // Of a generated key, rsa or ed25519
+    pub(crate) private_key_file: Option<String>, // Where a generated private key is written
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+/// A key pair AWS holds, with its private key when AWS just generated it
+#[derive(Debug)]
+pub struct CreatedKeyPair {
+    pub key_pair: ec2_types::KeyPairInfo,
+    pub private_key: Option<Sensitive>, // Only ever returned by CreateKeyPair, never stored
+}
+
+#[derive(Clone)]
+pub struct KeyPair {
+    client: aws_sdk_ec2::Client,
+}
+
+impl KeyPairOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+/// Default `private_key_file` of a generated key, next to the config file
+fn default_private_key_file(key_name: &str) -> String {
+    format!("{}.pem", key_name)
+}
+
+/**
+ * Anchor the `private_key_file` of a generated key to `base_dir`, the directory of the config
+ * file. A generated key without one gets `<key_name>.pem` there.
+ */
+pub fn anchor_private_key_file(properties: &mut serde_yaml::Value, name: &str, base_dir: &Path) {
+    if !properties
+        .get("generate")
+        .and_then(|g| g.as_bool())
+        .unwrap_or(false)
+    {
+        return;
+    }
+    let file = match properties.get("private_key_file").and_then(|f| f.as_str()) {
+        Some(file) => file.to_string(),
+        None => {
+            let key_name = properties
+                .get("key_name")
+                .and_then(|k| k.as_str())
+                .unwrap_or(name);
+            default_private_key_file(key_name)
+        }
+    };
+    if Path::new(&file).is_absolute() {
+        return;
+    }
+    if let Some(properties) = properties.as_mapping_mut() {
+        let anchored = base_dir.join(file).display().to_string();
+        properties.insert("private_key_file".into(), anchored.into());
+    }
+}
+
+/**
+ * Write a generated private key readable by the owner only. An existing file is never
+ * overwritten, it may hold the only copy of another key.
+ */
+pub fn write_private_key(path: &str, private_key: &Sensitive) -> Result<(), EC2Error> {
+    let failed = |err: std::io::Error| {
+        EC2Error::OptionsError(format!(
+            "Failed to write private key file {}: {}",
+            path, err
+        ))
+    };
+    if let Some(parent) = Path::new(path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+    {
+        std::fs::create_dir_all(parent).map_err(failed)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path).map_err(failed)?;
+    file.write_all(private_key.as_bytes()).map_err(failed)?;
+    info!("Private key written to {}", path);
+    Ok(())
+}
+
+/// Remove a private key file written by apply, one that is already gone is fine
+pub fn remove_private_key(path: &str) -> Result<(), String> {
+    match std::fs::remove_file(path) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(format!(
+            "Failed to remove private key file {}: {}",
+            path, err
+        )),
+    }
+}
+
+impl KeyPair {
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_ec2::Client::new(config);
+        KeyPair { client }
+    }
+
+    /**
+     * Parse and validate the properties, `key_name` defaults to the component name. Either
+     * `public_key` is imported or `generate: true` has AWS create the key.
+     */
+    pub fn opts_from_yaml(name: &str, yaml: &serde_yaml::Value) -> Result<KeyPairOpts, EC2Error> {
+        let get_string = |key: &str| {
+            yaml.get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+        let invalid = |message: &str| EC2Error::OptionsError(message.to_string());
+        let key_name = get_string("key_name").unwrap_or_else(|| name.to_string());
+        let public_key = get_string("public_key");
+        let generate = yaml
+            .get("generate")
+            .and_then(|g| g.as_bool())
+            .unwrap_or(false);
+        match (&public_key, generate) {
+            (Some(_), true) => {
+                return Err(invalid(
+                    "public_key and generate: true cannot be set together",
+                ));
+            }
+            (None, false) => {
+                return Err(invalid(
+                    "set public_key to import a key, or generate: true to have AWS create one",
+                ));
+            }
+            (Some(_), false) if yaml.get("private_key_file").is_some() => {
+                return Err(invalid("private_key_file is only used with generate: true"));
+            }
+            _ => {}
+        }
+        let key_type = get_string("key_type").unwrap_or_else(|| DEFAULT_KEY_TYPE.to_string());
+        if !KEY_TYPES.contains(&key_type.as_str()) {
+            return Err(EC2Error::OptionsError(format!(
+                "unknown key_type '{}', expected {}",
+                key_type,
+                KEY_TYPES.join(" or ")
+            )));
+        }
+        let private_key_file = generate.then(|| {
+            get_string("private_key_file").unwrap_or_else(|| default_private_key_file(&key_name))
+        });
+
+        Ok(KeyPairOpts {
+            key_name,
+            public_key,
+            key_type,
+            private_key_file,
+            tags: tags_from_yaml(yaml).map_err(EC2Error::OptionsError)?,
+        })
+    }
+
+    /// Import `public_key`, or have AWS generate the key pair and return its private key
+    pub async fn create_key_pair(&self, opts: &KeyPairOpts) -> Result<CreatedKeyPair, EC2Error> {
+        info!("Creating key pair with config: {:?}", opts);
+        let tags = tag_specification(ec2_types::ResourceType::KeyPair, &opts.tags);
+        let (key_pair_id, private_key) = match &opts.public_key {
+            Some(public_key) => {
+                let request = self
+                    .client
+                    .import_key_pair()
+                    .key_name(&opts.key_name)
+                    .public_key_material(Blob::new(public_key.as_bytes()))
+                    .set_tag_specifications(tags.map(|t| vec![t]));
+                let resp = retry_throttled(&RetryPolicy::from_env(), "ImportKeyPair", || {
+                    request.clone().send()
+                })
+                .await?;
+                (resp.key_pair_id().map(|id| id.to_string()), None)
+            }
+            None => {
+                let request = self
+                    .client
+                    .create_key_pair()
+                    .key_name(&opts.key_name)
+                    .key_type(ec2_types::KeyType::from(opts.key_type.as_str()))
+                    .key_format(ec2_types::KeyFormat::Pem)
+                    .set_tag_specifications(tags.map(|t| vec![t]));
+                let resp = retry_throttled(&RetryPolicy::from_env(), "CreateKeyPair", || {
+                    request.clone().send()
+                })
+                .await?;
+                let private_key = resp.key_material().map(|k| Sensitive::new(k.to_string()));
+                (resp.key_pair_id().map(|id| id.to_string()), private_key)
+            }
+        };
+        let key_pair_id = key_pair_id.ok_or_else(|| {
+            EC2Error::SdkError(format!("No key pair id returned for {}", opts.key_name))
+        })?;
+        info!("Key pair {} created", key_pair_id);
+        Ok(CreatedKeyPair {
+            key_pair: self.describe_key_pair(&key_pair_id).await?,
+            private_key,
+        })
+    }
+
+    pub async fn describe_key_pair(
+        &self,
+        key_pair_id: &str,
+    ) -> Result<ec2_types::KeyPairInfo, EC2Error> {
+        let request = self.client.describe_key_pairs().key_pair_ids(key_pair_id);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeKeyPairs", || {
+            request.clone().send()
+        })
+        .await?;
+        resp.key_pairs()
+            .first()
+            .cloned()
+            .ok_or_else(|| EC2Error::ResourceNotFound(key_pair_id.to_string()))
+    }
+
+    /// Delete a key pair, one that no longer exists is reported as AlreadyGone
+    pub async fn destroy_key_pair(&self, key_pair_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        let request = self.client.delete_key_pair().key_pair_id(key_pair_id);
+        let deleted = retry_throttled(&RetryPolicy::from_env(), "DeleteKeyPair", || {
+            request.clone().send()
+        })
+        .await;
+        match deleted.map_err(EC2Error::from) {
+            Ok(_) => {
+                info!("Key pair {} deleted", key_pair_id);
+                Ok(DestroyOutcome::Terminated)
+            }
+            Err(EC2Error::ResourceNotFound(_)) => {
+                info!("Key pair {} is already gone", key_pair_id);
+                Ok(DestroyOutcome::AlreadyGone)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
@@ -1 +1,7 @@
 pub mod ec2_instance;
+pub mod elastic_ip;
+pub mod key_pair;
+pub mod preflight;
+pub mod security_group;
+pub mod subnet;
+pub mod vpc;
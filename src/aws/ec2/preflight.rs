@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use aws_sdk_ec2::types as ec2_types;
+
+use crate::aws::ec2::ec2_instance::{EC2Error, EC2Instance};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found by talking to AWS while planning, attached to the component it concerns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PreflightIssue {
+    pub component: String,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageArchitecture {
+    pub architecture: String,
+    pub virtualization_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstanceTypeSupport {
+    pub architectures: Vec<String>,
+    pub virtualization_types: Vec<String>,
+}
+
+/**
+ * Compare the AMI's architecture and virtualization type against what the instance type supports.
+ * Returns a message naming both sides when they are incompatible.
+ */
+pub fn check_compatibility(
+    image_id: &str,
+    image: &ImageArchitecture,
+    instance_type: &str,
+    support: &InstanceTypeSupport,
+) -> Option<String> {
+    if !support.architectures.is_empty() && !support.architectures.contains(&image.architecture) {
+        return Some(format!(
+            "AMI '{}' is built for {} but instance type '{}' supports {}",
+            image_id,
+            image.architecture,
+            instance_type,
+            support.architectures.join(", ")
+        ));
+    }
+    if let Some(virtualization_type) = &image.virtualization_type {
+        if !support.virtualization_types.is_empty()
+            && !support.virtualization_types.contains(virtualization_type)
+        {
+            return Some(format!(
+                "AMI '{}' uses {} virtualization but instance type '{}' supports {}",
+                image_id,
+                virtualization_type,
+                instance_type,
+                support.virtualization_types.join(", ")
+            ));
+        }
+    }
+    None
+}
+
+/// Report an instance type that isn't offered in the requested availability zone,
+/// suggesting the zones of the region where it is
+pub fn check_offering(
+    instance_type: &str,
+    availability_zone: &str,
+    offered_in: &[String],
+) -> Option<String> {
+    if offered_in.iter().any(|az| az == availability_zone) {
+        return None;
+    }
+    if offered_in.is_empty() {
+        return Some(format!(
+            "Instance type '{}' is not offered in any availability zone of this region",
+            instance_type
+        ));
+    }
+    let mut suggestions = offered_in.to_vec();
+    suggestions.sort();
+    Some(format!(
+        "Instance type '{}' is not offered in {}, it is available in: {}",
+        instance_type,
+        availability_zone,
+        suggestions.join(", ")
+    ))
+}
+
+/**
+ * Plan-time checks that need AWS read access.
+ * Lookups are cached for the lifetime of the preflight, which is one plan in one region.
+ */
+pub struct Preflight {
+    ec2: EC2Instance,
+    instance_types: HashMap<String, InstanceTypeSupport>,
+    offerings: HashMap<String, Vec<String>>, // instance type -> availability zones offering it
+}
+
+impl Preflight {
+    pub fn new(ec2: EC2Instance) -> Self {
+        Preflight {
+            ec2,
+            instance_types: HashMap::new(),
+            offerings: HashMap::new(),
+        }
+    }
+
+    /// Verify the instance type is offered in the availability zone the instance will land in,
+    /// taken from the placement or, failing that, from the subnet.
+    pub async fn check_instance_type_offering(
+        &mut self,
+        component: &str,
+        instance_type: &str,
+        availability_zone: Option<&str>,
+        subnet_id: Option<&str>,
+    ) -> Vec<PreflightIssue> {
+        let availability_zone = match (availability_zone, subnet_id) {
+            (Some(az), _) if !az.contains("${") => az.to_string(),
+            (_, Some(subnet_id)) if !subnet_id.contains("${") => {
+                match self.ec2.describe_subnet_availability_zone(subnet_id).await {
+                    Ok(az) => az,
+                    Err(err) => {
+                        return vec![Self::lookup_issue(component, "DescribeSubnets", err)];
+                    }
+                }
+            }
+            // No concrete location, AWS picks a zone at launch time
+            _ => return vec![],
+        };
+
+        let cached = self.offerings.get(instance_type).cloned();
+        let offered_in = match cached {
+            Some(offered_in) => offered_in,
+            None => match self
+                .ec2
+                .describe_instance_type_offerings(&ec2_types::InstanceType::from(instance_type))
+                .await
+            {
+                Ok(offered_in) => {
+                    self.offerings
+                        .insert(instance_type.to_string(), offered_in.clone());
+                    offered_in
+                }
+                Err(err) => {
+                    return vec![Self::lookup_issue(
+                        component,
+                        "DescribeInstanceTypeOfferings",
+                        err,
+                    )];
+                }
+            },
+        };
+
+        check_offering(instance_type, &availability_zone, &offered_in)
+            .map(|message| PreflightIssue {
+                component: component.to_string(),
+                severity: Severity::Error,
+                message,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    /// Verify an EC2Instance's AMI can boot on its instance type.
+    /// AMI ids that are references resolved at apply time are skipped.
+    pub async fn check_image_compatibility(
+        &mut self,
+        component: &str,
+        image_id: &str,
+        instance_type: &str,
+    ) -> Vec<PreflightIssue> {
+        if image_id.contains("${") {
+            return vec![];
+        }
+
+        let image = match self.ec2.describe_image_architecture(image_id).await {
+            Ok(image) => image,
+            Err(err) => return vec![Self::lookup_issue(component, "DescribeImages", err)],
+        };
+        let support = match self.instance_type_support(instance_type).await {
+            Ok(support) => support,
+            Err(err) => return vec![Self::lookup_issue(component, "DescribeInstanceTypes", err)],
+        };
+
+        check_compatibility(image_id, &image, instance_type, &support)
+            .map(|message| PreflightIssue {
+                component: component.to_string(),
+                severity: Severity::Error,
+                message,
+            })
+            .into_iter()
+            .collect()
+    }
+
+    async fn instance_type_support(
+        &mut self,
+        instance_type: &str,
+    ) -> Result<InstanceTypeSupport, EC2Error> {
+        if let Some(support) = self.instance_types.get(instance_type) {
+            return Ok(support.clone());
+        }
+        let support = self
+            .ec2
+            .describe_instance_type_support(&ec2_types::InstanceType::from(instance_type))
+            .await?;
+        self.instance_types
+            .insert(instance_type.to_string(), support.clone());
+        Ok(support)
+    }
+
+    /// Missing permissions and throttling must not block a plan, the check becomes a warning
+    fn lookup_issue(component: &str, operation: &str, err: EC2Error) -> PreflightIssue {
+        let severity = match err {
+            EC2Error::Unauthorized(_) | EC2Error::Throttled(_) => Severity::Warning,
+            _ => Severity::Error,
+        };
+        PreflightIssue {
+            component: component.to_string(),
+            severity,
+            message: format!("{} failed during preflight: {}", operation, err),
+        }
+    }
+}
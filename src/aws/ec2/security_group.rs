@@ -0,0 +1,470 @@
+use std::any::Any;
+
+use aws_sdk_ec2::{error::ProvideErrorMetadata, types as ec2_types};
+use tracing::info;
+
+use crate::aws::{
+    AWSClient,
+    ec2::{
+        ec2_instance::{DestroyOutcome, EC2Error},
+        vpc::parse_ipv4_cidr,
+    },
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tag_specification, tags_from_yaml},
+        wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitDefaults, WaitError},
+    },
+};
+
+const DEFAULT_DESCRIPTION: &str = "Managed by letusinfra";
+const DELETED: &str = "deleted";
+const IN_USE: &str = "in-use"; // Still attached to an instance or referenced by another group
+
+/// One ingress or egress rule, ports are ICMP type and code for `icmp`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityGroupRule {
+    pub protocol: String, // tcp, udp, icmp or -1 for all traffic
+    pub from_port: i32,
+    pub to_port: i32,
+    pub cidr_blocks: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityGroupOpts {
+    pub(crate) group_name: String,
+    pub(crate) description: String,
+    pub(crate) vpc_id: Option<String>, // Default VPC when unset
+    pub(crate) ingress: Vec<SecurityGroupRule>,
+    pub(crate) egress: Vec<SecurityGroupRule>,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct SecurityGroup {
+    client: aws_sdk_ec2::Client,
+    wait: WaitDefaults,
+}
+
+impl SecurityGroupRule {
+    fn from_yaml(yaml: &serde_yaml::Value, location: &str) -> Result<Self, String> {
+        let protocol = match yaml.get("protocol").and_then(|p| p.as_str()) {
+            Some("all") | Some("-1") => "-1".to_string(),
+            Some(protocol @ ("tcp" | "udp" | "icmp")) => protocol.to_string(),
+            Some(other) => {
+                return Err(format!(
+                    "{}: unknown protocol '{}', expected tcp, udp, icmp or all",
+                    location, other
+                ));
+            }
+            None => return Err(format!("{}: missing protocol", location)),
+        };
+        let port = |key: &str| yaml.get(key).and_then(|p| p.as_i64());
+        let (from_port, to_port) = match (protocol.as_str(), port("from_port"), port("to_port")) {
+            // Ports are ignored by AWS for all traffic
+            ("-1", _, _) => (-1, -1),
+            ("icmp", from, to) => (from.unwrap_or(-1), to.unwrap_or(-1)),
+            (_, Some(from), Some(to)) => (from, to),
+            _ => return Err(format!("{}: from_port and to_port are required", location)),
+        };
+        let range = if protocol == "icmp" {
+            -1..=255
+        } else {
+            0..=65535
+        };
+        if !range.contains(&from_port) || !range.contains(&to_port) {
+            return Err(format!(
+                "{}: ports must be between {} and {}",
+                location,
+                range.start(),
+                range.end()
+            ));
+        }
+        if protocol != "icmp" && from_port > to_port {
+            return Err(format!(
+                "{}: from_port {} is greater than to_port {}",
+                location, from_port, to_port
+            ));
+        }
+
+        let cidr_blocks: Vec<String> = yaml
+            .get("cidr_blocks")
+            .and_then(|c| c.as_sequence())
+            .ok_or_else(|| format!("{}: cidr_blocks must be a list", location))?
+            .iter()
+            .map(|c| {
+                c.as_str()
+                    .map(|c| c.to_string())
+                    .ok_or_else(|| format!("{}: cidr_blocks must contain strings", location))
+            })
+            .collect::<Result<_, _>>()?;
+        for cidr in &cidr_blocks {
+            parse_ipv4_cidr(cidr).map_err(|err| format!("{}: {}", location, err))?;
+        }
+
+        Ok(SecurityGroupRule {
+            protocol,
+            from_port: from_port as i32,
+            to_port: to_port as i32,
+            cidr_blocks,
+        })
+    }
+
+    fn ip_permission(&self) -> ec2_types::IpPermission {
+        let mut permission = ec2_types::IpPermission::builder()
+            .ip_protocol(&self.protocol)
+            .from_port(self.from_port)
+            .to_port(self.to_port);
+        for cidr in &self.cidr_blocks {
+            permission = permission.ip_ranges(ec2_types::IpRange::builder().cidr_ip(cidr).build());
+        }
+        permission.build()
+    }
+}
+
+/// The egress rule AWS adds to every new group
+fn allow_all_egress() -> SecurityGroupRule {
+    SecurityGroupRule {
+        protocol: "-1".to_string(),
+        from_port: -1,
+        to_port: -1,
+        cidr_blocks: vec!["0.0.0.0/0".to_string()],
+    }
+}
+
+/**
+ * Egress rules to revoke from and to authorize on a new group, in that order. Without declared
+ * egress rules the allow-all rule AWS adds stays. Declared rules replace it, unless it is one of
+ * them, then it is kept rather than authorized a second time.
+ */
+pub(crate) fn egress_changes(
+    declared: &[SecurityGroupRule],
+) -> (Vec<SecurityGroupRule>, Vec<SecurityGroupRule>) {
+    if declared.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+    let default = allow_all_egress();
+    let revoke = if declared.contains(&default) {
+        Vec::new()
+    } else {
+        vec![default.clone()]
+    };
+    let authorize = declared
+        .iter()
+        .filter(|r| **r != default)
+        .cloned()
+        .collect();
+    (revoke, authorize)
+}
+
+impl SecurityGroupOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+impl SecurityGroup {
+    pub fn new(client: aws_sdk_ec2::Client) -> Self {
+        SecurityGroup {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_ec2::Client::new(config);
+        SecurityGroup {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// Parse and validate the properties, `group_name` defaults to the component name
+    pub fn opts_from_yaml(
+        name: &str,
+        yaml: &serde_yaml::Value,
+    ) -> Result<SecurityGroupOpts, EC2Error> {
+        let get_string = |key: &str| {
+            yaml.get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+        let rules = |direction: &str| -> Result<Vec<SecurityGroupRule>, EC2Error> {
+            match yaml.get(direction) {
+                None => Ok(Vec::new()),
+                Some(rules) => rules
+                    .as_sequence()
+                    .ok_or_else(|| EC2Error::OptionsError(format!("{} must be a list", direction)))?
+                    .iter()
+                    .enumerate()
+                    .map(|(index, rule)| {
+                        SecurityGroupRule::from_yaml(rule, &format!("{}[{}]", direction, index))
+                            .map_err(EC2Error::OptionsError)
+                    })
+                    .collect(),
+            }
+        };
+
+        Ok(SecurityGroupOpts {
+            group_name: get_string("group_name").unwrap_or_else(|| name.to_string()),
+            description: get_string("description")
+                .unwrap_or_else(|| DEFAULT_DESCRIPTION.to_string()),
+            vpc_id: get_string("vpc_id"),
+            ingress: rules("ingress")?,
+            egress: rules("egress")?,
+            tags: tags_from_yaml(yaml).map_err(EC2Error::OptionsError)?,
+        })
+    }
+
+    /// Create the group and authorize its rules, returns the group id
+    pub async fn create_security_group(
+        &self,
+        opts: &SecurityGroupOpts,
+    ) -> Result<String, EC2Error> {
+        info!("Creating security group with config: {:?}", opts);
+        let mut request = self
+            .client
+            .create_security_group()
+            .group_name(&opts.group_name)
+            .description(&opts.description);
+        if let Some(vpc_id) = &opts.vpc_id {
+            request = request.vpc_id(vpc_id);
+        }
+        if let Some(tags) = tag_specification(ec2_types::ResourceType::SecurityGroup, &opts.tags) {
+            request = request.tag_specifications(tags);
+        }
+        let group_id = retry_throttled(&RetryPolicy::from_env(), "CreateSecurityGroup", || {
+            request.clone().send()
+        })
+        .await?
+        .group_id()
+        .ok_or_else(|| EC2Error::SdkError("CreateSecurityGroup returned no group id".to_string()))?
+        .to_string();
+
+        // A group whose rules could not be set is deleted again, apply would not record it
+        if let Err(err) = self.authorize_rules(&group_id, opts).await {
+            let request = self.client.delete_security_group().group_id(&group_id);
+            return match retry_throttled(&RetryPolicy::from_env(), "DeleteSecurityGroup", || {
+                request.clone().send()
+            })
+            .await
+            {
+                Ok(_) => Err(err),
+                Err(cleanup) => Err(EC2Error::SdkError(format!(
+                    "{}, and security group {} could not be deleted, delete it by hand: {}",
+                    err,
+                    group_id,
+                    EC2Error::from(cleanup)
+                ))),
+            };
+        }
+        info!("Security group {} created", group_id);
+        Ok(group_id)
+    }
+
+    /**
+     * Authorize the declared rules of a new group. AWS gives a new group an allow-all egress rule,
+     * it is replaced when egress rules are declared so only those apply, see `egress_changes`.
+     */
+    async fn authorize_rules(
+        &self,
+        group_id: &str,
+        opts: &SecurityGroupOpts,
+    ) -> Result<(), EC2Error> {
+        if !opts.ingress.is_empty() {
+            let request = self
+                .client
+                .authorize_security_group_ingress()
+                .group_id(group_id)
+                .set_ip_permissions(Some(
+                    opts.ingress.iter().map(|r| r.ip_permission()).collect(),
+                ));
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "AuthorizeSecurityGroupIngress",
+                || request.clone().send(),
+            )
+            .await?;
+        }
+        let (revoke, authorize) = egress_changes(&opts.egress);
+        if !revoke.is_empty() {
+            let request = self
+                .client
+                .revoke_security_group_egress()
+                .group_id(group_id)
+                .set_ip_permissions(Some(revoke.iter().map(|r| r.ip_permission()).collect()));
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "RevokeSecurityGroupEgress",
+                || request.clone().send(),
+            )
+            .await?;
+        }
+        if !authorize.is_empty() {
+            let request = self
+                .client
+                .authorize_security_group_egress()
+                .group_id(group_id)
+                .set_ip_permissions(Some(authorize.iter().map(|r| r.ip_permission()).collect()));
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "AuthorizeSecurityGroupEgress",
+                || request.clone().send(),
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Revoke every rule of a group, rules that reference other groups would otherwise keep those
+     * groups in use and block their deletion. False when the group is already gone.
+     */
+    async fn revoke_rules(&self, group_id: &str) -> Result<bool, EC2Error> {
+        let group = match self.describe_security_group(group_id).await {
+            Ok(group) => group,
+            Err(EC2Error::ResourceNotFound(_)) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        if !group.ip_permissions().is_empty() {
+            let request = self
+                .client
+                .revoke_security_group_ingress()
+                .group_id(group_id)
+                .set_ip_permissions(Some(group.ip_permissions().to_vec()));
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "RevokeSecurityGroupIngress",
+                || request.clone().send(),
+            )
+            .await?;
+        }
+        if !group.ip_permissions_egress().is_empty() {
+            let request = self
+                .client
+                .revoke_security_group_egress()
+                .group_id(group_id)
+                .set_ip_permissions(Some(group.ip_permissions_egress().to_vec()));
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "RevokeSecurityGroupEgress",
+                || request.clone().send(),
+            )
+            .await?;
+        }
+        Ok(true)
+    }
+
+    pub async fn describe_security_group(
+        &self,
+        group_id: &str,
+    ) -> Result<ec2_types::SecurityGroup, EC2Error> {
+        let request = self.client.describe_security_groups().group_ids(group_id);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeSecurityGroups", || {
+            request.clone().send()
+        })
+        .await?;
+        resp.security_groups()
+            .first()
+            .cloned()
+            .ok_or_else(|| EC2Error::ResourceNotFound(group_id.to_string()))
+    }
+
+    /**
+     * Revoke the rules of a security group, then delete it.
+     * Instances that are still terminating keep the group in use for a while, DependencyViolation
+     * is retried with backoff until the group can be deleted or the wait times out.
+     */
+    pub async fn destroy_security_group(&self, group_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        if !self.revoke_rules(group_id).await? {
+            info!("Security group {} is already gone", group_id);
+            return Ok(DestroyOutcome::AlreadyGone);
+        }
+        let request = self.client.delete_security_group().group_id(group_id);
+        let deleted = retry_throttled(&RetryPolicy::from_env(), "DeleteSecurityGroup", || {
+            request.clone().send()
+        })
+        .await;
+        match deleted {
+            Ok(_) => {
+                info!("Security group {} deleted", group_id);
+                return Ok(DestroyOutcome::Terminated);
+            }
+            Err(err) if err.code() == Some("DependencyViolation") => {
+                info!("Security group {} is still in use, retrying", group_id);
+            }
+            Err(err) => match EC2Error::from(err) {
+                EC2Error::ResourceNotFound(_) => {
+                    info!("Security group {} is already gone", group_id);
+                    return Ok(DestroyOutcome::AlreadyGone);
+                }
+                err => return Err(err),
+            },
+        }
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![DELETED.to_string()],
+            vec![IN_USE.to_string()],
+            Box::new(SecurityGroup::try_delete),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        match wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                group_id.to_string(),
+            )
+            .await
+        {
+            Ok(_) | Err(WaitError::NotFound { .. }) => {}
+            Err(err) => return Err(err.into()),
+        }
+        info!("Security group {} deleted", group_id);
+        Ok(DestroyOutcome::Terminated)
+    }
+
+    /// Refresh function that attempts the delete, `in-use` while AWS reports a dependency
+    fn try_delete(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for security group".to_string()),
+            };
+
+            let request = ec2_client
+                .delete_security_group()
+                .group_id(resource_id.clone());
+            let state =
+                match retry_throttled(&RetryPolicy::from_env(), "DeleteSecurityGroup", || {
+                    request.clone().send()
+                })
+                .await
+                {
+                    Ok(_) => DELETED,
+                    Err(err) if err.code() == Some("DependencyViolation") => IN_USE,
+                    Err(err) if err.code() == Some("InvalidGroup.NotFound") => DELETED,
+                    Err(err) => {
+                        return Err(format!(
+                            "Failed to delete security group {}: {}",
+                            resource_id, err
+                        ));
+                    }
+                };
+            Ok(Some((
+                Box::new(resource_id.clone()) as Box<dyn Any + Send>,
+                vec![state.to_string()],
+            )))
+        })
+    }
+}
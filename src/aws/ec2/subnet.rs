@@ -0,0 +1,279 @@
+use std::any::Any;
+
+use aws_sdk_ec2::{error::ProvideErrorMetadata, types as ec2_types};
+use tracing::info;
+
+use crate::aws::{
+    AWSClient,
+    ec2::{
+        ec2_instance::{DestroyOutcome, EC2Error},
+        vpc::validate_cidr,
+    },
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tag_specification, tags_from_yaml},
+        wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitDefaults, WaitError},
+    },
+};
+
+const DELETED: &str = "deleted";
+const IN_USE: &str = "in-use"; // Network interfaces of the subnet are still being released
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubnetOpts {
+    pub(crate) vpc_id: String,
+    pub(crate) cidr_block: String,
+    pub(crate) availability_zone: Option<String>,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct Subnet {
+    client: aws_sdk_ec2::Client,
+    wait: WaitDefaults,
+}
+
+impl SubnetOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+impl Subnet {
+    pub fn new(client: aws_sdk_ec2::Client) -> Self {
+        Subnet {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_ec2::Client::new(config);
+        Subnet {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn opts_from_yaml(yaml: &serde_yaml::Value) -> Result<SubnetOpts, EC2Error> {
+        let get_string = |key: &str| {
+            yaml.get(key)
+                .and_then(|v| v.as_str())
+                .map(|s| s.to_string())
+        };
+        let vpc_id = get_string("vpc_id")
+            .ok_or_else(|| EC2Error::OptionsError("Missing required field: vpc_id".to_string()))?;
+        let cidr_block = get_string("cidr")
+            .ok_or_else(|| EC2Error::OptionsError("Missing required field: cidr".to_string()))?;
+        validate_cidr(&cidr_block).map_err(EC2Error::OptionsError)?;
+
+        let tags = tags_from_yaml(yaml).map_err(EC2Error::OptionsError)?;
+
+        Ok(SubnetOpts {
+            vpc_id,
+            cidr_block,
+            availability_zone: get_string("availability_zone"),
+            tags,
+        })
+    }
+
+    /// Creates a subnet and waits until it is `available`
+    pub async fn create_subnet(&self, opts: &SubnetOpts) -> Result<ec2_types::Subnet, EC2Error> {
+        info!("Creating subnet with config: {:?}", opts);
+        let mut request = self
+            .client
+            .create_subnet()
+            .vpc_id(&opts.vpc_id)
+            .cidr_block(&opts.cidr_block);
+        if let Some(availability_zone) = &opts.availability_zone {
+            request = request.availability_zone(availability_zone);
+        }
+        if let Some(tags) = tag_specification(ec2_types::ResourceType::Subnet, &opts.tags) {
+            request = request.tag_specifications(tags);
+        }
+        let resp = retry_throttled(&RetryPolicy::from_env(), "CreateSubnet", || {
+            request.clone().send()
+        })
+        .await?;
+        let subnet_id = resp
+            .subnet()
+            .and_then(|s| s.subnet_id())
+            .ok_or_else(|| EC2Error::SdkError("CreateSubnet returned no subnet id".to_string()))?
+            .to_string();
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ec2_types::SubnetState::Available.to_string()],
+            vec![ec2_types::SubnetState::Pending.to_string()],
+            Box::new(Subnet::wait_for_completion),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let result = wait_state_config
+            .wait_until_state(AWSClient::EC2Client(self.client.clone()), subnet_id.clone())
+            .await?;
+        info!("Subnet {} created in {}", subnet_id, opts.vpc_id);
+        match result.and_then(|s| s.downcast::<ec2_types::Subnet>().ok()) {
+            Some(subnet) => Ok(*subnet),
+            None => self.describe_subnet(&subnet_id).await,
+        }
+    }
+
+    pub async fn describe_subnet(&self, subnet_id: &str) -> Result<ec2_types::Subnet, EC2Error> {
+        let request = self.client.describe_subnets().subnet_ids(subnet_id);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeSubnets", || {
+            request.clone().send()
+        })
+        .await?;
+        resp.subnets()
+            .first()
+            .cloned()
+            .ok_or_else(|| EC2Error::ResourceNotFound(subnet_id.to_string()))
+    }
+
+    /**
+     * Delete a subnet and wait until DescribeSubnets no longer lists it, one that no longer
+     * exists is reported as AlreadyGone. The network interfaces of a just terminated instance
+     * can hold on to the subnet for a while, the delete is retried until they are released.
+     */
+    pub async fn destroy_subnet(&self, subnet_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        let request = self.client.delete_subnet().subnet_id(subnet_id);
+        let deleted = retry_throttled(&RetryPolicy::from_env(), "DeleteSubnet", || {
+            request.clone().send()
+        })
+        .await;
+        match deleted {
+            Ok(_) => {}
+            Err(err) if err.code() == Some("DependencyViolation") => {
+                info!("Subnet {} is still in use, retrying", subnet_id);
+                let wait_state_config = StateChangeConfig::new(
+                    &self.wait,
+                    vec![DELETED.to_string()],
+                    vec![IN_USE.to_string()],
+                    Box::new(Subnet::try_delete),
+                    None,
+                    None,
+                    None,
+                    None,
+                    None,
+                );
+                match wait_state_config
+                    .wait_until_state(
+                        AWSClient::EC2Client(self.client.clone()),
+                        subnet_id.to_string(),
+                    )
+                    .await
+                {
+                    Ok(_) | Err(WaitError::NotFound { .. }) => {}
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            Err(err) => match EC2Error::from(err) {
+                EC2Error::ResourceNotFound(_) => {
+                    info!("Subnet {} is already gone", subnet_id);
+                    return Ok(DestroyOutcome::AlreadyGone);
+                }
+                err => return Err(err),
+            },
+        }
+
+        // No target state, the wait ends once the subnet is no longer described
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![],
+            vec![],
+            Box::new(Subnet::wait_for_completion),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::EC2Client(self.client.clone()),
+                subnet_id.to_string(),
+            )
+            .await?;
+        info!("Subnet {} deleted", subnet_id);
+        Ok(DestroyOutcome::Terminated)
+    }
+
+    /// Refresh function that attempts the delete, `in-use` while AWS reports a dependency
+    fn try_delete(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for subnet".to_string()),
+            };
+
+            let request = ec2_client.delete_subnet().subnet_id(resource_id.clone());
+            let state = match retry_throttled(&RetryPolicy::from_env(), "DeleteSubnet", || {
+                request.clone().send()
+            })
+            .await
+            {
+                Ok(_) => DELETED,
+                Err(err) if err.code() == Some("DependencyViolation") => IN_USE,
+                Err(err) if err.code() == Some("InvalidSubnetID.NotFound") => DELETED,
+                Err(err) => {
+                    return Err(format!("Failed to delete subnet {}: {}", resource_id, err));
+                }
+            };
+            Ok(Some((
+                Box::new(resource_id.clone()) as Box<dyn Any + Send>,
+                vec![state.to_string()],
+            )))
+        })
+    }
+
+    fn wait_for_completion(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for subnet".to_string()),
+            };
+
+            let request = ec2_client
+                .describe_subnets()
+                .subnet_ids(resource_id.clone());
+            let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeSubnets", || {
+                request.clone().send()
+            })
+            .await;
+            let resp = match resp.map_err(EC2Error::from) {
+                Ok(resp) => resp,
+                // A new subnet can take a moment to show up in DescribeSubnets
+                Err(EC2Error::ResourceNotFound(_)) => return Ok(None),
+                Err(err) => {
+                    return Err(format!(
+                        "Failed to describe subnet {}: {}",
+                        resource_id, err
+                    ));
+                }
+            };
+            let Some(subnet) = resp.subnets().first() else {
+                return Ok(None);
+            };
+            let state = subnet
+                .state()
+                .map(|s| s.as_str().to_string())
+                .unwrap_or_default();
+            Ok(Some((
+                Box::new(subnet.clone()) as Box<dyn Any + Send>,
+                vec![state],
+            )))
+        })
+    }
+}
@@ -0,0 +1,247 @@
+use std::any::Any;
+
+use aws_sdk_ec2::types as ec2_types;
+use tracing::info;
+
+use crate::aws::{
+    AWSClient,
+    ec2::ec2_instance::{DestroyOutcome, EC2Error},
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tag_specification, tags_from_yaml},
+        wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitDefaults},
+    },
+};
+
+/// Smallest and largest IPv4 block AWS accepts for a VPC
+const MIN_PREFIX_LENGTH: u8 = 16;
+const MAX_PREFIX_LENGTH: u8 = 28;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VpcOpts {
+    pub(crate) cidr_block: String,
+    enable_dns_support: Option<bool>,
+    enable_dns_hostnames: Option<bool>,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct Vpc {
+    client: aws_sdk_ec2::Client,
+    wait: WaitDefaults,
+}
+
+/// Prefix length of an IPv4 CIDR block such as `10.0.0.0/16`
+pub fn parse_ipv4_cidr(cidr: &str) -> Result<u8, String> {
+    let (address, prefix) = cidr
+        .split_once('/')
+        .ok_or_else(|| format!("'{}' is not a CIDR block, expected e.g. 10.0.0.0/16", cidr))?;
+    let octets: Vec<&str> = address.split('.').collect();
+    if octets.len() != 4 || octets.iter().any(|o| o.parse::<u8>().is_err()) {
+        return Err(format!("'{}' does not start with an IPv4 address", cidr));
+    }
+    match prefix.parse::<u8>() {
+        Ok(length) if length <= 32 => Ok(length),
+        _ => Err(format!("'{}' has an invalid prefix length", cidr)),
+    }
+}
+
+/// Check `cidr` is an IPv4 block AWS accepts for a VPC, e.g. `10.0.0.0/16`
+pub fn validate_cidr(cidr: &str) -> Result<(), String> {
+    let length = parse_ipv4_cidr(cidr)?;
+    if (MIN_PREFIX_LENGTH..=MAX_PREFIX_LENGTH).contains(&length) {
+        return Ok(());
+    }
+    Err(format!(
+        "'{}' must have a prefix length between /{} and /{}",
+        cidr, MIN_PREFIX_LENGTH, MAX_PREFIX_LENGTH
+    ))
+}
+
+impl VpcOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+impl Vpc {
+    pub fn new(client: aws_sdk_ec2::Client) -> Self {
+        Vpc {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_ec2::Client::new(config);
+        Vpc {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn opts_from_yaml(yaml: &serde_yaml::Value) -> Result<VpcOpts, EC2Error> {
+        let cidr_block = yaml
+            .get("cidr")
+            .and_then(|c| c.as_str())
+            .ok_or_else(|| EC2Error::OptionsError("Missing required field: cidr".to_string()))?
+            .to_string();
+        validate_cidr(&cidr_block).map_err(EC2Error::OptionsError)?;
+
+        let get_bool = |key: &str| yaml.get(key).and_then(|v| v.as_bool());
+        let tags = tags_from_yaml(yaml).map_err(EC2Error::OptionsError)?;
+
+        Ok(VpcOpts {
+            cidr_block,
+            enable_dns_support: get_bool("enable_dns_support"),
+            enable_dns_hostnames: get_bool("enable_dns_hostnames"),
+            tags,
+        })
+    }
+
+    /**
+     * Creates a VPC and waits until it is `available`.
+     * DNS attributes can only be set one per ModifyVpcAttribute call, so they are applied afterwards.
+     */
+    pub async fn create_vpc(&self, opts: &VpcOpts) -> Result<ec2_types::Vpc, EC2Error> {
+        info!("Creating VPC with config: {:?}", opts);
+        let request = self
+            .client
+            .create_vpc()
+            .cidr_block(&opts.cidr_block)
+            .set_tag_specifications(
+                tag_specification(ec2_types::ResourceType::Vpc, &opts.tags).map(|s| vec![s]),
+            );
+        let resp = retry_throttled(&RetryPolicy::from_env(), "CreateVpc", || {
+            request.clone().send()
+        })
+        .await?;
+        let vpc_id = resp
+            .vpc()
+            .and_then(|v| v.vpc_id())
+            .ok_or_else(|| EC2Error::SdkError("CreateVpc returned no vpc id".to_string()))?
+            .to_string();
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![ec2_types::VpcState::Available.to_string()],
+            vec![ec2_types::VpcState::Pending.to_string()],
+            Box::new(Vpc::wait_for_completion),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(AWSClient::EC2Client(self.client.clone()), vpc_id.clone())
+            .await?;
+
+        if let Some(enabled) = opts.enable_dns_support {
+            let request = self
+                .client
+                .modify_vpc_attribute()
+                .vpc_id(&vpc_id)
+                .enable_dns_support(
+                    ec2_types::AttributeBooleanValue::builder()
+                        .value(enabled)
+                        .build(),
+                );
+            retry_throttled(&RetryPolicy::from_env(), "ModifyVpcAttribute", || {
+                request.clone().send()
+            })
+            .await?;
+        }
+        if let Some(enabled) = opts.enable_dns_hostnames {
+            let request = self
+                .client
+                .modify_vpc_attribute()
+                .vpc_id(&vpc_id)
+                .enable_dns_hostnames(
+                    ec2_types::AttributeBooleanValue::builder()
+                        .value(enabled)
+                        .build(),
+                );
+            retry_throttled(&RetryPolicy::from_env(), "ModifyVpcAttribute", || {
+                request.clone().send()
+            })
+            .await?;
+        }
+        info!("VPC {} created", vpc_id);
+        self.describe_vpc(&vpc_id).await
+    }
+
+    pub async fn describe_vpc(&self, vpc_id: &str) -> Result<ec2_types::Vpc, EC2Error> {
+        let request = self.client.describe_vpcs().vpc_ids(vpc_id);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeVpcs", || {
+            request.clone().send()
+        })
+        .await?;
+        resp.vpcs()
+            .first()
+            .cloned()
+            .ok_or_else(|| EC2Error::ResourceNotFound(vpc_id.to_string()))
+    }
+
+    /// Delete a VPC, one that no longer exists is reported as AlreadyGone
+    pub async fn destroy_vpc(&self, vpc_id: &str) -> Result<DestroyOutcome, EC2Error> {
+        let request = self.client.delete_vpc().vpc_id(vpc_id);
+        let deleted = retry_throttled(&RetryPolicy::from_env(), "DeleteVpc", || {
+            request.clone().send()
+        })
+        .await;
+        match deleted.map_err(EC2Error::from) {
+            Ok(_) => {
+                info!("VPC {} deleted", vpc_id);
+                Ok(DestroyOutcome::Terminated)
+            }
+            Err(EC2Error::ResourceNotFound(_)) => {
+                info!("VPC {} is already gone", vpc_id);
+                Ok(DestroyOutcome::AlreadyGone)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn wait_for_completion(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let ec2_client = match client {
+                AWSClient::EC2Client(c) => c,
+                _ => return Err("Invalid client type for VPC".to_string()),
+            };
+
+            let request = ec2_client.describe_vpcs().vpc_ids(resource_id.clone());
+            let resp = retry_throttled(&RetryPolicy::from_env(), "DescribeVpcs", || {
+                request.clone().send()
+            })
+            .await;
+            let resp = match resp.map_err(EC2Error::from) {
+                Ok(resp) => resp,
+                // A new VPC can take a moment to show up in DescribeVpcs
+                Err(EC2Error::ResourceNotFound(_)) => return Ok(None),
+                Err(err) => {
+                    return Err(format!("Failed to describe VPC {}: {}", resource_id, err));
+                }
+            };
+            let Some(vpc) = resp.vpcs().first() else {
+                return Ok(None);
+            };
+            let state = vpc
+                .state()
+                .map(|s| s.as_str().to_string())
+                .unwrap_or_default();
+            Ok(Some((
+                Box::new(vpc.clone()) as Box<dyn Any + Send>,
+                vec![state],
+            )))
+        })
+    }
+}
@@ -0,0 +1,63 @@
+use aws_sdk_s3::error::ProvideErrorMetadata;
+
+use crate::aws::{
+    ec2::ec2_instance::EC2Error,
+    internal::{retry::is_throttling, wait_and_refresh::WaitError},
+};
+
+/**
+ * Error of the S3 and IAM calls, and of the component handlers of every type. EC2 keeps its own
+ * `EC2Error` for the instance specific cases, handlers wrap it as `Ec2`.
+ */
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum AwsError {
+    #[error("Resource not found: {0}")]
+    ResourceNotFound(String),
+    #[error("Options error: {0}")]
+    OptionsError(String),
+    #[error("AWS SDK error: {0}")]
+    SdkError(String),
+    #[error("Not authorized: {0}")]
+    Unauthorized(String),
+    #[error("Error while waiting for state to confirm the resource change: {0}")]
+    StateError(#[from] WaitError),
+    #[error("Resource must be replaced to apply the change: {0}")]
+    RequiresReplacement(String),
+    #[error("Throttled by AWS, gave up after retrying: {0}")]
+    Throttled(String),
+    #[error("{0}")]
+    Ec2(#[from] EC2Error),
+}
+
+impl AwsError {
+    /// Whether a resource other than an instance does not exist, whichever service reported it
+    pub fn is_not_found(&self) -> bool {
+        matches!(
+            self,
+            AwsError::ResourceNotFound(_) | AwsError::Ec2(EC2Error::ResourceNotFound(_))
+        )
+    }
+}
+
+impl<T: ProvideErrorMetadata + std::fmt::Display> From<T> for AwsError {
+    fn from(value: T) -> Self {
+        match value.code() {
+            Some(code) if code.ends_with(".NotFound") => {
+                AwsError::ResourceNotFound(value.message().unwrap_or(code).to_string())
+            }
+            Some(code) if code == "AccessDenied" => {
+                AwsError::Unauthorized(value.message().unwrap_or(code).to_string())
+            }
+            // Throttling only surfaces once both the SDK and retry_throttled have given up
+            Some(code) if is_throttling(code) => {
+                AwsError::Throttled(value.message().unwrap_or(code).to_string())
+            }
+            _ => AwsError::SdkError(format!(
+                "AWS SDK error: {} (code: {:?}, message: {:?})",
+                value,
+                value.code(),
+                value.message()
+            )),
+        }
+    }
+}
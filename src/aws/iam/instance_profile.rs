@@ -0,0 +1,307 @@
+use std::any::Any;
+
+use aws_sdk_iam::types as iam_types;
+use tracing::{info, warn};
+
+use crate::aws::{
+    AWSClient,
+    ec2::ec2_instance::DestroyOutcome,
+    error::AwsError,
+    iam::{iam_error, iam_tags, validate_iam_name},
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tags_from_yaml},
+        wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitDefaults},
+    },
+};
+
+const EXISTS: &str = "exists";
+/// Longest name IAM accepts for an instance profile
+pub const MAX_PROFILE_NAME_LEN: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileOpts {
+    pub(crate) instance_profile_name: String,
+    pub(crate) role: String, // Name of the role, apply fills it in from an IAMRole in dependsOn
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct InstanceProfile {
+    client: aws_sdk_iam::Client,
+    wait: WaitDefaults,
+}
+
+impl ProfileOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+impl InstanceProfile {
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_iam::Client::new(config);
+        InstanceProfile {
+            client,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    /// `instance_profile_name` defaults to the name of the component
+    pub fn opts_from_yaml(name: &str, yaml: &serde_yaml::Value) -> Result<ProfileOpts, AwsError> {
+        let instance_profile_name = yaml
+            .get("instance_profile_name")
+            .and_then(|n| n.as_str())
+            .unwrap_or(name)
+            .to_string();
+        validate_iam_name(
+            "instance profile",
+            &instance_profile_name,
+            MAX_PROFILE_NAME_LEN,
+        )
+        .map_err(AwsError::OptionsError)?;
+        let role = yaml
+            .get("role")
+            .and_then(|r| r.as_str())
+            .ok_or_else(|| AwsError::OptionsError("Missing required field: role".to_string()))?
+            .to_string();
+
+        Ok(ProfileOpts {
+            instance_profile_name,
+            role,
+            tags: tags_from_yaml(yaml).map_err(AwsError::OptionsError)?,
+        })
+    }
+
+    /**
+     * Create the instance profile, wait until GetInstanceProfile finds it and add the role. A
+     * profile the role cannot be added to is deleted again.
+     */
+    pub async fn create_instance_profile(
+        &self,
+        opts: &ProfileOpts,
+    ) -> Result<iam_types::InstanceProfile, AwsError> {
+        info!("Creating IAM instance profile with config: {:?}", opts);
+        let request = self
+            .client
+            .create_instance_profile()
+            .instance_profile_name(&opts.instance_profile_name)
+            .set_tags(iam_tags(&opts.tags)?);
+        retry_throttled(&RetryPolicy::from_env(), "CreateInstanceProfile", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![EXISTS.to_string()],
+            vec![],
+            Box::new(InstanceProfile::wait_for_instance_profile),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let added = match wait_state_config
+            .wait_until_state(
+                AWSClient::IAMClient(self.client.clone()),
+                opts.instance_profile_name.clone(),
+            )
+            .await
+        {
+            Ok(_) => self.add_role(&opts.instance_profile_name, &opts.role).await,
+            Err(err) => Err(err.into()),
+        };
+        if let Err(err) = added {
+            if let Err(cleanup) = self
+                .destroy_instance_profile(&opts.instance_profile_name)
+                .await
+            {
+                warn!(
+                    "Failed to delete IAM instance profile {}: {}",
+                    opts.instance_profile_name, cleanup
+                );
+            }
+            return Err(err);
+        }
+        info!(
+            "IAM instance profile {} created with role {}",
+            opts.instance_profile_name, opts.role
+        );
+        self.describe_instance_profile(&opts.instance_profile_name)
+            .await
+    }
+
+    /// Swap the role of an existing instance profile for `opts.role` and apply its tags
+    pub async fn update_instance_profile(
+        &self,
+        opts: &ProfileOpts,
+    ) -> Result<iam_types::InstanceProfile, AwsError> {
+        let profile = self
+            .describe_instance_profile(&opts.instance_profile_name)
+            .await?;
+        let roles: Vec<&str> = profile.roles().iter().map(|r| r.role_name()).collect();
+        if roles != [opts.role.as_str()] {
+            for role in roles {
+                self.remove_role(&opts.instance_profile_name, role).await?;
+            }
+            self.add_role(&opts.instance_profile_name, &opts.role)
+                .await?;
+        }
+        if let Some(tags) = iam_tags(&opts.tags)? {
+            let request = self
+                .client
+                .tag_instance_profile()
+                .instance_profile_name(&opts.instance_profile_name)
+                .set_tags(Some(tags));
+            retry_throttled(&RetryPolicy::from_env(), "TagInstanceProfile", || {
+                request.clone().send()
+            })
+            .await
+            .map_err(iam_error)?;
+        }
+        self.describe_instance_profile(&opts.instance_profile_name)
+            .await
+    }
+
+    pub async fn describe_instance_profile(
+        &self,
+        instance_profile_name: &str,
+    ) -> Result<iam_types::InstanceProfile, AwsError> {
+        let request = self
+            .client
+            .get_instance_profile()
+            .instance_profile_name(instance_profile_name);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "GetInstanceProfile", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        resp.instance_profile()
+            .cloned()
+            .ok_or_else(|| AwsError::ResourceNotFound(instance_profile_name.to_string()))
+    }
+
+    /**
+     * Remove the role from the instance profile, then delete it. A profile that no longer exists
+     * is reported as AlreadyGone.
+     */
+    pub async fn destroy_instance_profile(
+        &self,
+        instance_profile_name: &str,
+    ) -> Result<DestroyOutcome, AwsError> {
+        let profile = match self.describe_instance_profile(instance_profile_name).await {
+            Ok(profile) => profile,
+            Err(AwsError::ResourceNotFound(_)) => {
+                info!(
+                    "IAM instance profile {} is already deleted",
+                    instance_profile_name
+                );
+                return Ok(DestroyOutcome::AlreadyGone);
+            }
+            Err(err) => return Err(err),
+        };
+        for role in profile.roles() {
+            self.remove_role(instance_profile_name, role.role_name())
+                .await?;
+        }
+
+        let request = self
+            .client
+            .delete_instance_profile()
+            .instance_profile_name(instance_profile_name);
+        match retry_throttled(&RetryPolicy::from_env(), "DeleteInstanceProfile", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)
+        {
+            Ok(_) => {
+                info!("IAM instance profile {} deleted", instance_profile_name);
+                Ok(DestroyOutcome::Terminated)
+            }
+            Err(AwsError::ResourceNotFound(_)) => {
+                info!(
+                    "IAM instance profile {} is already deleted",
+                    instance_profile_name
+                );
+                Ok(DestroyOutcome::AlreadyGone)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn add_role(&self, instance_profile_name: &str, role: &str) -> Result<(), AwsError> {
+        let request = self
+            .client
+            .add_role_to_instance_profile()
+            .instance_profile_name(instance_profile_name)
+            .role_name(role);
+        retry_throttled(&RetryPolicy::from_env(), "AddRoleToInstanceProfile", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        Ok(())
+    }
+
+    /// A role that is already gone from the profile counts as removed
+    async fn remove_role(&self, instance_profile_name: &str, role: &str) -> Result<(), AwsError> {
+        let request = self
+            .client
+            .remove_role_from_instance_profile()
+            .instance_profile_name(instance_profile_name)
+            .role_name(role);
+        match retry_throttled(
+            &RetryPolicy::from_env(),
+            "RemoveRoleFromInstanceProfile",
+            || request.clone().send(),
+        )
+        .await
+        .map_err(iam_error)
+        {
+            Ok(_) | Err(AwsError::ResourceNotFound(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn wait_for_instance_profile(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let iam_client = match client {
+                AWSClient::IAMClient(c) => c,
+                _ => return Err("Invalid client type for IAM instance profile".to_string()),
+            };
+
+            let request = iam_client
+                .get_instance_profile()
+                .instance_profile_name(resource_id.clone());
+            let resp = retry_throttled(&RetryPolicy::from_env(), "GetInstanceProfile", || {
+                request.clone().send()
+            })
+            .await;
+            match resp.map_err(iam_error) {
+                Ok(resp) => Ok(resp.instance_profile().map(|profile| {
+                    (
+                        Box::new(profile.clone()) as Box<dyn Any + Send>,
+                        vec![EXISTS.to_string()],
+                    )
+                })),
+                Err(AwsError::ResourceNotFound(_)) => Ok(None),
+                Err(err) => Err(format!(
+                    "Failed to read IAM instance profile {}: {}",
+                    resource_id, err
+                )),
+            }
+        })
+    }
+}
@@ -0,0 +1,51 @@
+pub mod instance_profile;
+pub mod role;
+
+use aws_sdk_iam::{error::ProvideErrorMetadata, types as iam_types};
+
+use crate::aws::error::AwsError;
+
+/// IAM reports missing roles and instance profiles as NoSuchEntity rather than `*.NotFound`
+pub(crate) fn iam_error<E: ProvideErrorMetadata + std::fmt::Display>(err: E) -> AwsError {
+    match err.code() {
+        Some("NoSuchEntity") => {
+            AwsError::ResourceNotFound(err.message().unwrap_or("NoSuchEntity").to_string())
+        }
+        _ => err.into(),
+    }
+}
+
+/// IAM tags for `tags`, None when there are none
+pub(crate) fn iam_tags(tags: &[(String, String)]) -> Result<Option<Vec<iam_types::Tag>>, AwsError> {
+    if tags.is_empty() {
+        return Ok(None);
+    }
+    tags.iter()
+        .map(|(key, value)| iam_types::Tag::builder().key(key).value(value).build())
+        .collect::<Result<Vec<_>, _>>()
+        .map(Some)
+        .map_err(|err| AwsError::OptionsError(err.to_string()))
+}
+
+/**
+ * Check `name` is a valid name for a role or instance profile: 1 to `max_len` letters, digits
+ * and any of `+=,.@_-`.
+ */
+pub fn validate_iam_name(kind: &str, name: &str, max_len: usize) -> Result<(), String> {
+    if name.is_empty() || name.len() > max_len {
+        return Err(format!(
+            "invalid {} name '{}': must be between 1 and {} characters long",
+            kind, name, max_len
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "+=,.@_-".contains(c))
+    {
+        return Err(format!(
+            "invalid {} name '{}': only letters, digits and +=,.@_- are allowed",
+            kind, name
+        ));
+    }
+    Ok(())
+}
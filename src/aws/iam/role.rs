@@ -0,0 +1,422 @@
+use aws_sdk_iam::types as iam_types;
+use tracing::{info, warn};
+
+use crate::aws::{
+    ec2::ec2_instance::DestroyOutcome,
+    error::AwsError,
+    iam::{iam_error, iam_tags, validate_iam_name},
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tags_from_yaml},
+    },
+};
+
+/// Longest name IAM accepts for a role
+const MAX_ROLE_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleOpts {
+    pub(crate) role_name: String,
+    pub(crate) assume_role_policy: String, // Trust policy as JSON
+    pub(crate) description: Option<String>,
+    pub(crate) managed_policy_arns: Vec<String>,
+    pub(crate) inline_policies: Vec<(String, String)>, // (policy name, document as JSON)
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct IamRole {
+    client: aws_sdk_iam::Client,
+}
+
+impl RoleOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+/**
+ * JSON text of a policy document written either as a YAML map or as a JSON string. IAM rejects
+ * a document without a Statement with a vague MalformedPolicyDocument, it is caught here instead.
+ */
+pub fn policy_json(document: &serde_yaml::Value) -> Result<String, String> {
+    let document: serde_json::Value = match document.as_str() {
+        Some(text) => serde_json::from_str(text)
+            .map_err(|err| format!("policy document is not valid JSON: {}", err))?,
+        None if document.is_mapping() => serde_json::to_value(document)
+            .map_err(|err| format!("policy document cannot be converted to JSON: {}", err))?,
+        None => return Err("policy document must be a map or a JSON string".to_string()),
+    };
+    if document.get("Statement").is_none() {
+        return Err("policy document has no Statement".to_string());
+    }
+    serde_json::to_string(&document).map_err(|err| err.to_string())
+}
+
+impl IamRole {
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_iam::Client::new(config);
+        IamRole { client }
+    }
+
+    /// `role_name` defaults to the name of the component
+    pub fn opts_from_yaml(name: &str, yaml: &serde_yaml::Value) -> Result<RoleOpts, AwsError> {
+        let role_name = yaml
+            .get("role_name")
+            .and_then(|n| n.as_str())
+            .unwrap_or(name)
+            .to_string();
+        validate_iam_name("role", &role_name, MAX_ROLE_NAME_LEN).map_err(AwsError::OptionsError)?;
+        let assume_role_policy = yaml
+            .get("assume_role_policy")
+            .ok_or_else(|| {
+                AwsError::OptionsError("Missing required field: assume_role_policy".to_string())
+            })
+            .and_then(|document| {
+                policy_json(document)
+                    .map_err(|err| AwsError::OptionsError(format!("assume_role_policy: {}", err)))
+            })?;
+
+        let mut managed_policy_arns = Vec::new();
+        if let Some(arns) = yaml.get("managed_policy_arns") {
+            let arns = arns.as_sequence().ok_or_else(|| {
+                AwsError::OptionsError("managed_policy_arns must be a list".to_string())
+            })?;
+            for arn in arns {
+                match arn.as_str() {
+                    Some(arn) if arn.starts_with("arn:") => {
+                        managed_policy_arns.push(arn.to_string())
+                    }
+                    _ => {
+                        return Err(AwsError::OptionsError(format!(
+                            "managed_policy_arns must hold policy ARNs, got {:?}",
+                            arn
+                        )));
+                    }
+                }
+            }
+        }
+
+        let mut inline_policies = Vec::new();
+        if let Some(policies) = yaml.get("inline_policies") {
+            let policies = policies.as_mapping().ok_or_else(|| {
+                AwsError::OptionsError(
+                    "inline_policies must be a map of policy names to documents".to_string(),
+                )
+            })?;
+            for (policy_name, document) in policies {
+                let policy_name = policy_name.as_str().ok_or_else(|| {
+                    AwsError::OptionsError("inline_policies names must be strings".to_string())
+                })?;
+                let document = policy_json(document).map_err(|err| {
+                    AwsError::OptionsError(format!("inline_policies.{}: {}", policy_name, err))
+                })?;
+                inline_policies.push((policy_name.to_string(), document));
+            }
+        }
+
+        Ok(RoleOpts {
+            role_name,
+            assume_role_policy,
+            description: yaml
+                .get("description")
+                .and_then(|d| d.as_str())
+                .map(|d| d.to_string()),
+            managed_policy_arns,
+            inline_policies,
+            tags: tags_from_yaml(yaml).map_err(AwsError::OptionsError)?,
+        })
+    }
+
+    /**
+     * Create the role and attach its managed and inline policies. A role whose policies cannot be
+     * attached is deleted again, it would otherwise be left behind without being in state.
+     */
+    pub async fn create_role(&self, opts: &RoleOpts) -> Result<iam_types::Role, AwsError> {
+        info!("Creating IAM role with config: {:?}", opts);
+        let request = self
+            .client
+            .create_role()
+            .role_name(&opts.role_name)
+            .assume_role_policy_document(&opts.assume_role_policy)
+            .set_description(opts.description.clone())
+            .set_tags(iam_tags(&opts.tags)?);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "CreateRole", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        let role = resp
+            .role()
+            .cloned()
+            .ok_or_else(|| AwsError::SdkError("CreateRole returned no role".into()))?;
+        info!("IAM role {} created ({})", role.role_name(), role.arn());
+
+        if let Err(err) = self.sync_policies(opts).await {
+            if let Err(cleanup) = self.destroy_role(&opts.role_name).await {
+                warn!("Failed to delete IAM role {}: {}", opts.role_name, cleanup);
+            }
+            return Err(err);
+        }
+        Ok(role)
+    }
+
+    /**
+     * Bring an existing role in line with `opts`: its trust policy, description and tags, and
+     * the managed and inline policies, detaching and deleting those the config no longer lists.
+     */
+    pub async fn update_role(&self, opts: &RoleOpts) -> Result<iam_types::Role, AwsError> {
+        let request = self
+            .client
+            .update_assume_role_policy()
+            .role_name(&opts.role_name)
+            .policy_document(&opts.assume_role_policy);
+        retry_throttled(&RetryPolicy::from_env(), "UpdateAssumeRolePolicy", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        let request = self
+            .client
+            .update_role()
+            .role_name(&opts.role_name)
+            .description(opts.description.clone().unwrap_or_default());
+        retry_throttled(&RetryPolicy::from_env(), "UpdateRole", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        if let Some(tags) = iam_tags(&opts.tags)? {
+            let request = self
+                .client
+                .tag_role()
+                .role_name(&opts.role_name)
+                .set_tags(Some(tags));
+            retry_throttled(&RetryPolicy::from_env(), "TagRole", || {
+                request.clone().send()
+            })
+            .await
+            .map_err(iam_error)?;
+        }
+        self.sync_policies(opts).await?;
+        info!("IAM role {} updated", opts.role_name);
+        self.describe_role(&opts.role_name).await
+    }
+
+    pub async fn describe_role(&self, role_name: &str) -> Result<iam_types::Role, AwsError> {
+        let request = self.client.get_role().role_name(role_name);
+        let resp = retry_throttled(&RetryPolicy::from_env(), "GetRole", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        resp.role()
+            .cloned()
+            .ok_or_else(|| AwsError::ResourceNotFound(role_name.to_string()))
+    }
+
+    /**
+     * Detach the managed policies, delete the inline policies and remove the role from the
+     * instance profiles holding it, IAM refuses to delete it before. A role that no longer exists
+     * is reported as AlreadyGone.
+     */
+    pub async fn destroy_role(&self, role_name: &str) -> Result<DestroyOutcome, AwsError> {
+        let attached = match self.attached_policy_arns(role_name).await {
+            Ok(attached) => attached,
+            Err(AwsError::ResourceNotFound(_)) => {
+                info!("IAM role {} is already deleted", role_name);
+                return Ok(DestroyOutcome::AlreadyGone);
+            }
+            Err(err) => return Err(err),
+        };
+        for arn in &attached {
+            self.detach_policy(role_name, arn).await?;
+        }
+        for policy_name in self.inline_policy_names(role_name).await? {
+            self.delete_inline_policy(role_name, &policy_name).await?;
+        }
+        let request = self
+            .client
+            .list_instance_profiles_for_role()
+            .role_name(role_name);
+        let profiles = retry_throttled(
+            &RetryPolicy::from_env(),
+            "ListInstanceProfilesForRole",
+            || request.clone().send(),
+        )
+        .await
+        .map_err(iam_error)?;
+        for profile in profiles.instance_profiles() {
+            let request = self
+                .client
+                .remove_role_from_instance_profile()
+                .instance_profile_name(profile.instance_profile_name())
+                .role_name(role_name);
+            retry_throttled(
+                &RetryPolicy::from_env(),
+                "RemoveRoleFromInstanceProfile",
+                || request.clone().send(),
+            )
+            .await
+            .map_err(iam_error)?;
+        }
+
+        let request = self.client.delete_role().role_name(role_name);
+        match retry_throttled(&RetryPolicy::from_env(), "DeleteRole", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)
+        {
+            Ok(_) => {
+                info!("IAM role {} deleted", role_name);
+                Ok(DestroyOutcome::Terminated)
+            }
+            Err(AwsError::ResourceNotFound(_)) => {
+                info!("IAM role {} is already deleted", role_name);
+                Ok(DestroyOutcome::AlreadyGone)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Attach and put the policies `opts` lists, detach and delete the ones it does not
+    async fn sync_policies(&self, opts: &RoleOpts) -> Result<(), AwsError> {
+        let role_name = &opts.role_name;
+        let attached = self.attached_policy_arns(role_name).await?;
+        for arn in &opts.managed_policy_arns {
+            if !attached.contains(arn) {
+                let request = self
+                    .client
+                    .attach_role_policy()
+                    .role_name(role_name)
+                    .policy_arn(arn);
+                retry_throttled(&RetryPolicy::from_env(), "AttachRolePolicy", || {
+                    request.clone().send()
+                })
+                .await
+                .map_err(iam_error)?;
+                info!("Attached {} to IAM role {}", arn, role_name);
+            }
+        }
+        for arn in attached
+            .iter()
+            .filter(|a| !opts.managed_policy_arns.contains(a))
+        {
+            self.detach_policy(role_name, arn).await?;
+        }
+
+        let existing = self.inline_policy_names(role_name).await?;
+        for (policy_name, document) in &opts.inline_policies {
+            let request = self
+                .client
+                .put_role_policy()
+                .role_name(role_name)
+                .policy_name(policy_name)
+                .policy_document(document);
+            retry_throttled(&RetryPolicy::from_env(), "PutRolePolicy", || {
+                request.clone().send()
+            })
+            .await
+            .map_err(iam_error)?;
+        }
+        for policy_name in existing
+            .iter()
+            .filter(|p| !opts.inline_policies.iter().any(|(name, _)| name == *p))
+        {
+            self.delete_inline_policy(role_name, policy_name).await?;
+        }
+        Ok(())
+    }
+
+    async fn detach_policy(&self, role_name: &str, arn: &str) -> Result<(), AwsError> {
+        let request = self
+            .client
+            .detach_role_policy()
+            .role_name(role_name)
+            .policy_arn(arn);
+        retry_throttled(&RetryPolicy::from_env(), "DetachRolePolicy", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        info!("Detached {} from IAM role {}", arn, role_name);
+        Ok(())
+    }
+
+    async fn delete_inline_policy(
+        &self,
+        role_name: &str,
+        policy_name: &str,
+    ) -> Result<(), AwsError> {
+        let request = self
+            .client
+            .delete_role_policy()
+            .role_name(role_name)
+            .policy_name(policy_name);
+        retry_throttled(&RetryPolicy::from_env(), "DeleteRolePolicy", || {
+            request.clone().send()
+        })
+        .await
+        .map_err(iam_error)?;
+        info!(
+            "Deleted inline policy {} of IAM role {}",
+            policy_name, role_name
+        );
+        Ok(())
+    }
+
+    /// ARNs of every managed policy attached to the role, a page at a time
+    async fn attached_policy_arns(&self, role_name: &str) -> Result<Vec<String>, AwsError> {
+        let mut arns = Vec::new();
+        let mut marker = None;
+        loop {
+            let request = self
+                .client
+                .list_attached_role_policies()
+                .role_name(role_name)
+                .set_marker(marker);
+            let page =
+                retry_throttled(&RetryPolicy::from_env(), "ListAttachedRolePolicies", || {
+                    request.clone().send()
+                })
+                .await
+                .map_err(iam_error)?;
+            arns.extend(
+                page.attached_policies()
+                    .iter()
+                    .filter_map(|p| p.policy_arn())
+                    .map(|arn| arn.to_string()),
+            );
+            if !page.is_truncated() {
+                return Ok(arns);
+            }
+            marker = page.marker().map(|m| m.to_string());
+        }
+    }
+
+    /// Names of every inline policy of the role
+    async fn inline_policy_names(&self, role_name: &str) -> Result<Vec<String>, AwsError> {
+        let mut names = Vec::new();
+        let mut marker = None;
+        loop {
+            let request = self
+                .client
+                .list_role_policies()
+                .role_name(role_name)
+                .set_marker(marker);
+            let page = retry_throttled(&RetryPolicy::from_env(), "ListRolePolicies", || {
+                request.clone().send()
+            })
+            .await
+            .map_err(iam_error)?;
+            names.extend(page.policy_names().iter().cloned());
+            if !page.is_truncated() {
+                return Ok(names);
+            }
+            marker = page.marker().map(|m| m.to_string());
+        }
+    }
+}
@@ -1 +1,3 @@
+pub mod retry;
+pub mod tags;
 pub mod wait_and_refresh;
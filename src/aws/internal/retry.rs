@@ -0,0 +1,258 @@
+/// Module for retrying AWS calls that were throttled or hit a server-side error
+use std::{
+    env,
+    future::Future,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_ec2::error::ProvideErrorMetadata;
+use tokio::time::Instant;
+use tracing::warn;
+
+pub const THROTTLE_RETRIES_ENV: &str = "LETUS_THROTTLE_RETRIES";
+pub const THROTTLE_BASE_DELAY_ENV: &str = "LETUS_THROTTLE_BASE_DELAY_MS";
+pub const THROTTLE_MAX_DELAY_ENV: &str = "LETUS_THROTTLE_MAX_DELAY_MS";
+pub const PROPAGATION_TIMEOUT_ENV: &str = "LETUS_PROPAGATION_TIMEOUT_MS";
+
+/// Codes AWS returns when a caller goes over its request rate
+const THROTTLING_CODES: &[&str] = &[
+    "RequestLimitExceeded",
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottled",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "SlowDown",
+];
+
+/// Codes of errors on the AWS side that usually go away on their own
+const SERVER_CODES: &[&str] = &[
+    "InternalError",
+    "InternalFailure",
+    "ServiceUnavailable",
+    "Unavailable",
+];
+
+/**
+ * Errors that only mean a resource created moments ago is not visible to the service yet, as
+ * (code, part of the message). IAM is eventually consistent, a new instance profile can be
+ * rejected by RunInstances for several seconds.
+ */
+const PROPAGATION_ERRORS: &[(&str, &str)] =
+    &[("InvalidParameterValue", "Invalid IAM Instance Profile")];
+
+/**
+ * RunInstances error codes and Spot request status codes for an instance type AWS has no spare
+ * capacity of right now. Capacity usually frees up within minutes.
+ */
+const CAPACITY_CODES: &[&str] = &[
+    "InsufficientInstanceCapacity",
+    "capacity-not-available",
+    "capacity-oversubscribed",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    Throttling,
+    Server,      // 5xx, retried like throttling
+    Propagating, // A new resource is not visible yet, retried until the propagation timeout
+    Client,      // Anything else, retrying would fail the same way
+}
+
+/// Whether `code` means the request was throttled
+pub fn is_throttling(code: &str) -> bool {
+    THROTTLING_CODES.contains(&code)
+}
+
+/// Whether `code` and `message` mean a resource created moments ago has not propagated yet
+pub fn is_propagating(code: &str, message: Option<&str>) -> bool {
+    PROPAGATION_ERRORS
+        .iter()
+        .any(|(c, m)| *c == code && message.is_some_and(|message| message.contains(m)))
+}
+
+/// Whether `code` means AWS has no capacity for the instance type at the moment
+pub fn is_capacity_unavailable(code: &str) -> bool {
+    CAPACITY_CODES.contains(&code)
+}
+
+/// Sort an SDK error by its code, errors without a code are left to the SDK's own retries
+pub fn classify<E: ProvideErrorMetadata>(err: &E) -> ErrorClass {
+    match err.code() {
+        Some(code) if is_throttling(code) => ErrorClass::Throttling,
+        Some(code) if SERVER_CODES.contains(&code) => ErrorClass::Server,
+        Some(code) if is_propagating(code, err.message()) => ErrorClass::Propagating,
+        _ => ErrorClass::Client,
+    }
+}
+
+/// How often and how long to back off, on top of the retries the SDK makes itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: u32,         // Retries after the first attempt, 0 disables retrying
+    pub base_delay: Duration, // Delay before the first retry, doubled for every next one
+    pub max_delay: Duration,  // Cap of a single delay
+    pub propagation_timeout: Duration, // How long propagation errors are retried for
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 5,                                   // default 5 retries
+            base_delay: Duration::from_millis(500),       // default 0.5 seconds
+            max_delay: Duration::from_secs(20),           // default 20 seconds
+            propagation_timeout: Duration::from_secs(60), // default 1 minute
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Build the policy from the LETUS_THROTTLE_* environment variables.
+    /// Unset or unparsable variables keep the built-in default.
+    pub fn from_env() -> Self {
+        let defaults = RetryPolicy::default();
+        RetryPolicy {
+            retries: env_parse::<u32>(THROTTLE_RETRIES_ENV).unwrap_or(defaults.retries),
+            base_delay: env_parse::<u64>(THROTTLE_BASE_DELAY_ENV)
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.base_delay),
+            max_delay: env_parse::<u64>(THROTTLE_MAX_DELAY_ENV)
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.max_delay),
+            propagation_timeout: env_parse::<u64>(PROPAGATION_TIMEOUT_ENV)
+                .map(Duration::from_millis)
+                .unwrap_or(defaults.propagation_timeout),
+        }
+    }
+
+    /**
+     * Delay before retry number `attempt` (counting from 0): the base delay doubled once per
+     * earlier retry and capped, then scaled into its upper half by `jitter` (0.0 to 1.0) so
+     * concurrent components do not retry in lockstep.
+     */
+    pub fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let exponential = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        exponential.div_f64(2.0) + exponential.div_f64(2.0).mul_f64(jitter.clamp(0.0, 1.0))
+    }
+}
+
+/**
+ * Run `call` until it succeeds, fails with a client error or the retries of `policy` are used up.
+ * Throttling and server errors are retried with jittered exponential backoff, the last error is
+ * returned once the budget is spent. Propagation errors do not count against the retries, they
+ * are retried with the same backoff until `propagation_timeout` has passed.
+ */
+pub async fn retry_throttled<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    operation: &str,
+    mut call: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: ProvideErrorMetadata,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    let mut propagation_attempt = 0;
+    loop {
+        match call().await {
+            Err(err)
+                if classify(&err) == ErrorClass::Propagating
+                    && started.elapsed() < policy.propagation_timeout =>
+            {
+                let remaining = policy.propagation_timeout.saturating_sub(started.elapsed());
+                let delay = policy.backoff(propagation_attempt, jitter()).min(remaining);
+                warn!(
+                    "{} failed with {}, a new resource may not have propagated yet, retrying in {:?}",
+                    operation,
+                    err.code().unwrap_or_default(),
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                propagation_attempt += 1;
+            }
+            Err(err) if attempt < policy.retries && classify(&err) != ErrorClass::Client => {
+                let delay = policy.backoff(attempt, jitter());
+                warn!(
+                    "{} failed with {}, retry {} of {} in {:?}",
+                    operation,
+                    err.code().unwrap_or_default(),
+                    attempt + 1,
+                    policy.retries,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/**
+ * Run `call` through `retry_throttled`, and retry it again while it fails because AWS has no
+ * capacity, until `budget` has passed. Only Spot launches use this, an On-Demand launch without
+ * capacity fails right away. The last capacity error is returned once the budget is spent.
+ */
+pub async fn retry_capacity<T, E, F, Fut>(
+    policy: &RetryPolicy,
+    budget: Duration,
+    operation: &str,
+    mut call: F,
+) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: ProvideErrorMetadata,
+{
+    let started = Instant::now();
+    let mut attempt = 0;
+    loop {
+        match retry_throttled(policy, operation, &mut call).await {
+            Err(err)
+                if err.code().is_some_and(is_capacity_unavailable)
+                    && started.elapsed() < budget =>
+            {
+                let remaining = budget.saturating_sub(started.elapsed());
+                let delay = policy.backoff(attempt, jitter()).min(remaining);
+                warn!(
+                    "{} failed with {}, no capacity available, retrying in {:?}",
+                    operation,
+                    err.code().unwrap_or_default(),
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Random enough to spread retries apart, without pulling in a rand dependency
+pub(crate) fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    f64::from(nanos % 1000) / 1000.0
+}
+
+fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = env::var(key).ok()?;
+    match value.trim().parse::<T>() {
+        Ok(parsed) => Some(parsed),
+        Err(err) => {
+            warn!("Ignoring invalid value '{}' for {}: {}", value, key, err);
+            None
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use aws_sdk_ec2::types as ec2_types;
+
+pub const DEPLOYMENT_TAG: &str = "letusinfra:deployment";
+pub const WORKSPACE_TAG: &str = "letusinfra:workspace";
+pub const COMPONENT_TAG: &str = "letusinfra:component";
+pub const COMPONENT_TYPE_TAG: &str = "letusinfra:component-type";
+
+/// Where a resource comes from, injected as tags on everything apply creates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeploymentTags {
+    pub deployment: String, // metadata.name of the config
+    pub workspace: String,
+    pub component_type: String,
+    pub component_name: String,
+}
+
+impl DeploymentTags {
+    /// Unique across deployments and workspaces, `<deployment>/<workspace>/<type>.<name>`
+    pub fn component_id(&self) -> String {
+        format!(
+            "{}/{}/{}.{}",
+            self.deployment, self.workspace, self.component_type, self.component_name
+        )
+    }
+
+    pub fn tags(&self) -> Vec<(String, String)> {
+        vec![
+            (DEPLOYMENT_TAG.to_string(), self.deployment.clone()),
+            (WORKSPACE_TAG.to_string(), self.workspace.clone()),
+            (COMPONENT_TAG.to_string(), self.component_id()),
+            (COMPONENT_TYPE_TAG.to_string(), self.component_type.clone()),
+        ]
+    }
+}
+
+/// Parse the `tags` property of a component, a map of string keys to string values
+pub fn tags_from_yaml(yaml: &serde_yaml::Value) -> Result<Vec<(String, String)>, String> {
+    let Some(tags) = yaml.get("tags") else {
+        return Ok(Vec::new());
+    };
+    tags.as_mapping()
+        .ok_or_else(|| "tags must be a map".to_string())?
+        .iter()
+        .map(|(k, v)| match (k.as_str(), v.as_str()) {
+            (Some(k), Some(v)) => Ok((k.to_string(), v.to_string())),
+            _ => Err("tags keys and values must be strings".to_string()),
+        })
+        .collect()
+}
+
+/// `user` tags followed by the `injected` ones they do not override, user tags always win
+pub fn merge_tags(
+    user: &[(String, String)],
+    injected: &[(String, String)],
+) -> Vec<(String, String)> {
+    let mut merged = user.to_vec();
+    for (key, value) in injected {
+        if !user.iter().any(|(k, _)| k == key) {
+            merged.push((key.clone(), value.clone()));
+        }
+    }
+    merged
+}
+
+/// Tag specification applying `tags` to `resource_type`, None when there are no tags
+pub fn tag_specification(
+    resource_type: ec2_types::ResourceType,
+    tags: &[(String, String)],
+) -> Option<ec2_types::TagSpecification> {
+    if tags.is_empty() {
+        return None;
+    }
+    let mut specification = ec2_types::TagSpecification::builder().resource_type(resource_type);
+    for (key, value) in tags {
+        specification = specification.tags(ec2_types::Tag::builder().key(key).value(value).build());
+    }
+    Some(specification.build())
+}
+
+/**
+ * Merge `injected` into the specification of `resource_type`, adding one when the user did not
+ * tag that resource type. Tags the user set on the same resource type win, other resource types
+ * are left untouched.
+ */
+pub fn merge_tag_specifications(
+    specifications: Vec<ec2_types::TagSpecification>,
+    resource_type: ec2_types::ResourceType,
+    injected: &[(String, String)],
+) -> Vec<ec2_types::TagSpecification> {
+    let mut merged = Vec::with_capacity(specifications.len() + 1);
+    let mut found = false;
+    for specification in specifications {
+        if specification.resource_type() != Some(&resource_type) {
+            merged.push(specification);
+            continue;
+        }
+        found = true;
+        let user: Vec<(String, String)> = specification
+            .tags()
+            .iter()
+            .filter_map(|t| Some((t.key()?.to_string(), t.value()?.to_string())))
+            .collect();
+        merged.extend(tag_specification(
+            resource_type.clone(),
+            &merge_tags(&user, injected),
+        ));
+    }
+    if !found {
+        merged.extend(tag_specification(resource_type, injected));
+    }
+    merged
+}
@@ -1,8 +1,15 @@
-use std::{any::Any, future::Future, pin::Pin, time::Duration};
+use std::{
+    any::Any,
+    env,
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use tokio::time::{Instant, sleep, timeout};
 
-use crate::aws::AWSClient;
+use crate::{aws::AWSClient, models::InfraConfig};
 #[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
 pub enum WaitError {
     #[error(
@@ -23,13 +30,167 @@ pub enum WaitError {
     #[error("Error refreshing resource state: {0}")]
     RefreshError(String),
 }
-pub type RefreshFunctionReturn =
-    Pin<Box<dyn Future<Output = Result<Option<(Box<dyn Any>, Vec<String>)>, String>>>>;
-pub type RefreshFunction = Box<dyn Fn(AWSClient, String) -> RefreshFunctionReturn>;
+/// Send, so a wait can run in a task spawned onto the runtime
+pub type RefreshFunctionReturn = Pin<
+    Box<dyn Future<Output = Result<Option<(Box<dyn Any + Send>, Vec<String>)>, String>> + Send>,
+>;
+pub type RefreshFunction = Box<dyn Fn(AWSClient, String) -> RefreshFunctionReturn + Send + Sync>;
+
+/// How the states a refresh reported compare to the ones a wait expects
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateMatch {
+    Target,     // Every state is a target state
+    Pending,    // Every state is a pending or target state, at least one is pending
+    Unexpected, // Some state is neither
+}
+
+/**
+ * Compare the states of a refresh, one per instance or check, with the target and pending states.
+ * Each of `current_state` must be one of `target_state` for the wait to be done, so
+ * `["running", "stopped"]` accepts instances in either state. An empty `pending_state` accepts any
+ * state while waiting. No state at all is never the target.
+ */
+pub fn match_states(
+    current_state: &[String],
+    target_state: &[String],
+    pending_state: &[String],
+) -> StateMatch {
+    if !current_state.is_empty() && current_state.iter().all(|s| target_state.contains(s)) {
+        return StateMatch::Target;
+    }
+    let waiting = |s: &String| pending_state.contains(s) || target_state.contains(s);
+    if pending_state.is_empty() || current_state.iter().all(waiting) {
+        return StateMatch::Pending;
+    }
+    StateMatch::Unexpected
+}
+
+pub const WAIT_INITIAL_DELAY_ENV: &str = "LETUS_WAIT_INITIAL_DELAY_MS";
+pub const WAIT_TIMEOUT_ENV: &str = "LETUS_WAIT_TIMEOUT_MS";
+pub const WAIT_MIN_DELAY_ENV: &str = "LETUS_WAIT_MIN_DELAY_MS";
+pub const WAIT_MAX_DELAY_ENV: &str = "LETUS_WAIT_MAX_DELAY_MS";
+pub const WAIT_REFRESH_TIMEOUT_ENV: &str = "LETUS_WAIT_REFRESH_TIMEOUT_MS";
+pub const WAIT_NOT_FOUND_CHECKS_ENV: &str = "LETUS_WAIT_NOT_FOUND_CHECKS";
+pub const NOT_FOUND_GRACE_ENV: &str = "LETUS_NOT_FOUND_GRACE_MS";
+
+/// Every tunable setting, by the environment variable that sets it
+const WAIT_SETTINGS: [&str; 7] = [
+    WAIT_INITIAL_DELAY_ENV,
+    WAIT_TIMEOUT_ENV,
+    WAIT_MIN_DELAY_ENV,
+    WAIT_MAX_DELAY_ENV,
+    WAIT_REFRESH_TIMEOUT_ENV,
+    WAIT_NOT_FOUND_CHECKS_ENV,
+    NOT_FOUND_GRACE_ENV,
+];
+
+/**
+ * Global polling defaults applied to every StateChangeConfig.
+ * Values not passed explicitly to StateChangeConfig::new fall back to these. A command builds them
+ * once through `for_config`, from the LETUS_WAIT_* environment variables and the config's
+ * variables, and hands them to the resources it waits on (durations in milliseconds).
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitDefaults {
+    pub initial_delay: Duration,
+    pub timeout: Duration,
+    pub min_delay: Duration,
+    pub max_delay: Duration,
+    pub refresh_timeout: Duration, // Cap applied to every individual refresh call
+    pub not_found_checks: u32,
+    pub not_found_grace: Duration, // Window after creation in which NotFound means "still materializing"
+}
+
+impl Default for WaitDefaults {
+    fn default() -> Self {
+        WaitDefaults {
+            initial_delay: Duration::from_secs(0),    // default 0 seconds
+            timeout: Duration::from_secs(300),        // default 5 minutes
+            min_delay: Duration::from_secs(5),        // default 5 seconds
+            max_delay: Duration::from_secs(60),       // default 1 minute
+            refresh_timeout: Duration::from_secs(30), // default 30 seconds
+            not_found_checks: 20,                     // default 20 checks
+            not_found_grace: Duration::from_secs(60), // default 1 minute
+        }
+    }
+}
+
+impl WaitDefaults {
+    /// Build the defaults from the LETUS_WAIT_* environment variables.
+    /// Unset or unparsable variables keep the built-in default.
+    pub fn from_env() -> Self {
+        WaitDefaults::default().overridden_by(|key| env::var(key).ok())
+    }
+
+    /**
+     * Defaults of the deployment `config` describes: the environment variables, overridden by
+     * variables of the config named like them in lower case without the prefix, e.g.
+     * `wait_min_delay_ms` or `not_found_grace_ms`.
+     */
+    pub fn for_config(config: &InfraConfig) -> Self {
+        WaitDefaults::from_env().overridden_by(|key| {
+            let name = setting_variable(key);
+            let variable = config.variables.iter().find(|v| v.name == name)?;
+            match variable.value.as_ref().or(variable.default.as_ref())? {
+                serde_yaml::Value::String(value) => Some(value.clone()),
+                serde_yaml::Value::Number(value) => Some(value.to_string()),
+                _ => None,
+            }
+        })
+    }
+
+    /**
+     * Replace every setting `setting` has a value for, looked up by its environment variable.
+     * Values that do not parse are ignored with a warning.
+     */
+    pub fn overridden_by(self, setting: impl Fn(&str) -> Option<String>) -> Self {
+        let millis = |key: &str| parse_setting::<u64>(key, setting(key)).map(Duration::from_millis);
+        WaitDefaults {
+            initial_delay: millis(WAIT_INITIAL_DELAY_ENV).unwrap_or(self.initial_delay),
+            timeout: millis(WAIT_TIMEOUT_ENV).unwrap_or(self.timeout),
+            min_delay: millis(WAIT_MIN_DELAY_ENV).unwrap_or(self.min_delay),
+            max_delay: millis(WAIT_MAX_DELAY_ENV).unwrap_or(self.max_delay),
+            refresh_timeout: millis(WAIT_REFRESH_TIMEOUT_ENV).unwrap_or(self.refresh_timeout),
+            not_found_checks: parse_setting(
+                WAIT_NOT_FOUND_CHECKS_ENV,
+                setting(WAIT_NOT_FOUND_CHECKS_ENV),
+            )
+            .unwrap_or(self.not_found_checks),
+            not_found_grace: millis(NOT_FOUND_GRACE_ENV).unwrap_or(self.not_found_grace),
+        }
+    }
+}
+
+/// Name of the config variable tuning the setting of environment variable `key`
+fn setting_variable(key: &str) -> String {
+    key.trim_start_matches("LETUS_").to_lowercase()
+}
+
+/// Whether variable `name` tunes the waits, those are read without a `${var.<name>}`
+pub fn is_wait_variable(name: &str) -> bool {
+    WAIT_SETTINGS
+        .iter()
+        .any(|key| setting_variable(key) == name)
+}
+
+fn parse_setting<T: std::str::FromStr>(key: &str, value: Option<String>) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = value?;
+    match value.trim().parse::<T>() {
+        Ok(parsed) => Some(parsed),
+        Err(err) => {
+            tracing::warn!("Ignoring invalid value '{}' for {}: {}", value, key, err);
+            None
+        }
+    }
+}
+
 /**
  * Configuration for waiting on a resource to reach a desired state.
- * target_state: The desired state to wait for.
- * pending_state: The intermediate state indicating the resource is still in transition.
+ * target_state: The desired states to wait for, every reported state has to be one of them.
+ * pending_state: The intermediate states indicating the resource is still in transition.
  * refresh_fn: A function that refreshes the resource state. It takes an AWS EC2 client and a resource identifier,
  * and returns a Future that resolves to the current state of the resource.
  */
@@ -41,11 +202,14 @@ pub struct StateChangeConfig {
     pub timeout: Duration,       // Maximum time to wait for the desired state
     pub min_delay: Duration,     // Minimum delay between refresh attempts
     pub max_delay: Duration, // Maximum delay between refresh attempts, Used for exponential backoff
+    pub refresh_timeout: Duration, // Maximum time a single refresh call may take
     pub not_found_checks: u32, // Number of consecutive not found checks before giving up
 }
 
 impl StateChangeConfig {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        defaults: &WaitDefaults,
         target_state: Vec<String>,
         pending_state: Vec<String>,
         refresh_fn: RefreshFunction,
@@ -55,16 +219,17 @@ impl StateChangeConfig {
         max_delay: Option<Duration>,
         not_found_checks: Option<u32>,
     ) -> Self {
+        // Explicit per-resource values win over the defaults of the deployment
         StateChangeConfig {
             target_state,
             pending_state,
             refresh_fn,
-            initial_delay: start_delay.unwrap_or(Duration::from_secs(0)), // default 0 seconds
-            timeout: timeout.unwrap_or(Duration::from_secs(300)),         // default 5 minutes
-            min_delay: min_delay.unwrap_or(Duration::from_secs(5)),
-            // default 5 seconds
-            max_delay: max_delay.unwrap_or(Duration::from_secs(60)), // default 1 minute
-            not_found_checks: not_found_checks.unwrap_or(20),        // default 20 checks
+            initial_delay: start_delay.unwrap_or(defaults.initial_delay),
+            timeout: timeout.unwrap_or(defaults.timeout),
+            min_delay: min_delay.unwrap_or(defaults.min_delay),
+            max_delay: max_delay.unwrap_or(defaults.max_delay),
+            refresh_timeout: defaults.refresh_timeout,
+            not_found_checks: not_found_checks.unwrap_or(defaults.not_found_checks),
         }
     }
 
@@ -72,12 +237,12 @@ impl StateChangeConfig {
         &self,
         client: AWSClient,
         resource_id: String,
-    ) -> Result<Option<Box<dyn Any>>, WaitError> {
+    ) -> Result<Option<Box<dyn Any + Send>>, WaitError> {
         let start_time = Instant::now(); // Track the start time for timeout calculation
         let mut not_found_count = 0u32;
         let mut current_delay = self.min_delay;
         let mut last_state = String::new();
-        let mut last_resource: Option<Box<dyn Any>>;
+        let mut last_resource: Option<Box<dyn Any + Send>>;
         let mut i: u32 = 0;
 
         // Initial delay
@@ -86,12 +251,11 @@ impl StateChangeConfig {
         }
 
         loop {
+            // Logged rather than printed, waits of concurrent components would interleave
             tracing::info!(
-                "Waiting for EC2 instance creation, elapsed: {:?}",
-                start_time.elapsed()
-            );
-            println!(
-                "Waiting for EC2 instance creation, elapsed: {:?}",
+                "Waiting for {} to reach {}, elapsed: {:?}",
+                resource_id,
+                self.target_state.join(" or "),
                 start_time.elapsed()
             );
             i += 1;
@@ -107,7 +271,7 @@ impl StateChangeConfig {
             // Refresh the resource state with timeout
             let remaining_time = self.timeout.saturating_sub(start_time.elapsed());
             let refresh_result = timeout(
-                remaining_time.min(Duration::from_secs(30)), // Cap individual refresh timeout
+                remaining_time.min(self.refresh_timeout), // Cap individual refresh timeout
                 (self.refresh_fn)(client.clone(), resource_id.clone()),
             )
             .await
@@ -116,7 +280,10 @@ impl StateChangeConfig {
             let (resource, current_state) = match refresh_result {
                 Ok(Some((res, state))) => (Some(res), state),
                 Ok(None) => (None, vec![]),
-                Err(_) => return Err(WaitError::RefreshError("Refresh timeout".to_string())),
+                Err(err) => {
+                    tracing::warn!("Refresh of {} failed: {}", resource_id, err);
+                    return Err(WaitError::RefreshError("Refresh timeout".to_string()));
+                }
             };
 
             last_state = current_state
@@ -144,26 +311,24 @@ impl StateChangeConfig {
                 not_found_count = 0;
                 last_resource = resource;
 
-                // Check if current state is a target state
-                if self.target_state == current_state {
-                    return Ok(last_resource);
-                }
-
-                // Check if current state is a pending state
-                if self.pending_state != current_state && !self.pending_state.is_empty() {
-                    return Err(WaitError::UnexpectedState {
-                        current_state: current_state
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect::<Vec<_>>()
-                            .join("\n"),
-                        expected_states: self
-                            .target_state
-                            .iter()
-                            .chain(self.pending_state.iter())
-                            .map(|s| s.to_string())
-                            .collect(),
-                    });
+                match match_states(&current_state, &self.target_state, &self.pending_state) {
+                    StateMatch::Target => return Ok(last_resource),
+                    StateMatch::Pending => {}
+                    StateMatch::Unexpected => {
+                        return Err(WaitError::UnexpectedState {
+                            current_state: current_state
+                                .iter()
+                                .map(|s| s.to_string())
+                                .collect::<Vec<_>>()
+                                .join("\n"),
+                            expected_states: self
+                                .target_state
+                                .iter()
+                                .chain(self.pending_state.iter())
+                                .map(|s| s.to_string())
+                                .collect(),
+                        });
+                    }
                 }
             }
 
@@ -176,3 +341,73 @@ impl StateChangeConfig {
         }
     }
 }
+
+const FOUND_STATE: &str = "found";
+
+/**
+ * Read a resource that may have been created moments ago.
+ * Right after creation the API can briefly report the new id as missing (eventual consistency),
+ * so a NotFound within the `not_found_grace` of `defaults` after `created_at` is retried through
+ * wait_until_state until the resource shows up or the window closes. Outside the window NotFound
+ * means the resource is gone.
+ */
+pub async fn read_with_grace(
+    refresh_fn: RefreshFunction,
+    client: AWSClient,
+    resource_id: String,
+    created_at: Option<SystemTime>,
+    defaults: &WaitDefaults,
+) -> Result<Option<Box<dyn Any + Send>>, WaitError> {
+    let refresh_fn = Arc::new(refresh_fn);
+    match (refresh_fn)(client.clone(), resource_id.clone()).await {
+        Ok(Some((resource, _))) => return Ok(Some(resource)),
+        Ok(None) => {}
+        Err(err) => return Err(WaitError::RefreshError(err)),
+    }
+
+    // Unknown creation time means outside the window, a clock behind the state means just created
+    let age = created_at
+        .map(|created| {
+            SystemTime::now()
+                .duration_since(created)
+                .unwrap_or(Duration::ZERO)
+        })
+        .unwrap_or(Duration::MAX);
+    let remaining = defaults.not_found_grace.saturating_sub(age);
+    if remaining.is_zero() {
+        return Ok(None);
+    }
+    tracing::info!(
+        "{} not found {:?} after creation, waiting up to {:?} for it to materialize",
+        resource_id,
+        age,
+        remaining
+    );
+
+    // Any state of the resource will do, only its existence matters
+    let inner = Arc::clone(&refresh_fn);
+    let exists_fn: RefreshFunction = Box::new(move |client, resource_id| {
+        let refresh = (inner)(client, resource_id);
+        Box::pin(async move {
+            Ok(refresh
+                .await?
+                .map(|(resource, _)| (resource, vec![FOUND_STATE.to_string()])))
+        })
+    });
+    let config = StateChangeConfig::new(
+        defaults,
+        vec![FOUND_STATE.to_string()],
+        vec![],
+        exists_fn,
+        None,
+        Some(remaining),
+        None,
+        None,
+        Some(u32::MAX), // Bounded by the grace window instead
+    );
+    match config.wait_until_state(client, resource_id).await {
+        Ok(resource) => Ok(resource),
+        Err(WaitError::Timeout { .. }) | Err(WaitError::NotFound { .. }) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
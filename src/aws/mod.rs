@@ -1,8 +1,14 @@
 pub mod credentials;
 pub mod ec2;
+pub mod error;
+pub mod iam;
 pub mod internal;
+pub mod s3;
+pub mod sdk_config;
 
 #[derive(Debug, Clone)]
 pub enum AWSClient {
     EC2Client(aws_sdk_ec2::Client),
+    S3Client(aws_sdk_s3::Client),
+    IAMClient(aws_sdk_iam::Client),
 }
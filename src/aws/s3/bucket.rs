@@ -0,0 +1,452 @@
+use std::any::Any;
+
+use aws_sdk_s3::{error::ProvideErrorMetadata, types as s3_types};
+use tracing::info;
+
+use crate::aws::{
+    AWSClient,
+    ec2::ec2_instance::DestroyOutcome,
+    error::AwsError,
+    internal::{
+        retry::{RetryPolicy, retry_throttled},
+        tags::{merge_tags, tags_from_yaml},
+        wait_and_refresh::{RefreshFunctionReturn, StateChangeConfig, WaitDefaults},
+    },
+};
+
+const EXISTS: &str = "exists";
+/// Region a bucket is created in without a LocationConstraint
+const DEFAULT_REGION: &str = "us-east-1";
+/// Most keys a single DeleteObjects call accepts
+const DELETE_BATCH_SIZE: usize = 1000;
+const RESERVED_PREFIXES: &[&str] = &["xn--", "sthree-", "amzn-s3-demo-"];
+const RESERVED_SUFFIXES: &[&str] = &["-s3alias", "--ol-s3", ".mrap", "--x-s3", "--table-s3"];
+
+/// Settings of the bucket's public access block, every one is on unless turned off
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PublicAccessBlock {
+    pub(crate) block_public_acls: bool,
+    pub(crate) ignore_public_acls: bool,
+    pub(crate) block_public_policy: bool,
+    pub(crate) restrict_public_buckets: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketOpts {
+    pub(crate) bucket: String,
+    pub(crate) versioning: bool,
+    pub(crate) public_access_block: PublicAccessBlock,
+    pub(crate) tags: Vec<(String, String)>,
+}
+
+#[derive(Clone)]
+pub struct S3Bucket {
+    client: aws_sdk_s3::Client,
+    region: String,
+    wait: WaitDefaults,
+}
+
+impl BucketOpts {
+    /// Add tags injected by apply, tags from the properties win
+    pub fn with_tags(mut self, injected: &[(String, String)]) -> Self {
+        self.tags = merge_tags(&self.tags, injected);
+        self
+    }
+}
+
+/**
+ * Check `name` follows the S3 naming rules for general purpose buckets: 3 to 63 lowercase
+ * letters, digits, dots and hyphens, starting and ending with a letter or digit, no adjacent
+ * dots, not formatted as an IP address and none of the prefixes and suffixes AWS reserves.
+ */
+pub fn validate_bucket_name(name: &str) -> Result<(), String> {
+    let invalid = |reason: &str| Err(format!("invalid bucket name '{}': {}", name, reason));
+    if !(3..=63).contains(&name.len()) {
+        return invalid("must be between 3 and 63 characters long");
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return invalid("only lowercase letters, digits, dots and hyphens are allowed");
+    }
+    let alphanumeric = |c: Option<char>| c.is_some_and(|c| c.is_ascii_alphanumeric());
+    if !alphanumeric(name.chars().next()) || !alphanumeric(name.chars().last()) {
+        return invalid("must start and end with a letter or digit");
+    }
+    if name.contains("..") {
+        return invalid("must not contain two adjacent dots");
+    }
+    let octets: Vec<&str> = name.split('.').collect();
+    if octets.len() == 4 && octets.iter().all(|o| o.parse::<u8>().is_ok()) {
+        return invalid("must not be formatted as an IP address");
+    }
+    if let Some(prefix) = RESERVED_PREFIXES.iter().find(|p| name.starts_with(*p)) {
+        return invalid(&format!("the prefix '{}' is reserved", prefix));
+    }
+    if let Some(suffix) = RESERVED_SUFFIXES.iter().find(|s| name.ends_with(*s)) {
+        return invalid(&format!("the suffix '{}' is reserved", suffix));
+    }
+    Ok(())
+}
+
+/// ARN of a bucket, in the partition of `region`
+pub fn bucket_arn(bucket: &str, region: &str) -> String {
+    let partition = if region.starts_with("cn-") {
+        "aws-cn"
+    } else if region.starts_with("us-gov-") {
+        "aws-us-gov"
+    } else {
+        "aws"
+    };
+    format!("arn:{}:s3:::{}", partition, bucket)
+}
+
+/// A bucket that does not exist, HeadBucket only reports it as a bare 404
+fn is_missing(code: Option<&str>) -> bool {
+    matches!(code, Some("NoSuchBucket") | Some("NotFound"))
+}
+
+impl S3Bucket {
+    pub fn from_config(config: &aws_types::SdkConfig) -> Self {
+        let client = aws_sdk_s3::Client::new(config);
+        let region = config
+            .region()
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| DEFAULT_REGION.to_string());
+        S3Bucket {
+            client,
+            region,
+            wait: WaitDefaults::default(),
+        }
+    }
+
+    /// Poll with the wait settings of the deployment instead of the built-in ones
+    pub fn with_wait(mut self, wait: WaitDefaults) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn opts_from_yaml(yaml: &serde_yaml::Value) -> Result<BucketOpts, AwsError> {
+        let bucket = yaml
+            .get("bucket")
+            .and_then(|b| b.as_str())
+            .ok_or_else(|| AwsError::OptionsError("Missing required field: bucket".to_string()))?
+            .to_string();
+        validate_bucket_name(&bucket).map_err(AwsError::OptionsError)?;
+        let block = yaml.get("public_access_block");
+        let blocked = |key: &str| {
+            block
+                .and_then(|b| b.get(key))
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true)
+        };
+
+        Ok(BucketOpts {
+            bucket,
+            versioning: yaml
+                .get("versioning")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            public_access_block: PublicAccessBlock {
+                block_public_acls: blocked("block_public_acls"),
+                ignore_public_acls: blocked("ignore_public_acls"),
+                block_public_policy: blocked("block_public_policy"),
+                restrict_public_buckets: blocked("restrict_public_buckets"),
+            },
+            tags: tags_from_yaml(yaml).map_err(AwsError::OptionsError)?,
+        })
+    }
+
+    /**
+     * Create the bucket in the region of the client, wait until HeadBucket finds it and apply
+     * its public access block, versioning and tags.
+     */
+    pub async fn create_bucket(&self, opts: &BucketOpts) -> Result<(), AwsError> {
+        info!("Creating S3 bucket with config: {:?}", opts);
+        // us-east-1 rejects a LocationConstraint naming itself
+        let configuration = (self.region != DEFAULT_REGION).then(|| {
+            s3_types::CreateBucketConfiguration::builder()
+                .location_constraint(s3_types::BucketLocationConstraint::from(
+                    self.region.as_str(),
+                ))
+                .build()
+        });
+        let request = self
+            .client
+            .create_bucket()
+            .bucket(&opts.bucket)
+            .set_create_bucket_configuration(configuration);
+        retry_throttled(&RetryPolicy::from_env(), "CreateBucket", || {
+            request.clone().send()
+        })
+        .await?;
+
+        // A new bucket is not visible to every endpoint right away
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![EXISTS.to_string()],
+            vec![],
+            Box::new(S3Bucket::wait_for_bucket),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(
+                AWSClient::S3Client(self.client.clone()),
+                opts.bucket.clone(),
+            )
+            .await?;
+        info!("S3 bucket {} created in {}", opts.bucket, self.region);
+        self.configure_bucket(opts, false).await
+    }
+
+    /**
+     * Bring the public access block, versioning and tags of the bucket in line with `opts`.
+     * Versioning cannot be turned off once enabled, `existing` buckets are suspended instead.
+     */
+    pub async fn configure_bucket(
+        &self,
+        opts: &BucketOpts,
+        existing: bool,
+    ) -> Result<(), AwsError> {
+        let block = &opts.public_access_block;
+        let request = self
+            .client
+            .put_public_access_block()
+            .bucket(&opts.bucket)
+            .public_access_block_configuration(
+                s3_types::PublicAccessBlockConfiguration::builder()
+                    .block_public_acls(block.block_public_acls)
+                    .ignore_public_acls(block.ignore_public_acls)
+                    .block_public_policy(block.block_public_policy)
+                    .restrict_public_buckets(block.restrict_public_buckets)
+                    .build(),
+            );
+        retry_throttled(&RetryPolicy::from_env(), "PutPublicAccessBlock", || {
+            request.clone().send()
+        })
+        .await?;
+
+        if opts.versioning || existing {
+            let status = if opts.versioning {
+                s3_types::BucketVersioningStatus::Enabled
+            } else {
+                s3_types::BucketVersioningStatus::Suspended
+            };
+            let request = self
+                .client
+                .put_bucket_versioning()
+                .bucket(&opts.bucket)
+                .versioning_configuration(
+                    s3_types::VersioningConfiguration::builder()
+                        .status(status)
+                        .build(),
+                );
+            retry_throttled(&RetryPolicy::from_env(), "PutBucketVersioning", || {
+                request.clone().send()
+            })
+            .await?;
+        }
+
+        if !opts.tags.is_empty() {
+            let tags = opts
+                .tags
+                .iter()
+                .map(|(key, value)| s3_types::Tag::builder().key(key).value(value).build())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+            let tagging = s3_types::Tagging::builder()
+                .set_tag_set(Some(tags))
+                .build()
+                .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+            let request = self
+                .client
+                .put_bucket_tagging()
+                .bucket(&opts.bucket)
+                .tagging(tagging);
+            retry_throttled(&RetryPolicy::from_env(), "PutBucketTagging", || {
+                request.clone().send()
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    /// Region the bucket is in, ResourceNotFound when it does not exist
+    pub async fn describe_bucket(&self, bucket: &str) -> Result<String, AwsError> {
+        let request = self.client.head_bucket().bucket(bucket);
+        match retry_throttled(&RetryPolicy::from_env(), "HeadBucket", || {
+            request.clone().send()
+        })
+        .await
+        {
+            Ok(resp) => Ok(resp.bucket_region().unwrap_or(&self.region).to_string()),
+            Err(err) if is_missing(err.code()) => {
+                Err(AwsError::ResourceNotFound(bucket.to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /**
+     * Delete a bucket, one that no longer exists is reported as AlreadyGone. With `force_destroy`
+     * every object version and delete marker is removed first, otherwise a bucket that still
+     * holds objects fails to delete.
+     */
+    pub async fn destroy_bucket(
+        &self,
+        bucket: &str,
+        force_destroy: bool,
+    ) -> Result<DestroyOutcome, AwsError> {
+        if force_destroy {
+            match self.empty_bucket(bucket).await {
+                Ok(()) => {}
+                Err(AwsError::ResourceNotFound(_)) => {
+                    info!("S3 bucket {} is already gone", bucket);
+                    return Ok(DestroyOutcome::AlreadyGone);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        let request = self.client.delete_bucket().bucket(bucket);
+        match retry_throttled(&RetryPolicy::from_env(), "DeleteBucket", || {
+            request.clone().send()
+        })
+        .await
+        {
+            Ok(_) => {}
+            Err(err) if is_missing(err.code()) => {
+                info!("S3 bucket {} is already gone", bucket);
+                return Ok(DestroyOutcome::AlreadyGone);
+            }
+            Err(err) if err.code() == Some("BucketNotEmpty") => {
+                return Err(AwsError::OptionsError(format!(
+                    "S3 bucket {} is not empty, set force_destroy: true to delete its objects",
+                    bucket
+                )));
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        // No target state, the wait ends once HeadBucket no longer finds the bucket
+        let wait_state_config = StateChangeConfig::new(
+            &self.wait,
+            vec![],
+            vec![],
+            Box::new(S3Bucket::wait_for_bucket),
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        wait_state_config
+            .wait_until_state(AWSClient::S3Client(self.client.clone()), bucket.to_string())
+            .await?;
+        info!("S3 bucket {} deleted", bucket);
+        Ok(DestroyOutcome::Terminated)
+    }
+
+    /// Delete every object version and delete marker, a page of ListObjectVersions at a time
+    async fn empty_bucket(&self, bucket: &str) -> Result<(), AwsError> {
+        let mut markers: (Option<String>, Option<String>) = (None, None);
+        let mut deleted = 0;
+        loop {
+            let request = self
+                .client
+                .list_object_versions()
+                .bucket(bucket)
+                .set_key_marker(markers.0.take())
+                .set_version_id_marker(markers.1.take());
+            let listed = retry_throttled(&RetryPolicy::from_env(), "ListObjectVersions", || {
+                request.clone().send()
+            })
+            .await;
+            let page = match listed {
+                Ok(page) => page,
+                Err(err) if is_missing(err.code()) => {
+                    return Err(AwsError::ResourceNotFound(bucket.to_string()));
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let versions = page
+                .versions()
+                .iter()
+                .map(|v| (v.key(), v.version_id()))
+                .chain(
+                    page.delete_markers()
+                        .iter()
+                        .map(|m| (m.key(), m.version_id())),
+                );
+            let objects = versions
+                .filter_map(|(key, version_id)| {
+                    s3_types::ObjectIdentifier::builder()
+                        .set_key(key.map(|k| k.to_string()))
+                        .set_version_id(version_id.map(|v| v.to_string()))
+                        .build()
+                        .ok()
+                })
+                .collect::<Vec<_>>();
+            for batch in objects.chunks(DELETE_BATCH_SIZE) {
+                let delete = s3_types::Delete::builder()
+                    .set_objects(Some(batch.to_vec()))
+                    .quiet(true)
+                    .build()
+                    .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+                let request = self.client.delete_objects().bucket(bucket).delete(delete);
+                let resp = retry_throttled(&RetryPolicy::from_env(), "DeleteObjects", || {
+                    request.clone().send()
+                })
+                .await?;
+                if let Some(error) = resp.errors().first() {
+                    return Err(AwsError::SdkError(format!(
+                        "Failed to delete {} from S3 bucket {}: {}",
+                        error.key().unwrap_or("-"),
+                        bucket,
+                        error.message().unwrap_or("unknown error")
+                    )));
+                }
+                deleted += batch.len();
+            }
+            if !page.is_truncated().unwrap_or(false) {
+                break;
+            }
+            markers = (
+                page.next_key_marker().map(|m| m.to_string()),
+                page.next_version_id_marker().map(|m| m.to_string()),
+            );
+        }
+        info!(
+            "Deleted {} object version(s) from S3 bucket {}",
+            deleted, bucket
+        );
+        Ok(())
+    }
+
+    fn wait_for_bucket(client: AWSClient, resource_id: String) -> RefreshFunctionReturn {
+        Box::pin(async move {
+            let s3_client = match client {
+                AWSClient::S3Client(c) => c,
+                _ => return Err("Invalid client type for S3 bucket".to_string()),
+            };
+
+            let request = s3_client.head_bucket().bucket(resource_id.clone());
+            match retry_throttled(&RetryPolicy::from_env(), "HeadBucket", || {
+                request.clone().send()
+            })
+            .await
+            {
+                Ok(_) => Ok(Some((
+                    Box::new(resource_id.clone()) as Box<dyn Any + Send>,
+                    vec![EXISTS.to_string()],
+                ))),
+                Err(err) if is_missing(err.code()) => Ok(None),
+                Err(err) => Err(format!("Failed to read S3 bucket {}: {}", resource_id, err)),
+            }
+        })
+    }
+}
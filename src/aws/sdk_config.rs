@@ -0,0 +1,193 @@
+/// Module for building the AWS SDK configuration shared by every client
+use std::{env, sync::OnceLock, time::Duration};
+
+use aws_config::{BehaviorVersion, Region, retry::RetryConfig, timeout::TimeoutConfig};
+
+pub const MAX_ATTEMPTS_ENV: &str = "LETUS_AWS_MAX_ATTEMPTS";
+pub const RETRY_MODE_ENV: &str = "LETUS_AWS_RETRY_MODE";
+pub const TIMEOUT_ENV: &str = "LETUS_AWS_TIMEOUT_MS";
+
+const DEFAULT_PROFILE: &str = "default";
+const MAX_ALLOWED_ATTEMPTS: u32 = 20;
+const MIN_TIMEOUT: Duration = Duration::from_millis(100);
+const MAX_TIMEOUT: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryMode {
+    Standard,
+    Adaptive, // Client-side rate limiting on top of the standard retries
+}
+
+/// Retry and timeout behaviour of SDK calls, tunable without recompiling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SdkSettings {
+    pub max_attempts: u32,
+    pub retry_mode: RetryMode,
+    pub timeout: Option<Duration>, // Connect and read timeout, SDK defaults when unset
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum SdkSettingsError {
+    #[error("LETUS_AWS_MAX_ATTEMPTS must be a number between 1 and 20, got '{0}'")]
+    InvalidMaxAttempts(String),
+    #[error("LETUS_AWS_RETRY_MODE must be 'standard' or 'adaptive', got '{0}'")]
+    InvalidRetryMode(String),
+    #[error(
+        "LETUS_AWS_TIMEOUT_MS must be a number of milliseconds between 100 and 600000, got '{0}'"
+    )]
+    InvalidTimeout(String),
+}
+
+impl Default for SdkSettings {
+    fn default() -> Self {
+        SdkSettings {
+            max_attempts: 3, // Same as the SDK's standard mode
+            retry_mode: RetryMode::Standard,
+            timeout: None,
+        }
+    }
+}
+
+impl SdkSettings {
+    /// Load the settings from the LETUS_AWS_* environment variables.
+    /// Unset variables keep the default, invalid ones are rejected instead of reaching the SDK.
+    pub fn from_env() -> Result<Self, SdkSettingsError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, SdkSettingsError> {
+        let defaults = SdkSettings::default();
+
+        let max_attempts = match lookup(MAX_ATTEMPTS_ENV) {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(attempts) if (1..=MAX_ALLOWED_ATTEMPTS).contains(&attempts) => attempts,
+                _ => return Err(SdkSettingsError::InvalidMaxAttempts(value)),
+            },
+            None => defaults.max_attempts,
+        };
+
+        let retry_mode = match lookup(RETRY_MODE_ENV) {
+            Some(value) => match value.trim().to_ascii_lowercase().as_str() {
+                "standard" => RetryMode::Standard,
+                "adaptive" => RetryMode::Adaptive,
+                _ => return Err(SdkSettingsError::InvalidRetryMode(value)),
+            },
+            None => defaults.retry_mode,
+        };
+
+        let timeout = match lookup(TIMEOUT_ENV) {
+            Some(value) => match value.trim().parse::<u64>().map(Duration::from_millis) {
+                Ok(timeout) if (MIN_TIMEOUT..=MAX_TIMEOUT).contains(&timeout) => Some(timeout),
+                _ => return Err(SdkSettingsError::InvalidTimeout(value)),
+            },
+            None => defaults.timeout,
+        };
+
+        Ok(SdkSettings {
+            max_attempts,
+            retry_mode,
+            timeout,
+        })
+    }
+
+    pub fn retry_config(&self) -> RetryConfig {
+        let config = match self.retry_mode {
+            RetryMode::Standard => RetryConfig::standard(),
+            RetryMode::Adaptive => RetryConfig::adaptive(),
+        };
+        config.with_max_attempts(self.max_attempts)
+    }
+
+    pub fn timeout_config(&self) -> TimeoutConfig {
+        let mut builder = TimeoutConfig::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.connect_timeout(timeout).read_timeout(timeout);
+        }
+        builder.build()
+    }
+}
+
+/// Profile set by `use_profile`, every client is built from `default` otherwise
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Build every client of the process from `profile`, selftest points apply and destroy at localstack
+pub fn use_profile(profile: &str) {
+    let _ = PROFILE.set(profile.to_string());
+}
+
+/// SdkConfig for the given region with the retry and timeout settings applied
+pub async fn load_sdk_config(region: &str) -> Result<aws_types::SdkConfig, SdkSettingsError> {
+    let settings = SdkSettings::from_env()?;
+    tracing::info!(
+        "AWS SDK settings: retry mode {:?}, max attempts {}, timeout {}",
+        settings.retry_mode,
+        settings.max_attempts,
+        settings
+            .timeout
+            .map(|t| format!("{:?}", t))
+            .unwrap_or_else(|| "SDK default".to_string())
+    );
+    Ok(aws_config::defaults(BehaviorVersion::latest())
+        .profile_name(PROFILE.get().map_or(DEFAULT_PROFILE, |p| p.as_str()))
+        .region(Region::new(region.to_string()))
+        .retry_config(settings.retry_config())
+        .timeout_config(settings.timeout_config())
+        .load()
+        .await)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn settings(vars: &[(&str, &str)]) -> Result<SdkSettings, SdkSettingsError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        SdkSettings::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_defaults_when_unset() {
+        assert_eq!(settings(&[]).unwrap(), SdkSettings::default());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let settings = settings(&[
+            (MAX_ATTEMPTS_ENV, "5"),
+            (RETRY_MODE_ENV, "Adaptive"),
+            (TIMEOUT_ENV, "2500"),
+        ])
+        .unwrap();
+        assert_eq!(settings.max_attempts, 5);
+        assert_eq!(settings.retry_mode, RetryMode::Adaptive);
+        assert_eq!(settings.timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(settings.retry_config().max_attempts(), 5);
+        assert_eq!(
+            settings.timeout_config().read_timeout(),
+            Some(Duration::from_millis(2500))
+        );
+    }
+
+    #[test]
+    fn test_invalid_values_are_rejected() {
+        let cases = [
+            (MAX_ATTEMPTS_ENV, "0"),
+            (MAX_ATTEMPTS_ENV, "500"),
+            (MAX_ATTEMPTS_ENV, "three"),
+            (RETRY_MODE_ENV, "legacy"),
+            (TIMEOUT_ENV, "0"),
+            (TIMEOUT_ENV, "86400000"),
+            (TIMEOUT_ENV, "-1"),
+        ];
+        for (key, value) in cases {
+            let err = settings(&[(key, value)]).unwrap_err();
+            assert!(err.to_string().contains(key), "{}", err);
+            assert!(err.to_string().contains(value), "{}", err);
+        }
+    }
+}
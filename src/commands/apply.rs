@@ -1,8 +1,56 @@
-use aws_config::{BehaviorVersion, Region};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::IsTerminal,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    sync::Semaphore,
+    task::JoinSet,
+    time::{Instant, sleep_until},
+};
 
 use crate::{
-    aws::ec2::ec2_instance::EC2Instance, commands::validate::validate_file, models::InfraConfig,
-    utils::constants::TEMPLATES_DIR,
+    aws::{
+        ec2::ec2_instance::KNOWN_AFTER_APPLY,
+        internal::{tags::DeploymentTags, wait_and_refresh::WaitDefaults},
+        s3::bucket::bucket_arn,
+    },
+    commands::{
+        destroy::{Confirmation, confirm},
+        output::render_outputs,
+        validate::validate_file,
+    },
+    handlers::{self, Applied, ApplyContext},
+    models::{
+        Component, ConfigFormat, InfraConfig, OutputDeclaration, PlanError,
+        state::{
+            ComponentMode, ComponentState, Datatype, DeclaredOutput, Output, OutputType, State,
+        },
+    },
+    state::{
+        self as state_file, STATE_VERSION,
+        backend::{Backend, StateBackend},
+    },
+    utils::{
+        OperationType, PlanPreviewDeployment,
+        ami_lookup::resolve_ami_lookups,
+        anchor_config_paths, component_hash, config_documents,
+        constants::TEMPLATES_DIR,
+        diagnostics::Diagnostic,
+        diff_against_state, exclude_untargeted,
+        interpolation::{
+            InterpolationError, interpolate_outputs, output_value, preview_outputs, resolve_config,
+        },
+        plan_components, plan_components_sequence, read_config_source,
+        redact::{self, redact},
+        render::{RenderOptions, render_plan_preview},
+        replacement_hash, schema, select_workspace, stable_hash, target_closure,
+        templates::resolve_templates,
+        timeouts::ResourceTimeouts,
+        variables::{VariableOptions, bind_variables},
+    },
 };
 
 #[derive(clap::Args, Debug)]
@@ -12,70 +60,1338 @@ pub struct Config {
     pub options: Options,
 }
 
-#[derive(clap::Args, Debug)]
+#[derive(clap::Args, Debug, Clone)]
 pub struct Options {
     #[clap(short = 'f', long = "filepath")]
     pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Workspace to apply, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// Where created resources are recorded.
+    /// Defaults to the state of the workspace, .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Only apply these components and what they depend on, `<type>.<name>`, can be repeated.
+    /// The state of every other component is left as it is
+    #[clap(long = "target")]
+    pub targets: Vec<String>,
+    /// Most components created at once, components that depend on each other never run together
+    #[clap(
+        long = "parallelism",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub parallelism: u32,
+    /// Seconds to wait for in-flight components after Ctrl-C before abandoning them
+    #[clap(long = "shutdown-timeout", default_value_t = 300)]
+    pub shutdown_timeout: u64,
+    /// Validate, order and plan every component as apply would, without creating anything
+    #[clap(long = "dry-run")]
+    pub dry_run: bool,
+    /// Apply without showing the plan and asking for confirmation, for CI
+    #[clap(long = "auto-approve")]
+    pub auto_approve: bool,
+    /// Deployment of a `-f` holding several, each has its own state in the backend
+    #[clap(skip)]
+    pub deployment: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApplyError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+    #[error("{} output(s) could not be resolved: {}", .0.len(), .0.join("; "))]
+    Outputs(Vec<String>),
+    #[error("{failed} of {total} component(s) failed to apply")]
+    ComponentsFailed { failed: usize, total: usize },
+    #[error("Apply cancelled")]
+    Cancelled,
+    #[error(
+        "Apply interrupted, {applied} of {total} component(s) applied. Run apply again to continue"
+    )]
+    Interrupted { applied: usize, total: usize },
+    #[error(
+        "Cannot ask for confirmation, stdin is not a terminal. Pass --auto-approve to apply without a prompt"
+    )]
+    NotInteractive,
 }
 
-pub async fn execute(config: &Config) {
-    println!("Executing plan command with config: {:?}", config);
+/**
+ * Apply every deployment of `-f`, one after the other, stopping at the first that fails.
+ * Several deployments each get their own state, `<workspace>/<name>/state.json`.
+ */
+pub async fn execute(config: &Config) -> Result<(), ApplyError> {
+    let options = &config.options;
+    let documents = config_documents(&options.file_path).map_err(ApplyError::Config)?;
+    if documents.len() > 1 && options.state_file.is_some() {
+        return Err(ApplyError::Config(
+            "--state-file cannot be used with several deployments, each has its own state"
+                .to_string(),
+        ));
+    }
+    for document in &documents {
+        let mut options = options.clone();
+        options.file_path = document.source.clone();
+        if documents.len() > 1 {
+            options.deployment = Some(document.name.clone());
+            println!(
+                "\n=== Deployment {} ({}) ===",
+                document.name, document.source
+            );
+        }
+        apply_deployment(&Config { options }).await?;
+    }
+    Ok(())
+}
 
-    let file_path = &config.options.file_path;
-    println!("File path is: {}", file_path);
-    let is_valid = validate_file(file_path);
+async fn apply_deployment(config: &Config) -> Result<(), ApplyError> {
+    let options = &config.options;
+    let file_path = &options.file_path;
+    // Missing or mistyped properties are reported here rather than halfway through the apply
+    validate_file(file_path, &options.variables)
+        .map_err(|err| ApplyError::Config(format!("[{}] {}", err.code(), err)))?;
 
-    let content = match std::fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Failed to read file: {}", err);
-            return;
+    let content =
+        read_config_source(file_path).map_err(|err| ApplyError::FileRead(err.to_string()))?;
+
+    // Try to parse using the structured model
+    let mut config = InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| ApplyError::Parse(err.to_string()))?;
+    tracing::debug!("Successfully parsed YAML using InfraConfig model");
+    resolve_templates(&mut config, file_path).map_err(ApplyError::Config)?;
+    anchor_config_paths(&mut config, file_path);
+    bind_variables(&mut config, &options.variables)
+        .map_err(|err| ApplyError::Config(format!("[{}] {}", err.code(), err)))?;
+    redact::install(&config.redact, schema::sensitive_values(&config.components))
+        .map_err(|err| ApplyError::Config(err.to_string()))?;
+    check_capabilities(&config)?;
+
+    // Resolve built-in references before any provider call is made
+    let workspace = select_workspace(options.workspace.as_deref()).map_err(ApplyError::Config)?;
+    // Fail before any provider call when the backend cannot be used or the state cannot be read
+    let mut backend = state_file::backend::open(
+        config.backend.as_ref(),
+        options.state_file.as_deref(),
+        &workspace,
+        options.deployment.as_deref(),
+        Some(&config.region),
+    )
+    .await?;
+    // A dry run writes nothing, so it neither waits for nor blocks other runs
+    if !options.dry_run {
+        backend.lock("apply").await?;
+    }
+    let applied = apply_with_backend(&backend, options, config, &workspace).await;
+    state_file::backend::unlock_after(&mut backend, applied).await
+}
+
+async fn apply_with_backend(
+    backend: &Backend,
+    options: &Options,
+    mut config: InfraConfig,
+    workspace: &str,
+) -> Result<(), ApplyError> {
+    let previous = backend.load().await?;
+    // Done once, so the confirmation, the dry run and the apply all launch the same AMI
+    resolve_ami_lookups(&mut config, previous.as_ref(), true)
+        .await
+        .map_err(ApplyError::Config)?;
+    let raw_config = config;
+    let config = resolve_config(&raw_config, workspace).map_err(|err| {
+        ApplyError::Config(format!("Failed to interpolate configuration: {}", err))
+    })?;
+    let mut sequence = plan_components_sequence(&config.components)
+        .map_err(|err| ApplyError::Config(format!("[{}] {}", err.code(), err)))?;
+    // Components outside --target keep their state untouched, merge_components leaves them be
+    let mut targeted = None;
+    if !options.targets.is_empty() {
+        let keys =
+            target_closure(&config.components, &options.targets).map_err(ApplyError::Config)?;
+        sequence.retain(|c| keys.contains(&c.key()));
+        targeted = Some(keys);
+    }
+    if options.dry_run {
+        return dry_run(
+            &raw_config,
+            workspace,
+            &sequence,
+            previous.as_ref(),
+            targeted.as_deref(),
+        );
+    }
+    if !options.auto_approve {
+        // Reading the answer from a pipe would hang or take whatever the pipe happens to contain
+        if !std::io::stdin().is_terminal() {
+            return Err(ApplyError::NotInteractive);
+        }
+        let preview = plan_preview(
+            &raw_config,
+            workspace,
+            previous.as_ref(),
+            targeted.as_deref(),
+        )?;
+        let summary = format!(
+            "\n{}\nWorkspace: {}",
+            render_plan_preview(&preview, &RenderOptions::detect(None)),
+            workspace
+        );
+        let confirmed = confirm(
+            Confirmation::Apply,
+            &config.metadata.name,
+            &summary,
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+        );
+        if !confirmed {
+            return Err(ApplyError::Cancelled);
         }
+    }
+
+    // Outputs of every applied component by `<type>.<name>`, later components read ids from it
+    let mut outputs = previous
+        .as_ref()
+        .and_then(|p| p.outputs.value.as_object().cloned())
+        .unwrap_or_default();
+    let (created, failed, interrupted) = create_components(
+        &config.region,
+        (&config.metadata.name, workspace),
+        &sequence,
+        previous.as_ref(),
+        &mut outputs,
+        options.parallelism as usize,
+        Duration::from_secs(options.shutdown_timeout),
+        &WaitDefaults::for_config(&config),
+    )
+    .await;
+    let applied = created.len();
+    let (declared, unresolved) = resolve_declared_outputs(&config.outputs, &outputs);
+    // Record what was created even when other components failed or the apply was interrupted,
+    // so destroy can find it and the next apply picks up from there
+    let mut not_saved = None;
+    if !created.is_empty() {
+        let launches = recorded_launches(previous.as_ref(), &created);
+        let state = State {
+            version: STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: config.metadata.name.clone(),
+            outputs: Output {
+                value: serde_json::Value::Object(outputs),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: declared.clone(),
+            },
+            components: state_file::merge_components(previous.as_ref(), created),
+            launches,
+        };
+        match backend.save(state).await {
+            Ok(state) => println!(
+                "State written to {} (serial {})",
+                backend.describe(),
+                state.serial
+            ),
+            Err(err @ state_file::StateError::NotSaved { .. }) => not_saved = Some(err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    if !declared.is_empty() {
+        println!("\nOutputs:\n{}", redact(&render_outputs(&declared)));
+    }
+    // Last, so it is what the run ends with: the resources exist but the backend does not know
+    if let Some(err) = not_saved {
+        eprintln!(
+            "\n*** STATE NOT SAVED to {} ***\n{}",
+            backend.describe(),
+            redact(&err.to_string())
+        );
+        return Err(err.into());
+    }
+
+    if interrupted {
+        return Err(ApplyError::Interrupted {
+            applied,
+            total: sequence.len(),
+        });
+    }
+    if failed > 0 {
+        return Err(ApplyError::ComponentsFailed {
+            failed,
+            total: sequence.len(),
+        });
+    }
+    if !unresolved.is_empty() {
+        return Err(ApplyError::Outputs(unresolved));
+    }
+    Ok(())
+}
+
+/**
+ * Values of the `outputs:` block taken from the outputs of the applied components, by name, and
+ * one message for every entry that could not be resolved.
+ */
+pub(crate) fn resolve_declared_outputs(
+    declarations: &BTreeMap<String, OutputDeclaration>,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+) -> (BTreeMap<String, DeclaredOutput>, Vec<String>) {
+    let mut declared = BTreeMap::new();
+    let mut unresolved = Vec::new();
+    for (name, declaration) in declarations {
+        match output_value(declaration.value(), outputs) {
+            Ok(value) => {
+                declared.insert(
+                    name.clone(),
+                    DeclaredOutput {
+                        value,
+                        sensitive: declaration.sensitive(),
+                        description: declaration.description().map(|d| d.to_string()),
+                    },
+                );
+            }
+            // The dependsOn hint of the error does not apply to the outputs block
+            Err(InterpolationError::MissingOutput { component, output }) => unresolved.push(
+                format!("{}: {}.{} is not available", name, component, output),
+            ),
+            Err(err) => unresolved.push(format!("{}: {}", name, err)),
+        }
+    }
+    (declared, unresolved)
+}
+
+/// The plan of the config against the last applied state, as the plan command shows it
+fn plan_preview(
+    raw_config: &InfraConfig,
+    workspace: &str,
+    previous: Option<&State>,
+    targeted: Option<&[String]>,
+) -> Result<PlanPreviewDeployment, ApplyError> {
+    let describe = |err: &PlanError| format!("[{}] {}", err.code(), err);
+    let (_, mut preview, errors) =
+        plan_components(raw_config, workspace).map_err(|err| ApplyError::Config(describe(&err)))?;
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(describe).collect();
+        return Err(ApplyError::Config(errors.join("\n")));
+    }
+    if let Some(previous) = previous {
+        diff_against_state(&mut preview, previous);
+    }
+    if let Some(targeted) = targeted {
+        exclude_untargeted(&mut preview, targeted);
+    }
+    Ok(preview)
+}
+
+/**
+ * Everything apply does short of creating resources: every component is planned and listed in the
+ * order apply would create it, with the properties it would be created with. Outputs of components
+ * that are unchanged since the last apply are taken from the state, any other output is shown as
+ * known after apply.
+ */
+fn dry_run(
+    raw_config: &InfraConfig,
+    workspace: &str,
+    sequence: &[&Component],
+    previous: Option<&State>,
+    targeted: Option<&[String]>,
+) -> Result<(), ApplyError> {
+    let preview = plan_preview(raw_config, workspace, previous, targeted)?;
+    let operation = |key: &str| {
+        preview
+            .components
+            .iter()
+            .find(|c| format!("{}.{}", c.component_type, c.name) == key)
+            .map_or(OperationType::Create, |c| c.operation_type.clone())
     };
 
-    // Try to parse using the structured model
-    match InfraConfig::from_yaml(&content) {
-        Ok(config) => {
-            println!("Successfully parsed YAML using InfraConfig model");
-            create_components(&config.metadata.name, &config.region, &config.components).await;
+    let mut outputs = previous
+        .and_then(|p| p.outputs.value.as_object().cloned())
+        .unwrap_or_default();
+    for component in sequence {
+        if operation(&component.key()) != OperationType::NoOp {
+            // Unknown until applied, as are the outputs dependents are filled in from
+            let mut unknown = serde_json::json!({ "id": KNOWN_AFTER_APPLY });
+            for (_, dependency_type, _, output) in DEPENDENCY_IDS {
+                if *dependency_type == component.component_type {
+                    unknown[*output] = KNOWN_AFTER_APPLY.into();
+                }
+            }
+            outputs.insert(component.key(), unknown);
         }
-        Err(err) => {
-            eprintln!("Failed to parse YAML into InfraConfig: {}", err);
+    }
+
+    for (index, level) in apply_levels(sequence).into_iter().enumerate() {
+        println!("Level {}:", index + 1);
+        for component in level {
+            let label = match operation(&component.key()) {
+                OperationType::Create => "create",
+                OperationType::Update => "update",
+                OperationType::NoOp => "no change",
+                OperationType::Delete => "delete",
+            };
+            println!("  {} {}", label, component.key());
+            let mut component = component.clone();
+            component.properties =
+                preview_outputs(&component.properties, &outputs, KNOWN_AFTER_APPLY)
+                    .map_err(|err| ApplyError::Config(format!("[{}] {}", err.code(), err)))?;
+            let component = with_dependency_ids(&component, &outputs)
+                .map_err(|err| ApplyError::Config(format!("{}: {}", component.key(), err)))?;
+            let masked = schema::mask_sensitive(&component, &component.properties);
+            let properties = serde_yaml::to_string(&masked).unwrap_or_default();
+            for line in properties.lines() {
+                println!("      {}", redact(line));
+            }
+        }
+    }
+
+    let count = |operation: OperationType| {
+        preview
+            .components
+            .iter()
+            .filter(|c| !c.excluded && c.operation_type == operation)
+            .count()
+    };
+    println!(
+        "\nDry run — no changes made: {} to create, {} to update, {} unchanged",
+        count(OperationType::Create),
+        count(OperationType::Update),
+        count(OperationType::NoOp)
+    );
+    let deleted = count(OperationType::Delete);
+    if deleted > 0 {
+        println!(
+            "{} component(s) only in the state are left in place by apply, run destroy to remove them",
+            deleted
+        );
+    }
+    Ok(())
+}
+
+/// Reject the whole run before anything is created when a component type cannot be applied
+fn check_capabilities(config: &InfraConfig) -> Result<(), ApplyError> {
+    let unsupported: Vec<&Component> = config
+        .components
+        .iter()
+        .filter(|c| handlers::handler_for(&c.component_type).is_none())
+        .collect();
+    if unsupported.is_empty() {
+        return Ok(());
+    }
+    Err(ApplyError::Config(format!(
+        "The aws provider does not support {}. Supported types: {} (see `letusinfra providers`)",
+        unsupported
+            .iter()
+            .map(|c| format!("{} ({})", c.component_type, c.key()))
+            .collect::<Vec<_>>()
+            .join(", "),
+        handlers::handlers()
+            .map(|h| h.schema().component_type)
+            .collect::<Vec<_>>()
+            .join(", ")
+    )))
+}
+
+/// Properties filled with an output of a dependency when they are not set:
+/// (component type, dependency type, property, output)
+const DEPENDENCY_IDS: &[(&str, &str, &str, &str)] = &[
+    ("Subnet", "VPC", "vpc_id", "id"),
+    ("SecurityGroup", "VPC", "vpc_id", "id"),
+    ("EC2Instance", "Subnet", "subnet_id", "id"),
+    ("EC2Instance", "KeyPair", "key_name", "key_name"),
+    ("InstanceProfile", "IAMRole", "role", "role_name"),
+];
+
+/**
+ * Copy of a component with the ids of the components it `dependsOn` filled in, e.g. the `vpc_id`
+ * of a Subnet from the VPC it depends on. Explicit properties always win.
+ */
+fn with_dependency_ids(
+    component: &Component,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Component, String> {
+    let mut component = component.clone();
+    for (component_type, dependency_type, property, output) in DEPENDENCY_IDS {
+        if component.component_type != *component_type || component.get_property(property).is_some()
+        {
+            continue;
+        }
+        let dependencies: Vec<String> = component
+            .depends_on
+            .iter()
+            .flatten()
+            .filter(|d| d.dep_type == *dependency_type)
+            .map(|d| format!("{}.{}", d.dep_type, d.name))
+            .collect();
+        let key = match dependencies.as_slice() {
+            [] => continue,
+            [key] => key,
+            _ => {
+                return Err(format!(
+                    "depends on {} and cannot pick a {}, set it explicitly",
+                    dependencies.join(", "),
+                    property
+                ));
+            }
+        };
+        let id = outputs
+            .get(key)
+            .and_then(|o| o.get(*output))
+            .and_then(|id| id.as_str())
+            .ok_or_else(|| format!("{} has no {}, it has not been applied", key, output))?;
+        component
+            .properties
+            .as_mapping_mut()
+            .ok_or_else(|| "properties must be a map".to_string())?
+            .insert(
+                serde_yaml::Value::String(property.to_string()),
+                serde_yaml::Value::String(id.to_string()),
+            );
+    }
+    Ok(component)
+}
+
+/**
+ * Copy of an EC2Instance with the ids of the security groups it `connectsTo` added to its
+ * `security_group_ids`. The groups must already be applied, in this run or an earlier one.
+ */
+pub(crate) fn with_connected_security_groups(
+    component: &Component,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Component, String> {
+    let mut component = component.clone();
+    let mut group_ids = Vec::new();
+    for connection in component.connects_to.iter().flatten() {
+        if connection.dep_type != "SecurityGroup" {
+            continue;
         }
+        let key = format!("{}.{}", connection.dep_type, connection.name);
+        let group_id = outputs
+            .get(&key)
+            .and_then(|o| o.get("id"))
+            .and_then(|id| id.as_str())
+            .ok_or_else(|| {
+                format!(
+                    "connectsTo {} but it has not been applied, add it to dependsOn as well so it is created first",
+                    key
+                )
+            })?;
+        group_ids.push(serde_yaml::Value::String(group_id.to_string()));
     }
+    if group_ids.is_empty() {
+        return Ok(component);
+    }
+
+    let properties = component
+        .properties
+        .as_mapping_mut()
+        .ok_or_else(|| "properties must be a map".to_string())?;
+    let existing = properties
+        .entry(serde_yaml::Value::String("security_group_ids".to_string()))
+        .or_insert_with(|| serde_yaml::Value::Sequence(Vec::new()));
+    let existing = existing
+        .as_sequence_mut()
+        .ok_or_else(|| "security_group_ids must be a list".to_string())?;
+    for group_id in group_ids {
+        if !existing.contains(&group_id) {
+            existing.push(group_id);
+        }
+    }
+    Ok(component)
 }
 
-async fn create_components(_name: &str, region: &str, components: &[crate::models::Component]) {
+/**
+ * Group components sorted in dependency order into levels: a component is one level after the
+ * last of the components it depends on, so the components of a level never depend on each other.
+ */
+fn apply_levels<'a>(components: &[&'a Component]) -> Vec<Vec<&'a Component>> {
+    let mut level_of: HashMap<String, usize> = HashMap::new();
+    let mut levels: Vec<Vec<&Component>> = Vec::new();
     for component in components {
-        match component.component_type.as_str() {
-            "EC2Instance" => {
-                // Create EC2 instance Terraform code
-                match create_ec2_instance(region, component).await {
-                    Ok(instance) => {
-                        println!("Successfully created EC2 instance: {:?}", instance);
-                    }
-                    Err(err) => {
-                        eprintln!("Failed to create EC2 instance: {}", err);
+        let level = component
+            .depends_on
+            .iter()
+            .flatten()
+            .filter_map(|d| level_of.get(&format!("{}.{}", d.dep_type, d.name)))
+            .map(|level| level + 1)
+            .max()
+            .unwrap_or(0);
+        level_of.insert(component.key(), level);
+        if levels.len() <= level {
+            levels.resize_with(level + 1, Vec::new);
+        }
+        levels[level].push(component);
+    }
+    levels
+}
+
+/// Outcome of applying one component, its console lines are printed together once it finishes
+struct AppliedComponent {
+    key: String,
+    log: Vec<String>,
+    created: Option<(serde_json::Value, ComponentState)>,
+}
+
+/**
+ * Create components level by level, the components of a level run concurrently with at most
+ * `parallelism` in flight. A failure does not stop the rest of its level, but components that
+ * depend on a failed one are skipped.
+ * Returns the state of every created component, the number of failures and whether the apply
+ * was interrupted, `outputs` gains the outputs of every created component. `deployment` is the
+ * metadata name and workspace every created resource is tagged with, `previous` the state the
+ * components are updated from.
+ * On Ctrl-C no further component is started and the ones in flight get `shutdown_timeout` to
+ * finish, a second Ctrl-C or the timeout abandons them. An abandoned instance is found again by
+ * its client token on the next apply, so it is not launched twice.
+ */
+#[allow(clippy::too_many_arguments)]
+async fn create_components(
+    region: &str,
+    deployment: (&str, &str),
+    components: &[&Component],
+    previous: Option<&State>,
+    outputs: &mut serde_json::Map<String, serde_json::Value>,
+    parallelism: usize,
+    shutdown_timeout: Duration,
+    wait: &WaitDefaults,
+) -> (Vec<ComponentState>, usize, bool) {
+    let mut created: Vec<ComponentState> = Vec::new();
+    let mut failed: Vec<String> = Vec::new();
+    let semaphore = Arc::new(Semaphore::new(parallelism.max(1)));
+    // Replaces the default Ctrl-C handling for the rest of the apply
+    let interrupt = tokio::signal::ctrl_c();
+    tokio::pin!(interrupt);
+    // Set once interrupted, in-flight components are abandoned past it
+    let mut deadline: Option<Instant> = None;
+    for level in apply_levels(components) {
+        if deadline.is_some() {
+            break;
+        }
+        // Every component of a level sees the outputs of the levels before it
+        let level_outputs = Arc::new(outputs.clone());
+        let mut tasks = JoinSet::new();
+        let mut keys = HashMap::new();
+        for component in level {
+            let key = component.key();
+            if let Some(dependency) = component
+                .depends_on
+                .iter()
+                .flatten()
+                .map(|d| format!("{}.{}", d.dep_type, d.name))
+                .find(|d| failed.contains(d))
+            {
+                eprintln!(
+                    "{}: skipped, depends on failed component {}",
+                    key, dependency
+                );
+                failed.push(key);
+                continue;
+            }
+
+            let semaphore = semaphore.clone();
+            let region = region.to_string();
+            let deployment = (deployment.0.to_string(), deployment.1.to_string());
+            let component = component.clone();
+            let existing = previous
+                .and_then(|p| {
+                    p.components.iter().find(|c| {
+                        c.component_type == component.component_type && c.name == component.name
+                    })
+                })
+                .cloned();
+            let level_outputs = level_outputs.clone();
+            let generation = launch_generation(previous, &key);
+            let wait = wait.clone();
+            let task_key = key.clone();
+            // Validated before the apply started, the whole operation is bounded by the block
+            let timeouts =
+                ResourceTimeouts::from_properties(&component.properties).unwrap_or_default();
+            let limit = if existing.is_some() {
+                timeouts.update
+            } else {
+                timeouts.create
+            };
+            let handle = tasks.spawn(async move {
+                let key = task_key;
+                // The semaphore is closed on Ctrl-C, components still queued are not started
+                let Ok(_permit) = semaphore.acquire_owned().await else {
+                    return AppliedComponent {
+                        log: vec![format!("{}: not started, the apply was interrupted", key)],
+                        key,
+                        created: None,
+                    };
+                };
+                let deployment = (deployment.0.as_str(), deployment.1.as_str());
+                let existing = existing.as_ref();
+                let apply = apply_component(
+                    &region,
+                    deployment,
+                    &component,
+                    existing,
+                    &level_outputs,
+                    generation,
+                    &wait,
+                );
+                let Some(limit) = limit else {
+                    return apply.await;
+                };
+                match tokio::time::timeout(limit, apply).await {
+                    Ok(applied) => applied,
+                    Err(_) => AppliedComponent {
+                        log: vec![format!(
+                            "{}: timed out after {:?}, raise it in the timeouts block",
+                            key, limit
+                        )],
+                        key,
+                        created: None,
+                    },
+                }
+            });
+            keys.insert(handle.id(), key);
+        }
+
+        loop {
+            let joined = tokio::select! {
+                joined = tasks.join_next_with_id() => match joined {
+                    Some(joined) => joined,
+                    None => break,
+                },
+                _ = &mut interrupt, if deadline.is_none() => {
+                    deadline = Some(Instant::now() + shutdown_timeout);
+                    eprintln!(
+                        "Interrupted, waiting up to {}s for {} in-flight component(s), press Ctrl-C again to stop now",
+                        shutdown_timeout.as_secs(),
+                        tasks.len()
+                    );
+                    semaphore.close();
+                    continue;
+                },
+                _ = abandon(deadline) => {
+                    tasks.abort_all();
+                    for key in keys.values() {
+                        eprintln!("{}: abandoned, run apply again to pick it up", key);
                     }
+                    break;
+                },
+            };
+            let applied = match joined {
+                Ok((id, applied)) => {
+                    keys.remove(&id);
+                    applied
+                }
+                Err(err) => {
+                    let key = keys.remove(&err.id()).unwrap_or_default();
+                    eprintln!(
+                        "{}",
+                        redact(&format!("{}: apply task failed: {}", key, err))
+                    );
+                    failed.push(key);
+                    continue;
+                }
+            };
+            match applied.created {
+                Some((component_outputs, state)) => {
+                    applied
+                        .log
+                        .iter()
+                        .for_each(|line| println!("{}", redact(line)));
+                    outputs.insert(applied.key, component_outputs);
+                    created.push(state);
+                }
+                None => {
+                    applied
+                        .log
+                        .iter()
+                        .for_each(|line| eprintln!("{}", redact(line)));
+                    failed.push(applied.key);
                 }
             }
-            _ => {
-                eprintln!("Unsupported component type: {}", component.component_type);
+        }
+    }
+
+    (created, failed.len(), deadline.is_some())
+}
+
+/// Completes at the shutdown deadline or on a second Ctrl-C, never before an interrupt
+async fn abandon(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => {
+            tokio::select! {
+                _ = sleep_until(deadline) => {}
+                _ = tokio::signal::ctrl_c() => {}
             }
         }
+        None => std::future::pending().await,
+    }
+}
+
+/**
+ * Create one component through the handler of its type, ids and `${...}` references are taken
+ * from `outputs`. An EC2Instance already in state whose properties changed is updated in place.
+ */
+async fn apply_component(
+    region: &str,
+    deployment: (&str, &str),
+    component: &Component,
+    existing: Option<&ComponentState>,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+    generation: u64,
+    wait: &WaitDefaults,
+) -> AppliedComponent {
+    let key = component.key();
+    let mut log = Vec::new();
+    let fail = |log: &mut Vec<String>, message: String| {
+        log.push(format!("{}: {}", key, message));
+        AppliedComponent {
+            key: key.clone(),
+            log: std::mem::take(log),
+            created: None,
+        }
+    };
+
+    // Hashed before outputs are filled in, the same way plan hashes it
+    let properties_hash = component_hash(component);
+    let replacement_hash = replacement_hash(component);
+    let launch = launch_id(&properties_hash, generation);
+    let mut component = component.clone();
+    component.properties = match interpolate_outputs(&component.properties, outputs) {
+        Ok(properties) => properties,
+        Err(err) => return fail(&mut log, format!("[{}] {}", err.code(), err)),
+    };
+    let component = match with_dependency_ids(&component, outputs) {
+        Ok(component) => component,
+        Err(err) => return fail(&mut log, err),
+    };
+    let component = &component;
+    let Some(handler) = handlers::handler_for(&component.component_type) else {
+        return fail(&mut log, handlers::unsupported(&component.component_type));
+    };
+    let tags = DeploymentTags {
+        deployment: deployment.0.to_string(),
+        workspace: deployment.1.to_string(),
+        component_type: component.component_type.clone(),
+        component_name: component.name.clone(),
+    };
+    let context = ApplyContext {
+        region,
+        component,
+        tags: &tags,
+        existing,
+        outputs,
+        properties_hash: &properties_hash,
+        launch: &launch,
+        wait,
+    };
+    let action = if handler.updates(&context) {
+        "update"
+    } else {
+        "create"
+    };
+
+    match handler.apply(context, &mut log).await {
+        Ok(Applied {
+            outputs: component_outputs,
+            instances,
+        }) => AppliedComponent {
+            created: Some((
+                component_outputs.clone(),
+                ComponentState {
+                    // Imported resources stay imported once apply has updated them
+                    mode: existing
+                        .map(|e| e.mode.clone())
+                        .unwrap_or(ComponentMode::Managed),
+                    name: component.name.clone(),
+                    component_type: component.component_type.clone(),
+                    provider: "aws".to_string(),
+                    instances: instances.unwrap_or_else(|| vec![component_outputs]),
+                    // An updated component keeps the time it was first created
+                    created_at: existing.and_then(|e| e.created_at).or_else(|| {
+                        std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .ok()
+                            .map(|d| d.as_secs())
+                    }),
+                    properties_hash: Some(properties_hash),
+                    replacement_hash: Some(replacement_hash),
+                    retained: false,
+                },
+            )),
+            key,
+            log,
+        },
+        Err(err) => {
+            let message = format!(
+                "failed to {} {}: [{}] {}",
+                action,
+                component.component_type,
+                err.code(),
+                redact(&err.to_string())
+            );
+            fail(&mut log, message)
+        }
     }
 }
 
-async fn create_ec2_instance(
+/// Outputs recorded for an EC2 instance, refresh and import read them back the same way
+/// Keys are referenced as `${EC2Instance.<name>.<key>}`, the schema lists them, keep them stable
+pub(crate) fn instance_outputs(
+    instance: &aws_sdk_ec2::types::Instance,
+    region: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": instance.instance_id(),
+        "region": region,
+        "public_ip": instance.public_ip_address(),
+        // AWS returns an empty name rather than none when the instance has no public address
+        "public_dns": instance.public_dns_name().filter(|name| !name.is_empty()),
+        "private_ip": instance.private_ip_address(),
+        "availability_zone": instance.placement().and_then(|p| p.availability_zone()),
+        "subnet_id": instance.subnet_id(),
+        "image_id": instance.image_id(),
+        "spot_request_id": instance.spot_instance_request_id(),
+    })
+}
+
+pub(crate) fn subnet_outputs(
+    subnet: &aws_sdk_ec2::types::Subnet,
+    region: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": subnet.subnet_id(),
+        "region": region,
+        "vpc_id": subnet.vpc_id(),
+        "cidr": subnet.cidr_block(),
+        "availability_zone": subnet.availability_zone(),
+    })
+}
+
+pub(crate) fn vpc_outputs(vpc: &aws_sdk_ec2::types::Vpc, region: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": vpc.vpc_id(),
+        "region": region,
+        "cidr": vpc.cidr_block(),
+    })
+}
+
+/// Outputs recorded for a key pair, never the private key itself
+pub(crate) fn key_pair_outputs(
+    key_pair: &aws_sdk_ec2::types::KeyPairInfo,
+    region: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": key_pair.key_pair_id(),
+        "region": region,
+        "key_name": key_pair.key_name(),
+        "fingerprint": key_pair.key_fingerprint(),
+        "key_type": key_pair.key_type().map(|t| t.as_str()),
+    })
+}
+
+pub(crate) fn elastic_ip_outputs(
+    address: &aws_sdk_ec2::types::Address,
+    region: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": address.allocation_id(),
+        "region": region,
+        "allocation_id": address.allocation_id(),
+        "public_ip": address.public_ip(),
+        "instance_id": address.instance_id(),
+        "association_id": address.association_id(),
+    })
+}
+
+pub(crate) fn bucket_outputs(bucket: &str, region: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": bucket,
+        "region": region,
+        "bucket": bucket,
+        "arn": bucket_arn(bucket, region),
+    })
+}
+
+/// Outputs recorded for a role, its name is the id IAM looks it up by
+pub(crate) fn role_outputs(role: &aws_sdk_iam::types::Role, region: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": role.role_name(),
+        "region": region,
+        "arn": role.arn(),
+        "role_name": role.role_name(),
+        "role_id": role.role_id(),
+    })
+}
+
+pub(crate) fn instance_profile_outputs(
+    profile: &aws_sdk_iam::types::InstanceProfile,
     region: &str,
-    component: &crate::models::Component,
-) -> Result<aws_sdk_ec2::types::Instance, crate::aws::ec2::ec2_instance::EC2Error> {
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .profile_name("default")
-        .region(Region::new(region.to_string()))
-        .load()
+) -> serde_json::Value {
+    serde_json::json!({
+        "id": profile.instance_profile_name(),
+        "region": region,
+        "arn": profile.arn(),
+        "instance_profile_name": profile.instance_profile_name(),
+        "role": profile.roles().first().map(|r| r.role_name()),
+    })
+}
+
+/**
+ * Token of one launch of a component. A retried RunInstances of that launch, also by a rerun of an
+ * apply that failed before recording it, returns the instance it already created, while a later
+ * launch (after a destroy, or with changed properties) gets a new token.
+ */
+pub(crate) fn client_token(component_id: &str, launch: &str) -> String {
+    format!(
+        "letusinfra-{}",
+        stable_hash(&format!("{}#{}", component_id, launch))
+    )
+}
+
+/// What sets one launch of a component apart: the properties it is launched with and its generation
+fn launch_id(properties_hash: &str, generation: u64) -> String {
+    format!("{}@{}", properties_hash, generation)
+}
+
+/**
+ * Generation of the launch of component `key`: the one recorded while the component is in state,
+ * the next one once a destroy has removed it. Nothing is recorded for a launch that failed, so a
+ * rerun launches with the same generation.
+ */
+fn launch_generation(previous: Option<&State>, key: &str) -> u64 {
+    let Some(previous) = previous else {
+        return 1;
+    };
+    let launched = previous.launches.get(key).copied().unwrap_or(0);
+    if previous.components.iter().any(|c| c.key() == key) {
+        launched
+    } else {
+        launched + 1
+    }
+}
+
+/// Launches of `previous` with the generation of every component in `created`
+fn recorded_launches(
+    previous: Option<&State>,
+    created: &[ComponentState],
+) -> BTreeMap<String, u64> {
+    let mut launches = previous.map(|p| p.launches.clone()).unwrap_or_default();
+    for component in created {
+        let key = component.key();
+        launches.insert(key.clone(), launch_generation(previous, &key));
+    }
+    launches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(yaml: &str) -> Component {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_connected_security_groups_are_added() {
+        let component = instance(
+            r#"
+type: EC2Instance
+name: web
+properties:
+  security_group_ids: [sg-existing]
+connectsTo:
+  - type: SecurityGroup
+    name: web
+  - type: EC2Instance
+    name: db
+"#,
+        );
+        let outputs = serde_json::json!({ "SecurityGroup.web": { "id": "sg-123" } });
+        let component =
+            with_connected_security_groups(&component, outputs.as_object().unwrap()).unwrap();
+        assert_eq!(
+            component.get_property_as_string_array("security_group_ids"),
+            Some(vec!["sg-existing".to_string(), "sg-123".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_independent_components_share_a_level() {
+        let vpc = instance("type: VPC\nname: main\nproperties: {}\n");
+        let public = instance(
+            "type: Subnet\nname: public\nproperties: {}\ndependsOn: [{type: VPC, name: main}]\n",
+        );
+        let private = instance(
+            "type: Subnet\nname: private\nproperties: {}\ndependsOn: [{type: VPC, name: main}]\n",
+        );
+        let web = instance(
+            "type: EC2Instance\nname: web\nproperties: {}\ndependsOn: [{type: Subnet, name: public}]\n",
+        );
+        let worker = instance("type: EC2Instance\nname: worker\nproperties: {}\n");
+
+        let levels = apply_levels(&[&vpc, &public, &private, &web, &worker]);
+        let keys: Vec<Vec<String>> = levels
+            .iter()
+            .map(|level| level.iter().map(|c| c.key()).collect())
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                vec!["VPC.main".to_string(), "EC2Instance.worker".to_string()],
+                vec!["Subnet.public".to_string(), "Subnet.private".to_string()],
+                vec!["EC2Instance.web".to_string()],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_declared_outputs_are_resolved_from_the_applied_components() {
+        let declarations: BTreeMap<String, OutputDeclaration> = serde_yaml::from_str(
+            "web_ip: ${EC2Instance.web.public_ip}\n\
+             web_ids:\n  value: ${EC2Instance.web.instance_ids}\n  description: Every instance\n\
+             key:\n  value: ${KeyPair.deploy.private_key_file}\n  sensitive: true\n\
+             typo: ${EC2Instance.web.public_dns}\n",
+        )
+        .unwrap();
+        let outputs = serde_json::json!({
+            "EC2Instance.web": { "public_ip": "203.0.113.10", "instance_ids": ["i-1", "i-2"] },
+            "KeyPair.deploy": { "private_key_file": "deploy.pem" },
+        });
+        let (declared, unresolved) =
+            resolve_declared_outputs(&declarations, outputs.as_object().unwrap());
+        assert_eq!(declared["web_ip"].value, "203.0.113.10");
+        assert_eq!(declared["web_ids"].value, serde_json::json!(["i-1", "i-2"]));
+        assert_eq!(
+            declared["web_ids"].description.as_deref(),
+            Some("Every instance")
+        );
+        assert!(declared["key"].sensitive);
+        assert!(!declared.contains_key("typo"));
+        assert_eq!(
+            unresolved,
+            vec!["typo: EC2Instance.web.public_dns is not available"]
+        );
+    }
+
+    #[test]
+    fn test_instance_outputs_have_addresses_and_placement() {
+        let instance = aws_sdk_ec2::types::Instance::builder()
+            .instance_id("i-1")
+            .private_ip_address("10.0.1.5")
+            .public_dns_name("")
+            .subnet_id("subnet-1")
+            .placement(
+                aws_sdk_ec2::types::Placement::builder()
+                    .availability_zone("us-west-2a")
+                    .build(),
+            )
+            .build();
+        let outputs = instance_outputs(&instance, "us-west-2");
+        assert_eq!(outputs["private_ip"], "10.0.1.5");
+        assert_eq!(outputs["availability_zone"], "us-west-2a");
+        assert_eq!(outputs["subnet_id"], "subnet-1");
+        assert!(outputs["public_ip"].is_null());
+        assert!(outputs["public_dns"].is_null());
+
+        // Every key is documented for `${EC2Instance.<name>.<key>}` references
+        let documented: Vec<&str> = schema::EC2_INSTANCE
+            .outputs
+            .iter()
+            .map(|o| o.name)
+            .collect();
+        for key in outputs.as_object().unwrap().keys() {
+            assert!(
+                documented.contains(&key.as_str()),
+                "{} is not in the schema",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_client_token_is_stable_per_launch() {
+        let web = client_token("shop/default/EC2Instance.web", "abc@1");
+        assert_eq!(web, client_token("shop/default/EC2Instance.web", "abc@1"));
+        assert_ne!(web, client_token("shop/staging/EC2Instance.web", "abc@1"));
+        assert_ne!(
+            web,
+            client_token("shop/default/EC2Instance.worker", "abc@1")
+        );
+        assert!(web.len() <= 64, "AWS limits client tokens to 64 characters");
+    }
+
+    /// State after an apply that created `components`, on top of `previous`
+    fn applied_state(previous: Option<&State>, components: &[&str]) -> State {
+        let created: Vec<ComponentState> = components
+            .iter()
+            .map(|key| {
+                let (component_type, name) = key.split_once('.').unwrap();
+                ComponentState {
+                    mode: ComponentMode::Managed,
+                    name: name.to_string(),
+                    component_type: component_type.to_string(),
+                    provider: "aws".to_string(),
+                    instances: vec![],
+                    created_at: None,
+                    properties_hash: None,
+                    replacement_hash: None,
+                    retained: false,
+                }
+            })
+            .collect();
+        State {
+            version: STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: serde_json::json!({}),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: Default::default(),
+            },
+            launches: recorded_launches(previous, &created),
+            components: state_file::merge_components(previous, created),
+        }
+    }
+
+    #[test]
+    fn test_client_token_is_the_same_for_a_rerun() {
+        let token = |previous: Option<&State>, properties_hash: &str| {
+            let generation = launch_generation(previous, "EC2Instance.web");
+            client_token(
+                "shop/default/EC2Instance.web",
+                &launch_id(properties_hash, generation),
+            )
+        };
+        // A rerun after an apply that failed before recording the instance launches it again
+        let first = token(None, "abc");
+        assert_eq!(token(None, "abc"), first);
+        let applied = applied_state(None, &["VPC.main"]);
+        assert_eq!(token(Some(&applied), "abc"), first);
+
+        // Once recorded the component keeps its generation
+        let applied = applied_state(Some(&applied), &["EC2Instance.web"]);
+        assert_eq!(applied.launches.get("EC2Instance.web"), Some(&1));
+        assert_eq!(token(Some(&applied), "abc"), first);
+        let applied = applied_state(Some(&applied), &["EC2Instance.web"]);
+        assert_eq!(token(Some(&applied), "abc"), first);
+        // A launch with changed properties must not hit IdempotentParameterMismatch
+        assert_ne!(token(None, "def"), first);
+    }
+
+    #[test]
+    fn test_client_token_changes_when_the_component_is_created_again() {
+        // apply, destroy, apply: the second launch must not get the terminated instance back
+        let applied = applied_state(None, &["EC2Instance.web"]);
+        let destroyed = State {
+            components: vec![],
+            ..applied.clone()
+        };
+        assert_eq!(launch_generation(Some(&applied), "EC2Instance.web"), 1);
+        assert_eq!(launch_generation(Some(&destroyed), "EC2Instance.web"), 2);
+        let reapplied = applied_state(Some(&destroyed), &["EC2Instance.web"]);
+        assert_eq!(reapplied.launches.get("EC2Instance.web"), Some(&2));
+        assert_ne!(
+            client_token("shop/default/EC2Instance.web", &launch_id("abc", 1)),
+            client_token("shop/default/EC2Instance.web", &launch_id("abc", 2))
+        );
+    }
+
+    #[test]
+    fn test_dependency_ids_are_filled_in() {
+        let outputs = serde_json::json!({
+            "VPC.main": { "id": "vpc-123" },
+            "Subnet.private": { "id": "subnet-456" },
+            "KeyPair.deploy": { "id": "key-789", "key_name": "deploy" },
+        });
+        let outputs = outputs.as_object().unwrap();
+
+        let subnet = instance(
+            "type: Subnet\nname: private\nproperties: {cidr: 10.0.1.0/24}\ndependsOn: [{type: VPC, name: main}]\n",
+        );
+        let subnet = with_dependency_ids(&subnet, outputs).unwrap();
+        assert_eq!(
+            subnet.get_property_as_string("vpc_id").as_deref(),
+            Some("vpc-123")
+        );
+
+        let web = instance(
+            "type: EC2Instance\nname: web\nproperties: {}\ndependsOn: [{type: Subnet, name: private}]\n",
+        );
+        let web = with_dependency_ids(&web, outputs).unwrap();
+        assert_eq!(
+            web.get_property_as_string("subnet_id").as_deref(),
+            Some("subnet-456")
+        );
+
+        // A key pair is referenced by name, not id
+        let web = instance(
+            "type: EC2Instance\nname: web\nproperties: {}\ndependsOn: [{type: KeyPair, name: deploy}]\n",
+        );
+        let web = with_dependency_ids(&web, outputs).unwrap();
+        assert_eq!(
+            web.get_property_as_string("key_name").as_deref(),
+            Some("deploy")
+        );
+
+        // Explicit ids are kept
+        let pinned = instance(
+            "type: Subnet\nname: private\nproperties: {vpc_id: vpc-999}\ndependsOn: [{type: VPC, name: main}]\n",
+        );
+        let pinned = with_dependency_ids(&pinned, outputs).unwrap();
+        assert_eq!(
+            pinned.get_property_as_string("vpc_id").as_deref(),
+            Some("vpc-999")
+        );
+
+        let unapplied = instance(
+            "type: Subnet\nname: private\nproperties: {}\ndependsOn: [{type: VPC, name: other}]\n",
+        );
+        let err = with_dependency_ids(&unapplied, outputs).unwrap_err();
+        assert!(err.contains("VPC.other"), "{}", err);
+    }
+
+    #[test]
+    fn test_unapplied_security_group_is_rejected() {
+        let component = instance(
+            "type: EC2Instance\nname: web\nproperties: {}\nconnectsTo:\n  - type: SecurityGroup\n    name: web\n",
+        );
+        let err = with_connected_security_groups(&component, &serde_json::Map::new()).unwrap_err();
+        assert!(err.contains("SecurityGroup.web"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_type_is_not_applied() {
+        let component = instance("type: RDSInstance\nname: db\nproperties: {}\n");
+        let applied = apply_component(
+            "us-west-2",
+            ("shop", "default"),
+            &component,
+            None,
+            &serde_json::Map::new(),
+            1,
+            &WaitDefaults::default(),
+        )
         .await;
-    let ec2_instance = EC2Instance::from_config(&config);
-    let instance_opts = EC2Instance::opts_from_yaml(&component.properties)?;
-    ec2_instance.create_instance(&instance_opts).await
+        assert!(applied.created.is_none());
+        assert_eq!(
+            applied.log,
+            vec!["RDSInstance.db: unsupported component type: RDSInstance"]
+        );
+
+        let config = InfraConfig::from_yaml(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: shop\ncomponents:\n  - type: RDSInstance\n    name: db\n",
+        )
+        .unwrap();
+        let err = check_capabilities(&config).unwrap_err().to_string();
+        assert!(err.contains("RDSInstance (RDSInstance.db)"), "{}", err);
+    }
 }
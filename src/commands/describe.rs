@@ -0,0 +1,149 @@
+use crate::utils::schema::{AttributeSchema, ResourceSchema, SCHEMAS, schema_for, suggest};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    /// Component type, e.g. EC2Instance
+    pub component_type: String,
+    /// Property path to describe, e.g. placement.availability_zone
+    pub path: Option<String>,
+}
+
+fn did_you_mean(suggestions: Vec<&str>) -> String {
+    match suggestions.as_slice() {
+        [] => String::new(),
+        _ => format!(", did you mean {}?", suggestions.join(" or ")),
+    }
+}
+
+fn flags(attribute: &AttributeSchema) -> String {
+    let flags: Vec<&str> = [
+        (attribute.required, "required"),
+        (attribute.force_new, "force-new"),
+        (attribute.sensitive, "sensitive"),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, flag)| *flag)
+    .collect();
+    if flags.is_empty() {
+        String::new()
+    } else {
+        format!(" [{}]", flags.join(", "))
+    }
+}
+
+/// Required properties first, then the optional ones with nested blocks grouped together
+pub fn describe_type(schema: &ResourceSchema) -> String {
+    let mut output = format!("{}\n\nRequired:\n", schema.component_type);
+    for attribute in schema.attributes.iter().filter(|a| a.required) {
+        output.push_str(&format!(
+            "  {} ({}){}\n      {}\n",
+            attribute.name,
+            attribute.kind.as_str(),
+            flags(attribute),
+            attribute.description
+        ));
+    }
+
+    let mut blocks: Vec<Option<&str>> = Vec::new();
+    for attribute in schema.attributes.iter().filter(|a| !a.required) {
+        if !blocks.contains(&attribute.block()) {
+            blocks.push(attribute.block());
+        }
+    }
+    for block in blocks {
+        match block {
+            None => output.push_str("\nOptional:\n"),
+            Some(block) => output.push_str(&format!("\nOptional, {} block:\n", block)),
+        }
+        for attribute in schema
+            .attributes
+            .iter()
+            .filter(|a| !a.required && a.block() == block)
+        {
+            output.push_str(&format!(
+                "  {} ({}){}\n      {}\n",
+                attribute.name,
+                attribute.kind.as_str(),
+                flags(attribute),
+                attribute.description
+            ));
+        }
+    }
+    if !schema.outputs.is_empty() {
+        output.push_str("\nOutputs, as ${<type>.<name>.<output>}:\n");
+        for recorded in schema.outputs {
+            output.push_str(&format!(
+                "  {}\n      {}\n",
+                recorded.name, recorded.description
+            ));
+        }
+    }
+    output
+}
+
+pub fn describe_attribute(schema: &ResourceSchema, attribute: &AttributeSchema) -> String {
+    let mut output = format!(
+        "{}.{}\n\n{}\n\nType:      {}\nRequired:  {}\n",
+        schema.component_type,
+        attribute.name,
+        attribute.description,
+        attribute.kind.as_str(),
+        if attribute.required { "yes" } else { "no" }
+    );
+    if let Some(default) = attribute.default {
+        output.push_str(&format!("Default:   {}\n", default));
+    }
+    if !attribute.aliases.is_empty() {
+        output.push_str(&format!("Aliases:   {}\n", attribute.aliases.join(", ")));
+    }
+    if !attribute.conflicts_with.is_empty() {
+        output.push_str(&format!(
+            "Conflicts: {}\n",
+            attribute.conflicts_with.join(", ")
+        ));
+    }
+    if attribute.force_new {
+        output.push_str("Force new: changing it replaces the resource\n");
+    }
+    if attribute.sensitive {
+        output
+            .push_str("Sensitive: the value may contain secrets, add a `redact:` pattern for it\n");
+    }
+    output
+}
+
+pub fn execute(config: &Config) -> Result<(), String> {
+    let Some(schema) = schema_for(&config.component_type) else {
+        return Err(format!(
+            "Unknown component type '{}'{}\nKnown types: {}",
+            config.component_type,
+            did_you_mean(suggest(
+                &config.component_type,
+                SCHEMAS.iter().map(|s| s.component_type)
+            )),
+            SCHEMAS
+                .iter()
+                .map(|s| s.component_type)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    };
+
+    match &config.path {
+        None => print!("{}", describe_type(schema)),
+        Some(path) => match schema.attribute(path) {
+            Some(attribute) => print!("{}", describe_attribute(schema, attribute)),
+            None => {
+                return Err(format!(
+                    "{} has no property '{}'{}",
+                    schema.component_type,
+                    path,
+                    did_you_mean(suggest(path, schema.attributes.iter().map(|a| a.name)))
+                ));
+            }
+        },
+    }
+    Ok(())
+}
@@ -1,5 +1,36 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, IsTerminal, Write},
+    sync::Arc,
+    time::Duration,
+};
+
+use comfy_table::{Cell, Color, Table};
+use tokio::{sync::Semaphore, task::JoinSet, time::Instant};
+
 use crate::{
-    commands::validate::validate_file, models::InfraConfig, utils::constants::TEMPLATES_DIR,
+    aws::{
+        ec2::{ec2_instance::DestroyOutcome, key_pair::remove_private_key},
+        internal::wait_and_refresh::WaitDefaults,
+    },
+    commands::validate::validate_file,
+    handlers,
+    models::{
+        Component, ConfigFormat, InfraConfig,
+        state::{ComponentState, State},
+    },
+    state::{self as state_file, backend::StateBackend},
+    utils::{
+        config_documents,
+        diagnostics::Diagnostic,
+        interpolation::resolve_config,
+        plan_components_sequence, read_config_source,
+        redact::redact,
+        select_workspace,
+        templates::resolve_templates,
+        timeouts::ResourceTimeouts,
+        variables::{VariableOptions, bind_variables},
+    },
 };
 
 #[derive(clap::Args, Debug)]
@@ -9,114 +40,1160 @@ pub struct Config {
     pub options: Options,
 }
 
-#[derive(clap::Args, Debug)]
+#[derive(clap::Args, Debug, Clone)]
 pub struct Options {
     #[clap(short = 'f', long = "filepath")]
     pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Workspace to destroy, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// Only destroy these components and their dependents, `<type>.<name>`, can be repeated
+    #[clap(long = "target")]
+    pub targets: Vec<String>,
+    /// Skip the confirmation prompt of partial destroys
+    #[clap(long = "auto-approve")]
+    pub auto_approve: bool,
+    /// Together with --auto-approve, also skip the confirmation of a full destroy
+    #[clap(long = "yes-i-mean-it")]
+    pub yes_i_mean_it: bool,
+    /// Id of the resource behind a component, as `<type>.<name>=<id>`, can be repeated. An
+    /// EC2Instance with several instances takes their ids comma separated
+    #[clap(long = "resource-id")]
+    pub resource_ids: Vec<String>,
+    /// State to destroy from, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Keep components with lifecycle.retain_on_destroy in state, marked `retained`, instead of
+    /// removing them
+    #[clap(long = "keep-in-state")]
+    pub keep_in_state: bool,
+    /// Most components deleted at once, components that depend on each other never run together
+    #[clap(
+        long = "parallelism",
+        default_value_t = 4,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub parallelism: u32,
+    /// Deployment of a `-f` holding several, each has its own state in the backend
+    #[clap(skip)]
+    pub deployment: Option<String>,
 }
 
-pub fn execute(config: &Config) {
-    println!("Executing plan command with config: {:?}", config);
+/// What the user has to do before resources are destroyed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    TypeName, // Full destroy, the deployment name must be typed exactly
+    YesNo,    // Partial destroy with --target
+    Apply,    // Apply of the plan shown above the prompt
+    Forget,   // `state rm`, the resources are dropped from the state but keep running
+    Skip,
+}
 
-    let file_path = &config.options.file_path;
-    println!("File path is: {}", file_path);
-    let is_valid = validate_file(file_path);
-    // if is_valid {
-    //     println!("YAML file is valid.");
-    // } else {
-    //     println!("YAML file is invalid.");
-    // }
+/**
+ * A full destroy (every resource of the deployment) can only skip the prompt with both
+ * --auto-approve and --yes-i-mean-it, --auto-approve alone still asks for the deployment name.
+ */
+pub fn required_confirmation(
+    full_destroy: bool,
+    auto_approve: bool,
+    yes_i_mean_it: bool,
+) -> Confirmation {
+    match (full_destroy, auto_approve, yes_i_mean_it) {
+        (true, true, true) => Confirmation::Skip,
+        (true, _, _) => Confirmation::TypeName,
+        (false, true, _) => Confirmation::Skip,
+        (false, false, _) => Confirmation::YesNo,
+    }
+}
 
-    let content = match std::fs::read_to_string(file_path) {
-        Ok(content) => content,
-        Err(err) => {
-            eprintln!("Failed to read file: {}", err);
-            return;
+/**
+ * Removing a private key file cannot be undone, AWS never hands the key out again. Only
+ * --yes-i-mean-it lets --auto-approve skip the prompt then.
+ */
+fn confirm_key_files(
+    confirmation: Confirmation,
+    has_key_files: bool,
+    yes_i_mean_it: bool,
+) -> Confirmation {
+    if confirmation == Confirmation::Skip && has_key_files && !yes_i_mean_it {
+        return Confirmation::YesNo;
+    }
+    confirmation
+}
+
+/// Ask for confirmation, anything but the exact expected answer (including EOF) cancels the destroy
+pub fn confirm<R: BufRead, W: Write>(
+    confirmation: Confirmation,
+    deployment_name: &str,
+    summary: &str,
+    input: &mut R,
+    output: &mut W,
+) -> bool {
+    let question = match confirmation {
+        Confirmation::Skip => return true,
+        Confirmation::TypeName => format!(
+            "{}\nThis destroys EVERY resource of the deployment. Type the deployment name '{}' to confirm: ",
+            summary, deployment_name
+        ),
+        Confirmation::YesNo => format!(
+            "{}\nDo you want to destroy these resources? [yes/no]: ",
+            summary
+        ),
+        Confirmation::Apply => format!(
+            "{}\nDo you want to perform these actions? (yes/no): ",
+            summary
+        ),
+        Confirmation::Forget => format!(
+            "{}\nDo you want to remove these resources from the state? They are not destroyed. [yes/no]: ",
+            summary
+        ),
+    };
+    if write!(output, "{}", question)
+        .and_then(|_| output.flush())
+        .is_err()
+    {
+        return false;
+    }
+    let mut answer = String::new();
+    if input.read_line(&mut answer).unwrap_or(0) == 0 {
+        return false;
+    }
+    let answer = answer.trim_end_matches(['\r', '\n']);
+    match confirmation {
+        Confirmation::TypeName => answer == deployment_name,
+        _ => answer == "yes",
+    }
+}
+
+/**
+ * Components selected by --target together with everything that depends on them, directly or
+ * through other components, all of them when no target is given.
+ */
+fn select_targets<'a>(
+    components: &'a [Component],
+    targets: &[String],
+) -> Result<Vec<&'a Component>, String> {
+    if targets.is_empty() {
+        return Ok(components.iter().collect());
+    }
+    if let Some(unknown) = targets
+        .iter()
+        .find(|t| !components.iter().any(|c| &c.key() == *t))
+    {
+        return Err(format!(
+            "Unknown target '{}', expected <type>.<name>",
+            unknown
+        ));
+    }
+    let mut selected = targets.to_vec();
+    loop {
+        let dependents: Vec<String> = components
+            .iter()
+            .filter(|c| !selected.contains(&c.key()))
+            .filter(|c| selected.iter().any(|key| depends_on(c, key)))
+            .map(|c| c.key())
+            .collect();
+        if dependents.is_empty() {
+            break;
         }
+        selected.extend(dependents);
+    }
+    Ok(components
+        .iter()
+        .filter(|c| selected.contains(&c.key()))
+        .collect())
+}
+
+/// Whether `component` lists the component `key` in its dependsOn
+fn depends_on(component: &Component, key: &str) -> bool {
+    component
+        .depends_on
+        .iter()
+        .flatten()
+        .any(|d| format!("{}.{}", d.dep_type, d.name) == key)
+}
+
+/**
+ * Map every component that will be destroyed to the id of its resource, `<type>.<name>=<id>`
+ * first and the id recorded in state otherwise. Without a state every component needs an id,
+ * with one a component missing from it was destroyed by an earlier run and has none. Retained
+ * components need no id, the recorded one only shows what is left behind.
+ */
+fn resource_ids(
+    components: &[&Component],
+    mappings: &[String],
+    recorded: Option<&State>,
+) -> Result<HashMap<String, String>, String> {
+    let mut ids = HashMap::new();
+    for mapping in mappings {
+        let Some((key, id)) = mapping.split_once('=') else {
+            return Err(format!(
+                "Invalid --resource-id '{}', expected <type>.<name>=<id>",
+                mapping
+            ));
+        };
+        if !components.iter().any(|c| c.key() == key) {
+            return Err(format!(
+                "--resource-id given for unknown component '{}'",
+                key
+            ));
+        }
+        ids.insert(key.to_string(), id.to_string());
+    }
+
+    let unmapped: Vec<String> = components
+        .iter()
+        .filter(|c| !ids.contains_key(&c.key()))
+        .map(|c| c.key())
+        .collect();
+    let Some(state) = recorded else {
+        // Retained components are left alone, they need no id
+        let missing: Vec<String> = components
+            .iter()
+            .filter(|c| !c.retain_on_destroy() && unmapped.contains(&c.key()))
+            .map(|c| c.key())
+            .collect();
+        if missing.is_empty() {
+            return Ok(ids);
+        }
+        return Err(format!(
+            "No resource id for {}, pass --resource-id <type>.<name>=<id> for each",
+            missing.join(", ")
+        ));
     };
+    for key in unmapped {
+        if let Some(id) = recorded_id(state, &key) {
+            ids.insert(key, id);
+        }
+    }
+    Ok(ids)
+}
+
+/**
+ * Id recorded in state for the component `key`. An EC2Instance that launched several instances
+ * has all of their ids, comma separated, the same way `--resource-id` takes them.
+ */
+fn recorded_id(state: &State, key: &str) -> Option<String> {
+    let ids: Vec<&str> = state
+        .components
+        .iter()
+        .find(|c| format!("{}.{}", c.component_type, c.name) == key)?
+        .instances
+        .iter()
+        .filter_map(|instance| instance.get("id").and_then(|id| id.as_str()))
+        .collect();
+    if ids.is_empty() {
+        return None;
+    }
+    Some(ids.join(","))
+}
+
+/// Private key files apply wrote for the key pairs among `components`, by component key
+fn private_key_files(components: &[&Component], recorded: Option<&State>) -> Vec<(String, String)> {
+    let Some(state) = recorded else {
+        return Vec::new();
+    };
+    components
+        .iter()
+        .filter(|c| c.component_type == "KeyPair" && !c.retain_on_destroy())
+        .filter_map(|c| {
+            let file = state
+                .components
+                .iter()
+                .find(|s| s.component_type == c.component_type && s.name == c.name)?
+                .instances
+                .first()?
+                .get("private_key_file")?
+                .as_str()?;
+            Some((c.key(), file.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DestroyError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("Destroy cancelled")]
+    Cancelled,
+    #[error(
+        "Cannot ask for confirmation, stdin is not a terminal. Pass --auto-approve (together with --yes-i-mean-it to destroy every resource) to destroy without a prompt"
+    )]
+    NotInteractive,
+    #[error("{failed} of {total} component(s) failed to destroy")]
+    ComponentsFailed { failed: usize, total: usize },
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+}
+
+/**
+ * Destroy every deployment of `-f`, the last one first, stopping at the first that fails.
+ * Several deployments each have their own state, `<workspace>/<name>/state.json`.
+ */
+pub async fn execute(config: &Config) -> Result<(), DestroyError> {
+    let options = &config.options;
+    let documents = config_documents(&options.file_path).map_err(DestroyError::Config)?;
+    if documents.len() > 1 && options.state_file.is_some() {
+        return Err(DestroyError::Config(
+            "--state-file cannot be used with several deployments, each has its own state"
+                .to_string(),
+        ));
+    }
+    if documents.len() > 1 && !options.resource_ids.is_empty() {
+        return Err(DestroyError::Config(
+            "--resource-id cannot be used with several deployments, the ids are read from the state of each"
+                .to_string(),
+        ));
+    }
+    for document in documents.iter().rev() {
+        let mut options = options.clone();
+        options.file_path = document.source.clone();
+        if documents.len() > 1 {
+            options.deployment = Some(document.name.clone());
+            println!(
+                "\n=== Deployment {} ({}) ===",
+                document.name, document.source
+            );
+        }
+        destroy_deployment(&Config { options }).await?;
+    }
+    Ok(())
+}
+
+async fn destroy_deployment(config: &Config) -> Result<(), DestroyError> {
+    let options = &config.options;
+    let file_path = &options.file_path;
+    // An invalid config would pick the wrong components and order, nothing is deleted for it
+    validate_file(file_path, &options.variables)
+        .map_err(|err| DestroyError::Config(format!("[{}] {}", err.code(), err)))?;
+    let workspace = select_workspace(options.workspace.as_deref()).map_err(DestroyError::Config)?;
+
+    let content =
+        read_config_source(file_path).map_err(|err| DestroyError::FileRead(err.to_string()))?;
 
     // Try to parse using the structured model
-    match InfraConfig::from_yaml(&content) {
-        Ok(config) => {
-            println!("Successfully parsed YAML using InfraConfig model");
-            delete_components(&config.region, &config.components);
+    let mut config = InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| DestroyError::Parse(err.to_string()))?;
+    tracing::debug!("Successfully parsed YAML using InfraConfig model");
+    resolve_templates(&mut config, file_path).map_err(DestroyError::Config)?;
+    // Region and deployment name may come from variables
+    bind_variables(&mut config, &options.variables)
+        .map_err(|err| DestroyError::Config(format!("[{}] {}", err.code(), err)))?;
+    let config = resolve_config(&config, &workspace).map_err(|err| {
+        DestroyError::Config(format!("Failed to interpolate configuration: {}", err))
+    })?;
+
+    let selected =
+        select_targets(&config.components, &options.targets).map_err(DestroyError::Config)?;
+    // Dependents go first, so destroy in the reverse of the apply order
+    let mut sequence: Vec<&Component> = plan_components_sequence(&config.components)
+        .map_err(|err| DestroyError::Config(format!("[{}] {}", err.code(), err)))?
+        .into_iter()
+        .filter(|c| selected.iter().any(|s| s.key() == c.key()))
+        .collect();
+    sequence.reverse();
+    let mut backend = state_file::backend::open(
+        config.backend.as_ref(),
+        options.state_file.as_deref(),
+        &workspace,
+        options.deployment.as_deref(),
+        Some(&config.region),
+    )
+    .await?;
+    backend.lock("destroy").await?;
+    let outcome: Result<(), DestroyError> = async {
+        let previous = backend.load().await?;
+        let ids = resource_ids(&sequence, &options.resource_ids, previous.as_ref())
+            .map_err(DestroyError::Config)?;
+
+        let full_destroy = selected.len() == config.components.len();
+        let destroyed = sequence.iter().filter(|c| !c.retain_on_destroy()).count();
+        let backend_summary = match &previous {
+            Some(_) => format!("{} ({})", backend.name(), backend.describe()),
+            None => "none (resource ids from --resource-id)".to_string(),
+        };
+        let key_files = private_key_files(&sequence, previous.as_ref());
+        let mut summary = format!(
+            "Workspace: {}\nBackend:   {}\nResources: {} to destroy",
+            workspace, backend_summary, destroyed
+        );
+        if !key_files.is_empty() {
+            let files: Vec<&str> = key_files.iter().map(|(_, file)| file.as_str()).collect();
+            summary.push_str(&format!(
+                "\nPrivate key files to delete: {}",
+                files.join(", ")
+            ));
+        }
+        let confirmation = confirm_key_files(
+            required_confirmation(full_destroy, options.auto_approve, options.yes_i_mean_it),
+            !key_files.is_empty(),
+            options.yes_i_mean_it,
+        );
+        // Reading the answer from a pipe would hang or take whatever the pipe happens to contain
+        if confirmation != Confirmation::Skip && !std::io::stdin().is_terminal() {
+            return Err(DestroyError::NotInteractive);
+        }
+        if !confirm(
+            confirmation,
+            &config.metadata.name,
+            &summary,
+            &mut std::io::stdin().lock(),
+            &mut std::io::stdout(),
+        ) {
+            if confirmation == Confirmation::TypeName {
+                println!(
+                    "\nPass --auto-approve together with --yes-i-mean-it to skip this prompt."
+                );
+            }
+            return Err(DestroyError::Cancelled);
         }
-        Err(err) => {
-            eprintln!("Failed to parse YAML into InfraConfig: {}", err);
+        let (removed, retained, failed) = delete_components(
+            &config.region,
+            &sequence,
+            &ids,
+            &WaitDefaults::for_config(&config),
+            options.parallelism as usize,
+        )
+        .await;
+        if !retained.is_empty() {
+            println!(
+                "\n{}",
+                retained_summary(&retained, &ids, options.keep_in_state)
+            );
+        }
+        // Only once the key pair is gone, the file is useless without it
+        for (key, file) in key_files.iter().filter(|(key, _)| removed.contains(key)) {
+            match remove_private_key(file) {
+                Ok(()) => println!("{}: removed private key file {}", key, file),
+                Err(err) => eprintln!("{}: {}", key, redact(&err.to_string())),
+            }
+        }
+
+        // Forget what is gone even when other components failed, a rerun only retries the rest
+        if let Some(previous) = previous.filter(|_| !removed.is_empty() || !retained.is_empty()) {
+            let state = remaining_state(previous, &removed, &retained, options.keep_in_state);
+            let state = backend.save(state).await?;
+            println!(
+                "State written to {} (serial {})",
+                backend.describe(),
+                state.serial
+            );
         }
+
+        if failed == 0 {
+            return Ok(());
+        }
+        Err(DestroyError::ComponentsFailed {
+            failed,
+            total: sequence.len(),
+        })
+    }
+    .await;
+    state_file::backend::unlock_after(&mut backend, outcome).await
+}
+
+/**
+ * State left after a destroy: the `removed` components are gone from it, the `retained` ones are
+ * kept marked `retained` with `keep_in_state` and removed with the rest otherwise.
+ */
+fn remaining_state(
+    previous: State,
+    removed: &[String],
+    retained: &[String],
+    keep_in_state: bool,
+) -> State {
+    let forgotten =
+        |key: &String| removed.contains(key) || (!keep_in_state && retained.contains(key));
+    let mut outputs = previous.outputs.clone();
+    if let Some(values) = outputs.value.as_object_mut() {
+        values.retain(|key, _| !forgotten(key));
+    }
+    let components: Vec<_> = previous
+        .components
+        .iter()
+        .filter(|c| !forgotten(&c.key()))
+        .map(|c| ComponentState {
+            retained: c.retained || retained.contains(&c.key()),
+            ..c.clone()
+        })
+        .collect();
+    // The declared outputs point at components, with none left they only show stale values
+    if components.is_empty() {
+        outputs.declared.clear();
+    }
+    State {
+        outputs,
+        components,
+        ..previous
+    }
+}
+
+/**
+ * What happened to the retained components: they are still running, with their ids, and either
+ * still tracked in state or no longer.
+ */
+fn retained_summary(
+    retained: &[String],
+    ids: &HashMap<String, String>,
+    keep_in_state: bool,
+) -> String {
+    let mut summary = String::from("Retained, still running:");
+    for key in retained {
+        let id = ids.get(key).map(String::as_str).unwrap_or("id unknown");
+        summary.push_str(&format!("\n  {} {}", key, id));
+    }
+    if keep_in_state {
+        summary.push_str("\nKept in state, marked retained.");
+    } else {
+        summary.push_str(
+            "\nRemoved from state, delete them by hand or pass --keep-in-state to keep tracking them.",
+        );
     }
+    summary
 }
 
-fn delete_components(region: &str, components: &[crate::models::Component]) {
+/**
+ * Components in the order destroy goes through them, grouped into levels: a component comes one
+ * level after the last of the components that depend on it, so nothing in a level depends on
+ * another component of it. `components` is in destroy order, dependents first.
+ */
+fn destroy_levels<'a>(components: &[&'a Component]) -> Vec<Vec<&'a Component>> {
+    let mut level_of: HashMap<String, usize> = HashMap::new();
+    let mut levels: Vec<Vec<&Component>> = Vec::new();
     for component in components {
-        match component.component_type.as_str() {
-            "EC2Instance" => {
-                // Create EC2 instance Terraform code
-                delete_ec2_instance(region, component);
+        let key = component.key();
+        let level = components
+            .iter()
+            .filter(|c| depends_on(c, &key))
+            .filter_map(|c| level_of.get(&c.key()))
+            .map(|level| level + 1)
+            .max()
+            .unwrap_or(0);
+        level_of.insert(key, level);
+        if levels.len() <= level {
+            levels.resize_with(level + 1, Vec::new);
+        }
+        levels[level].push(component);
+    }
+    levels
+}
+
+/// AWS codes of a delete refused because something still uses the resource
+const DEPENDENCY_VIOLATIONS: [&str; 2] = ["DependencyViolation", "DeleteConflict"];
+
+/// Whether a destroy failed on a dependency AWS still sees, the SDK errors carry the code quoted
+fn is_dependency_violation(err: &str) -> bool {
+    DEPENDENCY_VIOLATIONS
+        .iter()
+        .any(|code| err.contains(&format!("\"{}\"", code)))
+}
+
+/**
+ * Run `destroy` until it does not fail on a dependency violation or `window` is over. AWS can
+ * still see a dependent that is already gone for a while, a detaching network interface or a
+ * security group reference, so the delete is retried with the backoff of `wait`.
+ */
+async fn retry_dependency_violation<F, Fut>(
+    key: &str,
+    window: Duration,
+    wait: &WaitDefaults,
+    mut destroy: F,
+) -> Result<DestroyOutcome, String>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<DestroyOutcome, String>>,
+{
+    let deadline = Instant::now() + window;
+    let mut delay = wait.min_delay;
+    loop {
+        match destroy().await {
+            Err(err) if is_dependency_violation(&err) && Instant::now() + delay < deadline => {
+                println!("{}: still in use, retrying in {:?}", key, delay);
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(wait.max_delay);
             }
-            _ => {
-                eprintln!("Unsupported component type: {}", component.component_type);
+            result => return result,
+        }
+    }
+}
+
+/**
+ * Delete the resource of one component, bounded by the delete timeout of its timeouts block.
+ * Dependency violations are retried within that timeout, the wait timeout without one.
+ */
+async fn delete_component(
+    region: &str,
+    component: &Component,
+    resource_id: &str,
+    wait: &WaitDefaults,
+) -> Result<DestroyOutcome, String> {
+    let Some(handler) = handlers::handler_for(&component.component_type) else {
+        return Err(handlers::unsupported(&component.component_type));
+    };
+    let timeouts = ResourceTimeouts::from_properties(&component.properties).unwrap_or_default();
+    let key = component.key();
+    let destroy =
+        retry_dependency_violation(&key, timeouts.delete.unwrap_or(wait.timeout), wait, || {
+            handler.destroy(region, component, resource_id, wait)
+        });
+    // The timeouts block bounds the whole destroy, not only the waits inside it
+    match timeouts.delete {
+        Some(limit) => tokio::time::timeout(limit, destroy)
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "timed out after {:?}, raise it in the timeouts block",
+                    limit
+                ))
+            }),
+        None => destroy.await,
+    }
+}
+
+/**
+ * Components left in place because `failed` was not destroyed: the ones it depends on, and in
+ * turn theirs. `skipped` pairs each skipped component with the component that blocked it.
+ */
+fn blocked_ancestors(failed: &str, skipped: &[(String, String)]) -> Vec<String> {
+    let mut blocked: Vec<String> = Vec::new();
+    let mut blockers = vec![failed.to_string()];
+    while let Some(blocker) = blockers.pop() {
+        for (key, _) in skipped.iter().filter(|(_, by)| *by == blocker) {
+            if !blocked.contains(key) {
+                blocked.push(key.clone());
+                blockers.push(key.clone());
             }
         }
     }
+    blocked
 }
 
-fn delete_ec2_instance(region: &str, component: &crate::models::Component) {
-    let name = &component.name;
-    let instance_type = component
-        .get_property_as_string("instanceType")
-        .unwrap_or_else(|| "t2.micro".to_string());
-    let ami_id = component
-        .get_property_as_string("ami")
-        .unwrap_or_else(|| "ami-0c55b159cbfafe1f0".to_string()); // Default to Ubuntu 20.04 LTS AMI
-
-    // let temp_dir = std::env::temp_dir();
-    let temp_dir = std::env::current_dir()
-        .expect("Unable to get current directory")
-        .join("workspaces")
-        .join(name);
-
-    // Copy the template files to a new directory for this component from TEMPLATES_DIR
-    let component_dir = temp_dir.join(format!("{}_{}", name, "EC2Instance"));
-    std::fs::create_dir_all(&component_dir).expect("Unable to create component directory");
-
-    let templates_dir = std::path::Path::new(TEMPLATES_DIR).join("EC2Instance");
-    println!("Copying templates from {:?}", templates_dir);
-    for entry in std::fs::read_dir(templates_dir).expect("Unable to read templates directory") {
-        let entry = entry.expect("Unable to read entry");
-        let src = entry.path();
-        let dst = component_dir.join(src.file_name().expect("Unable to get file name"));
-        std::fs::copy(&src, &dst).expect("Unable to copy template file");
-    }
-
-    let tfvars_content = format!(
-        "aws_region = \"{}\"\ninstance_type = \"{}\"\nami_id = \"{}\"",
-        region, instance_type, ami_id
-    );
-    std::fs::write(component_dir.join("vars.tfvars"), tfvars_content)
-        .expect("Unable to write tfvars file");
-
-    // Run terraform init and apply in the component directory
-    let mut init_status = std::process::Command::new("terraform");
-    let init_status = init_status
-        .current_dir(&component_dir)
-        .arg("init")
-        .status()
-        .expect("Failed to execute terraform init");
-    if !init_status.success() {
-        eprintln!("terraform init failed");
-        return;
-    }
-    let mut apply_status = std::process::Command::new("terraform");
-    let apply_status = apply_status
-        .arg("destroy")
-        .current_dir(&component_dir)
-        .arg("-var-file=vars.tfvars")
-        .arg("-auto-approve")
-        .status()
-        .expect("Failed to execute terraform apply");
-    if !apply_status.success() {
-        eprintln!("terraform apply failed");
-        return;
+/**
+ * Destroy `components` level by level, see `destroy_levels`: the components of a level are
+ * deleted concurrently with at most `parallelism` in flight, and each level is gone before the
+ * next starts. A component whose dependent failed or was skipped is skipped as well, deleting it
+ * would only fail. Progress is printed as components start and finish, the table and the
+ * components each failure left in place once all are done.
+ * Returns the keys of the components that no longer need to be in state, the keys of the
+ * retained ones, left in place, and the failure count.
+ */
+async fn delete_components(
+    region: &str,
+    components: &[&Component],
+    ids: &HashMap<String, String>,
+    wait: &WaitDefaults,
+    parallelism: usize,
+) -> (Vec<String>, Vec<String>, usize) {
+    let mut rows: HashMap<String, Vec<Cell>> = HashMap::new();
+    let mut removed = Vec::new();
+    let mut retained = Vec::new();
+    let mut blocked: Vec<&Component> = Vec::new(); // Failed or skipped, still in place
+    let mut failures: Vec<String> = Vec::new();
+    let mut skipped: Vec<(String, String)> = Vec::new();
+    let semaphore = Arc::new(Semaphore::new(parallelism.max(1)));
+
+    for level in destroy_levels(components) {
+        let mut tasks = JoinSet::new();
+        let mut keys = HashMap::new();
+        for component in level {
+            let key = component.key();
+            if component.retain_on_destroy() {
+                let row = vec![
+                    Cell::new(&key),
+                    Cell::new(ids.get(&key).map(String::as_str).unwrap_or("-")),
+                    Cell::new("retained (lifecycle.retain_on_destroy)").fg(Color::Yellow),
+                ];
+                rows.insert(key.clone(), row);
+                retained.push(key);
+                continue;
+            }
+            let Some(resource_id) = ids.get(&key) else {
+                let row = vec![
+                    Cell::new(&key),
+                    Cell::new("-"),
+                    Cell::new("not in state").fg(Color::Yellow),
+                ];
+                rows.insert(key, row);
+                continue;
+            };
+            if let Some(dependent) = blocked.iter().find(|b| depends_on(b, &key)) {
+                let reason = format!("skipped: {} was not destroyed", dependent.key());
+                skipped.push((key.clone(), dependent.key()));
+                let row = vec![
+                    Cell::new(&key),
+                    Cell::new(resource_id),
+                    Cell::new(reason).fg(Color::Yellow),
+                ];
+                rows.insert(key, row);
+                blocked.push(component);
+                continue;
+            }
+
+            let semaphore = semaphore.clone();
+            let region = region.to_string();
+            let owned = (*component).clone();
+            let resource_id = resource_id.clone();
+            let wait = wait.clone();
+            let handle = tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                println!("{}: destroying {}", owned.key(), resource_id);
+                delete_component(&region, &owned, &resource_id, &wait).await
+            });
+            keys.insert(handle.id(), component);
+        }
+
+        while let Some(joined) = tasks.join_next_with_id().await {
+            let (component, result) = match joined {
+                Ok((id, result)) => (keys[&id], result),
+                Err(err) => (
+                    keys[&err.id()],
+                    Err(format!("destroy task failed: {}", err)),
+                ),
+            };
+            let key = component.key();
+            let cell = match &result {
+                Ok(DestroyOutcome::Terminated) => Cell::new("destroyed").fg(Color::Green),
+                Ok(DestroyOutcome::AlreadyGone) => Cell::new("already gone").fg(Color::Green),
+                Err(err) => Cell::new(format!("failed: {}", redact(err))).fg(Color::Red),
+            };
+            match &result {
+                Ok(_) => println!("{}: destroyed", key),
+                Err(err) => eprintln!("{}: failed: {}", key, redact(err)),
+            }
+            let resource_id = ids.get(&key).cloned().unwrap_or_default();
+            rows.insert(
+                key.clone(),
+                vec![Cell::new(&key), Cell::new(resource_id), cell],
+            );
+            match result {
+                Ok(_) => removed.push(key),
+                Err(_) => {
+                    failures.push(key);
+                    blocked.push(component);
+                }
+            }
+        }
+    }
+
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(vec!["Component", "Resource id", "Result"]);
+    for component in components {
+        if let Some(row) = rows.remove(&component.key()) {
+            table.add_row(row);
+        }
+    }
+    println!("\n{}", table);
+    for key in &failures {
+        let left = blocked_ancestors(key, &skipped);
+        if !left.is_empty() {
+            eprintln!(
+                "{} failed, left in place because of it: {}",
+                key,
+                left.join(", ")
+            );
+        }
+    }
+    (removed, retained, failures.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_required_confirmation() {
+        assert_eq!(
+            required_confirmation(true, false, false),
+            Confirmation::TypeName
+        );
+        assert_eq!(
+            required_confirmation(true, true, false),
+            Confirmation::TypeName
+        );
+        assert_eq!(
+            required_confirmation(true, false, true),
+            Confirmation::TypeName
+        );
+        assert_eq!(required_confirmation(true, true, true), Confirmation::Skip);
+        assert_eq!(
+            required_confirmation(false, false, false),
+            Confirmation::YesNo
+        );
+        assert_eq!(
+            required_confirmation(false, true, false),
+            Confirmation::Skip
+        );
+    }
+
+    #[test]
+    fn test_private_key_files_need_confirmation() {
+        assert_eq!(
+            confirm_key_files(Confirmation::Skip, true, false),
+            Confirmation::YesNo
+        );
+        assert_eq!(
+            confirm_key_files(Confirmation::Skip, true, true),
+            Confirmation::Skip
+        );
+        assert_eq!(
+            confirm_key_files(Confirmation::Skip, false, false),
+            Confirmation::Skip
+        );
+        assert_eq!(
+            confirm_key_files(Confirmation::TypeName, true, false),
+            Confirmation::TypeName
+        );
+    }
+
+    fn answer(confirmation: Confirmation, input: &str) -> (bool, String) {
+        let mut output = Vec::new();
+        let confirmed = confirm(
+            confirmation,
+            "shop",
+            "Workspace: prod\nBackend:   local (workspaces/shop)\nResources: 3 to destroy",
+            &mut input.as_bytes(),
+            &mut output,
+        );
+        (confirmed, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn test_full_destroy_requires_exact_name() {
+        let (confirmed, prompt) = answer(Confirmation::TypeName, "shop\n");
+        assert!(confirmed);
+        assert!(prompt.contains("Workspace: prod"));
+        assert!(prompt.contains("workspaces/shop"));
+        assert!(prompt.contains("3 to destroy"));
+
+        assert!(!answer(Confirmation::TypeName, "yes\n").0);
+        assert!(!answer(Confirmation::TypeName, "Shop\n").0);
+        assert!(!answer(Confirmation::TypeName, " shop\n").0);
+        assert!(!answer(Confirmation::TypeName, "").0);
+    }
+
+    #[test]
+    fn test_partial_destroy_asks_yes_no() {
+        assert!(answer(Confirmation::YesNo, "yes\n").0);
+        assert!(!answer(Confirmation::YesNo, "no\n").0);
+        assert!(answer(Confirmation::Skip, "").0);
+    }
+
+    #[test]
+    fn test_apply_asks_to_perform_the_actions() {
+        let (confirmed, prompt) = answer(Confirmation::Apply, "yes\n");
+        assert!(confirmed);
+        assert!(prompt.ends_with("Do you want to perform these actions? (yes/no): "));
+        assert!(!answer(Confirmation::Apply, "y\n").0);
+        assert!(!answer(Confirmation::Apply, "YES\n").0);
+    }
+
+    #[test]
+    fn test_resource_ids_must_cover_every_destroyed_component() {
+        let config = InfraConfig::from_yaml(
+            r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: EC2Instance
+    name: web
+  - type: EC2Instance
+    name: db
+    lifecycle:
+      retain_on_destroy: true
+"#,
+        )
+        .unwrap();
+        let components: Vec<&Component> = config.components.iter().collect();
+
+        let mapping = ["EC2Instance.web=i-123".to_string()];
+        let ids = resource_ids(&components, &mapping, None).unwrap();
+        assert_eq!(ids["EC2Instance.web"], "i-123");
+
+        let err = resource_ids(&components, &[], None).unwrap_err();
+        assert!(err.contains("EC2Instance.web"));
+        assert!(
+            !err.contains("EC2Instance.db"),
+            "retained components need no id"
+        );
+
+        assert!(resource_ids(&components, &["EC2Instance.web".to_string()], None).is_err());
+        assert!(resource_ids(&components, &["EC2Instance.api=i-1".to_string()], None).is_err());
+    }
+
+    #[test]
+    fn test_resource_ids_fall_back_to_state() {
+        let config = InfraConfig::from_yaml(NETWORK).unwrap();
+        let components: Vec<&Component> = config.components.iter().collect();
+        let state: State = serde_json::from_value(serde_json::json!({
+            "version": "1",
+            "modulepack": "shop",
+            "outputs": {"value": {}, "output_type": {"datatype": "object", "value": {}}},
+            "components": [
+                {"mode": "managed", "name": "main", "type": "VPC", "provider": "aws",
+                 "instances": [{"id": "vpc-1"}]},
+                {"mode": "managed", "name": "web", "type": "EC2Instance", "provider": "aws",
+                 "instances": [{"id": "i-1"}]},
+            ],
+        }))
+        .unwrap();
+
+        let mapping = ["EC2Instance.web=i-2".to_string()];
+        let ids = resource_ids(&components, &mapping, Some(&state)).unwrap();
+        assert_eq!(
+            ids["EC2Instance.web"], "i-2",
+            "--resource-id wins over state"
+        );
+        assert_eq!(ids["VPC.main"], "vpc-1");
+        // Destroyed by an earlier run, nothing left to do for it
+        assert!(!ids.contains_key("Subnet.public"));
+    }
+
+    #[test]
+    fn test_resource_ids_list_every_launched_instance() {
+        let config = InfraConfig::from_yaml(NETWORK).unwrap();
+        let components: Vec<&Component> = config.components.iter().collect();
+        let state: State = serde_json::from_value(serde_json::json!({
+            "version": "1",
+            "modulepack": "shop",
+            "outputs": {"value": {}, "output_type": {"datatype": "object", "value": {}}},
+            "components": [
+                {"mode": "managed", "name": "web", "type": "EC2Instance", "provider": "aws",
+                 "instances": [{"id": "i-1"}, {"id": "i-2"}, {"id": "i-3"}]},
+            ],
+        }))
+        .unwrap();
+
+        let ids = resource_ids(&components, &[], Some(&state)).unwrap();
+        assert_eq!(ids["EC2Instance.web"], "i-1,i-2,i-3");
+    }
+
+    const NETWORK: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: VPC
+    name: main
+  - type: Subnet
+    name: public
+    dependsOn:
+      - type: VPC
+        name: main
+  - type: EC2Instance
+    name: web
+    dependsOn:
+      - type: Subnet
+        name: public
+  - type: SecurityGroup
+    name: web
+"#;
+
+    #[test]
+    fn test_target_selects_its_dependents() {
+        let config = InfraConfig::from_yaml(NETWORK).unwrap();
+        let keys = |targets: &[&str]| -> Vec<String> {
+            let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+            select_targets(&config.components, &targets)
+                .unwrap()
+                .iter()
+                .map(|c| c.key())
+                .collect()
+        };
+        assert_eq!(
+            keys(&["VPC.main"]),
+            vec!["VPC.main", "Subnet.public", "EC2Instance.web"]
+        );
+        assert_eq!(
+            keys(&["Subnet.public"]),
+            vec!["Subnet.public", "EC2Instance.web"]
+        );
+        assert_eq!(keys(&["EC2Instance.web"]), vec!["EC2Instance.web"]);
+        assert_eq!(keys(&[]).len(), 4);
+        assert!(select_targets(&config.components, &["VPC.other".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_type_is_not_destroyed() {
+        let component: Component =
+            serde_yaml::from_str("type: RDSInstance\nname: db\nproperties: {}\n").unwrap();
+        let ids = HashMap::from([(component.key(), "db-1".to_string())]);
+        let (removed, _, failed) = delete_components(
+            "us-west-2",
+            &[&component],
+            &ids,
+            &WaitDefaults::default(),
+            1,
+        )
+        .await;
+        // Kept in state, the resource may still exist
+        assert!(removed.is_empty());
+        assert_eq!(failed, 1);
+    }
+
+    fn network_state() -> State {
+        serde_json::from_value(serde_json::json!({
+            "version": "1",
+            "modulepack": "shop",
+            "outputs": {
+                "value": {"VPC.main": {"id": "vpc-1"}, "EC2Instance.web": {"id": "i-1"}},
+                "output_type": {"datatype": "object", "value": {}},
+            },
+            "components": [
+                {"mode": "managed", "name": "main", "type": "VPC", "provider": "aws",
+                 "instances": [{"id": "vpc-1"}]},
+                {"mode": "managed", "name": "web", "type": "EC2Instance", "provider": "aws",
+                 "instances": [{"id": "i-1"}]},
+            ],
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_retained_components_are_kept_in_state_when_asked() {
+        let removed = ["EC2Instance.web".to_string()];
+        let retained = ["VPC.main".to_string()];
+
+        let state = remaining_state(network_state(), &removed, &retained, true);
+        assert_eq!(state.components.len(), 1);
+        assert_eq!(state.components[0].key(), "VPC.main");
+        assert!(state.components[0].retained);
+        let outputs = state.outputs.value.as_object().unwrap();
+        assert!(outputs.contains_key("VPC.main"));
+        assert!(!outputs.contains_key("EC2Instance.web"));
+
+        let state = remaining_state(network_state(), &removed, &retained, false);
+        assert!(state.components.is_empty());
+        assert!(state.outputs.value.as_object().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_retained_summary_lists_the_ids() {
+        let config = InfraConfig::from_yaml(
+            r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: VPC
+    name: main
+    lifecycle:
+      retain_on_destroy: true
+"#,
+        )
+        .unwrap();
+        let components: Vec<&Component> = config.components.iter().collect();
+        let ids = resource_ids(&components, &[], Some(&network_state())).unwrap();
+        let retained = ["VPC.main".to_string()];
+
+        let summary = retained_summary(&retained, &ids, false);
+        assert!(summary.contains("VPC.main vpc-1"));
+        assert!(summary.contains("--keep-in-state"));
+        assert!(retained_summary(&retained, &ids, true).contains("marked retained"));
+    }
+
+    #[test]
+    fn test_destroy_levels_put_dependents_first() {
+        let config = InfraConfig::from_yaml(NETWORK).unwrap();
+        let mut sequence = plan_components_sequence(&config.components).unwrap();
+        sequence.reverse();
+        let levels: Vec<Vec<String>> = destroy_levels(&sequence)
+            .iter()
+            .map(|level| {
+                let mut keys: Vec<String> = level.iter().map(|c| c.key()).collect();
+                keys.sort();
+                keys
+            })
+            .collect();
+        assert_eq!(
+            levels,
+            vec![
+                vec!["EC2Instance.web", "SecurityGroup.web"],
+                vec!["Subnet.public"],
+                vec!["VPC.main"],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_blocked_ancestors_follow_the_skipped_chain() {
+        let skipped = vec![
+            ("Subnet.public".to_string(), "EC2Instance.web".to_string()),
+            ("VPC.main".to_string(), "Subnet.public".to_string()),
+            ("Subnet.private".to_string(), "EC2Instance.db".to_string()),
+        ];
+        assert_eq!(
+            blocked_ancestors("EC2Instance.web", &skipped),
+            vec!["Subnet.public", "VPC.main"]
+        );
+        assert_eq!(
+            blocked_ancestors("EC2Instance.db", &skipped),
+            vec!["Subnet.private"]
+        );
+        assert!(blocked_ancestors("SecurityGroup.web", &skipped).is_empty());
+    }
+
+    fn fast_wait() -> WaitDefaults {
+        WaitDefaults {
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            ..WaitDefaults::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dependency_violations_are_retried() {
+        let violation =
+            r#"AWS SDK error: service error (code: Some("DependencyViolation"), message: None)"#;
+        let mut attempts = 0;
+        let result =
+            retry_dependency_violation("VPC.main", Duration::from_secs(5), &fast_wait(), || {
+                attempts += 1;
+                let result = match attempts {
+                    1 | 2 => Err(violation.to_string()),
+                    _ => Ok(DestroyOutcome::Terminated),
+                };
+                async move { result }
+            })
+            .await;
+        assert_eq!(result, Ok(DestroyOutcome::Terminated));
+        assert_eq!(attempts, 3);
+
+        // Any other failure is reported at once
+        let mut attempts = 0;
+        let result =
+            retry_dependency_violation("VPC.main", Duration::from_secs(5), &fast_wait(), || {
+                attempts += 1;
+                async { Err("[LI-E001] access denied".to_string()) }
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+
+        // Until the delete timeout is over
+        let result = retry_dependency_violation(
+            "VPC.main",
+            Duration::from_millis(20),
+            &fast_wait(),
+            || async move { Err(violation.to_string()) },
+        )
+        .await;
+        assert_eq!(result, Err(violation.to_string()));
     }
 }
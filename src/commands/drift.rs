@@ -0,0 +1,448 @@
+use comfy_table::Table;
+
+use crate::{
+    commands::{import::read_resource, output::display_value},
+    models::{Component, ConfigFormat, InfraConfig, state::ComponentState},
+    state::{self as state_file, backend::StateBackend},
+    utils::{
+        anchor_config_paths, config_documents,
+        diagnostics::Diagnostic,
+        interpolation::{interpolate_outputs, resolve_config},
+        read_config_source,
+        redact::{self, redact},
+        schema, select_workspace,
+        templates::resolve_templates,
+        variables::{VariableOptions, bind_variables},
+    },
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Options {
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Workspace to check, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State to compare with, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Deployment of a `-f` holding several, each has its own state in the backend
+    #[clap(skip)]
+    pub deployment: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DriftError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+    #[error("{failed} of {total} component(s) could not be read")]
+    ComponentsFailed { failed: usize, total: usize },
+}
+
+/// What the live value was compared to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriftSource {
+    State,  // Recorded by the last apply, import or refresh
+    Config, // Declared in the YAML
+}
+
+impl std::fmt::Display for DriftSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DriftSource::State => write!(f, "state"),
+            DriftSource::Config => write!(f, "config"),
+        }
+    }
+}
+
+/// One attribute of a resource whose live value is not the expected one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttributeDrift {
+    pub address: String,
+    pub id: String, // Resource of the component, one of several when it launched more than one
+    pub attribute: String,
+    pub source: DriftSource,
+    pub expected: String,
+    pub actual: String,
+    pub sensitive: bool, // Only reported as differing, the values are never shown
+}
+
+/**
+ * Check every deployment of `-f` for drift, one after the other. Returns whether any of them
+ * drifted, a deployment that cannot be read stops the check.
+ */
+pub async fn execute(config: &Config) -> Result<bool, DriftError> {
+    let options = &config.options;
+    let documents = config_documents(&options.file_path).map_err(DriftError::Config)?;
+    if documents.len() > 1 && options.state_file.is_some() {
+        return Err(DriftError::Config(
+            "--state-file cannot be used with several deployments, each has its own state"
+                .to_string(),
+        ));
+    }
+    let mut drifted = false;
+    for document in &documents {
+        let mut options = options.clone();
+        options.file_path = document.source.clone();
+        if documents.len() > 1 {
+            options.deployment = Some(document.name.clone());
+            println!(
+                "\n=== Deployment {} ({}) ===",
+                document.name, document.source
+            );
+        }
+        drifted |= drift_deployment(&Config { options }).await?;
+    }
+    Ok(drifted)
+}
+
+/**
+ * Read every resource recorded in the state from AWS and compare it to the state and to the
+ * config, without writing anything. Returns whether drift was found, the report is printed.
+ */
+async fn drift_deployment(config: &Config) -> Result<bool, DriftError> {
+    let options = &config.options;
+    let file_path = &options.file_path;
+    let content =
+        read_config_source(file_path).map_err(|err| DriftError::FileRead(err.to_string()))?;
+    let mut config = InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| DriftError::Parse(err.to_string()))?;
+    resolve_templates(&mut config, file_path).map_err(DriftError::Config)?;
+    anchor_config_paths(&mut config, file_path);
+    bind_variables(&mut config, &options.variables)
+        .map_err(|err| DriftError::Config(format!("[{}] {}", err.code(), err)))?;
+    redact::install(&config.redact, schema::sensitive_values(&config.components))
+        .map_err(|err| DriftError::Config(err.to_string()))?;
+    let workspace = select_workspace(options.workspace.as_deref()).map_err(DriftError::Config)?;
+    let config = resolve_config(&config, &workspace).map_err(|err| {
+        DriftError::Config(format!("Failed to interpolate configuration: {}", err))
+    })?;
+
+    // Nothing is written, the state is read without taking the lock like plan does
+    let backend = state_file::backend::open(
+        config.backend.as_ref(),
+        options.state_file.as_deref(),
+        &workspace,
+        options.deployment.as_deref(),
+        Some(&config.region),
+    )
+    .await?;
+    let Some(state) = backend.load().await? else {
+        println!("No state at {}, nothing to compare", backend.describe());
+        return Ok(false);
+    };
+    let outputs = state.outputs.value.as_object().cloned().unwrap_or_default();
+
+    let mut drifted = Vec::new();
+    let mut failed = 0;
+    for component in &state.components {
+        let address = component.key();
+        let declared = config.components.iter().find(|c| c.key() == address);
+        let sensitive = match declared {
+            Some(declared) => schema::sensitive_paths(declared),
+            None => schema::flagged_sensitive(&component.component_type),
+        };
+        // Every instance is compared, min_count/max_count can launch several
+        let mut component_failed = false;
+        for recorded in &component.instances {
+            let Some(id) = recorded.get("id").and_then(|id| id.as_str()) else {
+                continue;
+            };
+            let region = recorded
+                .get("region")
+                .and_then(|r| r.as_str())
+                .unwrap_or(&config.region);
+            let found = match read_resource(&component.component_type, id, region).await {
+                Ok(found) => found,
+                Err(err) => {
+                    component_failed = true;
+                    eprintln!(
+                        "{}: read of {} failed: [{}] {}",
+                        address,
+                        id,
+                        err.code(),
+                        redact(&err.to_string())
+                    );
+                    continue;
+                }
+            };
+            let Some(found) = found else {
+                drifted.push(AttributeDrift {
+                    address: address.clone(),
+                    id: id.to_string(),
+                    attribute: "id".to_string(),
+                    source: DriftSource::State,
+                    expected: id.to_string(),
+                    actual: "(gone)".to_string(),
+                    sensitive: false,
+                });
+                continue;
+            };
+            drifted.extend(state_drift(component, recorded, &found.outputs, &sensitive));
+            if let Some(declared) = declared {
+                drifted.extend(config_drift(
+                    declared,
+                    id,
+                    &found.attributes,
+                    &outputs,
+                    &sensitive,
+                ));
+            }
+        }
+        if component_failed {
+            failed += 1;
+        }
+    }
+
+    let total = state.components.len();
+    if drifted.is_empty() {
+        println!("No drift: the resources match the state and the config");
+    } else {
+        println!("{}", render_drift(&drifted));
+        let mut resources: Vec<&str> = drifted.iter().map(|d| d.address.as_str()).collect();
+        resources.dedup();
+        println!(
+            "Drift detected: {} attribute(s) of {} resource(s)",
+            drifted.len(),
+            resources.len()
+        );
+    }
+    if failed > 0 {
+        return Err(DriftError::ComponentsFailed { failed, total });
+    }
+    Ok(!drifted.is_empty())
+}
+
+/**
+ * Outputs read from AWS that differ from the ones recorded in the state for one instance of the
+ * component. Outputs the schema marks as computed change on their own, and outputs the read does
+ * not return are not compared.
+ */
+fn state_drift(
+    component: &ComponentState,
+    recorded: &serde_json::Value,
+    live: &serde_json::Value,
+    sensitive: &[String],
+) -> Vec<AttributeDrift> {
+    let (Some(recorded), Some(live)) = (recorded.as_object(), live.as_object()) else {
+        return Vec::new();
+    };
+    let id = recorded.get("id").and_then(|id| id.as_str()).unwrap_or("-");
+    let null = serde_json::Value::Null;
+    live.iter()
+        .filter(|(name, _)| !schema::is_computed(&component.component_type, name))
+        .filter(|(name, value)| recorded.get(name.as_str()).unwrap_or(&null) != *value)
+        .map(|(name, value)| AttributeDrift {
+            address: component.key(),
+            id: id.to_string(),
+            attribute: name.clone(),
+            source: DriftSource::State,
+            expected: show(recorded.get(name.as_str())),
+            actual: show(Some(value)),
+            sensitive: sensitive.contains(name),
+        })
+        .collect()
+}
+
+/**
+ * Declared properties that differ from the live resource. References to other components are
+ * resolved with the outputs in the state, properties that are not set are not compared.
+ */
+fn config_drift(
+    component: &Component,
+    id: &str,
+    attributes: &[(&str, Option<String>)],
+    outputs: &serde_json::Map<String, serde_json::Value>,
+    sensitive: &[String],
+) -> Vec<AttributeDrift> {
+    attributes
+        .iter()
+        .filter_map(|(property, found)| {
+            let declared = component.get_property_as_string(property)?;
+            let declared = match declared.contains("${") {
+                true => {
+                    let value = serde_yaml::Value::String(declared);
+                    interpolate_outputs(&value, outputs)
+                        .ok()?
+                        .as_str()
+                        .map(|v| v.to_string())?
+                }
+                false => declared,
+            };
+            if Some(&declared) == found.as_ref() {
+                return None;
+            }
+            Some(AttributeDrift {
+                address: component.key(),
+                id: id.to_string(),
+                attribute: property.to_string(),
+                source: DriftSource::Config,
+                expected: declared,
+                actual: found.clone().unwrap_or_else(|| "-".to_string()),
+                sensitive: sensitive.iter().any(|s| s == property),
+            })
+        })
+        .collect()
+}
+
+fn show(value: Option<&serde_json::Value>) -> String {
+    match value {
+        None | Some(serde_json::Value::Null) => "-".to_string(),
+        Some(value) => display_value(value),
+    }
+}
+
+/// One row per drifted attribute, a sensitive one only says that the values differ
+fn render_drift(drifted: &[AttributeDrift]) -> String {
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(vec![
+        "Resource",
+        "Id",
+        "Attribute",
+        "Source",
+        "Expected",
+        "Actual",
+    ]);
+    for drift in drifted {
+        let (expected, actual) = match drift.sensitive {
+            true => ("values differ".to_string(), String::new()),
+            false => (redact(&drift.expected), redact(&drift.actual)),
+        };
+        table.add_row(vec![
+            drift.address.clone(),
+            drift.id.clone(),
+            drift.attribute.clone(),
+            drift.source.to_string(),
+            expected,
+            actual,
+        ]);
+    }
+    table.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::ComponentMode;
+    use serde_json::json;
+
+    fn instance(recorded: serde_json::Value) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: "web".to_string(),
+            component_type: "EC2Instance".to_string(),
+            provider: "aws".to_string(),
+            instances: vec![recorded],
+            created_at: None,
+            properties_hash: None,
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    fn declared(yaml: &str) -> Component {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_state_drift_skips_computed_outputs() {
+        let component = instance(json!({
+            "id": "i-1",
+            "public_ip": "1.1.1.1",
+            "subnet_id": "subnet-1",
+            "instance_ids": ["i-1"],
+        }));
+        let live = json!({ "id": "i-1", "public_ip": "2.2.2.2", "subnet_id": "subnet-2" });
+        let drifted = state_drift(&component, &component.instances[0], &live, &[]);
+        assert_eq!(drifted.len(), 1, "{:?}", drifted);
+        assert_eq!(drifted[0].attribute, "subnet_id");
+        assert_eq!(
+            (drifted[0].expected.as_str(), drifted[0].actual.as_str()),
+            ("subnet-1", "subnet-2")
+        );
+        assert_eq!(drifted[0].source, DriftSource::State);
+    }
+
+    #[test]
+    fn test_state_drift_compares_each_instance_to_its_own_record() {
+        let mut component = instance(json!({ "id": "i-1", "subnet_id": "subnet-1" }));
+        component
+            .instances
+            .push(json!({ "id": "i-2", "subnet_id": "subnet-2" }));
+        let live = json!({ "id": "i-2", "subnet_id": "subnet-3" });
+        let drifted = state_drift(&component, &component.instances[1], &live, &[]);
+        assert_eq!(drifted.len(), 1, "{:?}", drifted);
+        assert_eq!(drifted[0].id, "i-2");
+        assert_eq!(
+            (drifted[0].expected.as_str(), drifted[0].actual.as_str()),
+            ("subnet-2", "subnet-3")
+        );
+        let live = json!({ "id": "i-2", "subnet_id": "subnet-2" });
+        assert!(state_drift(&component, &component.instances[1], &live, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_config_drift_resolves_references_and_skips_unset_properties() {
+        let component = declared(
+            r#"
+type: EC2Instance
+name: web
+properties:
+  instance_type: t3.micro
+  subnet_id: '${Subnet.main.id}'
+"#,
+        );
+        let outputs = json!({ "Subnet.main": { "id": "subnet-1" } });
+        let attributes = vec![
+            ("instance_type", Some("t3.large".to_string())),
+            ("subnet_id", Some("subnet-1".to_string())),
+            ("key_name", Some("ops".to_string())),
+        ];
+        let drifted = config_drift(
+            &component,
+            "i-1",
+            &attributes,
+            outputs.as_object().unwrap(),
+            &[],
+        );
+        assert_eq!(drifted.len(), 1, "{:?}", drifted);
+        assert_eq!(drifted[0].attribute, "instance_type");
+        assert_eq!(
+            (drifted[0].expected.as_str(), drifted[0].actual.as_str()),
+            ("t3.micro", "t3.large")
+        );
+    }
+
+    #[test]
+    fn test_sensitive_drift_hides_the_values() {
+        let drift = AttributeDrift {
+            address: "EC2Instance.web".to_string(),
+            id: "i-1".to_string(),
+            attribute: "user_data".to_string(),
+            source: DriftSource::Config,
+            expected: "echo secret".to_string(),
+            actual: "echo other".to_string(),
+            sensitive: true,
+        };
+        let rendered = render_drift(&[drift]);
+        assert!(rendered.contains("values differ"), "{}", rendered);
+        assert!(!rendered.contains("secret"), "{}", rendered);
+        assert!(!rendered.contains("other"), "{}", rendered);
+    }
+}
@@ -0,0 +1,28 @@
+use crate::utils::diagnostics::{CATALOGUE, lookup};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    /// Diagnostic code to explain, e.g. LETUS2001
+    pub code: String,
+}
+
+pub fn execute(config: &Config) -> Result<(), String> {
+    match lookup(&config.code) {
+        Some(entry) => {
+            println!("{} {}\n", entry.code, entry.name);
+            println!("{}\n", entry.description);
+            println!("Remediation: {}", entry.remediation);
+            Ok(())
+        }
+        None => Err(format!(
+            "Unknown diagnostic code '{}'. Known codes: {}",
+            config.code,
+            CATALOGUE
+                .iter()
+                .map(|c| c.code)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
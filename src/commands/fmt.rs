@@ -0,0 +1,35 @@
+use crate::{
+    models::{ConfigFormat, InfraConfig},
+    utils::read_config_source,
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: String,
+    /// Output format, defaults to the format of the input file
+    #[clap(long = "to", value_enum)]
+    pub to: Option<ConfigFormat>,
+}
+
+/// Prints the config normalised in the requested format, converting between YAML and JSON
+pub fn execute(config: &Config) -> Result<(), String> {
+    let file_path = &config.options.file_path;
+    let content = read_config_source(file_path).map_err(|err| err.to_string())?;
+    let input_format = ConfigFormat::detect(file_path, &content);
+    let infra_config = InfraConfig::parse(&content, input_format).map_err(|err| err.to_string())?;
+
+    let output = match config.options.to.unwrap_or(input_format) {
+        ConfigFormat::Yaml => infra_config.to_yaml().map_err(|err| err.to_string())?,
+        ConfigFormat::Json => infra_config.to_json().map_err(|err| err.to_string())?,
+    };
+    println!("{}", output.trim_end());
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use crate::{
+    aws::{
+        ec2::{
+            ec2_instance::{EC2Error, EC2Instance},
+            elastic_ip::ElasticIp,
+            key_pair::KeyPair,
+            security_group::SecurityGroup,
+            subnet::Subnet,
+            vpc::Vpc,
+        },
+        error::AwsError,
+        iam::{instance_profile::InstanceProfile, role::IamRole},
+        s3::bucket::S3Bucket,
+        sdk_config::load_sdk_config,
+    },
+    commands::{
+        apply::{
+            bucket_outputs, elastic_ip_outputs, instance_outputs, instance_profile_outputs,
+            key_pair_outputs, role_outputs, subnet_outputs, vpc_outputs,
+        },
+        refresh::not_found_as_none,
+    },
+    models::{
+        Component, ConfigFormat, InfraConfig,
+        state::{ComponentMode, ComponentState, Datatype, Output, OutputType, State},
+    },
+    state::{self as state_file, STATE_VERSION, backend::StateBackend},
+    utils::{
+        anchor_config_paths,
+        diagnostics::Diagnostic,
+        interpolation::resolve_config,
+        read_config_source,
+        redact::{self, redact},
+        schema, select_document, select_workspace,
+        templates::resolve_templates,
+        variables::{VariableOptions, bind_variables},
+    },
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Component the resource belongs to, as `<type>.<name>`
+    #[clap(long = "component")]
+    pub component: String,
+    /// Id of the existing resource, e.g. `i-0abc123`
+    #[clap(long = "id")]
+    pub id: String,
+    /// Workspace to import into, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State to import into, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Deployment the component belongs to, needed when `-f` holds several
+    #[clap(long = "deployment")]
+    pub deployment: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+    #[error("{component} is already in state as {id}, destroy or remove it before importing")]
+    AlreadyManaged { component: String, id: String },
+    #[error("{component_type} {id} does not exist in {region}")]
+    NotFound {
+        component_type: String,
+        id: String,
+        region: String,
+    },
+    #[error("Failed to read {id}: [{code}] {message}")]
+    Read {
+        id: String,
+        code: &'static str,
+        message: String,
+    },
+}
+
+/// Outputs of the live resource and the attributes its declared properties are compared to
+pub(crate) struct Found {
+    pub(crate) outputs: serde_json::Value,
+    pub(crate) attributes: Vec<(&'static str, Option<String>)>,
+}
+
+/**
+ * Record an existing resource in state as the resource of a component, so plans treat it as
+ * already created. The properties of the component are compared to the live resource and every
+ * difference is printed as a warning, the next apply brings what can be updated in line.
+ */
+pub async fn execute(config: &Config) -> Result<(), ImportError> {
+    let options = &config.options;
+    let (document, deployment) = select_document(&options.file_path, options.deployment.as_deref())
+        .map_err(ImportError::Config)?;
+    if deployment.is_some() && options.state_file.is_some() {
+        return Err(ImportError::Config(
+            "--state-file cannot be used with several deployments, each has its own state"
+                .to_string(),
+        ));
+    }
+    let file_path = &document.source;
+    let content =
+        read_config_source(file_path).map_err(|err| ImportError::FileRead(err.to_string()))?;
+    let mut config = InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| ImportError::Parse(err.to_string()))?;
+    resolve_templates(&mut config, file_path).map_err(ImportError::Config)?;
+    anchor_config_paths(&mut config, file_path);
+    bind_variables(&mut config, &options.variables)
+        .map_err(|err| ImportError::Config(format!("[{}] {}", err.code(), err)))?;
+    redact::install(&config.redact, schema::sensitive_values(&config.components))
+        .map_err(|err| ImportError::Config(err.to_string()))?;
+    let workspace = select_workspace(options.workspace.as_deref()).map_err(ImportError::Config)?;
+    let config = resolve_config(&config, &workspace).map_err(|err| {
+        ImportError::Config(format!("Failed to interpolate configuration: {}", err))
+    })?;
+    let component =
+        find_component(&config.components, &options.component).map_err(ImportError::Config)?;
+
+    let mut backend = state_file::backend::open(
+        config.backend.as_ref(),
+        options.state_file.as_deref(),
+        &workspace,
+        deployment.as_deref(),
+        Some(&config.region),
+    )
+    .await?;
+    backend.lock("import").await?;
+    let outcome: Result<(), ImportError> = async {
+        let previous = backend.load().await?;
+        if let Some(existing) = previous.as_ref().and_then(|state| {
+            state
+                .components
+                .iter()
+                .find(|c| c.component_type == component.component_type && c.name == component.name)
+        }) {
+            return Err(ImportError::AlreadyManaged {
+                component: component.key(),
+                id: existing
+                    .instances
+                    .first()
+                    .and_then(|i| i.get("id"))
+                    .and_then(|id| id.as_str())
+                    .unwrap_or("-")
+                    .to_string(),
+            });
+        }
+
+        let found = read_resource(&component.component_type, &options.id, &config.region)
+            .await
+            .map_err(|err| ImportError::Read {
+                id: options.id.clone(),
+                code: err.code(),
+                message: redact(&err.to_string()),
+            })?
+            .ok_or_else(|| ImportError::NotFound {
+                component_type: component.component_type.clone(),
+                id: options.id.clone(),
+                region: config.region.clone(),
+            })?;
+        for mismatch in mismatches(component, &found.attributes) {
+            eprintln!("warning: {}: {}", component.key(), redact(&mismatch));
+        }
+
+        let imported = ComponentState {
+            mode: ComponentMode::Imported,
+            name: component.name.clone(),
+            component_type: component.component_type.clone(),
+            provider: "aws".to_string(),
+            instances: vec![found.outputs.clone()],
+            created_at: None,
+            // No hash, the next plan shows the component as an update to the declared properties
+            properties_hash: None,
+            replacement_hash: None,
+            retained: false,
+        };
+        let mut outputs = previous
+            .as_ref()
+            .and_then(|p| p.outputs.value.as_object().cloned())
+            .unwrap_or_default();
+        outputs.insert(component.key(), found.outputs);
+        let state = State {
+            version: STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: config.metadata.name.clone(),
+            outputs: Output {
+                value: serde_json::Value::Object(outputs),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                // Only apply resolves the outputs block, the last values stay until then
+                declared: previous
+                    .as_ref()
+                    .map(|p| p.outputs.declared.clone())
+                    .unwrap_or_default(),
+            },
+            components: state_file::merge_components(previous.as_ref(), vec![imported]),
+            launches: previous
+                .as_ref()
+                .map(|p| p.launches.clone())
+                .unwrap_or_default(),
+        };
+        let state = backend.save(state).await?;
+        println!(
+            "Imported {} as {}, state written to {} (serial {})",
+            options.id,
+            component.key(),
+            backend.describe(),
+            state.serial
+        );
+        Ok(())
+    }
+    .await;
+    state_file::backend::unlock_after(&mut backend, outcome).await
+}
+
+fn find_component<'a>(components: &'a [Component], key: &str) -> Result<&'a Component, String> {
+    if let Some(component) = components.iter().find(|c| c.key() == key) {
+        return Ok(component);
+    }
+    let keys: Vec<String> = components.iter().map(|c| c.key()).collect();
+    let suggestions = schema::suggest(key, keys.iter().map(|k| k.as_str()));
+    match suggestions.first() {
+        Some(suggestion) => Err(format!(
+            "Unknown component '{}', did you mean '{}'?",
+            key, suggestion
+        )),
+        None => Err(format!(
+            "Unknown component '{}', expected <type>.<name> of a component in the config",
+            key
+        )),
+    }
+}
+
+/**
+ * Declared properties that differ from the live resource, as `<property>: declared <x>, found <y>`.
+ * Properties that are not set, or still reference other components, are not compared.
+ */
+fn mismatches(component: &Component, attributes: &[(&str, Option<String>)]) -> Vec<String> {
+    attributes
+        .iter()
+        .filter_map(|(property, found)| {
+            let declared = component.get_property_as_string(property)?;
+            if declared.contains("${") || Some(&declared) == found.as_ref() {
+                return None;
+            }
+            Some(format!(
+                "{}: declared {}, found {}",
+                property,
+                declared,
+                found.as_deref().unwrap_or("-")
+            ))
+        })
+        .collect()
+}
+
+/// Describe the resource, None when it does not exist or, for an instance, is terminated
+pub(crate) async fn read_resource(
+    component_type: &str,
+    id: &str,
+    region: &str,
+) -> Result<Option<Found>, AwsError> {
+    let config = load_sdk_config(region)
+        .await
+        .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+    let owned = |value: Option<&str>| value.map(|v| v.to_string());
+    let found = match component_type {
+        "EC2Instance" => match EC2Instance::from_config(&config)
+            .describe_instance(id)
+            .await
+        {
+            Ok(instance)
+                if instance.state().and_then(|s| s.name())
+                    == Some(&aws_sdk_ec2::types::InstanceStateName::Terminated) =>
+            {
+                None
+            }
+            Ok(instance) => Some(Found {
+                outputs: instance_outputs(&instance, region),
+                attributes: vec![
+                    ("ami", owned(instance.image_id())),
+                    ("image_id", owned(instance.image_id())),
+                    (
+                        "instance_type",
+                        owned(instance.instance_type().map(|t| t.as_str())),
+                    ),
+                    ("subnet_id", owned(instance.subnet_id())),
+                    ("key_name", owned(instance.key_name())),
+                ],
+            }),
+            Err(EC2Error::InstanceNotFound) => None,
+            Err(err) => return Err(err.into()),
+        },
+        "VPC" => {
+            not_found_as_none(Vpc::from_config(&config).describe_vpc(id).await)?.map(|vpc| Found {
+                outputs: vpc_outputs(&vpc, region),
+                attributes: vec![("cidr", owned(vpc.cidr_block()))],
+            })
+        }
+        "Subnet" => not_found_as_none(Subnet::from_config(&config).describe_subnet(id).await)?.map(
+            |subnet| Found {
+                outputs: subnet_outputs(&subnet, region),
+                attributes: vec![
+                    ("cidr", owned(subnet.cidr_block())),
+                    ("vpc_id", owned(subnet.vpc_id())),
+                    ("availability_zone", owned(subnet.availability_zone())),
+                ],
+            },
+        ),
+        "ElasticIP" => not_found_as_none(
+            ElasticIp::from_config(&config).describe_address(id).await,
+        )?
+        .map(|address| Found {
+            outputs: elastic_ip_outputs(&address, region),
+            attributes: vec![("associate_with", owned(address.instance_id()))],
+        }),
+        "S3Bucket" => not_found_as_none(S3Bucket::from_config(&config).describe_bucket(id).await)?
+            .map(|bucket_region| Found {
+                outputs: bucket_outputs(id, &bucket_region),
+                attributes: vec![("bucket", Some(id.to_string()))],
+            }),
+        "KeyPair" => not_found_as_none(KeyPair::from_config(&config).describe_key_pair(id).await)?
+            .map(|key_pair| Found {
+                outputs: key_pair_outputs(&key_pair, region),
+                attributes: vec![
+                    ("key_name", owned(key_pair.key_name())),
+                    ("key_type", owned(key_pair.key_type().map(|t| t.as_str()))),
+                ],
+            }),
+        "IAMRole" => {
+            not_found_as_none(IamRole::from_config(&config).describe_role(id).await)?.map(|role| {
+                Found {
+                    outputs: role_outputs(&role, region),
+                    attributes: vec![
+                        ("role_name", Some(role.role_name().to_string())),
+                        ("description", owned(role.description())),
+                    ],
+                }
+            })
+        }
+        "InstanceProfile" => not_found_as_none(
+            InstanceProfile::from_config(&config)
+                .describe_instance_profile(id)
+                .await,
+        )?
+        .map(|profile| Found {
+            outputs: instance_profile_outputs(&profile, region),
+            attributes: vec![
+                (
+                    "instance_profile_name",
+                    Some(profile.instance_profile_name().to_string()),
+                ),
+                (
+                    "role",
+                    owned(profile.roles().first().map(|r| r.role_name())),
+                ),
+            ],
+        }),
+        "SecurityGroup" => not_found_as_none(
+            SecurityGroup::from_config(&config)
+                .describe_security_group(id)
+                .await,
+        )?
+        .map(|group| Found {
+            outputs: serde_json::json!({
+                "id": group.group_id(),
+                "region": region,
+                "vpc_id": group.vpc_id(),
+            }),
+            attributes: vec![
+                ("group_name", owned(group.group_name())),
+                ("description", owned(group.description())),
+                ("vpc_id", owned(group.vpc_id())),
+            ],
+        }),
+        other => {
+            return Err(AwsError::OptionsError(format!(
+                "unsupported component type: {}",
+                other
+            )));
+        }
+    };
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components() -> Vec<Component> {
+        serde_yaml::from_str(
+            "- type: EC2Instance\n  name: web\n  properties: {instance_type: t2.micro, ami: ami-1, subnet_id: '${Subnet.public.id}'}\n- type: VPC\n  name: main\n  properties: {cidr: 10.0.0.0/16}\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_find_component() {
+        let components = components();
+        assert_eq!(
+            find_component(&components, "VPC.main").unwrap().name,
+            "main"
+        );
+        let err = find_component(&components, "EC2Instance.wbe").unwrap_err();
+        assert!(err.contains("did you mean 'EC2Instance.web'"), "{}", err);
+    }
+
+    #[test]
+    fn test_mismatches_skip_unset_and_referenced_properties() {
+        let components = components();
+        let found = vec![
+            ("ami", Some("ami-2".to_string())),
+            ("instance_type", Some("t2.micro".to_string())),
+            ("subnet_id", Some("subnet-1".to_string())),
+            ("key_name", Some("ops".to_string())),
+        ];
+        assert_eq!(
+            mismatches(&components[0], &found),
+            vec!["ami: declared ami-1, found ami-2"]
+        );
+    }
+}
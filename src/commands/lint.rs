@@ -0,0 +1,62 @@
+use crate::{
+    commands::validate::{ValidationError, validate_file},
+    models::{ConfigFormat, InfraConfig},
+    utils::{
+        current_workspace,
+        diagnostics::Diagnostic,
+        interpolation::resolve_config,
+        lint::lint_config,
+        read_config_source,
+        redact::redact,
+        templates::resolve_templates,
+        variables::{VariableOptions, bind_variables},
+    },
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Exit with a non-zero status when any warning is reported
+    #[clap(long = "deny-warnings")]
+    pub deny_warnings: bool,
+}
+
+/// Runs strict validation plus the hygiene analyses, returns the number of warnings found
+pub fn execute(config: &Config) -> Result<usize, ValidationError> {
+    let file_path = &config.options.file_path;
+    validate_file(file_path, &config.options.variables)?;
+
+    let content = read_config_source(file_path)
+        .map_err(|err| ValidationError::FileReadError(err.to_string()))?;
+    let mut infra_config = InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| ValidationError::YamlParseError(err.to_string()))?;
+    resolve_templates(&mut infra_config, file_path)
+        .map_err(ValidationError::InfraConfigValidationError)?;
+    bind_variables(&mut infra_config, &config.options.variables)
+        .map_err(|err| ValidationError::Variables(vec![err]))?;
+    let resolved = resolve_config(&infra_config, &current_workspace())
+        .map_err(|err| ValidationError::InfraConfigValidationError(err.to_string()))?;
+
+    let warnings = lint_config(&infra_config, &resolved);
+    for warning in &warnings {
+        println!(
+            "warning[{}]: {}",
+            warning.code(),
+            redact(&warning.to_string())
+        );
+    }
+    if warnings.is_empty() {
+        println!("No lint warnings found");
+    }
+    Ok(warnings.len())
+}
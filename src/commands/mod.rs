@@ -1,4 +1,16 @@
 pub mod apply;
+pub mod describe;
 pub mod destroy;
+pub mod drift;
+pub mod explain;
+pub mod fmt;
+pub mod import;
+pub mod lint;
+pub mod output;
 pub mod plan;
+pub mod providers;
+pub mod refresh;
+pub mod selftest;
+pub mod state;
 pub mod validate;
+pub mod workspace;
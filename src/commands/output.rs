@@ -0,0 +1,449 @@
+use std::{collections::BTreeMap, io::Write};
+
+use comfy_table::Table;
+
+use crate::{
+    commands::refresh,
+    models::state::DeclaredOutput,
+    state::{self as state_file, backend::StateBackend},
+    utils::{constants::SENSITIVE_VALUE, select_workspace, variables::VariableOptions},
+};
+
+/// Formats for handing every output to another tool, as environment variables
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// `LETUS_<NAME>=value` per line
+    Dotenv,
+    /// `export LETUS_<NAME>='value'` per line, for `eval`
+    Shell,
+    /// Step outputs, appended to $GITHUB_OUTPUT when set
+    GithubActions,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    /// Output to print on its own, strings without quotes. Every output when left out
+    pub name: Option<String>,
+    /// Print JSON instead, sensitive values included
+    #[clap(long = "json")]
+    pub json: bool,
+    /// Print every output as environment variables, sensitive ones left out
+    #[clap(long = "format", value_enum, conflicts_with_all = ["name", "json"])]
+    pub format: Option<ExportFormat>,
+    /// Include the sensitive outputs in --format
+    #[clap(long = "show-sensitive", requires = "format")]
+    pub show_sensitive: bool,
+    /// Workspace to read, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State to read, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Deployment to read, when `-f` applied several. Its state is
+    /// .letusinfra/<workspace>/<deployment>/state.json
+    #[clap(long = "deployment", conflicts_with = "state_file")]
+    pub deployment: Option<String>,
+    /// Read the components the outputs come from back from AWS first, like refresh --target
+    #[clap(long = "refresh", requires = "file_path")]
+    pub refresh: bool,
+    /// Config the state was applied from, for --refresh
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: Option<String>,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum OutputError {
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+    #[error("No state at {0}, run apply first")]
+    NoState(String),
+    #[error("Output '{name}' not found, the state has: {available}")]
+    UnknownOutput { name: String, available: String },
+    #[error("Outputs {first} and {second} would both be exported as {key}, rename one")]
+    DuplicateKey {
+        key: String,
+        first: String,
+        second: String,
+    },
+    #[error("Failed to write {path}: {message}")]
+    Write { path: String, message: String },
+    #[error("Refresh failed: {0}")]
+    Refresh(#[from] refresh::RefreshError),
+}
+
+/**
+ * Print the outputs of the last apply, from the state alone. Without a config to read a
+ * `backend:` block from, the state is the local one unless LETUS_BACKEND_CONFIG names a backend.
+ * `--refresh -f` reads the components behind the outputs from AWS first, through that config.
+ */
+pub async fn execute(config: &Config) -> Result<(), OutputError> {
+    let workspace = select_workspace(config.workspace.as_deref()).map_err(OutputError::Config)?;
+    let mut refreshed = None;
+    if let Some(file_path) = config.file_path.as_ref().filter(|_| config.refresh) {
+        let options = refresh::Options {
+            file_path: file_path.clone(),
+            variables: config.variables.clone(),
+            workspace: Some(workspace.clone()),
+            state_file: config.state_file.clone(),
+            targets: Vec::new(),
+            deployment: config.deployment.clone(),
+            report_on_stderr: true,
+        };
+        let names: Vec<String> = config.name.iter().cloned().collect();
+        refreshed = Some(refresh::refresh_outputs(&options, &names).await?);
+    }
+    // After --refresh, the state the refresh wrote to, which may be the `backend:` of the config
+    let backend = state_file::backend::open(
+        refreshed.as_ref().and_then(|c| c.backend.as_ref()),
+        config.state_file.as_deref(),
+        &workspace,
+        config.deployment.as_deref(),
+        refreshed.as_ref().map(|c| c.region.as_str()),
+    )
+    .await?;
+    let Some(state) = backend.load().await? else {
+        return Err(OutputError::NoState(backend.describe()));
+    };
+    let declared = state.outputs.declared;
+
+    if let Some(format) = config.format {
+        if config.show_sensitive && declared.values().any(|o| o.sensitive) {
+            eprintln!("Warning: --show-sensitive prints the values of sensitive outputs");
+        }
+        let exported = render_export(&declared, format, config.show_sensitive)?;
+        if format == ExportFormat::GithubActions && config.show_sensitive {
+            print!("{}", mask_commands(&declared));
+        }
+        return match std::env::var("GITHUB_OUTPUT") {
+            Ok(path) if format == ExportFormat::GithubActions && !path.is_empty() => {
+                append_to(&path, &exported)
+            }
+            _ => {
+                print!("{}", exported);
+                Ok(())
+            }
+        };
+    }
+
+    let Some(name) = &config.name else {
+        if config.json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&declared).unwrap_or_default()
+            );
+        } else if declared.is_empty() {
+            println!("No outputs, declare them in the outputs block of the config");
+        } else {
+            println!("{}", render_outputs(&declared));
+        }
+        return Ok(());
+    };
+    let Some(output) = declared.get(name) else {
+        return Err(OutputError::UnknownOutput {
+            name: name.clone(),
+            available: match declared.is_empty() {
+                true => "none".to_string(),
+                false => declared.keys().cloned().collect::<Vec<_>>().join(", "),
+            },
+        });
+    };
+    // Asked for by name, a sensitive value is printed like any other
+    if config.json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output.value).unwrap_or_default()
+        );
+    } else {
+        println!("{}", display_value(&output.value));
+    }
+    Ok(())
+}
+
+/// Strings without their quotes, anything else as compact JSON
+pub fn display_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// Upper snake case name of the environment variable of output `name`, `webIp` is `LETUS_WEB_IP`
+pub fn export_key(name: &str) -> String {
+    let mut key = String::from("LETUS_");
+    let mut previous: Option<char> = None;
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase()
+                && previous.is_some_and(|p| p.is_ascii_lowercase() || p.is_ascii_digit())
+            {
+                key.push('_');
+            }
+            key.push(c.to_ascii_uppercase());
+        } else if !key.ends_with('_') {
+            key.push('_');
+        }
+        previous = Some(c);
+    }
+    key
+}
+
+/**
+ * Every output in `format`, one variable each. Sensitive outputs are left out unless
+ * `show_sensitive`.
+ */
+pub fn render_export(
+    outputs: &BTreeMap<String, DeclaredOutput>,
+    format: ExportFormat,
+    show_sensitive: bool,
+) -> Result<String, OutputError> {
+    let mut keys: BTreeMap<String, &str> = BTreeMap::new();
+    let mut exported = String::new();
+    for (name, output) in outputs {
+        if output.sensitive && !show_sensitive {
+            continue;
+        }
+        let key = export_key(name);
+        if let Some(first) = keys.insert(key.clone(), name) {
+            return Err(OutputError::DuplicateKey {
+                key,
+                first: first.to_string(),
+                second: name.clone(),
+            });
+        }
+        let value = display_value(&output.value);
+        let line = match format {
+            ExportFormat::Dotenv => format!("{}={}\n", key, dotenv_quote(&value)),
+            ExportFormat::Shell => format!("export {}={}\n", key, shell_quote(&value)),
+            ExportFormat::GithubActions => github_output(&key, &value),
+        };
+        exported.push_str(&line);
+    }
+    Ok(exported)
+}
+
+/// Bare when nothing in it needs quoting, double quoted otherwise with newlines as `\n`
+fn dotenv_quote(value: &str) -> String {
+    let bare = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-.,:/@+%".contains(c));
+    if bare {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '$' => quoted.push_str("\\$"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Single quoted, a quote in the value closes the quotes, adds an escaped one and reopens them
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// `KEY=value`, a multiline value as a `KEY<<DELIMITER` block with a delimiter not in the value
+fn github_output(key: &str, value: &str) -> String {
+    if !value.contains(['\n', '\r']) {
+        return format!("{}={}\n", key, value);
+    }
+    let mut delimiter = String::from("LETUS_EOF");
+    while value.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+    format!("{}<<{}\n{}\n{}\n", key, delimiter, value, delimiter)
+}
+
+/// `::add-mask::` for every line of the sensitive values, the runner hides them in the job log
+fn mask_commands(outputs: &BTreeMap<String, DeclaredOutput>) -> String {
+    outputs
+        .values()
+        .filter(|o| o.sensitive)
+        .flat_map(|o| {
+            display_value(&o.value)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| format!("::add-mask::{}\n", line))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Append to the file GitHub Actions reads step outputs from
+fn append_to(path: &str, content: &str) -> Result<(), OutputError> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(content.as_bytes()))
+        .map_err(|err| OutputError::Write {
+            path: path.to_string(),
+            message: err.to_string(),
+        })
+}
+
+/// One output per row, sensitive values are masked
+pub fn render_outputs(outputs: &BTreeMap<String, DeclaredOutput>) -> String {
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(vec!["Output", "Value", "Description"]);
+    for (name, output) in outputs {
+        let value = match output.sensitive {
+            true => SENSITIVE_VALUE.to_string(),
+            false => display_value(&output.value),
+        };
+        table.add_row(vec![
+            name.as_str(),
+            value.as_str(),
+            output.description.as_deref().unwrap_or(""),
+        ]);
+    }
+    table.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sensitive_outputs_are_masked() {
+        let outputs = BTreeMap::from([
+            (
+                "web_ip".to_string(),
+                DeclaredOutput {
+                    value: json!("203.0.113.10"),
+                    sensitive: false,
+                    description: Some("Public address of web".to_string()),
+                },
+            ),
+            (
+                "db_password".to_string(),
+                DeclaredOutput {
+                    value: json!("hunter2"),
+                    sensitive: true,
+                    description: None,
+                },
+            ),
+            (
+                "worker_ids".to_string(),
+                DeclaredOutput {
+                    value: json!(["i-1", "i-2"]),
+                    sensitive: false,
+                    description: None,
+                },
+            ),
+        ]);
+        let rendered = render_outputs(&outputs);
+        assert!(rendered.contains("203.0.113.10"), "{}", rendered);
+        assert!(rendered.contains("Public address of web"), "{}", rendered);
+        assert!(rendered.contains(r#"["i-1","i-2"]"#), "{}", rendered);
+        assert!(rendered.contains(SENSITIVE_VALUE), "{}", rendered);
+        assert!(!rendered.contains("hunter2"), "{}", rendered);
+    }
+
+    fn declared(outputs: &[(&str, serde_json::Value, bool)]) -> BTreeMap<String, DeclaredOutput> {
+        outputs
+            .iter()
+            .map(|(name, value, sensitive)| {
+                let output = DeclaredOutput {
+                    value: value.clone(),
+                    sensitive: *sensitive,
+                    description: None,
+                };
+                (name.to_string(), output)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_export_keys_are_upper_snake_case() {
+        assert_eq!(export_key("web_ip"), "LETUS_WEB_IP");
+        assert_eq!(export_key("webIp"), "LETUS_WEB_IP");
+        assert_eq!(export_key("web-ip.v4"), "LETUS_WEB_IP_V4");
+        assert_eq!(export_key("db2Host"), "LETUS_DB2_HOST");
+
+        let outputs = declared(&[("web_ip", json!("a"), false), ("webIp", json!("b"), false)]);
+        let err = render_export(&outputs, ExportFormat::Dotenv, false).unwrap_err();
+        assert!(matches!(err, OutputError::DuplicateKey { .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_dotenv_format() {
+        let outputs = declared(&[
+            ("web_ip", json!("203.0.113.10"), false),
+            ("motd", json!("hello \"world\"\nsecond $line"), false),
+            ("worker_ids", json!(["i-1", "i-2"]), false),
+            ("db_password", json!("hunter2"), true),
+        ]);
+        let exported = render_export(&outputs, ExportFormat::Dotenv, false).unwrap();
+        assert_eq!(
+            exported,
+            concat!(
+                "LETUS_MOTD=\"hello \\\"world\\\"\\nsecond \\$line\"\n",
+                "LETUS_WEB_IP=203.0.113.10\n",
+                "LETUS_WORKER_IDS=\"[\\\"i-1\\\",\\\"i-2\\\"]\"\n",
+            )
+        );
+
+        let exported = render_export(&outputs, ExportFormat::Dotenv, true).unwrap();
+        assert!(
+            exported.starts_with("LETUS_DB_PASSWORD=hunter2\n"),
+            "{}",
+            exported
+        );
+    }
+
+    #[test]
+    fn test_shell_format() {
+        let outputs = declared(&[
+            ("motd", json!("it's\nsecond line"), false),
+            ("web_ip", json!("203.0.113.10"), false),
+            ("db_password", json!("hunter2"), true),
+        ]);
+        let exported = render_export(&outputs, ExportFormat::Shell, false).unwrap();
+        assert_eq!(
+            exported,
+            "export LETUS_MOTD='it'\\''s\nsecond line'\nexport LETUS_WEB_IP='203.0.113.10'\n"
+        );
+        let exported = render_export(&outputs, ExportFormat::Shell, true).unwrap();
+        assert!(
+            exported.contains("export LETUS_DB_PASSWORD='hunter2'\n"),
+            "{}",
+            exported
+        );
+    }
+
+    #[test]
+    fn test_github_actions_format() {
+        let outputs = declared(&[
+            ("motd", json!("first\nLETUS_EOF\nlast"), false),
+            ("web_ip", json!("203.0.113.10"), false),
+            ("db_password", json!("hunter2\nline"), true),
+        ]);
+        let exported = render_export(&outputs, ExportFormat::GithubActions, false).unwrap();
+        assert_eq!(
+            exported,
+            concat!(
+                "LETUS_MOTD<<LETUS_EOF_\nfirst\nLETUS_EOF\nlast\nLETUS_EOF_\n",
+                "LETUS_WEB_IP=203.0.113.10\n",
+            )
+        );
+        assert_eq!(
+            mask_commands(&outputs),
+            "::add-mask::hunter2\n::add-mask::line\n"
+        );
+    }
+}
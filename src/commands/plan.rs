@@ -1,9 +1,38 @@
+use serde::Serialize;
+
 use crate::{
-    commands::validate::validate_file,
-    models::{InfraConfig, PlanError},
-    utils::{OperationType, PlanPreviewDeployment, plan_components},
+    aws::{
+        ec2::{
+            ec2_instance::EC2Instance,
+            preflight::{Preflight, PreflightIssue, Severity},
+        },
+        sdk_config::load_sdk_config,
+    },
+    commands::validate::{validate_file, validate_infra_config},
+    models::{ConfigFormat, InfraConfig, Plan, PlanError},
+    state::{self, backend::StateBackend},
+    utils::{
+        ComponentPreview, OperationType, PlanPreviewDeployment,
+        ami_lookup::resolve_ami_lookups,
+        anchor_config_paths, config_documents,
+        diagnostics::Diagnostic,
+        diff_against_state, exclude_untargeted,
+        interpolation::resolve_config,
+        lint::lint_config,
+        plan_components, read_config_source,
+        redact::{self, redact},
+        render::{RenderOptions, render_plan_preview},
+        schema, select_workspace, target_closure,
+        templates::resolve_templates,
+        variables::{VariableOptions, bind_variables},
+    },
 };
-use comfy_table::{Attribute, Cell, Color, Table};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Json,
+}
 
 #[derive(clap::Args, Debug)]
 #[command(version, about, long_about = None)]
@@ -12,90 +41,666 @@ pub struct Config {
     pub options: Options,
 }
 
-#[derive(clap::Args, Debug)]
+#[derive(clap::Args, Debug, Clone)]
 pub struct Options {
     #[clap(short = 'f', long = "filepath")]
     pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Run checks that call AWS (AMI compatibility, AZ offerings), off by default so plans work offline
+    #[clap(long = "preflight")]
+    pub preflight: bool,
+    /// Output width in columns, defaults to LETUS_WIDTH or the terminal width
+    #[clap(long = "width")]
+    pub width: Option<usize>,
+    /// Workspace to plan, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State of the last apply, components in it are planned as updates or deletes.
+    /// Defaults to the state of the workspace, .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Only plan these components and what they depend on, `<type>.<name>`, can be repeated
+    #[clap(long = "target")]
+    pub targets: Vec<String>,
+    /// `json` prints a single machine readable document on stdout instead of the table
+    #[clap(long = "output", value_enum, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+    /// Deployment of a `-f` holding several, each has its own state in the backend
+    #[clap(skip)]
+    pub deployment: Option<String>,
+}
+
+/// Version of the `--output json` document, bumped on any incompatible change
+pub const PLAN_FORMAT_VERSION: &str = "1";
+
+#[derive(Debug, Serialize)]
+pub struct PlanDocument {
+    pub format_version: &'static str,
+    pub workspace: String,
+    pub plan_hash: String,
+    pub deployment: DeploymentSummary,
+    pub summary: OperationSummary,
+    pub components: Vec<PlannedComponent>, // In file order
+    pub preflight: bool,                   // False when the plan was built without calling AWS
+    pub diagnostics: Vec<PlanDiagnostic>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeploymentSummary {
+    #[serde(rename = "type")]
+    pub deployment_type: String,
+    pub name: String,
+    pub region: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct OperationSummary {
+    pub create: usize,
+    pub update: usize,
+    pub delete: usize,
+    pub no_op: usize,
+    pub excluded: usize, // Left out by --target, not counted as any operation
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedComponent {
+    #[serde(flatten)]
+    pub preview: ComponentPreview,
+    pub properties: serde_json::Value, // Resolved properties, null for components only in state
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlanDiagnostic {
+    pub severity: &'static str,
+    pub code: Option<&'static str>,
+    pub component: Option<String>,
+    pub message: String,
+}
+
+/// The plan as a JSON document, `resolved` gives the properties of the planned components
+fn plan_document(
+    plan: &Plan,
+    preview: &PlanPreviewDeployment,
+    resolved: Option<&InfraConfig>,
+    preflight: bool,
+    diagnostics: Vec<PlanDiagnostic>,
+) -> PlanDocument {
+    let mut summary = OperationSummary::default();
+    let components = preview
+        .components
+        .iter()
+        .map(|component| {
+            if component.excluded {
+                summary.excluded += 1;
+            } else {
+                match component.operation_type {
+                    OperationType::Create => summary.create += 1,
+                    OperationType::Update => summary.update += 1,
+                    OperationType::Delete => summary.delete += 1,
+                    OperationType::NoOp => summary.no_op += 1,
+                }
+            }
+            let properties = resolved
+                .and_then(|config| {
+                    config.components.iter().find(|c| {
+                        c.component_type == component.component_type && c.name == component.name
+                    })
+                })
+                .filter(|_| component.operation_type != OperationType::Delete)
+                .map(|c| schema::mask_sensitive(c, &c.properties))
+                .and_then(|properties| serde_json::to_value(properties).ok())
+                .unwrap_or(serde_json::Value::Null);
+            PlannedComponent {
+                preview: component.clone(),
+                properties,
+            }
+        })
+        .collect();
+    PlanDocument {
+        format_version: PLAN_FORMAT_VERSION,
+        workspace: plan.workspace.clone(),
+        plan_hash: plan.hash.clone(),
+        deployment: DeploymentSummary {
+            deployment_type: preview.deployment_type.clone(),
+            name: preview.deployment_name.clone(),
+            region: resolved.map(|c| c.region.clone()).unwrap_or_default(),
+        },
+        summary,
+        components,
+        preflight,
+        diagnostics,
+    }
 }
 
-fn format_plan_preview(preview: &PlanPreviewDeployment) {
-    let mut table = Table::new();
-    table.load_preset(comfy_table::presets::NOTHING);
-    table.set_header(vec!["", "Type", "Name", "Plan", "Info"]);
-
-    // Add deployment row
-    table.add_row(vec![
-        Cell::new(""),
-        Cell::new(&preview.deployment_type),
-        Cell::new(&preview.deployment_name),
-        Cell::new(""),
-        Cell::new(""),
-    ]);
-
-    // Add component rows
-    for (index, component) in preview.components.iter().enumerate() {
-        let is_last = index == preview.components.len() - 1;
-        let prefix = if is_last { "└─" } else { "├─" };
-
-        let (operation_symbol, operation_text, operation_color) = match component.operation_type {
-            OperationType::Create => ("+", "create", Color::Green),
-            OperationType::Update => ("~", "update", Color::Yellow),
-            OperationType::Delete => ("-", "delete", Color::Red),
+/// Checks every EC2Instance against the live AMI and instance type metadata of the region
+async fn run_preflight(config: &InfraConfig) -> Vec<PreflightIssue> {
+    let sdk_config = match load_sdk_config(&config.region).await {
+        Ok(sdk_config) => sdk_config,
+        Err(err) => {
+            eprintln!("Skipping preflight: {}", redact(&err.to_string()));
+            return vec![];
+        }
+    };
+    let mut preflight = Preflight::new(EC2Instance::from_config(&sdk_config));
+
+    let mut issues = Vec::new();
+    for component in &config.components {
+        if component.component_type != "EC2Instance" {
+            continue;
+        }
+        let image_id = component
+            .get_property_as_string("image_id")
+            .or_else(|| component.get_property_as_string("ami"));
+        let instance_type = component.get_property_as_string("instance_type");
+        let Some(instance_type) = instance_type else {
+            continue;
         };
+        if let Some(image_id) = image_id {
+            issues.extend(
+                preflight
+                    .check_image_compatibility(&component.key(), &image_id, &instance_type)
+                    .await,
+            );
+        }
+        let availability_zone = component
+            .get_property("placement")
+            .and_then(|p| p.get("availability_zone"))
+            .and_then(|az| az.as_str());
+        // A subnet created in the same apply has no id to check yet
+        let subnet_id = component
+            .get_property_as_string("subnet_id")
+            .filter(|id| !id.contains("${"));
+        issues.extend(
+            preflight
+                .check_instance_type_offering(
+                    &component.key(),
+                    &instance_type,
+                    availability_zone,
+                    subnet_id.as_deref(),
+                )
+                .await,
+        );
+    }
+    issues
+}
 
-        table.add_row(vec![
-            Cell::new(operation_symbol).fg(operation_color),
-            Cell::new(format!(" {} {}", prefix, component.component_type)),
-            Cell::new(&component.name),
-            Cell::new(operation_text).fg(operation_color),
-            Cell::new(""),
-        ]);
+/**
+ * Put the preflight findings of each component in its Info column.
+ * Components with an error-level finding are marked unsupported, the row is styled as a warning
+ * and the plan fails.
+ */
+fn annotate_preview(preview: &mut PlanPreviewDeployment, issues: &[PreflightIssue]) {
+    for component in &mut preview.components {
+        let key = format!("{}.{}", component.component_type, component.name);
+        let messages: Vec<&str> = issues
+            .iter()
+            .filter(|issue| issue.component == key)
+            .map(|issue| issue.message.as_str())
+            .collect();
+        if messages.is_empty() {
+            continue;
+        }
+        component.supported &= !issues
+            .iter()
+            .any(|issue| issue.component == key && issue.severity == Severity::Error);
+        component.info = messages.join("; ");
     }
+}
 
-    println!("\n{}", table);
+/// Preflight findings that fail the plan, an error means the component cannot be created as declared
+fn preflight_errors(issues: &[PreflightIssue]) -> usize {
+    issues
+        .iter()
+        .filter(|issue| issue.severity == Severity::Error)
+        .count()
 }
 
-pub fn execute(config: &Config) {
-    println!("Executing plan command with config: {:?}", config);
+/// Components the plan would apply although they cannot be created as declared
+fn unsupported_components(preview: &PlanPreviewDeployment) -> Vec<String> {
+    preview
+        .components
+        .iter()
+        .filter(|c| !c.supported && !c.excluded)
+        .map(|c| format!("{}.{}", c.component_type, c.name))
+        .collect()
+}
+
+fn format_plan_preview(preview: &PlanPreviewDeployment, width: Option<usize>) {
+    let options = RenderOptions::detect(width);
+    println!("\n{}", redact(&render_plan_preview(preview, &options)));
+}
 
-    let file_path = &config.options.file_path;
-    println!("File path is: {}", file_path);
+/**
+ * Plan every deployment of `-f`, one after the other. Several deployments each get their own
+ * state, `<workspace>/<name>/state.json`, and in json mode one document each.
+ * Returns false when any deployment could not be planned or has errors.
+ */
+pub async fn execute(config: &Config) -> bool {
+    let options = &config.options;
+    let documents = match config_documents(&options.file_path) {
+        Ok(documents) => documents,
+        Err(err) => {
+            eprintln!("{}", redact(&err.to_string()));
+            return false;
+        }
+    };
+    if documents.len() > 1 && options.state_file.is_some() {
+        eprintln!("--state-file cannot be used with several deployments, each has its own state");
+        return false;
+    }
+    if let Err(err) = select_workspace(options.workspace.as_deref()) {
+        eprintln!("{}", redact(&err.to_string()));
+        return false;
+    }
 
-    match validate_file(file_path) {
-        Ok(()) => {
-            println!("Validation is passed");
-            tracing::debug!("Plan Validation is passed");
+    let mut planned = true;
+    for document in &documents {
+        let mut options = options.clone();
+        options.file_path = document.source.clone();
+        if documents.len() > 1 {
+            options.deployment = Some(document.name.clone());
+            if options.output == OutputFormat::Table {
+                println!(
+                    "\n=== Deployment {} ({}) ===",
+                    document.name, document.source
+                );
+            }
         }
+        planned &= plan_deployment(&Config { options }).await;
+    }
+    planned
+}
+
+/// Returns false when no plan could be made or a component has errors, so the exit code is non-zero
+async fn plan_deployment(config: &Config) -> bool {
+    let options = &config.options;
+    let file_path = &options.file_path;
+    // Nothing but the document goes to stdout in json mode
+    let json = options.output == OutputFormat::Json;
+    let workspace = match select_workspace(options.workspace.as_deref()) {
+        Ok(workspace) => workspace,
         Err(err) => {
-            println!("Plan Failed while validating file: {}", err.to_string());
-            tracing::error!(message = "Plan Failed", error = %err);
+            eprintln!("{}", redact(&err.to_string()));
+            return false;
+        }
+    };
+    if !json {
+        match validate_file(file_path, &options.variables) {
+            Ok(()) => {
+                println!("Validation is passed");
+                tracing::debug!("Plan Validation is passed");
+            }
+            Err(err) => {
+                eprintln!(
+                    "Plan Failed while validating file: [{}] {}",
+                    err.code(),
+                    redact(&err.to_string())
+                );
+                tracing::error!(message = "Plan Failed", error = %err);
+                return false;
+            }
         }
     }
 
-    let content = match std::fs::read_to_string(file_path) {
+    let content = match read_config_source(file_path) {
         Ok(content) => content,
         Err(err) => {
-            eprintln!("Failed to read file: {}", err);
-            return;
+            eprintln!("Failed to read file: {}", redact(&err.to_string()));
+            return false;
         }
     };
 
     // Try to parse using the structured model
-    match InfraConfig::from_yaml(&content) {
-        Ok(config) => {
+    match InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content)) {
+        Ok(mut config) => {
+            if let Err(err) = resolve_templates(&mut config, file_path) {
+                eprintln!("{}", redact(&err.to_string()));
+                return false;
+            }
+            anchor_config_paths(&mut config, file_path);
+            if let Err(err) = bind_variables(&mut config, &options.variables) {
+                eprintln!("[{}] {}", err.code(), redact(&err.to_string()));
+                return false;
+            }
+            let sensitive = schema::sensitive_values(&config.components);
+            if let Err(err) = redact::install(&config.redact, sensitive) {
+                eprintln!("{}", redact(&err.to_string()));
+                return false;
+            }
+            if json {
+                if let Err(err) = validate_infra_config(&config) {
+                    eprintln!(
+                        "Plan Failed while validating file: [{}] {}",
+                        err.code(),
+                        redact(&err.to_string())
+                    );
+                    return false;
+                }
+            }
+            // Only read, a plan neither takes nor waits for the lock
+            let backend = state::backend::open(
+                config.backend.as_ref(),
+                options.state_file.as_deref(),
+                &workspace,
+                options.deployment.as_deref(),
+                Some(&config.region),
+            )
+            .await;
+            let previous = match backend {
+                Ok(backend) => backend.load().await,
+                Err(err) => Err(err),
+            };
+            let previous = match previous {
+                Ok(previous) => previous,
+                Err(err) => {
+                    eprintln!("{}", redact(&err.to_string()));
+                    return false;
+                }
+            };
+            // AMIs of ami_lookup blocks are looked up along with the other checks that call AWS
+            let online = options.preflight;
+            if let Err(err) = resolve_ami_lookups(&mut config, previous.as_ref(), online).await {
+                eprintln!("{}", redact(&err.to_string()));
+                return false;
+            }
             tracing::info!("Starting the planning stage with the config: {}", config);
-            match plan_components(&config) {
-                Ok((_plan, preview)) => {
-                    println!("Plan generated successfully:");
-                    format_plan_preview(&preview);
+            match plan_components(&config, &workspace) {
+                Ok((plan, mut preview, errors)) => {
+                    if !json {
+                        println!(
+                            "Plan generated successfully for workspace '{}' (hash {}):",
+                            plan.workspace, plan.hash
+                        );
+                    }
+                    let resolved = resolve_config(&config, &plan.workspace).ok();
+                    let issues = match (&resolved, options.preflight) {
+                        (Some(resolved), true) => run_preflight(resolved).await,
+                        _ => Vec::new(),
+                    };
+                    let failed = errors.len() + preflight_errors(&issues);
+                    if let Some(state) = &previous {
+                        diff_against_state(&mut preview, state);
+                    }
+                    if !options.targets.is_empty() {
+                        // Names may hold references, the preview has them resolved
+                        let components = match &resolved {
+                            Some(resolved) => &resolved.components,
+                            None => &config.components,
+                        };
+                        match target_closure(components, &options.targets) {
+                            Ok(targeted) => exclude_untargeted(&mut preview, &targeted),
+                            Err(err) => {
+                                eprintln!("{}", redact(&err.to_string()));
+                                return false;
+                            }
+                        }
+                    }
+                    annotate_preview(&mut preview, &issues);
+                    let unsupported = unsupported_components(&preview);
+                    let warnings = resolved
+                        .as_ref()
+                        .map(|resolved| lint_config(&config, resolved))
+                        .unwrap_or_default();
+
+                    if json {
+                        let mut diagnostics: Vec<PlanDiagnostic> = errors
+                            .iter()
+                            .map(|err| PlanDiagnostic {
+                                severity: "error",
+                                code: Some(err.code()),
+                                component: None,
+                                message: err.to_string(),
+                            })
+                            .collect();
+                        diagnostics.extend(warnings.iter().map(|warning| PlanDiagnostic {
+                            severity: "warning",
+                            code: Some(warning.code()),
+                            component: None,
+                            message: warning.to_string(),
+                        }));
+                        diagnostics.extend(issues.into_iter().map(|issue| PlanDiagnostic {
+                            severity: match issue.severity {
+                                Severity::Error => "error",
+                                Severity::Warning => "warning",
+                            },
+                            code: None,
+                            component: Some(issue.component),
+                            message: issue.message,
+                        }));
+                        let document = plan_document(
+                            &plan,
+                            &preview,
+                            resolved.as_ref(),
+                            options.preflight,
+                            diagnostics,
+                        );
+                        match serde_json::to_string_pretty(&document) {
+                            Ok(document) => println!("{}", redact(&document)),
+                            Err(err) => {
+                                eprintln!("Failed to serialize plan: {}", redact(&err.to_string()));
+                                return false;
+                            }
+                        }
+                        return failed == 0 && unsupported.is_empty();
+                    }
+
+                    format_plan_preview(&preview, options.width);
+                    let excluded = preview.components.iter().filter(|c| c.excluded).count();
+                    if excluded > 0 {
+                        println!(
+                            "Note: this plan is partial, {} component(s) outside --target are excluded and left untouched",
+                            excluded
+                        );
+                    }
+                    if !options.preflight {
+                        println!(
+                            "Note: this plan was built locally without querying AWS, run with --preflight to check it against the provider"
+                        );
+                    }
+                    for warning in warnings {
+                        println!(
+                            "warning[{}]: {}",
+                            warning.code(),
+                            redact(&warning.to_string())
+                        );
+                    }
+                    for issue in issues {
+                        let label = match issue.severity {
+                            Severity::Error => "error",
+                            Severity::Warning => "warning",
+                        };
+                        println!(
+                            "{}: {}",
+                            label,
+                            redact(&format!("{}: {}", issue.component, issue.message))
+                        );
+                    }
+                    for err in &errors {
+                        println!("error[{}]: {}", err.code(), redact(&err.to_string()));
+                    }
+                    for key in &unsupported {
+                        println!("error: {}: cannot be applied as declared (`!` above)", key);
+                    }
+                    if failed > 0 || !unsupported.is_empty() {
+                        eprintln!(
+                            "Plan has {} error(s) and {} unsupported component(s), nothing can be applied",
+                            failed,
+                            unsupported.len()
+                        );
+                        return false;
+                    }
+                    true
                 }
                 Err(err) => {
-                    eprintln!("Failed to generate plan: {}", err);
+                    eprintln!(
+                        "Failed to generate plan: [{}] {}",
+                        err.code(),
+                        redact(&err.to_string())
+                    );
+                    false
                 }
             }
         }
         Err(err) => {
-            eprintln!("Failed to parse YAML into InfraConfig: {}", err);
+            eprintln!(
+                "Failed to parse config into InfraConfig: {}",
+                redact(&err.to_string())
+            );
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{ComponentPreview, OperationType};
+
+    fn component(name: &str) -> ComponentPreview {
+        ComponentPreview {
+            component_type: "EC2Instance".to_string(),
+            name: name.to_string(),
+            operation_type: OperationType::Create,
+            properties_hash: String::new(),
+            info: "t2.micro".to_string(),
+            supported: true,
+            replacement_hash: String::new(),
+            replace: false,
+            computed: None,
+            template: None,
+            excluded: false,
         }
     }
+
+    #[test]
+    fn test_annotate_preview_with_preflight_issues() {
+        let mut preview = PlanPreviewDeployment {
+            deployment_type: "Infra".to_string(),
+            deployment_name: "shop".to_string(),
+            components: vec![component("web"), component("worker"), component("db")],
+        };
+        let issues = vec![
+            PreflightIssue {
+                component: "EC2Instance.web".to_string(),
+                severity: Severity::Error,
+                message: "t2.micro is not offered in us-west-2d".to_string(),
+            },
+            PreflightIssue {
+                component: "EC2Instance.worker".to_string(),
+                severity: Severity::Warning,
+                message: "AMI is deprecated".to_string(),
+            },
+        ];
+        annotate_preview(&mut preview, &issues);
+
+        let [web, worker, db] = &preview.components[..] else {
+            panic!("expected three components");
+        };
+        assert!(!web.supported);
+        assert_eq!(web.info, "t2.micro is not offered in us-west-2d");
+        assert!(worker.supported);
+        assert_eq!(worker.info, "AMI is deprecated");
+        assert!(db.supported);
+        assert_eq!(db.info, "t2.micro");
+    }
+
+    #[test]
+    fn test_unsupported_components_fail_unless_excluded() {
+        let mut preview = PlanPreviewDeployment {
+            deployment_type: "Infra".to_string(),
+            deployment_name: "shop".to_string(),
+            components: vec![component("web"), component("worker"), component("db")],
+        };
+        assert!(unsupported_components(&preview).is_empty());
+        preview.components[0].supported = false;
+        preview.components[1].supported = false;
+        preview.components[1].excluded = true;
+        assert_eq!(unsupported_components(&preview), vec!["EC2Instance.web"]);
+    }
+
+    #[test]
+    fn test_preflight_errors_fail_the_plan() {
+        let issue = |severity| PreflightIssue {
+            component: "EC2Instance.web".to_string(),
+            severity,
+            message: "ami-1 is arm64, t3.micro supports x86_64".to_string(),
+        };
+        assert_eq!(preflight_errors(&[]), 0);
+        assert_eq!(preflight_errors(&[issue(Severity::Warning)]), 0);
+        assert_eq!(
+            preflight_errors(&[issue(Severity::Error), issue(Severity::Warning)]),
+            1
+        );
+    }
+
+    #[test]
+    fn test_plan_document_counts_operations() {
+        let mut deleted = component("old");
+        deleted.operation_type = OperationType::Delete;
+        let mut unchanged = component("db");
+        unchanged.operation_type = OperationType::NoOp;
+        let mut untargeted = component("batch");
+        untargeted.excluded = true;
+        let preview = PlanPreviewDeployment {
+            deployment_type: "Infra".to_string(),
+            deployment_name: "shop".to_string(),
+            components: vec![component("web"), unchanged, deleted, untargeted],
+        };
+        let plan = Plan {
+            workspace: "default".to_string(),
+            hash: "abc".to_string(),
+        };
+        let document = plan_document(&plan, &preview, None, false, vec![]);
+        assert_eq!(
+            document.summary,
+            OperationSummary {
+                create: 1,
+                update: 0,
+                delete: 1,
+                no_op: 1,
+                excluded: 1
+            }
+        );
+
+        let json = serde_json::to_value(&document).unwrap();
+        assert_eq!(json["format_version"], "1");
+        assert_eq!(json["deployment"]["name"], "shop");
+        assert_eq!(json["components"][0]["name"], "web");
+        assert_eq!(json["components"][0]["operation_type"], "create");
+        assert_eq!(json["components"][1]["operation_type"], "no_op");
+        assert_eq!(json["components"][2]["properties"], serde_json::Value::Null);
+        assert_eq!(json["components"][2].get("excluded"), None);
+        assert_eq!(json["components"][3]["excluded"], true);
+    }
+
+    #[test]
+    fn test_plan_document_masks_sensitive_properties() {
+        let config = InfraConfig::from_yaml(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: shop\ncomponents:\n  - type: EC2Instance\n    name: web\n    sensitive: [key_name]\n    properties:\n      instance_type: t2.micro\n      ami: ami-1\n      key_name: ops-key\n      user_data: export TOKEN=hunter2\n",
+        )
+        .unwrap();
+        let preview = PlanPreviewDeployment {
+            deployment_type: "Infra".to_string(),
+            deployment_name: "shop".to_string(),
+            components: vec![component("web")],
+        };
+        let plan = Plan {
+            workspace: "default".to_string(),
+            hash: "abc".to_string(),
+        };
+        let document = plan_document(&plan, &preview, Some(&config), false, vec![]);
+        let json = serde_json::to_string(&document).unwrap();
+        assert!(!json.contains("hunter2"), "{}", json);
+        assert!(!json.contains("ops-key"), "{}", json);
+        let json = serde_json::to_value(&document).unwrap();
+        assert_eq!(
+            json["components"][0]["properties"]["user_data"],
+            "(sensitive)"
+        );
+        assert_eq!(
+            json["components"][0]["properties"]["instance_type"],
+            "t2.micro"
+        );
+    }
 }
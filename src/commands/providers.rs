@@ -0,0 +1,50 @@
+use comfy_table::Table;
+
+use crate::{handlers, utils::schema::ResourceSchema};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {}
+
+/// Resource types the aws provider handles, with their schema version and supported operations
+pub fn render_capabilities<'a>(schemas: impl IntoIterator<Item = &'a ResourceSchema>) -> String {
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(vec!["Provider", "Type", "Schema", "Update", "Destroy"]);
+    let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+    for schema in schemas {
+        table.add_row(vec![
+            "aws".to_string(),
+            schema.component_type.to_string(),
+            format!("v{}", schema.version),
+            yes_no(schema.supports_update).to_string(),
+            yes_no(schema.supports_destroy).to_string(),
+        ]);
+    }
+    table.to_string()
+}
+
+pub fn execute(_config: &Config) {
+    println!(
+        "{}",
+        render_capabilities(handlers::handlers().map(|h| h.schema()))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::SCHEMAS;
+
+    #[test]
+    fn test_every_type_is_listed() {
+        let rendered = render_capabilities(SCHEMAS);
+        for schema in SCHEMAS {
+            let row = rendered
+                .lines()
+                .find(|line| line.contains(&format!(" {} ", schema.component_type)))
+                .unwrap_or_else(|| panic!("{} is not listed", schema.component_type));
+            assert!(row.contains("v1"), "{}", row);
+        }
+    }
+}
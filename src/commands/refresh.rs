@@ -0,0 +1,840 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    aws::{
+        ec2::{
+            ec2_instance::EC2Instance, elastic_ip::ElasticIp, key_pair::KeyPair,
+            security_group::SecurityGroup, subnet::Subnet, vpc::Vpc,
+        },
+        error::AwsError,
+        iam::{instance_profile::InstanceProfile, role::IamRole},
+        internal::wait_and_refresh::WaitDefaults,
+        s3::bucket::S3Bucket,
+        sdk_config::load_sdk_config,
+    },
+    commands::{
+        apply::{
+            bucket_outputs, elastic_ip_outputs, instance_outputs, instance_profile_outputs,
+            key_pair_outputs, resolve_declared_outputs, role_outputs, subnet_outputs, vpc_outputs,
+        },
+        output::display_value,
+    },
+    models::{
+        ConfigFormat, InfraConfig, OutputDeclaration,
+        state::{ComponentState, DeclaredOutput, State},
+    },
+    state::{self as state_file, backend::StateBackend},
+    utils::{
+        config_documents,
+        constants::SENSITIVE_VALUE,
+        diagnostics::Diagnostic,
+        interpolation::{component_reference, references_in, resolve_config},
+        read_config_source,
+        redact::{self, redact},
+        schema, select_workspace,
+        variables::{VariableOptions, bind_variables},
+    },
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug, Clone)]
+pub struct Options {
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: String,
+    /// Values for the variables the outputs block uses, its outputs are recomputed
+    #[clap(flatten)]
+    pub variables: VariableOptions,
+    /// Workspace to refresh, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State to refresh, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Only read these components, `<type>.<name>`, can be repeated
+    #[clap(long = "target")]
+    pub targets: Vec<String>,
+    /// Deployment of a `-f` holding several, each has its own state in the backend
+    #[clap(skip)]
+    pub deployment: Option<String>,
+    /// Print the report on stderr, `output --refresh` keeps stdout for the value
+    #[clap(skip)]
+    pub report_on_stderr: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RefreshError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("{0}")]
+    State(#[from] state_file::StateError),
+    #[error("{failed} of {total} component(s) could not be refreshed")]
+    ComponentsFailed { failed: usize, total: usize },
+}
+
+/// The live resource behind a state entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum Refreshed {
+    Current(serde_json::Value), // Outputs read back from AWS, the same keys apply records
+    Gone,
+}
+
+/// Refresh the state of every deployment of `-f`, one after the other
+pub async fn execute(config: &Config) -> Result<(), RefreshError> {
+    let options = &config.options;
+    let documents = config_documents(&options.file_path).map_err(RefreshError::Config)?;
+    if documents.len() > 1 && options.state_file.is_some() {
+        return Err(RefreshError::Config(
+            "--state-file cannot be used with several deployments, each has its own state"
+                .to_string(),
+        ));
+    }
+    for document in &documents {
+        let mut options = options.clone();
+        options.file_path = document.source.clone();
+        if documents.len() > 1 {
+            options.deployment = Some(document.name.clone());
+            println!(
+                "\n=== Deployment {} ({}) ===",
+                document.name, document.source
+            );
+        }
+        refresh_deployment(&Config { options }).await?;
+    }
+    Ok(())
+}
+
+/**
+ * Refresh the components the outputs `names` of the outputs block come from, of every declared
+ * output when `names` is empty, for `output --refresh`. `options.file_path` may hold several
+ * deployments, `options.deployment` picks one then. Returns the config of that deployment, its
+ * backend holds the refreshed state.
+ */
+pub async fn refresh_outputs(
+    options: &Options,
+    names: &[String],
+) -> Result<InfraConfig, RefreshError> {
+    let documents = config_documents(&options.file_path).map_err(RefreshError::Config)?;
+    let document = match (&options.deployment, documents.as_slice()) {
+        (None, [document]) => document,
+        (None, _) => {
+            return Err(RefreshError::Config(format!(
+                "{} holds several deployments, pick one with --deployment",
+                options.file_path
+            )));
+        }
+        (Some(deployment), _) => documents
+            .iter()
+            .find(|d| &d.name == deployment)
+            .ok_or_else(|| {
+                RefreshError::Config(format!(
+                    "No deployment '{}' in {}",
+                    deployment, options.file_path
+                ))
+            })?,
+    };
+    let config = read_config(&document.source)?;
+    let mut options = options.clone();
+    options.file_path = document.source.clone();
+    options.targets = output_sources(&config.outputs, names);
+    if options.targets.is_empty() {
+        eprintln!("No component behind the outputs, nothing to refresh");
+        return Ok(config);
+    }
+    refresh_deployment(&Config { options }).await?;
+    Ok(config)
+}
+
+/// Components `<type>.<name>` the outputs `names` reference, of every output when `names` is empty
+fn output_sources(outputs: &BTreeMap<String, OutputDeclaration>, names: &[String]) -> Vec<String> {
+    let mut sources: Vec<String> = Vec::new();
+    let selected = outputs
+        .iter()
+        .filter(|(name, _)| names.is_empty() || names.contains(name));
+    for (_, declaration) in selected {
+        for reference in references_in(declaration.value()) {
+            if let Some((component, _)) = component_reference(&reference)
+                && !sources.iter().any(|s| s == component)
+            {
+                sources.push(component.to_string());
+            }
+        }
+    }
+    sources
+}
+
+fn read_config(file_path: &str) -> Result<InfraConfig, RefreshError> {
+    let content =
+        read_config_source(file_path).map_err(|err| RefreshError::FileRead(err.to_string()))?;
+    InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content))
+        .map_err(|err| RefreshError::Parse(err.to_string()))
+}
+
+/**
+ * The outputs block with variables and workspace resolved, as apply records it. The component
+ * references are left in place for `resolve_declared_outputs`.
+ */
+fn output_declarations(
+    mut config: InfraConfig,
+    variables: &VariableOptions,
+    workspace: &str,
+) -> Result<BTreeMap<String, OutputDeclaration>, String> {
+    if config.outputs.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    bind_variables(&mut config, variables).map_err(|err| format!("[{}] {}", err.code(), err))?;
+    resolve_config(&config, workspace)
+        .map(|resolved| resolved.outputs)
+        .map_err(|err| err.to_string())
+}
+
+/**
+ * Read every resource recorded in the state back from AWS, only the `--target` ones when given,
+ * and write what was found to the state: changed outputs (addresses, cidr, ...) replace the
+ * recorded ones, and resources that no longer exist are dropped so the next plan creates them
+ * again. The outputs block is then recomputed from the outputs of every component at once.
+ */
+async fn refresh_deployment(config: &Config) -> Result<(), RefreshError> {
+    let options = &config.options;
+    let report_line = |line: &str| {
+        if options.report_on_stderr {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    let config = read_config(&options.file_path)?;
+    redact::install(&config.redact, schema::sensitive_values(&config.components))
+        .map_err(|err| RefreshError::Config(err.to_string()))?;
+    let workspace = select_workspace(options.workspace.as_deref()).map_err(RefreshError::Config)?;
+    let mut backend = state_file::backend::open(
+        config.backend.as_ref(),
+        options.state_file.as_deref(),
+        &workspace,
+        options.deployment.as_deref(),
+        Some(&config.region),
+    )
+    .await?;
+    backend.lock("refresh").await?;
+    let outcome: Result<(), RefreshError> = async {
+        let Some(state) = backend.load().await? else {
+            report_line(&format!(
+                "No state at {}, nothing to refresh",
+                backend.describe()
+            ));
+            return Ok(());
+        };
+        check_targets(&state, &options.targets).map_err(RefreshError::Config)?;
+
+        // Every instance of a component is read, min_count/max_count can launch several
+        let wait = WaitDefaults::for_config(&config);
+        let mut refreshed = Vec::with_capacity(state.components.len());
+        let mut failed = 0;
+        let mut total = 0;
+        let mut skipped = 0; // Instances outside --target, reconcile counts them as unchanged
+        for component in &state.components {
+            let key = component.key();
+            if !options.targets.is_empty() && !options.targets.contains(&key) {
+                skipped += component.instances.len().max(1);
+                refreshed.push(Vec::new());
+                continue;
+            }
+            total += 1;
+            let mut instances = Vec::with_capacity(component.instances.len());
+            let mut component_failed = false;
+            for recorded in &component.instances {
+                let region = recorded
+                    .get("region")
+                    .and_then(|r| r.as_str())
+                    .unwrap_or(&config.region);
+                match read_instance(component, recorded, region, &wait).await {
+                    Ok(live) => instances.push(Some(live)),
+                    Err(err) => {
+                        component_failed = true;
+                        eprintln!(
+                            "{}: refresh failed: [{}] {}",
+                            key,
+                            err.code(),
+                            redact(&err.to_string())
+                        );
+                        instances.push(None);
+                    }
+                }
+            }
+            if component_failed {
+                failed += 1;
+            }
+            refreshed.push(instances);
+        }
+
+        let (mut state, mut report) = reconcile(state, refreshed);
+        report.unchanged -= skipped;
+        if report.changed > 0 || report.removed > 0 {
+            match output_declarations(config.clone(), &options.variables, &workspace) {
+                Ok(declarations) => {
+                    let lines = refresh_declared_outputs(&mut state, &declarations);
+                    report.lines.extend(lines);
+                }
+                Err(err) => report
+                    .lines
+                    .push(format!("Outputs block not recomputed: {}", err)),
+            }
+        }
+        report
+            .lines
+            .iter()
+            .for_each(|line| report_line(&redact(line)));
+        if report.changed > 0 || report.removed > 0 {
+            let state = backend.save(state).await?;
+            report_line(&format!(
+                "State written to {} (serial {})",
+                backend.describe(),
+                state.serial
+            ));
+        }
+        report_line(&format!(
+            "Refresh complete: {} changed, {} removed, {} unchanged",
+            report.changed, report.removed, report.unchanged
+        ));
+
+        if failed == 0 {
+            return Ok(());
+        }
+        Err(RefreshError::ComponentsFailed { failed, total })
+    }
+    .await;
+    state_file::backend::unlock_after(&mut backend, outcome).await
+}
+
+/// Every `--target` must name a component of the state, there is nothing to read for others
+fn check_targets(state: &State, targets: &[String]) -> Result<(), String> {
+    let keys: Vec<String> = state.components.iter().map(|c| c.key()).collect();
+    match targets.iter().find(|t| !keys.contains(t)) {
+        Some(unknown) => Err(format!(
+            "Unknown target '{}', the state has: {}",
+            unknown,
+            keys.join(", ")
+        )),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Recompute the outputs block from the refreshed outputs of every component, so an output made
+ * of several components sees all of them as of this refresh. One line per output that changed
+ * or can no longer be resolved, which is dropped like apply does.
+ */
+fn refresh_declared_outputs(
+    state: &mut State,
+    declarations: &BTreeMap<String, OutputDeclaration>,
+) -> Vec<String> {
+    let outputs = state.outputs.value.as_object().cloned().unwrap_or_default();
+    let (declared, unresolved) = resolve_declared_outputs(declarations, &outputs);
+    let show = |output: &DeclaredOutput| match output.sensitive {
+        true => SENSITIVE_VALUE.to_string(),
+        false => display_value(&output.value),
+    };
+    let mut lines = Vec::new();
+    for (name, output) in &declared {
+        match state.outputs.declared.get(name) {
+            Some(recorded) if recorded.value == output.value => {}
+            Some(_) if output.sensitive => {
+                lines.push(format!("Output {} changed {}", name, SENSITIVE_VALUE))
+            }
+            Some(recorded) => lines.push(format!(
+                "Output {} changed: {} -> {}",
+                name,
+                show(recorded),
+                show(output)
+            )),
+            None => lines.push(format!("Output {} added: {}", name, show(output))),
+        }
+    }
+    for message in unresolved {
+        lines.push(format!("Output {}, removed from state", message));
+    }
+    state.outputs.declared = declared;
+    lines
+}
+
+/// What reconciling the state changed, one console line per changed or removed instance
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RefreshReport {
+    pub lines: Vec<String>,
+    pub changed: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+}
+
+/**
+ * Apply what was read from AWS to the state, `refreshed` has one entry per recorded instance of
+ * every state component. Instances read as None (no id recorded, or the read failed) are kept as
+ * they are. Only the instances that are gone are dropped, a component goes once none is left.
+ */
+fn reconcile(mut state: State, refreshed: Vec<Vec<Option<Refreshed>>>) -> (State, RefreshReport) {
+    let mut report = RefreshReport::default();
+    let mut outputs = state.outputs.value.as_object().cloned().unwrap_or_default();
+    let mut components = Vec::with_capacity(refreshed.len());
+    for (mut component, live) in std::mem::take(&mut state.components)
+        .into_iter()
+        .zip(refreshed)
+    {
+        let key = format!("{}.{}", component.component_type, component.name);
+        if component.instances.is_empty() {
+            report.unchanged += 1;
+            components.push(component);
+            continue;
+        }
+        let recorded_instances = std::mem::take(&mut component.instances);
+        let mut instances = Vec::with_capacity(recorded_instances.len());
+        let mut touched = false;
+        let live = live.into_iter().chain(std::iter::repeat(None));
+        for (recorded, live) in recorded_instances.into_iter().zip(live) {
+            let id = recorded
+                .get("id")
+                .and_then(|id| id.as_str())
+                .unwrap_or("-")
+                .to_string();
+            match live {
+                Some(Refreshed::Gone) => {
+                    report.removed += 1;
+                    report.lines.push(format!(
+                        "{}: {} no longer exists, removed from state",
+                        key, id
+                    ));
+                    touched = true;
+                }
+                Some(Refreshed::Current(current)) => {
+                    let changes = drift(&recorded, &current);
+                    if changes.is_empty() {
+                        report.unchanged += 1;
+                        instances.push(recorded);
+                        continue;
+                    }
+                    report.changed += 1;
+                    report
+                        .lines
+                        .push(format!("{}: {} changed: {}", key, id, changes.join(", ")));
+                    // Outputs the read does not return are kept
+                    let mut merged = recorded.as_object().cloned().unwrap_or_default();
+                    merged.extend(current.as_object().cloned().unwrap_or_default());
+                    instances.push(serde_json::Value::Object(merged));
+                    touched = true;
+                }
+                None => {
+                    report.unchanged += 1;
+                    instances.push(recorded);
+                }
+            }
+        }
+        if instances.is_empty() {
+            outputs.remove(&key);
+            continue;
+        }
+        if touched {
+            // Outputs apply adds next to the instance's own, such as instance_ids, stay as well
+            let mut output = outputs
+                .get(&key)
+                .and_then(|o| o.as_object())
+                .cloned()
+                .unwrap_or_default();
+            // The first instance left answers `${<type>.<name>.<output>}`
+            output.extend(instances[0].as_object().cloned().unwrap_or_default());
+            if output.contains_key("instance_ids") {
+                let ids: Vec<serde_json::Value> = instances
+                    .iter()
+                    .filter_map(|i| i.get("id").cloned())
+                    .collect();
+                output.insert("instance_ids".to_string(), serde_json::Value::Array(ids));
+            }
+            outputs.insert(key, serde_json::Value::Object(output));
+        }
+        component.instances = instances;
+        components.push(component);
+    }
+    state.components = components;
+    state.outputs.value = serde_json::Value::Object(outputs);
+    (state, report)
+}
+
+/// Outputs whose live value differs from the recorded one, as `<output>: <old> -> <new>`
+fn drift(recorded: &serde_json::Value, current: &serde_json::Value) -> Vec<String> {
+    let Some(current) = current.as_object() else {
+        return Vec::new();
+    };
+    let show = |value: Option<&serde_json::Value>| match value {
+        None | Some(serde_json::Value::Null) => "-".to_string(),
+        Some(serde_json::Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    };
+    let null = serde_json::Value::Null;
+    current
+        .iter()
+        .filter(|(name, value)| recorded.get(name.as_str()).unwrap_or(&null) != *value)
+        .map(|(name, value)| {
+            format!(
+                "{}: {} -> {}",
+                name,
+                show(recorded.get(name.as_str())),
+                show(Some(value))
+            )
+        })
+        .collect()
+}
+
+/// Describe one resource recorded for a component, a terminated instance counts as gone
+async fn read_instance(
+    component: &ComponentState,
+    recorded: &serde_json::Value,
+    region: &str,
+    wait: &WaitDefaults,
+) -> Result<Refreshed, AwsError> {
+    let id = recorded
+        .get("id")
+        .and_then(|id| id.as_str())
+        .ok_or_else(|| AwsError::OptionsError("no resource id recorded in state".to_string()))?;
+    let config = load_sdk_config(region)
+        .await
+        .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+    let found = match component.component_type.as_str() {
+        "EC2Instance" => EC2Instance::from_config(&config)
+            .with_wait(wait.clone())
+            .read_instance(id, component.created_at())
+            .await?
+            .filter(|instance| {
+                instance.state().and_then(|s| s.name())
+                    != Some(&aws_sdk_ec2::types::InstanceStateName::Terminated)
+            })
+            .map(|instance| instance_outputs(&instance, region)),
+        "VPC" => not_found_as_none(Vpc::from_config(&config).describe_vpc(id).await)?
+            .map(|vpc| vpc_outputs(&vpc, region)),
+        "Subnet" => not_found_as_none(Subnet::from_config(&config).describe_subnet(id).await)?
+            .map(|subnet| subnet_outputs(&subnet, region)),
+        "ElasticIP" => {
+            not_found_as_none(ElasticIp::from_config(&config).describe_address(id).await)?
+                .map(|address| elastic_ip_outputs(&address, region))
+        }
+        "S3Bucket" => not_found_as_none(S3Bucket::from_config(&config).describe_bucket(id).await)?
+            .map(|bucket_region| bucket_outputs(id, &bucket_region)),
+        "KeyPair" => not_found_as_none(KeyPair::from_config(&config).describe_key_pair(id).await)?
+            .map(|key_pair| key_pair_outputs(&key_pair, region)),
+        "IAMRole" => not_found_as_none(IamRole::from_config(&config).describe_role(id).await)?
+            .map(|role| role_outputs(&role, region)),
+        "InstanceProfile" => not_found_as_none(
+            InstanceProfile::from_config(&config)
+                .describe_instance_profile(id)
+                .await,
+        )?
+        .map(|profile| instance_profile_outputs(&profile, region)),
+        "SecurityGroup" => not_found_as_none(
+            SecurityGroup::from_config(&config)
+                .describe_security_group(id)
+                .await,
+        )?
+        .map(|group| {
+            serde_json::json!({
+                "id": group.group_id(),
+                "region": region,
+                "vpc_id": group.vpc_id(),
+            })
+        }),
+        other => {
+            return Err(AwsError::OptionsError(format!(
+                "unsupported component type: {}",
+                other
+            )));
+        }
+    };
+    Ok(found.map(Refreshed::Current).unwrap_or(Refreshed::Gone))
+}
+
+/// A resource other than an instance that does not exist as None, whichever service read it
+pub(crate) fn not_found_as_none<T, E: Into<AwsError>>(
+    result: Result<T, E>,
+) -> Result<Option<T>, AwsError> {
+    match result.map_err(Into::into) {
+        Ok(found) => Ok(Some(found)),
+        Err(err) if err.is_not_found() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, Datatype, Output, OutputType};
+    use serde_json::json;
+
+    fn component(name: &str, instance: serde_json::Value) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: name.to_string(),
+            component_type: "EC2Instance".to_string(),
+            provider: "aws".to_string(),
+            instances: vec![instance],
+            created_at: None,
+            properties_hash: Some("abc".to_string()),
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    #[test]
+    fn test_reconcile_writes_drift_and_drops_missing_resources() {
+        let web = component(
+            "web",
+            json!({ "id": "i-1", "region": "us-west-2", "public_ip": "1.1.1.1" }),
+        );
+        let db = component("db", json!({ "id": "i-2", "region": "us-west-2" }));
+        let worker = component("worker", json!({ "id": "i-3", "region": "us-west-2" }));
+        let state = State {
+            version: "1".to_string(),
+            serial: 3,
+            lineage: "abc".to_string(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: json!({
+                    "EC2Instance.web": web.instances[0].clone(),
+                    "EC2Instance.db": db.instances[0].clone(),
+                }),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![web, db, worker],
+            launches: Default::default(),
+        };
+        let (state, report) = reconcile(
+            state,
+            vec![
+                vec![Some(Refreshed::Current(json!({
+                    "id": "i-1",
+                    "region": "us-west-2",
+                    "public_ip": "2.2.2.2",
+                })))],
+                vec![Some(Refreshed::Gone)],
+                vec![None],
+            ],
+        );
+
+        assert_eq!(
+            report.lines,
+            vec![
+                "EC2Instance.web: i-1 changed: public_ip: 1.1.1.1 -> 2.2.2.2",
+                "EC2Instance.db: i-2 no longer exists, removed from state",
+            ]
+        );
+        assert_eq!(
+            (report.changed, report.removed, report.unchanged),
+            (1, 1, 1)
+        );
+        let names: Vec<&str> = state.components.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["web", "worker"]);
+        assert_eq!(state.components[0].instances[0]["public_ip"], "2.2.2.2");
+        assert_eq!(
+            state.outputs.value["EC2Instance.web"]["public_ip"],
+            "2.2.2.2"
+        );
+        assert!(state.outputs.value.get("EC2Instance.db").is_none());
+    }
+
+    #[test]
+    fn test_reconcile_drops_only_the_instances_that_are_gone() {
+        let mut web = component("web", json!({ "id": "i-1", "region": "us-west-2" }));
+        web.instances
+            .push(json!({ "id": "i-2", "region": "us-west-2" }));
+        web.instances
+            .push(json!({ "id": "i-3", "region": "us-west-2" }));
+        let mut output = web.instances[0].clone();
+        output["instance_ids"] = json!(["i-1", "i-2", "i-3"]);
+        let state = State {
+            version: "1".to_string(),
+            serial: 3,
+            lineage: "abc".to_string(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: json!({ "EC2Instance.web": output }),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![web],
+            launches: Default::default(),
+        };
+        let (state, report) = reconcile(
+            state,
+            vec![vec![
+                Some(Refreshed::Gone),
+                Some(Refreshed::Current(
+                    json!({ "id": "i-2", "region": "us-west-2" }),
+                )),
+                None,
+            ]],
+        );
+
+        assert_eq!(
+            report.lines,
+            vec!["EC2Instance.web: i-1 no longer exists, removed from state"]
+        );
+        assert_eq!(
+            (report.changed, report.removed, report.unchanged),
+            (0, 1, 2)
+        );
+        let ids: Vec<&str> = state.components[0]
+            .instances
+            .iter()
+            .filter_map(|i| i["id"].as_str())
+            .collect();
+        assert_eq!(ids, vec!["i-2", "i-3"]);
+        assert_eq!(state.outputs.value["EC2Instance.web"]["id"], "i-2");
+        assert_eq!(
+            state.outputs.value["EC2Instance.web"]["instance_ids"],
+            json!(["i-2", "i-3"])
+        );
+
+        // Once every instance is gone the component goes too
+        let (state, _) = reconcile(
+            state,
+            vec![vec![Some(Refreshed::Gone), Some(Refreshed::Gone)]],
+        );
+        assert!(state.components.is_empty());
+        assert!(state.outputs.value.get("EC2Instance.web").is_none());
+    }
+
+    #[test]
+    fn test_drift_treats_missing_and_null_alike() {
+        let recorded = json!({ "id": "i-1", "public_ip": null });
+        assert!(drift(&recorded, &json!({ "id": "i-1", "public_ip": null })).is_empty());
+        assert!(drift(&json!({ "id": "i-1" }), &json!({ "public_ip": null })).is_empty());
+        assert_eq!(
+            drift(&recorded, &json!({ "public_ip": "3.3.3.3" })),
+            vec!["public_ip: - -> 3.3.3.3"]
+        );
+    }
+
+    fn state(components: Vec<ComponentState>, outputs: serde_json::Value) -> State {
+        State {
+            version: "1".to_string(),
+            serial: 3,
+            lineage: "abc".to_string(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: outputs,
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components,
+            launches: Default::default(),
+        }
+    }
+
+    const OUTPUTS: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: EC2Instance
+    name: web
+  - type: EC2Instance
+    name: db
+outputs:
+  web_ip: ${EC2Instance.web.public_ip}
+  endpoints: "${EC2Instance.web.public_ip},${EC2Instance.db.private_ip}"
+  db_ip:
+    value: ${EC2Instance.db.private_ip}
+    sensitive: true
+"#;
+
+    #[test]
+    fn test_output_sources_are_the_referenced_components() {
+        let config = InfraConfig::from_yaml(OUTPUTS).unwrap();
+        assert_eq!(
+            output_sources(&config.outputs, &["web_ip".to_string()]),
+            vec!["EC2Instance.web"]
+        );
+        assert_eq!(
+            output_sources(&config.outputs, &[]),
+            vec!["EC2Instance.db", "EC2Instance.web"]
+        );
+        assert!(output_sources(&config.outputs, &["typo".to_string()]).is_empty());
+    }
+
+    #[test]
+    fn test_targets_must_be_in_state() {
+        let state = state(vec![component("web", json!({ "id": "i-1" }))], json!({}));
+        assert!(check_targets(&state, &["EC2Instance.web".to_string()]).is_ok());
+        let err = check_targets(&state, &["EC2Instance.api".to_string()]).unwrap_err();
+        assert!(err.contains("EC2Instance.api"), "{}", err);
+        assert!(err.contains("EC2Instance.web"), "{}", err);
+    }
+
+    #[test]
+    fn test_declared_outputs_are_recomputed_from_every_component() {
+        let config = InfraConfig::from_yaml(OUTPUTS).unwrap();
+        let declarations =
+            output_declarations(config, &VariableOptions::default(), "default").unwrap();
+        let mut state = state(
+            Vec::new(),
+            json!({
+                "EC2Instance.web": { "id": "i-1", "public_ip": "1.1.1.1" },
+                "EC2Instance.db": { "id": "i-2", "private_ip": "10.0.0.5" },
+            }),
+        );
+        refresh_declared_outputs(&mut state, &declarations);
+        assert_eq!(
+            state.outputs.declared["endpoints"].value,
+            "1.1.1.1,10.0.0.5"
+        );
+
+        // Only web was refreshed, the output made of both still sees db
+        state.outputs.value["EC2Instance.web"]["public_ip"] = json!("2.2.2.2");
+        let lines = refresh_declared_outputs(&mut state, &declarations);
+        assert_eq!(
+            lines,
+            vec![
+                "Output endpoints changed: 1.1.1.1,10.0.0.5 -> 2.2.2.2,10.0.0.5",
+                "Output web_ip changed: 1.1.1.1 -> 2.2.2.2",
+            ]
+        );
+        assert_eq!(state.outputs.declared["web_ip"].value, "2.2.2.2");
+
+        // Gone with its component, and a sensitive value is never shown
+        state.outputs.value["EC2Instance.db"]["private_ip"] = json!("10.0.0.6");
+        state
+            .outputs
+            .value
+            .as_object_mut()
+            .unwrap()
+            .remove("EC2Instance.web");
+        let lines = refresh_declared_outputs(&mut state, &declarations);
+        assert_eq!(
+            lines[0],
+            format!("Output db_ip changed {}", SENSITIVE_VALUE)
+        );
+        assert!(!state.outputs.declared.contains_key("web_ip"));
+        assert!(
+            lines.iter().any(|l| l.starts_with("Output web_ip: ")),
+            "{:?}",
+            lines
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use comfy_table::{Cell, Color, Table};
+
+use crate::{
+    aws::{
+        credentials::AwsCredentials,
+        ec2::ec2_instance::EC2Instance,
+        internal::tags::DeploymentTags,
+        sdk_config::{load_sdk_config, use_profile},
+    },
+    commands::{apply, destroy},
+    models::state::State,
+    state as state_file,
+    utils::variables::VariableOptions,
+};
+
+const LOCALSTACK_PROFILE: &str = "localstack";
+const LOCALSTACK_REGION: &str = "us-east-1";
+const LOCALSTACK_AMI: &str = "ami-04c174f38aefd7dc8";
+const AMAZON_LINUX_OWNER: &str = "amazon";
+const AMAZON_LINUX_PATTERN: &str = "al2023-ami-minimal-*-x86_64";
+const SELFTEST_TAG: &str = "letusinfra-selftest";
+const CANARY_TYPE: &str = "EC2Instance";
+const CANARY_NAME: &str = "canary";
+const CANARY_INSTANCE_TYPE: &str = "t3.nano";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Cloud {
+    Aws,
+}
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(flatten)]
+    pub options: Options,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    #[clap(long = "cloud", value_enum)]
+    pub cloud: Cloud,
+    /// Run against the real AWS account of the default profile, this launches a billed t3.nano
+    #[clap(long = "real", conflicts_with = "localstack")]
+    pub real: bool,
+    /// Run against localstack through the `localstack` profile (the default)
+    #[clap(long = "localstack")]
+    pub localstack: bool,
+}
+
+/// One timed step of the self test
+#[derive(Debug)]
+pub struct Step {
+    pub name: &'static str,
+    pub elapsed: Duration,
+    pub result: Result<String, String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub steps: Vec<Step>,
+    pub leaked: Vec<String>, // Ids of resources that could not be cleaned up
+    /// Workspace of a run that leaked, its config and state can still destroy what is left
+    pub kept: Option<PathBuf>,
+}
+
+impl Report {
+    fn record<T>(
+        &mut self,
+        name: &'static str,
+        started: Instant,
+        result: Result<T, String>,
+        describe: impl Fn(&T) -> String,
+    ) -> Option<T> {
+        let elapsed = started.elapsed();
+        let (step, value) = match result {
+            Ok(value) => (Ok(describe(&value)), Some(value)),
+            Err(err) => (Err(err), None),
+        };
+        self.steps.push(Step {
+            name,
+            elapsed,
+            result: step,
+        });
+        value
+    }
+
+    pub fn passed(&self) -> bool {
+        self.leaked.is_empty() && self.steps.iter().all(|s| s.result.is_ok())
+    }
+
+    pub fn render(&self) -> String {
+        let mut table = Table::new();
+        table.load_preset(comfy_table::presets::NOTHING);
+        table.set_header(vec!["Step", "Result", "Time", "Details"]);
+        for step in &self.steps {
+            let (result, details) = match &step.result {
+                Ok(details) => (Cell::new("pass").fg(Color::Green), details.as_str()),
+                Err(err) => (Cell::new("FAIL").fg(Color::Red), err.as_str()),
+            };
+            table.add_row(vec![
+                Cell::new(step.name),
+                result,
+                Cell::new(format!("{:.1?}", step.elapsed)),
+                Cell::new(details),
+            ]);
+        }
+        let mut output = table.to_string();
+        for id in &self.leaked {
+            output.push_str(&format!(
+                "\nLeaked resource {} (tag {}), terminate it manually",
+                id, SELFTEST_TAG
+            ));
+        }
+        if let Some(dir) = &self.kept {
+            output.push_str(&format!(
+                "\nConfig and state of the run are kept in {}",
+                dir.display()
+            ));
+        }
+        output
+    }
+}
+
+/// Throwaway directory the canary config and its state are written to, one per run
+struct Workspace {
+    name: String,
+    dir: PathBuf,
+}
+
+impl Workspace {
+    fn create(region: &str, image_id: &str) -> Result<Self, String> {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let name = format!("selftest-{}", started);
+        let dir = std::env::temp_dir().join(format!("letusinfra-{}", name));
+        let workspace = Workspace { name, dir };
+        std::fs::create_dir_all(&workspace.dir)
+            .and_then(|_| {
+                std::fs::write(
+                    workspace.config_file(),
+                    canary_config(region, image_id, CANARY_INSTANCE_TYPE),
+                )
+            })
+            .map_err(|err| format!("Failed to write {}: {}", workspace.dir.display(), err))?;
+        Ok(workspace)
+    }
+
+    fn config_file(&self) -> String {
+        self.dir.join("infra.yml").display().to_string()
+    }
+
+    fn state_file(&self) -> String {
+        self.dir.join("state.json").display().to_string()
+    }
+
+    /// Tag apply puts on the canary, finds it even when the run never recorded its id
+    fn component_id(&self) -> String {
+        DeploymentTags {
+            deployment: SELFTEST_TAG.to_string(),
+            workspace: self.name.clone(),
+            component_type: CANARY_TYPE.to_string(),
+            component_name: CANARY_NAME.to_string(),
+        }
+        .component_id()
+    }
+
+    fn load_state(&self) -> Result<Option<State>, String> {
+        state_file::load(self.dir.join("state.json").as_path()).map_err(|err| err.to_string())
+    }
+}
+
+fn canary_config(region: &str, image_id: &str, instance_type: &str) -> String {
+    format!(
+        r#"version: v1
+kind: Infra
+cloud: AWS
+region: {}
+metadata:
+  name: {}
+components:
+  - type: {}
+    name: {}
+    properties:
+      ami: {}
+      instance_type: {}
+      tag_specifications:
+        - resource_type: instance
+          tags:
+            Name: {}
+"#,
+        region, SELFTEST_TAG, CANARY_TYPE, CANARY_NAME, image_id, instance_type, SELFTEST_TAG
+    )
+}
+
+/// Id of the canary instance, once state and outputs agree on it
+fn verify_state(state: Option<&State>) -> Result<String, String> {
+    let key = format!("{}.{}", CANARY_TYPE, CANARY_NAME);
+    let state = state.ok_or_else(|| "apply wrote no state".to_string())?;
+    let recorded = state
+        .components
+        .iter()
+        .find(|c| c.key() == key)
+        .and_then(|c| c.instances.first())
+        .and_then(|instance| instance.get("id"))
+        .and_then(|id| id.as_str())
+        .ok_or_else(|| format!("state has no instance id for {}", key))?;
+    let output = state
+        .outputs
+        .value
+        .get(&key)
+        .and_then(|outputs| outputs.get("id"))
+        .and_then(|id| id.as_str());
+    if output != Some(recorded) {
+        return Err(format!(
+            "state records {} but the outputs hold {:?}",
+            recorded, output
+        ));
+    }
+    Ok(recorded.to_string())
+}
+
+/**
+ * Apply a canary instance into a throwaway workspace, check the state and the instance, destroy
+ * it and confirm it is gone. Apply and destroy are the commands themselves, so a pass covers the
+ * CLI, the AWS calls and the state. Once apply has run, destroy and cleanup always run, anything
+ * left with the canary's tag is reported as leaked.
+ */
+pub async fn execute(config: &Config) -> Report {
+    let options = &config.options;
+    let mut report = Report::default();
+
+    let started = Instant::now();
+    let (region, target) = if options.real {
+        let region = AwsCredentials::get_region_or_default("us-east-1");
+        (region.clone(), format!("AWS {}", region))
+    } else {
+        use_profile(LOCALSTACK_PROFILE);
+        (
+            LOCALSTACK_REGION.to_string(),
+            format!("localstack profile '{}'", LOCALSTACK_PROFILE),
+        )
+    };
+    let sdk_config = load_sdk_config(&region)
+        .await
+        .map_err(|err| err.to_string());
+    let Some(sdk_config) = report.record("configure", started, sdk_config, |_| target.clone())
+    else {
+        return report;
+    };
+    let ec2 = EC2Instance::from_config(&sdk_config);
+    let image_id = if options.real {
+        let started = Instant::now();
+        let image = ec2
+            .find_latest_image(AMAZON_LINUX_OWNER, AMAZON_LINUX_PATTERN)
+            .await
+            .map_err(|err| err.to_string());
+        let Some(image_id) = report.record("find image", started, image, |id| id.clone()) else {
+            return report;
+        };
+        image_id
+    } else {
+        LOCALSTACK_AMI.to_string()
+    };
+
+    let started = Instant::now();
+    let workspace = Workspace::create(&region, &image_id);
+    let Some(workspace) = report.record("prepare workspace", started, workspace, |w| {
+        w.dir.display().to_string()
+    }) else {
+        return report;
+    };
+
+    let started = Instant::now();
+    let applied = apply::execute(&apply::Config {
+        options: apply::Options {
+            file_path: workspace.config_file(),
+            variables: VariableOptions::default(),
+            workspace: Some(workspace.name.clone()),
+            state_file: Some(workspace.state_file()),
+            targets: Vec::new(),
+            parallelism: 1,
+            shutdown_timeout: 300,
+            dry_run: false,
+            auto_approve: true,
+            deployment: None,
+        },
+    })
+    .await
+    .map_err(|err| err.to_string());
+    if report
+        .record("apply", started, applied, |_| {
+            format!("{} {}", CANARY_INSTANCE_TYPE, workspace.name)
+        })
+        .is_none()
+    {
+        // The instance may have been launched before apply failed, find it by its tag
+        confirm_cleanup(&mut report, &ec2, &workspace, None).await;
+        return report;
+    }
+
+    let started = Instant::now();
+    let state = workspace.load_state();
+    let instance_id = state
+        .as_ref()
+        .map_err(|err| err.clone())
+        .and_then(|state| verify_state(state.as_ref()));
+    let instance_id = report.record("verify state", started, instance_id, |id| {
+        format!("{} recorded", id)
+    });
+
+    if let Some(instance_id) = &instance_id {
+        let recorded_ip = state.ok().flatten().and_then(|state| {
+            state
+                .outputs
+                .value
+                .get(format!("{}.{}", CANARY_TYPE, CANARY_NAME))
+                .and_then(|outputs| outputs.get("private_ip"))
+                .and_then(|ip| ip.as_str().map(|ip| ip.to_string()))
+        });
+        let started = Instant::now();
+        let verified = ec2
+            .describe_instance(instance_id)
+            .await
+            .map_err(|err| err.to_string())
+            .and_then(|i| {
+                let state = i
+                    .state()
+                    .and_then(|s| s.name())
+                    .map(|n| n.as_str().to_string());
+                match (state.as_deref(), i.private_ip_address()) {
+                    (Some("running"), Some(ip)) if recorded_ip.as_deref() == Some(ip) => {
+                        Ok(format!("running, private ip {}", ip))
+                    }
+                    (Some("running"), Some(ip)) => Err(format!(
+                        "private ip {} does not match the output {:?}",
+                        ip, recorded_ip
+                    )),
+                    (other, _) => Err(format!("unexpected state {:?} or missing address", other)),
+                }
+            });
+        report.record("verify outputs", started, verified, |details| {
+            details.clone()
+        });
+    }
+
+    let started = Instant::now();
+    let destroyed = destroy::execute(&destroy::Config {
+        options: destroy::Options {
+            file_path: workspace.config_file(),
+            variables: VariableOptions::default(),
+            workspace: Some(workspace.name.clone()),
+            targets: Vec::new(),
+            auto_approve: true,
+            yes_i_mean_it: true,
+            resource_ids: Vec::new(),
+            state_file: Some(workspace.state_file()),
+            keep_in_state: false,
+            parallelism: 4,
+            deployment: None,
+        },
+    })
+    .await
+    .map_err(|err| err.to_string());
+    report.record("destroy", started, destroyed, |_| "destroyed".to_string());
+
+    confirm_cleanup(&mut report, &ec2, &workspace, instance_id.as_deref()).await;
+    report
+}
+
+/**
+ * Look for anything left with the canary's tag and check destroy emptied the state. What is left
+ * is leaked, the workspace is then kept for a manual destroy and removed otherwise.
+ */
+async fn confirm_cleanup(
+    report: &mut Report,
+    ec2: &EC2Instance,
+    workspace: &Workspace,
+    instance_id: Option<&str>,
+) {
+    let started = Instant::now();
+    let remaining = ec2
+        .find_component_instances(&workspace.component_id())
+        .await
+        .map(|instances| {
+            instances
+                .iter()
+                .filter_map(|i| i.instance_id().map(|id| id.to_string()))
+                .collect::<Vec<String>>()
+        });
+    let recorded = workspace
+        .load_state()
+        .map(|state| state.is_some_and(|state| !state.components.is_empty()));
+    let cleanup = match (remaining, recorded) {
+        (Ok(remaining), _) if !remaining.is_empty() => {
+            report.leaked.extend(remaining.iter().cloned());
+            Err(format!("{} is still there", remaining.join(", ")))
+        }
+        (Ok(_), Ok(true)) => Err("state still lists the canary".to_string()),
+        (Ok(_), Ok(false)) => Ok("gone".to_string()),
+        (Ok(_), Err(err)) => Err(err),
+        (Err(err), _) => {
+            // Without the lookup only the id apply recorded can be reported
+            report.leaked.extend(instance_id.map(|id| id.to_string()));
+            Err(err.to_string())
+        }
+    };
+    report.record("confirm cleanup", started, cleanup, |details| {
+        details.clone()
+    });
+
+    if report.leaked.is_empty() {
+        let _ = std::fs::remove_dir_all(&workspace.dir);
+    } else {
+        report.kept = Some(workspace.dir.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{commands::validate::validate_infra_config, models::InfraConfig};
+
+    #[test]
+    fn test_report_fails_on_any_failed_step_or_leak() {
+        let mut report = Report::default();
+        report.record("apply", Instant::now(), Ok::<_, String>(()), |_| {
+            "ok".to_string()
+        });
+        assert!(report.passed());
+
+        report.leaked.push("i-123".to_string());
+        report.kept = Some(PathBuf::from("/tmp/letusinfra-selftest-1"));
+        assert!(!report.passed());
+        assert!(report.render().contains("Leaked resource i-123"));
+        assert!(
+            report
+                .render()
+                .contains("kept in /tmp/letusinfra-selftest-1")
+        );
+
+        let mut report = Report::default();
+        let value: Option<()> =
+            report.record("destroy", Instant::now(), Err("boom".to_string()), |_| {
+                String::new()
+            });
+        assert!(value.is_none());
+        assert!(!report.passed());
+        assert!(report.render().contains("boom"));
+    }
+
+    #[test]
+    fn test_canary_config_is_valid() {
+        let config =
+            InfraConfig::from_yaml(&canary_config("us-east-1", "ami-123", "t3.nano")).unwrap();
+        validate_infra_config(&config).unwrap();
+        let canary = &config.components[0];
+        assert_eq!(canary.key(), "EC2Instance.canary");
+        let opts = EC2Instance::opts_from_yaml(&canary.properties).unwrap();
+        assert_eq!(opts.image_id, "ami-123");
+        assert_eq!(opts.instance_type.as_str(), "t3.nano");
+    }
+
+    #[test]
+    fn test_state_must_record_the_canary() {
+        let state = |outputs: serde_json::Value| -> State {
+            serde_json::from_value(serde_json::json!({
+                "version": "1",
+                "serial": 1,
+                "lineage": "l",
+                "modulepack": SELFTEST_TAG,
+                "outputs": {"value": outputs, "output_type": {"datatype": "object", "value": {}}},
+                "components": [{
+                    "mode": "managed",
+                    "name": "canary",
+                    "type": "EC2Instance",
+                    "provider": "aws",
+                    "instances": [{"id": "i-1"}],
+                }],
+            }))
+            .unwrap()
+        };
+        assert!(verify_state(None).is_err());
+        let recorded = state(serde_json::json!({"EC2Instance.canary": {"id": "i-1"}}));
+        assert_eq!(verify_state(Some(&recorded)).unwrap(), "i-1");
+        let mismatched = state(serde_json::json!({"EC2Instance.canary": {"id": "i-2"}}));
+        assert!(verify_state(Some(&mismatched)).unwrap_err().contains("i-2"));
+    }
+}
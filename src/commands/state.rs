@@ -0,0 +1,531 @@
+use std::{io::IsTerminal, path::Path};
+
+use crate::{
+    commands::destroy::{Confirmation, confirm},
+    models::{ConfigFormat, InfraConfig, state::State},
+    state::{
+        self as state_file, STATE_VERSION, StateError,
+        backend::{Backend, StateBackend},
+    },
+    utils::{
+        constants::SENSITIVE_VALUE, read_config_source, redact::redact, schema, select_document,
+        select_workspace,
+    },
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Rewrite the state in the format of this letusinfra, keeping a copy of it as it was
+    Upgrade(Options),
+    /// Print the address of every resource in the state
+    List(Options),
+    /// Print the stored attributes of a resource, sensitive values masked
+    Show {
+        /// Resource address, <type>.<name>
+        address: String,
+        #[clap(flatten)]
+        options: Options,
+    },
+    /// Remove a resource from the state without destroying it
+    Rm {
+        /// Resource address, <type>.<name>
+        address: String,
+        /// Skip the confirmation prompt
+        #[clap(long = "auto-approve")]
+        auto_approve: bool,
+        #[clap(flatten)]
+        options: Options,
+    },
+    /// Move a resource to a new address, after its component was renamed in the config
+    Mv {
+        /// Current address, <type>.<name>
+        old: String,
+        /// New address, <type>.<name>
+        new: String,
+        #[clap(flatten)]
+        options: Options,
+    },
+    /// Store a state file in the backend, such as the errored.state.json of a failed save
+    Push {
+        /// State file to store
+        file: String,
+        /// Store it even when it belongs to another state or is older than the stored one
+        #[clap(long = "force")]
+        force: bool,
+        #[clap(flatten)]
+        options: Options,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// Config whose `backend:` block says where the state is, the local state when left out
+    #[clap(short = 'f', long = "filepath")]
+    pub file_path: Option<String>,
+    /// Workspace of the state, overrides LETUS_WORKSPACE
+    #[clap(long = "workspace")]
+    pub workspace: Option<String>,
+    /// State to use, defaults to .letusinfra/<workspace>/state.json
+    #[clap(long = "state-file")]
+    pub state_file: Option<String>,
+    /// Deployment whose state to use, when `-f` applied several. Its state is
+    /// .letusinfra/<workspace>/<deployment>/state.json
+    #[clap(long = "deployment")]
+    pub deployment: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateCommandError {
+    #[error("Failed to read file: {0}")]
+    FileRead(String),
+    #[error("Failed to parse config into InfraConfig: {0}")]
+    Parse(String),
+    #[error("{0}")]
+    Config(String),
+    #[error("No state at {0}")]
+    NoState(String),
+    #[error("{0}")]
+    Address(String),
+    #[error("Removal cancelled")]
+    Cancelled,
+    #[error(
+        "Cannot ask for confirmation, stdin is not a terminal. Pass --auto-approve to remove without a prompt"
+    )]
+    NotInteractive,
+    #[error("{0}. Pass --force to push it anyway")]
+    Push(String),
+    #[error("{0}")]
+    State(#[from] StateError),
+}
+
+pub async fn execute(config: &Config) -> Result<(), StateCommandError> {
+    match &config.command {
+        Command::Upgrade(options) => {
+            let mut backend = open_backend(options).await?.0;
+            backend.lock("state upgrade").await?;
+            let upgraded = upgrade(&backend).await;
+            state_file::backend::unlock_after(&mut backend, upgraded).await
+        }
+        Command::List(options) => {
+            let backend = open_backend(options).await?.0;
+            let state = load(&backend).await?;
+            for component in &state.components {
+                println!("{}", component.key());
+            }
+            Ok(())
+        }
+        Command::Show { address, options } => {
+            let (backend, config) = open_backend(options).await?;
+            let state = load(&backend).await?;
+            println!("{}", show(&state, address, config.as_ref())?);
+            Ok(())
+        }
+        Command::Rm {
+            address,
+            auto_approve,
+            options,
+        } => {
+            let mut backend = open_backend(options).await?.0;
+            backend.lock("state rm").await?;
+            let removed: Result<(), StateCommandError> = async {
+                let mut state = load(&backend).await?;
+                let summary = format!(
+                    "{} will be removed from the state {}, the resource itself is left as it is.",
+                    address,
+                    backend.describe()
+                );
+                remove(&mut state, address)?;
+                let confirmation = match *auto_approve {
+                    true => Confirmation::Skip,
+                    false => Confirmation::Forget,
+                };
+                // Reading the answer from a pipe would hang or take whatever the pipe happens to contain
+                if confirmation != Confirmation::Skip && !std::io::stdin().is_terminal() {
+                    return Err(StateCommandError::NotInteractive);
+                }
+                if !confirm(
+                    confirmation,
+                    &state.modulepack,
+                    &summary,
+                    &mut std::io::stdin().lock(),
+                    &mut std::io::stdout(),
+                ) {
+                    return Err(StateCommandError::Cancelled);
+                }
+                let saved = backend.save(state).await?;
+                println!(
+                    "Removed {} from the state (serial {})",
+                    address, saved.serial
+                );
+                Ok(())
+            }
+            .await;
+            state_file::backend::unlock_after(&mut backend, removed).await
+        }
+        Command::Mv { old, new, options } => {
+            let mut backend = open_backend(options).await?.0;
+            backend.lock("state mv").await?;
+            let moved: Result<(), StateCommandError> = async {
+                let mut state = load(&backend).await?;
+                rename(&mut state, old, new)?;
+                let saved = backend.save(state).await?;
+                println!("Moved {} to {} (serial {})", old, new, saved.serial);
+                Ok(())
+            }
+            .await;
+            state_file::backend::unlock_after(&mut backend, moved).await
+        }
+        Command::Push {
+            file,
+            force,
+            options,
+        } => {
+            let pushed = state_file::load(Path::new(file))?
+                .ok_or_else(|| StateCommandError::NoState(file.clone()))?;
+            let mut backend = open_backend(options).await?.0;
+            backend.lock("state push").await?;
+            let stored: Result<(), StateCommandError> = async {
+                check_push(&pushed, backend.load().await?.as_ref(), *force)?;
+                let saved = backend.save(pushed).await?;
+                println!(
+                    "Pushed {} to {} (serial {})",
+                    file,
+                    backend.describe(),
+                    saved.serial
+                );
+                Ok(())
+            }
+            .await;
+            state_file::backend::unlock_after(&mut backend, stored).await
+        }
+    }
+}
+
+/**
+ * The `-f` config, if one was given, and the deployment whose state to use. A `-f` holding
+ * several deployments needs --deployment to pick one.
+ */
+fn read_config(
+    options: &Options,
+) -> Result<(Option<InfraConfig>, Option<String>), StateCommandError> {
+    let Some(file_path) = &options.file_path else {
+        return Ok((None, options.deployment.clone()));
+    };
+    let (document, deployment) = select_document(file_path, options.deployment.as_deref())
+        .map_err(StateCommandError::Config)?;
+    let content = read_config_source(&document.source)
+        .map_err(|err| StateCommandError::FileRead(err.to_string()))?;
+    let config = InfraConfig::parse(&content, ConfigFormat::detect(&document.source, &content))
+        .map_err(|err| StateCommandError::Parse(err.to_string()))?;
+    Ok((Some(config), deployment))
+}
+
+/**
+ * Backend of the `-f` config, or the one LETUS_BACKEND_CONFIG or the defaults point at, together
+ * with the config
+ */
+async fn open_backend(
+    options: &Options,
+) -> Result<(Backend, Option<InfraConfig>), StateCommandError> {
+    let (config, deployment) = read_config(options)?;
+    let workspace =
+        select_workspace(options.workspace.as_deref()).map_err(StateCommandError::Config)?;
+    let backend = state_file::backend::open(
+        config.as_ref().and_then(|c| c.backend.as_ref()),
+        options.state_file.as_deref(),
+        &workspace,
+        deployment.as_deref(),
+        config.as_ref().map(|c| c.region.as_str()),
+    )
+    .await?;
+    Ok((backend, config))
+}
+
+async fn load(backend: &Backend) -> Result<State, StateCommandError> {
+    backend
+        .load()
+        .await?
+        .ok_or_else(|| StateCommandError::NoState(backend.describe()))
+}
+
+/// Index of the resource at `address`, an unknown address lists the close matches in the state
+fn find(state: &State, address: &str) -> Result<usize, StateCommandError> {
+    if let Some(index) = state.components.iter().position(|c| c.key() == address) {
+        return Ok(index);
+    }
+    let addresses: Vec<String> = state.components.iter().map(|c| c.key()).collect();
+    let mut message = format!("No resource '{}' in the state", address);
+    if !address.contains('.') {
+        message.push_str(", expected <type>.<name>");
+    }
+    // A bare name is matched against the names, anything else against the whole addresses
+    let mut matches: Vec<&str> = state
+        .components
+        .iter()
+        .zip(&addresses)
+        .filter(|(c, _)| c.name == address)
+        .map(|(_, a)| a.as_str())
+        .collect();
+    matches.extend(schema::suggest(
+        address,
+        addresses.iter().map(|a| a.as_str()),
+    ));
+    if !matches.is_empty() {
+        message.push_str(&format!(". Did you mean: {}?", matches.join(", ")));
+    }
+    Err(StateCommandError::Address(message))
+}
+
+/**
+ * Attributes of the resource at `address` as pretty JSON. The attributes the schema flags as
+ * sensitive, and those listed under `sensitive:` of the component in the config, are masked.
+ */
+fn show(
+    state: &State,
+    address: &str,
+    config: Option<&InfraConfig>,
+) -> Result<String, StateCommandError> {
+    let component = &state.components[find(state, address)?];
+    let paths = match config.and_then(|c| c.components.iter().find(|c| c.key() == address)) {
+        Some(declared) => schema::sensitive_paths(declared),
+        None => schema::flagged_sensitive(&component.component_type),
+    };
+    let instances: Vec<serde_json::Value> = component
+        .instances
+        .iter()
+        .map(|instance| {
+            serde_yaml::to_value(instance)
+                .ok()
+                .and_then(|yaml| serde_json::to_value(schema::mask_paths(&yaml, &paths)).ok())
+                // Nothing of an instance that cannot be masked is shown
+                .unwrap_or_else(|| serde_json::Value::String(SENSITIVE_VALUE.to_string()))
+        })
+        .collect();
+    let shown = serde_json::json!({
+        "address": address,
+        "mode": component.mode,
+        "provider": component.provider,
+        "instances": instances,
+    });
+    let rendered = serde_json::to_string_pretty(&shown).unwrap_or_default();
+    // Secrets in attributes no schema knows about are caught by the redactor
+    Ok(redact(&rendered))
+}
+
+/// Drop the resource at `address` and its outputs from the state, the resource is not touched
+fn remove(state: &mut State, address: &str) -> Result<(), StateCommandError> {
+    let index = find(state, address)?;
+    state.components.remove(index);
+    if let Some(values) = state.outputs.value.as_object_mut() {
+        values.remove(address);
+    }
+    // The declared outputs point at components, with none left they only show stale values
+    if state.components.is_empty() {
+        state.outputs.declared.clear();
+    }
+    Ok(())
+}
+
+/// Move the resource at `old` to `new`, a component renamed in the config keeps its resource
+fn rename(state: &mut State, old: &str, new: &str) -> Result<(), StateCommandError> {
+    let index = find(state, old)?;
+    let Some((component_type, name)) = new.split_once('.').filter(|(_, n)| !n.is_empty()) else {
+        return Err(StateCommandError::Address(format!(
+            "Invalid address '{}', expected <type>.<name>",
+            new
+        )));
+    };
+    if component_type != state.components[index].component_type {
+        return Err(StateCommandError::Address(format!(
+            "Cannot move {} to {}, a resource keeps its type",
+            old, new
+        )));
+    }
+    if state.components.iter().any(|c| c.key() == new) {
+        return Err(StateCommandError::Address(format!(
+            "{} is already in the state",
+            new
+        )));
+    }
+    state.components[index].name = name.to_string();
+    let output = state
+        .outputs
+        .value
+        .as_object_mut()
+        .and_then(|values| values.remove(old));
+    if let (Some(values), Some(output)) = (state.outputs.value.as_object_mut(), output) {
+        values.insert(new.to_string(), output);
+    }
+    Ok(())
+}
+
+/**
+ * Refuse to push a state of another lineage, or one older than the stored state, over it. An
+ * errored state has no serial or lineage yet, it gets those of the stored state when saved.
+ */
+fn check_push(
+    pushed: &State,
+    stored: Option<&State>,
+    force: bool,
+) -> Result<(), StateCommandError> {
+    let Some(stored) = stored.filter(|_| !force) else {
+        return Ok(());
+    };
+    if !pushed.lineage.is_empty() && !stored.lineage.is_empty() && pushed.lineage != stored.lineage
+    {
+        return Err(StateCommandError::Push(format!(
+            "The state has lineage {}, the stored one {}",
+            pushed.lineage, stored.lineage
+        )));
+    }
+    if pushed.serial != 0 && pushed.serial < stored.serial {
+        return Err(StateCommandError::Push(format!(
+            "The state has serial {}, older than the stored serial {}",
+            pushed.serial, stored.serial
+        )));
+    }
+    Ok(())
+}
+
+async fn upgrade(backend: &Backend) -> Result<(), StateCommandError> {
+    if backend.load().await?.is_none() {
+        println!("No state at {}, nothing to upgrade", backend.describe());
+        return Ok(());
+    }
+    let Some(upgraded) = backend.upgrade().await? else {
+        println!(
+            "State {} is already version {}, nothing to upgrade",
+            backend.describe(),
+            STATE_VERSION
+        );
+        return Ok(());
+    };
+    println!(
+        "Upgraded state {} from version {} to version {}:",
+        backend.describe(),
+        upgraded.from,
+        STATE_VERSION
+    );
+    for step in &upgraded.steps {
+        println!("  - {}", step);
+    }
+    println!("The state as it was is kept in {}", upgraded.backup);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, ComponentState, Datatype, Output, OutputType};
+    use serde_json::json;
+
+    fn component(component_type: &str, name: &str, instance: serde_json::Value) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: name.to_string(),
+            component_type: component_type.to_string(),
+            provider: "aws".to_string(),
+            instances: vec![instance],
+            created_at: None,
+            properties_hash: None,
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    fn state() -> State {
+        let web = component(
+            "EC2Instance",
+            "web",
+            json!({ "id": "i-1", "user_data": "echo secret", "public_ip": "1.1.1.1" }),
+        );
+        let vpc = component("VPC", "main", json!({ "id": "vpc-1" }));
+        State {
+            version: STATE_VERSION.to_string(),
+            serial: 4,
+            lineage: "abc".to_string(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: json!({
+                    "EC2Instance.web": web.instances[0].clone(),
+                    "VPC.main": vpc.instances[0].clone(),
+                }),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![web, vpc],
+            launches: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_unknown_address_lists_close_matches() {
+        let err = find(&state(), "EC2Instance.wbe").unwrap_err().to_string();
+        assert!(err.contains("Did you mean: EC2Instance.web?"), "{}", err);
+        let err = find(&state(), "web").unwrap_err().to_string();
+        assert!(err.contains("expected <type>.<name>"), "{}", err);
+        assert!(err.contains("Did you mean: EC2Instance.web?"), "{}", err);
+    }
+
+    #[test]
+    fn test_show_masks_sensitive_attributes() {
+        let shown = show(&state(), "EC2Instance.web", None).unwrap();
+        assert!(!shown.contains("echo secret"), "{}", shown);
+        assert!(shown.contains(SENSITIVE_VALUE), "{}", shown);
+        assert!(shown.contains("1.1.1.1"), "{}", shown);
+    }
+
+    #[test]
+    fn test_remove_drops_the_resource_and_its_outputs() {
+        let mut state = state();
+        remove(&mut state, "EC2Instance.web").unwrap();
+        let keys: Vec<String> = state.components.iter().map(|c| c.key()).collect();
+        assert_eq!(keys, vec!["VPC.main"]);
+        assert!(state.outputs.value.get("EC2Instance.web").is_none());
+        assert!(remove(&mut state, "EC2Instance.web").is_err());
+    }
+
+    #[test]
+    fn test_rename_moves_the_resource_and_its_outputs() {
+        let mut state = state();
+        rename(&mut state, "EC2Instance.web", "EC2Instance.frontend").unwrap();
+        assert_eq!(state.components[0].key(), "EC2Instance.frontend");
+        assert_eq!(state.outputs.value["EC2Instance.frontend"]["id"], "i-1");
+        assert!(state.outputs.value.get("EC2Instance.web").is_none());
+
+        // The type stays, the target must be free and well formed
+        assert!(rename(&mut state, "EC2Instance.frontend", "VPC.frontend").is_err());
+        assert!(rename(&mut state, "VPC.main", "VPC.main").is_err());
+        assert!(rename(&mut state, "VPC.main", "VPC.").is_err());
+    }
+
+    #[test]
+    fn test_check_push_refuses_another_lineage_or_an_older_serial() {
+        let stored = state();
+        let mut errored = state();
+        (errored.serial, errored.lineage) = (0, String::new());
+        assert!(check_push(&errored, Some(&stored), false).is_ok());
+        assert!(check_push(&errored, None, false).is_ok());
+
+        let mut other = state();
+        other.lineage = "def".to_string();
+        assert!(check_push(&other, Some(&stored), false).is_err());
+        assert!(check_push(&other, Some(&stored), true).is_ok());
+
+        let mut older = state();
+        older.serial = 3;
+        assert!(check_push(&older, Some(&stored), false).is_err());
+        assert!(check_push(&older, Some(&stored), true).is_ok());
+        assert!(check_push(&stored, Some(&stored), false).is_ok());
+    }
+}
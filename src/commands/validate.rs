@@ -1,4 +1,15 @@
-use crate::models::InfraConfig;
+use crate::{
+    models::{ConfigFormat, InfraConfig, PlanError},
+    utils::{
+        config_documents,
+        diagnostics::Diagnostic,
+        read_config_source,
+        redact::{Redactor, redact},
+        schema,
+        templates::resolve_templates,
+        variables::{VariableError, VariableOptions, bind_variables, check_declarations},
+    },
+};
 
 #[derive(clap::Args, Debug)]
 #[command(version, about, long_about = None)]
@@ -11,43 +22,73 @@ pub struct Config {
 pub struct Options {
     #[clap(short = 'f', long = "filepath")]
     pub file_path: String,
+    #[clap(flatten)]
+    pub variables: VariableOptions,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ValidationError {
     #[error("Failed to read file: {0}")]
     FileReadError(String),
-    #[error("Failed to parse YAML: {0}")]
+    #[error("Failed to parse config: {0}")]
     YamlParseError(String),
     #[error("InfraConfig validation error: {0}")]
     InfraConfigValidationError(String),
+    #[error("Invalid component properties:\n{}", describe_errors(.0))]
+    Properties(Vec<PlanError>),
+    #[error("Invalid variables:\n{}", describe_errors(.0))]
+    Variables(Vec<VariableError>),
 }
 
-pub fn execute(config: &Config) -> Result<(), ValidationError> {
-    println!("Executing validate command with config: {:?}", config);
+fn describe_errors<E: Diagnostic + std::fmt::Display>(errors: &[E]) -> String {
+    errors
+        .iter()
+        .map(|err| format!("[{}] {}", err.code(), err))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
+pub fn execute(config: &Config) -> Result<(), ValidationError> {
     let file_path = &config.options.file_path;
-    println!("File path is: {}", file_path);
-    validate_file(file_path)?;
+    let documents =
+        config_documents(file_path).map_err(ValidationError::InfraConfigValidationError)?;
+    for document in &documents {
+        if documents.len() > 1 {
+            println!(
+                "\n=== Deployment {} ({}) ===",
+                document.name, document.source
+            );
+        }
+        validate_file(&document.source, &config.options.variables)?;
+        println!("InfraConfig validation passed");
+    }
     Ok(())
 }
-pub fn validate_file(file_path: &str) -> Result<(), ValidationError> {
-    let content = match std::fs::read_to_string(file_path) {
+/// Validate the config and the variable values given for it
+pub fn validate_file(file_path: &str, variables: &VariableOptions) -> Result<(), ValidationError> {
+    let content = match read_config_source(file_path) {
         Ok(content) => content,
         Err(err) => {
-            eprintln!("Failed to read file: {}", err);
+            eprintln!("Failed to read file: {}", redact(&err.to_string()));
             return Err(ValidationError::FileReadError(err.to_string()));
         }
     };
 
     // Try to parse using the structured model
-    match InfraConfig::from_yaml(&content) {
-        Ok(config) => {
-            println!("Successfully parsed YAML using InfraConfig model");
-            return validate_infra_config(&config);
+    match InfraConfig::parse(&content, ConfigFormat::detect(file_path, &content)) {
+        Ok(mut config) => {
+            tracing::debug!("Successfully parsed YAML using InfraConfig model");
+            resolve_templates(&mut config, file_path)
+                .map_err(ValidationError::InfraConfigValidationError)?;
+            validate_infra_config(&config)?;
+            return bind_variables(&mut config, variables)
+                .map_err(|err| ValidationError::Variables(vec![err]));
         }
         Err(err) => {
-            eprintln!("Failed to parse YAML into InfraConfig: {}", err);
+            eprintln!(
+                "Failed to parse config into InfraConfig: {}",
+                redact(&err.to_string())
+            );
             return Err(ValidationError::YamlParseError(err.to_string()));
         }
     }
@@ -99,6 +140,32 @@ pub fn validate_infra_config(config: &InfraConfig) -> Result<(), ValidationError
         }
     }
 
-    println!("InfraConfig validation passed");
+    // Properties are checked against the schema of their type, other types only get the
+    // structural checks above and fail later at plan
+    let mut errors = Vec::new();
+    for component in &config.components {
+        match schema::validate_component(component) {
+            Some(component_errors) => errors.extend(component_errors),
+            None => eprintln!(
+                "No schema for component type '{}', only structural checks were run for {}",
+                component.component_type,
+                component.key()
+            ),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(ValidationError::Properties(errors));
+    }
+    let variable_errors = check_declarations(config);
+    if !variable_errors.is_empty() {
+        return Err(ValidationError::Variables(variable_errors));
+    }
+
+    if let Err(err) = Redactor::new(&config.redact) {
+        eprintln!("{}", redact(&err.to_string()));
+        return Err(ValidationError::InfraConfigValidationError(err.to_string()));
+    }
+
+    tracing::debug!("InfraConfig validation passed");
     Ok(())
 }
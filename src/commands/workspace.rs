@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use crate::{
+    state,
+    utils::{constants::STATE_DIR, current_workspace},
+};
+
+#[derive(clap::Args, Debug)]
+#[command(version, about, long_about = None)]
+pub struct Config {
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// List the workspaces that have a state, the current one is marked with `*`
+    List(Options),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct Options {
+    /// Directory holding the state of every workspace
+    #[clap(long = "state-dir", default_value = STATE_DIR)]
+    pub state_dir: String,
+}
+
+pub fn execute(config: &Config) -> Result<(), String> {
+    match &config.command {
+        Command::List(options) => {
+            let workspaces = state::list_workspaces(Path::new(&options.state_dir))
+                .map_err(|err| err.to_string())?;
+            print!("{}", render_list(&workspaces, &current_workspace()));
+            Ok(())
+        }
+    }
+}
+
+/// One workspace per line, the current one is listed even before it has a state
+fn render_list(workspaces: &[String], current: &str) -> String {
+    let mut names: Vec<&str> = workspaces.iter().map(|w| w.as_str()).collect();
+    if !names.contains(&current) {
+        names.push(current);
+        names.sort();
+    }
+    names
+        .iter()
+        .map(|name| {
+            let marker = if *name == current { "*" } else { " " };
+            format!("{} {}\n", marker, name)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_workspace_is_marked() {
+        let workspaces = vec!["prod".to_string(), "staging".to_string()];
+        assert_eq!(render_list(&workspaces, "staging"), "  prod\n* staging\n");
+        assert_eq!(
+            render_list(&workspaces, "default"),
+            "* default\n  prod\n  staging\n"
+        );
+    }
+}
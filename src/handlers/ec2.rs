@@ -0,0 +1,687 @@
+use std::time::Duration;
+
+use aws_sdk_ec2::types::{Instance, InstanceStateName};
+
+use crate::{
+    aws::{
+        ec2::{
+            ec2_instance::{
+                AmiLookup, DestroyOutcome, EC2Error, EC2Instance, InstanceChanges, InstanceOpts,
+            },
+            elastic_ip::ElasticIp,
+            key_pair::{KeyPair, write_private_key},
+            security_group::SecurityGroup,
+            subnet::Subnet,
+            vpc::Vpc,
+        },
+        error::AwsError,
+        internal::{tags::DeploymentTags, wait_and_refresh::WaitDefaults},
+        sdk_config::load_sdk_config,
+    },
+    commands::apply::{
+        client_token, elastic_ip_outputs, instance_outputs, key_pair_outputs, subnet_outputs,
+        vpc_outputs, with_connected_security_groups,
+    },
+    handlers::{Applied, ApplyContext, ComponentHandler, HandlerFuture},
+    models::{Component, PlanError},
+    utils::{
+        self,
+        diagnostics::Diagnostic,
+        redact::redact,
+        schema::{self, ResourceSchema},
+        timeouts::ResourceTimeouts,
+    },
+};
+
+pub struct Ec2InstanceHandler;
+
+impl Ec2InstanceHandler {
+    /// Ids of the recorded instances to update in place, none for a new or unchanged component
+    fn instances_to_update(context: &ApplyContext<'_>) -> Vec<String> {
+        context
+            .existing
+            .filter(|e| e.properties_hash.as_deref() != Some(context.properties_hash))
+            .map(|e| {
+                e.instances
+                    .iter()
+                    .filter_map(|instance| instance.get("id").and_then(|id| id.as_str()))
+                    .map(|id| id.to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl ComponentHandler for Ec2InstanceHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::EC2_INSTANCE
+    }
+
+    fn plan(
+        &self,
+        region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_ec2_instance(region, component).map(Some)
+    }
+
+    fn updates(&self, context: &ApplyContext<'_>) -> bool {
+        !Self::instances_to_update(context).is_empty()
+    }
+
+    /// Every instance recorded for the component is updated, min_count/max_count can launch several
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let ApplyContext {
+                region,
+                tags,
+                launch,
+                ..
+            } = context;
+            let key = context.component.key();
+            let update_ids = Self::instances_to_update(&context);
+            let component = with_connected_security_groups(context.component, context.outputs)
+                .map_err(EC2Error::OptionsError)?;
+            let (instances, done) = if update_ids.is_empty() {
+                let instances =
+                    create_ec2_instance(region, &component, tags, launch, context.wait).await?;
+                (instances, "created")
+            } else {
+                let mut instances = Vec::with_capacity(update_ids.len());
+                for instance_id in &update_ids {
+                    let (instance, changes, status_checks) = update_ec2_instance(
+                        region,
+                        &component,
+                        tags,
+                        instance_id,
+                        launch,
+                        context.wait,
+                    )
+                    .await?;
+                    let changed = changes.changed();
+                    if changed.is_empty() {
+                        log.push(format!("{}: {} is up to date", key, instance_id));
+                    } else {
+                        log.push(format!(
+                            "{}: modified {} of {}",
+                            key,
+                            changed.join(", "),
+                            instance_id
+                        ));
+                    }
+                    instances.push((instance, status_checks));
+                }
+                (instances, "updated")
+            };
+
+            let mut recorded: Vec<serde_json::Value> = Vec::with_capacity(instances.len());
+            for (instance, status_checks) in &instances {
+                log.push(format!(
+                    "{}: {} {} (public ip: {}, private ip: {})",
+                    key,
+                    done,
+                    instance.instance_id().unwrap_or("<unknown id>"),
+                    instance.public_ip_address().unwrap_or("-"),
+                    instance.private_ip_address().unwrap_or("-")
+                ));
+                if let Some(took) = status_checks {
+                    log.push(format!(
+                        "{}: status checks of {} passed after {}s",
+                        key,
+                        instance.instance_id().unwrap_or("<unknown id>"),
+                        took.as_secs()
+                    ));
+                }
+                tracing::debug!(
+                    "Applied EC2 instance: {}",
+                    redact(&format!("{:?}", instance))
+                );
+                let mut outputs = instance_outputs(instance, region);
+                // Filters the AMI was resolved with, it is looked up again once they change
+                if let Ok(Some(lookup)) = AmiLookup::from_yaml(&component.properties) {
+                    outputs["ami_lookup"] = serde_json::Value::String(lookup.filters());
+                }
+                // Whether this apply waited for the status checks, and for how long
+                outputs["status_checks_waited"] = status_checks.is_some().into();
+                outputs["status_checks_seconds"] = status_checks.map(|took| took.as_secs()).into();
+                recorded.push(outputs);
+            }
+            // The first instance answers `${EC2Instance.<name>.id}`, instance_ids lists them all
+            let mut outputs = recorded.first().cloned().unwrap_or_default();
+            outputs["instance_ids"] = instances
+                .iter()
+                .filter_map(|(i, _)| i.instance_id())
+                .collect::<Vec<_>>()
+                .into();
+            Ok(Applied {
+                outputs,
+                instances: Some(recorded),
+            })
+        })
+    }
+
+    /// Terminate every instance of `resource_id`, comma separated, one after the other
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let timeouts =
+                ResourceTimeouts::from_properties(&component.properties).unwrap_or_default();
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            let ec2_instance = EC2Instance::from_config(&config)
+                .with_timeouts(timeouts)
+                .with_wait(wait.clone());
+            let mut outcome = DestroyOutcome::AlreadyGone;
+            for instance_id in resource_id
+                .split(',')
+                .map(str::trim)
+                .filter(|id| !id.is_empty())
+            {
+                let destroyed = ec2_instance
+                    .destroy_instance(instance_id)
+                    .await
+                    .map_err(|err| format!("{}: [{}] {}", instance_id, err.code(), err))?;
+                if destroyed == DestroyOutcome::Terminated {
+                    outcome = DestroyOutcome::Terminated;
+                }
+            }
+            Ok(outcome)
+        })
+    }
+}
+
+async fn create_ec2_instance(
+    region: &str,
+    component: &Component,
+    tags: &DeploymentTags,
+    launch: &str,
+    wait: &WaitDefaults,
+) -> Result<Vec<(Instance, Option<Duration>)>, EC2Error> {
+    let config = load_sdk_config(region)
+        .await
+        .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+    let timeouts =
+        ResourceTimeouts::from_properties(&component.properties).map_err(EC2Error::OptionsError)?;
+    let ec2_instance = EC2Instance::from_config(&config)
+        .with_timeouts(timeouts)
+        .with_wait(wait.clone());
+    let instance_opts = EC2Instance::opts_from_yaml(&component.properties)?
+        .for_component(tags, &client_token(&tags.component_id(), launch));
+    let mut launched = Vec::new();
+    for instance in ec2_instance.create_instances(&instance_opts).await? {
+        let status_checks =
+            wait_for_status_checks(&ec2_instance, &instance_opts, &instance).await?;
+        launched.push((instance, status_checks));
+    }
+    Ok(launched)
+}
+
+async fn update_ec2_instance(
+    region: &str,
+    component: &Component,
+    tags: &DeploymentTags,
+    instance_id: &str,
+    launch: &str,
+    wait: &WaitDefaults,
+) -> Result<(Instance, InstanceChanges, Option<Duration>), EC2Error> {
+    let config = load_sdk_config(region)
+        .await
+        .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+    let timeouts =
+        ResourceTimeouts::from_properties(&component.properties).map_err(EC2Error::OptionsError)?;
+    let instance_opts = EC2Instance::opts_from_yaml(&component.properties)?
+        .for_component(tags, &client_token(&tags.component_id(), launch));
+    let ec2_instance = EC2Instance::from_config(&config)
+        .with_timeouts(timeouts)
+        .with_wait(wait.clone());
+    let (instance, changes) = ec2_instance
+        .update_instance(instance_id, &instance_opts)
+        .await?;
+    // Only an instance that was stopped and started again runs its checks anew
+    let status_checks = if changes.needs_stop() {
+        wait_for_status_checks(&ec2_instance, &instance_opts, &instance).await?
+    } else {
+        None
+    };
+    Ok((instance, changes, status_checks))
+}
+
+/// Wait for the status checks of a running instance when the properties ask to, returns how long
+async fn wait_for_status_checks(
+    ec2_instance: &EC2Instance,
+    opts: &InstanceOpts,
+    instance: &Instance,
+) -> Result<Option<Duration>, EC2Error> {
+    let running = instance.state().and_then(|s| s.name()) == Some(&InstanceStateName::Running);
+    if !opts.wait_for_status_checks || !running {
+        return Ok(None);
+    }
+    ec2_instance
+        .wait_for_status_checks(
+            instance.instance_id().unwrap_or_default().to_string(),
+            opts.status_checks_timeout,
+        )
+        .await
+        .map(Some)
+}
+
+pub struct VpcHandler;
+
+impl ComponentHandler for VpcHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::VPC
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_vpc(component).map(|_| None)
+    }
+
+    fn updates(&self, _context: &ApplyContext<'_>) -> bool {
+        false
+    }
+
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let config = load_sdk_config(context.region)
+                .await
+                .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+            let opts =
+                Vpc::opts_from_yaml(&context.component.properties)?.with_tags(&context.tags.tags());
+            let vpc = Vpc::from_config(&config)
+                .with_wait(context.wait.clone())
+                .create_vpc(&opts)
+                .await?;
+            log.push(format!(
+                "{}: created {} (cidr: {})",
+                context.component.key(),
+                vpc.vpc_id().unwrap_or("<unknown id>"),
+                vpc.cidr_block().unwrap_or("-")
+            ));
+            Ok(Applied {
+                outputs: vpc_outputs(&vpc, context.region),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            Vpc::from_config(&config)
+                .with_wait(wait.clone())
+                .destroy_vpc(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
+
+pub struct SecurityGroupHandler;
+
+impl ComponentHandler for SecurityGroupHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::SECURITY_GROUP
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_security_group(component).map(|_| None)
+    }
+
+    fn updates(&self, _context: &ApplyContext<'_>) -> bool {
+        false
+    }
+
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let component = context.component;
+            let config = load_sdk_config(context.region)
+                .await
+                .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+            let opts = SecurityGroup::opts_from_yaml(&component.name, &component.properties)?
+                .with_tags(&context.tags.tags());
+            let group_id = SecurityGroup::from_config(&config)
+                .with_wait(context.wait.clone())
+                .create_security_group(&opts)
+                .await?;
+            log.push(format!("{}: created {}", component.key(), group_id));
+            Ok(Applied {
+                outputs: serde_json::json!({
+                    "id": group_id,
+                    "region": context.region,
+                    "vpc_id": component.get_property_as_string("vpc_id"),
+                }),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            SecurityGroup::from_config(&config)
+                .with_wait(wait.clone())
+                .destroy_security_group(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
+
+pub struct SubnetHandler;
+
+impl ComponentHandler for SubnetHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::SUBNET
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_subnet(component).map(|_| None)
+    }
+
+    fn updates(&self, _context: &ApplyContext<'_>) -> bool {
+        false
+    }
+
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let config = load_sdk_config(context.region)
+                .await
+                .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+            let opts = Subnet::opts_from_yaml(&context.component.properties)?
+                .with_tags(&context.tags.tags());
+            let subnet = Subnet::from_config(&config)
+                .with_wait(context.wait.clone())
+                .create_subnet(&opts)
+                .await?;
+            log.push(format!(
+                "{}: created {} in {} (cidr: {})",
+                context.component.key(),
+                subnet.subnet_id().unwrap_or("<unknown id>"),
+                subnet.vpc_id().unwrap_or("-"),
+                subnet.cidr_block().unwrap_or("-")
+            ));
+            Ok(Applied {
+                outputs: subnet_outputs(&subnet, context.region),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            Subnet::from_config(&config)
+                .with_wait(wait.clone())
+                .destroy_subnet(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
+
+pub struct KeyPairHandler;
+
+impl ComponentHandler for KeyPairHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::KEY_PAIR
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_key_pair(component).map(Some)
+    }
+
+    fn updates(&self, _context: &ApplyContext<'_>) -> bool {
+        false
+    }
+
+    /**
+     * Create the key pair of a component, or keep the one in state while its properties are
+     * unchanged. A generated private key is written to `private_key_file` and only the path is
+     * recorded, the key pair is deleted again when the file cannot be written so no key is left
+     * without its private half.
+     */
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let ApplyContext {
+                region,
+                component,
+                existing,
+                ..
+            } = context;
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+            let key_pair = KeyPair::from_config(&config);
+            let recorded = match existing.and_then(|e| e.instances.first()) {
+                Some(recorded) => {
+                    let unchanged = existing.is_some_and(|e| {
+                        e.properties_hash.as_deref() == Some(context.properties_hash)
+                    });
+                    if !unchanged {
+                        return Err(EC2Error::RequiresReplacement(format!(
+                            "a key pair cannot be changed, destroy {} with --target and apply again",
+                            component.key()
+                        ))
+                        .into());
+                    }
+                    let id = recorded
+                        .get("id")
+                        .and_then(|id| id.as_str())
+                        .unwrap_or_default();
+                    let mut outputs =
+                        key_pair_outputs(&key_pair.describe_key_pair(id).await?, region);
+                    if let Some(file) = recorded.get("private_key_file") {
+                        outputs["private_key_file"] = file.clone();
+                    }
+                    outputs
+                }
+                None => {
+                    let opts = KeyPair::opts_from_yaml(&component.name, &component.properties)?
+                        .with_tags(&context.tags.tags());
+                    if let Some(path) = opts.private_key_file.as_deref() {
+                        if std::path::Path::new(path).exists() {
+                            return Err(EC2Error::OptionsError(format!(
+                                "private_key_file {} already exists, remove it or set another path",
+                                path
+                            ))
+                            .into());
+                        }
+                    }
+                    let created = key_pair.create_key_pair(&opts).await?;
+                    let mut outputs = key_pair_outputs(&created.key_pair, region);
+                    if let (Some(path), Some(private_key)) =
+                        (&opts.private_key_file, &created.private_key)
+                    {
+                        if let Err(err) = write_private_key(path, private_key) {
+                            let id = created.key_pair.key_pair_id().unwrap_or_default();
+                            if let Err(cleanup) = key_pair.destroy_key_pair(id).await {
+                                tracing::warn!("Failed to delete key pair {}: {}", id, cleanup);
+                            }
+                            return Err(err.into());
+                        }
+                        outputs["private_key_file"] = serde_json::Value::String(path.clone());
+                    }
+                    outputs
+                }
+            };
+            let private_key = recorded
+                .get("private_key_file")
+                .and_then(|path| path.as_str())
+                .map(|path| format!(", private key in {}", path))
+                .unwrap_or_default();
+            log.push(format!(
+                "{}: key pair {} ({}){}",
+                component.key(),
+                recorded["id"].as_str().unwrap_or("<unknown id>"),
+                recorded["key_name"].as_str().unwrap_or("-"),
+                private_key
+            ));
+            Ok(Applied {
+                outputs: recorded,
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        _wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            KeyPair::from_config(&config)
+                .destroy_key_pair(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
+
+pub struct ElasticIpHandler;
+
+impl ComponentHandler for ElasticIpHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::ELASTIC_IP
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_elastic_ip(component).map(|_| None)
+    }
+
+    /**
+     * Allocate the address of a component, or keep the one in state so its public IP stays the
+     * same and only move its association to the instance `associate_with` names now.
+     */
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let config = load_sdk_config(context.region)
+                .await
+                .map_err(|err| EC2Error::OptionsError(err.to_string()))?;
+            let elastic_ip = ElasticIp::from_config(&config).with_wait(context.wait.clone());
+            let opts = ElasticIp::opts_from_yaml(&context.component.properties)?
+                .with_tags(&context.tags.tags());
+            let address = match context.recorded_id() {
+                Some(allocation_id) => {
+                    elastic_ip
+                        .update_association(allocation_id, opts.associate_with.as_deref())
+                        .await?
+                }
+                None => elastic_ip.allocate_address(&opts).await?,
+            };
+            log.push(format!(
+                "{}: {} {} (public ip: {}, instance: {})",
+                context.component.key(),
+                if context.existing.is_some() {
+                    "updated"
+                } else {
+                    "allocated"
+                },
+                address.allocation_id().unwrap_or("<unknown id>"),
+                address.public_ip().unwrap_or("-"),
+                address.instance_id().unwrap_or("-")
+            ));
+            Ok(Applied {
+                outputs: elastic_ip_outputs(&address, context.region),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            ElasticIp::from_config(&config)
+                .with_wait(wait.clone())
+                .destroy_address(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{
+    aws::{
+        ec2::ec2_instance::DestroyOutcome,
+        error::AwsError,
+        iam::{instance_profile::InstanceProfile, role::IamRole},
+        internal::wait_and_refresh::WaitDefaults,
+        sdk_config::load_sdk_config,
+    },
+    commands::apply::{instance_profile_outputs, role_outputs},
+    handlers::{Applied, ApplyContext, ComponentHandler, HandlerFuture},
+    models::{Component, PlanError},
+    utils::{
+        self,
+        diagnostics::Diagnostic,
+        schema::{self, ResourceSchema},
+    },
+};
+
+pub struct IamRoleHandler;
+
+impl ComponentHandler for IamRoleHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::IAM_ROLE
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_iam_role(component).map(|_| None)
+    }
+
+    /**
+     * Create the role of a component, or update the one in state in place. A role cannot be
+     * renamed, a changed `role_name` needs the old role destroyed first.
+     */
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let ApplyContext {
+                region, component, ..
+            } = context;
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+            let iam_role = IamRole::from_config(&config);
+            let opts = IamRole::opts_from_yaml(&component.name, &component.properties)?
+                .with_tags(&context.tags.tags());
+            let role = match context.recorded_id() {
+                Some(name) if name == opts.role_name => iam_role.update_role(&opts).await?,
+                Some(name) => {
+                    return Err(AwsError::RequiresReplacement(format!(
+                        "role {} cannot be renamed to {}, destroy {} with --target and apply again",
+                        name,
+                        opts.role_name,
+                        component.key()
+                    )));
+                }
+                None => iam_role.create_role(&opts).await?,
+            };
+            let done = if context.existing.is_some() {
+                "updated"
+            } else {
+                "created"
+            };
+            log.push(format!(
+                "{}: {} {} ({})",
+                component.key(),
+                done,
+                role.role_name(),
+                role.arn()
+            ));
+            Ok(Applied {
+                outputs: role_outputs(&role, region),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        _wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            IamRole::from_config(&config)
+                .destroy_role(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
+
+pub struct InstanceProfileHandler;
+
+impl ComponentHandler for InstanceProfileHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::INSTANCE_PROFILE
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_instance_profile(component).map(|_| None)
+    }
+
+    /// Create the instance profile of a component, or move the one in state to its current role
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let ApplyContext {
+                region, component, ..
+            } = context;
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+            let instance_profile =
+                InstanceProfile::from_config(&config).with_wait(context.wait.clone());
+            let opts = InstanceProfile::opts_from_yaml(&component.name, &component.properties)?
+                .with_tags(&context.tags.tags());
+            let profile = match context.recorded_id() {
+                Some(name) if name == opts.instance_profile_name => {
+                    instance_profile.update_instance_profile(&opts).await?
+                }
+                Some(name) => {
+                    return Err(AwsError::RequiresReplacement(format!(
+                        "instance profile {} cannot be renamed to {}, destroy {} with --target and apply again",
+                        name,
+                        opts.instance_profile_name,
+                        component.key()
+                    )));
+                }
+                None => instance_profile.create_instance_profile(&opts).await?,
+            };
+            let done = if context.existing.is_some() {
+                "updated"
+            } else {
+                "created"
+            };
+            log.push(format!(
+                "{}: {} {} (role: {})",
+                component.key(),
+                done,
+                profile.instance_profile_name(),
+                profile.roles().first().map_or("-", |r| r.role_name())
+            ));
+            Ok(Applied {
+                outputs: instance_profile_outputs(&profile, region),
+                instances: None,
+            })
+        })
+    }
+
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        _component: &'a Component,
+        resource_id: &'a str,
+        _wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            InstanceProfile::from_config(&config)
+                .destroy_instance_profile(resource_id)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
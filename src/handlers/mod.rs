@@ -0,0 +1,237 @@
+use std::{future::Future, pin::Pin};
+
+use crate::{
+    aws::{
+        ec2::ec2_instance::DestroyOutcome,
+        error::AwsError,
+        internal::{tags::DeploymentTags, wait_and_refresh::WaitDefaults},
+    },
+    models::{Component, PlanError, state::ComponentState},
+    utils::schema::ResourceSchema,
+};
+
+pub mod ec2;
+pub mod iam;
+pub mod s3;
+
+/// Future of the async methods of a handler, boxed so every handler fits in the one registry
+pub type HandlerFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What apply hands a handler, references and dependency ids in the component are filled in
+#[derive(Debug, Clone, Copy)]
+pub struct ApplyContext<'a> {
+    pub region: &'a str,
+    pub component: &'a Component,
+    pub tags: &'a DeploymentTags,
+    /// State entry of the component when an earlier apply created it
+    pub existing: Option<&'a ComponentState>,
+    /// Outputs of the components applied before it, by `<type>.<name>`
+    pub outputs: &'a serde_json::Map<String, serde_json::Value>,
+    pub properties_hash: &'a str,
+    /// Sets this launch apart from earlier ones of the component, see `apply::launch_id`
+    pub launch: &'a str,
+    /// How the waits on the resource poll, see `WaitDefaults::for_config`
+    pub wait: &'a WaitDefaults,
+}
+
+impl ApplyContext<'_> {
+    /// Id recorded for the component by an earlier apply
+    pub fn recorded_id(&self) -> Option<&str> {
+        self.existing
+            .and_then(|e| e.instances.first())
+            .and_then(|instance| instance.get("id"))
+            .and_then(|id| id.as_str())
+    }
+}
+
+/// What a successful apply of a component leaves behind
+#[derive(Debug, Clone, PartialEq)]
+pub struct Applied {
+    /// What `${<type>.<name>.<output>}` resolves to
+    pub outputs: serde_json::Value,
+    /// One state entry per resource when the component made several, the outputs otherwise
+    pub instances: Option<Vec<serde_json::Value>>,
+}
+
+/**
+ * Plan, apply and destroy of one component type. Supporting a new type takes an implementation
+ * of this trait added to `HANDLERS`, plan, apply, destroy and `providers` all go through it.
+ */
+pub trait ComponentHandler: Sync {
+    fn schema(&self) -> &'static ResourceSchema;
+
+    /// Check the properties without calling AWS, with the outputs known before apply
+    fn plan(
+        &self,
+        region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>>;
+
+    /// Whether apply changes the recorded resource rather than creating one, for messages
+    fn updates(&self, context: &ApplyContext<'_>) -> bool {
+        context.existing.is_some()
+    }
+
+    /// Create the resource or bring the recorded one up to date, progress lines go to `log`
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>>;
+
+    /// Delete the resource recorded under `resource_id`, waiting on it as `wait` says
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>>;
+}
+
+/// Every supported component type, in the order `providers` lists them
+static HANDLERS: &[&dyn ComponentHandler] = &[
+    &ec2::Ec2InstanceHandler,
+    &ec2::VpcHandler,
+    &ec2::SecurityGroupHandler,
+    &ec2::SubnetHandler,
+    &ec2::KeyPairHandler,
+    &ec2::ElasticIpHandler,
+    &s3::S3BucketHandler,
+    &iam::IamRoleHandler,
+    &iam::InstanceProfileHandler,
+];
+
+pub fn handlers() -> impl Iterator<Item = &'static dyn ComponentHandler> {
+    HANDLERS.iter().copied()
+}
+
+pub fn handler_for(component_type: &str) -> Option<&'static dyn ComponentHandler> {
+    handlers().find(|h| h.schema().component_type == component_type)
+}
+
+/// Failure of apply and destroy for a type without a handler, plan explains it in more detail
+pub fn unsupported(component_type: &str) -> String {
+    format!("unsupported component type: {}", component_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        commands::providers::render_capabilities,
+        models::{
+            InfraConfig,
+            state::{ComponentMode, ComponentState},
+        },
+        utils::schema::SCHEMAS,
+    };
+
+    #[test]
+    fn test_every_schema_has_a_handler() {
+        let registered: Vec<&str> = handlers().map(|h| h.schema().component_type).collect();
+        let declared: Vec<&str> = SCHEMAS.iter().map(|s| s.component_type).collect();
+        assert_eq!(registered, declared);
+        for schema in SCHEMAS {
+            let handler = handler_for(schema.component_type).unwrap();
+            assert_eq!(handler.schema(), schema);
+        }
+    }
+
+    #[test]
+    fn test_unregistered_type_is_not_planned() {
+        assert!(handler_for("RDSInstance").is_none());
+        let config = InfraConfig::from_yaml(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: shop\ncomponents:\n  - type: RDSInstance\n    name: db\n",
+        )
+        .unwrap();
+        let (_, preview, errors) = crate::utils::plan_components(&config, "default").unwrap();
+        assert!(!preview.components[0].supported);
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("unsupported component type 'RDSInstance' for 'db'")
+        );
+    }
+
+    /// State entry of a component applied before with other properties than now
+    fn recorded(component_type: &str) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: "main".to_string(),
+            component_type: component_type.to_string(),
+            provider: "aws".to_string(),
+            instances: vec![serde_json::json!({ "id": "res-1" })],
+            created_at: None,
+            properties_hash: Some("before".to_string()),
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    #[test]
+    fn test_handlers_update_only_what_their_schema_declares_updatable() {
+        let tags = DeploymentTags {
+            deployment: "shop".to_string(),
+            workspace: "default".to_string(),
+            component_type: String::new(),
+            component_name: "main".to_string(),
+        };
+        let wait = WaitDefaults::default();
+        let outputs = serde_json::Map::new();
+        for handler in handlers() {
+            let schema = handler.schema();
+            let component: Component =
+                serde_yaml::from_str(&format!("type: {}\nname: main\n", schema.component_type))
+                    .unwrap();
+            let existing = recorded(schema.component_type);
+            let context = ApplyContext {
+                region: "us-west-2",
+                component: &component,
+                tags: &tags,
+                existing: Some(&existing),
+                outputs: &outputs,
+                properties_hash: "after",
+                launch: "1",
+                wait: &wait,
+            };
+            assert_eq!(
+                handler.updates(&context),
+                schema.supports_update,
+                "{}",
+                schema.component_type
+            );
+            let created = ApplyContext {
+                existing: None,
+                ..context
+            };
+            assert!(!handler.updates(&created), "{}", schema.component_type);
+        }
+    }
+
+    #[test]
+    fn test_providers_lists_the_capabilities_of_every_handler() {
+        let rendered = render_capabilities(handlers().map(|h| h.schema()));
+        let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+        for handler in handlers() {
+            let schema = handler.schema();
+            let row: Vec<&str> = rendered
+                .lines()
+                .find(|line| line.contains(&format!(" {} ", schema.component_type)))
+                .unwrap_or_else(|| panic!("{} is not listed", schema.component_type))
+                .split_whitespace()
+                .collect();
+            assert_eq!(
+                row,
+                vec![
+                    "aws",
+                    schema.component_type,
+                    format!("v{}", schema.version).as_str(),
+                    yes_no(schema.supports_update),
+                    yes_no(schema.supports_destroy),
+                ],
+            );
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{
+    aws::{
+        ec2::ec2_instance::DestroyOutcome, error::AwsError,
+        internal::wait_and_refresh::WaitDefaults, s3::bucket::S3Bucket,
+        sdk_config::load_sdk_config,
+    },
+    commands::apply::bucket_outputs,
+    handlers::{Applied, ApplyContext, ComponentHandler, HandlerFuture},
+    models::{Component, PlanError},
+    utils::{
+        self,
+        diagnostics::Diagnostic,
+        schema::{self, ResourceSchema},
+    },
+};
+
+pub struct S3BucketHandler;
+
+impl ComponentHandler for S3BucketHandler {
+    fn schema(&self) -> &'static ResourceSchema {
+        &schema::S3_BUCKET
+    }
+
+    fn plan(
+        &self,
+        _region: &str,
+        component: &Component,
+    ) -> Result<Option<serde_json::Value>, Vec<PlanError>> {
+        utils::plan_s3_bucket(component).map(|_| None)
+    }
+
+    /// Create the bucket of a component, or configure the one in state again
+    fn apply<'a>(
+        &'a self,
+        context: ApplyContext<'a>,
+        log: &'a mut Vec<String>,
+    ) -> HandlerFuture<'a, Result<Applied, AwsError>> {
+        Box::pin(async move {
+            let ApplyContext {
+                region, component, ..
+            } = context;
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| AwsError::OptionsError(err.to_string()))?;
+            let bucket = S3Bucket::from_config(&config).with_wait(context.wait.clone());
+            let opts =
+                S3Bucket::opts_from_yaml(&component.properties)?.with_tags(&context.tags.tags());
+            match context.recorded_id() {
+                Some(name) if name == opts.bucket => bucket.configure_bucket(&opts, true).await?,
+                Some(name) => {
+                    return Err(AwsError::RequiresReplacement(format!(
+                        "bucket {} cannot be renamed to {}, destroy {} with --target and apply again",
+                        name,
+                        opts.bucket,
+                        component.key()
+                    )));
+                }
+                None => bucket.create_bucket(&opts).await?,
+            }
+            let done = if context.existing.is_some() {
+                "updated"
+            } else {
+                "created"
+            };
+            log.push(format!(
+                "{}: {} {} in {}",
+                component.key(),
+                done,
+                opts.bucket,
+                region
+            ));
+            Ok(Applied {
+                outputs: bucket_outputs(&opts.bucket, region),
+                instances: None,
+            })
+        })
+    }
+
+    /// Objects are only deleted along with the bucket when `force_destroy` is set
+    fn destroy<'a>(
+        &'a self,
+        region: &'a str,
+        component: &'a Component,
+        resource_id: &'a str,
+        wait: &'a WaitDefaults,
+    ) -> HandlerFuture<'a, Result<DestroyOutcome, String>> {
+        Box::pin(async move {
+            let force_destroy = component
+                .get_property("force_destroy")
+                .and_then(|f| f.as_bool())
+                .unwrap_or(false);
+            let config = load_sdk_config(region)
+                .await
+                .map_err(|err| err.to_string())?;
+            S3Bucket::from_config(&config)
+                .with_wait(wait.clone())
+                .destroy_bucket(resource_id, force_destroy)
+                .await
+                .map_err(|err| format!("[{}] {}", err.code(), err))
+        })
+    }
+}
@@ -2,9 +2,16 @@ use clap::Parser;
 use std::io::Error;
 use tracing::info;
 use tracing_subscriber;
+use utils::{
+    constants::{DRIFT_EXIT_CODE, INTERRUPTED_EXIT_CODE},
+    diagnostics::Diagnostic,
+    redact::{RedactingMakeWriter, redact},
+};
 mod aws;
 mod commands;
+mod handlers;
 mod models;
+mod state;
 mod tests;
 mod utils;
 
@@ -16,30 +23,153 @@ enum Config {
     Plan(commands::plan::Config),
     Apply(commands::apply::Config),
     Destroy(commands::destroy::Config),
+    Refresh(commands::refresh::Config),
+    Import(commands::import::Config),
+    Drift(commands::drift::Config),
+    Lint(commands::lint::Config),
+    Fmt(commands::fmt::Config),
+    Explain(commands::explain::Config),
+    Describe(commands::describe::Config),
+    Output(commands::output::Config),
+    Providers(commands::providers::Config),
+    Selftest(commands::selftest::Config),
+    State(commands::state::Config),
+    Workspace(commands::workspace::Config),
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt::init();
+    // Every log line goes through the redactor, built-in patterns until a config installs its own
+    tracing_subscriber::fmt()
+        .with_writer(RedactingMakeWriter)
+        .init();
 
     info!("Starting Infrastructure Management CLI Tool");
     let args = Config::parse();
     match args {
         Config::Validate(validate_config) => {
             info!("Validate command called with config: {:?}", validate_config);
-            commands::validate::execute(&validate_config);
+            if let Err(err) = commands::validate::execute(&validate_config) {
+                eprintln!(
+                    "Validate failed: [{}] {}",
+                    err.code(),
+                    redact(&err.to_string())
+                );
+                std::process::exit(1);
+            }
         }
         Config::Plan(plan_config) => {
             info!("Plan command called with config: {:?}", plan_config);
-            commands::plan::execute(&plan_config);
+            if !commands::plan::execute(&plan_config).await {
+                std::process::exit(1);
+            }
         }
         Config::Apply(apply_config) => {
             info!("Apply command called with config: {:?}", apply_config);
-            commands::apply::execute(&apply_config).await;
+            if let Err(err) = commands::apply::execute(&apply_config).await {
+                eprintln!("Apply failed: {}", redact(&err.to_string()));
+                let code = match err {
+                    commands::apply::ApplyError::Interrupted { .. } => INTERRUPTED_EXIT_CODE,
+                    _ => 1,
+                };
+                std::process::exit(code);
+            }
         }
         Config::Destroy(destroy_config) => {
             info!("Destroy command called with config: {:?}", destroy_config);
-            commands::destroy::execute(&destroy_config);
+            if let Err(err) = commands::destroy::execute(&destroy_config).await {
+                eprintln!("Destroy failed: {}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Refresh(refresh_config) => {
+            info!("Refresh command called with config: {:?}", refresh_config);
+            if let Err(err) = commands::refresh::execute(&refresh_config).await {
+                eprintln!("Refresh failed: {}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Import(import_config) => {
+            info!("Import command called with config: {:?}", import_config);
+            if let Err(err) = commands::import::execute(&import_config).await {
+                eprintln!("Import failed: {}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Drift(drift_config) => {
+            info!("Drift command called with config: {:?}", drift_config);
+            match commands::drift::execute(&drift_config).await {
+                Ok(false) => {}
+                Ok(true) => std::process::exit(DRIFT_EXIT_CODE),
+                Err(err) => {
+                    eprintln!("Drift failed: {}", redact(&err.to_string()));
+                    std::process::exit(1);
+                }
+            }
+        }
+        Config::Fmt(fmt_config) => {
+            info!("Fmt command called with config: {:?}", fmt_config);
+            if let Err(err) = commands::fmt::execute(&fmt_config) {
+                eprintln!("Fmt failed: {}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Selftest(selftest_config) => {
+            info!("Selftest command called with config: {:?}", selftest_config);
+            let report = commands::selftest::execute(&selftest_config).await;
+            println!("{}", redact(&report.render()));
+            if !report.passed() {
+                eprintln!("Selftest failed");
+                std::process::exit(1);
+            }
+        }
+        Config::Describe(describe_config) => {
+            if let Err(err) = commands::describe::execute(&describe_config) {
+                eprintln!("{}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Output(output_config) => {
+            if let Err(err) = commands::output::execute(&output_config).await {
+                eprintln!("{}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Providers(providers_config) => {
+            commands::providers::execute(&providers_config);
+        }
+        Config::Explain(explain_config) => {
+            if let Err(err) = commands::explain::execute(&explain_config) {
+                eprintln!("{}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::State(state_config) => {
+            info!("State command called with config: {:?}", state_config);
+            if let Err(err) = commands::state::execute(&state_config).await {
+                eprintln!("State failed: {}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Workspace(workspace_config) => {
+            if let Err(err) = commands::workspace::execute(&workspace_config) {
+                eprintln!("{}", redact(&err.to_string()));
+                std::process::exit(1);
+            }
+        }
+        Config::Lint(lint_config) => {
+            info!("Lint command called with config: {:?}", lint_config);
+            match commands::lint::execute(&lint_config) {
+                Ok(warnings) if warnings > 0 && lint_config.options.deny_warnings => {
+                    eprintln!("Lint failed: {} warning(s) denied", warnings);
+                    std::process::exit(1);
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    eprintln!("Lint failed: [{}] {}", err.code(), redact(&err.to_string()));
+                    std::process::exit(1);
+                }
+            }
         }
     }
 
@@ -1,7 +1,10 @@
 use core::fmt;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::Error as _};
 use std::fmt::Display;
-use std::{collections::HashMap, hash::Hash};
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::Hash,
+};
 pub mod state;
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub enum Kind {
@@ -46,6 +49,37 @@ impl Display for CloudProvider {
     }
 }
 
+/// Input format of a config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConfigFormat {
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Detect the format from the file extension, falling back to the content (stdin, no extension)
+    pub fn detect(path: &str, content: &str) -> Self {
+        let extension = std::path::Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => ConfigFormat::Json,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ if content.trim_start().starts_with('{') => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("{0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("invalid JSON at '{pointer}': {message}")]
+    Json { pointer: String, message: String },
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct InfraConfig {
     pub version: String,
@@ -54,6 +88,156 @@ pub struct InfraConfig {
     pub region: String,
     pub metadata: Metadata,
     pub components: Vec<Component>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lint: Option<LintConfig>,
+    /// Extra patterns masked in every log line and error message, on top of the built-in ones
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact: Vec<RedactPattern>,
+    /// Inputs referenced as `${var.<name>}` in region, metadata and component properties
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variables: Vec<Variable>,
+    /// Values shown after apply and kept in the state, e.g. `web_ip: ${EC2Instance.web.public_ip}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, OutputDeclaration>,
+    /// Where the state is kept, the local state directory when left out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<BackendConfig>,
+}
+
+/// The `backend:` block, of the config or of the file in LETUS_BACKEND_CONFIG
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendConfig {
+    Local {
+        /// Directory of the state files, .letusinfra by default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path: Option<String>,
+    },
+    S3 {
+        bucket: String,
+        /// Object key of the default workspace, others use `workspaces/<workspace>/<key>`
+        key: String,
+        /// Region of the bucket and the lock table, the region of the config by default
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+        /// Table with a `LockID` string key holding the lock, no locking without it
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dynamodb_table: Option<String>,
+    },
+}
+
+/// Entry of the `outputs:` block, a bare reference or a block with `sensitive` and `description`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum OutputDeclaration {
+    Reference(String),
+    Block {
+        value: String,
+        #[serde(default)]
+        sensitive: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        description: Option<String>,
+    },
+}
+
+impl OutputDeclaration {
+    /// What the output resolves, interpolated against the outputs of the applied components
+    pub fn value(&self) -> &str {
+        match self {
+            OutputDeclaration::Reference(value) | OutputDeclaration::Block { value, .. } => value,
+        }
+    }
+
+    pub fn value_mut(&mut self) -> &mut String {
+        match self {
+            OutputDeclaration::Reference(value) | OutputDeclaration::Block { value, .. } => value,
+        }
+    }
+
+    /// Whether the value is masked when printed in a table
+    pub fn sensitive(&self) -> bool {
+        matches!(
+            self,
+            OutputDeclaration::Block {
+                sensitive: true,
+                ..
+            }
+        )
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        match self {
+            OutputDeclaration::Block { description, .. } => description.as_deref(),
+            OutputDeclaration::Reference(_) => None,
+        }
+    }
+}
+
+/// Declared input of a config, its value comes from --var, --var-file or the default
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Variable {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub var_type: VariableType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<serde_yaml::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Value the variable resolves to, set by `bind_variables`
+    #[serde(skip)]
+    pub value: Option<serde_yaml::Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableType {
+    #[default]
+    String,
+    Int,
+    Bool,
+}
+
+impl VariableType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            VariableType::String => "string",
+            VariableType::Int => "int",
+            VariableType::Bool => "bool",
+        }
+    }
+
+    /// Whether a value from the file or a default is of this type
+    pub fn accepts(&self, value: &serde_yaml::Value) -> bool {
+        match self {
+            VariableType::String => value.is_string(),
+            VariableType::Int => value.as_i64().is_some(),
+            VariableType::Bool => value.is_bool(),
+        }
+    }
+
+    /// Read a command line value as this type
+    pub fn parse(&self, raw: &str) -> Option<serde_yaml::Value> {
+        match self {
+            VariableType::String => Some(serde_yaml::Value::String(raw.to_string())),
+            VariableType::Int => raw.trim().parse::<i64>().ok().map(serde_yaml::Value::from),
+            VariableType::Bool => raw.trim().parse::<bool>().ok().map(serde_yaml::Value::Bool),
+        }
+    }
+}
+
+/// Settings for the config hygiene analyses run by `lint` and `plan`
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LintConfig {
+    /// Items that are intentionally unused, as component keys (`<type>.<name>`) or `var.<name>`
+    #[serde(default)]
+    pub ignore_unused: Vec<String>,
+}
+
+/// User-supplied redaction rule, matches are replaced with `[REDACTED:<name>]`
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RedactPattern {
+    pub name: String,
+    pub pattern: String, // Regex, a `secret` capture group limits the mask to that group
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -63,15 +247,62 @@ pub struct Metadata {
 
 #[derive(Debug, Serialize, Deserialize, Clone, Eq, PartialEq)]
 pub struct Component {
-    #[serde(rename = "type")]
+    /// May be left out when a template gives it
+    #[serde(rename = "type", default)]
     pub component_type: String,
     pub name: String,
+    /// Name of a `kind: Component` document whose properties this component starts from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
     #[serde(default)]
     pub properties: serde_yaml::Value,
     #[serde(rename = "dependsOn", skip_serializing_if = "Option::is_none")]
     pub depends_on: Option<Vec<Dependency>>,
     #[serde(rename = "connectsTo", skip_serializing_if = "Option::is_none")]
     pub connects_to: Option<Vec<Dependency>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lifecycle: Option<Lifecycle>,
+    /// Properties to mask like the ones the schema flags as sensitive, e.g. `key_name`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sensitive: Vec<String>,
+}
+
+/**
+ * Reusable component, a `kind: Component` document next to the Infra documents.
+ * Components that name it in `template` start from its properties.
+ */
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ComponentTemplate {
+    #[serde(default)]
+    pub version: Option<String>,
+    pub kind: Kind,
+    pub metadata: Metadata,
+    #[serde(rename = "type")]
+    pub component_type: String,
+    #[serde(default)]
+    pub properties: serde_yaml::Value,
+}
+
+/// Per component lifecycle settings
+#[derive(Debug, Serialize, Deserialize, Clone, Default, Eq, PartialEq)]
+pub struct Lifecycle {
+    /// Keep the real resource when the deployment is destroyed, only forget about it
+    #[serde(default)]
+    pub retain_on_destroy: bool,
+}
+
+impl Component {
+    /// Whether destroy must leave the real resource in place
+    pub fn retain_on_destroy(&self) -> bool {
+        self.lifecycle
+            .as_ref()
+            .is_some_and(|lifecycle| lifecycle.retain_on_destroy)
+    }
+
+    /// Address of the component in the form `<type>.<name>`
+    pub fn key(&self) -> String {
+        format!("{}.{}", self.component_type, self.name)
+    }
 }
 
 impl Hash for Component {
@@ -88,16 +319,201 @@ pub struct Dependency {
     pub name: String,
 }
 
+const MERGE_KEY: &str = "<<";
+const MAX_MERGE_DEPTH: usize = 64;
+
+/**
+ * Expand YAML merge keys (`<<: *anchor` or `<<: [*a, *b]`) into concrete mappings.
+ * Keys written explicitly win over merged ones, and earlier entries of a merge list win over later ones.
+ * Merged blocks may themselves contain merge keys, so a mapping is re-checked until none is left.
+ */
+fn resolve_merge_keys(value: &mut serde_yaml::Value, depth: usize) -> Result<(), String> {
+    if depth > MAX_MERGE_DEPTH {
+        return Err("YAML merge keys nested too deeply, check for recursive aliases".to_string());
+    }
+    match value {
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut rounds = 0;
+            while let Some(merge) = mapping.remove(MERGE_KEY) {
+                rounds += 1;
+                if rounds > MAX_MERGE_DEPTH {
+                    return Err("Recursive YAML merge key detected".to_string());
+                }
+                let sources = match merge {
+                    serde_yaml::Value::Mapping(source) => vec![source],
+                    serde_yaml::Value::Sequence(items) => items
+                        .into_iter()
+                        .map(|item| match item {
+                            serde_yaml::Value::Mapping(source) => Ok(source),
+                            _ => Err("Merge key list entries must be mappings".to_string()),
+                        })
+                        .collect::<Result<_, _>>()?,
+                    _ => {
+                        return Err(
+                            "Merge key value must be a mapping or a list of mappings".to_string()
+                        );
+                    }
+                };
+                for source in sources {
+                    for (k, v) in source {
+                        if !mapping.contains_key(&k) {
+                            mapping.insert(k, v);
+                        }
+                    }
+                }
+            }
+            for (_, v) in mapping.iter_mut() {
+                resolve_merge_keys(v, depth + 1)?;
+            }
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for item in items.iter_mut() {
+                resolve_merge_keys(item, depth + 1)?;
+            }
+        }
+        serde_yaml::Value::Tagged(tagged) => resolve_merge_keys(&mut tagged.value, depth + 1)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Keys of an Infra document, top level ones starting with `x-` are free to hold anchors
+const CONFIG_KEYS: &[&str] = &[
+    "version",
+    "kind",
+    "cloud",
+    "region",
+    "metadata",
+    "components",
+    "lint",
+    "redact",
+    "variables",
+    "outputs",
+    "backend",
+];
+const COMPONENT_KEYS: &[&str] = &[
+    "type",
+    "name",
+    "template",
+    "properties",
+    "dependsOn",
+    "connectsTo",
+    "lifecycle",
+    "sensitive",
+];
+const EXTENSION_PREFIX: &str = "x-";
+
+/**
+ * First top level or component key the config does not know, as its path and the error message.
+ * Checked after merge keys are expanded, serde would otherwise drop a misspelled key silently.
+ */
+fn unknown_key(value: &serde_yaml::Value) -> Option<(Vec<String>, String)> {
+    let unknown = |mapping: &serde_yaml::Mapping, known: &[&'static str], top_level: bool| {
+        mapping
+            .keys()
+            .filter_map(|key| key.as_str())
+            .find(|key| {
+                !known.iter().any(|known| known == key)
+                    && !(top_level && key.starts_with(EXTENSION_PREFIX))
+            })
+            .map(|key| {
+                let hint = match crate::utils::schema::suggest(key, known.iter().copied()).first() {
+                    Some(suggestion) => format!(", did you mean '{}'?", suggestion),
+                    None => String::new(),
+                };
+                (key.to_string(), format!("unknown key '{}'{}", key, hint))
+            })
+    };
+    let mapping = value.as_mapping()?;
+    if let Some((key, message)) = unknown(mapping, CONFIG_KEYS, true) {
+        return Some((vec![key], message));
+    }
+    let components = mapping.get("components")?.as_sequence()?;
+    components
+        .iter()
+        .enumerate()
+        .find_map(|(index, component)| {
+            let (key, message) = unknown(component.as_mapping()?, COMPONENT_KEYS, false)?;
+            Some((
+                vec!["components".to_string(), index.to_string(), key],
+                message,
+            ))
+        })
+}
+
+/// JSON pointer of a path, e.g. `/components/0/name`
+fn json_pointer(segments: impl IntoIterator<Item = String>) -> String {
+    segments
+        .into_iter()
+        .fold(String::new(), |pointer, segment| {
+            pointer + "/" + &segment.replace('~', "~0").replace('/', "~1")
+        })
+}
+
 impl InfraConfig {
     /// Parse YAML content into InfraConfig
+    /// Anchors, aliases and merge keys are expanded first so shared property blocks are
+    /// deserialized and validated in every place they are merged into.
     pub fn from_yaml(content: &str) -> Result<Self, serde_yaml::Error> {
-        serde_yaml::from_str(content)
+        let mut value: serde_yaml::Value = serde_yaml::from_str(content)?;
+        resolve_merge_keys(&mut value, 0).map_err(serde_yaml::Error::custom)?;
+        if let Some((path, message)) = unknown_key(&value) {
+            return Err(serde_yaml::Error::custom(format!(
+                "{}: {}",
+                path.join("."),
+                message
+            )));
+        }
+        serde_yaml::from_value(value)
+    }
+
+    /// Parse JSON content into InfraConfig
+    /// Failures carry the JSON pointer of the offending value, e.g. `/components/0/name`.
+    pub fn from_json(content: &str) -> Result<Self, ConfigError> {
+        let deserializer = &mut serde_json::Deserializer::from_str(content);
+        let config = serde_path_to_error::deserialize(deserializer).map_err(|err| {
+            let pointer = json_pointer(err.path().iter().filter_map(|segment| match segment {
+                serde_path_to_error::Segment::Seq { index } => Some(index.to_string()),
+                serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+                serde_path_to_error::Segment::Enum { variant } => Some(variant.clone()),
+                serde_path_to_error::Segment::Unknown => None,
+            }));
+            ConfigError::Json {
+                pointer,
+                message: err.into_inner().to_string(),
+            }
+        })?;
+        let value: serde_yaml::Value =
+            serde_json::from_str(content).map_err(|err| ConfigError::Json {
+                pointer: String::new(),
+                message: err.to_string(),
+            })?;
+        match unknown_key(&value) {
+            Some((path, message)) => Err(ConfigError::Json {
+                pointer: json_pointer(path),
+                message,
+            }),
+            None => Ok(config),
+        }
+    }
+
+    /// Parse content in the given format
+    pub fn parse(content: &str, format: ConfigFormat) -> Result<Self, ConfigError> {
+        match format {
+            ConfigFormat::Yaml => Ok(Self::from_yaml(content)?),
+            ConfigFormat::Json => Self::from_json(content),
+        }
     }
 
     /// Convert InfraConfig to YAML string
     pub fn to_yaml(&self) -> Result<String, serde_yaml::Error> {
         serde_yaml::to_string(self)
     }
+
+    /// Convert InfraConfig to a pretty printed JSON string
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string_pretty(self)
+    }
 }
 
 impl Component {
@@ -150,18 +566,29 @@ impl fmt::Display for InfraConfig {
     }
 }
 
-#[derive(Debug, thiserror::Error)]
+#[derive(Debug, PartialEq, thiserror::Error)]
 pub enum PlanError {
     #[error("Invalid component: {0}")]
     InvalidComponent(String),
-    #[error("Missing mandatory property '{0}' in component '{1}'")]
+    /// The component is given as `<type>.<name>`, the message ends with an example of a valid block
+    #[error(
+        "Missing mandatory property '{0}' in component '{1}'{hint}",
+        hint = crate::utils::schema::missing_property_hint(.1)
+    )]
     MissingProperty(String, String),
     #[error("Invalid property type for '{0}' in component '{1}' : expected {2}, found {3}")]
     InvalidPropertyType(String, String, String, String),
+    #[error("Interpolation error: {0}")]
+    Interpolation(#[from] crate::utils::interpolation::InterpolationError),
+    #[error("Dependency cycle between components: {}", .0.join(", "))]
+    DependencyCycle(Vec<String>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct Plan {}
+pub struct Plan {
+    pub workspace: String,
+    pub hash: String, // Changes whenever the resolved configuration of any component changes
+}
 
 #[cfg(test)]
 mod tests {
@@ -200,4 +627,197 @@ components:
         assert_eq!(config.components[0].name, "sample-vpc");
         assert!(config.components[1].depends_on.is_some());
     }
+
+    #[test]
+    fn test_merge_keys_are_expanded_with_overrides() {
+        let yaml_content = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+x-common: &common
+  instance_type: t2.micro
+  ami: ami-123
+  monitoring: &monitoring
+    enabled: false
+x-tagged: &tagged
+  <<: *common
+  tag_specifications:
+    - resource_type: instance
+      tags:
+        Team: infra
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      <<: *tagged
+      instance_type: t3.small
+  - type: EC2Instance
+    name: worker
+    properties:
+      <<: [*common, {ebs_optimized: true}]
+      monitoring:
+        <<: *monitoring
+        enabled: true
+"#;
+
+        let config = InfraConfig::from_yaml(yaml_content).unwrap();
+        let web = &config.components[0];
+        assert_eq!(
+            web.get_property_as_string("instance_type").unwrap(),
+            "t3.small"
+        );
+        assert_eq!(web.get_property_as_string("ami").unwrap(), "ami-123");
+        assert!(web.get_property("tag_specifications").is_some());
+        assert!(web.get_property("<<").is_none());
+
+        let worker = &config.components[1];
+        assert_eq!(
+            worker.get_property_as_string("instance_type").unwrap(),
+            "t2.micro"
+        );
+        assert_eq!(worker.get_property_as_bool("ebs_optimized"), Some(true));
+        assert_eq!(
+            worker.get_property("monitoring").unwrap()["enabled"].as_bool(),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let header =
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: sample\n";
+        let components = "components:\n  - type: VPC\n    name: main\n";
+
+        let err = InfraConfig::from_yaml(&format!("{}regoin: us-east-1\n{}", header, components))
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("regoin: unknown key 'regoin', did you mean 'region'?"),
+            "{}",
+            err
+        );
+
+        // Anchor holders must be marked as extensions, a bare key is as suspicious as a typo
+        let anchors = "common: &common\n  cidr: 10.0.0.0/16\n";
+        let err =
+            InfraConfig::from_yaml(&format!("{}{}{}", header, anchors, components)).unwrap_err();
+        assert!(err.to_string().contains("unknown key 'common'"), "{}", err);
+        InfraConfig::from_yaml(&format!("{}x-{}{}", header, anchors, components)).unwrap();
+
+        // Keys brought in by a merged block are checked like the ones written out
+        let merged = "x-links: &links\n  dependson: [{type: VPC, name: main}]\ncomponents:\n  - type: Subnet\n    name: a\n    <<: *links\n";
+        let err = InfraConfig::from_yaml(&format!("{}{}", header, merged)).unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "components.0.dependson: unknown key 'dependson', did you mean 'dependsOn'?"
+            ),
+            "{}",
+            err
+        );
+
+        let json_content = r#"{"version": "v1", "kind": "Infra", "cloud": "AWS", "region": "us-west-2",
+  "metadata": {"name": "sample"}, "components": [{"type": "VPC", "name": "main", "propertes": {}}]}"#;
+        match InfraConfig::from_json(json_content) {
+            Err(ConfigError::Json { pointer, message }) => {
+                assert_eq!(pointer, "/components/0/propertes");
+                assert_eq!(
+                    message,
+                    "unknown key 'propertes', did you mean 'properties'?"
+                );
+            }
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_json_and_yaml_produce_the_same_config() {
+        let json_content = r#"{
+  "version": "v1",
+  "kind": "Infra",
+  "cloud": "AWS",
+  "region": "us-west-2",
+  "metadata": {"name": "sample"},
+  "components": [
+    {
+      "type": "EC2Instance",
+      "name": "web",
+      "properties": {"instance_type": "t2.micro", "ami": "ami-123", "min_count": 1}
+    }
+  ]
+}"#;
+
+        assert_eq!(ConfigFormat::detect("-", json_content), ConfigFormat::Json);
+        let from_json = InfraConfig::parse(json_content, ConfigFormat::Json).unwrap();
+        let yaml_content = from_json.to_yaml().unwrap();
+        assert_eq!(
+            ConfigFormat::detect("infra.yml", &yaml_content),
+            ConfigFormat::Yaml
+        );
+        let from_yaml = InfraConfig::from_yaml(&yaml_content).unwrap();
+        assert_eq!(from_json.components, from_yaml.components);
+        assert_eq!(from_json.metadata.name, from_yaml.metadata.name);
+    }
+
+    #[test]
+    fn test_json_errors_include_pointer() {
+        let json_content = r#"{
+  "version": "v1",
+  "kind": "Infra",
+  "cloud": "AWS",
+  "region": "us-west-2",
+  "metadata": {"name": "sample"},
+  "components": [{"type": "EC2Instance", "name": 5}]
+}"#;
+
+        match InfraConfig::from_json(json_content).unwrap_err() {
+            ConfigError::Json { pointer, .. } => assert_eq!(pointer, "/components/0/name"),
+            other => panic!("expected a JSON error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_lifecycle() {
+        let yaml_content = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+components:
+  - type: S3Bucket
+    name: logs
+    lifecycle:
+      retain_on_destroy: true
+  - type: EC2Instance
+    name: web
+"#;
+
+        let config = InfraConfig::from_yaml(yaml_content).unwrap();
+        assert!(config.components[0].retain_on_destroy());
+        assert!(!config.components[1].retain_on_destroy());
+    }
+
+    #[test]
+    fn test_invalid_merge_value_is_rejected() {
+        let yaml_content = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      <<: not-a-mapping
+"#;
+
+        let err = InfraConfig::from_yaml(yaml_content).unwrap_err();
+        assert!(err.to_string().contains("Merge key"));
+    }
 }
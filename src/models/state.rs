@@ -1,17 +1,28 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct State {
     pub version: String,
+    #[serde(default)]
+    pub serial: u64, // Incremented on every write
+    #[serde(default)]
+    pub lineage: String, // Set on the first write and kept for the lifetime of the state
     pub modulepack: String,
     pub outputs: Output,
     pub components: Vec<ComponentState>,
+    /// Launches of every component by `<type>.<name>`, kept after a destroy so the next launch of
+    /// the component gets a client token of its own
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub launches: BTreeMap<String, u64>,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ComponentMode {
     BYO,
     Managed,
+    Imported, // Created outside of letusinfra and adopted with `import`
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -22,12 +33,48 @@ pub struct ComponentState {
     pub component_type: String,
     pub provider: String,
     pub instances: Vec<serde_json::Value>,
+    /// Unix timestamp (seconds) of the creation, NotFound shortly after it is not treated as a deletion
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Hash of the properties the component was applied with, plan compares it to detect changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub properties_hash: Option<String>,
+    /// Hash of the properties that force a new resource, plan flags a change to it as a replacement
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replacement_hash: Option<String>,
+    /// Left running by a destroy with --keep-in-state, the component has lifecycle.retain_on_destroy
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub retained: bool,
+}
+
+impl ComponentState {
+    /// Address of the resource, `<type>.<name>` like the key of its component
+    pub fn key(&self) -> String {
+        format!("{}.{}", self.component_type, self.name)
+    }
+
+    pub fn created_at(&self) -> Option<std::time::SystemTime> {
+        self.created_at
+            .map(|secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Output {
     pub value: serde_json::Value,
     pub output_type: OutputType,
+    /// Outputs of the `outputs:` block of the config as of the last apply, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub declared: BTreeMap<String, DeclaredOutput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeclaredOutput {
+    pub value: serde_json::Value,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -58,6 +105,10 @@ mod tests {
 
         assert_eq!(serde_json::to_string(&byo).unwrap(), r#""byo""#);
         assert_eq!(serde_json::to_string(&managed).unwrap(), r#""managed""#);
+        assert_eq!(
+            serde_json::to_string(&ComponentMode::Imported).unwrap(),
+            r#""imported""#
+        );
     }
 
     #[test]
@@ -108,6 +159,7 @@ mod tests {
                 datatype: Datatype::Object,
                 value: json!({}),
             },
+            declared: Default::default(),
         };
 
         let serialized = serde_json::to_value(&output).unwrap();
@@ -123,6 +175,10 @@ mod tests {
             component_type: "database".to_string(),
             provider: "aws".to_string(),
             instances: vec![json!({"id": "instance-1"})],
+            created_at: None,
+            properties_hash: None,
+            replacement_hash: None,
+            retained: false,
         };
 
         let serialized = serde_json::to_value(&component).unwrap();
@@ -149,12 +205,15 @@ mod tests {
         assert_eq!(component.component_type, "storage");
         assert_eq!(component.provider, "gcp");
         assert_eq!(component.instances.len(), 1);
+        assert_eq!(component.created_at, None);
     }
 
     #[test]
     fn test_state_complete_serialization() {
         let state = State {
             version: "1.0.0".to_string(),
+            serial: 3,
+            lineage: "18f0a2b4c6d8e0f2-1234".to_string(),
             modulepack: "test-pack".to_string(),
             outputs: Output {
                 value: json!({"endpoint": "http://example.com"}),
@@ -162,6 +221,7 @@ mod tests {
                     datatype: Datatype::Object,
                     value: json!({}),
                 },
+                declared: Default::default(),
             },
             components: vec![ComponentState {
                 mode: ComponentMode::Managed,
@@ -169,7 +229,12 @@ mod tests {
                 component_type: "postgres".to_string(),
                 provider: "aws".to_string(),
                 instances: vec![json!({"id": "db-1"})],
+                created_at: Some(1_700_000_000),
+                properties_hash: Some("0123456789abcdef".to_string()),
+                replacement_hash: None,
+                retained: false,
             }],
+            launches: Default::default(),
         };
 
         let serialized = serde_json::to_string(&state).unwrap();
@@ -203,6 +268,8 @@ mod tests {
 
         let state: State = serde_json::from_str(json_str).unwrap();
         assert_eq!(state.version, "2.0.0");
+        assert_eq!(state.serial, 0);
+        assert_eq!(state.lineage, "");
         assert_eq!(state.modulepack, "prod-pack");
         assert_eq!(state.components.len(), 1);
         assert_eq!(state.components[0].mode, ComponentMode::BYO);
@@ -212,6 +279,8 @@ mod tests {
     fn test_empty_components() {
         let state = State {
             version: "1.0.0".to_string(),
+            serial: 3,
+            lineage: "18f0a2b4c6d8e0f2-1234".to_string(),
             modulepack: "empty-pack".to_string(),
             outputs: Output {
                 value: json!(null),
@@ -219,8 +288,10 @@ mod tests {
                     datatype: Datatype::Object,
                     value: json!({}),
                 },
+                declared: Default::default(),
             },
             components: vec![],
+            launches: Default::default(),
         };
 
         assert_eq!(state.components.len(), 0);
@@ -0,0 +1,1071 @@
+/// Where the state is kept, the local state file or an object in S3 locked through DynamoDB
+use std::{
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use aws_sdk_dynamodb::{error::ProvideErrorMetadata as _, types::AttributeValue};
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::{DisplayErrorContext, ProvideErrorMetadata, SdkError},
+    primitives::ByteStream,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use super::{
+    StateError, Upgraded, backup_name, continue_lineage, decode, deployment_directory,
+    deployment_path, new_lineage, workspace_path,
+};
+use crate::{
+    aws::{
+        internal::retry::{RetryPolicy, is_throttling, jitter},
+        sdk_config::load_sdk_config,
+    },
+    models::{BackendConfig, state::State},
+    utils::{
+        constants::{BACKEND_CONFIG_ENV, DEFAULT_WORKSPACE, STATE_DIR},
+        redact::redact,
+        validate_workspace,
+    },
+};
+
+/// Key attribute of the lock table, the item of a state is keyed `<bucket>/<key>`
+pub const LOCK_ID_ATTRIBUTE: &str = "LockID";
+const LOCK_OWNER_ATTRIBUTE: &str = "ID";
+const LOCK_INFO_ATTRIBUTE: &str = "Info";
+
+/// Local file a state the backend failed to save is written to, `state push` sends it later
+pub const ERRORED_STATE_FILE: &str = "errored.state.json";
+
+/// Codes of a write refused because the object changed since it was read
+const CONFLICT_CODES: &[&str] = &["PreconditionFailed", "ConditionalRequestConflict"];
+/// Codes of a request the credentials are not allowed to make
+const PERMISSION_CODES: &[&str] = &[
+    "AccessDenied",
+    "Forbidden",
+    "InvalidAccessKeyId",
+    "SignatureDoesNotMatch",
+    "ExpiredToken",
+    "AllAccessDisabled",
+];
+/// Codes of content S3 did not receive intact
+const CORRUPTION_CODES: &[&str] = &["BadDigest", "InvalidDigest", "IncompleteBody"];
+/// Codes of failures on the S3 side that usually go away on their own
+const TRANSIENT_CODES: &[&str] = &[
+    "InternalError",
+    "ServiceUnavailable",
+    "RequestTimeout",
+    "RequestTimeTooSkewed",
+];
+
+/**
+ * Failure of a request to a remote backend. Transient ones (throttling, 5xx, timeouts, a dropped
+ * connection) are retried, the others would fail the same way again.
+ */
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BackendError {
+    #[error("{0}, still failing after retrying")]
+    Transient(String),
+    #[error("changed by another run since it was read: {0}")]
+    Conflict(String),
+    #[error("not allowed: {0}")]
+    Permission(String),
+    #[error("content corrupted in transfer: {0}")]
+    Corrupt(String),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl BackendError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, BackendError::Transient(_))
+    }
+
+    /// Sort a failed request by its AWS error code, or its HTTP status without one
+    pub fn classify(code: Option<&str>, status: Option<u16>, message: String) -> Self {
+        match code {
+            Some(code) if CONFLICT_CODES.contains(&code) => BackendError::Conflict(message),
+            Some(code) if PERMISSION_CODES.contains(&code) => BackendError::Permission(message),
+            Some(code) if CORRUPTION_CODES.contains(&code) => BackendError::Corrupt(message),
+            Some(code) if is_throttling(code) || TRANSIENT_CODES.contains(&code) => {
+                BackendError::Transient(message)
+            }
+            Some(_) => BackendError::Other(message),
+            None => match status {
+                Some(412) => BackendError::Conflict(message),
+                Some(403) => BackendError::Permission(message),
+                Some(429) | Some(500..) => BackendError::Transient(message),
+                _ => BackendError::Other(message),
+            },
+        }
+    }
+
+    /// Error of an S3 request, one that never got a response counts as transient
+    pub fn from_sdk<E>(err: &SdkError<E, HttpResponse>) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error + 'static,
+    {
+        let message = DisplayErrorContext(err).to_string();
+        match err {
+            SdkError::TimeoutError(_)
+            | SdkError::DispatchFailure(_)
+            | SdkError::ResponseError(_) => BackendError::Transient(message),
+            _ => BackendError::classify(
+                err.code(),
+                err.raw_response()
+                    .map(|response| response.status().as_u16()),
+                message,
+            ),
+        }
+    }
+}
+
+/**
+ * Run `call` until it succeeds, fails with anything but a transient error, or the retries of
+ * `policy` are used up, backing off like `retry_throttled`. The last error is returned then.
+ */
+pub async fn retry_transient<T, F, Fut>(
+    policy: &RetryPolicy,
+    operation: &str,
+    mut call: F,
+) -> Result<T, BackendError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, BackendError>>,
+{
+    let mut attempt = 0;
+    loop {
+        match call().await {
+            Err(err) if err.is_transient() && attempt < policy.retries => {
+                let delay = policy.backoff(attempt, jitter());
+                warn!(
+                    "{} failed with {}, retry {} of {} in {:?}",
+                    operation,
+                    err,
+                    attempt + 1,
+                    policy.retries,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/**
+ * Write `state` to `errored.state.json` in `dir`, or `errored.state.<n>.json` when an earlier one
+ * was not pushed yet, so the record of a run is kept when its backend fails to save it.
+ */
+pub fn write_errored_state(dir: &Path, state: &State) -> Result<PathBuf, String> {
+    let content = serde_json::to_vec_pretty(state).map_err(|err| err.to_string())?;
+    let stem = ERRORED_STATE_FILE.trim_end_matches(".json");
+    for n in 1.. {
+        let path = match n {
+            1 => dir.join(ERRORED_STATE_FILE),
+            n => dir.join(format!("{}.{}.json", stem, n)),
+        };
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        match file {
+            Ok(mut file) => {
+                std::io::Write::write_all(&mut file, &content).map_err(|err| err.to_string())?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(format!("{}: {}", path.display(), err)),
+        }
+    }
+    unreachable!("an unused file name is always found")
+}
+
+/// The error of a failed save once `state` is kept in an errored state file in `dir`
+pub fn keep_unsaved(dir: &Path, state: &State, backend: String, err: StateError) -> StateError {
+    match write_errored_state(dir, state) {
+        Ok(path) => StateError::NotSaved {
+            backend,
+            error: err.to_string(),
+            errored: path.display().to_string(),
+        },
+        Err(write_err) => {
+            eprintln!(
+                "The unsaved state could not be kept either: {}",
+                redact(&write_err)
+            );
+            err
+        }
+    }
+}
+
+/// Who holds a lock, written to the lock so a blocked run can say who it waits for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockInfo {
+    pub id: String,
+    pub operation: String,
+    pub who: String,
+    pub created: u64, // Seconds since the epoch
+}
+
+impl LockInfo {
+    fn new(operation: &str) -> Self {
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+        let who = match std::env::var("HOSTNAME") {
+            Ok(host) => format!("{}@{}", user, host),
+            Err(_) => user,
+        };
+        LockInfo {
+            id: new_lineage(),
+            operation: operation.to_string(),
+            who,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl std::fmt::Display for LockInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}, lock {}, taken at {})",
+            self.who, self.operation, self.id, self.created
+        )
+    }
+}
+
+/**
+ * Storage of the state. `lock` is taken before the state is read by a command that writes it
+ * and released with `unlock` once the command is done, `save` only writes while it is held.
+ */
+pub trait StateBackend {
+    /// The stored state, None before the first apply
+    fn load(&self) -> impl Future<Output = Result<Option<State>, StateError>> + Send;
+    /// Store `state`, continuing the serial and lineage of the stored one
+    fn save(&self, state: State) -> impl Future<Output = Result<State, StateError>> + Send;
+    fn lock(&mut self, operation: &str) -> impl Future<Output = Result<(), StateError>> + Send;
+    /// Release the lock taken by `lock`, nothing to do when none is held
+    fn unlock(&mut self) -> impl Future<Output = Result<(), StateError>> + Send;
+    /**
+     * Rewrite the stored state in the current format after keeping a copy of it as it was,
+     * None when there is no state or it is already current
+     */
+    fn upgrade(&self) -> impl Future<Output = Result<Option<Upgraded>, StateError>> + Send;
+    /// Where the state is, for messages
+    fn describe(&self) -> String;
+}
+
+/// State file on disk, locked by a `<file>.lock` next to it
+#[derive(Debug)]
+pub struct LocalBackend {
+    path: PathBuf,
+    lock: Option<LockInfo>,
+}
+
+impl LocalBackend {
+    pub fn new(path: PathBuf) -> Self {
+        LocalBackend { path, lock: None }
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        self.path.with_file_name(name)
+    }
+}
+
+impl StateBackend for LocalBackend {
+    async fn load(&self) -> Result<Option<State>, StateError> {
+        super::load(&self.path)
+    }
+
+    async fn save(&self, state: State) -> Result<State, StateError> {
+        super::write(&self.path, state)
+    }
+
+    async fn upgrade(&self) -> Result<Option<Upgraded>, StateError> {
+        super::upgrade(&self.path)
+    }
+
+    async fn lock(&mut self, operation: &str) -> Result<(), StateError> {
+        let lock_path = self.lock_path();
+        let lock_err = |message: String| StateError::Backend {
+            backend: lock_path.display().to_string(),
+            message,
+        };
+        if let Some(dir) = lock_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| lock_err(err.to_string()))?;
+        }
+        let info = LockInfo::new(operation);
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path);
+        match file {
+            Ok(file) => {
+                serde_json::to_writer(file, &info).map_err(|err| lock_err(err.to_string()))?
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                let holder = fs::read_to_string(&lock_path)
+                    .ok()
+                    .and_then(|content| serde_json::from_str::<LockInfo>(&content).ok())
+                    .map(|holder| holder.to_string())
+                    .unwrap_or_else(|| "an unknown run".to_string());
+                return Err(StateError::Locked {
+                    backend: self.describe(),
+                    holder: format!("{}, lock file {}", holder, lock_path.display()),
+                });
+            }
+            Err(err) => return Err(lock_err(err.to_string())),
+        }
+        self.lock = Some(info);
+        Ok(())
+    }
+
+    async fn unlock(&mut self) -> Result<(), StateError> {
+        if self.lock.take().is_none() {
+            return Ok(());
+        }
+        let lock_path = self.lock_path();
+        match fs::remove_file(&lock_path) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(StateError::Backend {
+                backend: lock_path.display().to_string(),
+                message: err.to_string(),
+            }),
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.path.display().to_string()
+    }
+}
+
+/**
+ * Where the S3 backend keeps its locks, one item per state keyed by `lock_id`. A DynamoDB table
+ * outside of tests.
+ */
+pub trait LockTable {
+    /// Fails when the table cannot be used, checked before the backend is
+    fn check(&self) -> impl Future<Output = Result<(), String>> + Send;
+    /// Write `info` as the lock of `lock_id`, Ok(false) when a lock is there already
+    fn acquire(
+        &self,
+        lock_id: &str,
+        info: &LockInfo,
+    ) -> impl Future<Output = Result<bool, String>> + Send;
+    /// The lock of `lock_id`, when one is there and can be read
+    fn holder(&self, lock_id: &str) -> impl Future<Output = Option<LockInfo>> + Send;
+    /// Delete the lock of `lock_id` taken as `owner`, Ok(false) when the lock there is not theirs
+    fn release(
+        &self,
+        lock_id: &str,
+        owner: &str,
+    ) -> impl Future<Output = Result<bool, String>> + Send;
+}
+
+/// Locks as items of a DynamoDB table whose key is `LockID`
+#[derive(Debug)]
+pub struct DynamoDbLocks {
+    client: aws_sdk_dynamodb::Client,
+    table: String,
+}
+
+impl DynamoDbLocks {
+    pub fn from_config(config: &aws_types::SdkConfig, table: &str) -> Self {
+        DynamoDbLocks {
+            client: aws_sdk_dynamodb::Client::new(config),
+            table: table.to_string(),
+        }
+    }
+}
+
+impl LockTable for DynamoDbLocks {
+    async fn check(&self) -> Result<(), String> {
+        match self
+            .client
+            .describe_table()
+            .table_name(&self.table)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) if err.code() == Some("ResourceNotFoundException") => {
+                Err(format!("DynamoDB table {} does not exist", self.table))
+            }
+            Err(err) => Err(DisplayErrorContext(&err).to_string()),
+        }
+    }
+
+    async fn acquire(&self, lock_id: &str, info: &LockInfo) -> Result<bool, String> {
+        let content = serde_json::to_string(info).unwrap_or_default();
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table)
+            .item(LOCK_ID_ATTRIBUTE, AttributeValue::S(lock_id.to_string()))
+            .item(LOCK_OWNER_ATTRIBUTE, AttributeValue::S(info.id.clone()))
+            .item(LOCK_INFO_ATTRIBUTE, AttributeValue::S(content))
+            .condition_expression("attribute_not_exists(#lock)")
+            .expression_attribute_names("#lock", LOCK_ID_ATTRIBUTE)
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some("ConditionalCheckFailedException") => Ok(false),
+            Err(err) => Err(DisplayErrorContext(&err).to_string()),
+        }
+    }
+
+    async fn holder(&self, lock_id: &str) -> Option<LockInfo> {
+        let item = self
+            .client
+            .get_item()
+            .table_name(&self.table)
+            .key(LOCK_ID_ATTRIBUTE, AttributeValue::S(lock_id.to_string()))
+            .consistent_read(true)
+            .send()
+            .await
+            .ok()?
+            .item?;
+        let info = item.get(LOCK_INFO_ATTRIBUTE)?.as_s().ok()?;
+        serde_json::from_str(info).ok()
+    }
+
+    async fn release(&self, lock_id: &str, owner: &str) -> Result<bool, String> {
+        // Only the item this run wrote, a lock taken over by another run is left alone
+        let result = self
+            .client
+            .delete_item()
+            .table_name(&self.table)
+            .key(LOCK_ID_ATTRIBUTE, AttributeValue::S(lock_id.to_string()))
+            .condition_expression("#owner = :id")
+            .expression_attribute_names("#owner", LOCK_OWNER_ATTRIBUTE)
+            .expression_attribute_values(":id", AttributeValue::S(owner.to_string()))
+            .send()
+            .await;
+        match result {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == Some("ConditionalCheckFailedException") => Ok(false),
+            Err(err) => Err(DisplayErrorContext(&err).to_string()),
+        }
+    }
+}
+
+/**
+ * State kept as an object in S3. With a `dynamodb_table` every run that writes holds an item of
+ * the table while it runs, and every write is conditional on the object being the one read.
+ */
+#[derive(Debug)]
+pub struct S3Backend<L: LockTable = DynamoDbLocks> {
+    s3: aws_sdk_s3::Client,
+    bucket: String,
+    key: String,
+    locks: Option<L>,
+    lock: Option<LockInfo>,
+    retry: RetryPolicy, // Of the requests for the state object
+}
+
+impl S3Backend {
+    pub fn from_config(
+        config: &aws_types::SdkConfig,
+        bucket: &str,
+        key: &str,
+        table: Option<&str>,
+    ) -> Self {
+        S3Backend::with_locks(
+            aws_sdk_s3::Client::new(config),
+            bucket,
+            key,
+            table.map(|table| DynamoDbLocks::from_config(config, table)),
+        )
+    }
+}
+
+impl<L: LockTable> S3Backend<L> {
+    /// Backend keeping its locks in `locks`, without any the lock is only remembered by this run
+    pub fn with_locks(s3: aws_sdk_s3::Client, bucket: &str, key: &str, locks: Option<L>) -> Self {
+        S3Backend {
+            s3,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+            locks,
+            lock: None,
+            retry: RetryPolicy::from_env(),
+        }
+    }
+
+    /// Retry transient failures of the state object requests as `retry` says
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Fails when the bucket or the lock table cannot be reached, before the backend is used
+    pub async fn check(&self) -> Result<(), StateError> {
+        match self.s3.head_bucket().bucket(&self.bucket).send().await {
+            Ok(_) => {}
+            Err(err) if matches!(err.code(), Some("NoSuchBucket") | Some("NotFound")) => {
+                return Err(self.backend_err(format!("bucket {} does not exist", self.bucket)));
+            }
+            Err(err) => return Err(self.backend_err(DisplayErrorContext(&err).to_string())),
+        }
+        match &self.locks {
+            Some(locks) => locks.check().await.map_err(|err| self.backend_err(err)),
+            None => Ok(()),
+        }
+    }
+
+    fn lock_id(&self) -> String {
+        format!("{}/{}", self.bucket, self.key)
+    }
+
+    fn backend_err(&self, message: String) -> StateError {
+        StateError::Backend {
+            backend: self.describe(),
+            message,
+        }
+    }
+
+    /// The stored object and the ETag the next write is conditional on
+    async fn read_object(&self) -> Result<Option<(Vec<u8>, Option<String>)>, StateError> {
+        retry_transient(&self.retry, "GetObject", || self.get_object())
+            .await
+            .map_err(|err| StateError::Read {
+                path: self.describe(),
+                message: err.to_string(),
+            })
+    }
+
+    async fn get_object(&self) -> Result<Option<(Vec<u8>, Option<String>)>, BackendError> {
+        let resp = match self
+            .s3
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+        {
+            Ok(resp) => resp,
+            Err(err) if err.code() == Some("NoSuchKey") => return Ok(None),
+            Err(err) => return Err(BackendError::from_sdk(&err)),
+        };
+        let e_tag = resp.e_tag().map(|tag| tag.to_string());
+        // A body cut off midway is a dropped connection
+        let content = resp
+            .body
+            .collect()
+            .await
+            .map_err(|err| BackendError::Transient(err.to_string()))?
+            .into_bytes();
+        Ok(Some((content.to_vec(), e_tag)))
+    }
+
+    /// The stored state and the ETag the next write is conditional on
+    async fn read(&self) -> Result<Option<(State, Option<String>)>, StateError> {
+        let Some((content, e_tag)) = self.read_object().await? else {
+            return Ok(None);
+        };
+        let (state, _) = decode(&self.describe(), &content)?;
+        Ok(Some((state, e_tag)))
+    }
+
+    /// Fails unless this run holds the lock item, it may have been removed by hand meanwhile
+    pub async fn check_lock(&self) -> Result<(), StateError> {
+        let Some(locks) = &self.locks else {
+            return Ok(());
+        };
+        let held = self.lock.as_ref().map(|lock| lock.id.as_str());
+        let holder = locks.holder(&self.lock_id()).await;
+        if held.is_none() || holder.as_ref().map(|h| h.id.as_str()) != held {
+            return Err(StateError::Locked {
+                backend: self.describe(),
+                holder: holder
+                    .map(|h| h.to_string())
+                    .unwrap_or_else(|| "nobody, this run no longer holds it".to_string()),
+            });
+        }
+        Ok(())
+    }
+
+    /**
+     * Store `state` as the object. Only replaces the object with `e_tag` when one existed, and
+     * only creates it when none did, so a run that raced this one is not overwritten. Transient
+     * failures are retried, a write that landed without its answer then fails as a conflict.
+     */
+    async fn put(
+        &self,
+        state: &State,
+        existed: bool,
+        e_tag: Option<String>,
+    ) -> Result<(), StateError> {
+        let content = serde_json::to_vec_pretty(state).map_err(|err| StateError::Write {
+            path: self.describe(),
+            message: err.to_string(),
+        })?;
+        let (content, e_tag) = (&content, &e_tag);
+        let put = move || async move {
+            let request = self
+                .s3
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&self.key)
+                .content_type("application/json")
+                .body(ByteStream::from(content.clone()));
+            let request = match (existed, e_tag) {
+                (true, Some(e_tag)) => request.if_match(e_tag),
+                (true, None) => request,
+                (false, _) => request.if_none_match("*"),
+            };
+            request
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(|err| BackendError::from_sdk(&err))
+        };
+        match retry_transient(&self.retry, "PutObject", put).await {
+            Ok(()) => Ok(()),
+            Err(BackendError::Conflict(_)) => Err(StateError::Conflict(self.describe())),
+            Err(error) => Err(StateError::Remote {
+                backend: self.describe(),
+                error,
+            }),
+        }
+    }
+}
+
+impl<L: LockTable + Send + Sync> StateBackend for S3Backend<L> {
+    async fn load(&self) -> Result<Option<State>, StateError> {
+        Ok(self.read().await?.map(|(state, _)| state))
+    }
+
+    async fn save(&self, mut state: State) -> Result<State, StateError> {
+        self.check_lock().await?;
+        let (previous, e_tag) = match self.read().await? {
+            Some((previous, e_tag)) => (Some(previous), e_tag),
+            None => (None, None),
+        };
+        let existed = previous.is_some();
+        continue_lineage(&mut state, previous);
+        self.put(&state, existed, e_tag).await?;
+        Ok(state)
+    }
+
+    async fn upgrade(&self) -> Result<Option<Upgraded>, StateError> {
+        self.check_lock().await?;
+        let Some((content, e_tag)) = self.read_object().await? else {
+            return Ok(None);
+        };
+        let (state, migrated) = decode(&self.describe(), &content)?;
+        if migrated.steps.is_empty() {
+            return Ok(None);
+        }
+        let backup = backup_name(&self.key, migrated.from);
+        self.s3
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&backup)
+            .content_type("application/json")
+            .body(ByteStream::from(content))
+            .send()
+            .await
+            .map_err(|err| StateError::Write {
+                path: format!("s3://{}/{}", self.bucket, backup),
+                message: DisplayErrorContext(&err).to_string(),
+            })?;
+        self.put(&state, true, e_tag).await?;
+        Ok(Some(Upgraded {
+            from: migrated.from,
+            steps: migrated.steps,
+            backup: format!("s3://{}/{}", self.bucket, backup),
+        }))
+    }
+
+    async fn lock(&mut self, operation: &str) -> Result<(), StateError> {
+        let info = LockInfo::new(operation);
+        let Some(locks) = &self.locks else {
+            // No table to hold the lock in, remembered only so save knows it was taken
+            self.lock = Some(info);
+            return Ok(());
+        };
+        let lock_id = self.lock_id();
+        match locks.acquire(&lock_id, &info).await {
+            Ok(true) => {
+                self.lock = Some(info);
+                Ok(())
+            }
+            Ok(false) => Err(StateError::Locked {
+                backend: self.describe(),
+                holder: locks
+                    .holder(&lock_id)
+                    .await
+                    .map(|holder| holder.to_string())
+                    .unwrap_or_else(|| "an unknown run".to_string()),
+            }),
+            Err(err) => Err(self.backend_err(err)),
+        }
+    }
+
+    async fn unlock(&mut self) -> Result<(), StateError> {
+        let Some(info) = self.lock.take() else {
+            return Ok(());
+        };
+        let Some(locks) = &self.locks else {
+            return Ok(());
+        };
+        match locks.release(&self.lock_id(), &info.id).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(self.backend_err(format!(
+                "lock {} is no longer held by this run, it was left as it is",
+                info.id
+            ))),
+            Err(err) => Err(self.backend_err(err)),
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.key)
+    }
+}
+
+/// The backend a command reads and writes the state through
+#[derive(Debug)]
+pub enum Backend {
+    Local(LocalBackend),
+    S3(S3Backend),
+}
+
+impl Backend {
+    /// Kind of backend, shown next to the location in summaries
+    pub fn name(&self) -> &'static str {
+        match self {
+            Backend::Local(_) => "local",
+            Backend::S3(_) => "s3",
+        }
+    }
+}
+
+impl StateBackend for Backend {
+    async fn load(&self) -> Result<Option<State>, StateError> {
+        match self {
+            Backend::Local(backend) => backend.load().await,
+            Backend::S3(backend) => backend.load().await,
+        }
+    }
+
+    /// A state that could not be saved is kept in an errored state file, see `keep_unsaved`
+    async fn save(&self, state: State) -> Result<State, StateError> {
+        let saved = match self {
+            Backend::Local(backend) => backend.save(state.clone()).await,
+            Backend::S3(backend) => backend.save(state.clone()).await,
+        };
+        saved.map_err(|err| keep_unsaved(Path::new("."), &state, self.describe(), err))
+    }
+
+    async fn upgrade(&self) -> Result<Option<Upgraded>, StateError> {
+        match self {
+            Backend::Local(backend) => backend.upgrade().await,
+            Backend::S3(backend) => backend.upgrade().await,
+        }
+    }
+
+    async fn lock(&mut self, operation: &str) -> Result<(), StateError> {
+        match self {
+            Backend::Local(backend) => backend.lock(operation).await,
+            Backend::S3(backend) => backend.lock(operation).await,
+        }
+    }
+
+    async fn unlock(&mut self) -> Result<(), StateError> {
+        match self {
+            Backend::Local(backend) => backend.unlock().await,
+            Backend::S3(backend) => backend.unlock().await,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Backend::Local(backend) => backend.describe(),
+            Backend::S3(backend) => backend.describe(),
+        }
+    }
+}
+
+/// File in LETUS_BACKEND_CONFIG, the `backend:` block on its own
+#[derive(Deserialize)]
+struct BackendFile {
+    backend: BackendConfig,
+}
+
+/// The `backend:` block of the file in LETUS_BACKEND_CONFIG when set, `declared` otherwise
+pub fn select_config(
+    declared: Option<&BackendConfig>,
+) -> Result<Option<BackendConfig>, StateError> {
+    let Ok(file) = std::env::var(BACKEND_CONFIG_ENV) else {
+        return Ok(declared.cloned());
+    };
+    let backend_err = |message: String| StateError::Backend {
+        backend: file.clone(),
+        message,
+    };
+    let content = fs::read_to_string(&file).map_err(|err| backend_err(err.to_string()))?;
+    let parsed: BackendFile =
+        serde_yaml::from_str(&content).map_err(|err| backend_err(err.to_string()))?;
+    Ok(Some(parsed.backend))
+}
+
+/// Object key of a workspace and deployment, `[workspaces/<workspace>/][<deployment>/]<key>`
+pub fn object_key(key: &str, workspace: &str, deployment: Option<&str>) -> String {
+    let mut object_key = String::new();
+    if workspace != DEFAULT_WORKSPACE {
+        object_key.push_str(&format!("workspaces/{}/", workspace));
+    }
+    if let Some(deployment) = deployment {
+        object_key.push_str(&format!("{}/", deployment_directory(deployment)));
+    }
+    object_key.push_str(key.trim_start_matches('/'));
+    object_key
+}
+
+/**
+ * Backend of a workspace, and of one deployment when `-f` holds several. `--state-file` always
+ * means that local file. The bucket and lock table of an S3 backend are checked here, so a
+ * backend that cannot be used fails the command before any resource is touched. `region` is the
+ * one of the config, used when the backend does not name its own.
+ */
+pub async fn open(
+    declared: Option<&BackendConfig>,
+    state_file: Option<&str>,
+    workspace: &str,
+    deployment: Option<&str>,
+    region: Option<&str>,
+) -> Result<Backend, StateError> {
+    validate_workspace(workspace).map_err(StateError::InvalidWorkspace)?;
+    if let Some(state_file) = state_file {
+        return Ok(Backend::Local(LocalBackend::new(PathBuf::from(state_file))));
+    }
+    let local = |dir: &Path| -> Result<Backend, StateError> {
+        let path = match deployment {
+            Some(deployment) => deployment_path(dir, workspace, deployment)?,
+            None => workspace_path(dir, workspace)?,
+        };
+        Ok(Backend::Local(LocalBackend::new(path)))
+    };
+    match select_config(declared)? {
+        None | Some(BackendConfig::Local { path: None }) => local(Path::new(STATE_DIR)),
+        Some(BackendConfig::Local { path: Some(dir) }) => local(Path::new(&dir)),
+        Some(BackendConfig::S3 {
+            bucket,
+            key,
+            region: backend_region,
+            dynamodb_table,
+        }) => {
+            let key = object_key(&key, workspace, deployment);
+            let backend_err = |message: String| StateError::Backend {
+                backend: format!("s3://{}/{}", bucket, key),
+                message,
+            };
+            let Some(region) = backend_region.as_deref().or(region) else {
+                return Err(backend_err(
+                    "no region, set region in the backend block".to_string(),
+                ));
+            };
+            let sdk_config = load_sdk_config(region)
+                .await
+                .map_err(|err| backend_err(err.to_string()))?;
+            let backend =
+                S3Backend::from_config(&sdk_config, &bucket, &key, dynamodb_table.as_deref());
+            backend.check().await?;
+            Ok(Backend::S3(backend))
+        }
+    }
+}
+
+/**
+ * Release the lock of `backend` once a command is done with `result`. A failed unlock is the
+ * error of the command only when the command itself succeeded, otherwise it is printed.
+ */
+pub async fn unlock_after<T, E: From<StateError>>(
+    backend: &mut Backend,
+    result: Result<T, E>,
+) -> Result<T, E> {
+    match (result, backend.unlock().await) {
+        (result, Ok(())) => result,
+        (Ok(_), Err(err)) => Err(err.into()),
+        (Err(result), Err(err)) => {
+            eprintln!("{}", redact(&err.to_string()));
+            Err(result)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{Datatype, Output, OutputType};
+
+    fn temp_backend(test: &str) -> LocalBackend {
+        let dir =
+            std::env::temp_dir().join(format!("letus-backend-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        LocalBackend::new(dir.join("state.json"))
+    }
+
+    #[tokio::test]
+    async fn test_local_lock_is_exclusive() {
+        let mut first = temp_backend("lock");
+        let mut second = LocalBackend::new(first.path.clone());
+        first.lock("apply").await.unwrap();
+        let err = second.lock("destroy").await.unwrap_err();
+        assert!(
+            matches!(&err, StateError::Locked { holder, .. } if holder.contains("apply")),
+            "{}",
+            err
+        );
+
+        first.unlock().await.unwrap();
+        second.lock("destroy").await.unwrap();
+        second.unlock().await.unwrap();
+        assert!(!first.lock_path().exists());
+    }
+
+    #[test]
+    fn test_object_key_of_workspaces_and_deployments() {
+        assert_eq!(
+            object_key("shop/state.json", "default", None),
+            "shop/state.json"
+        );
+        assert_eq!(
+            object_key("shop/state.json", "prod", Some("web ${workspace}")),
+            "workspaces/prod/web___workspace_/shop/state.json"
+        );
+    }
+
+    fn fast_retries(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: std::time::Duration::from_millis(1),
+            max_delay: std::time::Duration::from_millis(1),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_retries_only_transient_errors() {
+        let counter = std::sync::atomic::AtomicU32::new(0);
+        let calls = &counter;
+        let call = move |fail: BackendError, failures: u32| {
+            move || {
+                let fail = fail.clone();
+                async move {
+                    match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) < failures {
+                        true => Err(fail),
+                        false => Ok("saved"),
+                    }
+                }
+            }
+        };
+
+        let transient = BackendError::Transient("SlowDown".to_string());
+        let saved = retry_transient(&fast_retries(3), "put", call(transient.clone(), 2)).await;
+        assert_eq!(saved, Ok("saved"));
+        assert_eq!(calls.swap(0, std::sync::atomic::Ordering::SeqCst), 3);
+
+        // Gives up once the retries are used up, with the last error
+        let failed = retry_transient(&fast_retries(3), "put", call(transient.clone(), 10)).await;
+        assert_eq!(failed, Err(transient));
+        assert_eq!(calls.swap(0, std::sync::atomic::Ordering::SeqCst), 4);
+
+        let denied = BackendError::Permission("AccessDenied".to_string());
+        let failed = retry_transient(&fast_retries(3), "put", call(denied.clone(), 10)).await;
+        assert_eq!(failed, Err(denied));
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_classify_by_code_then_status() {
+        let classify = |code, status| BackendError::classify(code, status, String::new());
+        assert!(classify(Some("SlowDown"), Some(503)).is_transient());
+        assert!(classify(Some("InternalError"), None).is_transient());
+        assert!(classify(Some("RequestTimeout"), Some(400)).is_transient());
+        assert!(matches!(
+            classify(Some("PreconditionFailed"), Some(412)),
+            BackendError::Conflict(_)
+        ));
+        assert!(matches!(
+            classify(Some("AccessDenied"), Some(403)),
+            BackendError::Permission(_)
+        ));
+        assert!(matches!(
+            classify(Some("BadDigest"), Some(400)),
+            BackendError::Corrupt(_)
+        ));
+        // A code of its own is not overridden by the status
+        assert!(matches!(
+            classify(Some("NoSuchBucket"), Some(500)),
+            BackendError::Other(_)
+        ));
+        assert!(classify(None, Some(502)).is_transient());
+        assert!(classify(None, Some(429)).is_transient());
+        assert!(matches!(
+            classify(None, Some(412)),
+            BackendError::Conflict(_)
+        ));
+        assert!(matches!(classify(None, Some(404)), BackendError::Other(_)));
+        assert!(matches!(classify(None, None), BackendError::Other(_)));
+    }
+
+    #[test]
+    fn test_errored_state_does_not_overwrite_an_earlier_one() {
+        let dir = std::env::temp_dir().join(format!("letus-errored-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let state = State {
+            version: crate::state::STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: serde_json::json!({ "VPC.main": { "id": "vpc-1" } }),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![],
+            launches: Default::default(),
+        };
+
+        let first = write_errored_state(&dir, &state).unwrap();
+        let second = write_errored_state(&dir, &state).unwrap();
+        assert_eq!(first, dir.join("errored.state.json"));
+        assert_eq!(second, dir.join("errored.state.2.json"));
+        assert_eq!(crate::state::load(&second).unwrap(), Some(state.clone()));
+
+        let err = keep_unsaved(
+            &dir,
+            &state,
+            "s3://bucket/state.json".to_string(),
+            StateError::Remote {
+                backend: "s3://bucket/state.json".to_string(),
+                error: BackendError::Transient("SlowDown".to_string()),
+            },
+        );
+        assert!(
+            matches!(&err, StateError::NotSaved { errored, .. } if errored.ends_with("errored.state.3.json")),
+            "{}",
+            err
+        );
+        assert!(err.to_string().contains("letusinfra state push"), "{}", err);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,201 @@
+/// Upgrades of the state format, applied one version at a time up to STATE_VERSION
+use serde_json::{Map, Value, json};
+
+use super::STATE_VERSION;
+
+/// Upgrade of a state document from version `from` to `from + 1`
+struct Migration {
+    from: u32,
+    description: &'static str,
+    upgrade: fn(&mut Map<String, Value>) -> Result<(), String>,
+}
+
+/// Version of states written before the format was versioned, whatever their `version` says
+const UNVERSIONED: u32 = 0;
+
+/// Every step, in order. A change to the format adds a step here and bumps STATE_VERSION
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: UNVERSIONED,
+    description: "components recorded as resources with a list of instance objects",
+    upgrade: components_to_resources,
+}];
+
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum MigrationError {
+    #[error("version '{0}' is not a state version letusinfra has written")]
+    Unknown(String),
+    #[error("version {found} is newer than version {supported} this letusinfra supports")]
+    TooNew { found: u32, supported: u32 },
+    #[error("upgrade from version {from} failed: {message}")]
+    Failed { from: u32, message: String },
+}
+
+/// The state was upgraded from `from`, by the steps in `steps`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migrated {
+    pub from: u32,
+    pub steps: Vec<&'static str>,
+}
+
+pub fn current_version() -> u32 {
+    STATE_VERSION.parse().unwrap_or_default()
+}
+
+/**
+ * Version of a state document, the `version` it was written with. States from before the format
+ * was versioned carry a free-form version such as `1.0.0`, they are version 0.
+ */
+pub fn version_of(document: &Value) -> Result<u32, MigrationError> {
+    match document.get("version") {
+        Some(Value::String(version)) => Ok(version.parse::<u32>().unwrap_or(UNVERSIONED)),
+        Some(other) => Err(MigrationError::Unknown(other.to_string())),
+        None => Err(MigrationError::Unknown("none".to_string())),
+    }
+}
+
+/**
+ * Bring a state document to the current version, one step after the other. Only the shape of the
+ * document changes, `serial` and `lineage` stay as they are. A document of the current version is
+ * returned untouched with no steps.
+ */
+pub fn migrate(mut document: Value) -> Result<(Value, Migrated), MigrationError> {
+    let from = version_of(&document)?;
+    let supported = current_version();
+    if from > supported {
+        return Err(MigrationError::TooNew {
+            found: from,
+            supported,
+        });
+    }
+    let mut steps = Vec::new();
+    for version in from..supported {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|m| m.from == version)
+            .ok_or_else(|| MigrationError::Failed {
+                from: version,
+                message: "no upgrade to the next version".to_string(),
+            })?;
+        let Some(fields) = document.as_object_mut() else {
+            return Err(MigrationError::Failed {
+                from: version,
+                message: "the state is not a JSON object".to_string(),
+            });
+        };
+        (migration.upgrade)(fields).map_err(|message| MigrationError::Failed {
+            from: version,
+            message,
+        })?;
+        fields.insert(
+            "version".to_string(),
+            Value::String((version + 1).to_string()),
+        );
+        steps.push(migration.description);
+    }
+    Ok((document, Migrated { from, steps }))
+}
+
+/**
+ * 0 to 1, every component becomes a resource with a list of instance objects. An instance recorded
+ * as a bare id becomes `{"id": <id>}`, a single instance becomes a list of one, and a component
+ * without mode or provider is a managed AWS resource, the only kind letusinfra created.
+ */
+fn components_to_resources(fields: &mut Map<String, Value>) -> Result<(), String> {
+    let components = fields
+        .get_mut("components")
+        .and_then(|c| c.as_array_mut())
+        .ok_or_else(|| "components is missing".to_string())?;
+    for (idx, component) in components.iter_mut().enumerate() {
+        let entry = component
+            .as_object_mut()
+            .ok_or_else(|| format!("components[{}] is not an object", idx))?;
+        entry.entry("mode").or_insert_with(|| json!("managed"));
+        entry.entry("provider").or_insert_with(|| json!("aws"));
+        let instances = match entry.remove("instances") {
+            Some(Value::Array(instances)) => instances,
+            Some(Value::Null) | None => Vec::new(),
+            Some(instance) => vec![instance],
+        };
+        let instances = instances
+            .into_iter()
+            .map(|instance| match instance {
+                Value::String(id) => Ok(json!({ "id": id })),
+                Value::Object(_) => Ok(instance),
+                other => Err(format!(
+                    "components[{}] has an instance that is neither an id nor an object: {}",
+                    idx, other
+                )),
+            })
+            .collect::<Result<Vec<Value>, String>>()?;
+        entry.insert("instances".to_string(), Value::Array(instances));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, State};
+    use serde_json::json;
+
+    fn version_0() -> Value {
+        json!({
+            "version": "1.0.0",
+            "serial": 7,
+            "lineage": "18c2-4f1",
+            "modulepack": "shop",
+            "outputs": {"value": {}, "output_type": {"datatype": "object", "value": {}}},
+            "components": [
+                {"mode": "managed", "name": "main", "type": "VPC", "provider": "aws",
+                 "instances": ["vpc-1"]},
+                {"name": "web", "type": "EC2Instance", "instances": {"id": "i-1"}},
+            ],
+        })
+    }
+
+    #[test]
+    fn test_every_version_upgrades_to_the_current_one() {
+        let (document, migrated) = migrate(version_0()).unwrap();
+        assert_eq!(version_of(&document).unwrap(), current_version());
+        assert_eq!(migrated.from, 0);
+        assert_eq!(migrated.steps.len() as u32, current_version());
+
+        let state: State = serde_json::from_value(document).unwrap();
+        assert_eq!(state.modulepack, "shop");
+        assert_eq!((state.serial, state.lineage.as_str()), (7, "18c2-4f1"));
+        assert_eq!(state.components[0].instances, vec![json!({"id": "vpc-1"})]);
+        assert_eq!(state.components[1].mode, ComponentMode::Managed);
+        assert_eq!(state.components[1].provider, "aws");
+        assert_eq!(state.components[1].instances, vec![json!({"id": "i-1"})]);
+    }
+
+    #[test]
+    fn test_current_version_is_untouched() {
+        let (document, _) = migrate(version_0()).unwrap();
+        let (again, migrated) = migrate(document.clone()).unwrap();
+        assert_eq!(again, document);
+        assert!(migrated.steps.is_empty());
+    }
+
+    #[test]
+    fn test_newer_and_unknown_versions_are_rejected() {
+        let newer = json!({ "version": (current_version() + 1).to_string() });
+        assert!(matches!(migrate(newer), Err(MigrationError::TooNew { .. })));
+        for version in [json!(1), json!(null)] {
+            let document = json!({ "version": version });
+            assert!(
+                matches!(migrate(document), Err(MigrationError::Unknown(_))),
+                "{}",
+                version
+            );
+        }
+        assert!(matches!(
+            migrate(json!({})),
+            Err(MigrationError::Unknown(_))
+        ));
+        assert!(matches!(
+            migrate(json!({ "version": "1.0.0" })),
+            Err(MigrationError::Failed { from: 0, .. })
+        ));
+    }
+}
@@ -0,0 +1,522 @@
+/// Module for the state file recording what apply created, kept locally or in a backend
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    models::state::{ComponentState, State},
+    state::migrations::{Migrated, MigrationError},
+    utils::{
+        constants::{DEFAULT_WORKSPACE, STATE_FILE_NAME},
+        validate_workspace,
+    },
+};
+
+pub mod backend;
+pub mod migrations;
+
+pub const STATE_VERSION: &str = "1";
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("Failed to read state file {path}: {message}")]
+    Read { path: String, message: String },
+    #[error("State file {path} is not valid: {message}")]
+    Parse { path: String, message: String },
+    #[error("Failed to write state file {path}: {message}")]
+    Write { path: String, message: String },
+    #[error("{0}")]
+    InvalidWorkspace(String),
+    #[error("State backend {backend}: {message}")]
+    Backend { backend: String, message: String },
+    #[error(
+        "State {backend} is locked by {holder}. Wait for that run to finish, or remove the lock once it is gone"
+    )]
+    Locked { backend: String, holder: String },
+    #[error("State {0} was changed by another run since it was read, run the command again")]
+    Conflict(String),
+    #[error("State backend {backend}: {error}")]
+    Remote {
+        backend: String,
+        error: backend::BackendError,
+    },
+    #[error(
+        "State could not be saved to {backend}: {error}\nIt was written to {errored} instead, push it once the backend works again: letusinfra state push {errored}"
+    )]
+    NotSaved {
+        backend: String,
+        error: String,
+        errored: String,
+    },
+    #[error(
+        "State {path} is version {found}, this letusinfra only supports up to version {supported}. Upgrade letusinfra to use it"
+    )]
+    TooNew {
+        path: String,
+        found: u32,
+        supported: u32,
+    },
+}
+
+/// A state rewritten in the current format by `state upgrade`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upgraded {
+    pub from: u32,
+    pub steps: Vec<&'static str>,
+    pub backup: String, // Where the state was copied to as it was
+}
+
+/**
+ * State file of `workspace` in `dir`, every workspace has its own `<workspace>/state.json`.
+ * The default workspace keeps using a `state.json` written directly in `dir` by earlier versions
+ * until its own file exists.
+ */
+pub fn workspace_path(dir: &Path, workspace: &str) -> Result<PathBuf, StateError> {
+    validate_workspace(workspace).map_err(StateError::InvalidWorkspace)?;
+    let path = dir.join(workspace).join(STATE_FILE_NAME);
+    let legacy = dir.join(STATE_FILE_NAME);
+    if workspace == DEFAULT_WORKSPACE && !path.exists() && legacy.is_file() {
+        return Ok(legacy);
+    }
+    Ok(path)
+}
+
+/**
+ * State of one of several deployments applied from the same `-f`, `<workspace>/<name>/state.json`
+ * in `dir`
+ */
+pub fn deployment_path(
+    dir: &Path,
+    workspace: &str,
+    deployment: &str,
+) -> Result<PathBuf, StateError> {
+    validate_workspace(workspace).map_err(StateError::InvalidWorkspace)?;
+    Ok(dir
+        .join(workspace)
+        .join(deployment_directory(deployment))
+        .join(STATE_FILE_NAME))
+}
+
+/// Names may hold references or other characters that do not belong in a path or an object key
+fn deployment_directory(deployment: &str) -> String {
+    deployment
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Workspaces with a state file in `dir`, sorted by name
+pub fn list_workspaces(dir: &Path) -> Result<Vec<String>, StateError> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(StateError::Read {
+                path: dir.display().to_string(),
+                message: err.to_string(),
+            });
+        }
+    };
+    let mut workspaces: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join(STATE_FILE_NAME).is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| validate_workspace(name).is_ok())
+        .collect();
+    let has_default = workspaces.iter().any(|w| w == DEFAULT_WORKSPACE);
+    if !has_default && dir.join(STATE_FILE_NAME).is_file() {
+        workspaces.push(DEFAULT_WORKSPACE.to_string());
+    }
+    workspaces.sort();
+    Ok(workspaces)
+}
+
+/// Content of the state file at `path`, None when nothing has been written there yet
+fn read(path: &Path) -> Result<Option<String>, StateError> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(StateError::Read {
+            path: path.display().to_string(),
+            message: err.to_string(),
+        }),
+    }
+}
+
+/// State stored at `path`, None when nothing has been written there yet
+pub fn load(path: &Path) -> Result<Option<State>, StateError> {
+    match read(path)? {
+        Some(content) => {
+            let (state, _) = decode(&path.display().to_string(), content.as_bytes())?;
+            Ok(Some(state))
+        }
+        None => Ok(None),
+    }
+}
+
+/**
+ * State of a stored document, upgraded in memory when it was written in an older format. The
+ * next write stores it in the current format, `state upgrade` does so right away.
+ */
+fn decode(path: &str, content: &[u8]) -> Result<(State, Migrated), StateError> {
+    let parse_err = |message: String| StateError::Parse {
+        path: path.to_string(),
+        message,
+    };
+    let document: serde_json::Value =
+        serde_json::from_slice(content).map_err(|err| parse_err(err.to_string()))?;
+    let (document, migrated) = migrations::migrate(document).map_err(|err| match err {
+        MigrationError::TooNew { found, supported } => StateError::TooNew {
+            path: path.to_string(),
+            found,
+            supported,
+        },
+        err => parse_err(err.to_string()),
+    })?;
+    let state = serde_json::from_value(document).map_err(|err| parse_err(err.to_string()))?;
+    Ok((state, migrated))
+}
+
+/// Name of the copy `state upgrade` keeps of a state of version `from`, next to the state
+fn backup_name(name: &str, from: u32) -> String {
+    format!("{}.v{}.backup", name, from)
+}
+
+/**
+ * Rewrite the state at `path` in the current format, once a copy of the file as it was is written
+ * next to it. Serial and lineage are kept, the content is the same state. None when there is no
+ * state or it is already current.
+ */
+pub fn upgrade(path: &Path) -> Result<Option<Upgraded>, StateError> {
+    let Some(content) = read(path)? else {
+        return Ok(None);
+    };
+    let (state, migrated) = decode(&path.display().to_string(), content.as_bytes())?;
+    if migrated.steps.is_empty() {
+        return Ok(None);
+    }
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| STATE_FILE_NAME.to_string());
+    let backup = path.with_file_name(backup_name(&name, migrated.from));
+    fs::write(&backup, &content).map_err(|err| StateError::Write {
+        path: backup.display().to_string(),
+        message: err.to_string(),
+    })?;
+    replace(path, &state)?;
+    Ok(Some(Upgraded {
+        from: migrated.from,
+        steps: migrated.steps,
+        backup: backup.display().to_string(),
+    }))
+}
+
+/// Random enough to tell two unrelated states apart, without pulling in a uuid dependency
+fn new_lineage() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{:x}-{:x}", nanos, std::process::id())
+}
+
+/// Next serial of `previous` and its lineage, a new lineage when there is nothing before `state`
+fn continue_lineage(state: &mut State, previous: Option<State>) {
+    state.serial = previous.as_ref().map(|p| p.serial + 1).unwrap_or(1);
+    state.lineage = match previous {
+        Some(previous) if !previous.lineage.is_empty() => previous.lineage,
+        _ => new_lineage(),
+    };
+}
+
+/**
+ * Replace the components of `previous` that were applied again and keep the others, so a partial
+ * apply does not forget resources created by earlier runs.
+ */
+pub fn merge_components(
+    previous: Option<&State>,
+    applied: Vec<ComponentState>,
+) -> Vec<ComponentState> {
+    let mut components: Vec<ComponentState> = previous
+        .map(|state| state.components.clone())
+        .unwrap_or_default();
+    for component in applied {
+        match components
+            .iter_mut()
+            .find(|c| c.component_type == component.component_type && c.name == component.name)
+        {
+            Some(existing) => *existing = component,
+            None => components.push(component),
+        }
+    }
+    components
+}
+
+/**
+ * Write `state` to `path`, continuing the serial and lineage of the state already there.
+ * The new content goes to a temporary file in the same directory that is then renamed over the
+ * old one, so a crash mid-write leaves the previous state intact.
+ */
+pub fn write(path: &Path, mut state: State) -> Result<State, StateError> {
+    let previous = load(path)?;
+    continue_lineage(&mut state, previous);
+    replace(path, &state)?;
+    Ok(state)
+}
+
+/// Put `state` at `path` as it is, through a temporary file renamed over the old one
+fn replace(path: &Path, state: &State) -> Result<(), StateError> {
+    let write_err = |message: String| StateError::Write {
+        path: path.display().to_string(),
+        message,
+    };
+    let content = serde_json::to_string_pretty(state).map_err(|err| write_err(err.to_string()))?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir).map_err(|err| write_err(err.to_string()))?;
+    }
+    let mut temp_path = PathBuf::from(path);
+    temp_path.set_file_name(format!(
+        ".{}.tmp-{}",
+        path.file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| "state.json".to_string()),
+        std::process::id()
+    ));
+
+    let result = fs::File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(content.as_bytes())?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&temp_path, path));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(write_err(err.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, Datatype, Output, OutputType};
+    use crate::utils::constants::STATE_DIR;
+    use serde_json::json;
+
+    fn temp_state_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("letus-state-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir.join(".letusinfra").join("state.json")
+    }
+
+    fn component(name: &str, id: &str) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: name.to_string(),
+            component_type: "EC2Instance".to_string(),
+            provider: "aws".to_string(),
+            instances: vec![json!({ "id": id })],
+            created_at: Some(1_700_000_000),
+            properties_hash: None,
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    fn state(components: Vec<ComponentState>) -> State {
+        State {
+            version: STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: json!({}),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components,
+            launches: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_missing_state_is_none() {
+        assert!(load(&temp_state_path("missing")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_write_increments_serial_and_keeps_lineage() {
+        let path = temp_state_path("serial");
+        let first = write(&path, state(vec![component("web", "i-1")])).unwrap();
+        assert_eq!(first.serial, 1);
+        assert!(!first.lineage.is_empty());
+
+        let second = write(&path, state(vec![component("web", "i-2")])).unwrap();
+        assert_eq!(second.serial, 2);
+        assert_eq!(second.lineage, first.lineage);
+        assert_eq!(load(&path).unwrap(), Some(second));
+
+        // No temporary files are left next to the state
+        let entries: Vec<_> = fs::read_dir(path.parent().unwrap()).unwrap().collect();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_invalid_state_is_not_overwritten() {
+        let path = temp_state_path("invalid");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+        assert!(matches!(
+            write(&path, state(vec![])),
+            Err(StateError::Parse { .. })
+        ));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{ not json");
+    }
+
+    #[test]
+    fn test_upgrade_keeps_a_backup_and_the_serial() {
+        let path = temp_state_path("upgrade");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let version_0 = json!({
+            "version": "1.0.0",
+            "serial": 4,
+            "lineage": "18c2-4f1",
+            "modulepack": "shop",
+            "outputs": {"value": {}, "output_type": {"datatype": "object", "value": {}}},
+            "components": [
+                {"mode": "managed", "name": "main", "type": "VPC", "provider": "aws",
+                 "instances": ["vpc-1"]},
+            ],
+        })
+        .to_string();
+        fs::write(&path, &version_0).unwrap();
+        // Read in the current format before the file is upgraded
+        let loaded = load(&path).unwrap().unwrap();
+        assert_eq!(loaded.components[0].instances[0]["id"], "vpc-1");
+
+        let upgraded = upgrade(&path).unwrap().unwrap();
+        assert_eq!(upgraded.from, 0);
+        assert!(upgraded.backup.ends_with(".v0.backup"));
+        assert_eq!(fs::read_to_string(&upgraded.backup).unwrap(), version_0);
+        let state = load(&path).unwrap().unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.modulepack, "shop");
+        assert_eq!((state.serial, state.lineage.as_str()), (4, "18c2-4f1"));
+        assert_eq!(upgrade(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_newer_state_is_a_hard_error() {
+        let path = temp_state_path("newer");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, r#"{"version": "99"}"#).unwrap();
+        assert!(matches!(
+            load(&path),
+            Err(StateError::TooNew { found: 99, .. })
+        ));
+        assert!(matches!(
+            write(&path, state(vec![])),
+            Err(StateError::TooNew { .. })
+        ));
+    }
+
+    #[test]
+    fn test_workspaces_have_their_own_state() {
+        let dir = temp_state_path("workspaces")
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let staging = workspace_path(&dir, "staging").unwrap();
+        let prod = workspace_path(&dir, "prod").unwrap();
+        assert_eq!(staging, dir.join("staging").join("state.json"));
+
+        write(&staging, state(vec![component("web", "i-1")])).unwrap();
+        assert_eq!(load(&prod).unwrap(), None);
+        write(&prod, state(vec![component("web", "i-2")])).unwrap();
+        assert_eq!(
+            load(&staging).unwrap().unwrap().components[0].instances[0]["id"],
+            "i-1"
+        );
+        assert_eq!(list_workspaces(&dir).unwrap(), vec!["prod", "staging"]);
+
+        assert!(matches!(
+            workspace_path(&dir, "../prod"),
+            Err(StateError::InvalidWorkspace(_))
+        ));
+    }
+
+    #[test]
+    fn test_deployments_of_one_source_have_their_own_state() {
+        let dir = Path::new(STATE_DIR);
+        assert_eq!(
+            deployment_path(dir, "prod", "shop-${workspace}").unwrap(),
+            dir.join("prod")
+                .join("shop-__workspace_")
+                .join("state.json")
+        );
+        assert!(deployment_path(dir, "../prod", "shop").is_err());
+    }
+
+    #[test]
+    fn test_default_workspace_keeps_legacy_state() {
+        let dir = temp_state_path("legacy").parent().unwrap().to_path_buf();
+        let legacy = dir.join("state.json");
+        write(&legacy, state(vec![])).unwrap();
+        assert_eq!(workspace_path(&dir, "default").unwrap(), legacy);
+        assert_eq!(list_workspaces(&dir).unwrap(), vec!["default"]);
+        assert_eq!(
+            workspace_path(&dir, "staging").unwrap(),
+            dir.join("staging").join("state.json")
+        );
+    }
+
+    #[test]
+    fn test_state_keeps_only_a_hash_of_properties() {
+        let web: crate::models::Component = serde_yaml::from_str(
+            "type: EC2Instance\nname: web\nproperties: {user_data: export TOKEN=hunter2}\n",
+        )
+        .unwrap();
+        let mut applied = component("web", "i-1");
+        applied.properties_hash = Some(crate::utils::component_hash(&web));
+
+        let path = temp_state_path("sensitive");
+        write(&path, state(vec![applied])).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        assert!(!content.contains("hunter2"), "{}", content);
+    }
+
+    #[test]
+    fn test_merge_components_replaces_by_type_and_name() {
+        let previous = state(vec![component("web", "i-1"), component("db", "i-2")]);
+        let merged = merge_components(
+            Some(&previous),
+            vec![component("web", "i-3"), component("worker", "i-4")],
+        );
+        let ids: Vec<(&str, &serde_json::Value)> = merged
+            .iter()
+            .map(|c| (c.name.as_str(), &c.instances[0]["id"]))
+            .collect();
+        assert_eq!(
+            ids,
+            vec![
+                ("web", &json!("i-3")),
+                ("db", &json!("i-2")),
+                ("worker", &json!("i-4"))
+            ]
+        );
+    }
+}
@@ -1,6 +1,11 @@
 #[cfg(test)]
 mod tests {
-    use crate::aws::ec2::ec2_instance::{EC2Error, EC2Instance};
+    use crate::aws::ec2::ec2_instance::{
+        AmiLookup, DestroyOutcome, EC2Error, EC2Instance, KNOWN_AFTER_APPLY, SpotProgress,
+        select_image, spot_progress,
+    };
+    use crate::aws::internal::tags::{COMPONENT_TAG, DeploymentTags};
+    use crate::aws::internal::wait_and_refresh::WaitDefaults;
     use aws_config::BehaviorVersion;
     use aws_sdk_ec2::{config::ProvideCredentials, types as ec2_types};
     use serial_test::serial;
@@ -14,6 +19,17 @@ mod tests {
         }
     }
 
+    /// Polls aggressively, localstack answers right away
+    fn fast_wait() -> WaitDefaults {
+        WaitDefaults {
+            min_delay: std::time::Duration::from_millis(100),
+            max_delay: std::time::Duration::from_millis(500),
+            not_found_checks: 5,
+            refresh_timeout: std::time::Duration::from_secs(2),
+            ..WaitDefaults::default()
+        }
+    }
+
     /// Test helper to cleanup test environment
     fn cleanup_test_credentials() {
         unsafe {
@@ -230,6 +246,434 @@ instance_type: t2.micro
         assert_eq!(opts.image_id, "ami-87654321");
     }
 
+    #[test]
+    fn test_opts_from_yaml_status_checks() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+ami: ami-123
+instance_type: t2.micro
+wait_for_status_checks: true
+status_checks_timeout: 120
+"#,
+        )
+        .unwrap();
+        let opts = EC2Instance::opts_from_yaml(&yaml).unwrap();
+        assert!(opts.wait_for_status_checks);
+        assert_eq!(
+            opts.status_checks_timeout,
+            std::time::Duration::from_secs(120)
+        );
+        assert_eq!(opts.computed()["status_checks_seconds"], KNOWN_AFTER_APPLY);
+
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("ami: ami-123\ninstance_type: t2.micro").unwrap();
+        let opts = EC2Instance::opts_from_yaml(&yaml).unwrap();
+        assert!(!opts.wait_for_status_checks);
+        assert!(opts.computed().get("status_checks_seconds").is_none());
+        assert_eq!(
+            opts.status_checks_timeout,
+            std::time::Duration::from_secs(600)
+        );
+    }
+
+    #[test]
+    fn test_opts_from_yaml_launch_template() {
+        let opts = |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        let base = "ami: ami-123\ninstance_type: t2.micro\n";
+
+        let by_id = opts(&format!(
+            "{}launch_template: {{id: lt-0abc, version: 3}}",
+            base
+        ))
+        .unwrap();
+        let template = by_id.launch_template.unwrap();
+        assert_eq!(template.launch_template_id(), Some("lt-0abc"));
+        assert_eq!(template.launch_template_name(), None);
+        assert_eq!(template.version(), Some("3"));
+
+        let by_name = opts(&format!("{}launch_template: {{name: web}}", base)).unwrap();
+        let template = by_name.launch_template.unwrap();
+        assert_eq!(template.launch_template_name(), Some("web"));
+        assert_eq!(template.version(), None);
+
+        assert!(opts(base).unwrap().launch_template.is_none());
+        for invalid in [
+            "launch_template: {id: lt-0abc, name: web}",
+            "launch_template: {version: $Latest}",
+        ] {
+            assert!(
+                opts(&format!("{}{}", base, invalid)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_opts_from_yaml_spot_market_options() {
+        let opts = |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        let base = "ami: ami-123\ninstance_type: t2.micro\n";
+
+        let spot = opts(&format!(
+            "{}instance_market_options:\n  market_type: spot\n  spot_options:\n    max_price: 0.05\n    spot_instance_type: persistent\n    instance_interruption_behavior: stop\n",
+            base
+        ))
+        .unwrap();
+        let market = spot.instance_market_options.unwrap();
+        assert_eq!(market.market_type(), Some(&ec2_types::MarketType::Spot));
+        let spot_options = market.spot_options().unwrap();
+        assert_eq!(spot_options.max_price(), Some("0.05"));
+        assert_eq!(
+            spot_options.spot_instance_type(),
+            Some(&ec2_types::SpotInstanceType::Persistent)
+        );
+        assert_eq!(
+            spot_options.instance_interruption_behavior(),
+            Some(&ec2_types::InstanceInterruptionBehavior::Stop)
+        );
+
+        // Spot options alone mean a Spot Instance
+        let implied = opts(&format!(
+            "{}instance_market_options: {{spot_options: {{}}}}",
+            base
+        ))
+        .unwrap();
+        let market = implied.instance_market_options.unwrap();
+        assert_eq!(market.market_type(), Some(&ec2_types::MarketType::Spot));
+
+        for invalid in [
+            "instance_market_options: {market_type: reserved}",
+            "instance_market_options: {market_type: capacity-block, spot_options: {}}",
+            "instance_market_options: {spot_options: {max_price: cheap}}",
+            "instance_market_options: {spot_options: {max_price: -1}}",
+            "instance_market_options: {spot_options: {spot_instance_type: forever}}",
+            "instance_market_options: {spot_options: {instance_interruption_behavior: stop}}",
+        ] {
+            assert!(
+                opts(&format!("{}{}", base, invalid)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_spot_opts_and_progress() {
+        let opts =
+            |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap()).unwrap();
+        let on_demand = opts("ami: ami-123\ninstance_type: t3.micro");
+        assert!(!on_demand.is_spot());
+        assert_eq!(
+            on_demand.spot_fulfillment_timeout,
+            std::time::Duration::from_secs(300)
+        );
+        assert!(on_demand.computed().get("spot_request_id").is_none());
+
+        let spot = opts(
+            "ami: ami-123\ninstance_type: t3.micro\nspot_fulfillment_timeout: 60\ninstance_market_options: {market_type: spot}",
+        );
+        assert!(spot.is_spot());
+        assert_eq!(
+            spot.spot_fulfillment_timeout,
+            std::time::Duration::from_secs(60)
+        );
+        assert_eq!(spot.computed()["spot_request_id"], KNOWN_AFTER_APPLY);
+
+        assert_eq!(
+            spot_progress("active", "fulfilled"),
+            SpotProgress::Fulfilled
+        );
+        assert_eq!(
+            spot_progress("open", "pending-evaluation"),
+            SpotProgress::Pending("pending-evaluation".to_string())
+        );
+        assert_eq!(
+            spot_progress("open", "capacity-not-available"),
+            SpotProgress::WaitingForCapacity("capacity-not-available".to_string())
+        );
+        assert_eq!(
+            spot_progress("closed", "price-too-low"),
+            SpotProgress::Failed("price-too-low".to_string())
+        );
+        assert_eq!(
+            spot_progress("cancelled", "canceled-before-fulfillment"),
+            SpotProgress::Failed("canceled-before-fulfillment".to_string())
+        );
+    }
+
+    #[test]
+    fn test_opts_from_yaml_capacity_reservation_and_maintenance() {
+        let opts = |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        let base = "ami: ami-123\ninstance_type: t2.micro\n";
+
+        let open = opts(&format!(
+            "{}capacity_reservation_specification: {{capacity_reservation_preference: none}}\nmaintenance_options: {{auto_recovery: disabled}}",
+            base
+        ))
+        .unwrap();
+        assert_eq!(
+            open.capacity_reservation_specification
+                .unwrap()
+                .capacity_reservation_preference(),
+            Some(&ec2_types::CapacityReservationPreference::None)
+        );
+        assert_eq!(
+            open.maintenance_options.unwrap().auto_recovery(),
+            Some(&ec2_types::InstanceAutoRecoveryState::Disabled)
+        );
+
+        let targeted = opts(&format!(
+            "{}capacity_reservation_specification: {{capacity_reservation_id: cr-0abc}}",
+            base
+        ))
+        .unwrap();
+        let specification = targeted.capacity_reservation_specification.unwrap();
+        assert_eq!(specification.capacity_reservation_preference(), None);
+        assert_eq!(
+            specification
+                .capacity_reservation_target()
+                .and_then(|t| t.capacity_reservation_id()),
+            Some("cr-0abc")
+        );
+
+        for invalid in [
+            "capacity_reservation_specification: {}",
+            "capacity_reservation_specification: {capacity_reservation_preference: always}",
+            "capacity_reservation_specification: {capacity_reservation_preference: open, capacity_reservation_id: cr-0abc}",
+            "capacity_reservation_specification: {capacity_reservation_id: cr-0abc, capacity_reservation_resource_group_arn: 'arn:aws:resource-groups:us-west-2:123456789012:group/crs'}",
+            "maintenance_options: {auto_recovery: always}",
+        ] {
+            assert!(
+                opts(&format!("{}{}", base, invalid)).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_opts_problems_and_computed_values() {
+        let opts =
+            |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap()).unwrap();
+        let valid = opts("ami: ami-123\ninstance_type: t3.micro");
+        assert!(valid.problems().is_empty());
+        let computed = valid.computed();
+        assert_eq!(computed["instance_id"], KNOWN_AFTER_APPLY);
+        assert_eq!(computed["instance_type"], "t3.micro");
+        assert_eq!(computed["min_count"], 1);
+        assert_eq!(computed["max_count"], 1);
+
+        let invalid = opts("ami: ami-123\ninstance_type: t3.mega\nmin_count: 3\nmax_count: 2");
+        assert_eq!(
+            invalid.problems(),
+            vec![
+                "unknown instance_type 't3.mega'".to_string(),
+                "min_count 3 is greater than max_count 2".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_public_ip_is_waited_for_when_requested() {
+        let opts =
+            |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap()).unwrap();
+        let private = opts("ami: ami-123\ninstance_type: t3.micro\nsubnet_id: subnet-1");
+        assert!(!private.requests_public_ip());
+        assert_eq!(
+            private.public_ip_timeout,
+            std::time::Duration::from_secs(120)
+        );
+        assert_eq!(private.computed()["public_dns"], KNOWN_AFTER_APPLY);
+
+        let public = opts(
+            "ami: ami-123\ninstance_type: t3.micro\npublic_ip_timeout: 30\nnetwork_interfaces: [{device_index: 0, subnet_id: subnet-1, associate_public_ip_address: true}]",
+        );
+        assert!(public.requests_public_ip());
+        assert_eq!(public.public_ip_timeout, std::time::Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_user_data_is_base64_encoded() {
+        let opts = |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        let base = "ami: ami-123\ninstance_type: t2.micro\n";
+
+        let plain = opts(&format!("{}user_data: \"#!/bin/bash\\necho hi\"", base)).unwrap();
+        assert_eq!(
+            plain.user_data.as_deref(),
+            Some("IyEvYmluL2Jhc2gKZWNobyBoaQ==")
+        );
+
+        let encoded = opts(&format!("{}user_data: IyEvYmluL2Jhc2gKZWNobyBoaQ==", base)).unwrap();
+        assert_eq!(
+            encoded.user_data.as_deref(),
+            Some("IyEvYmluL2Jhc2gKZWNobyBoaQ==")
+        );
+
+        // Gzipped user data does not decode to text, the flag says it is encoded already
+        let flagged = opts(&format!(
+            "{}user_data: H4sIAAAA\nuser_data_base64: true",
+            base
+        ))
+        .unwrap();
+        assert_eq!(flagged.user_data.as_deref(), Some("H4sIAAAA"));
+
+        let path = std::env::temp_dir().join(format!("letus-user-data-{}.sh", std::process::id()));
+        std::fs::write(&path, "#!/bin/bash\necho hi").unwrap();
+        let from_file = opts(&format!("{}user_data_file: {}", base, path.display())).unwrap();
+        assert_eq!(
+            from_file.user_data.as_deref(),
+            Some("IyEvYmluL2Jhc2gKZWNobyBoaQ==")
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = opts(&format!("{}user_data_file: {}", base, path.display()));
+        let Err(EC2Error::OptionsError(message)) = missing else {
+            panic!("expected an options error, got {:?}", missing);
+        };
+        assert!(
+            message.starts_with("Failed to read user_data_file"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_changes_against_existing_instance() {
+        let opts = |yaml: &str| EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        let current = ec2_types::Instance::builder()
+            .image_id("ami-123")
+            .instance_type(ec2_types::InstanceType::T2Micro)
+            .subnet_id("subnet-1")
+            .security_groups(
+                ec2_types::GroupIdentifier::builder()
+                    .group_id("sg-1")
+                    .build(),
+            )
+            .build();
+
+        let unchanged =
+            opts("ami: ami-123\ninstance_type: t2.micro\nsecurity_group_ids: [sg-1]").unwrap();
+        assert!(unchanged.replacement_reasons(&current).is_empty());
+        assert!(unchanged.changes(&current, None, None).is_empty());
+
+        let resized = opts(
+            "ami: ami-123\ninstance_type: t3.large\nsecurity_group_ids: [sg-2, sg-1]\ndisable_api_termination: true\nuser_data: echo hi",
+        )
+        .unwrap();
+        let changes = resized.changes(&current, None, Some(false));
+        assert_eq!(
+            changes.changed(),
+            vec![
+                "instance_type",
+                "user_data",
+                "disable_api_termination",
+                "security_group_ids"
+            ]
+        );
+        assert!(changes.needs_stop());
+        assert_eq!(
+            changes.security_group_ids,
+            Some(vec!["sg-1".to_string(), "sg-2".to_string()])
+        );
+        // Same user data as the instance already has is not modified again
+        let same_user_data = resized.changes(&current, resized.user_data.as_deref(), Some(true));
+        assert_eq!(
+            same_user_data.changed(),
+            vec!["instance_type", "security_group_ids"]
+        );
+
+        let moved = opts("ami: ami-456\ninstance_type: t2.micro\nsubnet_id: subnet-2").unwrap();
+        assert_eq!(
+            moved.replacement_reasons(&current),
+            vec![
+                "ami: ami-123 -> ami-456".to_string(),
+                "subnet_id: subnet-1 -> subnet-2".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_debug_output_hides_user_data() {
+        let yaml = "ami: ami-123\ninstance_type: t2.micro\nuser_data: export TOKEN=hunter2\n";
+        let opts = EC2Instance::opts_from_yaml(&serde_yaml::from_str(yaml).unwrap()).unwrap();
+        let logged = format!("{:?}", opts);
+        assert!(
+            logged.contains("user_data: Some((sensitive))"),
+            "{}",
+            logged
+        );
+        assert!(!logged.contains("hunter2"), "{}", logged);
+        assert!(
+            !logged.contains(opts.user_data.as_deref().unwrap()),
+            "{}",
+            logged
+        );
+    }
+
+    fn lookup(most_recent: bool) -> AmiLookup {
+        let yaml = format!(
+            "ami_lookup: {{owners: [amazon], name_pattern: al2023-ami-*, architecture: arm64, most_recent: {}}}",
+            most_recent
+        );
+        AmiLookup::from_yaml(&serde_yaml::from_str(&yaml).unwrap())
+            .unwrap()
+            .unwrap()
+    }
+
+    fn image(id: &str, created: &str) -> ec2_types::Image {
+        ec2_types::Image::builder()
+            .image_id(id)
+            .name(format!("al2023-ami-{}", id))
+            .creation_date(created)
+            .build()
+    }
+
+    #[test]
+    fn test_ami_lookup_from_yaml() {
+        let parse = |yaml: &str| AmiLookup::from_yaml(&serde_yaml::from_str(yaml).unwrap());
+        assert_eq!(parse("ami: ami-123").unwrap(), None);
+        assert_eq!(
+            lookup(false).filters(),
+            "owners=amazon, name=al2023-ami-*, architecture=arm64"
+        );
+        assert!(parse("ami_lookup: {name_pattern: al2023-*}").is_err());
+        assert!(parse("ami_lookup: {owners: [amazon]}").is_err());
+        assert!(parse("ami_lookup: al2023").is_err());
+    }
+
+    #[test]
+    fn test_select_image() {
+        let images = vec![
+            image("ami-old", "2024-01-10T00:00:00.000Z"),
+            image("ami-new", "2024-03-10T00:00:00.000Z"),
+            image("ami-mid", "2024-02-10T00:00:00.000Z"),
+        ];
+        assert_eq!(select_image(&images, &lookup(true)).unwrap(), "ami-new");
+        assert_eq!(
+            select_image(&images[..1], &lookup(false)).unwrap(),
+            "ami-old"
+        );
+
+        let Err(EC2Error::OptionsError(ambiguous)) = select_image(&images, &lookup(false)) else {
+            panic!("several matches without most_recent must fail");
+        };
+        assert!(ambiguous.contains("matches 3 AMIs"), "{}", ambiguous);
+        assert!(
+            ambiguous.contains("ami-new (al2023-ami-ami-new, 2024-03-10T00:00:00.000Z), ami-mid"),
+            "{}",
+            ambiguous
+        );
+
+        let Err(EC2Error::OptionsError(none)) = select_image(&[], &lookup(true)) else {
+            panic!("no match must fail");
+        };
+        assert!(
+            none.contains("owners=amazon, name=al2023-ami-*, architecture=arm64"),
+            "{}",
+            none
+        );
+    }
+
     // Test for testing actual ec2 instance creation would go here
     #[tokio::test]
     async fn test_ec2_instance_creation() {
@@ -299,6 +743,107 @@ instance_type: t2.micro
         );
     }
 
+    fn web_tags() -> DeploymentTags {
+        DeploymentTags {
+            deployment: "shop".to_string(),
+            workspace: "default".to_string(),
+            component_type: "EC2Instance".to_string(),
+            component_name: "web".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_for_component_tags_instance_and_keeps_user_token() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+ami: ami-12345678
+instance_type: t2.micro
+tag_specifications:
+  - resource_type: instance
+    tags:
+      Name: web
+"#,
+        )
+        .unwrap();
+        let opts = EC2Instance::opts_from_yaml(&yaml)
+            .unwrap()
+            .for_component(&web_tags(), "letusinfra-token");
+        assert_eq!(opts.client_token.as_deref(), Some("letusinfra-token"));
+        assert_eq!(
+            opts.component_id.as_deref(),
+            Some("shop/default/EC2Instance.web")
+        );
+
+        let opts = format!("{:?}", opts);
+        assert!(opts.contains(COMPONENT_TAG));
+        assert!(opts.contains("Name"), "existing instance tags are kept");
+
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            "ami: ami-12345678\ninstance_type: t2.micro\nclient_token: mine\n",
+        )
+        .unwrap();
+        let opts = EC2Instance::opts_from_yaml(&yaml)
+            .unwrap()
+            .for_component(&web_tags(), "letusinfra-token");
+        assert_eq!(opts.client_token.as_deref(), Some("mine"));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_ec2_instance_create_twice_returns_same_instance_localstack() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("ami: ami-04c174f38aefd7dc8\ninstance_type: t2.micro\n").unwrap();
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let ec2_instance = EC2Instance::from_config(&config).with_wait(fast_wait());
+        let tags = DeploymentTags {
+            component_name: format!("rerun-{}", std::process::id()),
+            ..web_tags()
+        };
+        let opts = EC2Instance::opts_from_yaml(&yaml)
+            .unwrap()
+            .for_component(&tags, &format!("rerun-{}", std::process::id()));
+
+        let first = ec2_instance.create_instance(&opts).await.unwrap();
+        let second = ec2_instance.create_instance(&opts).await.unwrap();
+        let instance_id = first.instance_id().unwrap().to_string();
+        ec2_instance.destroy_instance(&instance_id).await.unwrap();
+
+        assert_eq!(second.instance_id(), Some(instance_id.as_str()));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_ec2_instance_create_terminate_cycle_localstack() {
+        let yaml_str = r#"
+ami: ami-04c174f38aefd7dc8
+instance_type: t2.micro
+"#;
+        let yaml: serde_yaml::Value = serde_yaml::from_str(yaml_str).unwrap();
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let ec2_instance = EC2Instance::from_config(&config).with_wait(fast_wait());
+        let opts = EC2Instance::opts_from_yaml(&yaml).unwrap();
+
+        let started = std::time::Instant::now();
+        let created_instance = ec2_instance.create_instance(&opts).await.unwrap();
+        ec2_instance
+            .terminate_instance(&created_instance.instance_id.clone().unwrap())
+            .await
+            .unwrap();
+        let elapsed = started.elapsed();
+
+        assert!(
+            elapsed < std::time::Duration::from_secs(5),
+            "Create/terminate cycle against localstack should complete quickly, took {:?}",
+            elapsed
+        );
+    }
+
     // #[tokio::test]
     // async fn test_ec2_instance_deletion() {
     //     // This test would require integration testing with AWS or localstack
@@ -331,6 +876,48 @@ instance_type: t2.micro
         assert_eq!(error.err(), Some(EC2Error::InstanceNotFound));
     }
 
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_ec2_instance_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let ec2_instance = EC2Instance::from_config(&config);
+
+        let outcome = ec2_instance.destroy_instance("i-f45b1068dd622f3c").await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_ec2_instance_destroy_waits_for_terminated_localstack() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("ami: ami-04c174f38aefd7dc8\ninstance_type: t2.micro\n").unwrap();
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let ec2_instance = EC2Instance::from_config(&config).with_wait(fast_wait());
+        let opts = EC2Instance::opts_from_yaml(&yaml).unwrap();
+
+        let created_instance = ec2_instance.create_instance(&opts).await.unwrap();
+        let instance_id = created_instance.instance_id.clone().unwrap();
+        let outcome = ec2_instance.destroy_instance(&instance_id).await;
+        let state = ec2_instance
+            .describe_instance(&instance_id)
+            .await
+            .ok()
+            .and_then(|i| i.state)
+            .and_then(|s| s.name);
+
+        assert_eq!(outcome, Ok(DestroyOutcome::Terminated));
+        assert!(matches!(
+            state,
+            None | Some(ec2_types::InstanceStateName::Terminated)
+        ));
+    }
+
     #[tokio::test]
     async fn test_ec2_instance_stop_not_found() {
         // This test would require integration testing with AWS or localstack
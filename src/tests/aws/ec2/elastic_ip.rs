@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::ec2::{ec2_instance::DestroyOutcome, elastic_ip::ElasticIp};
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+associate_with: i-0123456789abcdef0
+tags:
+  Name: web
+"#,
+        )
+        .unwrap();
+        let opts = ElasticIp::opts_from_yaml(&yaml).unwrap();
+        assert_eq!(opts.associate_with.as_deref(), Some("i-0123456789abcdef0"));
+        assert_eq!(opts.tags, vec![("Name".to_string(), "web".to_string())]);
+
+        let unassociated: serde_yaml::Value = serde_yaml::from_str("tags: {}").unwrap();
+        assert_eq!(
+            ElasticIp::opts_from_yaml(&unassociated)
+                .unwrap()
+                .associate_with,
+            None
+        );
+    }
+
+    #[test]
+    fn test_associate_with_must_be_an_instance_id() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str("associate_with: web").unwrap();
+        assert!(ElasticIp::opts_from_yaml(&yaml).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_elastic_ip_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let elastic_ip = ElasticIp::from_config(&config);
+
+        let outcome = elastic_ip
+            .destroy_address("eipalloc-0f45b1068dd622f3c")
+            .await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+}
@@ -1 +1,7 @@
 pub mod ec2_instance;
+mod elastic_ip;
+mod key_pair;
+mod preflight;
+mod security_group;
+mod subnet;
+mod vpc;
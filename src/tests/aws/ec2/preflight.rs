@@ -0,0 +1,63 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::ec2::preflight::{
+        ImageArchitecture, InstanceTypeSupport, check_compatibility, check_offering,
+    };
+
+    fn x86_support() -> InstanceTypeSupport {
+        InstanceTypeSupport {
+            architectures: vec!["i386".to_string(), "x86_64".to_string()],
+            virtualization_types: vec!["hvm".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_matching_architecture_passes() {
+        let image = ImageArchitecture {
+            architecture: "x86_64".to_string(),
+            virtualization_type: Some("hvm".to_string()),
+        };
+        assert_eq!(
+            check_compatibility("ami-123", &image, "t3.micro", &x86_support()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_arm_image_on_x86_instance_type_names_both() {
+        let image = ImageArchitecture {
+            architecture: "arm64".to_string(),
+            virtualization_type: Some("hvm".to_string()),
+        };
+        let message = check_compatibility("ami-arm", &image, "t3.micro", &x86_support()).unwrap();
+        assert!(message.contains("ami-arm"));
+        assert!(message.contains("arm64"));
+        assert!(message.contains("t3.micro"));
+        assert!(message.contains("x86_64"));
+    }
+
+    #[test]
+    fn test_virtualization_mismatch_is_reported() {
+        let image = ImageArchitecture {
+            architecture: "x86_64".to_string(),
+            virtualization_type: Some("paravirtual".to_string()),
+        };
+        let message = check_compatibility("ami-pv", &image, "t3.micro", &x86_support()).unwrap();
+        assert!(message.contains("paravirtual"));
+    }
+
+    #[test]
+    fn test_offered_availability_zone_passes() {
+        let offered = vec!["us-west-2a".to_string(), "us-west-2b".to_string()];
+        assert_eq!(check_offering("t3.micro", "us-west-2a", &offered), None);
+    }
+
+    #[test]
+    fn test_unoffered_availability_zone_suggests_alternatives() {
+        let offered = vec!["us-west-2c".to_string(), "us-west-2b".to_string()];
+        let message = check_offering("p4d.24xlarge", "us-west-2a", &offered).unwrap();
+        assert!(message.contains("p4d.24xlarge"));
+        assert!(message.contains("us-west-2a"));
+        assert!(message.ends_with("us-west-2b, us-west-2c"));
+    }
+}
@@ -0,0 +1,148 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::ec2::{
+        ec2_instance::DestroyOutcome,
+        security_group::{SecurityGroup, SecurityGroupRule, egress_changes},
+    };
+    use aws_config::BehaviorVersion;
+
+    fn opts(yaml: &str) -> Result<crate::aws::ec2::security_group::SecurityGroupOpts, String> {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        SecurityGroup::opts_from_yaml("web", &yaml).map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn test_opts_from_yaml_defaults() {
+        let opts = opts("vpc_id: vpc-123").unwrap();
+        assert_eq!(opts.group_name, "web");
+        assert_eq!(opts.description, "Managed by letusinfra");
+        assert_eq!(opts.vpc_id.as_deref(), Some("vpc-123"));
+        assert!(opts.ingress.is_empty() && opts.egress.is_empty());
+    }
+
+    #[test]
+    fn test_opts_from_yaml_rules() {
+        let opts = opts(
+            r#"
+ingress:
+  - protocol: tcp
+    from_port: 22
+    to_port: 22
+    cidr_blocks: [10.0.0.0/8]
+egress:
+  - protocol: all
+    cidr_blocks: [0.0.0.0/0]
+"#,
+        )
+        .unwrap();
+        assert_eq!(opts.ingress[0].protocol, "tcp");
+        assert_eq!(
+            (opts.ingress[0].from_port, opts.ingress[0].to_port),
+            (22, 22)
+        );
+        assert_eq!(opts.egress[0].protocol, "-1");
+        assert_eq!((opts.egress[0].from_port, opts.egress[0].to_port), (-1, -1));
+    }
+
+    #[test]
+    fn test_opts_from_yaml_rejects_invalid_rules() {
+        let cases = [
+            (
+                "ingress: [{protocol: tcp, from_port: 80, to_port: 22, cidr_blocks: []}]",
+                "greater",
+            ),
+            (
+                "ingress: [{protocol: tcp, from_port: -1, to_port: 22, cidr_blocks: []}]",
+                "between",
+            ),
+            (
+                "ingress: [{protocol: tcp, from_port: 22, cidr_blocks: []}]",
+                "required",
+            ),
+            (
+                "ingress: [{protocol: sctp, from_port: 1, to_port: 2, cidr_blocks: []}]",
+                "sctp",
+            ),
+            (
+                "egress: [{protocol: udp, from_port: 1, to_port: 2}]",
+                "cidr_blocks",
+            ),
+            (
+                "egress: [{protocol: udp, from_port: 1, to_port: 2, cidr_blocks: [x]}]",
+                "CIDR",
+            ),
+            ("ingress: {protocol: tcp}", "must be a list"),
+        ];
+        for (yaml, expected) in cases {
+            let err = opts(yaml).unwrap_err();
+            assert!(err.contains(expected), "{}: {}", yaml, err);
+        }
+    }
+
+    #[test]
+    fn test_declared_egress_replaces_the_default_rule_offline() {
+        let allow_all = opts("egress: [{protocol: all, cidr_blocks: [0.0.0.0/0]}]")
+            .unwrap()
+            .egress
+            .remove(0);
+        let https = opts(
+            "egress: [{protocol: tcp, from_port: 443, to_port: 443, cidr_blocks: [10.0.0.0/8]}]",
+        )
+        .unwrap()
+        .egress
+        .remove(0);
+        let none: Vec<SecurityGroupRule> = Vec::new();
+
+        // Nothing declared, the default rule AWS adds stays
+        assert_eq!(egress_changes(&[]), (none.clone(), none.clone()));
+        assert_eq!(
+            egress_changes(std::slice::from_ref(&https)),
+            (vec![allow_all.clone()], vec![https.clone()])
+        );
+        // Declared next to other rules the default is kept, authorizing it again would fail
+        assert_eq!(
+            egress_changes(&[allow_all.clone(), https.clone()]),
+            (none.clone(), vec![https.clone()])
+        );
+        assert_eq!(egress_changes(&[allow_all]), (none.clone(), none));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_security_group_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let group = SecurityGroup::from_config(&config);
+
+        let outcome = group.destroy_security_group("sg-0f45b1068dd622f3c").await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_declared_egress_replaces_the_default_rule() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let group = SecurityGroup::from_config(&config);
+        let opts = opts(
+            "group_name: letusinfra-egress-test\negress:\n  - {protocol: tcp, from_port: 443, to_port: 443, cidr_blocks: [10.0.0.0/8]}\n",
+        )
+        .unwrap();
+
+        let group_id = group.create_security_group(&opts).await.unwrap();
+        let created = group.describe_security_group(&group_id).await.unwrap();
+        let egress: Vec<(Option<&str>, Option<i32>)> = created
+            .ip_permissions_egress()
+            .iter()
+            .map(|p| (p.ip_protocol(), p.from_port()))
+            .collect();
+        assert_eq!(egress, vec![(Some("tcp"), Some(443))]);
+
+        let outcome = group.destroy_security_group(&group_id).await;
+        assert_eq!(outcome, Ok(DestroyOutcome::Terminated));
+    }
+}
@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::ec2::{ec2_instance::DestroyOutcome, subnet::Subnet};
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+vpc_id: vpc-0123456789abcdef0
+cidr: 10.0.1.0/24
+availability_zone: us-east-1a
+tags:
+  Name: private
+"#,
+        )
+        .unwrap();
+        let opts = Subnet::opts_from_yaml(&yaml).unwrap();
+        assert_eq!(opts.vpc_id, "vpc-0123456789abcdef0");
+        assert_eq!(opts.cidr_block, "10.0.1.0/24");
+        assert_eq!(opts.availability_zone.as_deref(), Some("us-east-1a"));
+    }
+
+    #[test]
+    fn test_opts_from_yaml_requires_vpc_and_cidr() {
+        let no_vpc: serde_yaml::Value = serde_yaml::from_str("cidr: 10.0.1.0/24").unwrap();
+        assert!(Subnet::opts_from_yaml(&no_vpc).is_err());
+
+        let no_cidr: serde_yaml::Value = serde_yaml::from_str("vpc_id: vpc-123").unwrap();
+        assert!(Subnet::opts_from_yaml(&no_cidr).is_err());
+
+        let invalid: serde_yaml::Value =
+            serde_yaml::from_str("vpc_id: vpc-123\ncidr: 10.0.1.0/30").unwrap();
+        assert!(Subnet::opts_from_yaml(&invalid).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_subnet_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let subnet = Subnet::from_config(&config);
+
+        let outcome = subnet.destroy_subnet("subnet-0f45b1068dd622f3c").await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::ec2::{
+        ec2_instance::DestroyOutcome,
+        vpc::{Vpc, validate_cidr},
+    };
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_validate_cidr() {
+        assert!(validate_cidr("10.0.0.0/16").is_ok());
+        assert!(validate_cidr("172.31.0.0/28").is_ok());
+        assert!(validate_cidr("10.0.0.0").is_err());
+        assert!(validate_cidr("10.0.0/16").is_err());
+        assert!(validate_cidr("10.0.0.256/16").is_err());
+        assert!(
+            validate_cidr("10.0.0.0/8").is_err(),
+            "AWS rejects blocks larger than /16"
+        );
+        assert!(validate_cidr("10.0.0.0/29").is_err());
+    }
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+cidr: 10.0.0.0/16
+enable_dns_hostnames: true
+tags:
+  Name: main
+"#,
+        )
+        .unwrap();
+        let opts = Vpc::opts_from_yaml(&yaml).unwrap();
+        assert_eq!(opts.cidr_block, "10.0.0.0/16");
+    }
+
+    #[test]
+    fn test_opts_from_yaml_rejects_missing_or_invalid_cidr() {
+        let missing: serde_yaml::Value = serde_yaml::from_str("enable_dns_support: true").unwrap();
+        assert!(Vpc::opts_from_yaml(&missing).is_err());
+
+        let invalid: serde_yaml::Value = serde_yaml::from_str("cidr: 10.0.0.0/8").unwrap();
+        assert!(Vpc::opts_from_yaml(&invalid).is_err());
+
+        let bad_tags: serde_yaml::Value =
+            serde_yaml::from_str("cidr: 10.0.0.0/16\ntags: [main]").unwrap();
+        assert!(Vpc::opts_from_yaml(&bad_tags).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_vpc_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let vpc = Vpc::from_config(&config);
+
+        let outcome = vpc.destroy_vpc("vpc-0f45b1068dd622f3c").await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_vpc_create_destroy_cycle_localstack() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("cidr: 10.42.0.0/16\nenable_dns_hostnames: true\n").unwrap();
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let vpc = Vpc::from_config(&config);
+        let opts = Vpc::opts_from_yaml(&yaml).unwrap();
+
+        let created = vpc.create_vpc(&opts).await.unwrap();
+        let vpc_id = created.vpc_id().unwrap().to_string();
+        assert_eq!(created.cidr_block(), Some("10.42.0.0/16"));
+
+        assert_eq!(
+            vpc.destroy_vpc(&vpc_id).await,
+            Ok(DestroyOutcome::Terminated)
+        );
+        assert!(vpc.describe_vpc(&vpc_id).await.is_err());
+    }
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::{
+        ec2::ec2_instance::DestroyOutcome, iam::instance_profile::InstanceProfile,
+        iam::validate_iam_name,
+    };
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_validate_iam_name() {
+        for valid in [
+            "web",
+            "web-server",
+            "app_role+1",
+            "ops@example.com",
+            "a=b,c",
+        ] {
+            assert_eq!(validate_iam_name("role", valid, 64), Ok(()), "{}", valid);
+        }
+        let too_long = "r".repeat(65);
+        for invalid in ["", too_long.as_str(), "web server", "web/server", "rôle"] {
+            assert!(
+                validate_iam_name("role", invalid, 64).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("role: web-server\ntags:\n  Team: web").unwrap();
+        let opts = InstanceProfile::opts_from_yaml("web", &yaml).unwrap();
+        assert_eq!(opts.instance_profile_name, "web");
+        assert_eq!(opts.role, "web-server");
+        assert_eq!(opts.tags, vec![("Team".to_string(), "web".to_string())]);
+
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("role: web-server\ninstance_profile_name: web-profile").unwrap();
+        let opts = InstanceProfile::opts_from_yaml("web", &yaml).unwrap();
+        assert_eq!(opts.instance_profile_name, "web-profile");
+
+        let missing: serde_yaml::Value = serde_yaml::from_str("tags: {}").unwrap();
+        assert!(InstanceProfile::opts_from_yaml("web", &missing).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_instance_profile_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let profile = InstanceProfile::from_config(&config);
+
+        let outcome = profile
+            .destroy_instance_profile("letusinfra-missing-profile")
+            .await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+}
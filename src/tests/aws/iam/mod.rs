@@ -0,0 +1,2 @@
+mod instance_profile;
+mod role;
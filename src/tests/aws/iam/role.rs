@@ -0,0 +1,132 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::{
+        ec2::ec2_instance::DestroyOutcome,
+        iam::role::{IamRole, policy_json},
+    };
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_policy_json_accepts_yaml_and_json() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+Version: "2012-10-17"
+Statement:
+  - Effect: Allow
+    Principal: { Service: ec2.amazonaws.com }
+    Action: sts:AssumeRole
+"#,
+        )
+        .unwrap();
+        let converted: serde_json::Value =
+            serde_json::from_str(&policy_json(&yaml).unwrap()).unwrap();
+        assert_eq!(converted["Version"], "2012-10-17");
+        assert_eq!(
+            converted["Statement"][0]["Principal"]["Service"],
+            "ec2.amazonaws.com"
+        );
+
+        let text =
+            serde_yaml::Value::String(r#"{"Version": "2012-10-17", "Statement": []}"#.to_string());
+        let converted: serde_json::Value =
+            serde_json::from_str(&policy_json(&text).unwrap()).unwrap();
+        assert_eq!(
+            converted,
+            serde_json::json!({ "Version": "2012-10-17", "Statement": [] })
+        );
+    }
+
+    #[test]
+    fn test_policy_json_rejects_invalid_documents() {
+        for invalid in [
+            "'{not json'",
+            "Version: \"2012-10-17\"",
+            "[1, 2]",
+            "'{\"Version\": 1}'",
+        ] {
+            let yaml: serde_yaml::Value = serde_yaml::from_str(invalid).unwrap();
+            assert!(policy_json(&yaml).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+assume_role_policy:
+  Version: "2012-10-17"
+  Statement:
+    - Effect: Allow
+      Principal: { Service: ec2.amazonaws.com }
+      Action: sts:AssumeRole
+managed_policy_arns:
+  - arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore
+inline_policies:
+  read-assets:
+    Version: "2012-10-17"
+    Statement:
+      - Effect: Allow
+        Action: s3:GetObject
+        Resource: arn:aws:s3:::shop-assets/*
+tags:
+  Team: web
+"#,
+        )
+        .unwrap();
+        let opts = IamRole::opts_from_yaml("web", &yaml).unwrap();
+        assert_eq!(opts.role_name, "web");
+        assert!(opts.assume_role_policy.contains("\"sts:AssumeRole\""));
+        assert_eq!(
+            opts.managed_policy_arns,
+            vec!["arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore".to_string()]
+        );
+        assert_eq!(opts.inline_policies.len(), 1);
+        assert_eq!(opts.inline_policies[0].0, "read-assets");
+        assert!(opts.inline_policies[0].1.contains("\"s3:GetObject\""));
+        assert_eq!(opts.description, None);
+        assert_eq!(opts.tags, vec![("Team".to_string(), "web".to_string())]);
+    }
+
+    #[test]
+    fn test_opts_from_yaml_rejects_invalid_properties() {
+        let policy = "assume_role_policy: '{\"Statement\": []}'";
+        for invalid in [
+            "role_name: web".to_string(),
+            format!("{}\nrole_name: web server", policy),
+            format!("{}\nrole_name: {}", policy, "r".repeat(65)),
+            format!(
+                "{}\nmanaged_policy_arns: AmazonSSMManagedInstanceCore",
+                policy
+            ),
+            format!(
+                "{}\nmanaged_policy_arns: [AmazonSSMManagedInstanceCore]",
+                policy
+            ),
+            format!("{}\ninline_policies: [read-assets]", policy),
+            format!(
+                "{}\ninline_policies:\n  read-assets: {{ Version: x }}",
+                policy
+            ),
+        ] {
+            let yaml: serde_yaml::Value = serde_yaml::from_str(&invalid).unwrap();
+            assert!(
+                IamRole::opts_from_yaml("web", &yaml).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_role_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let role = IamRole::from_config(&config);
+
+        let outcome = role.destroy_role("letusinfra-missing-role").await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+}
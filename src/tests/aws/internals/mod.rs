@@ -1 +1,3 @@
+mod retry_tests;
+mod tags_tests;
 mod wait_and_refresh_tests;
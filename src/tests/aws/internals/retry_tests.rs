@@ -0,0 +1,249 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use aws_sdk_ec2::error::{ErrorMetadata, ProvideErrorMetadata};
+
+    use crate::aws::ec2::ec2_instance::EC2Error;
+    use crate::aws::internal::retry::{
+        ErrorClass, PROPAGATION_TIMEOUT_ENV, RetryPolicy, THROTTLE_BASE_DELAY_ENV,
+        THROTTLE_MAX_DELAY_ENV, THROTTLE_RETRIES_ENV, classify, retry_capacity, retry_throttled,
+    };
+    use serial_test::serial;
+
+    #[derive(Debug)]
+    struct FakeError(ErrorMetadata);
+
+    impl ProvideErrorMetadata for FakeError {
+        fn meta(&self) -> &ErrorMetadata {
+            &self.0
+        }
+    }
+
+    impl std::fmt::Display for FakeError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "fake error {:?}", self.0.code())
+        }
+    }
+
+    fn error(code: &str) -> FakeError {
+        FakeError(
+            ErrorMetadata::builder()
+                .code(code)
+                .message(format!("{} message", code))
+                .build(),
+        )
+    }
+
+    fn fast_policy(retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            propagation_timeout: Duration::from_millis(50),
+        }
+    }
+
+    fn cleanup_throttle_env() {
+        unsafe {
+            std::env::remove_var(THROTTLE_RETRIES_ENV);
+            std::env::remove_var(THROTTLE_BASE_DELAY_ENV);
+            std::env::remove_var(THROTTLE_MAX_DELAY_ENV);
+            std::env::remove_var(PROPAGATION_TIMEOUT_ENV);
+        }
+    }
+
+    #[test]
+    fn classify_sorts_errors_by_code() {
+        assert_eq!(
+            classify(&error("RequestLimitExceeded")),
+            ErrorClass::Throttling
+        );
+        assert_eq!(classify(&error("Throttling")), ErrorClass::Throttling);
+        assert_eq!(classify(&error("InternalError")), ErrorClass::Server);
+        assert_eq!(classify(&error("ServiceUnavailable")), ErrorClass::Server);
+        assert_eq!(
+            classify(&error("InvalidParameterValue")),
+            ErrorClass::Client
+        );
+        assert_eq!(
+            classify(&error("UnauthorizedOperation")),
+            ErrorClass::Client
+        );
+        assert_eq!(
+            classify(&FakeError(ErrorMetadata::builder().build())),
+            ErrorClass::Client
+        );
+    }
+
+    fn invalid_profile() -> FakeError {
+        FakeError(
+            ErrorMetadata::builder()
+                .code("InvalidParameterValue")
+                .message("Value (web) for parameter iamInstanceProfile.name is invalid. Invalid IAM Instance Profile name")
+                .build(),
+        )
+    }
+
+    #[test]
+    fn classify_recognizes_unpropagated_instance_profiles() {
+        assert_eq!(classify(&invalid_profile()), ErrorClass::Propagating);
+    }
+
+    #[tokio::test]
+    async fn propagation_errors_are_retried_without_using_the_retries() {
+        let calls = AtomicUsize::new(0);
+        let result = retry_throttled(&fast_policy(0), "RunInstances", || {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 | 1 => Err(invalid_profile()),
+                    _ => Ok("i-123"),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "i-123");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn propagation_errors_surface_after_the_timeout() {
+        let result: Result<(), FakeError> =
+            retry_throttled(&fast_policy(0), "RunInstances", || async {
+                Err(invalid_profile())
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Some("InvalidParameterValue"));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_and_stays_in_the_upper_half() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.backoff(0, 0.0), Duration::from_millis(250));
+        assert_eq!(policy.backoff(0, 1.0), Duration::from_millis(500));
+        assert_eq!(policy.backoff(3, 1.0), Duration::from_secs(4));
+        assert_eq!(policy.backoff(10, 1.0), Duration::from_secs(20));
+        assert_eq!(policy.backoff(40, 0.0), Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn throttling_is_retried_until_the_call_succeeds() {
+        let calls = AtomicUsize::new(0);
+        let result = retry_throttled(&fast_policy(5), "RunInstances", || {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match call {
+                    0 => Err(error("RequestLimitExceeded")),
+                    1 => Err(error("InternalError")),
+                    _ => Ok("i-123"),
+                }
+            }
+        })
+        .await;
+        assert_eq!(result.unwrap(), "i-123");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_not_retried() {
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), FakeError> =
+            retry_throttled(&fast_policy(5), "RunInstances", || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(error("InvalidParameterValue")) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().code(), Some("InvalidParameterValue"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn spent_budget_surfaces_as_throttled() {
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), FakeError> =
+            retry_throttled(&fast_policy(2), "StopInstances", || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(error("RequestLimitExceeded")) }
+            })
+            .await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            EC2Error::from(result.unwrap_err()),
+            EC2Error::Throttled("RequestLimitExceeded message".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_capacity_is_retried_within_the_budget() {
+        let calls = AtomicUsize::new(0);
+        let result = retry_capacity(
+            &fast_policy(0),
+            Duration::from_secs(5),
+            "RunInstances",
+            || {
+                let call = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    match call {
+                        0 | 1 => Err(error("InsufficientInstanceCapacity")),
+                        _ => Ok("i-123"),
+                    }
+                }
+            },
+        )
+        .await;
+        assert_eq!(result.unwrap(), "i-123");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let result: Result<(), FakeError> = retry_capacity(
+            &fast_policy(0),
+            Duration::from_millis(20),
+            "RunInstances",
+            || async { Err(error("InsufficientInstanceCapacity")) },
+        )
+        .await;
+        assert_eq!(
+            result.unwrap_err().code(),
+            Some("InsufficientInstanceCapacity")
+        );
+
+        let calls = AtomicUsize::new(0);
+        let result: Result<(), FakeError> = retry_capacity(
+            &fast_policy(0),
+            Duration::from_secs(5),
+            "RunInstances",
+            || {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async { Err(error("InvalidParameterValue")) }
+            },
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            1,
+            "client errors are not retried"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn retry_policy_from_env_reads_overrides_and_ignores_invalid_values() {
+        cleanup_throttle_env();
+        assert_eq!(RetryPolicy::from_env(), RetryPolicy::default());
+
+        unsafe {
+            std::env::set_var(THROTTLE_RETRIES_ENV, "8");
+            std::env::set_var(THROTTLE_BASE_DELAY_ENV, "100");
+            std::env::set_var(THROTTLE_MAX_DELAY_ENV, "soon");
+            std::env::set_var(PROPAGATION_TIMEOUT_ENV, "30000");
+        }
+        let policy = RetryPolicy::from_env();
+        assert_eq!(policy.retries, 8);
+        assert_eq!(policy.base_delay, Duration::from_millis(100));
+        assert_eq!(policy.max_delay, RetryPolicy::default().max_delay);
+        assert_eq!(policy.propagation_timeout, Duration::from_secs(30));
+        cleanup_throttle_env();
+    }
+}
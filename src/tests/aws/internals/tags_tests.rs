@@ -0,0 +1,94 @@
+#[cfg(test)]
+mod tests {
+    use aws_sdk_ec2::types as ec2_types;
+
+    use crate::aws::ec2::vpc::Vpc;
+    use crate::aws::internal::tags::{
+        COMPONENT_TAG, COMPONENT_TYPE_TAG, DEPLOYMENT_TAG, DeploymentTags, WORKSPACE_TAG,
+        merge_tag_specifications, merge_tags, tag_specification,
+    };
+
+    fn deployment_tags() -> DeploymentTags {
+        DeploymentTags {
+            deployment: "shop".to_string(),
+            workspace: "staging".to_string(),
+            component_type: "VPC".to_string(),
+            component_name: "main".to_string(),
+        }
+    }
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    fn tags_of(specification: &ec2_types::TagSpecification) -> Vec<(String, String)> {
+        specification
+            .tags()
+            .iter()
+            .map(|t| pair(t.key().unwrap(), t.value().unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_deployment_tags() {
+        assert_eq!(
+            deployment_tags().tags(),
+            vec![
+                pair(DEPLOYMENT_TAG, "shop"),
+                pair(WORKSPACE_TAG, "staging"),
+                pair(COMPONENT_TAG, "shop/staging/VPC.main"),
+                pair(COMPONENT_TYPE_TAG, "VPC"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_tags_win() {
+        let merged = merge_tags(
+            &[pair("Name", "main"), pair(WORKSPACE_TAG, "pinned")],
+            &deployment_tags().tags(),
+        );
+        assert_eq!(merged.len(), 5);
+        assert!(merged.contains(&pair(WORKSPACE_TAG, "pinned")));
+        assert!(!merged.contains(&pair(WORKSPACE_TAG, "staging")));
+        assert!(merged.contains(&pair("Name", "main")));
+    }
+
+    #[test]
+    fn test_merge_tag_specifications_keeps_other_resource_types() {
+        let injected = deployment_tags().tags();
+        let user = vec![
+            tag_specification(ec2_types::ResourceType::Instance, &[pair("Name", "web")]).unwrap(),
+            tag_specification(ec2_types::ResourceType::Volume, &[pair("Backup", "daily")]).unwrap(),
+        ];
+        let merged = merge_tag_specifications(user, ec2_types::ResourceType::Instance, &injected);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(tags_of(&merged[0]).len(), 5);
+        assert_eq!(tags_of(&merged[0])[0], pair("Name", "web"));
+        assert_eq!(tags_of(&merged[1]), vec![pair("Backup", "daily")]);
+
+        let added = merge_tag_specifications(vec![], ec2_types::ResourceType::Instance, &injected);
+        assert_eq!(added.len(), 1);
+        assert_eq!(
+            added[0].resource_type(),
+            Some(&ec2_types::ResourceType::Instance)
+        );
+        assert_eq!(tags_of(&added[0]), injected);
+    }
+
+    #[test]
+    fn test_component_opts_merge_injected_tags() {
+        let yaml: serde_yaml::Value =
+            serde_yaml::from_str("cidr: 10.0.0.0/16\ntags:\n  letusinfra:deployment: legacy\n")
+                .unwrap();
+        let opts = Vpc::opts_from_yaml(&yaml)
+            .unwrap()
+            .with_tags(&deployment_tags().tags());
+        assert!(opts.tags.contains(&pair(DEPLOYMENT_TAG, "legacy")));
+        assert!(
+            opts.tags
+                .contains(&pair(COMPONENT_TAG, "shop/staging/VPC.main"))
+        );
+        assert_eq!(opts.tags.len(), 4);
+    }
+}
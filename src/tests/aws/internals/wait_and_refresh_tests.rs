@@ -9,7 +9,12 @@ mod tests {
     use aws_types::region::Region;
 
     use crate::aws::AWSClient;
-    use crate::aws::internal::wait_and_refresh::{RefreshFunction, StateChangeConfig, WaitError};
+    use crate::aws::internal::wait_and_refresh::{
+        RefreshFunction, StateChangeConfig, StateMatch, WAIT_MAX_DELAY_ENV, WAIT_MIN_DELAY_ENV,
+        WAIT_NOT_FOUND_CHECKS_ENV, WAIT_REFRESH_TIMEOUT_ENV, WAIT_TIMEOUT_ENV, WaitDefaults,
+        WaitError, is_wait_variable, match_states, read_with_grace,
+    };
+    use crate::models::InfraConfig;
 
     fn test_client() -> AWSClient {
         let sdk_config = aws_types::SdkConfig::builder()
@@ -37,13 +42,14 @@ mod tests {
                     vec![String::from("running")]
                 };
                 Ok(Some((
-                    Box::new(String::from("resource")) as Box<dyn Any>,
+                    Box::new(String::from("resource")) as Box<dyn Any + Send>,
                     state,
                 )))
             })
         });
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             vec![String::from("running")],
             vec![String::from("pending")],
             refresh_fn,
@@ -68,6 +74,125 @@ mod tests {
         assert!(call_counter.load(Ordering::SeqCst) >= 3);
     }
 
+    fn states(states: &[&str]) -> Vec<String> {
+        states.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn match_states_needs_every_state_in_the_targets() {
+        let target = states(&["running", "stopped"]);
+        let pending = states(&["pending"]);
+        let matched = |current: &[&str]| match_states(&states(current), &target, &pending);
+
+        assert_eq!(matched(&["running"]), StateMatch::Target);
+        assert_eq!(matched(&["stopped"]), StateMatch::Target);
+        assert_eq!(
+            matched(&["running", "stopped", "running"]),
+            StateMatch::Target
+        );
+        assert_eq!(matched(&["running", "pending"]), StateMatch::Pending);
+        assert_eq!(matched(&["pending", "pending"]), StateMatch::Pending);
+        assert_eq!(matched(&["running", "terminated"]), StateMatch::Unexpected);
+        assert_eq!(matched(&[]), StateMatch::Pending);
+        // Without pending states anything short of the targets keeps waiting
+        assert_eq!(
+            match_states(&states(&["shutting-down"]), &target, &[]),
+            StateMatch::Pending
+        );
+    }
+
+    /// Refresh function reporting one state per instance, the n-th call gets the n-th states
+    fn instance_states(calls: Vec<Vec<&'static str>>) -> RefreshFunction {
+        let counter = Arc::new(AtomicUsize::new(0));
+        Box::new(move |_client, _resource_id| {
+            let call = counter.fetch_add(1, Ordering::SeqCst);
+            let current = states(&calls[call.min(calls.len() - 1)]);
+            Box::pin(async move {
+                Ok(Some((
+                    Box::new(String::from("resource")) as Box<dyn Any + Send>,
+                    current,
+                )))
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn wait_until_state_waits_for_every_instance() {
+        let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
+            states(&["running"]),
+            states(&["pending"]),
+            instance_states(vec![
+                vec!["pending", "pending", "pending"],
+                vec!["running", "pending", "running"],
+                vec!["running", "running", "running"],
+            ]),
+            Some(Duration::from_millis(0)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(1)),
+            Some(Duration::from_millis(1)),
+            Some(5),
+        );
+        let resource = config
+            .wait_until_state(test_client(), "i-1,i-2,i-3".to_string())
+            .await
+            .expect("every instance should be running");
+        assert!(resource.is_some());
+
+        let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
+            states(&["running"]),
+            states(&["pending"]),
+            instance_states(vec![
+                vec!["running", "pending"],
+                vec!["running", "terminated"],
+            ]),
+            Some(Duration::from_millis(0)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(1)),
+            Some(Duration::from_millis(1)),
+            Some(5),
+        );
+        let err = config
+            .wait_until_state(test_client(), "i-1,i-2".to_string())
+            .await
+            .expect_err("a terminated instance should end the wait");
+        match err {
+            WaitError::UnexpectedState { current_state, .. } => {
+                assert_eq!(current_state, "running\nterminated")
+            }
+            other => panic!("expected UnexpectedState error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_until_state_accepts_any_of_several_targets() {
+        let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
+            states(&["running", "stopped"]),
+            states(&["pending", "stopping"]),
+            instance_states(vec![
+                vec!["pending", "stopping"],
+                vec!["running", "stopped"],
+            ]),
+            Some(Duration::from_millis(0)),
+            Some(Duration::from_millis(200)),
+            Some(Duration::from_millis(1)),
+            Some(Duration::from_millis(1)),
+            Some(5),
+        );
+        // Spawned to make sure the wait can run on another task
+        let resource = tokio::spawn(async move {
+            config
+                .wait_until_state(test_client(), "i-1,i-2".to_string())
+                .await
+        })
+        .await
+        .unwrap()
+        .expect("running and stopped are both targets");
+        assert!(resource.is_some());
+    }
+
     #[tokio::test]
     async fn wait_until_state_returns_not_found_after_threshold() {
         let client = test_client();
@@ -75,6 +200,7 @@ mod tests {
         let refresh_fn: RefreshFunction = Box::new(|_, _| Box::pin(async { Ok(None) }));
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             vec![String::from("running")],
             vec![String::from("pending")],
             refresh_fn,
@@ -103,13 +229,14 @@ mod tests {
         let refresh_fn: RefreshFunction = Box::new(|_, _| {
             Box::pin(async {
                 Ok(Some((
-                    Box::new(String::from("resource")) as Box<dyn Any>,
+                    Box::new(String::from("resource")) as Box<dyn Any + Send>,
                     vec![String::from("failed")],
                 )))
             })
         });
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             vec![String::from("running")],
             vec![String::from("pending")],
             refresh_fn,
@@ -147,6 +274,7 @@ mod tests {
         let refresh_fn: RefreshFunction = Box::new(|_, _| Box::pin(async { Ok(None) }));
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             vec![String::from("running")],
             vec![String::from("pending")],
             refresh_fn,
@@ -175,6 +303,7 @@ mod tests {
         let refresh_fn: RefreshFunction = Box::new(|_, _| Box::pin(async { Ok(None) }));
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             Vec::<String>::new(),
             Vec::<String>::new(),
             refresh_fn,
@@ -201,6 +330,7 @@ mod tests {
             Box::new(|_, _| Box::pin(async { Err("boom".to_string()) }));
 
         let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
             vec![String::from("running")],
             vec![String::from("pending")],
             refresh_fn,
@@ -221,4 +351,195 @@ mod tests {
             other => panic!("expected RefreshError, got {:?}", other),
         }
     }
+
+    #[tokio::test]
+    async fn wait_until_state_fails_when_one_check_leaves_the_pending_states() {
+        let client = test_client();
+
+        let refresh_fn: RefreshFunction = Box::new(|_, _| {
+            Box::pin(async {
+                Ok(Some((
+                    Box::new(String::from("status")) as Box<dyn Any + Send>,
+                    vec![String::from("system:ok"), String::from("instance:impaired")],
+                )))
+            })
+        });
+
+        let config = StateChangeConfig::new(
+            &WaitDefaults::default(),
+            vec![String::from("system:ok"), String::from("instance:ok")],
+            vec![
+                String::from("system:initializing"),
+                String::from("instance:initializing"),
+            ],
+            refresh_fn,
+            Some(Duration::from_millis(0)),
+            Some(Duration::from_millis(100)),
+            Some(Duration::from_millis(1)),
+            Some(Duration::from_millis(1)),
+            Some(5),
+        );
+
+        let err = config
+            .wait_until_state(client, "i-1234".to_string())
+            .await
+            .expect_err("an impaired check should end the wait");
+
+        match err {
+            WaitError::UnexpectedState { current_state, .. } => {
+                assert_eq!(current_state, "system:ok\ninstance:impaired")
+            }
+            other => panic!("expected UnexpectedState, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn wait_defaults_keep_the_builtin_values_without_settings() {
+        assert_eq!(
+            WaitDefaults::default().overridden_by(|_| None),
+            WaitDefaults::default()
+        );
+        assert_eq!(
+            WaitDefaults::default().refresh_timeout,
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn wait_defaults_read_overrides_and_ignore_invalid_values() {
+        let settings = std::collections::HashMap::from([
+            (WAIT_MIN_DELAY_ENV, "10"),
+            (WAIT_MAX_DELAY_ENV, "50"),
+            (WAIT_REFRESH_TIMEOUT_ENV, "2000"),
+            (WAIT_NOT_FOUND_CHECKS_ENV, "3"),
+            (WAIT_TIMEOUT_ENV, "not-a-number"),
+        ]);
+
+        let defaults = WaitDefaults::default()
+            .overridden_by(|key| settings.get(key).map(|value| value.to_string()));
+        assert_eq!(defaults.min_delay, Duration::from_millis(10));
+        assert_eq!(defaults.max_delay, Duration::from_millis(50));
+        assert_eq!(defaults.refresh_timeout, Duration::from_millis(2000));
+        assert_eq!(defaults.not_found_checks, 3);
+        assert_eq!(defaults.timeout, WaitDefaults::default().timeout);
+    }
+
+    #[test]
+    fn wait_defaults_of_a_config_come_from_its_variables() {
+        let config = InfraConfig::from_yaml(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: shop\ncomponents: []\nvariables:\n  - name: wait_min_delay_ms\n    type: int\n    default: 10\n  - name: not_found_grace_ms\n    default: \"250\"\n",
+        )
+        .unwrap();
+
+        let defaults = WaitDefaults::for_config(&config);
+        assert_eq!(defaults.min_delay, Duration::from_millis(10));
+        assert_eq!(defaults.not_found_grace, Duration::from_millis(250));
+        assert!(is_wait_variable("wait_min_delay_ms"));
+        assert!(!is_wait_variable("min_delay_ms"));
+    }
+
+    #[test]
+    fn state_change_config_prefers_explicit_values_over_the_defaults() {
+        let defaults = WaitDefaults {
+            min_delay: Duration::from_millis(10),
+            not_found_checks: 3,
+            ..WaitDefaults::default()
+        };
+
+        let refresh_fn: RefreshFunction = Box::new(|_, _| Box::pin(async { Ok(None) }));
+        let config = StateChangeConfig::new(
+            &defaults,
+            vec![String::from("running")],
+            vec![String::from("pending")],
+            refresh_fn,
+            None,
+            None,
+            None,
+            None,
+            Some(7),
+        );
+
+        assert_eq!(config.min_delay, Duration::from_millis(10));
+        assert_eq!(config.not_found_checks, 7);
+    }
+
+    /// Polls right away, with `grace` as the window for resources that were just created
+    fn fast_wait(grace: Duration) -> WaitDefaults {
+        WaitDefaults {
+            min_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            not_found_grace: grace,
+            ..WaitDefaults::default()
+        }
+    }
+
+    /// Refresh function that reports the resource missing for the first `missing_calls` calls
+    fn not_found_then_found(missing_calls: usize, counter: Arc<AtomicUsize>) -> RefreshFunction {
+        Box::new(move |_client, _resource_id| {
+            let counter = Arc::clone(&counter);
+            Box::pin(async move {
+                if counter.fetch_add(1, Ordering::SeqCst) < missing_calls {
+                    return Ok(None);
+                }
+                Ok(Some((
+                    Box::new(String::from("i-123")) as Box<dyn Any + Send>,
+                    vec![String::from("pending")],
+                )))
+            })
+        })
+    }
+
+    #[tokio::test]
+    async fn read_with_grace_retries_not_found_for_fresh_resources() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let resource = read_with_grace(
+            not_found_then_found(2, Arc::clone(&counter)),
+            test_client(),
+            "i-123".to_string(),
+            Some(std::time::SystemTime::now()),
+            &fast_wait(Duration::from_secs(60)),
+        )
+        .await
+        .expect("fresh resource should materialize");
+
+        assert_eq!(*resource.unwrap().downcast::<String>().unwrap(), "i-123");
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn read_with_grace_treats_not_found_as_gone_outside_the_window() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let resource = read_with_grace(
+            not_found_then_found(2, Arc::clone(&counter)),
+            test_client(),
+            "i-123".to_string(),
+            Some(std::time::SystemTime::now() - Duration::from_secs(120)),
+            &fast_wait(Duration::from_secs(60)),
+        )
+        .await
+        .unwrap();
+
+        assert!(resource.is_none());
+        assert_eq!(counter.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn read_with_grace_gives_up_when_the_window_closes() {
+        let counter = Arc::new(AtomicUsize::new(0));
+
+        let resource = read_with_grace(
+            not_found_then_found(usize::MAX, Arc::clone(&counter)),
+            test_client(),
+            "i-123".to_string(),
+            Some(std::time::SystemTime::now()),
+            &fast_wait(Duration::from_millis(50)),
+        )
+        .await
+        .unwrap();
+
+        assert!(resource.is_none());
+        assert!(counter.load(Ordering::SeqCst) > 1);
+    }
 }
@@ -1,2 +1,4 @@
 pub mod ec2;
+mod iam;
 mod internals;
+mod s3;
@@ -0,0 +1,85 @@
+#[cfg(test)]
+mod tests {
+    use crate::aws::{
+        ec2::ec2_instance::DestroyOutcome,
+        s3::bucket::{S3Bucket, bucket_arn, validate_bucket_name},
+    };
+    use aws_config::BehaviorVersion;
+
+    #[test]
+    fn test_validate_bucket_name() {
+        for valid in ["abc", "shop-assets", "logs.example.com", "a1-b2.c3"] {
+            assert_eq!(validate_bucket_name(valid), Ok(()), "{}", valid);
+        }
+        let too_long = "a".repeat(64);
+        for invalid in [
+            "ab",
+            too_long.as_str(),
+            "Shop-Assets",
+            "shop_assets",
+            "-shop",
+            "shop.",
+            "shop..assets",
+            "192.168.5.4",
+            "xn--shop",
+            "shop-s3alias",
+            "shop--x-s3",
+        ] {
+            assert!(validate_bucket_name(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_bucket_arn_follows_the_partition() {
+        assert_eq!(bucket_arn("shop", "us-west-2"), "arn:aws:s3:::shop");
+        assert_eq!(bucket_arn("shop", "cn-north-1"), "arn:aws-cn:s3:::shop");
+        assert_eq!(
+            bucket_arn("shop", "us-gov-west-1"),
+            "arn:aws-us-gov:s3:::shop"
+        );
+    }
+
+    #[test]
+    fn test_opts_from_yaml() {
+        let yaml: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+bucket: shop-assets
+versioning: true
+public_access_block:
+  block_public_policy: false
+tags:
+  Team: web
+"#,
+        )
+        .unwrap();
+        let opts = S3Bucket::opts_from_yaml(&yaml).unwrap();
+        assert_eq!(opts.bucket, "shop-assets");
+        assert!(opts.versioning);
+        assert!(opts.public_access_block.block_public_acls);
+        assert!(!opts.public_access_block.block_public_policy);
+        assert_eq!(opts.tags, vec![("Team".to_string(), "web".to_string())]);
+
+        let defaults: serde_yaml::Value = serde_yaml::from_str("bucket: shop-assets").unwrap();
+        let opts = S3Bucket::opts_from_yaml(&defaults).unwrap();
+        assert!(!opts.versioning);
+        assert!(opts.public_access_block.restrict_public_buckets);
+
+        let missing: serde_yaml::Value = serde_yaml::from_str("versioning: true").unwrap();
+        assert!(S3Bucket::opts_from_yaml(&missing).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_bucket_destroy_not_found_is_success() {
+        let config = aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await;
+        let bucket = S3Bucket::from_config(&config);
+
+        let outcome = bucket
+            .destroy_bucket("letusinfra-missing-bucket", true)
+            .await;
+        assert_eq!(outcome, Ok(DestroyOutcome::AlreadyGone));
+    }
+}
@@ -1 +1,2 @@
 pub mod aws;
+mod state;
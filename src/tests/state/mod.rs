@@ -0,0 +1 @@
+mod s3_backend;
@@ -0,0 +1,254 @@
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+
+    use crate::{
+        models::state::{Datatype, Output, OutputType, State},
+        state::{
+            STATE_VERSION, StateError,
+            backend::{LOCK_ID_ATTRIBUTE, LockInfo, LockTable, S3Backend, StateBackend},
+        },
+    };
+    use aws_config::BehaviorVersion;
+    use aws_sdk_dynamodb::{
+        error::ProvideErrorMetadata,
+        types::{AttributeDefinition, BillingMode, KeySchemaElement, KeyType, ScalarAttributeType},
+    };
+    use serde_json::json;
+
+    const BUCKET: &str = "letusinfra-state-backend";
+    const TABLE: &str = "letusinfra-state-locks";
+
+    async fn localstack() -> aws_types::SdkConfig {
+        aws_config::defaults(BehaviorVersion::latest())
+            .profile_name("localstack")
+            .load()
+            .await
+    }
+
+    /// Bucket and lock table the backend is pointed at, left in place for the next run
+    async fn create_bucket_and_table(config: &aws_types::SdkConfig) {
+        let s3 = aws_sdk_s3::Client::new(config);
+        if let Err(err) = s3.create_bucket().bucket(BUCKET).send().await {
+            assert_eq!(err.code(), Some("BucketAlreadyOwnedByYou"), "{:?}", err);
+        }
+        let dynamodb = aws_sdk_dynamodb::Client::new(config);
+        let created = dynamodb
+            .create_table()
+            .table_name(TABLE)
+            .attribute_definitions(
+                AttributeDefinition::builder()
+                    .attribute_name(LOCK_ID_ATTRIBUTE)
+                    .attribute_type(ScalarAttributeType::S)
+                    .build()
+                    .unwrap(),
+            )
+            .key_schema(
+                KeySchemaElement::builder()
+                    .attribute_name(LOCK_ID_ATTRIBUTE)
+                    .key_type(KeyType::Hash)
+                    .build()
+                    .unwrap(),
+            )
+            .billing_mode(BillingMode::PayPerRequest)
+            .send()
+            .await;
+        if let Err(err) = created {
+            assert_eq!(err.code(), Some("ResourceInUseException"), "{:?}", err);
+        }
+    }
+
+    fn state(id: &str) -> State {
+        State {
+            version: STATE_VERSION.to_string(),
+            serial: 0,
+            lineage: String::new(),
+            modulepack: "shop".to_string(),
+            outputs: Output {
+                value: json!({ "VPC.main": { "id": id } }),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![],
+            launches: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_s3_backend_saves_under_the_lock() {
+        let config = localstack().await;
+        create_bucket_and_table(&config).await;
+        let key = format!("tests/{}/state.json", std::process::id());
+        let mut first = S3Backend::from_config(&config, BUCKET, &key, Some(TABLE));
+        let mut second = S3Backend::from_config(&config, BUCKET, &key, Some(TABLE));
+        first.check().await.unwrap();
+
+        // Nothing stored before the first apply
+        assert_eq!(first.load().await.unwrap(), None);
+        assert!(matches!(
+            first.save(state("vpc-1")).await,
+            Err(StateError::Locked { .. })
+        ));
+
+        first.lock("apply").await.unwrap();
+        let err = second.lock("destroy").await.unwrap_err();
+        assert!(
+            matches!(&err, StateError::Locked { holder, .. } if holder.contains("apply")),
+            "{}",
+            err
+        );
+        let saved = first.save(state("vpc-1")).await.unwrap();
+        assert_eq!(saved.serial, 1);
+        assert_eq!(first.load().await.unwrap(), Some(saved.clone()));
+        first.unlock().await.unwrap();
+
+        second.lock("destroy").await.unwrap();
+        let next = second.save(state("vpc-2")).await.unwrap();
+        assert_eq!(next.serial, 2);
+        assert_eq!(next.lineage, saved.lineage);
+        second.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore = "integration test, needs localstack: cargo test -- --ignored"]
+    async fn test_s3_backend_missing_bucket_fails_the_check() {
+        let config = localstack().await;
+        let backend =
+            S3Backend::from_config(&config, "letusinfra-missing-bucket", "state.json", None);
+        let err = backend.check().await.unwrap_err();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+    }
+
+    /// Lock table kept in memory, clones share the items like two runs share a DynamoDB table
+    #[derive(Debug, Clone, Default)]
+    struct FakeLocks {
+        items: Arc<Mutex<HashMap<String, LockInfo>>>,
+        unavailable: bool,
+    }
+
+    impl LockTable for FakeLocks {
+        async fn check(&self) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn acquire(&self, lock_id: &str, info: &LockInfo) -> Result<bool, String> {
+            if self.unavailable {
+                return Err("service unavailable".to_string());
+            }
+            let mut items = self.items.lock().unwrap();
+            if items.contains_key(lock_id) {
+                return Ok(false);
+            }
+            items.insert(lock_id.to_string(), info.clone());
+            Ok(true)
+        }
+
+        async fn holder(&self, lock_id: &str) -> Option<LockInfo> {
+            self.items.lock().unwrap().get(lock_id).cloned()
+        }
+
+        async fn release(&self, lock_id: &str, owner: &str) -> Result<bool, String> {
+            let mut items = self.items.lock().unwrap();
+            if items.get(lock_id).map(|info| info.id.as_str()) != Some(owner) {
+                return Ok(false);
+            }
+            items.remove(lock_id);
+            Ok(true)
+        }
+    }
+
+    /// Backend whose S3 client is never called, only the lock table is used
+    fn offline(locks: Option<FakeLocks>) -> S3Backend<FakeLocks> {
+        let config = aws_sdk_s3::Config::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(aws_sdk_s3::config::Region::new("us-east-1"))
+            .build();
+        S3Backend::with_locks(
+            aws_sdk_s3::Client::from_conf(config),
+            BUCKET,
+            "state.json",
+            locks,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_s3_backend_lock_is_held_by_one_run() {
+        let locks = FakeLocks::default();
+        let mut first = offline(Some(locks.clone()));
+        let mut second = offline(Some(locks.clone()));
+
+        first.lock("apply").await.unwrap();
+        first.check_lock().await.unwrap();
+        let err = second.lock("destroy").await.unwrap_err();
+        assert!(
+            matches!(&err, StateError::Locked { holder, .. } if holder.contains("apply")),
+            "{}",
+            err
+        );
+        // Without the lock a run may not write
+        assert!(matches!(
+            second.check_lock().await,
+            Err(StateError::Locked { .. })
+        ));
+
+        first.unlock().await.unwrap();
+        assert!(locks.items.lock().unwrap().is_empty());
+        second.lock("destroy").await.unwrap();
+        second.check_lock().await.unwrap();
+        second.unlock().await.unwrap();
+        // Nothing held, nothing to release
+        second.unlock().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_s3_backend_leaves_a_lock_taken_over_by_another_run() {
+        let locks = FakeLocks::default();
+        let mut backend = offline(Some(locks.clone()));
+        backend.lock("apply").await.unwrap();
+
+        // Removed by hand and taken by another run meanwhile
+        let other = LockInfo {
+            id: "other".to_string(),
+            operation: "destroy".to_string(),
+            who: "ci@runner".to_string(),
+            created: 0,
+        };
+        locks
+            .items
+            .lock()
+            .unwrap()
+            .insert(format!("{}/state.json", BUCKET), other.clone());
+        let err = backend.check_lock().await.unwrap_err();
+        assert!(err.to_string().contains("ci@runner"), "{}", err);
+
+        let err = backend.unlock().await.unwrap_err();
+        assert!(err.to_string().contains("no longer held"), "{}", err);
+        let items = locks.items.lock().unwrap();
+        assert_eq!(items.values().collect::<Vec<_>>(), vec![&other]);
+    }
+
+    #[tokio::test]
+    async fn test_s3_backend_lock_without_a_usable_table() {
+        let unavailable = FakeLocks {
+            unavailable: true,
+            ..Default::default()
+        };
+        let mut backend = offline(Some(unavailable));
+        let err = backend.lock("apply").await.unwrap_err();
+        assert!(matches!(&err, StateError::Backend { .. }), "{}", err);
+        assert!(err.to_string().contains("service unavailable"), "{}", err);
+
+        // Without a lock table the lock is only remembered by the run
+        let mut backend = offline(None);
+        backend.lock("apply").await.unwrap();
+        backend.check_lock().await.unwrap();
+        backend.unlock().await.unwrap();
+    }
+}
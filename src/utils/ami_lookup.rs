@@ -0,0 +1,205 @@
+/// Module for resolving the `ami_lookup:` blocks of EC2 instances to AMI ids
+use crate::{
+    aws::{
+        ec2::ec2_instance::{AmiLookup, EC2Instance},
+        sdk_config::load_sdk_config,
+    },
+    models::{Component, InfraConfig, state::State},
+};
+
+/**
+ * AMI the last apply resolved the lookup of `component` to, as recorded in its state. Ignored
+ * once the filters differ from the recorded ones, states written before the filters were recorded
+ * are trusted as is.
+ */
+fn recorded_image(
+    previous: Option<&State>,
+    component: &Component,
+    lookup: &AmiLookup,
+) -> Option<String> {
+    let recorded = previous?
+        .components
+        .iter()
+        .find(|c| c.component_type == component.component_type && c.name == component.name)?
+        .instances
+        .first()?;
+    let filters = recorded.get("ami_lookup").and_then(|f| f.as_str());
+    if filters.is_some_and(|filters| filters != lookup.filters()) {
+        return None;
+    }
+    recorded
+        .get("image_id")
+        .and_then(|id| id.as_str())
+        .map(|id| id.to_string())
+}
+
+/**
+ * Set `ami` of every EC2 instance that has an `ami_lookup` instead. The AMI recorded by the last
+ * apply is kept so a newly published AMI does not replace the instance, unless `most_recent` asks
+ * for it to be looked up again. Lookups only call AWS when `online`, offline an instance without
+ * a recorded AMI is left unresolved and plans it as known after apply.
+ */
+pub async fn resolve_ami_lookups(
+    config: &mut InfraConfig,
+    previous: Option<&State>,
+    online: bool,
+) -> Result<(), String> {
+    let needs_client = online
+        && config
+            .components
+            .iter()
+            .any(|c| c.get_property("ami_lookup").is_some());
+    let client = if needs_client {
+        let sdk_config = load_sdk_config(&config.region)
+            .await
+            .map_err(|err| err.to_string())?;
+        Some(EC2Instance::from_config(&sdk_config))
+    } else {
+        None
+    };
+    for component in config.components.iter_mut() {
+        let has_ami =
+            component.get_property("ami").is_some() || component.get_property("image_id").is_some();
+        if component.component_type != "EC2Instance" || has_ami {
+            continue;
+        }
+        let lookup = AmiLookup::from_yaml(&component.properties)
+            .map_err(|err| format!("{}: {}", component.key(), err))?;
+        let Some(lookup) = lookup else {
+            continue;
+        };
+        let image_id = match (recorded_image(previous, component, &lookup), &client) {
+            (Some(image_id), None) => image_id,
+            (Some(image_id), Some(_)) if !lookup.most_recent => image_id,
+            (_, Some(client)) => {
+                let image_id = client
+                    .lookup_image(&lookup)
+                    .await
+                    .map_err(|err| format!("{}: {}", component.key(), err))?;
+                tracing::info!("{}: ami_lookup resolved to {}", component.key(), image_id);
+                image_id
+            }
+            (None, None) => continue,
+        };
+        if let Some(properties) = component.properties.as_mapping_mut() {
+            properties.insert("ami".into(), image_id.into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, ComponentState, Datatype, Output, OutputType};
+
+    const LOOKUP: &str = "owners=amazon, name=al2023-ami-*";
+
+    fn lookup_config() -> InfraConfig {
+        InfraConfig::from_yaml(
+            r#"version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami_lookup:
+        owners: [amazon]
+        name_pattern: al2023-ami-*
+        most_recent: true
+  - type: EC2Instance
+    name: pinned
+    properties:
+      instance_type: t2.micro
+      ami: ami-pinned
+"#,
+        )
+        .unwrap()
+    }
+
+    fn state(instance: serde_json::Value) -> State {
+        State {
+            version: "1".to_string(),
+            serial: 1,
+            lineage: String::new(),
+            modulepack: "sample".to_string(),
+            outputs: Output {
+                value: serde_json::json!({}),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![ComponentState {
+                mode: ComponentMode::Managed,
+                name: "web".to_string(),
+                component_type: "EC2Instance".to_string(),
+                provider: "aws".to_string(),
+                instances: vec![instance],
+                created_at: None,
+                properties_hash: None,
+                replacement_hash: None,
+                retained: false,
+            }],
+            launches: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_recorded_ami_is_reused_offline() {
+        let previous =
+            state(serde_json::json!({ "id": "i-1", "image_id": "ami-old", "ami_lookup": LOOKUP }));
+        let mut config = lookup_config();
+        resolve_ami_lookups(&mut config, Some(&previous), false)
+            .await
+            .unwrap();
+        assert_eq!(
+            config.components[0]
+                .get_property_as_string("ami")
+                .as_deref(),
+            Some("ami-old")
+        );
+        assert_eq!(
+            config.components[1]
+                .get_property_as_string("ami")
+                .as_deref(),
+            Some("ami-pinned")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unrecorded_or_changed_lookup_stays_unresolved_offline() {
+        let mut config = lookup_config();
+        resolve_ami_lookups(&mut config, None, false).await.unwrap();
+        assert!(config.components[0].get_property("ami").is_none());
+
+        let previous = state(serde_json::json!({
+            "id": "i-1",
+            "image_id": "ami-old",
+            "ami_lookup": "owners=amazon, name=amzn2-ami-*",
+        }));
+        let mut config = lookup_config();
+        resolve_ami_lookups(&mut config, Some(&previous), false)
+            .await
+            .unwrap();
+        assert!(config.components[0].get_property("ami").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_lookup_is_rejected() {
+        let mut config = lookup_config();
+        config.components[0].properties["ami_lookup"] =
+            serde_yaml::from_str("{owners: [], name_pattern: al2023-*}").unwrap();
+        let err = resolve_ami_lookups(&mut config, None, false)
+            .await
+            .unwrap_err();
+        assert!(err.starts_with("EC2Instance.web: "), "{}", err);
+        assert!(err.contains("owners"), "{}", err);
+    }
+}
@@ -0,0 +1,76 @@
+/// Standard base64 with padding, as EC2 expects user data, without pulling in a dependency
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+pub fn encode(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                output.push(ALPHABET[sextet as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// None when `input` is not padded standard base64
+pub fn decode(input: &str) -> Option<Vec<u8>> {
+    let input = input.as_bytes();
+    if input.is_empty() || !input.chunks_exact(4).remainder().is_empty() {
+        return None;
+    }
+    let mut output = Vec::with_capacity(input.len() / 4 * 3);
+    for (position, chunk) in input.chunks(4).enumerate() {
+        let last = position == input.len() / 4 - 1;
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut group: u32 = 0;
+        for &c in &chunk[..4 - padding] {
+            let sextet = ALPHABET.iter().position(|&a| a == c)? as u32;
+            group = (group << 6) | sextet;
+        }
+        group <<= 6 * padding as u32;
+        let bytes = group.to_be_bytes();
+        output.extend_from_slice(&bytes[1..4 - padding]);
+    }
+    Some(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("#!/bin/bash\necho hi", "IyEvYmluL2Jhc2gKZWNobyBoaQ=="),
+        ] {
+            assert_eq!(encode(plain.as_bytes()), encoded);
+            if !plain.is_empty() {
+                assert_eq!(decode(encoded).as_deref(), Some(plain.as_bytes()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_input_is_rejected() {
+        for invalid in ["", "Zg", "Zg=a", "Z===", "Zg==Zg==", "#!/b"] {
+            assert_eq!(decode(invalid), None, "{}", invalid);
+        }
+    }
+}
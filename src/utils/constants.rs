@@ -1,4 +1,13 @@
 pub const TEMPLATES_DIR: &str = "terraform/templates";
+pub const WORKSPACE_ENV: &str = "LETUS_WORKSPACE";
+pub const BACKEND_CONFIG_ENV: &str = "LETUS_BACKEND_CONFIG"; // File with a `backend:` block
+pub const DEFAULT_WORKSPACE: &str = "default";
+pub const WIDTH_ENV: &str = "LETUS_WIDTH";
+pub const STATE_DIR: &str = ".letusinfra"; // Holds one `<workspace>/state.json` per workspace
+pub const STATE_FILE_NAME: &str = "state.json";
+pub const SENSITIVE_VALUE: &str = "(sensitive)"; // Shown in place of sensitive property values
+pub const INTERRUPTED_EXIT_CODE: i32 = 130; // Exit code of an apply stopped by Ctrl-C
+pub const DRIFT_EXIT_CODE: i32 = 2; // Exit code of a drift check that found drift
 pub enum SupportKind {
     Infra,
     App,
@@ -0,0 +1,493 @@
+use crate::{
+    aws::{ec2::ec2_instance::EC2Error, error::AwsError, internal::wait_and_refresh::WaitError},
+    commands::validate::ValidationError,
+    models::{ConfigError, PlanError},
+    utils::{interpolation::InterpolationError, lint::LintWarning, variables::VariableError},
+};
+
+/// Entry of the diagnostics catalogue, codes are stable and must never be reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiagnosticCode {
+    pub code: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub remediation: &'static str,
+}
+
+/**
+ * Every diagnostic the CLI can produce.
+ * LETUS1xxx: reading, parsing and validating config
+ * LETUS2xxx: references and dependencies between components
+ * AWS3xxx:   errors coming back from AWS
+ */
+pub const CATALOGUE: &[DiagnosticCode] = &[
+    DiagnosticCode {
+        code: "LETUS1001",
+        name: "FileRead",
+        description: "The config file could not be read.",
+        remediation: "Check the path passed with -f and the file permissions.",
+    },
+    DiagnosticCode {
+        code: "LETUS1002",
+        name: "ConfigParse",
+        description: "The config is not valid YAML or does not match the InfraConfig structure.",
+        remediation: "Fix the syntax error reported at the given line and column.",
+    },
+    DiagnosticCode {
+        code: "LETUS1003",
+        name: "InvalidConfig",
+        description: "The config parsed but failed structural validation (empty metadata name, no components, ...).",
+        remediation: "Fill in the field named in the message.",
+    },
+    DiagnosticCode {
+        code: "LETUS1004",
+        name: "MissingProperty",
+        description: "A component is missing a property its type requires.",
+        remediation: "Add the property under the component's `properties` block.",
+    },
+    DiagnosticCode {
+        code: "LETUS1005",
+        name: "InvalidPropertyType",
+        description: "A component property has a value of the wrong type.",
+        remediation: "Change the value to the expected type, e.g. a number instead of a string.",
+    },
+    DiagnosticCode {
+        code: "LETUS1006",
+        name: "InvalidComponent",
+        description: "A component cannot be planned as declared.",
+        remediation: "Check the component type and properties against the documentation.",
+    },
+    DiagnosticCode {
+        code: "LETUS1007",
+        name: "UnknownReference",
+        description: "A `${...}` reference does not name anything that can be interpolated.",
+        remediation: "Use one of the built-in references or escape a literal with `$${...}`.",
+    },
+    DiagnosticCode {
+        code: "LETUS1008",
+        name: "UnterminatedReference",
+        description: "A `${` was opened without a closing `}`.",
+        remediation: "Close the reference or escape it with `$${`.",
+    },
+    DiagnosticCode {
+        code: "LETUS1009",
+        name: "JsonParse",
+        description: "The JSON config does not match the InfraConfig structure.",
+        remediation: "Fix the value at the JSON pointer given in the message.",
+    },
+    DiagnosticCode {
+        code: "LETUS1010",
+        name: "MalformedVariable",
+        description: "A --var flag is not of the form name=value.",
+        remediation: "Pass the variable as --var name=value.",
+    },
+    DiagnosticCode {
+        code: "LETUS1011",
+        name: "UndeclaredVariable",
+        description: "A value was given for, or `${var.<name>}` references, a variable the config does not declare.",
+        remediation: "Declare the variable under `variables`, or fix the name.",
+    },
+    DiagnosticCode {
+        code: "LETUS1012",
+        name: "MissingVariable",
+        description: "A variable without a default was given no value.",
+        remediation: "Set it with --var name=value or in the --var-file, or give it a default.",
+    },
+    DiagnosticCode {
+        code: "LETUS1013",
+        name: "VariableType",
+        description: "The value or default of a variable does not match its declared type.",
+        remediation: "Change the value to the declared type (string, int or bool).",
+    },
+    DiagnosticCode {
+        code: "LETUS1014",
+        name: "VariableFile",
+        description: "The --var-file could not be read or is not a mapping of names to values.",
+        remediation: "Check the path and that the file is YAML or JSON with one entry per variable.",
+    },
+    DiagnosticCode {
+        code: "LETUS1015",
+        name: "MissingEnvironmentVariable",
+        description: "A property references `${env:<NAME>}` but the environment variable is not set.",
+        remediation: "Export the variable before running the command, or escape the reference as `$${env:<NAME>}`.",
+    },
+    DiagnosticCode {
+        code: "LETUS2001",
+        name: "ReferenceCycle",
+        description: "References form a cycle, e.g. `region: ${region}`.",
+        remediation: "Break the cycle by replacing one of the references with a literal value.",
+    },
+    DiagnosticCode {
+        code: "LETUS2002",
+        name: "UnreferencedComponent",
+        description: "A component is not connected to any other component and may be a leftover.",
+        remediation: "Remove the component, or list it under `lint.ignore_unused` if it is intentional.",
+    },
+    DiagnosticCode {
+        code: "LETUS2003",
+        name: "DependencyCycle",
+        description: "Components depend on each other through `dependsOn`, so none of them can go first.",
+        remediation: "Remove one of the `dependsOn` entries listed in the message.",
+    },
+    DiagnosticCode {
+        code: "LETUS2004",
+        name: "UnknownComponentReference",
+        description: "A `${<type>.<name>.<output>}` reference names a component that is not in the file.",
+        remediation: "Fix the type or name of the component, they are case sensitive.",
+    },
+    DiagnosticCode {
+        code: "LETUS2005",
+        name: "MissingOutput",
+        description: "A referenced output was not available when the component was applied.",
+        remediation: "List the referenced component in dependsOn so it is applied first, and check the output name.",
+    },
+    DiagnosticCode {
+        code: "LETUS2006",
+        name: "UnusedVariable",
+        description: "A declared variable is not referenced as `${var.<name>}` anywhere in the file.",
+        remediation: "Remove the variable, or list it as `var.<name>` under `lint.ignore_unused` if it is intentional.",
+    },
+    DiagnosticCode {
+        code: "AWS3001",
+        name: "InstanceNotFound",
+        description: "The EC2 instance does not exist (or no longer exists).",
+        remediation: "Check the instance id and region; terminated instances disappear after a while.",
+    },
+    DiagnosticCode {
+        code: "AWS3002",
+        name: "InstanceNotCreated",
+        description: "RunInstances returned without any instance.",
+        remediation: "Retry the apply and check the EC2 console for capacity or quota problems.",
+    },
+    DiagnosticCode {
+        code: "AWS3003",
+        name: "InvalidOptions",
+        description: "The component properties could not be turned into a valid AWS request.",
+        remediation: "Fix the property named in the message.",
+    },
+    DiagnosticCode {
+        code: "AWS3004",
+        name: "SdkError",
+        description: "AWS rejected the request.",
+        remediation: "The AWS error code and message are included; consult the AWS documentation for it.",
+    },
+    DiagnosticCode {
+        code: "AWS3005",
+        name: "Unauthorized",
+        description: "The credentials in use are not allowed to perform the operation.",
+        remediation: "Grant the missing IAM permission or use a different profile.",
+    },
+    DiagnosticCode {
+        code: "AWS3006",
+        name: "WaitTimeout",
+        description: "The resource did not reach the expected state in time.",
+        remediation: "Inspect the resource in the console; raise the LETUS_WAIT_TIMEOUT_MS if it is just slow.",
+    },
+    DiagnosticCode {
+        code: "AWS3007",
+        name: "WaitNotFound",
+        description: "The resource kept being reported as missing while waiting for it.",
+        remediation: "The resource may have been deleted externally; raise LETUS_WAIT_NOT_FOUND_CHECKS for slow regions.",
+    },
+    DiagnosticCode {
+        code: "AWS3008",
+        name: "UnexpectedState",
+        description: "The resource moved to a state that is neither pending nor the target.",
+        remediation: "Inspect the resource, e.g. an instance that went straight to `terminated`.",
+    },
+    DiagnosticCode {
+        code: "AWS3009",
+        name: "RefreshError",
+        description: "Reading the resource state from AWS failed while waiting.",
+        remediation: "See the attached message; transient failures can be retried.",
+    },
+    DiagnosticCode {
+        code: "AWS3010",
+        name: "ResourceNotFound",
+        description: "A resource other than an instance (VPC, subnet, S3 bucket, IAM role, ...) does not exist.",
+        remediation: "Check the id recorded in state or passed with --resource-id, the resource may have been deleted outside of letusinfra.",
+    },
+    DiagnosticCode {
+        code: "AWS3011",
+        name: "RequiresReplacement",
+        description: "A property that cannot change on an existing resource (ami, subnet_id, ...) differs from it.",
+        remediation: "Destroy the component and apply again to recreate it, or revert the property.",
+    },
+    DiagnosticCode {
+        code: "AWS3012",
+        name: "Throttled",
+        description: "AWS kept rejecting the request with a throttling error (RequestLimitExceeded, ...) after every retry.",
+        remediation: "Apply with a lower --parallelism, or raise LETUS_THROTTLE_RETRIES and LETUS_THROTTLE_MAX_DELAY_MS to retry for longer.",
+    },
+    DiagnosticCode {
+        code: "AWS3013",
+        name: "SpotRequestFailed",
+        description: "The Spot request of an instance failed, or found no capacity before spot_fulfillment_timeout; the message names its status code.",
+        remediation: "Raise spot_fulfillment_timeout or max_price, pick another instance type or availability zone, or launch On-Demand.",
+    },
+    DiagnosticCode {
+        code: "AWS3014",
+        name: "PublicIpNotAssigned",
+        description: "Warning: an instance whose network interface asked for a public IP had none once public_ip_timeout passed; its public_ip and public_dns outputs are empty.",
+        remediation: "Raise public_ip_timeout, or run refresh later to record the address once AWS assigns it.",
+    },
+];
+
+/// Look a code up in the catalogue, case-insensitively
+pub fn lookup(code: &str) -> Option<&'static DiagnosticCode> {
+    CATALOGUE.iter().find(|c| c.code.eq_ignore_ascii_case(code))
+}
+
+/// Errors and warnings that carry a stable diagnostic code
+pub trait Diagnostic {
+    fn code(&self) -> &'static str;
+}
+
+impl Diagnostic for ValidationError {
+    fn code(&self) -> &'static str {
+        match self {
+            ValidationError::FileReadError(_) => "LETUS1001",
+            ValidationError::YamlParseError(_) => "LETUS1002",
+            ValidationError::InfraConfigValidationError(_) => "LETUS1003",
+            // Reported under the code of the first problem, every problem carries its own
+            ValidationError::Properties(errors) => errors.first().map_or("LETUS1004", |e| e.code()),
+            ValidationError::Variables(errors) => errors.first().map_or("LETUS1013", |e| e.code()),
+        }
+    }
+}
+
+impl Diagnostic for ConfigError {
+    fn code(&self) -> &'static str {
+        match self {
+            ConfigError::Yaml(_) => "LETUS1002",
+            ConfigError::Json { .. } => "LETUS1009",
+        }
+    }
+}
+
+impl Diagnostic for PlanError {
+    fn code(&self) -> &'static str {
+        match self {
+            PlanError::InvalidComponent(_) => "LETUS1006",
+            PlanError::MissingProperty(_, _) => "LETUS1004",
+            PlanError::InvalidPropertyType(_, _, _, _) => "LETUS1005",
+            PlanError::Interpolation(err) => err.code(),
+            PlanError::DependencyCycle(_) => "LETUS2003",
+        }
+    }
+}
+
+impl Diagnostic for InterpolationError {
+    fn code(&self) -> &'static str {
+        match self {
+            InterpolationError::UnknownReference(_) => "LETUS1007",
+            InterpolationError::Unterminated(_) => "LETUS1008",
+            InterpolationError::Cycle(_) => "LETUS2001",
+            InterpolationError::UnknownComponent(_) => "LETUS2004",
+            InterpolationError::MissingOutput { .. } => "LETUS2005",
+            InterpolationError::UnsetVariable(_) => "LETUS1012",
+            InterpolationError::MissingEnv { .. } => "LETUS1015",
+        }
+    }
+}
+
+impl Diagnostic for VariableError {
+    fn code(&self) -> &'static str {
+        match self {
+            VariableError::Malformed(_) => "LETUS1010",
+            VariableError::Undeclared(_) => "LETUS1011",
+            VariableError::Missing(_) => "LETUS1012",
+            VariableError::TypeMismatch { .. } => "LETUS1013",
+            VariableError::File { .. } => "LETUS1014",
+        }
+    }
+}
+
+impl Diagnostic for LintWarning {
+    fn code(&self) -> &'static str {
+        match self {
+            LintWarning::UnreferencedComponent { .. } => "LETUS2002",
+            LintWarning::UnusedVariable { .. } => "LETUS2006",
+        }
+    }
+}
+
+impl Diagnostic for EC2Error {
+    fn code(&self) -> &'static str {
+        match self {
+            EC2Error::InstanceNotFound => "AWS3001",
+            EC2Error::InstanceNotCreated => "AWS3002",
+            EC2Error::OptionsError(_) => "AWS3003",
+            EC2Error::SdkError(_) => "AWS3004",
+            EC2Error::Unauthorized(_) => "AWS3005",
+            EC2Error::ResourceNotFound(_) => "AWS3010",
+            EC2Error::StateError(err) => err.code(),
+            EC2Error::RequiresReplacement(_) => "AWS3011",
+            EC2Error::Throttled(_) => "AWS3012",
+            EC2Error::SpotRequestFailed(_) => "AWS3013",
+        }
+    }
+}
+
+/// The same codes as the matching EC2Error variants, the service makes no difference to the fix
+impl Diagnostic for AwsError {
+    fn code(&self) -> &'static str {
+        match self {
+            AwsError::OptionsError(_) => "AWS3003",
+            AwsError::SdkError(_) => "AWS3004",
+            AwsError::Unauthorized(_) => "AWS3005",
+            AwsError::ResourceNotFound(_) => "AWS3010",
+            AwsError::StateError(err) => err.code(),
+            AwsError::RequiresReplacement(_) => "AWS3011",
+            AwsError::Throttled(_) => "AWS3012",
+            AwsError::Ec2(err) => err.code(),
+        }
+    }
+}
+
+impl Diagnostic for WaitError {
+    fn code(&self) -> &'static str {
+        match self {
+            WaitError::Timeout { .. } => "AWS3006",
+            WaitError::NotFound { .. } => "AWS3007",
+            WaitError::UnexpectedState { .. } => "AWS3008",
+            WaitError::RefreshError(_) => "AWS3009",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    /// One value per variant of every diagnostic-producing type, extend when adding a variant
+    fn samples() -> Vec<&'static str> {
+        vec![
+            ValidationError::FileReadError(String::new()).code(),
+            ValidationError::YamlParseError(String::new()).code(),
+            ValidationError::InfraConfigValidationError(String::new()).code(),
+            ValidationError::Properties(vec![]).code(),
+            ConfigError::Json {
+                pointer: String::new(),
+                message: String::new(),
+            }
+            .code(),
+            PlanError::InvalidComponent(String::new()).code(),
+            PlanError::MissingProperty(String::new(), String::new()).code(),
+            PlanError::InvalidPropertyType(
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+            )
+            .code(),
+            PlanError::DependencyCycle(vec![]).code(),
+            InterpolationError::UnknownReference(String::new()).code(),
+            InterpolationError::Unterminated(String::new()).code(),
+            InterpolationError::Cycle(vec![]).code(),
+            InterpolationError::UnknownComponent(String::new()).code(),
+            InterpolationError::MissingOutput {
+                component: String::new(),
+                output: String::new(),
+            }
+            .code(),
+            LintWarning::UnreferencedComponent {
+                key: String::new(),
+                location: String::new(),
+            }
+            .code(),
+            LintWarning::UnusedVariable {
+                name: String::new(),
+                location: String::new(),
+            }
+            .code(),
+            EC2Error::InstanceNotFound.code(),
+            EC2Error::InstanceNotCreated.code(),
+            EC2Error::OptionsError(String::new()).code(),
+            EC2Error::SdkError(String::new()).code(),
+            EC2Error::Unauthorized(String::new()).code(),
+            WaitError::Timeout {
+                last_state: String::new(),
+                timeout: std::time::Duration::from_secs(0),
+                expected_states: vec![],
+            }
+            .code(),
+            WaitError::NotFound { retries: 0 }.code(),
+            WaitError::UnexpectedState {
+                current_state: String::new(),
+                expected_states: vec![],
+            }
+            .code(),
+            WaitError::RefreshError(String::new()).code(),
+            EC2Error::ResourceNotFound(String::new()).code(),
+            EC2Error::RequiresReplacement(String::new()).code(),
+            EC2Error::Throttled(String::new()).code(),
+            EC2Error::SpotRequestFailed(String::new()).code(),
+            ValidationError::Variables(vec![]).code(),
+            InterpolationError::UnsetVariable(String::new()).code(),
+            InterpolationError::MissingEnv {
+                name: String::new(),
+                component: String::new(),
+                path: String::new(),
+            }
+            .code(),
+            VariableError::Malformed(String::new()).code(),
+            VariableError::Undeclared(String::new()).code(),
+            VariableError::Missing(String::new()).code(),
+            VariableError::TypeMismatch {
+                name: String::new(),
+                expected: String::new(),
+                found: String::new(),
+            }
+            .code(),
+            VariableError::File {
+                path: String::new(),
+                message: String::new(),
+            }
+            .code(),
+            AwsError::ResourceNotFound(String::new()).code(),
+            AwsError::OptionsError(String::new()).code(),
+            AwsError::SdkError(String::new()).code(),
+            AwsError::Unauthorized(String::new()).code(),
+            AwsError::StateError(WaitError::NotFound { retries: 0 }).code(),
+            AwsError::RequiresReplacement(String::new()).code(),
+            AwsError::Throttled(String::new()).code(),
+            AwsError::Ec2(EC2Error::InstanceNotFound).code(),
+        ]
+    }
+
+    #[test]
+    fn test_every_diagnostic_is_registered() {
+        for code in samples() {
+            assert!(lookup(code).is_some(), "{} is not in the catalogue", code);
+        }
+    }
+
+    #[test]
+    fn test_every_catalogue_entry_is_used() {
+        let used: HashSet<&str> = samples().into_iter().collect();
+        for entry in CATALOGUE {
+            assert!(
+                used.contains(entry.code),
+                "{} is never produced",
+                entry.code
+            );
+        }
+    }
+
+    #[test]
+    fn test_codes_and_names_are_unique() {
+        let codes: HashSet<&str> = CATALOGUE.iter().map(|c| c.code).collect();
+        let names: HashSet<&str> = CATALOGUE.iter().map(|c| c.name).collect();
+        assert_eq!(codes.len(), CATALOGUE.len());
+        assert_eq!(names.len(), CATALOGUE.len());
+    }
+
+    #[test]
+    fn test_lookup_is_case_insensitive() {
+        assert_eq!(lookup("letus2001").unwrap().name, "ReferenceCycle");
+        assert!(lookup("LETUS9999").is_none());
+    }
+}
@@ -0,0 +1,763 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::models::{Component, InfraConfig};
+
+pub const WORKSPACE_REF: &str = "workspace";
+pub const METADATA_NAME_REF: &str = "metadata.name";
+pub const REGION_REF: &str = "region";
+pub const CLOUD_REF: &str = "cloud";
+pub const VARIABLE_PREFIX: &str = "var."; // `${var.<name>}` reads a declared variable
+pub const ENV_PREFIX: &str = "env:"; // `${env:<NAME>}` reads an environment variable
+const OUTPUTS_BLOCK: &str = "outputs";
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InterpolationError {
+    #[error("Unknown reference '${{{0}}}'")]
+    UnknownReference(String),
+    #[error("Unterminated reference in '{0}'")]
+    Unterminated(String),
+    #[error("Cyclic reference detected: {}", .0.join(" -> "))]
+    Cycle(Vec<String>),
+    #[error("Reference '${{{0}}}' points to a component that is not defined in the file")]
+    UnknownComponent(String),
+    #[error(
+        "Output '{output}' of {component} is not available, it must be applied first (list it in dependsOn)"
+    )]
+    MissingOutput { component: String, output: String },
+    #[error("Variable '{0}' has no value, set it with --var or --var-file")]
+    UnsetVariable(String),
+    #[error("Environment variable '{name}' used by {component} at '{path}' is not set")]
+    MissingEnv {
+        name: String,
+        component: String,
+        path: String,
+    },
+}
+
+/**
+ * Values of the built-in references available to every interpolated string.
+ * Built from the effective deployment context (config + selected workspace) so that the
+ * same file planned against two workspaces resolves to two different sets of properties.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterpolationContext {
+    pub workspace: String,
+    pub metadata_name: String,
+    pub region: String,
+    pub cloud: String,
+    pub variables: BTreeMap<String, String>, // Bound value, or default, of every variable
+}
+
+impl InterpolationContext {
+    /// Build the context from the raw config, resolving references between the built-in
+    /// fields themselves (e.g. `metadata.name: ${workspace}-app`).
+    pub fn from_config(config: &InfraConfig, workspace: &str) -> Result<Self, InterpolationError> {
+        let variables = config
+            .variables
+            .iter()
+            .map(|variable| {
+                let value = match variable.value.as_ref().or(variable.default.as_ref()) {
+                    Some(serde_yaml::Value::String(value)) => value.clone(),
+                    Some(serde_yaml::Value::Number(value)) => value.to_string(),
+                    Some(serde_yaml::Value::Bool(value)) => value.to_string(),
+                    _ => return Err(InterpolationError::UnsetVariable(variable.name.clone())),
+                };
+                Ok((variable.name.clone(), value))
+            })
+            .collect::<Result<BTreeMap<_, _>, _>>()?;
+        let mut resolver = BuiltinResolver {
+            config,
+            workspace,
+            variables: &variables,
+            visiting: Vec::new(),
+        };
+        Ok(InterpolationContext {
+            workspace: workspace.to_string(),
+            metadata_name: resolver.resolve(METADATA_NAME_REF)?,
+            region: resolver.resolve(REGION_REF)?,
+            cloud: config.cloud.as_str().to_string(),
+            variables,
+        })
+    }
+
+    fn lookup(&self, reference: &str) -> Option<&str> {
+        match reference {
+            WORKSPACE_REF => Some(&self.workspace),
+            METADATA_NAME_REF => Some(&self.metadata_name),
+            REGION_REF => Some(&self.region),
+            CLOUD_REF => Some(&self.cloud),
+            other => self
+                .variables
+                .get(other.strip_prefix(VARIABLE_PREFIX)?)
+                .map(|v| v.as_str()),
+        }
+    }
+}
+
+/// Resolves the built-in fields that are themselves allowed to contain references,
+/// keeping track of the fields being resolved to detect cycles.
+struct BuiltinResolver<'a> {
+    config: &'a InfraConfig,
+    workspace: &'a str,
+    variables: &'a BTreeMap<String, String>,
+    visiting: Vec<String>,
+}
+
+impl BuiltinResolver<'_> {
+    fn resolve(&mut self, reference: &str) -> Result<String, InterpolationError> {
+        let raw = match reference {
+            WORKSPACE_REF => return Ok(self.workspace.to_string()),
+            CLOUD_REF => return Ok(self.config.cloud.as_str().to_string()),
+            METADATA_NAME_REF => self.config.metadata.name.clone(),
+            REGION_REF => self.config.region.clone(),
+            other if other.starts_with(VARIABLE_PREFIX) => {
+                return self
+                    .variables
+                    .get(&other[VARIABLE_PREFIX.len()..])
+                    .cloned()
+                    .ok_or_else(|| InterpolationError::UnknownReference(other.to_string()));
+            }
+            other => return Err(InterpolationError::UnknownReference(other.to_string())),
+        };
+
+        if self.visiting.iter().any(|r| r == reference) {
+            let mut chain = self.visiting.clone();
+            chain.push(reference.to_string());
+            return Err(InterpolationError::Cycle(chain));
+        }
+
+        self.visiting.push(reference.to_string());
+        let resolved = substitute(&raw, &mut |r| self.resolve(r).map(Some), |_| false);
+        self.visiting.pop();
+        resolved
+    }
+}
+
+/**
+ * Replace every `${...}` token in `input` using `lookup`.
+ * `$${...}` is an escape and produces a literal `${...}`, unless `keep_escaped` holds for the
+ * escaped reference: it then stays escaped for a later pass to unescape.
+ * A lookup returning `Ok(None)` is reported as an unknown reference.
+ */
+fn substitute<F>(
+    input: &str,
+    lookup: &mut F,
+    keep_escaped: fn(&str) -> bool,
+) -> Result<String, InterpolationError>
+where
+    F: FnMut(&str) -> Result<Option<String>, InterpolationError>,
+{
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        let tail = &rest[start..];
+
+        if tail.starts_with("$${") {
+            let escaped = tail[3..].find('}').map(|end| tail[3..3 + end].trim());
+            output.push_str(if escaped.is_some_and(keep_escaped) {
+                "$${"
+            } else {
+                "${"
+            });
+            rest = &tail[3..];
+            continue;
+        }
+        if !tail.starts_with("${") {
+            output.push('$');
+            rest = &tail[1..];
+            continue;
+        }
+
+        let end = tail
+            .find('}')
+            .ok_or_else(|| InterpolationError::Unterminated(input.to_string()))?;
+        let reference = tail[2..end].trim();
+        match lookup(reference)? {
+            Some(value) => output.push_str(&value),
+            None => return Err(InterpolationError::UnknownReference(reference.to_string())),
+        }
+        rest = &tail[end + 1..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// Interpolate a single string against the built-in references.
+pub fn interpolate_str(
+    input: &str,
+    context: &InterpolationContext,
+) -> Result<String, InterpolationError> {
+    substitute(
+        input,
+        &mut |reference| Ok(context.lookup(reference).map(|v| v.to_string())),
+        |_| false,
+    )
+}
+
+/// `<type>.<name>` and output name of a reference to another component, e.g. `${VPC.main.id}`
+pub fn component_reference(reference: &str) -> Option<(&str, &str)> {
+    let mut parts = reference.splitn(3, '.');
+    let (component_type, name, output) = (parts.next()?, parts.next()?, parts.next()?);
+    if !component_type.starts_with(|c: char| c.is_ascii_uppercase())
+        || name.is_empty()
+        || output.is_empty()
+    {
+        return None;
+    }
+    Some((&reference[..component_type.len() + 1 + name.len()], output))
+}
+
+/// Apply `f` to every string found in a YAML value, recursing through mappings and sequences.
+fn map_strings<F>(
+    value: &serde_yaml::Value,
+    f: &mut F,
+) -> Result<serde_yaml::Value, InterpolationError>
+where
+    F: FnMut(&str) -> Result<String, InterpolationError>,
+{
+    map_strings_at(value, "", &mut |s, _| f(s))
+}
+
+/// Like `map_strings`, `f` also gets the path of the string, e.g. `tag_specifications[0].tags.Name`
+fn map_strings_at<F>(
+    value: &serde_yaml::Value,
+    path: &str,
+    f: &mut F,
+) -> Result<serde_yaml::Value, InterpolationError>
+where
+    F: FnMut(&str, &str) -> Result<String, InterpolationError>,
+{
+    match value {
+        serde_yaml::Value::String(s) => Ok(serde_yaml::Value::String(f(s, path)?)),
+        serde_yaml::Value::Sequence(seq) => Ok(serde_yaml::Value::Sequence(
+            seq.iter()
+                .enumerate()
+                .map(|(i, item)| map_strings_at(item, &format!("{}[{}]", path, i), f))
+                .collect::<Result<_, _>>()?,
+        )),
+        serde_yaml::Value::Mapping(map) => {
+            let mut resolved = serde_yaml::Mapping::new();
+            for (k, v) in map {
+                let key = match k {
+                    serde_yaml::Value::String(key) => key.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let path = if path.is_empty() {
+                    key
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                resolved.insert(k.clone(), map_strings_at(v, &path, f)?);
+            }
+            Ok(serde_yaml::Value::Mapping(resolved))
+        }
+        other => Ok(other.clone()),
+    }
+}
+
+/// Interpolate every string found in a YAML value, recursing through mappings and sequences.
+pub fn interpolate_value(
+    value: &serde_yaml::Value,
+    context: &InterpolationContext,
+) -> Result<serde_yaml::Value, InterpolationError> {
+    map_strings(value, &mut |s| interpolate_str(s, context))
+}
+
+/**
+ * Resolve the built-in, variable and environment references of a component's properties.
+ * References to the outputs of other components are only checked to name a component of the
+ * file and are left in place, together with their escapes, for `interpolate_outputs` to fill in
+ * at apply time. `component` is the `<type>.<name>` of the component, for error messages.
+ */
+fn interpolate_properties(
+    value: &serde_yaml::Value,
+    context: &InterpolationContext,
+    components: &HashSet<String>,
+    component: &str,
+) -> Result<serde_yaml::Value, InterpolationError> {
+    let is_component_reference = |reference: &str| component_reference(reference).is_some();
+    map_strings_at(value, "", &mut |s, path| {
+        substitute(
+            s,
+            &mut |reference| {
+                if let Some(value) = context.lookup(reference) {
+                    return Ok(Some(value.to_string()));
+                }
+                if let Some(name) = env_reference(reference) {
+                    // Unset is an error, an empty value would be sent to AWS unnoticed
+                    return std::env::var(name).map(Some).map_err(|_| {
+                        InterpolationError::MissingEnv {
+                            name: name.to_string(),
+                            component: component.to_string(),
+                            path: path.to_string(),
+                        }
+                    });
+                }
+                match component_reference(reference) {
+                    Some((component, _)) if components.contains(component) => {
+                        Ok(Some(format!("${{{}}}", reference)))
+                    }
+                    Some(_) => Err(InterpolationError::UnknownComponent(reference.to_string())),
+                    None => Ok(None),
+                }
+            },
+            is_component_reference,
+        )
+    })
+}
+
+/**
+ * Substitute the `${<type>.<name>.<output>}` references left by `resolve_config` with the
+ * outputs of the components applied so far, keyed by `<type>.<name>` as in the state file.
+ * Any other `${...}` is a literal that `resolve_config` already unescaped and is kept as is.
+ */
+pub fn interpolate_outputs(
+    value: &serde_yaml::Value,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+) -> Result<serde_yaml::Value, InterpolationError> {
+    substitute_outputs(value, outputs, None)
+}
+
+/// Like `interpolate_outputs`, but an output that is not available yet becomes `unknown`
+pub fn preview_outputs(
+    value: &serde_yaml::Value,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+    unknown: &str,
+) -> Result<serde_yaml::Value, InterpolationError> {
+    substitute_outputs(value, outputs, Some(unknown))
+}
+
+fn substitute_outputs(
+    value: &serde_yaml::Value,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+    unknown: Option<&str>,
+) -> Result<serde_yaml::Value, InterpolationError> {
+    map_strings(value, &mut |s| {
+        substitute(
+            s,
+            &mut |reference| {
+                let Some((component, output)) = component_reference(reference) else {
+                    return Ok(Some(format!("${{{}}}", reference)));
+                };
+                match (outputs.get(component).and_then(|o| o.get(output)), unknown) {
+                    (Some(serde_json::Value::String(value)), _) => Ok(Some(value.clone())),
+                    (Some(value), _) if !value.is_null() => Ok(Some(value.to_string())),
+                    (_, Some(unknown)) => Ok(Some(unknown.to_string())),
+                    (_, None) => Err(InterpolationError::MissingOutput {
+                        component: component.to_string(),
+                        output: output.to_string(),
+                    }),
+                }
+            },
+            |_| false,
+        )
+    })
+}
+
+/**
+ * Produce the effective config for a workspace: built-in and variable references are resolved
+ * in metadata, region and every component's properties before anything reaches AWS.
+ */
+pub fn resolve_config(
+    config: &InfraConfig,
+    workspace: &str,
+) -> Result<InfraConfig, InterpolationError> {
+    let context = InterpolationContext::from_config(config, workspace)?;
+    let mut resolved = config.clone();
+    resolved.metadata.name = context.metadata_name.clone();
+    resolved.region = context.region.clone();
+    let components: HashSet<String> = config.components.iter().map(|c| c.key()).collect();
+    for component in resolved.components.iter_mut() {
+        let key = component.key();
+        component.properties =
+            interpolate_properties(&component.properties, &context, &components, &key)?;
+    }
+    // The outputs block may reference the same things as properties, by name for error messages
+    let declared: serde_yaml::Mapping = resolved
+        .outputs
+        .iter()
+        .map(|(name, output)| (name.as_str().into(), output.value().into()))
+        .collect();
+    let declared = interpolate_properties(
+        &serde_yaml::Value::Mapping(declared),
+        &context,
+        &components,
+        OUTPUTS_BLOCK,
+    )?;
+    for (name, output) in resolved.outputs.iter_mut() {
+        if let Some(value) = declared.get(name.as_str()).and_then(|v| v.as_str()) {
+            *output.value_mut() = value.to_string();
+        }
+    }
+    Ok(resolved)
+}
+
+/**
+ * Value of an entry of the `outputs:` block once its components are applied. An entry that is a
+ * single reference keeps the type of the output it points to, e.g. the list of `instance_ids`,
+ * any other entry is interpolated into a string.
+ */
+pub fn output_value(
+    expression: &str,
+    outputs: &serde_json::Map<String, serde_json::Value>,
+) -> Result<serde_json::Value, InterpolationError> {
+    let single = expression
+        .trim()
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .filter(|reference| !reference.contains(['$', '{', '}']))
+        .and_then(|reference| component_reference(reference.trim()));
+    if let Some((component, output)) = single {
+        return outputs
+            .get(component)
+            .and_then(|o| o.get(output))
+            .filter(|value| !value.is_null())
+            .cloned()
+            .ok_or_else(|| InterpolationError::MissingOutput {
+                component: component.to_string(),
+                output: output.to_string(),
+            });
+    }
+    let value = interpolate_outputs(&serde_yaml::Value::from(expression), outputs)?;
+    Ok(serde_json::Value::String(
+        value.as_str().unwrap_or_default().to_string(),
+    ))
+}
+
+/// Name of the environment variable of an `env:<NAME>` reference
+pub fn env_reference(reference: &str) -> Option<&str> {
+    reference
+        .strip_prefix(ENV_PREFIX)
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+}
+
+/// Values of the set environment variables the properties of `components` reference
+pub fn env_values(components: &[Component]) -> Vec<String> {
+    let mut found = Vec::new();
+    for component in components {
+        let _ = map_strings(&component.properties, &mut |s| {
+            for reference in references_in(s) {
+                let value = env_reference(&reference).and_then(|name| std::env::var(name).ok());
+                if let Some(value) = value.filter(|v| !v.is_empty() && !found.contains(v)) {
+                    found.push(value);
+                }
+            }
+            Ok(String::new())
+        });
+    }
+    found
+}
+
+/// Collect the references used in a string, ignoring escaped `$${...}` tokens.
+pub fn references_in(input: &str) -> Vec<String> {
+    let mut found = Vec::new();
+    let _ = substitute(
+        input,
+        &mut |reference| {
+            found.push(reference.to_string());
+            Ok(Some(String::new()))
+        },
+        |_| false,
+    );
+    let mut seen = HashSet::new();
+    found.retain(|r| seen.insert(r.clone()));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    fn config(yaml: &str) -> InfraConfig {
+        InfraConfig::from_yaml(yaml).unwrap()
+    }
+
+    const BASE: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+      tag_specifications:
+        - resource_type: instance
+          tags:
+            Name: ${metadata.name}-web
+            Environment: ${workspace}
+            Location: "${cloud}/${region}"
+            Literal: $${workspace}
+"#;
+
+    #[test]
+    fn test_resolve_builtin_references_in_properties() {
+        let resolved = resolve_config(&config(BASE), "staging").unwrap();
+        let tags = &resolved.components[0].properties["tag_specifications"][0]["tags"];
+        assert_eq!(tags["Name"].as_str(), Some("shop-web"));
+        assert_eq!(tags["Environment"].as_str(), Some("staging"));
+        assert_eq!(tags["Location"].as_str(), Some("AWS/us-west-2"));
+        assert_eq!(tags["Literal"].as_str(), Some("${workspace}"));
+    }
+
+    #[test]
+    fn test_builtin_fields_can_reference_each_other() {
+        let raw = BASE.replace("name: shop", "name: shop-${workspace}");
+        let resolved = resolve_config(&config(&raw), "prod").unwrap();
+        assert_eq!(resolved.metadata.name, "shop-prod");
+    }
+
+    #[test]
+    fn test_self_reference_is_rejected_as_cycle() {
+        let raw = BASE.replace("region: us-west-2", "region: ${region}");
+        let err = resolve_config(&config(&raw), "default").unwrap_err();
+        assert_eq!(
+            err,
+            InterpolationError::Cycle(vec!["region".to_string(), "region".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_indirect_cycle_is_rejected() {
+        let raw = BASE
+            .replace("region: us-west-2", "region: ${metadata.name}")
+            .replace("name: shop", "name: ${region}");
+        let err = resolve_config(&config(&raw), "default").unwrap_err();
+        assert!(matches!(err, InterpolationError::Cycle(_)));
+    }
+
+    #[test]
+    fn test_workspace_participates_in_plan_hash() {
+        let config = config(BASE);
+        let (staging, _, _) = crate::utils::plan_components(&config, "staging").unwrap();
+        let (prod, _, _) = crate::utils::plan_components(&config, "prod").unwrap();
+        let (staging_again, _, _) = crate::utils::plan_components(&config, "staging").unwrap();
+        assert_ne!(staging.hash, prod.hash);
+        assert_eq!(staging.hash, staging_again.hash);
+    }
+
+    const NETWORK: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: VPC
+    name: main
+    properties:
+      cidr: 10.0.0.0/16
+  - type: Subnet
+    name: web-subnet
+    properties:
+      vpc_id: ${VPC.main.id}
+      cidr: 10.0.1.0/24
+      tags:
+        Name: ${metadata.name}-${VPC.main.cidr}
+        Literal: $${VPC.main.id}
+    dependsOn:
+      - type: VPC
+        name: main
+"#;
+
+    #[test]
+    fn test_component_references_are_kept_until_apply() {
+        let resolved = resolve_config(&config(NETWORK), "default").unwrap();
+        let properties = &resolved.components[1].properties;
+        assert_eq!(properties["vpc_id"].as_str(), Some("${VPC.main.id}"));
+        assert_eq!(
+            properties["tags"]["Name"].as_str(),
+            Some("shop-${VPC.main.cidr}")
+        );
+
+        let outputs = serde_json::json!({
+            "VPC.main": { "id": "vpc-123", "cidr": "10.0.0.0/16" },
+        });
+        let applied = interpolate_outputs(properties, outputs.as_object().unwrap()).unwrap();
+        assert_eq!(applied["vpc_id"].as_str(), Some("vpc-123"));
+        assert_eq!(applied["tags"]["Name"].as_str(), Some("shop-10.0.0.0/16"));
+        assert_eq!(applied["tags"]["Literal"].as_str(), Some("${VPC.main.id}"));
+    }
+
+    #[test]
+    fn test_missing_outputs_and_components_are_named() {
+        let resolved = resolve_config(&config(NETWORK), "default").unwrap();
+        let outputs = serde_json::json!({ "VPC.main": { "cidr": "10.0.0.0/16" } });
+        assert_eq!(
+            interpolate_outputs(
+                &resolved.components[1].properties,
+                outputs.as_object().unwrap()
+            ),
+            Err(InterpolationError::MissingOutput {
+                component: "VPC.main".to_string(),
+                output: "id".to_string(),
+            })
+        );
+
+        let previewed = preview_outputs(
+            &resolved.components[1].properties,
+            outputs.as_object().unwrap(),
+            "(known after apply)",
+        )
+        .unwrap();
+        assert_eq!(previewed["vpc_id"].as_str(), Some("(known after apply)"));
+        assert_eq!(previewed["tags"]["Name"].as_str(), Some("shop-10.0.0.0/16"));
+
+        let raw = NETWORK.replace("${VPC.main.id}", "${VPC.other.id}");
+        assert_eq!(
+            resolve_config(&config(&raw), "default").unwrap_err(),
+            InterpolationError::UnknownComponent("VPC.other.id".to_string())
+        );
+    }
+
+    #[test]
+    fn test_outputs_block_is_resolved_after_apply() {
+        let raw = format!(
+            "{}outputs:\n  vpc: ${{VPC.main.id}}\n  label:\n    value: ${{metadata.name}}-${{VPC.main.cidr}}\n    sensitive: true\n",
+            NETWORK
+        );
+        let resolved = resolve_config(&config(&raw), "default").unwrap();
+        assert_eq!(resolved.outputs["label"].value(), "shop-${VPC.main.cidr}");
+        assert!(resolved.outputs["label"].sensitive());
+
+        let outputs = serde_json::json!({
+            "VPC.main": { "id": "vpc-123", "cidr": "10.0.0.0/16", "subnets": ["a", "b"] },
+        });
+        let outputs = outputs.as_object().unwrap();
+        assert_eq!(
+            output_value(resolved.outputs["label"].value(), outputs),
+            Ok(serde_json::json!("shop-10.0.0.0/16"))
+        );
+        // A single reference keeps the type of the output
+        assert_eq!(
+            output_value("${VPC.main.subnets}", outputs),
+            Ok(serde_json::json!(["a", "b"]))
+        );
+        assert_eq!(
+            output_value("${VPC.main.arn}", outputs),
+            Err(InterpolationError::MissingOutput {
+                component: "VPC.main".to_string(),
+                output: "arn".to_string(),
+            })
+        );
+
+        let raw = raw.replace("vpc: ${VPC.main.id}", "vpc: ${VPC.other.id}");
+        assert_eq!(
+            resolve_config(&config(&raw), "default").unwrap_err(),
+            InterpolationError::UnknownComponent("VPC.other.id".to_string())
+        );
+    }
+
+    #[test]
+    fn test_variables_resolve_in_region_metadata_and_properties() {
+        let raw = BASE
+            .replace("region: us-west-2", "region: ${var.region}")
+            .replace("name: shop", "name: shop-${var.env}")
+            .replace("instance_type: t2.micro", "instance_type: ${var.size}")
+            .replace(
+                "components:",
+                "variables:\n  - name: region\n    default: eu-west-1\n  - name: env\n  - name: size\n    default: t3.small\ncomponents:",
+            );
+        let mut config = config(&raw);
+        assert_eq!(
+            resolve_config(&config, "default").unwrap_err(),
+            InterpolationError::UnsetVariable("env".to_string())
+        );
+
+        config.variables[1].value = Some(serde_yaml::Value::String("prod".to_string()));
+        let resolved = resolve_config(&config, "default").unwrap();
+        assert_eq!(resolved.region, "eu-west-1");
+        assert_eq!(resolved.metadata.name, "shop-prod");
+        let properties = &resolved.components[0].properties;
+        assert_eq!(properties["instance_type"].as_str(), Some("t3.small"));
+        assert_eq!(
+            properties["tag_specifications"][0]["tags"]["Name"].as_str(),
+            Some("shop-prod-web")
+        );
+    }
+
+    const ENV: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: shop
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      key_name: ${env:LETUS_TEST_KEY_NAME}
+      tag_specifications:
+        - resource_type: instance
+          tags:
+            Owner: team-${env:LETUS_TEST_OWNER}
+            Literal: $${env:LETUS_TEST_OWNER}
+      security_groups: [default, "${env:LETUS_TEST_OWNER}-sg"]
+"#;
+
+    #[test]
+    #[serial]
+    fn test_env_references_resolve_in_nested_maps_and_lists() {
+        unsafe {
+            std::env::set_var("LETUS_TEST_KEY_NAME", "ops-key");
+            std::env::set_var("LETUS_TEST_OWNER", "payments");
+        }
+        let config = config(ENV);
+        let resolved = resolve_config(&config, "default").unwrap();
+        let properties = &resolved.components[0].properties;
+        assert_eq!(properties["key_name"].as_str(), Some("ops-key"));
+        let tags = &properties["tag_specifications"][0]["tags"];
+        assert_eq!(tags["Owner"].as_str(), Some("team-payments"));
+        assert_eq!(tags["Literal"].as_str(), Some("${env:LETUS_TEST_OWNER}"));
+        assert_eq!(
+            properties["security_groups"][1].as_str(),
+            Some("payments-sg")
+        );
+        assert_eq!(env_values(&config.components), vec!["ops-key", "payments"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_missing_env_names_component_and_path() {
+        unsafe {
+            std::env::set_var("LETUS_TEST_KEY_NAME", "ops-key");
+            std::env::remove_var("LETUS_TEST_OWNER");
+        }
+        assert_eq!(
+            resolve_config(&config(ENV), "default").unwrap_err(),
+            InterpolationError::MissingEnv {
+                name: "LETUS_TEST_OWNER".to_string(),
+                component: "EC2Instance.web".to_string(),
+                path: "tag_specifications[0].tags.Owner".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_and_unterminated_references() {
+        let context = InterpolationContext::from_config(&config(BASE), "default").unwrap();
+        assert_eq!(
+            interpolate_str("${nope}", &context),
+            Err(InterpolationError::UnknownReference("nope".to_string()))
+        );
+        assert!(matches!(
+            interpolate_str("${workspace", &context),
+            Err(InterpolationError::Unterminated(_))
+        ));
+        assert_eq!(interpolate_str("cost $5", &context).unwrap(), "cost $5");
+    }
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashSet, fmt::Display};
+
+use serde::Serialize;
+
+use crate::{
+    aws::internal::wait_and_refresh::is_wait_variable,
+    models::InfraConfig,
+    utils::interpolation::{VARIABLE_PREFIX, component_reference, references_in},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum LintWarning {
+    /// A component nothing depends on or connects to, and which has no dependencies itself
+    UnreferencedComponent { key: String, location: String },
+    /// A declared variable no `${var.<name>}` reads
+    UnusedVariable { name: String, location: String },
+}
+
+impl Display for LintWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintWarning::UnreferencedComponent { key, location } => write!(
+                f,
+                "component '{}' (declared at {}) is not referenced by any other component",
+                key, location
+            ),
+            LintWarning::UnusedVariable { name, location } => write!(
+                f,
+                "variable '{}' (declared at {}) is not referenced anywhere",
+                name, location
+            ),
+        }
+    }
+}
+
+/**
+ * Run the config hygiene analyses. Variables are looked up in `raw`, interpolation leaves no
+ * references to them in `resolved`, components and their output references in `resolved`.
+ * Only reports warnings, callers decide whether they should fail the run.
+ * Items listed under `lint.ignore_unused` are never reported.
+ */
+pub fn lint_config(raw: &InfraConfig, resolved: &InfraConfig) -> Vec<LintWarning> {
+    let ignored: HashSet<&str> = resolved
+        .lint
+        .as_ref()
+        .map(|lint| lint.ignore_unused.iter().map(|s| s.as_str()).collect())
+        .unwrap_or_default();
+
+    let mut warnings = Vec::new();
+    // A lone component is the whole deployment, there is nothing to reference it
+    if resolved.components.len() > 1 {
+        warnings.extend(unreferenced_components(resolved, &ignored));
+    }
+    warnings.extend(unused_variables(raw, &ignored));
+    warnings
+}
+
+/// Every string in `value`, recursing through mappings and sequences
+fn collect_strings(value: &serde_yaml::Value, found: &mut Vec<String>) {
+    match value {
+        serde_yaml::Value::String(s) => found.push(s.clone()),
+        serde_yaml::Value::Sequence(seq) => seq.iter().for_each(|v| collect_strings(v, found)),
+        serde_yaml::Value::Mapping(map) => map.values().for_each(|v| collect_strings(v, found)),
+        _ => {}
+    }
+}
+
+/// `<type>.<name>` of every component whose outputs a `${<type>.<name>.<output>}` of `value` reads
+fn referenced_components(value: &serde_yaml::Value) -> Vec<String> {
+    let mut strings = Vec::new();
+    collect_strings(value, &mut strings);
+    strings
+        .iter()
+        .flat_map(|s| references_in(s))
+        .filter_map(|r| component_reference(&r).map(|(key, _)| key.to_string()))
+        .collect()
+}
+
+fn unreferenced_components(config: &InfraConfig, ignored: &HashSet<&str>) -> Vec<LintWarning> {
+    let mut referenced: HashSet<String> = config
+        .components
+        .iter()
+        .flat_map(|c| {
+            c.depends_on
+                .iter()
+                .chain(c.connects_to.iter())
+                .flatten()
+                .map(|dep| format!("{}.{}", dep.dep_type, dep.name))
+        })
+        .collect();
+    // Reading the outputs of a component, in properties or the outputs block, references it too
+    let declared: Vec<serde_yaml::Value> = config
+        .outputs
+        .values()
+        .map(|output| output.value().into())
+        .collect();
+    referenced.extend(referenced_components(&serde_yaml::Value::Sequence(
+        declared,
+    )));
+    let mut reading = HashSet::new();
+    for component in &config.components {
+        let read = referenced_components(&component.properties);
+        if !read.is_empty() {
+            reading.insert(component.key());
+        }
+        referenced.extend(read);
+    }
+
+    config
+        .components
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| {
+            let has_dependencies = c.depends_on.as_ref().is_some_and(|d| !d.is_empty())
+                || c.connects_to.as_ref().is_some_and(|d| !d.is_empty())
+                || reading.contains(&c.key());
+            !has_dependencies
+                && !referenced.contains(&c.key())
+                && !ignored.contains(c.key().as_str())
+        })
+        .map(|(idx, c)| LintWarning::UnreferencedComponent {
+            key: c.key(),
+            location: format!("components[{}]", idx),
+        })
+        .collect()
+}
+
+/**
+ * Declared variables no `${var.<name>}` reads, anywhere in the config but the variables block.
+ * Variables tuning the waits are read by name, see `WaitDefaults::for_config`.
+ */
+fn unused_variables(config: &InfraConfig, ignored: &HashSet<&str>) -> Vec<LintWarning> {
+    let mut document = serde_yaml::to_value(config).unwrap_or_default();
+    if let Some(map) = document.as_mapping_mut() {
+        map.remove("variables");
+    }
+    let mut strings = Vec::new();
+    collect_strings(&document, &mut strings);
+    let used: HashSet<String> = strings
+        .iter()
+        .flat_map(|s| references_in(s))
+        .filter_map(|r| r.strip_prefix(VARIABLE_PREFIX).map(|name| name.to_string()))
+        .collect();
+
+    config
+        .variables
+        .iter()
+        .enumerate()
+        .filter(|(_, v)| {
+            !used.contains(&v.name)
+                && !is_wait_variable(&v.name)
+                && !ignored.contains(format!("{}{}", VARIABLE_PREFIX, v.name).as_str())
+        })
+        .map(|(idx, v)| LintWarning::UnusedVariable {
+            name: v.name.clone(),
+            location: format!("variables[{}]", idx),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+components:
+  - type: VPC
+    name: main
+  - type: EC2Instance
+    name: web
+    dependsOn:
+      - type: VPC
+        name: main
+  - type: S3Bucket
+    name: leftover
+"#;
+
+    #[test]
+    fn test_reports_isolated_components() {
+        let config = InfraConfig::from_yaml(CONFIG).unwrap();
+        assert_eq!(
+            lint_config(&config, &config),
+            vec![LintWarning::UnreferencedComponent {
+                key: "S3Bucket.leftover".to_string(),
+                location: "components[2]".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ignore_unused_suppresses_warning() {
+        let yaml = format!(
+            "{}lint:\n  ignore_unused:\n    - S3Bucket.leftover\n",
+            CONFIG
+        );
+        let config = InfraConfig::from_yaml(&yaml).unwrap();
+        assert!(lint_config(&config, &config).is_empty());
+    }
+
+    fn lint(yaml: &str) -> Vec<LintWarning> {
+        let config = InfraConfig::from_yaml(yaml).unwrap();
+        let resolved = crate::utils::interpolation::resolve_config(&config, "default").unwrap();
+        lint_config(&config, &resolved)
+    }
+
+    #[test]
+    fn test_output_references_count_as_references() {
+        let from_component = CONFIG.replace(
+            "    name: leftover\n",
+            "    name: leftover\n    properties:\n      bucket: logs-${VPC.main.id}\n",
+        );
+        assert!(lint(&from_component).is_empty());
+
+        let from_outputs = format!("{}outputs:\n  logs: ${{S3Bucket.leftover.arn}}\n", CONFIG);
+        assert!(lint(&from_outputs).is_empty());
+    }
+
+    #[test]
+    fn test_reports_unused_variables() {
+        let yaml = format!(
+            "{}variables:\n  - name: env\n    default: dev\n  - name: size\n    default: t3.nano\n  - name: spare\n    default: x\n  - name: wait_min_delay_ms\n    type: int\n    default: 100\nlint:\n  ignore_unused:\n    - S3Bucket.leftover\n    - var.spare\n",
+            CONFIG
+                .replace("name: sample", "name: sample-${var.env}")
+                .replace(
+                    "    name: web\n",
+                    "    name: web\n    properties:\n      instance_type: ${var.size}\n"
+                )
+        );
+        assert!(lint(&yaml).is_empty());
+
+        let unused = yaml.replace("${var.size}", "t3.nano");
+        assert_eq!(
+            lint(&unused),
+            vec![LintWarning::UnusedVariable {
+                name: "size".to_string(),
+                location: "variables[1]".to_string(),
+            }]
+        );
+    }
+}
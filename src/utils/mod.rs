@@ -1,14 +1,31 @@
 use serde::Serialize;
 
-use crate::models::{InfraConfig, Plan, PlanError};
+use crate::{
+    handlers,
+    models::{Component, InfraConfig, Plan, PlanError, state::State},
+};
 
+pub mod ami_lookup;
+pub mod base64;
 pub mod constants;
+pub mod diagnostics;
+pub mod interpolation;
+pub mod lint;
+pub mod redact;
+pub mod render;
+pub mod schema;
+pub mod templates;
+pub mod timeouts;
+pub mod values;
+pub mod variables;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OperationType {
     Create,
     Update,
     Delete,
+    NoOp, // Applied before with the same properties
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
@@ -16,6 +33,20 @@ pub struct ComponentPreview {
     pub component_type: String,
     pub name: String,
     pub operation_type: OperationType,
+    pub properties_hash: String, // Hash of the resolved properties, changes whenever an interpolated value does
+    pub info: String,            // Short human summary shown in the Info column, may be truncated
+    pub supported: bool, // False when a preflight check found the component cannot be created
+    pub replacement_hash: String, // Hash of the properties that force a new resource
+    pub replace: bool,   // The update can only be applied by replacing the resource
+    /// Values the provider fills in at apply, None for types that compute nothing up front
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub computed: Option<serde_json::Value>,
+    /// Template the component's properties start from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Left out of a plan or apply narrowed with --target
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub excluded: bool,
 }
 #[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct PlanPreviewDeployment {
@@ -24,9 +55,384 @@ pub struct PlanPreviewDeployment {
     pub components: Vec<ComponentPreview>,
 }
 
+static STDIN_CONFIG: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+
+/// Read a config file, `-` reads it from stdin
+/// Stdin is only consumed once and kept, as validation and the commands both load the config.
+/// `<file>#<n>` reads the n-th document of a multi-document YAML file, counting from 1.
+pub fn read_config_source(file_path: &str) -> std::io::Result<String> {
+    if let Some((path, index)) = document_selector(file_path) {
+        let content = read_config_source(path)?;
+        return split_documents(&content)
+            .into_iter()
+            .nth(index - 1)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{} has no document {}", path, index),
+                )
+            });
+    }
+    if file_path == "-" {
+        if let Some(content) = STDIN_CONFIG.get() {
+            return Ok(content.clone());
+        }
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut std::io::stdin(), &mut content)?;
+        return Ok(STDIN_CONFIG.get_or_init(|| content).clone());
+    }
+    std::fs::read_to_string(file_path)
+}
+
+/// `<file>#<n>` split into the file and the document number
+pub(crate) fn document_selector(file_path: &str) -> Option<(&str, usize)> {
+    let (path, index) = file_path.rsplit_once('#')?;
+    let index: usize = index.parse().ok().filter(|i| *i > 0)?;
+    Some((path, index))
+}
+
+/**
+ * Documents of a `---` separated YAML stream, documents holding nothing but comments are
+ * dropped. A JSON config is always a single document.
+ */
+pub fn split_documents(content: &str) -> Vec<String> {
+    if content.trim_start().starts_with('{') {
+        return vec![content.to_string()];
+    }
+    let mut documents = vec![String::new()];
+    for line in content.lines() {
+        if line.trim_end() == "---" || line.starts_with("--- ") {
+            documents.push(String::new());
+            continue;
+        }
+        let document = documents.last_mut().expect("documents is never empty");
+        document.push_str(line);
+        document.push('\n');
+    }
+    documents.retain(|document| {
+        document
+            .lines()
+            .any(|line| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+    });
+    documents
+}
+
+/// A deployment found in a config source, `source` reads it back through `read_config_source`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigDocument {
+    pub source: String,
+    pub name: String, // metadata.name as written, before interpolation
+}
+
+/**
+ * Deployments of `-f`: every document of a multi-document file, or of every `*.yaml`/`*.yml`
+ * file of a directory in name order. Documents of kind Component are templates and skipped,
+ * and two documents with the same deployment name are rejected.
+ */
+pub fn config_documents(file_path: &str) -> Result<Vec<ConfigDocument>, String> {
+    let path = std::path::Path::new(file_path);
+    let files = if path.is_dir() {
+        let entries = std::fs::read_dir(path)
+            .map_err(|err| format!("Failed to read directory {}: {}", file_path, err))?;
+        let is_yaml = |p: &std::path::Path| {
+            let extension = p
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(|e| e.to_lowercase());
+            p.is_file() && matches!(extension.as_deref(), Some("yaml") | Some("yml"))
+        };
+        let mut files: Vec<String> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|p| is_yaml(p))
+            .map(|p| p.display().to_string())
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            return Err(format!("No *.yaml or *.yml files in {}", file_path));
+        }
+        files
+    } else {
+        vec![file_path.to_string()]
+    };
+
+    let mut found: Vec<ConfigDocument> = Vec::new();
+    for file in files {
+        let content = read_config_source(&file)
+            .map_err(|err| format!("Failed to read file {}: {}", file, err))?;
+        let documents = split_documents(&content);
+        let count = documents.len();
+        for (index, document) in documents.iter().enumerate() {
+            let source = match count {
+                1 => file.clone(),
+                _ => format!("{}#{}", file, index + 1),
+            };
+            // Broken documents still get a name so validation reports their actual error
+            let raw: serde_yaml::Value = serde_yaml::from_str(document).unwrap_or_default();
+            // Component documents are templates, used by the deployments that name them
+            let kind = raw.get("kind").and_then(|k| k.as_str());
+            if kind == Some(crate::models::Kind::Component.as_str()) {
+                continue;
+            }
+            let name = raw
+                .get("metadata")
+                .and_then(|m| m.get("name"))
+                .and_then(|n| n.as_str())
+                .unwrap_or_default()
+                .to_string();
+            if let Some(other) = found.iter().find(|d| !name.is_empty() && d.name == name) {
+                return Err(format!(
+                    "Deployment name '{}' is used by both {} and {}",
+                    name, other.source, source
+                ));
+            }
+            found.push(ConfigDocument { source, name });
+        }
+    }
+    if found.is_empty() {
+        return Err(format!("No Infra documents in {}", file_path));
+    }
+    Ok(found)
+}
+
+/**
+ * The one deployment of `-f` a command works on. A source holding several needs `deployment` to
+ * name it. Returns the document and the deployment whose state it uses, None for a single
+ * deployment, which keeps the state of the workspace like apply does.
+ */
+pub fn select_document(
+    file_path: &str,
+    deployment: Option<&str>,
+) -> Result<(ConfigDocument, Option<String>), String> {
+    let mut documents = config_documents(file_path)?;
+    let names = || {
+        documents
+            .iter()
+            .map(|d| d.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let index = match deployment {
+        Some(name) => documents
+            .iter()
+            .position(|d| d.name == name)
+            .ok_or_else(|| {
+                format!(
+                    "No deployment '{}' in {}, it has: {}",
+                    name,
+                    file_path,
+                    names()
+                )
+            })?,
+        None if documents.len() == 1 => 0,
+        None => {
+            return Err(format!(
+                "{} holds several deployments ({}), pass --deployment to pick one",
+                file_path,
+                names()
+            ));
+        }
+    };
+    let several = documents.len() > 1;
+    let document = documents.swap_remove(index);
+    let deployment = several.then(|| document.name.clone());
+    Ok((document, deployment))
+}
+
+/// Make file paths in properties, e.g. `user_data_file`, relative to the config file's directory
+pub fn anchor_config_paths(config: &mut InfraConfig, file_path: &str) {
+    // A config read from stdin keeps paths relative to the working directory
+    if file_path == "-" {
+        return;
+    }
+    let base_dir = std::path::Path::new(file_path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    for component in config.components.iter_mut() {
+        match component.component_type.as_str() {
+            "EC2Instance" => crate::aws::ec2::ec2_instance::anchor_user_data_file(
+                &mut component.properties,
+                base_dir,
+            ),
+            "KeyPair" => crate::aws::ec2::key_pair::anchor_private_key_file(
+                &mut component.properties,
+                &component.name,
+                base_dir,
+            ),
+            _ => {}
+        }
+    }
+}
+
+/// Workspace selected through the LETUS_WORKSPACE environment variable, "default" otherwise
+pub fn current_workspace() -> String {
+    std::env::var(constants::WORKSPACE_ENV)
+        .ok()
+        .filter(|w| !w.trim().is_empty())
+        .unwrap_or_else(|| constants::DEFAULT_WORKSPACE.to_string())
+}
+
+/// Workspace names become directory names, so only letters, digits, `-` and `_` are allowed
+pub fn validate_workspace(name: &str) -> Result<(), String> {
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if name.is_empty() || !valid {
+        return Err(format!(
+            "Invalid workspace '{}': use only letters, digits, '-' and '_'",
+            name
+        ));
+    }
+    Ok(())
+}
+
+/// Workspace given with --workspace, which takes precedence over LETUS_WORKSPACE
+pub fn select_workspace(flag: Option<&str>) -> Result<String, String> {
+    let workspace = match flag {
+        Some(workspace) => workspace.trim().to_string(),
+        None => current_workspace(),
+    };
+    validate_workspace(&workspace)?;
+    Ok(workspace)
+}
+
+/// FNV-1a 64 bit hash, stable across runs and toolchains unlike DefaultHasher
+pub fn stable_hash(input: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in input.as_bytes() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
+}
+
+pub fn component_hash(component: &Component) -> String {
+    // Timeouts only bound how long apply waits, changing them changes nothing to update
+    let mut properties = component.properties.clone();
+    if let Some(map) = properties.as_mapping_mut() {
+        map.remove(timeouts::TIMEOUTS_PROPERTY);
+    }
+    // Hash the canonical form so `100` and `"100"` don't produce different plans
+    let schema = schema::schema_for(&component.component_type);
+    let properties = values::normalize(&properties, schema).to_string();
+    stable_hash(&format!(
+        "{}|{}|{}",
+        component.component_type, component.name, properties
+    ))
+}
+
+/// Hash of the properties that force a new resource, see `schema::force_new_properties`
+pub fn replacement_hash(component: &Component) -> String {
+    let properties = values::normalize(
+        &schema::force_new_properties(component),
+        schema::schema_for(&component.component_type),
+    )
+    .to_string();
+    stable_hash(&format!(
+        "{}|{}|{}",
+        component.component_type, component.name, properties
+    ))
+}
+
+/// One-line summary of the properties that matter most for the component type
+fn component_info(component: &Component) -> String {
+    match component.component_type.as_str() {
+        "EC2Instance" => {
+            let info = ["instance_type", "image_id", "ami"]
+                .iter()
+                .filter_map(|key| component.get_property_as_string(key))
+                .collect::<Vec<_>>()
+                .join(" ");
+            // Easy to miss in the properties, so a launch of several instances leads the summary
+            let count = |key: &str| component.get_property(key).and_then(|c| c.as_i64());
+            match (
+                count("min_count").unwrap_or(1),
+                count("max_count").unwrap_or(1),
+            ) {
+                (_, max) if max <= 1 => info,
+                (min, max) if min == max => format!("{} x {}", max, info),
+                (min, max) => format!("{}-{} x {}", min, max, info),
+            }
+        }
+        "VPC" => component.get_property_as_string("cidr").unwrap_or_default(),
+        "Subnet" => ["cidr", "availability_zone"]
+            .iter()
+            .filter_map(|key| component.get_property_as_string(key))
+            .collect::<Vec<_>>()
+            .join(" "),
+        "KeyPair" => {
+            let source = match component.get_property("generate").and_then(|g| g.as_bool()) {
+                Some(true) => "generated",
+                _ => "imported",
+            };
+            let name = component
+                .get_property_as_string("key_name")
+                .unwrap_or_else(|| component.name.clone());
+            format!("{} {}", name, source)
+        }
+        "S3Bucket" => {
+            let versioned = component
+                .get_property("versioning")
+                .and_then(|v| v.as_bool());
+            let bucket = component
+                .get_property_as_string("bucket")
+                .unwrap_or_default();
+            match versioned {
+                Some(true) => format!("{} versioned", bucket),
+                _ => bucket,
+            }
+        }
+        "IAMRole" => {
+            let policies = |key: &str| {
+                component.get_property(key).map_or(0, |p| {
+                    p.as_sequence()
+                        .map(|s| s.len())
+                        .or_else(|| p.as_mapping().map(|m| m.len()))
+                        .unwrap_or(0)
+                })
+            };
+            format!(
+                "{} managed, {} inline polic(ies)",
+                policies("managed_policy_arns"),
+                policies("inline_policies")
+            )
+        }
+        "InstanceProfile" => component
+            .get_property_as_string("role")
+            .map(|role| format!("role {}", role))
+            .unwrap_or_default(),
+        "ElasticIP" => component
+            .get_property_as_string("associate_with")
+            .map(|target| format!("associated with {}", target))
+            .unwrap_or_default(),
+        "SecurityGroup" => {
+            let rules = |direction: &str| {
+                component
+                    .get_property(direction)
+                    .and_then(|r| r.as_sequence())
+                    .map_or(0, |r| r.len())
+            };
+            format!(
+                "{} ingress, {} egress rule(s)",
+                rules("ingress"),
+                rules("egress")
+            )
+        }
+        _ => String::new(),
+    }
+}
+
+/**
+ * Plan every component of the config.
+ * Errors in a component's properties don't stop the plan: they are collected across all components
+ * and returned next to the preview, with the failing components marked unsupported. Only errors
+ * that affect the whole config, such as an unresolvable reference, are returned as Err.
+ */
 pub fn plan_components(
     config: &InfraConfig,
-) -> Result<(Plan, PlanPreviewDeployment), crate::models::PlanError> {
+    workspace: &str,
+) -> Result<(Plan, PlanPreviewDeployment, Vec<PlanError>), PlanError> {
+    // Built-in references (${workspace}, ${region}, ...) are resolved before anything is planned
+    let config = &interpolation::resolve_config(config, workspace)?;
     // let dependency_tree = plan_components_sequence(&config.components);
     let mut preview_plan = PlanPreviewDeployment {
         deployment_type: config.kind.as_str().to_string(),
@@ -34,58 +440,1163 @@ pub fn plan_components(
         components: Vec::new(),
     };
     tracing::info!("Planning components: {:?}", config.components);
+    let mut errors = Vec::new();
     for component in &config.components {
+        let planned = match handlers::handler_for(&component.component_type) {
+            Some(handler) => handler.plan(&config.region, component),
+            None => Err(vec![PlanError::InvalidComponent(
+                schema::unknown_type_hint(&component.component_type, &component.name),
+            )]),
+        };
+        let supported = planned.is_ok();
+        let computed = match planned {
+            Ok(computed) => computed,
+            Err(component_errors) => {
+                errors.extend(component_errors);
+                None
+            }
+        };
         preview_plan.components.push(ComponentPreview {
             component_type: component.component_type.clone(),
             name: component.name.clone(),
-            operation_type: OperationType::Create, // For now, assume all are Create operations
+            operation_type: OperationType::Create, // Reclassified by diff_against_state once there is state
+            properties_hash: component_hash(component),
+            info: component_info(component),
+            supported,
+            replacement_hash: replacement_hash(component),
+            replace: false,
+            computed,
+            template: component.template.clone(),
+            excluded: false,
         });
-        match component.component_type.as_str() {
-            "EC2Instance" => {
-                // Create EC2 instance Terraform code
-                plan_ec2_instance(&config.region, component)?;
-            }
-            _ => {
-                eprintln!("Unsupported component type: {}", component.component_type);
+    }
+
+    let plan_hash = stable_hash(&format!(
+        "{}|{}|{}|{}",
+        workspace,
+        config.metadata.name,
+        config.region,
+        preview_plan
+            .components
+            .iter()
+            .map(|c| c.properties_hash.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    ));
+
+    Ok((
+        Plan {
+            workspace: workspace.to_string(),
+            hash: plan_hash,
+        },
+        preview_plan,
+        errors,
+    ))
+}
+
+/**
+ * Classify the planned components against the last applied state, matched by type and name.
+ * Components already in state become Update, or NoOp when their properties hash is unchanged;
+ * components only in state are appended as Delete. An Update is flagged as a replacement when a
+ * `force_new` property changed, or when the type cannot be updated in place at all.
+ */
+pub fn diff_against_state(preview: &mut PlanPreviewDeployment, state: &State) {
+    for component in &mut preview.components {
+        let Some(existing) = state
+            .components
+            .iter()
+            .find(|c| c.component_type == component.component_type && c.name == component.name)
+        else {
+            continue;
+        };
+        component.operation_type = match &existing.properties_hash {
+            Some(hash) if *hash == component.properties_hash => OperationType::NoOp,
+            _ => OperationType::Update,
+        };
+        let updatable = schema::schema_for(&component.component_type)
+            .is_some_and(|schema| schema.supports_update);
+        let force_new_changed = existing
+            .replacement_hash
+            .as_ref()
+            .is_some_and(|hash| *hash != component.replacement_hash);
+        component.replace =
+            component.operation_type == OperationType::Update && (!updatable || force_new_changed);
+    }
+
+    for existing in &state.components {
+        let planned = preview
+            .components
+            .iter()
+            .any(|c| c.component_type == existing.component_type && c.name == existing.name);
+        if planned {
+            continue;
+        }
+        preview.components.push(ComponentPreview {
+            component_type: existing.component_type.clone(),
+            name: existing.name.clone(),
+            operation_type: OperationType::Delete,
+            properties_hash: existing.properties_hash.clone().unwrap_or_default(),
+            info: existing
+                .instances
+                .iter()
+                .filter_map(|i| i.get("id").and_then(|id| id.as_str()))
+                .collect::<Vec<_>>()
+                .join(" "),
+            supported: true,
+            replacement_hash: existing.replacement_hash.clone().unwrap_or_default(),
+            replace: false,
+            computed: None,
+            template: None,
+            excluded: false,
+        });
+    }
+}
+
+/**
+ * Keys of the components named by --target and of everything they depend on, directly or through
+ * other components. A target that is not a component of the config is an error.
+ */
+pub fn target_closure(components: &[Component], targets: &[String]) -> Result<Vec<String>, String> {
+    let keys: Vec<String> = components.iter().map(|c| c.key()).collect();
+    if let Some(unknown) = targets.iter().find(|t| !keys.contains(t)) {
+        return Err(format!(
+            "Unknown target '{}', the components are: {}",
+            unknown,
+            keys.join(", ")
+        ));
+    }
+    let mut selected: Vec<String> = Vec::new();
+    let mut pending: Vec<String> = targets.to_vec();
+    while let Some(key) = pending.pop() {
+        if selected.contains(&key) {
+            continue;
+        }
+        let component = components.iter().find(|c| c.key() == key);
+        for dependency in component.iter().flat_map(|c| c.depends_on.iter().flatten()) {
+            pending.push(format!("{}.{}", dependency.dep_type, dependency.name));
+        }
+        selected.push(key);
+    }
+    Ok(selected)
+}
+
+/// Mark every component outside `targeted` as excluded, components only in state included
+pub fn exclude_untargeted(preview: &mut PlanPreviewDeployment, targeted: &[String]) {
+    for component in &mut preview.components {
+        let key = format!("{}.{}", component.component_type, component.name);
+        component.excluded = !targeted.contains(&key);
+    }
+}
+
+/**
+ * Order components so every component comes after the ones it `dependsOn`.
+ * Components without a dependency between them keep their declaration order.
+ */
+pub fn plan_components_sequence(components: &[Component]) -> Result<Vec<&Component>, PlanError> {
+    let keys: Vec<String> = components.iter().map(|c| c.key()).collect();
+    let mut dependencies: Vec<Vec<usize>> = Vec::with_capacity(components.len());
+    for component in components {
+        let mut indexes = Vec::new();
+        for dependency in component.depends_on.iter().flatten() {
+            let key = format!("{}.{}", dependency.dep_type, dependency.name);
+            match keys.iter().position(|k| *k == key) {
+                Some(index) => indexes.push(index),
+                None => {
+                    return Err(PlanError::InvalidComponent(format!(
+                        "'{}' depends on unknown component '{}'",
+                        component.key(),
+                        key
+                    )));
+                }
             }
         }
+        dependencies.push(indexes);
     }
 
-    Ok((Plan {}, preview_plan))
+    let mut sequence = Vec::with_capacity(components.len());
+    let mut placed = vec![false; components.len()];
+    while sequence.len() < components.len() {
+        let next = (0..components.len())
+            .find(|&i| !placed[i] && dependencies[i].iter().all(|&d| placed[d]));
+        match next {
+            Some(index) => {
+                placed[index] = true;
+                sequence.push(&components[index]);
+            }
+            None => {
+                let cycle = (0..components.len())
+                    .filter(|&i| !placed[i])
+                    .map(|i| keys[i].clone())
+                    .collect();
+                return Err(PlanError::DependencyCycle(cycle));
+            }
+        }
+    }
+    Ok(sequence)
 }
 
-// fn plan_components_sequence(
-//     components: &[crate::models::Component],
-// ) -> Vec<&crate::models::Component> {
-//     let mut sequence = Vec::new();
-//     let mut visited = HashSet::new();
+/// A string property, looked up under `keys` in order so aliases are accepted
+fn required_string(component: &Component, keys: &[&str]) -> Result<String, PlanError> {
+    let Some((key, value)) = keys
+        .iter()
+        .find_map(|key| component.get_property(key).map(|value| (key, value)))
+    else {
+        return Err(PlanError::MissingProperty(
+            keys[0].to_string(),
+            component.key(),
+        ));
+    };
+    value.as_str().map(|s| s.to_string()).ok_or_else(|| {
+        PlanError::InvalidPropertyType(
+            key.to_string(),
+            component.key(),
+            "string".to_string(),
+            schema::kind_of(value).to_string(),
+        )
+    })
+}
 
-//     for component in components {
-//         if !visited.contains(component) {
-//             plan_component_sequence(component, &mut sequence, &mut visited);
-//         }
-//     }
+/**
+ * Parse the properties exactly as apply does and check them without calling AWS.
+ * Every problem of the component is returned, not just the first, so they can all be fixed in one
+ * go. A planned instance gives the values apply will launch it with, an AMI that `ami_lookup`
+ * has not resolved yet is known after apply.
+ */
+pub(crate) fn plan_ec2_instance(
+    region: &str,
+    component: &Component,
+) -> Result<serde_json::Value, Vec<PlanError>> {
+    use crate::aws::ec2::ec2_instance::{AmiLookup, EC2Instance, KNOWN_AFTER_APPLY};
 
-//     sequence
-// }
+    let invalid =
+        |message: String| PlanError::InvalidComponent(format!("{}: {}", component.key(), message));
+    let lookup = AmiLookup::from_yaml(&component.properties)
+        .map_err(|err| vec![invalid(err.to_string())])?;
+    let instance_type = required_string(component, &["instance_type"]);
+    let ami_id = match required_string(component, &["ami", "image_id"]) {
+        Err(PlanError::MissingProperty(..)) if lookup.is_some() => {
+            Ok(KNOWN_AFTER_APPLY.to_string())
+        }
+        result => result,
+    };
+    let unresolved = matches!(ami_id.as_deref(), Ok(KNOWN_AFTER_APPLY));
+    if instance_type.is_err() || ami_id.is_err() {
+        return Err([instance_type.err(), ami_id.err()]
+            .into_iter()
+            .flatten()
+            .collect());
+    }
 
-fn plan_ec2_instance(region: &str, component: &crate::models::Component) -> Result<(), PlanError> {
-    let name = &component.name;
-    let instance_type = component
-        .get_property_as_string("instance_type")
-        .expect("Missing mandatory property 'instance_type' in component 'EC2Instance'");
-    let ami_id = component
-        .get_property_as_string("ami")
-        .expect("Missing mandatory property 'ami' in component 'EC2Instance'");
+    let mut properties = component.properties.clone();
+    if let Some(map) = properties.as_mapping_mut().filter(|_| unresolved) {
+        map.insert("ami".into(), KNOWN_AFTER_APPLY.into());
+    }
+    let opts =
+        EC2Instance::opts_from_yaml(&properties).map_err(|err| vec![invalid(err.to_string())])?;
+    let problems = opts.problems();
+    if !problems.is_empty() {
+        return Err(problems.into_iter().map(invalid).collect());
+    }
 
     tracing::debug!(
-        "Planning EC2 Instance: name={}, region={}, instance_type={}, ami={}",
-        name,
+        "Planning EC2 Instance: name={}, region={}, options={:?}",
+        component.name,
         region,
-        instance_type,
-        ami_id
+        opts
     );
-    // Here you would generate the custom plan for the EC2 instance
+    Ok(opts.computed())
+}
 
+pub(crate) fn plan_vpc(component: &Component) -> Result<(), Vec<PlanError>> {
+    let cidr = required_string(component, &["cidr"]).map_err(|err| vec![err])?;
+    crate::aws::ec2::vpc::validate_cidr(&cidr).map_err(|err| {
+        vec![PlanError::InvalidComponent(format!(
+            "{}: {}",
+            component.key(),
+            err
+        ))]
+    })
+}
+
+/// The VPC is either given as `vpc_id` or comes from a VPC the subnet depends on
+pub(crate) fn plan_subnet(component: &Component) -> Result<(), Vec<PlanError>> {
+    let mut errors = Vec::new();
+    match required_string(component, &["cidr"]) {
+        Ok(cidr) => {
+            if let Err(err) = crate::aws::ec2::vpc::validate_cidr(&cidr) {
+                errors.push(PlanError::InvalidComponent(format!(
+                    "{}: {}",
+                    component.key(),
+                    err
+                )));
+            }
+        }
+        Err(err) => errors.push(err),
+    }
+    let depends_on_vpc = component
+        .depends_on
+        .iter()
+        .flatten()
+        .any(|d| d.dep_type == "VPC");
+    if component.get_property("vpc_id").is_none() && !depends_on_vpc {
+        errors.push(PlanError::InvalidComponent(format!(
+            "{}: set vpc_id or add the VPC to dependsOn",
+            component.key()
+        )));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// Rules are parsed exactly as apply parses them, so a plan that passes cannot fail on bad rules
+pub(crate) fn plan_security_group(component: &Component) -> Result<(), Vec<PlanError>> {
+    crate::aws::ec2::security_group::SecurityGroup::opts_from_yaml(
+        &component.name,
+        &component.properties,
+    )
+    .map(|_| ())
+    .map_err(|err| {
+        vec![PlanError::InvalidComponent(format!(
+            "{}: {}",
+            component.key(),
+            err
+        ))]
+    })
+}
+
+/// Parsed exactly as apply parses it, the key pair id and fingerprint are only known after apply
+pub(crate) fn plan_key_pair(component: &Component) -> Result<serde_json::Value, Vec<PlanError>> {
+    use crate::aws::ec2::ec2_instance::KNOWN_AFTER_APPLY;
+
+    let opts =
+        crate::aws::ec2::key_pair::KeyPair::opts_from_yaml(&component.name, &component.properties)
+            .map_err(|err| {
+                vec![PlanError::InvalidComponent(format!(
+                    "{}: {}",
+                    component.key(),
+                    err
+                ))]
+            })?;
+    Ok(serde_json::json!({
+        "key_pair_id": KNOWN_AFTER_APPLY,
+        "fingerprint": KNOWN_AFTER_APPLY,
+        "key_name": opts.key_name,
+        "private_key_file": opts.private_key_file,
+    }))
+}
+
+/// The bucket name is checked against the S3 naming rules exactly as apply checks it
+pub(crate) fn plan_s3_bucket(component: &Component) -> Result<(), Vec<PlanError>> {
+    crate::aws::s3::bucket::S3Bucket::opts_from_yaml(&component.properties)
+        .map(|_| ())
+        .map_err(|err| {
+            vec![PlanError::InvalidComponent(format!(
+                "{}: {}",
+                component.key(),
+                err
+            ))]
+        })
+}
+
+/// Policy documents are converted to JSON and checked exactly as apply converts them
+pub(crate) fn plan_iam_role(component: &Component) -> Result<(), Vec<PlanError>> {
+    crate::aws::iam::role::IamRole::opts_from_yaml(&component.name, &component.properties)
+        .map(|_| ())
+        .map_err(|err| {
+            vec![PlanError::InvalidComponent(format!(
+                "{}: {}",
+                component.key(),
+                err
+            ))]
+        })
+}
+
+/// The role is either given as `role` or comes from an IAMRole the instance profile depends on
+pub(crate) fn plan_instance_profile(component: &Component) -> Result<(), Vec<PlanError>> {
+    use crate::aws::iam::{instance_profile::MAX_PROFILE_NAME_LEN, validate_iam_name};
+
+    let mut errors = Vec::new();
+    let name = component
+        .get_property_as_string("instance_profile_name")
+        .unwrap_or_else(|| component.name.clone());
+    if let Err(err) = validate_iam_name("instance profile", &name, MAX_PROFILE_NAME_LEN) {
+        errors.push(PlanError::InvalidComponent(format!(
+            "{}: {}",
+            component.key(),
+            err
+        )));
+    }
+    let depends_on_role = component
+        .depends_on
+        .iter()
+        .flatten()
+        .any(|d| d.dep_type == "IAMRole");
+    if component.get_property("role").is_none() && !depends_on_role {
+        errors.push(PlanError::InvalidComponent(format!(
+            "{}: set role or add the IAMRole to dependsOn",
+            component.key()
+        )));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/**
+ * `associate_with` is an instance id or a whole `${EC2Instance.<name>.id}` reference. The instance
+ * it references must be in dependsOn, so it is applied and running before the association.
+ */
+pub(crate) fn plan_elastic_ip(component: &Component) -> Result<(), Vec<PlanError>> {
+    let invalid = |message: String| vec![PlanError::InvalidComponent(message)];
+    let Some(target) = component.get_property_as_string("associate_with") else {
+        return Ok(());
+    };
+    let reference = target
+        .strip_prefix("${")
+        .and_then(|t| t.strip_suffix('}'))
+        .and_then(interpolation::component_reference);
+    let Some((instance, _)) = reference else {
+        if target.starts_with("i-") {
+            return Ok(());
+        }
+        return Err(invalid(format!(
+            "{}: associate_with must be an instance id or ${{EC2Instance.<name>.id}}, got '{}'",
+            component.key(),
+            target
+        )));
+    };
+    if !instance.starts_with("EC2Instance.") {
+        return Err(invalid(format!(
+            "{}: associate_with references {}, only an EC2Instance can be associated",
+            component.key(),
+            instance
+        )));
+    }
+    let depends_on_instance = component
+        .depends_on
+        .iter()
+        .flatten()
+        .any(|d| format!("{}.{}", d.dep_type, d.name) == instance);
+    if !depends_on_instance {
+        return Err(invalid(format!(
+            "{}: associate_with references {}, add it to dependsOn so it is running first",
+            component.key(),
+            instance
+        )));
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::state::{ComponentMode, ComponentState, Datatype, Output, OutputType};
+
+    fn config(components: &str) -> InfraConfig {
+        InfraConfig::from_yaml(&format!(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: sample\ncomponents:\n{}",
+            components
+        ))
+        .unwrap()
+    }
+
+    fn applied(component_type: &str, name: &str, hash: Option<String>) -> ComponentState {
+        ComponentState {
+            mode: ComponentMode::Managed,
+            name: name.to_string(),
+            component_type: component_type.to_string(),
+            provider: "aws".to_string(),
+            instances: vec![serde_json::json!({ "id": format!("i-{}", name) })],
+            created_at: None,
+            properties_hash: hash,
+            replacement_hash: None,
+            retained: false,
+        }
+    }
+
+    #[test]
+    fn test_diff_against_state() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+  - type: EC2Instance
+    name: api
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+  - type: EC2Instance
+    name: new
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+"#,
+        );
+        let (_, mut preview, _) = plan_components(&config, "default").unwrap();
+        let web_hash = preview.components[0].properties_hash.clone();
+        let state = State {
+            version: "1".to_string(),
+            serial: 4,
+            lineage: "abc".to_string(),
+            modulepack: "sample".to_string(),
+            outputs: Output {
+                value: serde_json::json!({}),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![
+                applied("EC2Instance", "web", Some(web_hash)),
+                applied("EC2Instance", "api", Some("stale".to_string())),
+                applied("EC2Instance", "old", None),
+            ],
+            launches: Default::default(),
+        };
+        diff_against_state(&mut preview, &state);
+
+        let operations: Vec<(&str, &OperationType)> = preview
+            .components
+            .iter()
+            .map(|c| (c.name.as_str(), &c.operation_type))
+            .collect();
+        assert_eq!(
+            operations,
+            vec![
+                ("web", &OperationType::NoOp),
+                ("api", &OperationType::Update),
+                ("new", &OperationType::Create),
+                ("old", &OperationType::Delete),
+            ]
+        );
+        assert_eq!(preview.components[3].info, "i-old");
+        assert!(preview.components.iter().all(|c| !c.replace));
+    }
+
+    #[test]
+    fn test_changed_force_new_property_is_a_replacement() {
+        let before = config(
+            "  - type: EC2Instance\n    name: web\n    properties: {instance_type: t2.micro, ami: ami-1}\n",
+        );
+        let (_, before, _) = plan_components(&before, "default").unwrap();
+        let mut web = applied("EC2Instance", "web", Some("stale".to_string()));
+        web.replacement_hash = Some(before.components[0].replacement_hash.clone());
+        let state = State {
+            version: "1".to_string(),
+            serial: 1,
+            lineage: "abc".to_string(),
+            modulepack: "sample".to_string(),
+            outputs: Output {
+                value: serde_json::json!({}),
+                output_type: OutputType {
+                    datatype: Datatype::Object,
+                    value: serde_json::json!({}),
+                },
+                declared: Default::default(),
+            },
+            components: vec![web],
+            launches: Default::default(),
+        };
+
+        // instance_type can be modified in place, ami cannot
+        let resized = config(
+            "  - type: EC2Instance\n    name: web\n    properties: {instance_type: t3.large, ami: ami-1}\n",
+        );
+        let (_, mut preview, _) = plan_components(&resized, "default").unwrap();
+        diff_against_state(&mut preview, &state);
+        assert_eq!(preview.components[0].operation_type, OperationType::Update);
+        assert!(!preview.components[0].replace);
+
+        let new_image = config(
+            "  - type: EC2Instance\n    name: web\n    properties: {instance_type: t2.micro, ami: ami-2}\n",
+        );
+        let (_, mut preview, _) = plan_components(&new_image, "default").unwrap();
+        diff_against_state(&mut preview, &state);
+        assert_eq!(preview.components[0].operation_type, OperationType::Update);
+        assert!(preview.components[0].replace);
+    }
+
+    #[test]
+    fn test_vpc_cidr_is_validated() {
+        let valid =
+            config("  - type: VPC\n    name: main\n    properties:\n      cidr: 10.0.0.0/16\n");
+        let (_, preview, errors) = plan_components(&valid, "default").unwrap();
+        assert_eq!(preview.components[0].info, "10.0.0.0/16");
+        assert!(errors.is_empty());
+
+        let invalid =
+            config("  - type: VPC\n    name: main\n    properties:\n      cidr: 10.0.0.0/8\n");
+        let (_, preview, errors) = plan_components(&invalid, "default").unwrap();
+        assert!(!preview.components[0].supported);
+        assert!(matches!(
+            &errors[..],
+            [PlanError::InvalidComponent(message)] if message.contains("VPC.main")
+        ));
+
+        let missing = config("  - type: VPC\n    name: main\n    properties: {}\n");
+        let (_, _, errors) = plan_components(&missing, "default").unwrap();
+        assert!(matches!(&errors[..], [PlanError::MissingProperty(..)]));
+    }
+
+    #[test]
+    fn test_security_group_rules_are_validated() {
+        let group = |rule: &str| {
+            config(&format!(
+                "  - type: SecurityGroup\n    name: web\n    properties:\n      ingress:\n        - {}\n",
+                rule
+            ))
+        };
+        let valid =
+            group("{protocol: tcp, from_port: 443, to_port: 443, cidr_blocks: [0.0.0.0/0]}");
+        let (_, preview, _) = plan_components(&valid, "default").unwrap();
+        assert_eq!(preview.components[0].info, "1 ingress, 0 egress rule(s)");
+
+        for (rule, expected) in [
+            (
+                "{protocol: tcp, from_port: 443, to_port: 80, cidr_blocks: [0.0.0.0/0]}",
+                "from_port 443 is greater than to_port 80",
+            ),
+            (
+                "{protocol: tcp, from_port: 0, to_port: 70000, cidr_blocks: [0.0.0.0/0]}",
+                "ports must be between 0 and 65535",
+            ),
+            (
+                "{protocol: gre, from_port: 0, to_port: 0, cidr_blocks: [0.0.0.0/0]}",
+                "unknown protocol 'gre'",
+            ),
+            (
+                "{protocol: udp, from_port: 53, to_port: 53, cidr_blocks: [10.0.0.0]}",
+                "is not a CIDR block",
+            ),
+        ] {
+            let (_, _, errors) = plan_components(&group(rule), "default").unwrap();
+            let [err] = &errors[..] else {
+                panic!("expected one error, got {:?}", errors);
+            };
+            assert!(err.to_string().contains("SecurityGroup.web: "), "{}", err);
+            assert!(err.to_string().contains(expected), "{}", err);
+        }
+        // All traffic needs no ports
+        let all_traffic = group("{protocol: all, cidr_blocks: [10.0.0.0/8]}");
+        assert!(
+            plan_components(&all_traffic, "default")
+                .unwrap()
+                .2
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_subnet_needs_a_vpc() {
+        let with_dependency = config(
+            r#"
+  - type: VPC
+    name: main
+    properties:
+      cidr: 10.0.0.0/16
+  - type: Subnet
+    name: private
+    properties:
+      cidr: 10.0.1.0/24
+    dependsOn:
+      - type: VPC
+        name: main
+"#,
+        );
+        let (_, preview, errors) = plan_components(&with_dependency, "default").unwrap();
+        assert_eq!(preview.components[1].info, "10.0.1.0/24");
+        assert!(errors.is_empty());
+
+        let literal = config(
+            "  - type: Subnet\n    name: private\n    properties:\n      cidr: 10.0.1.0/24\n      vpc_id: vpc-123\n",
+        );
+        assert!(plan_components(&literal, "default").unwrap().2.is_empty());
+
+        let orphan = config(
+            "  - type: Subnet\n    name: private\n    properties:\n      cidr: 10.0.1.0/24\n",
+        );
+        let (_, _, errors) = plan_components(&orphan, "default").unwrap();
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("set vpc_id or add the VPC to dependsOn")
+        );
+    }
+
+    #[test]
+    fn test_s3_bucket_names_are_validated() {
+        let valid = config(
+            "  - type: S3Bucket\n    name: assets\n    properties:\n      bucket: shop-assets\n      versioning: true\n",
+        );
+        let (_, preview, errors) = plan_components(&valid, "default").unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(preview.components[0].info, "shop-assets versioned");
+
+        let invalid = config(
+            "  - type: S3Bucket\n    name: assets\n    properties:\n      bucket: Shop_Assets\n",
+        );
+        let (_, _, errors) = plan_components(&invalid, "default").unwrap();
+        let message = errors[0].to_string();
+        assert!(
+            message.contains("invalid bucket name 'Shop_Assets'"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_instance_profiles_need_a_role() {
+        let role = "  - type: IAMRole\n    name: web\n    properties:\n      assume_role_policy:\n        Statement:\n          - Effect: Allow\n            Principal: { Service: ec2.amazonaws.com }\n            Action: sts:AssumeRole\n      managed_policy_arns:\n        - arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore\n";
+        let wrapped = config(&format!(
+            "{}  - type: InstanceProfile\n    name: web\n    dependsOn:\n      - type: IAMRole\n        name: web\n",
+            role
+        ));
+        let (_, preview, errors) = plan_components(&wrapped, "default").unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(preview.components[0].info, "1 managed, 0 inline polic(ies)");
+
+        let roleless = config("  - type: InstanceProfile\n    name: web\n    properties: {}\n");
+        let (_, _, errors) = plan_components(&roleless, "default").unwrap();
+        let message = errors[0].to_string();
+        assert!(
+            message.contains("set role or add the IAMRole to dependsOn"),
+            "{}",
+            message
+        );
+
+        let untrusted = config(
+            "  - type: IAMRole\n    name: web\n    properties:\n      assume_role_policy: '{\"Version\": \"2012-10-17\"}'\n",
+        );
+        let (_, _, errors) = plan_components(&untrusted, "default").unwrap();
+        let message = errors[0].to_string();
+        assert!(
+            message.contains("policy document has no Statement"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn test_elastic_ip_association_targets_are_checked() {
+        let instance = "  - type: EC2Instance\n    name: web\n    properties:\n      instance_type: t2.micro\n      ami: ami-123\n";
+        let associated = config(&format!(
+            "{}  - type: ElasticIP\n    name: web\n    properties:\n      associate_with: ${{EC2Instance.web.id}}\n    dependsOn:\n      - type: EC2Instance\n        name: web\n",
+            instance
+        ));
+        let (_, preview, errors) = plan_components(&associated, "default").unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(
+            preview.components[1].info,
+            "associated with ${EC2Instance.web.id}"
+        );
+
+        let undeclared = config(
+            "  - type: ElasticIP\n    name: web\n    properties:\n      associate_with: ${EC2Instance.other.id}\n",
+        );
+        let err = plan_components(&undeclared, "default").unwrap_err();
+        assert!(err.to_string().contains("EC2Instance.other"), "{}", err);
+
+        let unordered = config(&format!(
+            "{}  - type: ElasticIP\n    name: web\n    properties:\n      associate_with: ${{EC2Instance.web.id}}\n",
+            instance
+        ));
+        let (_, _, errors) = plan_components(&unordered, "default").unwrap();
+        assert!(
+            errors[0].to_string().contains("add it to dependsOn"),
+            "{:?}",
+            errors
+        );
+
+        let literal = config(
+            "  - type: ElasticIP\n    name: web\n    properties:\n      associate_with: web-server\n",
+        );
+        let (_, _, errors) = plan_components(&literal, "default").unwrap();
+        assert!(
+            errors[0].to_string().contains("must be an instance id"),
+            "{:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn test_property_errors_are_collected_across_components() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      ami: ami-123
+  - type: EC2Instance
+    name: api
+    properties:
+      instance_type: 3
+      image_id: ami-123
+  - type: EC2Instance
+    name: ok
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+  - type: VPC
+    name: main
+    properties: {}
+"#,
+        );
+        let (_, preview, errors) = plan_components(&config, "default").unwrap();
+        assert_eq!(
+            errors,
+            vec![
+                PlanError::MissingProperty(
+                    "instance_type".to_string(),
+                    "EC2Instance.web".to_string()
+                ),
+                PlanError::InvalidPropertyType(
+                    "instance_type".to_string(),
+                    "EC2Instance.api".to_string(),
+                    "string".to_string(),
+                    "number".to_string()
+                ),
+                PlanError::MissingProperty("cidr".to_string(), "VPC.main".to_string()),
+            ]
+        );
+        let supported: Vec<bool> = preview.components.iter().map(|c| c.supported).collect();
+        assert_eq!(supported, vec![false, false, true, false]);
+        let computed = preview.components[2].computed.as_ref().unwrap();
+        assert_eq!(computed["instance_id"], "(known after apply)");
+        assert_eq!(computed["instance_type"], "t2.micro");
+        assert!(preview.components[3].computed.is_none());
+    }
+
+    #[test]
+    fn test_ec2_properties_are_checked_like_apply() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      ami: ami-123
+      instance_type: t2.mega
+      min_count: 0
+"#,
+        );
+        let (_, _, errors) = plan_components(&config, "default").unwrap();
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Invalid component: EC2Instance.web: unknown instance_type 't2.mega'",
+                "Invalid component: EC2Instance.web: min_count must be at least 1, got 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_instance_count_is_shown_in_the_plan() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      ami: ami-123
+      instance_type: t2.micro
+      min_count: 10
+      max_count: 10
+  - type: EC2Instance
+    name: workers
+    properties:
+      ami: ami-123
+      instance_type: t2.micro
+      min_count: 2
+      max_count: 5
+  - type: EC2Instance
+    name: single
+    properties:
+      ami: ami-123
+      instance_type: t2.micro
+"#,
+        );
+        let (_, preview, errors) = plan_components(&config, "default").unwrap();
+        assert!(errors.is_empty());
+        let info: Vec<&str> = preview.components.iter().map(|c| c.info.as_str()).collect();
+        assert_eq!(
+            info,
+            vec![
+                "10 x t2.micro ami-123",
+                "2-5 x t2.micro ami-123",
+                "t2.micro ami-123"
+            ]
+        );
+        let computed = preview.components[0].computed.as_ref().unwrap();
+        assert_eq!(computed["instance_ids"], "(known after apply)");
+        assert!(
+            preview.components[2]
+                .computed
+                .as_ref()
+                .unwrap()
+                .get("instance_ids")
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_unresolved_ami_lookup_is_known_after_apply() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami_lookup:
+        owners: [amazon]
+        name_pattern: al2023-ami-*
+  - type: EC2Instance
+    name: broken
+    properties:
+      instance_type: t2.micro
+      ami_lookup:
+        owners: [amazon]
+"#,
+        );
+        let (_, preview, errors) = plan_components(&config, "default").unwrap();
+        let computed = preview.components[0].computed.as_ref().unwrap();
+        assert_eq!(computed["image_id"], "(known after apply)");
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Invalid component: EC2Instance.broken: Options error: ami_lookup: name_pattern is required, e.g. al2023-ami-*-x86_64",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_user_data_file_is_relative_to_the_config() {
+        let mut config = config(
+            r#"
+  - type: EC2Instance
+    name: web
+    properties:
+      user_data_file: scripts/init.sh
+  - type: EC2Instance
+    name: api
+    properties:
+      user_data_file: /opt/init.sh
+"#,
+        );
+        anchor_config_paths(&mut config, "deploy/prod/infra.yaml");
+        let file = |index: usize| config.components[index].get_property_as_string("user_data_file");
+        assert_eq!(file(0).as_deref(), Some("deploy/prod/scripts/init.sh"));
+        assert_eq!(file(1).as_deref(), Some("/opt/init.sh"));
+    }
+
+    #[test]
+    fn test_workspace_flag_is_validated() {
+        assert_eq!(select_workspace(Some("staging")).unwrap(), "staging");
+        assert_eq!(select_workspace(Some(" prod-eu_1 ")).unwrap(), "prod-eu_1");
+        for invalid in ["", "../prod", "a/b", "prod eu"] {
+            assert!(select_workspace(Some(invalid)).is_err(), "{}", invalid);
+        }
+    }
+
+    fn keys(sequence: &[&Component]) -> Vec<String> {
+        sequence.iter().map(|c| c.key()).collect()
+    }
+
+    #[test]
+    fn test_sequence_follows_depends_on() {
+        let config = config(
+            r#"
+  - type: EC2Instance
+    name: app
+    properties: {}
+    dependsOn:
+      - type: EC2Instance
+        name: db
+  - type: EC2Instance
+    name: bastion
+    properties: {}
+  - type: EC2Instance
+    name: db
+    properties: {}
+"#,
+        );
+        let sequence = plan_components_sequence(&config.components).unwrap();
+        assert_eq!(
+            keys(&sequence),
+            vec!["EC2Instance.bastion", "EC2Instance.db", "EC2Instance.app"]
+        );
+    }
+
+    #[test]
+    fn test_target_closure_follows_depends_on() {
+        let config = config(
+            r#"
+  - type: VPC
+    name: main
+  - type: Subnet
+    name: public
+    dependsOn:
+      - type: VPC
+        name: main
+  - type: EC2Instance
+    name: web
+    dependsOn:
+      - type: Subnet
+        name: public
+  - type: EC2Instance
+    name: db
+"#,
+        );
+        let targets = |targets: &[&str]| -> Result<Vec<String>, String> {
+            let targets: Vec<String> = targets.iter().map(|t| t.to_string()).collect();
+            let mut selected = target_closure(&config.components, &targets)?;
+            selected.sort();
+            Ok(selected)
+        };
+        assert_eq!(
+            targets(&["EC2Instance.web"]).unwrap(),
+            vec!["EC2Instance.web", "Subnet.public", "VPC.main"]
+        );
+        assert_eq!(
+            targets(&["EC2Instance.db", "VPC.main"]).unwrap(),
+            vec!["EC2Instance.db", "VPC.main"]
+        );
+        assert_eq!(
+            targets(&["EC2Instance.api"]).unwrap_err(),
+            "Unknown target 'EC2Instance.api', the components are: VPC.main, Subnet.public, EC2Instance.web, EC2Instance.db"
+        );
+
+        let (_, mut preview, _) = plan_components(&config, "default").unwrap();
+        exclude_untargeted(&mut preview, &targets(&["Subnet.public"]).unwrap());
+        let excluded: Vec<&str> = preview
+            .components
+            .iter()
+            .filter(|c| c.excluded)
+            .map(|c| c.name.as_str())
+            .collect();
+        assert_eq!(excluded, vec!["web", "db"]);
+    }
+
+    #[test]
+    fn test_sequence_rejects_cycles_and_unknown_dependencies() {
+        let cyclic = config(
+            r#"
+  - type: EC2Instance
+    name: a
+    properties: {}
+    dependsOn:
+      - type: EC2Instance
+        name: b
+  - type: EC2Instance
+    name: b
+    properties: {}
+    dependsOn:
+      - type: EC2Instance
+        name: a
+"#,
+        );
+        assert!(matches!(
+            plan_components_sequence(&cyclic.components),
+            Err(PlanError::DependencyCycle(keys)) if keys == vec!["EC2Instance.a", "EC2Instance.b"]
+        ));
+
+        let unknown = config(
+            r#"
+  - type: EC2Instance
+    name: a
+    properties: {}
+    dependsOn:
+      - type: EC2Instance
+        name: missing
+"#,
+        );
+        assert!(matches!(
+            plan_components_sequence(&unknown.components),
+            Err(PlanError::InvalidComponent(_))
+        ));
+    }
+
+    #[test]
+    fn test_split_documents_drops_comment_only_documents() {
+        let content = "# shared header\n---\nmetadata: {name: web}\n---\n# empty\n--- # db\nmetadata: {name: db}\n";
+        assert_eq!(
+            split_documents(content),
+            vec!["metadata: {name: web}\n", "metadata: {name: db}\n"]
+        );
+        assert_eq!(split_documents("{\"kind\": \"Infra\"}").len(), 1);
+    }
+
+    #[test]
+    fn test_config_documents_from_directory_and_multi_document_files() {
+        let dir = std::env::temp_dir().join(format!("letus-documents-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let document =
+            |kind: &str, name: &str| format!("kind: {}\nmetadata:\n  name: {}\n", kind, name);
+        let multi = [
+            document("Infra", "web"),
+            document("Component", "pack"),
+            document("Infra", "db"),
+        ];
+        std::fs::write(dir.join("a.yaml"), multi.join("---\n")).unwrap();
+        std::fs::write(dir.join("b.yml"), document("Infra", "cache")).unwrap();
+        std::fs::write(dir.join("notes.txt"), "not a config").unwrap();
+
+        let path = dir.display().to_string();
+        let documents = config_documents(&path).unwrap();
+        let names: Vec<&str> = documents.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["web", "db", "cache"]);
+        assert!(
+            documents[1].source.ends_with("a.yaml#3"),
+            "{}",
+            documents[1].source
+        );
+        assert_eq!(
+            read_config_source(&documents[1].source).unwrap(),
+            document("Infra", "db")
+        );
+
+        std::fs::write(dir.join("c.yaml"), document("Infra", "web")).unwrap();
+        let err = config_documents(&path).unwrap_err();
+        assert!(
+            err.contains("Deployment name 'web' is used by both"),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_select_document_needs_a_deployment_when_there_are_several() {
+        let dir = std::env::temp_dir().join(format!("letus-select-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let document = |name: &str| format!("kind: Infra\nmetadata:\n  name: {}\n", name);
+        let single = dir.join("single.yaml");
+        let multi = dir.join("multi.yaml");
+        std::fs::write(&single, document("web")).unwrap();
+        std::fs::write(&multi, [document("web"), document("db")].join("---\n")).unwrap();
+        let single = single.display().to_string();
+        let multi = multi.display().to_string();
+
+        // A single deployment keeps the state of the workspace
+        let (found, deployment) = select_document(&single, None).unwrap();
+        assert_eq!((found.name.as_str(), deployment), ("web", None));
+        assert!(select_document(&single, Some("db")).is_err());
+
+        let (found, deployment) = select_document(&multi, Some("db")).unwrap();
+        assert_eq!(found.name, "db");
+        assert!(found.source.ends_with("multi.yaml#2"), "{}", found.source);
+        assert_eq!(deployment.as_deref(), Some("db"));
+        let err = select_document(&multi, None).unwrap_err();
+        assert!(err.contains("several deployments (web, db)"), "{}", err);
+        assert!(select_document(&multi, Some("cache")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+use std::io::IsTerminal;
+
+use comfy_table::{Cell, Color, ContentArrangement, Table};
+
+use crate::utils::{OperationType, PlanPreviewDeployment, constants::WIDTH_ENV};
+
+const ELLIPSIS: char = '…';
+const COLUMN_GAP: usize = 2;
+const HEADER: [&str; 5] = ["", "Type", "Name", "Plan", "Info"];
+
+/// How plan previews are rendered: terminal width and whether stdout is an interactive terminal
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub width: Option<usize>, // None means unconstrained
+    pub interactive: bool,    // Colors and box-drawing characters only on a TTY
+}
+
+impl RenderOptions {
+    /// Width from the --width flag, then LETUS_WIDTH, then the terminal itself when there is one
+    pub fn detect(width_flag: Option<usize>) -> Self {
+        let interactive = std::io::stdout().is_terminal();
+        let width = width_flag
+            .or_else(|| {
+                std::env::var(WIDTH_ENV)
+                    .ok()
+                    .and_then(|w| w.trim().parse::<usize>().ok())
+            })
+            .or_else(|| {
+                if interactive {
+                    Table::new().width().map(|w| w as usize)
+                } else {
+                    None
+                }
+            });
+        RenderOptions { width, interactive }
+    }
+}
+
+struct Row {
+    cells: [String; 5],
+    color: Option<Color>,
+}
+
+fn operation_display(operation_type: &OperationType) -> (&'static str, &'static str, Color) {
+    match operation_type {
+        OperationType::Create => ("+", "create", Color::Green),
+        OperationType::Update => ("~", "update", Color::Yellow),
+        OperationType::Delete => ("-", "delete", Color::Red),
+        OperationType::NoOp => ("", "no change", Color::Reset),
+    }
+}
+
+fn build_rows(preview: &PlanPreviewDeployment, interactive: bool) -> Vec<Row> {
+    let mut rows = vec![Row {
+        cells: [
+            String::new(),
+            preview.deployment_type.clone(),
+            preview.deployment_name.clone(),
+            String::new(),
+            String::new(),
+        ],
+        color: None,
+    }];
+
+    for (index, component) in preview.components.iter().enumerate() {
+        let is_last = index == preview.components.len() - 1;
+        let prefix = match (interactive, is_last) {
+            (true, true) => "└─",
+            (true, false) => "├─",
+            (false, true) => "`-",
+            (false, false) => "|-",
+        };
+        let (symbol, text, color) = if component.excluded {
+            ("", "excluded", Color::DarkGrey)
+        } else if component.replace {
+            ("-/+", "replace", Color::Magenta)
+        } else {
+            operation_display(&component.operation_type)
+        };
+        // Unsupported components keep their operation but are flagged with `!` and warning colors
+        let (symbol, color) = if component.supported || component.excluded {
+            (symbol, color)
+        } else {
+            ("!", Color::Yellow)
+        };
+        rows.push(Row {
+            cells: [
+                symbol.to_string(),
+                format!(" {} {}", prefix, component.component_type),
+                match &component.template {
+                    Some(template) => format!("{} (from {})", component.name, template),
+                    None => component.name.clone(),
+                },
+                text.to_string(),
+                component.info.clone(),
+            ],
+            color: Some(color),
+        });
+    }
+    rows
+}
+
+fn display_width(s: &str) -> usize {
+    s.chars().count()
+}
+
+fn truncate(s: &str, max: usize) -> String {
+    if display_width(s) <= max {
+        return s.to_string();
+    }
+    if max == 0 {
+        return String::new();
+    }
+    let mut truncated: String = s.chars().take(max - 1).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+/**
+ * Shrink the Info column so a row fits in `width`.
+ * Operation, type and name are never truncated, even if that means overflowing a very narrow terminal.
+ */
+fn fit_info_column(rows: &mut [Row], width: Option<usize>) {
+    let Some(width) = width else {
+        return;
+    };
+    let fixed: usize = (0..4)
+        .map(|col| {
+            rows.iter()
+                .map(|r| display_width(&r.cells[col]))
+                .chain(std::iter::once(display_width(HEADER[col])))
+                .max()
+                .unwrap_or(0)
+                + COLUMN_GAP
+        })
+        .sum();
+    let available = width.saturating_sub(fixed).max(display_width(HEADER[4]));
+    for row in rows.iter_mut() {
+        row.cells[4] = truncate(&row.cells[4], available);
+    }
+}
+
+/// Aligned plain text without colors or box-drawing characters, for CI logs
+fn render_plain(rows: &[Row]) -> String {
+    let widths: Vec<usize> = (0..5)
+        .map(|col| {
+            rows.iter()
+                .map(|r| display_width(&r.cells[col]))
+                .chain(std::iter::once(display_width(HEADER[col])))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let header = HEADER.map(|h| h.to_string());
+    std::iter::once(&header)
+        .chain(rows.iter().map(|r| &r.cells))
+        .map(|cells| {
+            cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, w)| format!("{}{}", cell, " ".repeat(w - display_width(cell))))
+                .collect::<Vec<_>>()
+                .join(&" ".repeat(COLUMN_GAP))
+                .trim_end()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn render_table(rows: &[Row], width: Option<usize>) -> String {
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::NOTHING);
+    table.set_header(HEADER.to_vec());
+    if let Some(width) = width {
+        table
+            .set_content_arrangement(ContentArrangement::Dynamic)
+            .set_width(width.min(u16::MAX as usize) as u16);
+    }
+    for row in rows {
+        let cells = row.cells.iter().enumerate().map(|(col, text)| {
+            let cell = Cell::new(text);
+            // Symbol and plan columns carry the operation color
+            match (row.color, col) {
+                (Some(color), 0) | (Some(color), 3) => cell.fg(color),
+                _ => cell,
+            }
+        });
+        table.add_row(cells.collect::<Vec<_>>());
+    }
+    table.to_string()
+}
+
+pub fn render_plan_preview(preview: &PlanPreviewDeployment, options: &RenderOptions) -> String {
+    let mut rows = build_rows(preview, options.interactive);
+    fit_info_column(&mut rows, options.width);
+    if options.interactive {
+        render_table(&rows, options.width)
+    } else {
+        render_plain(&rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ComponentPreview;
+
+    fn preview() -> PlanPreviewDeployment {
+        PlanPreviewDeployment {
+            deployment_type: "Infra".to_string(),
+            deployment_name: "shop".to_string(),
+            components: vec![
+                ComponentPreview {
+                    component_type: "EC2Instance".to_string(),
+                    name: "web".to_string(),
+                    operation_type: OperationType::Create,
+                    properties_hash: String::new(),
+                    info: "t2.micro from ami-04c174f38aefd7dc8 in us-west-2 with 2 volumes attached and monitoring enabled".to_string(),
+                    supported: true,
+                    replacement_hash: String::new(),
+                    replace: false,
+                    computed: None,
+                    template: None,
+                    excluded: false,
+                },
+                ComponentPreview {
+                    component_type: "EC2Instance".to_string(),
+                    name: "worker".to_string(),
+                    operation_type: OperationType::Delete,
+                    properties_hash: String::new(),
+                    info: String::new(),
+                    supported: true,
+                    replacement_hash: String::new(),
+                    replace: false,
+                    computed: None,
+                    template: None,
+                    excluded: false,
+                },
+            ],
+        }
+    }
+
+    fn plain(width: Option<usize>) -> String {
+        render_plan_preview(
+            &preview(),
+            &RenderOptions {
+                width,
+                interactive: false,
+            },
+        )
+    }
+
+    #[test]
+    fn test_plain_rendering_at_80_columns() {
+        let expected = [
+            "   Type             Name    Plan    Info",
+            "   Infra            shop",
+            "+   |- EC2Instance  web     create  t2.micro from ami-04c174f38aefd7dc8 in us-w…",
+            "-   `- EC2Instance  worker  delete",
+        ]
+        .join("\n");
+        assert_eq!(plain(Some(80)), expected);
+        assert!(plain(Some(80)).lines().all(|l| display_width(l) <= 80));
+    }
+
+    #[test]
+    fn test_plain_rendering_at_120_columns() {
+        let expected = [
+            "   Type             Name    Plan    Info",
+            "   Infra            shop",
+            "+   |- EC2Instance  web     create  t2.micro from ami-04c174f38aefd7dc8 in us-west-2 with 2 volumes attached and monito…",
+            "-   `- EC2Instance  worker  delete",
+        ]
+        .join("\n");
+        assert_eq!(plain(Some(120)), expected);
+    }
+
+    #[test]
+    fn test_plain_rendering_unconstrained_keeps_everything() {
+        let rendered = plain(None);
+        assert!(rendered.contains("monitoring enabled"));
+        assert!(!rendered.contains(ELLIPSIS));
+        assert!(!rendered.contains('└'));
+    }
+
+    #[test]
+    fn test_unsupported_component_is_flagged() {
+        let mut preview = preview();
+        preview.components[0].supported = false;
+        preview.components[0].info = "t3.nano is not offered in us-west-2d".to_string();
+        let rendered = render_plan_preview(
+            &preview,
+            &RenderOptions {
+                width: None,
+                interactive: false,
+            },
+        );
+        assert_eq!(
+            rendered.lines().nth(2).unwrap(),
+            "!   |- EC2Instance  web     create  t3.nano is not offered in us-west-2d"
+        );
+    }
+
+    #[test]
+    fn test_component_from_template_names_it() {
+        let mut preview = preview();
+        preview.components[0].template = Some("web-server".to_string());
+        preview.components[0].info = String::new();
+        let rendered = render_plan_preview(
+            &preview,
+            &RenderOptions {
+                width: None,
+                interactive: false,
+            },
+        );
+        assert_eq!(
+            rendered.lines().nth(2).unwrap(),
+            "+   |- EC2Instance  web (from web-server)  create"
+        );
+    }
+
+    #[test]
+    fn test_component_excluded_by_target_is_marked() {
+        let mut preview = preview();
+        preview.components[1].excluded = true;
+        let rendered = render_plan_preview(
+            &preview,
+            &RenderOptions {
+                width: None,
+                interactive: false,
+            },
+        );
+        assert_eq!(
+            rendered.lines().nth(3).unwrap(),
+            "    `- EC2Instance  worker  excluded"
+        );
+    }
+
+    #[test]
+    fn test_unchanged_component_has_no_symbol() {
+        let mut preview = preview();
+        preview.components[0].operation_type = OperationType::NoOp;
+        preview.components[0].info = String::new();
+        let rendered = render_plan_preview(
+            &preview,
+            &RenderOptions {
+                width: None,
+                interactive: false,
+            },
+        );
+        assert_eq!(
+            rendered.lines().nth(2).unwrap(),
+            "    |- EC2Instance  web     no change"
+        );
+    }
+
+    #[test]
+    fn test_replacement_is_flagged() {
+        let mut preview = preview();
+        preview.components[0].operation_type = OperationType::Update;
+        preview.components[0].replace = true;
+        let rendered = render_plan_preview(
+            &preview,
+            &RenderOptions {
+                width: None,
+                interactive: false,
+            },
+        );
+        let line = rendered.lines().nth(2).unwrap();
+        assert!(line.starts_with("-/+"), "{}", line);
+        assert!(line.contains("EC2Instance  web     replace"), "{}", line);
+    }
+
+    #[test]
+    fn test_name_and_operation_are_never_truncated() {
+        let rendered = plain(Some(20));
+        assert!(rendered.contains("worker"));
+        assert!(rendered.contains("create"));
+        assert!(rendered.contains("EC2Instance"));
+    }
+}
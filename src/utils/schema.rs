@@ -0,0 +1,2149 @@
+use crate::{
+    models::{Component, PlanError},
+    utils::{
+        constants::SENSITIVE_VALUE,
+        interpolation,
+        timeouts::{ResourceTimeouts, TIMEOUTS_PROPERTY},
+    },
+};
+
+/// Value type of a component property, decides the placeholder used in generated examples
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeKind {
+    String,
+    Integer,
+    Boolean,
+    StringList,
+    StringMap,
+    ObjectList, // Fields of each entry are described as `<attribute>.<field>`
+    Document,   // Free-form map such as an IAM policy, or the same as JSON text
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttributeSchema {
+    pub name: &'static str, // Dotted path for attributes of nested blocks, e.g. `placement.tenancy`
+    pub kind: AttributeKind,
+    pub required: bool,
+    pub common: bool, // Optional attributes worth showing in a minimal example
+    pub placeholder: Option<&'static str>, // Realistic value, falls back to one derived from the kind
+    pub default: Option<&'static str>,
+    pub force_new: bool, // Changing it replaces the resource
+    pub sensitive: bool,
+    pub aliases: &'static [&'static str], // Other accepted names, e.g. `image_id` for `ami`
+    pub conflicts_with: &'static [&'static str], // Properties that cannot be set alongside
+    pub description: &'static str,
+}
+
+/// Value a component records once applied, referenced as `${<type>.<name>.<output>}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputSchema {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub computed: bool, // Assigned by AWS and free to change on its own, drift leaves it out
+}
+
+impl AttributeSchema {
+    /// Nested block the attribute belongs to, None for top-level properties
+    pub fn block(&self) -> Option<&'static str> {
+        self.name.rsplit_once('.').map(|(block, _)| block)
+    }
+}
+
+impl AttributeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttributeKind::String => "string",
+            AttributeKind::Integer => "integer",
+            AttributeKind::Boolean => "boolean",
+            AttributeKind::StringList => "list of strings",
+            AttributeKind::StringMap => "map of strings",
+            AttributeKind::ObjectList => "list of objects",
+            AttributeKind::Document => "map or JSON string",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceSchema {
+    pub component_type: &'static str,
+    pub version: u32,          // Bumped whenever an attribute changes incompatibly
+    pub supports_update: bool, // False when changed properties can only be applied by recreating
+    pub supports_destroy: bool,
+    pub attributes: &'static [AttributeSchema],
+    pub outputs: &'static [OutputSchema], // Names are stable, configs reference them
+    pub passthrough: &'static [&'static str], // Properties sent to the API as given, without a typed attribute
+}
+
+impl ResourceSchema {
+    pub fn attribute(&self, path: &str) -> Option<&'static AttributeSchema> {
+        self.attributes.iter().find(|a| a.name == path)
+    }
+
+    /// Top level property keys a component of this type accepts
+    pub fn property_keys(&self) -> impl Iterator<Item = &'static str> {
+        self.attributes
+            .iter()
+            .flat_map(|a| std::iter::once(a.name).chain(a.aliases.iter().copied()))
+            .map(|name| name.split(['.', '[']).next().unwrap_or(name))
+            .chain(self.passthrough.iter().copied())
+            .chain(std::iter::once(TIMEOUTS_PROPERTY))
+    }
+}
+
+pub const EC2_INSTANCE: ResourceSchema = ResourceSchema {
+    component_type: "EC2Instance",
+    version: 1,
+    supports_update: true,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "instance_type",
+            kind: AttributeKind::String,
+            required: true,
+            common: false,
+            placeholder: Some("t2.micro"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Instance type to launch, e.g. t3.micro.",
+        },
+        AttributeSchema {
+            name: "ami",
+            kind: AttributeKind::String,
+            required: true,
+            common: false,
+            placeholder: Some("ami-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &["image_id"],
+            conflicts_with: &["ami_lookup"],
+            description: "AMI the instance boots from, `image_id` is accepted as an alias. Not needed when ami_lookup is set.",
+        },
+        AttributeSchema {
+            name: "key_name",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("my-key-pair"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the EC2 key pair allowed to SSH into the instance, taken from a KeyPair in dependsOn when unset.",
+        },
+        AttributeSchema {
+            name: "security_group_ids",
+            kind: AttributeKind::StringList,
+            required: false,
+            common: true,
+            placeholder: Some("sg-0123456789abcdef0"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Security groups attached to the primary network interface.",
+        },
+        AttributeSchema {
+            name: "subnet_id",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("subnet-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Subnet to launch in, taken from a Subnet in dependsOn or the default VPC when unset.",
+        },
+        AttributeSchema {
+            name: "network_interfaces",
+            kind: AttributeKind::ObjectList,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &["subnet_id", "security_group_ids"],
+            description: "Network interfaces to attach, each with its own subnet_id and groups.",
+        },
+        AttributeSchema {
+            name: "user_data",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: true,
+            aliases: &[],
+            conflicts_with: &["user_data_file"],
+            description: "Script or cloud-init config run on first boot, base64 encoded unless it already is.",
+        },
+        AttributeSchema {
+            name: "user_data_file",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("./init.sh"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "File with the user data, relative to the directory of the config file.",
+        },
+        AttributeSchema {
+            name: "user_data_base64",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "The user data is already base64 encoded and is passed on as is.",
+        },
+        AttributeSchema {
+            name: "min_count",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("1"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Minimum number of instances to launch.",
+        },
+        AttributeSchema {
+            name: "max_count",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("1"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Maximum number of instances to launch.",
+        },
+        AttributeSchema {
+            name: "ebs_optimized",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Launch the instance EBS-optimized.",
+        },
+        AttributeSchema {
+            name: "wait_for_status_checks",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Wait for the system and instance status checks to pass after launch.",
+        },
+        AttributeSchema {
+            name: "status_checks_timeout",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("600"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Seconds to wait for the status checks.",
+        },
+        AttributeSchema {
+            name: "spot_fulfillment_timeout",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("300"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Seconds to wait for Spot capacity and the Spot request to be fulfilled.",
+        },
+        AttributeSchema {
+            name: "public_ip_timeout",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("120"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Seconds to wait for the public IP a network interface asks for with associate_public_ip_address once the instance is running.",
+        },
+        AttributeSchema {
+            name: "monitoring.enabled",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Enable detailed CloudWatch monitoring.",
+        },
+        AttributeSchema {
+            name: "placement.availability_zone",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("us-west-2a"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Availability zone to launch in.",
+        },
+        AttributeSchema {
+            name: "placement.tenancy",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("default"),
+            default: Some("default"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "default, dedicated or host.",
+        },
+        AttributeSchema {
+            name: "metadata_options.http_tokens",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("required"),
+            default: Some("optional"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "`required` enforces IMDSv2.",
+        },
+        AttributeSchema {
+            name: "ami_lookup.owners",
+            kind: AttributeKind::StringList,
+            required: false,
+            common: false,
+            placeholder: Some("amazon"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Owners of the AMI, account ids or aliases such as amazon.",
+        },
+        AttributeSchema {
+            name: "ami_lookup.name_pattern",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("al2023-ami-*-x86_64"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "AMI name to look up, `*` matches any characters.",
+        },
+        AttributeSchema {
+            name: "ami_lookup.architecture",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("x86_64"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Only match AMIs of this architecture, e.g. x86_64 or arm64.",
+        },
+        AttributeSchema {
+            name: "ami_lookup.most_recent",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Take the newest of several matches and look it up again on every plan and apply, replacing the instance when a newer AMI is published.",
+        },
+        AttributeSchema {
+            name: "launch_template.id",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("lt-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &["launch_template.name"],
+            description: "Id of the launch template to start from, properties set here override it.",
+        },
+        AttributeSchema {
+            name: "launch_template.name",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("web-template"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the launch template to start from.",
+        },
+        AttributeSchema {
+            name: "launch_template.version",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("$Latest"),
+            default: Some("$Default"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Version of the launch template, a quoted number such as \"3\", $Latest or $Default.",
+        },
+        AttributeSchema {
+            name: "instance_market_options.market_type",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("spot"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "spot to launch a Spot Instance, or capacity-block.",
+        },
+        AttributeSchema {
+            name: "instance_market_options.spot_options.max_price",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("0.05"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Highest hourly price in USD as a quoted string, the On-Demand price when unset.",
+        },
+        AttributeSchema {
+            name: "instance_market_options.spot_options.spot_instance_type",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("one-time"),
+            default: Some("one-time"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "one-time, or persistent to have the request reopen after an interruption.",
+        },
+        AttributeSchema {
+            name: "instance_market_options.spot_options.instance_interruption_behavior",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("terminate"),
+            default: Some("terminate"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "terminate, stop or hibernate on interruption, one-time requests only allow terminate.",
+        },
+        AttributeSchema {
+            name: "capacity_reservation_specification.capacity_reservation_preference",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("open"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[
+                "capacity_reservation_specification.capacity_reservation_id",
+                "capacity_reservation_specification.capacity_reservation_resource_group_arn",
+            ],
+            description: "open, none or capacity-reservations-only.",
+        },
+        AttributeSchema {
+            name: "capacity_reservation_specification.capacity_reservation_id",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("cr-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[
+                "capacity_reservation_specification.capacity_reservation_resource_group_arn",
+            ],
+            description: "Capacity Reservation to launch into.",
+        },
+        AttributeSchema {
+            name: "capacity_reservation_specification.capacity_reservation_resource_group_arn",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Resource group of Capacity Reservations to launch into.",
+        },
+        AttributeSchema {
+            name: "maintenance_options.auto_recovery",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("disabled"),
+            default: Some("default"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "default recovers the instance after a system status check failure, disabled turns that off.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Instance id, the first instance when several were launched.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "public_ip",
+            description: "Public IPv4 address, empty when the instance has none.",
+            computed: true,
+        },
+        OutputSchema {
+            name: "public_dns",
+            description: "Public DNS name, empty when the instance has no public address.",
+            computed: true,
+        },
+        OutputSchema {
+            name: "private_ip",
+            description: "Private IPv4 address.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "availability_zone",
+            description: "Availability Zone the instance runs in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "subnet_id",
+            description: "Subnet the instance was launched in, also when AWS picked it.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "image_id",
+            description: "AMI the instance was launched from.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "spot_request_id",
+            description: "Spot request of a Spot Instance, empty otherwise.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "instance_ids",
+            description: "Ids of every instance launched by min_count/max_count.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "ami_lookup",
+            description: "Filters of ami_lookup the image was found with, when it was looked up.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "status_checks_waited",
+            description: "Whether the last apply waited for the status checks to pass.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "status_checks_seconds",
+            description: "Seconds the status checks took to pass, empty when they were not waited on.",
+            computed: false,
+        },
+    ],
+    passthrough: &[
+        "block_device_mappings",
+        "client_token",
+        "cpu_options",
+        "credit_specification",
+        "disable_api_termination",
+        "enclave_options",
+        "hibernation_options",
+        "iam_instance_profile",
+        "instance_initiated_shutdown_behavior",
+        "private_dns_name_options",
+        "private_ip_address",
+        "security_groups",
+        "tag_specifications",
+    ],
+};
+
+pub const VPC: ResourceSchema = ResourceSchema {
+    component_type: "VPC",
+    version: 1,
+    supports_update: false,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "cidr",
+            kind: AttributeKind::String,
+            required: true,
+            common: false,
+            placeholder: Some("10.0.0.0/16"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "IPv4 address block of the VPC, between /16 and /28.",
+        },
+        AttributeSchema {
+            name: "enable_dns_support",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("true"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Resolve DNS through the Amazon provided DNS server.",
+        },
+        AttributeSchema {
+            name: "enable_dns_hostnames",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Give instances with a public IP a public DNS hostname.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the VPC, e.g. `Name: main`.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "VPC id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "cidr",
+            description: "IPv4 address block.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const SECURITY_GROUP: ResourceSchema = ResourceSchema {
+    component_type: "SecurityGroup",
+    version: 1,
+    supports_update: false,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "description",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("Web servers"),
+            default: Some("Managed by letusinfra"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Human readable description of the group.",
+        },
+        AttributeSchema {
+            name: "group_name",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the group, defaults to the component name.",
+        },
+        AttributeSchema {
+            name: "vpc_id",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("vpc-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "VPC the group belongs to, the default VPC is used when unset.",
+        },
+        AttributeSchema {
+            name: "ingress",
+            kind: AttributeKind::ObjectList,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Inbound rules, each with protocol, ports and cidr_blocks.",
+        },
+        AttributeSchema {
+            name: "ingress.protocol",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("tcp"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "tcp, udp, icmp or all.",
+        },
+        AttributeSchema {
+            name: "ingress.from_port",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "First port of the range, 0 to 65535 (ICMP type for icmp).",
+        },
+        AttributeSchema {
+            name: "ingress.to_port",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Last port of the range, not below from_port (ICMP code for icmp).",
+        },
+        AttributeSchema {
+            name: "ingress.cidr_blocks",
+            kind: AttributeKind::StringList,
+            required: false,
+            common: false,
+            placeholder: Some("0.0.0.0/0"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "IPv4 ranges the rule applies to.",
+        },
+        AttributeSchema {
+            name: "egress",
+            kind: AttributeKind::ObjectList,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Outbound rules, each with protocol, ports and cidr_blocks.",
+        },
+        AttributeSchema {
+            name: "egress.protocol",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("tcp"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "tcp, udp, icmp or all.",
+        },
+        AttributeSchema {
+            name: "egress.from_port",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "First port of the range, 0 to 65535 (ICMP type for icmp).",
+        },
+        AttributeSchema {
+            name: "egress.to_port",
+            kind: AttributeKind::Integer,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Last port of the range, not below from_port (ICMP code for icmp).",
+        },
+        AttributeSchema {
+            name: "egress.cidr_blocks",
+            kind: AttributeKind::StringList,
+            required: false,
+            common: false,
+            placeholder: Some("0.0.0.0/0"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "IPv4 ranges the rule applies to.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the group, e.g. `Name: web`.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Security group id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "vpc_id",
+            description: "VPC of the group.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const SUBNET: ResourceSchema = ResourceSchema {
+    component_type: "Subnet",
+    version: 1,
+    supports_update: false,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "cidr",
+            kind: AttributeKind::String,
+            required: true,
+            common: false,
+            placeholder: Some("10.0.1.0/24"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "IPv4 address block of the subnet, inside the VPC's block.",
+        },
+        AttributeSchema {
+            name: "vpc_id",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("vpc-0123456789abcdef0"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "VPC to create the subnet in, taken from a VPC in dependsOn when unset.",
+        },
+        AttributeSchema {
+            name: "availability_zone",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("us-west-2a"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Availability zone of the subnet, AWS picks one when unset.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the subnet, e.g. `Name: private-a`.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Subnet id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "vpc_id",
+            description: "VPC of the subnet.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "cidr",
+            description: "IPv4 address block.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "availability_zone",
+            description: "Availability Zone of the subnet.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const KEY_PAIR: ResourceSchema = ResourceSchema {
+    component_type: "KeyPair",
+    version: 1,
+    supports_update: false,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "key_name",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("deploy"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the key pair in EC2, defaults to the component name.",
+        },
+        AttributeSchema {
+            name: "public_key",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExampleKey ops@example"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &["generate"],
+            description: "OpenSSH public key to import.",
+        },
+        AttributeSchema {
+            name: "generate",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Have AWS generate the key pair, the private key is written to private_key_file.",
+        },
+        AttributeSchema {
+            name: "key_type",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("ed25519"),
+            default: Some("rsa"),
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "rsa or ed25519, type of a generated key.",
+        },
+        AttributeSchema {
+            name: "private_key_file",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("./deploy.pem"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "File the generated private key is written to with 0600 permissions, relative to the directory of the config file. Defaults to <key_name>.pem.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the key pair.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Key pair id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "key_name",
+            description: "Name instances refer to the key pair by.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "fingerprint",
+            description: "Fingerprint of the public key.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "key_type",
+            description: "rsa or ed25519.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "private_key_file",
+            description: "Where the generated private key was written, when it was generated.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const ELASTIC_IP: ResourceSchema = ResourceSchema {
+    component_type: "ElasticIP",
+    version: 1,
+    supports_update: true,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "associate_with",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("i-0123456789abcdef0"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Instance the address is associated with once it is running, an instance id or a reference to an EC2Instance in dependsOn.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the address when it is allocated.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Allocation id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "allocation_id",
+            description: "Allocation id.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "public_ip",
+            description: "The address.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "instance_id",
+            description: "Instance the address is associated with.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "association_id",
+            description: "Id of the association with the instance.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const S3_BUCKET: ResourceSchema = ResourceSchema {
+    component_type: "S3Bucket",
+    version: 1,
+    supports_update: true,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "bucket",
+            kind: AttributeKind::String,
+            required: true,
+            common: false,
+            placeholder: Some("my-app-assets-example"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Globally unique bucket name, checked against the S3 naming rules at plan time.",
+        },
+        AttributeSchema {
+            name: "versioning",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Keep every version of an object. Turning it off again suspends versioning.",
+        },
+        AttributeSchema {
+            name: "public_access_block.block_public_acls",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("true"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Reject requests that add public ACLs.",
+        },
+        AttributeSchema {
+            name: "public_access_block.ignore_public_acls",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("true"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Ignore public ACLs on the bucket and its objects.",
+        },
+        AttributeSchema {
+            name: "public_access_block.block_public_policy",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("true"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Reject bucket policies that grant public access.",
+        },
+        AttributeSchema {
+            name: "public_access_block.restrict_public_buckets",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("true"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Only allow AWS services and the bucket owner's account into a bucket with a public policy.",
+        },
+        AttributeSchema {
+            name: "force_destroy",
+            kind: AttributeKind::Boolean,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: Some("false"),
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Delete every object version when the bucket is destroyed, otherwise only an empty bucket can be destroyed.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the bucket.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Bucket name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "bucket",
+            description: "Bucket name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "arn",
+            description: "ARN of the bucket.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const IAM_ROLE: ResourceSchema = ResourceSchema {
+    component_type: "IAMRole",
+    version: 1,
+    supports_update: true,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "role_name",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("web-server"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the role, defaults to the component name.",
+        },
+        AttributeSchema {
+            name: "assume_role_policy",
+            kind: AttributeKind::Document,
+            required: true,
+            common: false,
+            placeholder: Some(
+                "\n        Version: \"2012-10-17\"\n        Statement:\n          - Effect: Allow\n            Principal: { Service: ec2.amazonaws.com }\n            Action: sts:AssumeRole",
+            ),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Trust policy saying who may assume the role, written as YAML and sent to IAM as JSON.",
+        },
+        AttributeSchema {
+            name: "description",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Description of the role.",
+        },
+        AttributeSchema {
+            name: "managed_policy_arns",
+            kind: AttributeKind::StringList,
+            required: false,
+            common: true,
+            placeholder: Some("arn:aws:iam::aws:policy/AmazonSSMManagedInstanceCore"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Managed policies attached to the role, others attached outside the config are detached.",
+        },
+        AttributeSchema {
+            name: "inline_policies",
+            kind: AttributeKind::Document,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Inline policies of the role by name, each a policy document written as YAML.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the role.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Role name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "arn",
+            description: "ARN of the role.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "role_name",
+            description: "Role name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "role_id",
+            description: "Unique id IAM gave the role.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+pub const INSTANCE_PROFILE: ResourceSchema = ResourceSchema {
+    component_type: "InstanceProfile",
+    version: 1,
+    supports_update: true,
+    supports_destroy: true,
+    attributes: &[
+        AttributeSchema {
+            name: "instance_profile_name",
+            kind: AttributeKind::String,
+            required: false,
+            common: false,
+            placeholder: Some("web-server"),
+            default: None,
+            force_new: true,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Name of the instance profile, defaults to the component name.",
+        },
+        AttributeSchema {
+            name: "role",
+            kind: AttributeKind::String,
+            required: false,
+            common: true,
+            placeholder: Some("web-server"),
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Role the instance profile passes to instances, taken from an IAMRole in dependsOn when unset.",
+        },
+        AttributeSchema {
+            name: "tags",
+            kind: AttributeKind::StringMap,
+            required: false,
+            common: false,
+            placeholder: None,
+            default: None,
+            force_new: false,
+            sensitive: false,
+            aliases: &[],
+            conflicts_with: &[],
+            description: "Tags applied to the instance profile.",
+        },
+    ],
+    outputs: &[
+        OutputSchema {
+            name: "id",
+            description: "Instance profile name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "region",
+            description: "Region the resource was created in.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "arn",
+            description: "ARN of the instance profile.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "instance_profile_name",
+            description: "Instance profile name.",
+            computed: false,
+        },
+        OutputSchema {
+            name: "role",
+            description: "Name of the role in the profile.",
+            computed: false,
+        },
+    ],
+    passthrough: &[],
+};
+
+/// Every component type the planner understands
+pub const SCHEMAS: &[ResourceSchema] = &[
+    EC2_INSTANCE,
+    VPC,
+    SECURITY_GROUP,
+    SUBNET,
+    KEY_PAIR,
+    ELASTIC_IP,
+    S3_BUCKET,
+    IAM_ROLE,
+    INSTANCE_PROFILE,
+];
+
+pub fn schema_for(component_type: &str) -> Option<&'static ResourceSchema> {
+    SCHEMAS.iter().find(|s| s.component_type == component_type)
+}
+
+/// Whether an output of the type is assigned by AWS and may change without any change to the config
+pub fn is_computed(component_type: &str, output: &str) -> bool {
+    schema_for(component_type)
+        .and_then(|schema| schema.outputs.iter().find(|o| o.name == output))
+        .is_some_and(|o| o.computed)
+}
+
+/// Levenshtein distance, used to suggest near matches for mistyped types and property paths
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(!ca.eq_ignore_ascii_case(cb));
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Candidates within a third of the input's length, closest first
+pub fn suggest<'a>(input: &str, candidates: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let max_distance = (input.chars().count() / 3).max(2);
+    let mut scored: Vec<(usize, &str)> = candidates
+        .map(|c| (edit_distance(input, c), c))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    scored.sort();
+    scored.into_iter().map(|(_, c)| c).collect()
+}
+
+fn placeholder(attribute: &AttributeSchema) -> String {
+    let value = match (attribute.placeholder, attribute.kind) {
+        (_, AttributeKind::Boolean) => return "false".to_string(),
+        (Some(value), AttributeKind::Integer) => return value.to_string(),
+        (None, AttributeKind::Integer) => return "1".to_string(),
+        (Some(value), _) => value.to_string(),
+        (None, _) => format!("<{}>", attribute.name),
+    };
+    match attribute.kind {
+        AttributeKind::StringList => format!("\n        - {}", value),
+        _ => value,
+    }
+}
+
+/**
+ * Minimal YAML component block for a type: every required attribute plus the common optional ones,
+ * in schema order so the output is deterministic.
+ */
+pub fn example_component(schema: &ResourceSchema, name: &str) -> String {
+    let mut example = format!(
+        "  - type: {}\n    name: {}\n    properties:\n",
+        schema.component_type, name
+    );
+    for attribute in schema
+        .attributes
+        .iter()
+        .filter(|a| (a.required || a.common) && a.block().is_none())
+    {
+        let value = placeholder(attribute);
+        let separator = if value.starts_with('\n') { "" } else { " " };
+        example.push_str(&format!(
+            "      {}:{}{}\n",
+            attribute.name, separator, value
+        ));
+    }
+    example
+}
+
+/// Hint appended to MissingProperty errors, `component` is the `<type>.<name>` key
+pub fn missing_property_hint(component: &str) -> String {
+    let (component_type, name) = component.split_once('.').unwrap_or((component, "example"));
+    match schema_for(component_type) {
+        Some(schema) => format!(
+            "; a minimal {} looks like:\n{}",
+            schema.component_type,
+            example_component(schema, name)
+        ),
+        None => String::new(),
+    }
+}
+
+/// Hint for a component type the planner does not know, listing an example of every supported type
+pub fn unknown_type_hint(component_type: &str, name: &str) -> String {
+    let mut hint = format!(
+        "unsupported component type '{}' for '{}', supported types are: {}",
+        component_type,
+        name,
+        SCHEMAS
+            .iter()
+            .map(|s| s.component_type)
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for schema in SCHEMAS {
+        hint.push_str(&format!(
+            "\na minimal {} looks like:\n{}",
+            schema.component_type,
+            example_component(schema, name)
+        ));
+    }
+    hint
+}
+
+/// Values of an attribute path, one per entry when the path goes through an ObjectList
+fn values_at<'a>(properties: &'a serde_yaml::Value, path: &str) -> Vec<&'a serde_yaml::Value> {
+    let Some((block, field)) = path.split_once('.') else {
+        return properties.get(path).into_iter().collect();
+    };
+    match properties.get(block) {
+        Some(serde_yaml::Value::Sequence(entries)) => {
+            entries.iter().flat_map(|e| values_at(e, field)).collect()
+        }
+        Some(nested) => values_at(nested, field),
+        None => Vec::new(),
+    }
+}
+
+/// Replace the values of an attribute path in place, through every entry of an ObjectList
+fn mask_at(properties: &mut serde_yaml::Value, path: &str) {
+    let Some((block, field)) = path.split_once('.') else {
+        if let Some(value) = properties.get_mut(path) {
+            *value = serde_yaml::Value::String(SENSITIVE_VALUE.to_string());
+        }
+        return;
+    };
+    match properties.get_mut(block) {
+        Some(serde_yaml::Value::Sequence(entries)) => {
+            entries.iter_mut().for_each(|e| mask_at(e, field));
+        }
+        Some(nested) => mask_at(nested, field),
+        None => {}
+    }
+}
+
+/// Values of the attributes that force a new resource when changed, keyed by attribute path
+pub fn force_new_properties(component: &Component) -> serde_yaml::Value {
+    let mut found = serde_yaml::Mapping::new();
+    let Some(schema) = schema_for(&component.component_type) else {
+        return serde_yaml::Value::Mapping(found);
+    };
+    for attribute in schema.attributes.iter().filter(|a| a.force_new) {
+        let names = std::iter::once(attribute.name).chain(attribute.aliases.iter().copied());
+        for name in names {
+            let values = values_at(&component.properties, name);
+            if !values.is_empty() {
+                let values = values.into_iter().cloned().collect();
+                found.insert(name.into(), serde_yaml::Value::Sequence(values));
+            }
+        }
+    }
+    serde_yaml::Value::Mapping(found)
+}
+
+/// Attributes of a type the schema flags as sensitive, aliases included
+pub fn flagged_sensitive(component_type: &str) -> Vec<String> {
+    schema_for(component_type)
+        .into_iter()
+        .flat_map(|schema| schema.attributes.iter().filter(|a| a.sensitive))
+        .flat_map(|a| std::iter::once(a.name).chain(a.aliases.iter().copied()))
+        .map(|p| p.to_string())
+        .collect()
+}
+
+/// Property paths to mask: attributes the schema flags as sensitive, and the component's own list
+pub fn sensitive_paths(component: &Component) -> Vec<String> {
+    let mut paths = flagged_sensitive(&component.component_type);
+    for path in &component.sensitive {
+        if !paths.contains(path) {
+            paths.push(path.clone());
+        }
+    }
+    paths
+}
+
+/// Properties of the component with every sensitive value replaced by `(sensitive)`
+pub fn mask_sensitive(component: &Component, properties: &serde_yaml::Value) -> serde_yaml::Value {
+    mask_paths(properties, &sensitive_paths(component))
+}
+
+/// `value` with the values at every one of `paths` replaced by `(sensitive)`
+pub fn mask_paths(value: &serde_yaml::Value, paths: &[String]) -> serde_yaml::Value {
+    let mut masked = value.clone();
+    for path in paths {
+        mask_at(&mut masked, path);
+    }
+    masked
+}
+
+/// Literal sensitive values of every component and the values of the environment variables
+/// they reference, for the redactor to mask wherever they show up
+pub fn sensitive_values(components: &[Component]) -> Vec<String> {
+    let mut found = Vec::new();
+    for component in components {
+        for path in sensitive_paths(component) {
+            let values = values_at(&component.properties, &path);
+            for value in values.into_iter().filter_map(|v| v.as_str()) {
+                if !value.is_empty() && !value.contains("${") {
+                    found.push(value.to_string());
+                }
+            }
+        }
+    }
+    // Whatever comes from the environment is treated as a secret
+    found.extend(interpolation::env_values(components));
+    found
+}
+
+pub(crate) fn kind_of(value: &serde_yaml::Value) -> &'static str {
+    match value {
+        serde_yaml::Value::Null => "null",
+        serde_yaml::Value::Bool(_) => "boolean",
+        serde_yaml::Value::Number(_) => "number",
+        serde_yaml::Value::String(_) => "string",
+        serde_yaml::Value::Sequence(_) => "list",
+        serde_yaml::Value::Mapping(_) => "map",
+        serde_yaml::Value::Tagged(_) => "tagged value",
+    }
+}
+
+/// Whether `value` fits `kind`, references are only known at apply time and always fit
+fn matches_kind(value: &serde_yaml::Value, kind: AttributeKind) -> bool {
+    if value.as_str().is_some_and(|s| s.contains("${")) {
+        return true;
+    }
+    match kind {
+        AttributeKind::String => value.is_string(),
+        // `"100"` is accepted where 100 is, the same way plan hashes them
+        AttributeKind::Integer => {
+            value.is_i64() || value.as_str().is_some_and(|s| s.parse::<i64>().is_ok())
+        }
+        AttributeKind::Boolean => value.is_bool(),
+        AttributeKind::StringList => value
+            .as_sequence()
+            .is_some_and(|items| items.iter().all(|i| i.is_string())),
+        AttributeKind::StringMap => value
+            .as_mapping()
+            .is_some_and(|map| map.values().all(|v| v.is_string())),
+        AttributeKind::ObjectList => value
+            .as_sequence()
+            .is_some_and(|items| items.iter().all(|i| i.is_mapping())),
+        AttributeKind::Document => value.is_mapping() || value.is_string(),
+    }
+}
+
+/**
+ * Check a component's properties against the schema of its type: required properties (or one of
+ * their aliases, or a property they conflict with) are set, set properties have the declared kind
+ * and no conflicting properties are set together. Returns every problem found, and None when the
+ * type has no schema.
+ */
+pub fn validate_component(component: &Component) -> Option<Vec<PlanError>> {
+    let schema = schema_for(&component.component_type)?;
+    let properties = &component.properties;
+    let mut errors = Vec::new();
+    for attribute in schema.attributes {
+        let names = std::iter::once(attribute.name).chain(attribute.aliases.iter().copied());
+        let set: Vec<&str> = names
+            .filter(|n| !values_at(properties, n).is_empty())
+            .collect();
+        if set.is_empty() {
+            // A required property can be left out for the alternative it conflicts with
+            let alternative = attribute
+                .conflicts_with
+                .iter()
+                .any(|c| !values_at(properties, c).is_empty());
+            if attribute.required && attribute.block().is_none() && !alternative {
+                errors.push(PlanError::MissingProperty(
+                    attribute.name.to_string(),
+                    component.key(),
+                ));
+            }
+            continue;
+        }
+        if let [first, second, ..] = set.as_slice() {
+            errors.push(PlanError::InvalidComponent(format!(
+                "{}: '{}' and '{}' are the same property, set only one of them",
+                component.key(),
+                first,
+                second
+            )));
+        }
+        for name in &set {
+            if let Some(value) = values_at(properties, name)
+                .into_iter()
+                .find(|v| !matches_kind(v, attribute.kind))
+            {
+                errors.push(PlanError::InvalidPropertyType(
+                    name.to_string(),
+                    component.key(),
+                    attribute.kind.as_str().to_string(),
+                    kind_of(value).to_string(),
+                ));
+            }
+        }
+        for conflict in attribute.conflicts_with {
+            if !values_at(properties, conflict).is_empty() {
+                errors.push(PlanError::InvalidComponent(format!(
+                    "{}: '{}' conflicts with '{}', set only one of them",
+                    component.key(),
+                    attribute.name,
+                    conflict
+                )));
+            }
+        }
+    }
+    // Checked after merge keys are expanded, so a typo in a shared block fails every component using it
+    for key in properties.as_mapping().into_iter().flat_map(|m| m.keys()) {
+        let Some(key) = key.as_str() else {
+            continue;
+        };
+        if schema.property_keys().any(|known| known == key) {
+            continue;
+        }
+        let hint = match suggest(key, schema.property_keys()).first() {
+            Some(suggestion) => format!(", did you mean '{}'?", suggestion),
+            None => String::new(),
+        };
+        errors.push(PlanError::InvalidComponent(format!(
+            "{}: unknown property '{}'{}",
+            component.key(),
+            key,
+            hint
+        )));
+    }
+    // Every type takes the same timeouts block, bad durations are caught before anything waits
+    if let Err(err) = ResourceTimeouts::from_properties(properties) {
+        errors.push(PlanError::InvalidComponent(format!(
+            "{}: {}",
+            component.key(),
+            err
+        )));
+    }
+    Some(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InfraConfig;
+
+    const EC2_GOLDEN: &str = "  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami: ami-0123456789abcdef0
+      key_name: my-key-pair
+      security_group_ids:
+        - sg-0123456789abcdef0
+";
+
+    #[test]
+    fn test_ec2_example_golden() {
+        assert_eq!(example_component(&EC2_INSTANCE, "web"), EC2_GOLDEN);
+    }
+
+    #[test]
+    fn test_examples_are_valid_configs() {
+        // Every generated example must parse and plan, so examples follow schema changes
+        for schema in SCHEMAS {
+            let yaml = format!(
+                "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: sample\ncomponents:\n{}",
+                example_component(schema, "web")
+            );
+            let config = InfraConfig::from_yaml(&yaml).unwrap();
+            let component = &config.components[0];
+            for attribute in schema.attributes.iter().filter(|a| a.required) {
+                assert!(
+                    component.get_property(attribute.name).is_some(),
+                    "{} example misses {}",
+                    schema.component_type,
+                    attribute.name
+                );
+            }
+            assert_eq!(validate_component(component), Some(vec![]));
+            let (_, _, errors) = crate::utils::plan_components(&config, "default").unwrap();
+            assert!(errors.is_empty(), "{:?}", errors);
+        }
+    }
+
+    fn component(yaml: &str) -> Component {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_validate_component() {
+        let missing =
+            component("type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro}\n");
+        assert_eq!(
+            validate_component(&missing),
+            Some(vec![PlanError::MissingProperty(
+                "ami".to_string(),
+                "EC2Instance.web".to_string()
+            )])
+        );
+
+        let alias = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, image_id: ami-1}\n",
+        );
+        assert_eq!(validate_component(&alias), Some(vec![]));
+
+        let lookup = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, ami_lookup: {owners: [amazon], name_pattern: al2023-*}}\n",
+        );
+        assert_eq!(validate_component(&lookup), Some(vec![]));
+        let both = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, ami: ami-1, ami_lookup: {owners: [amazon], name_pattern: al2023-*}}\n",
+        );
+        let errors = validate_component(&both).unwrap();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("'ami' conflicts with 'ami_lookup'")
+        );
+
+        let mistyped = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: 5, ami: ami-1, min_count: \"2\", placement: {tenancy: [a]}}\n",
+        );
+        assert_eq!(
+            validate_component(&mistyped),
+            Some(vec![
+                PlanError::InvalidPropertyType(
+                    "instance_type".to_string(),
+                    "EC2Instance.web".to_string(),
+                    "string".to_string(),
+                    "number".to_string()
+                ),
+                PlanError::InvalidPropertyType(
+                    "placement.tenancy".to_string(),
+                    "EC2Instance.web".to_string(),
+                    "string".to_string(),
+                    "list".to_string()
+                ),
+            ])
+        );
+
+        let conflicting = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, ami: ami-1, image_id: ami-2, subnet_id: subnet-1, network_interfaces: [{device_index: 0}]}\n",
+        );
+        let errors = validate_component(&conflicting).unwrap();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(errors[0].to_string().contains("'ami' and 'image_id'"));
+        assert!(
+            errors[1]
+                .to_string()
+                .contains("'network_interfaces' conflicts with 'subnet_id'")
+        );
+
+        let rules = component(
+            "type: SecurityGroup\nname: web\nproperties: {ingress: [{protocol: tcp, from_port: 80, to_port: 80, cidr_blocks: 0.0.0.0/0}]}\n",
+        );
+        assert_eq!(
+            validate_component(&rules),
+            Some(vec![PlanError::InvalidPropertyType(
+                "ingress.cidr_blocks".to_string(),
+                "SecurityGroup.web".to_string(),
+                "list of strings".to_string(),
+                "string".to_string()
+            )])
+        );
+
+        let timeouts = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, ami: ami-1, timeouts: {create: 10m, delete: soon}}\n",
+        );
+        assert_eq!(
+            validate_component(&timeouts),
+            Some(vec![PlanError::InvalidComponent(
+                "EC2Instance.web: timeouts.delete: invalid duration soon, expected e.g. 90s, 10m or 1h30m"
+                    .to_string()
+            )])
+        );
+
+        let unknown = component("type: RDSInstance\nname: db\nproperties: {}\n");
+        assert_eq!(validate_component(&unknown), None);
+    }
+
+    #[test]
+    fn test_unknown_properties_are_rejected() {
+        // A typo in a shared block fails every component the block is merged into
+        let config = InfraConfig::from_yaml(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: sample\nx-common: &common\n  instance_type: t2.micro\n  ami: ami-1\n  instance_tpye: t3.small\ncomponents:\n  - type: EC2Instance\n    name: web\n    properties:\n      <<: *common\n  - type: EC2Instance\n    name: worker\n    properties:\n      <<: *common\n      timeouts: {create: 10m}\n",
+        )
+        .unwrap();
+        for component in &config.components {
+            assert_eq!(
+                validate_component(component),
+                Some(vec![PlanError::InvalidComponent(format!(
+                    "{}: unknown property 'instance_tpye', did you mean 'instance_type'?",
+                    component.key()
+                ))])
+            );
+        }
+
+        let unrelated =
+            component("type: VPC\nname: main\nproperties: {cidr: 10.0.0.0/16, colour: blue}\n");
+        assert_eq!(
+            validate_component(&unrelated),
+            Some(vec![PlanError::InvalidComponent(
+                "VPC.main: unknown property 'colour'".to_string()
+            )])
+        );
+
+        // Properties sent to the API as given have no attribute but are still known
+        let passthrough = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, image_id: ami-1, iam_instance_profile: {name: web}, tag_specifications: [{resource_type: instance, tags: {Team: web}}]}\n",
+        );
+        assert_eq!(validate_component(&passthrough), Some(vec![]));
+    }
+
+    #[test]
+    fn test_missing_property_hint() {
+        let err = crate::models::PlanError::MissingProperty(
+            "instance_type".to_string(),
+            "EC2Instance.web".to_string(),
+        );
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Missing mandatory property 'instance_type' in component 'EC2Instance.web'; a minimal EC2Instance looks like:\n{}",
+                EC2_GOLDEN
+            )
+        );
+        assert_eq!(missing_property_hint("Unknown.web"), "");
+    }
+
+    #[test]
+    fn test_unknown_type_hint() {
+        let hint = unknown_type_hint("EC2Instanse", "web");
+        assert!(hint.starts_with(
+            "unsupported component type 'EC2Instanse' for 'web', supported types are: EC2Instance, VPC, SecurityGroup, Subnet"
+        ));
+        assert!(hint.contains(EC2_GOLDEN));
+        assert!(
+            hint.contains(
+                "  - type: VPC\n    name: web\n    properties:\n      cidr: 10.0.0.0/16\n"
+            )
+        );
+        assert!(hint.contains(
+            "  - type: SecurityGroup\n    name: web\n    properties:\n      description: Web servers\n"
+        ));
+    }
+
+    #[test]
+    fn test_suggestions() {
+        assert_eq!(
+            suggest("EC2Instanse", SCHEMAS.iter().map(|s| s.component_type)),
+            vec!["EC2Instance"]
+        );
+        assert_eq!(
+            suggest(
+                "instance_tpye",
+                EC2_INSTANCE.attributes.iter().map(|a| a.name)
+            ),
+            vec!["instance_type"]
+        );
+        assert!(suggest("vpc", EC2_INSTANCE.attributes.iter().map(|a| a.name)).is_empty());
+    }
+
+    #[test]
+    fn test_mask_sensitive() {
+        let web = component(
+            "type: EC2Instance\nname: web\nsensitive: [key_name, placement.group_name]\nproperties:\n  instance_type: t2.micro\n  user_data: hunter2\n  key_name: ops-key\n  placement: {group_name: secret-group}\n",
+        );
+        let masked = mask_sensitive(&web, &web.properties);
+        assert_eq!(masked["user_data"], "(sensitive)");
+        assert_eq!(masked["key_name"], "(sensitive)");
+        assert_eq!(masked["placement"]["group_name"], "(sensitive)");
+        assert_eq!(masked["instance_type"], "t2.micro");
+        assert_eq!(
+            sensitive_values(std::slice::from_ref(&web)),
+            vec!["hunter2", "ops-key", "secret-group"]
+        );
+    }
+
+    #[test]
+    fn test_launch_options_conflicts() {
+        let both = component(
+            "type: EC2Instance\nname: web\nproperties: {instance_type: t2.micro, ami: ami-1, launch_template: {id: lt-1, name: web}, capacity_reservation_specification: {capacity_reservation_preference: open, capacity_reservation_id: cr-1}}\n",
+        );
+        let errors = validate_component(&both).unwrap();
+        assert_eq!(errors.len(), 2, "{:?}", errors);
+        assert!(
+            errors[0]
+                .to_string()
+                .contains("'launch_template.id' conflicts with 'launch_template.name'")
+        );
+        assert!(errors[1].to_string().contains(
+            "'capacity_reservation_specification.capacity_reservation_preference' conflicts with \
+             'capacity_reservation_specification.capacity_reservation_id'"
+        ));
+    }
+}
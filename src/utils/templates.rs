@@ -0,0 +1,235 @@
+use std::path::Path;
+
+use crate::{
+    models::{ComponentTemplate, InfraConfig, Kind},
+    utils::{document_selector, read_config_source, schema, split_documents},
+};
+
+/**
+ * Give the components that name a `template` the type and properties of that template.
+ * Templates are the `kind: Component` documents of the config's own file and of the other
+ * `*.yaml`/`*.yml` files in its directory.
+ */
+pub fn resolve_templates(config: &mut InfraConfig, file_path: &str) -> Result<(), String> {
+    if config.components.iter().all(|c| c.template.is_none()) {
+        return Ok(());
+    }
+    let templates = load_templates(file_path)?;
+    apply_templates(config, &templates)
+}
+
+/// Every `kind: Component` document next to `file_path`, template names must be unique
+pub fn load_templates(file_path: &str) -> Result<Vec<ComponentTemplate>, String> {
+    let file = document_selector(file_path).map_or(file_path, |(file, _)| file);
+    let mut files = vec![file.to_string()];
+    if file != "-" {
+        let path = Path::new(file);
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let mut siblings: Vec<String> = std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|e| e.path()))
+                    .filter(|p| p.is_file() && p.file_name() != path.file_name())
+                    .filter(|p| {
+                        let extension = p.extension().and_then(|e| e.to_str());
+                        matches!(extension, Some("yaml") | Some("yml"))
+                    })
+                    .map(|p| p.display().to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        siblings.sort();
+        files.extend(siblings);
+    }
+
+    let mut templates: Vec<(String, ComponentTemplate)> = Vec::new();
+    for (index, file) in files.iter().enumerate() {
+        let content = match read_config_source(file) {
+            Ok(content) => content,
+            // Only the config's own file has to be readable
+            Err(err) if index == 0 => {
+                return Err(format!("Failed to read file {}: {}", file, err));
+            }
+            Err(_) => continue,
+        };
+        for document in split_documents(&content) {
+            let Ok(raw) = serde_yaml::from_str::<serde_yaml::Value>(&document) else {
+                continue;
+            };
+            if raw.get("kind").and_then(|k| k.as_str()) != Some(Kind::Component.as_str()) {
+                continue;
+            }
+            let template: ComponentTemplate = serde_yaml::from_value(raw)
+                .map_err(|err| format!("Invalid component template in {}: {}", file, err))?;
+            let name = &template.metadata.name;
+            if let Some((other, _)) = templates.iter().find(|(_, t)| &t.metadata.name == name) {
+                return Err(format!(
+                    "Component template '{}' is defined in both {} and {}",
+                    name, other, file
+                ));
+            }
+            templates.push((file.clone(), template));
+        }
+    }
+    Ok(templates
+        .into_iter()
+        .map(|(_, template)| template)
+        .collect())
+}
+
+/**
+ * Merge the template of every component that names one into it: the component's own
+ * properties win, mappings are merged key by key. A component without a type takes the
+ * template's, one with a different type is an error, as is an unknown template.
+ */
+pub fn apply_templates(
+    config: &mut InfraConfig,
+    templates: &[ComponentTemplate],
+) -> Result<(), String> {
+    for component in config.components.iter_mut() {
+        let Some(name) = component.template.clone() else {
+            continue;
+        };
+        let Some(template) = templates.iter().find(|t| t.metadata.name == name) else {
+            let names = templates.iter().map(|t| t.metadata.name.as_str());
+            let hint = match schema::suggest(&name, names).first() {
+                Some(suggestion) => format!(", did you mean '{}'?", suggestion),
+                None => String::new(),
+            };
+            return Err(format!(
+                "Component '{}' uses template '{}' which is not defined{}",
+                component.name, name, hint
+            ));
+        };
+        if component.component_type.is_empty() {
+            component.component_type = template.component_type.clone();
+        } else if component.component_type != template.component_type {
+            return Err(format!(
+                "{} uses template '{}' of type {}",
+                component.key(),
+                name,
+                template.component_type
+            ));
+        }
+        component.properties = merge(&template.properties, &component.properties);
+    }
+    Ok(())
+}
+
+/// `overlay` on top of `base`, mappings are merged recursively and anything else is replaced
+fn merge(base: &serde_yaml::Value, overlay: &serde_yaml::Value) -> serde_yaml::Value {
+    match (base, overlay) {
+        (_, serde_yaml::Value::Null) => base.clone(),
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            let mut merged = base.clone();
+            for (key, value) in overlay {
+                let value = match base.get(key) {
+                    Some(existing) => merge(existing, value),
+                    None => value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            serde_yaml::Value::Mapping(merged)
+        }
+        _ => overlay.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"
+version: v1
+kind: Component
+metadata:
+  name: web-server
+type: EC2Instance
+properties:
+  instance_type: t2.micro
+  ami: ami-123
+  tags:
+    Team: web
+    Tier: frontend
+  security_groups: [default]
+"#;
+
+    fn config(components: &str) -> InfraConfig {
+        InfraConfig::from_yaml(&format!(
+            "version: v1\nkind: Infra\ncloud: AWS\nregion: us-west-2\nmetadata:\n  name: shop\ncomponents:{}",
+            components
+        ))
+        .unwrap()
+    }
+
+    fn templates() -> Vec<ComponentTemplate> {
+        vec![serde_yaml::from_str(TEMPLATE).unwrap()]
+    }
+
+    #[test]
+    fn test_component_properties_override_template_defaults() {
+        let mut config = config(
+            r#"
+  - name: web
+    template: web-server
+    properties:
+      instance_type: t3.small
+      tags:
+        Tier: edge
+      security_groups: [web]
+"#,
+        );
+        apply_templates(&mut config, &templates()).unwrap();
+        let component = &config.components[0];
+        assert_eq!(component.component_type, "EC2Instance");
+        let properties = &component.properties;
+        assert_eq!(properties["instance_type"].as_str(), Some("t3.small"));
+        assert_eq!(properties["ami"].as_str(), Some("ami-123"));
+        assert_eq!(properties["tags"]["Team"].as_str(), Some("web"));
+        assert_eq!(properties["tags"]["Tier"].as_str(), Some("edge"));
+        // Lists are replaced, not appended to
+        assert_eq!(
+            properties["security_groups"].as_sequence().map(|s| s.len()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_unknown_template_and_type_mismatch_are_rejected() {
+        let mut unknown = config("\n  - name: web\n    template: web-servr\n");
+        let err = apply_templates(&mut unknown, &templates()).unwrap_err();
+        assert!(err.contains("did you mean 'web-server'?"), "{}", err);
+
+        let mut mismatch = config("\n  - type: VPC\n    name: main\n    template: web-server\n");
+        let err = apply_templates(&mut mismatch, &templates()).unwrap_err();
+        assert_eq!(
+            err,
+            "VPC.main uses template 'web-server' of type EC2Instance"
+        );
+    }
+
+    #[test]
+    fn test_templates_are_loaded_from_the_file_and_its_directory() {
+        let dir = std::env::temp_dir().join(format!("letus-templates-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let infra = "kind: Infra\nmetadata:\n  name: shop\n---\nkind: Component\nmetadata:\n  name: db\ntype: EC2Instance\n";
+        std::fs::write(dir.join("infra.yaml"), infra).unwrap();
+        std::fs::write(dir.join("templates.yml"), TEMPLATE).unwrap();
+
+        let file = dir.join("infra.yaml").display().to_string();
+        let names: Vec<String> = load_templates(&format!("{}#1", file))
+            .unwrap()
+            .into_iter()
+            .map(|t| t.metadata.name)
+            .collect();
+        assert_eq!(names, vec!["db", "web-server"]);
+
+        std::fs::write(dir.join("more.yaml"), TEMPLATE).unwrap();
+        let err = load_templates(&file).unwrap_err();
+        assert!(err.contains("'web-server' is defined in both"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
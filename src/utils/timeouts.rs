@@ -0,0 +1,160 @@
+/// Module for the `timeouts:` block a component can set in its properties
+use std::time::Duration;
+
+pub const TIMEOUTS_PROPERTY: &str = "timeouts";
+
+const OPERATIONS: &[&str] = &["create", "update", "delete"];
+
+/**
+ * How long each operation on a component may take, None keeps the provider's default.
+ * Every provider reads them from the same `timeouts:` block:
+ *
+ * ```yaml
+ * timeouts:
+ *   create: 10m
+ *   delete: 1h30m
+ * ```
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceTimeouts {
+    pub create: Option<Duration>,
+    pub update: Option<Duration>,
+    pub delete: Option<Duration>,
+}
+
+impl ResourceTimeouts {
+    /// The `timeouts:` block of `properties`, all defaults when there is none
+    pub fn from_properties(properties: &serde_yaml::Value) -> Result<Self, String> {
+        let Some(block) = properties.get(TIMEOUTS_PROPERTY) else {
+            return Ok(ResourceTimeouts::default());
+        };
+        let Some(block) = block.as_mapping() else {
+            return Err(format!(
+                "{} must be a map of operation to duration, e.g. {{create: 10m}}",
+                TIMEOUTS_PROPERTY
+            ));
+        };
+        let mut timeouts = ResourceTimeouts::default();
+        for (operation, value) in block {
+            let operation = operation.as_str().unwrap_or_default();
+            let duration = match value {
+                serde_yaml::Value::Number(n) => {
+                    n.as_u64().filter(|s| *s > 0).map(Duration::from_secs)
+                }
+                serde_yaml::Value::String(s) => parse_duration(s).ok(),
+                _ => None,
+            }
+            .ok_or_else(|| {
+                format!(
+                    "{}.{}: invalid duration {}, expected e.g. 90s, 10m or 1h30m",
+                    TIMEOUTS_PROPERTY,
+                    operation,
+                    serde_yaml::to_string(value).unwrap_or_default().trim()
+                )
+            });
+            let slot = match operation {
+                "create" => &mut timeouts.create,
+                "update" => &mut timeouts.update,
+                "delete" => &mut timeouts.delete,
+                _ => {
+                    return Err(format!(
+                        "{}: unknown operation '{}', expected one of {}",
+                        TIMEOUTS_PROPERTY,
+                        operation,
+                        OPERATIONS.join(", ")
+                    ));
+                }
+            };
+            *slot = Some(duration?);
+        }
+        Ok(timeouts)
+    }
+}
+
+/**
+ * Parse a human-readable duration: a number of seconds (`90`), or numbers with a unit of `s`, `m`
+ * or `h`, optionally combined (`45s`, `10m`, `1h30m`). Zero is rejected, nothing could finish in it.
+ */
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "invalid duration '{}', expected e.g. 90s, 10m or 1h30m",
+            text
+        )
+    };
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Some(Duration::from_secs(seconds))
+            .filter(|d| !d.is_zero())
+            .ok_or_else(invalid);
+    }
+    let mut total: u64 = 0;
+    let mut digits = String::new();
+    for c in text.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            _ => return Err(invalid()),
+        };
+        let value: u64 = digits.parse().map_err(|_| invalid())?;
+        total = value
+            .checked_mul(unit)
+            .and_then(|seconds| total.checked_add(seconds))
+            .ok_or_else(invalid)?;
+        digits.clear();
+    }
+    if !digits.is_empty() || total == 0 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("10m"), Ok(Duration::from_secs(600)));
+        assert_eq!(parse_duration(" 1h30m "), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("2h5m10s"), Ok(Duration::from_secs(7510)));
+        for invalid in ["", "0", "0s", "m", "10x", "1.5h", "10m5", "-5m"] {
+            assert!(
+                parse_duration(invalid).is_err(),
+                "{} should not parse",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_timeouts_from_properties() {
+        let timeouts =
+            |yaml: &str| ResourceTimeouts::from_properties(&serde_yaml::from_str(yaml).unwrap());
+        assert_eq!(timeouts("ami: ami-1"), Ok(ResourceTimeouts::default()));
+        assert_eq!(
+            timeouts("timeouts: {create: 10m, delete: 1200}"),
+            Ok(ResourceTimeouts {
+                create: Some(Duration::from_secs(600)),
+                update: None,
+                delete: Some(Duration::from_secs(1200)),
+            })
+        );
+        assert_eq!(
+            timeouts("timeouts: {create: ten minutes}").unwrap_err(),
+            "timeouts.create: invalid duration ten minutes, expected e.g. 90s, 10m or 1h30m"
+        );
+        assert_eq!(
+            timeouts("timeouts: {read: 5m}").unwrap_err(),
+            "timeouts: unknown operation 'read', expected one of create, update, delete"
+        );
+        assert!(timeouts("timeouts: 10m").is_err());
+    }
+}
@@ -0,0 +1,240 @@
+use serde_json::{Number, Value as JsonValue};
+
+use crate::utils::schema::{AttributeKind, ResourceSchema};
+
+/**
+ * Canonical form of the properties of a component, used on both sides of every comparison.
+ * YAML and the stored state disagree on representations (`100` vs `"100"`, `true` vs `"True"`,
+ * `1.0` vs `1`), the schema of the component type decides which ones mean the same value:
+ * - numeric strings and integral floats of Integer attributes become integers
+ * - "true"/"false" strings of Boolean attributes become booleans, case-insensitively
+ * - mappings are keyed by their string representation and ordered
+ * Every other value is kept as written, `"007"` of a String attribute stays `"007"`.
+ */
+pub fn normalize(properties: &serde_yaml::Value, schema: Option<&ResourceSchema>) -> JsonValue {
+    normalize_at(properties, schema, None)
+}
+
+/// Compare the properties of two components of the same type by their canonical form
+pub fn values_equal(
+    left: &serde_yaml::Value,
+    right: &serde_yaml::Value,
+    schema: Option<&ResourceSchema>,
+) -> bool {
+    normalize(left, schema) == normalize(right, schema)
+}
+
+/// `path` is the dotted name of the attribute `value` belongs to, entries of a list share it
+fn normalize_at(
+    value: &serde_yaml::Value,
+    schema: Option<&ResourceSchema>,
+    path: Option<&str>,
+) -> JsonValue {
+    let kind = path
+        .and_then(|path| {
+            schema?
+                .attributes
+                .iter()
+                .find(|a| a.name == path || a.aliases.contains(&path))
+        })
+        .map(|attribute| attribute.kind);
+    match value {
+        serde_yaml::Value::Null => JsonValue::Null,
+        serde_yaml::Value::Bool(b) => JsonValue::Bool(*b),
+        serde_yaml::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                JsonValue::Number(i.into())
+            } else if let Some(u) = n.as_u64() {
+                JsonValue::Number(u.into())
+            } else if kind == Some(AttributeKind::Integer) {
+                normalize_float(n.as_f64().unwrap_or(f64::NAN))
+            } else {
+                float(n.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        serde_yaml::Value::String(s) => match kind {
+            Some(AttributeKind::Integer) => integer_str(s),
+            Some(AttributeKind::Boolean) => boolean_str(s),
+            _ => JsonValue::String(s.clone()),
+        },
+        serde_yaml::Value::Sequence(seq) => JsonValue::Array(
+            seq.iter()
+                .map(|entry| normalize_at(entry, schema, path))
+                .collect(),
+        ),
+        serde_yaml::Value::Mapping(map) => JsonValue::Object(
+            map.iter()
+                .map(|(k, v)| {
+                    let key = key_string(k);
+                    let child = match path {
+                        Some(path) => format!("{}.{}", path, key),
+                        None => key.clone(),
+                    };
+                    (key, normalize_at(v, schema, Some(&child)))
+                })
+                .collect(),
+        ),
+        serde_yaml::Value::Tagged(tagged) => normalize_at(&tagged.value, schema, path),
+    }
+}
+
+fn integer_str(s: &str) -> JsonValue {
+    if let Ok(i) = s.parse::<i64>() {
+        return JsonValue::Number(i.into());
+    }
+    // Only plain decimal forms, "inf"/"nan" and friends stay strings
+    let looks_numeric = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E'));
+    if looks_numeric && let Ok(f) = s.parse::<f64>() {
+        return normalize_float(f);
+    }
+    JsonValue::String(s.to_string())
+}
+
+fn boolean_str(s: &str) -> JsonValue {
+    if s.eq_ignore_ascii_case("true") {
+        return JsonValue::Bool(true);
+    }
+    if s.eq_ignore_ascii_case("false") {
+        return JsonValue::Bool(false);
+    }
+    JsonValue::String(s.to_string())
+}
+
+fn normalize_float(f: f64) -> JsonValue {
+    if f.is_finite() && f.fract() == 0.0 && f.abs() < i64::MAX as f64 {
+        return JsonValue::Number((f as i64).into());
+    }
+    float(f)
+}
+
+fn float(f: f64) -> JsonValue {
+    Number::from_f64(f)
+        .map(JsonValue::Number)
+        .unwrap_or_else(|| JsonValue::String(f.to_string()))
+}
+
+fn key_string(key: &serde_yaml::Value) -> String {
+    match key {
+        serde_yaml::Value::String(s) => s.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_end().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::schema::EC2_INSTANCE;
+
+    fn yaml(s: &str) -> serde_yaml::Value {
+        serde_yaml::from_str(s).unwrap()
+    }
+
+    fn equal(left: &str, right: &str) -> bool {
+        values_equal(&yaml(left), &yaml(right), Some(&EC2_INSTANCE))
+    }
+
+    #[test]
+    fn test_equivalent_representations() {
+        let cases = [
+            ("{max_count: 100}", "{max_count: '100'}"),
+            ("{max_count: 100}", "{max_count: 100.0}"),
+            ("{max_count: '1.0'}", "{max_count: 1}"),
+            ("{max_count: -3}", "{max_count: '-3'}"),
+            ("{ebs_optimized: true}", "{ebs_optimized: 'true'}"),
+            ("{ebs_optimized: true}", "{ebs_optimized: 'True'}"),
+            ("{ebs_optimized: false}", "{ebs_optimized: 'FALSE'}"),
+            (
+                "{monitoring: {enabled: true}}",
+                "{monitoring: {enabled: 'True'}}",
+            ),
+            ("{tags: {a: '1', b: '2'}}", "{tags: {b: '2', a: '1'}}"),
+            ("{key_name: null}", "{key_name: ~}"),
+        ];
+        for (left, right) in cases {
+            assert!(equal(left, right), "expected {} == {}", left, right);
+        }
+    }
+
+    #[test]
+    fn test_different_values() {
+        let cases = [
+            ("{max_count: 100}", "{max_count: '101'}"),
+            ("{max_count: 1.5}", "{max_count: 1}"),
+            ("{max_count: 'nan'}", "{max_count: 'NaN'}"),
+            ("{ebs_optimized: true}", "{ebs_optimized: 'yes'}"),
+            ("{instance_type: t2.micro}", "{instance_type: t3.micro}"),
+            (
+                "{security_group_ids: [sg-1, sg-2]}",
+                "{security_group_ids: [sg-2, sg-1]}",
+            ),
+            ("{key_name: ''}", "{key_name: null}"),
+        ];
+        for (left, right) in cases {
+            assert!(!equal(left, right), "expected {} != {}", left, right);
+        }
+    }
+
+    #[test]
+    fn test_strings_are_kept_outside_integer_and_boolean_attributes() {
+        let cases = [
+            ("{key_name: '007'}", "{key_name: 7}"),
+            ("{key_name: 'True'}", "{key_name: true}"),
+            ("{key_name: ' web '}", "{key_name: web}"),
+            ("{security_group_ids: ['1']}", "{security_group_ids: [1]}"),
+            ("{tags: {count: '1.0'}}", "{tags: {count: 1}}"),
+            // Not in the schema, nothing is known about the value
+            ("{volume_size: '100'}", "{volume_size: 100}"),
+            ("{volume_size: 1.0}", "{volume_size: 1}"),
+        ];
+        for (left, right) in cases {
+            assert!(!equal(left, right), "expected {} != {}", left, right);
+        }
+        assert!(!values_equal(&yaml("{a: '1'}"), &yaml("{a: 1}"), None));
+    }
+
+    #[test]
+    fn test_plan_hash_ignores_representation() {
+        let config = |count: &str| {
+            crate::models::InfraConfig::from_yaml(&format!(
+                r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: us-west-2
+metadata:
+  name: sample
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: t2.micro
+      ami: ami-123
+      ebs_optimized: true
+      max_count: {}
+"#,
+                count
+            ))
+            .unwrap()
+        };
+        let (numeric, _, _) = crate::utils::plan_components(&config("3"), "default").unwrap();
+        let (quoted, _, _) = crate::utils::plan_components(&config("'3.0'"), "default").unwrap();
+        assert_eq!(numeric.hash, quoted.hash);
+    }
+
+    #[test]
+    fn test_non_numeric_strings_are_preserved() {
+        let properties = normalize(
+            &yaml("{ami: ami-123, subnet_id: '10.0.0.0/16'}"),
+            Some(&EC2_INSTANCE),
+        );
+        assert_eq!(properties["ami"], JsonValue::String("ami-123".into()));
+        assert_eq!(
+            properties["subnet_id"],
+            JsonValue::String("10.0.0.0/16".into())
+        );
+    }
+}
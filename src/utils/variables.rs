@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    models::{InfraConfig, VariableType},
+    utils::interpolation::{VARIABLE_PREFIX, references_in},
+};
+
+/// Variable values given on the command line, shared by every command that resolves a config
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct VariableOptions {
+    /// Set a variable, `name=value`, can be repeated. Wins over --var-file and the default
+    #[clap(long = "var", value_name = "NAME=VALUE")]
+    pub vars: Vec<String>,
+    /// YAML or JSON file mapping variable names to values, wins over the defaults
+    #[clap(long = "var-file")]
+    pub var_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VariableError {
+    #[error("Invalid --var '{0}', expected name=value")]
+    Malformed(String),
+    #[error("Variable '{0}' is not declared in variables")]
+    Undeclared(String),
+    #[error("Variable '{0}' has no default, set it with --var or --var-file")]
+    Missing(String),
+    #[error("Variable '{name}' is declared as {expected}, got {found}")]
+    TypeMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+    #[error("Failed to read variables file {path}: {message}")]
+    File { path: String, message: String },
+}
+
+/**
+ * Give every declared variable its value, the `--var` flags win over the `--var-file` entries,
+ * which win over the default. A value for an undeclared variable, a variable left without a
+ * value and a value of the wrong type are all errors.
+ */
+pub fn bind_variables(
+    config: &mut InfraConfig,
+    options: &VariableOptions,
+) -> Result<(), VariableError> {
+    let mut given: BTreeMap<String, serde_yaml::Value> = BTreeMap::new();
+    if let Some(path) = &options.var_file {
+        given.extend(read_var_file(path)?);
+    }
+    for var in &options.vars {
+        let (name, raw) = var
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| VariableError::Malformed(var.clone()))?;
+        let name = name.trim();
+        let declared = config
+            .variables
+            .iter()
+            .find(|v| v.name == name)
+            .ok_or_else(|| VariableError::Undeclared(name.to_string()))?;
+        // Values on the command line are text, they are read as the declared type
+        let value = declared
+            .var_type
+            .parse(raw)
+            .ok_or_else(|| mismatch(name, &declared.var_type, &yaml_string(raw)))?;
+        given.insert(name.to_string(), value);
+    }
+    if let Some(name) = given
+        .keys()
+        .find(|name| !config.variables.iter().any(|v| &v.name == *name))
+    {
+        return Err(VariableError::Undeclared(name.clone()));
+    }
+
+    for variable in config.variables.iter_mut() {
+        let value = given
+            .remove(&variable.name)
+            .or_else(|| variable.default.clone())
+            .ok_or_else(|| VariableError::Missing(variable.name.clone()))?;
+        if !variable.var_type.accepts(&value) {
+            return Err(mismatch(&variable.name, &variable.var_type, &value));
+        }
+        variable.value = Some(value);
+    }
+    Ok(())
+}
+
+/**
+ * Problems in the variable declarations themselves: defaults of the wrong type and `${var.*}`
+ * references to variables that are not declared. Run by validate, before any value is given.
+ */
+pub fn check_declarations(config: &InfraConfig) -> Vec<VariableError> {
+    let mut errors: Vec<VariableError> = config
+        .variables
+        .iter()
+        .filter_map(|variable| {
+            let default = variable.default.as_ref()?;
+            (!variable.var_type.accepts(default))
+                .then(|| mismatch(&variable.name, &variable.var_type, default))
+        })
+        .collect();
+
+    let mut strings = vec![config.region.clone(), config.metadata.name.clone()];
+    for component in &config.components {
+        collect_strings(&component.properties, &mut strings);
+    }
+    for reference in strings.iter().flat_map(|s| references_in(s)) {
+        let Some(name) = reference.strip_prefix(VARIABLE_PREFIX) else {
+            continue;
+        };
+        let undeclared = VariableError::Undeclared(name.to_string());
+        if !config.variables.iter().any(|v| v.name == name) && !errors.contains(&undeclared) {
+            errors.push(undeclared);
+        }
+    }
+    errors
+}
+
+fn read_var_file(path: &str) -> Result<BTreeMap<String, serde_yaml::Value>, VariableError> {
+    let file_error = |message: String| VariableError::File {
+        path: path.to_string(),
+        message,
+    };
+    let content = std::fs::read_to_string(path).map_err(|err| file_error(err.to_string()))?;
+    // JSON is valid YAML, one parser reads both
+    let values: Option<BTreeMap<String, serde_yaml::Value>> =
+        serde_yaml::from_str(&content).map_err(|err| file_error(err.to_string()))?;
+    Ok(values.unwrap_or_default())
+}
+
+fn mismatch(name: &str, expected: &VariableType, found: &serde_yaml::Value) -> VariableError {
+    let found = match found {
+        serde_yaml::Value::String(s) => format!("string '{}'", s),
+        serde_yaml::Value::Number(n) => format!("number {}", n),
+        serde_yaml::Value::Bool(b) => format!("bool {}", b),
+        serde_yaml::Value::Null => "null".to_string(),
+        serde_yaml::Value::Sequence(_) => "a list".to_string(),
+        serde_yaml::Value::Mapping(_) => "a mapping".to_string(),
+        serde_yaml::Value::Tagged(_) => "a tagged value".to_string(),
+    };
+    VariableError::TypeMismatch {
+        name: name.to_string(),
+        expected: expected.as_str().to_string(),
+        found,
+    }
+}
+
+fn yaml_string(raw: &str) -> serde_yaml::Value {
+    serde_yaml::Value::String(raw.to_string())
+}
+
+fn collect_strings(value: &serde_yaml::Value, strings: &mut Vec<String>) {
+    match value {
+        serde_yaml::Value::String(s) => strings.push(s.clone()),
+        serde_yaml::Value::Sequence(seq) => seq.iter().for_each(|v| collect_strings(v, strings)),
+        serde_yaml::Value::Mapping(map) => map.values().for_each(|v| collect_strings(v, strings)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+version: v1
+kind: Infra
+cloud: AWS
+region: ${var.region}
+metadata:
+  name: shop
+variables:
+  - name: region
+    default: us-west-2
+  - name: instance_type
+    description: Size of the web server
+  - name: count
+    type: int
+    default: 1
+components:
+  - type: EC2Instance
+    name: web
+    properties:
+      instance_type: ${var.instance_type}
+      ami: ami-123
+"#;
+
+    fn config() -> InfraConfig {
+        InfraConfig::from_yaml(CONFIG).unwrap()
+    }
+
+    fn options(vars: &[&str], var_file: Option<&str>) -> VariableOptions {
+        VariableOptions {
+            vars: vars.iter().map(|v| v.to_string()).collect(),
+            var_file: var_file.map(|f| f.to_string()),
+        }
+    }
+
+    fn value(config: &InfraConfig, name: &str) -> Option<serde_yaml::Value> {
+        config
+            .variables
+            .iter()
+            .find(|v| v.name == name)
+            .and_then(|v| v.value.clone())
+    }
+
+    #[test]
+    fn test_cli_wins_over_file_wins_over_default() {
+        let dir = std::env::temp_dir().join(format!("letus-vars-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("vars.yaml");
+        std::fs::write(&file, "region: eu-west-1\ninstance_type: t3.small\n").unwrap();
+
+        let mut config = config();
+        let options = options(&["instance_type=t3.large", "count=3"], file.to_str());
+        bind_variables(&mut config, &options).unwrap();
+        assert_eq!(value(&config, "region"), Some(yaml_string("eu-west-1")));
+        assert_eq!(
+            value(&config, "instance_type"),
+            Some(yaml_string("t3.large"))
+        );
+        assert_eq!(value(&config, "count"), Some(serde_yaml::Value::from(3)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_undeclared_missing_and_mistyped_values_are_rejected() {
+        let bind = |vars: &[&str]| bind_variables(&mut config(), &options(vars, None));
+        assert_eq!(
+            bind(&[]),
+            Err(VariableError::Missing("instance_type".to_string()))
+        );
+        assert_eq!(
+            bind(&["instance_type=t2.micro", "size=large"]),
+            Err(VariableError::Undeclared("size".to_string()))
+        );
+        assert_eq!(
+            bind(&["instance_type=t2.micro", "count=two"]),
+            Err(VariableError::TypeMismatch {
+                name: "count".to_string(),
+                expected: "int".to_string(),
+                found: "string 'two'".to_string(),
+            })
+        );
+        assert_eq!(
+            bind(&["instance_type"]),
+            Err(VariableError::Malformed("instance_type".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_check_declarations() {
+        let raw = CONFIG
+            .replace("default: 1", "default: one")
+            .replace("ami: ami-123", "ami: ${var.ami}");
+        let errors = check_declarations(&InfraConfig::from_yaml(&raw).unwrap());
+        assert_eq!(
+            errors,
+            vec![
+                VariableError::TypeMismatch {
+                    name: "count".to_string(),
+                    expected: "int".to_string(),
+                    found: "string 'one'".to_string(),
+                },
+                VariableError::Undeclared("ami".to_string()),
+            ]
+        );
+        assert!(check_declarations(&config()).is_empty());
+    }
+}